@@ -0,0 +1,104 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use himalaya_lib::{AccountConfig, Backend, Flag, Flags, MaildirBackend, MaildirConfig};
+use std::{
+    borrow::Cow,
+    fs,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use tempfile::tempdir;
+
+const EMAILS_COUNT: usize = 1000;
+
+/// Builds a synthetic email of about 10 KB.
+fn email(i: usize) -> Vec<u8> {
+    let mut email = format!(
+        "Message-ID: <{i}@localhost>\r\nFrom: alice@localhost\r\nTo: bob@localhost\r\nSubject: Email {i}\r\n\r\n"
+    );
+    for _ in 0..128 {
+        email.push_str("Lorem ipsum dolor sit amet, consectetur adipiscing elit sed do.\r\n");
+    }
+    email.into_bytes()
+}
+
+/// Returns the paths of the emails of the given maildir folder.
+fn email_paths(dir: &Path) -> Vec<PathBuf> {
+    fs::read_dir(dir.join("cur"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect()
+}
+
+fn copy_emails_benchmark(c: &mut Criterion) {
+    let root = tempdir().unwrap();
+    let account_config = AccountConfig::default();
+    let mdir = MaildirBackend::new(
+        Cow::Borrowed(&account_config),
+        Cow::Owned(MaildirConfig {
+            root_dir: root.path().to_owned(),
+        }),
+    )
+    .unwrap();
+
+    let flags = Flags::from_iter([Flag::Seen]);
+    let internal_ids: Vec<String> = (0..EMAILS_COUNT)
+        .map(|i| mdir.add_email_internal("INBOX", &email(i), &flags).unwrap())
+        .collect();
+    let internal_ids: Vec<&str> = internal_ids.iter().map(String::as_str).collect();
+    let paths = email_paths(root.path());
+
+    // every iteration targets a new empty folder
+    let folders = AtomicUsize::new(0);
+    let new_folder = || {
+        let folder = format!("copy-{}", folders.fetch_add(1, Ordering::Relaxed));
+        mdir.add_folder(&folder).unwrap();
+        folder
+    };
+
+    let mut group = c.benchmark_group("maildir_copy_emails");
+    group.sample_size(10);
+
+    // hard links, used by the backend within the same filesystem
+    group.bench_function("1000 hard links", |b| {
+        b.iter_batched(
+            &new_folder,
+            |folder| {
+                let dir = root.path().join(format!(".{folder}")).join("cur");
+                for (i, path) in paths.iter().enumerate() {
+                    fs::hard_link(path, dir.join(i.to_string())).unwrap();
+                }
+            },
+            BatchSize::PerIteration,
+        )
+    });
+
+    // plain copies, used by the backend across filesystems
+    group.bench_function("1000 copies", |b| {
+        b.iter_batched(
+            &new_folder,
+            |folder| {
+                let dir = root.path().join(format!(".{folder}")).join("cur");
+                for (i, path) in paths.iter().enumerate() {
+                    fs::copy(path, dir.join(i.to_string())).unwrap();
+                }
+            },
+            BatchSize::PerIteration,
+        )
+    });
+
+    group.bench_function("1000 copy_emails_internal", |b| {
+        b.iter_batched(
+            &new_folder,
+            |folder| {
+                mdir.copy_emails_internal("INBOX", &folder, internal_ids.clone())
+                    .unwrap()
+            },
+            BatchSize::PerIteration,
+        )
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, copy_emails_benchmark);
+criterion_main!(benches);
@@ -1,16 +1,140 @@
-use criterion::{black_box, criterion_group, criterion_main, Criterion};
-
-fn fibonacci(n: u64) -> u64 {
-    match n {
-        0 => 1,
-        1 => 1,
-        n => fibonacci(n - 1) + fibonacci(n - 2),
-    }
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use himalaya_lib::{
+    envelope::sync::{build_patch, Envelopes},
+    Envelope, Flag, Flags,
+};
+
+const ENVELOPES_COUNT: usize = 100_000;
+
+/// Lists synthetic envelopes like a backend would, the given closure
+/// deciding the flags of each envelope.
+fn listed_envelopes(flags: impl Fn(usize) -> Flags) -> himalaya_lib::Envelopes {
+    (0..ENVELOPES_COUNT)
+        .map(|i| Envelope {
+            id: i.to_string(),
+            internal_id: i.to_string(),
+            message_id: format!("<{i}@localhost>"),
+            flags: flags(i),
+            subject: format!("Subject {i}"),
+            ..Envelope::default()
+        })
+        .collect()
+}
+
+/// Builds a folder of synthetic envelopes, the given closure deciding
+/// the flags of each envelope.
+fn envelopes(flags: impl Fn(usize) -> Flags) -> Envelopes {
+    Envelopes::from_iter(
+        listed_envelopes(flags)
+            .into_iter()
+            .map(|envelope| (envelope.message_id.clone(), envelope)),
+    )
 }
 
-fn criterion_benchmark(c: &mut Criterion) {
-    c.bench_function("fib 20", |b| b.iter(|| fibonacci(black_box(20))));
+fn build_patch_benchmark(c: &mut Criterion) {
+    let seen = |_| Flags::from_iter([Flag::Seen]);
+    let seen_and_flagged = |i| match i % 10 {
+        0 => Flags::from_iter([Flag::Seen, Flag::Flagged]),
+        _ => Flags::from_iter([Flag::Seen]),
+    };
+
+    let mut group = c.benchmark_group("build_patch");
+    group.sample_size(10);
+
+    // nothing changed since the last synchronization
+    let noop = envelopes(seen);
+    group.bench_function("100k unchanged", |b| {
+        b.iter_batched(
+            || (noop.clone(), noop.clone(), noop.clone(), noop.clone()),
+            |(local_cache, local, remote_cache, remote)| {
+                black_box(build_patch(
+                    "INBOX",
+                    local_cache,
+                    local,
+                    remote_cache,
+                    remote,
+                ))
+            },
+            BatchSize::LargeInput,
+        )
+    });
+
+    // one remote email out of ten has been flagged
+    let flagged = envelopes(seen_and_flagged);
+    group.bench_function("100k with 10% flags changed", |b| {
+        b.iter_batched(
+            || (noop.clone(), noop.clone(), noop.clone(), flagged.clone()),
+            |(local_cache, local, remote_cache, remote)| {
+                black_box(build_patch(
+                    "INBOX",
+                    local_cache,
+                    local,
+                    remote_cache,
+                    remote,
+                ))
+            },
+            BatchSize::LargeInput,
+        )
+    });
+
+    // the local side is empty, every remote email is downloaded
+    group.bench_function("100k new remote emails", |b| {
+        b.iter_batched(
+            || noop.clone(),
+            |remote| {
+                black_box(build_patch(
+                    "INBOX",
+                    Envelopes::default(),
+                    Envelopes::default(),
+                    Envelopes::default(),
+                    remote,
+                ))
+            },
+            BatchSize::LargeInput,
+        )
+    });
+
+    group.finish();
+}
+
+fn envelopes_map_benchmark(c: &mut Criterion) {
+    let listed = listed_envelopes(|_| Flags::from_iter([Flag::Seen]));
+
+    let mut group = c.benchmark_group("envelopes_map");
+    group.sample_size(10);
+
+    // the listed envelopes are cloned into the map, then dropped
+    group.bench_function("100k cloned", |b| {
+        b.iter_batched(
+            || listed.clone(),
+            |listed| {
+                black_box(Envelopes::from_iter(listed.iter().map(|envelope| {
+                    (
+                        envelope.message_id.clone(),
+                        envelope.clone_without_custom_flags(),
+                    )
+                })))
+            },
+            BatchSize::LargeInput,
+        )
+    });
+
+    // the listed envelopes are moved into the map, like the
+    // synchronization does
+    group.bench_function("100k moved", |b| {
+        b.iter_batched(
+            || listed.clone(),
+            |listed| {
+                black_box(Envelopes::from_iter(listed.into_iter().map(|envelope| {
+                    (envelope.message_id.clone(), envelope.without_custom_flags())
+                })))
+            },
+            BatchSize::LargeInput,
+        )
+    });
+
+    group.finish();
 }
 
-criterion_group!(benches, criterion_benchmark);
+criterion_group!(benches, build_patch_benchmark, envelopes_map_benchmark);
 criterion_main!(benches);
@@ -0,0 +1,280 @@
+//! Async backend module.
+//!
+//! This module exposes an async facade over the blocking backends.
+//! Operations run on dedicated worker threads owned by the facade,
+//! so that async runtimes never block their own threads, neither on
+//! network I/O nor on the IMAP sessions pool.
+
+use log::{debug, warn};
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{mpsc, Arc, Mutex, MutexGuard},
+    task::{Context, Poll, Waker},
+    thread,
+};
+
+use crate::{
+    AccountConfig, Backend, BackendSyncBuilder, BackendSyncReport, Emails, Envelopes, Flags,
+};
+
+use super::{Error, Result};
+
+type Job<B> = Box<dyn FnOnce(&B) + Send>;
+
+/// Represents the async facade of a blocking backend.
+///
+/// Each operation is sent to a pool of worker threads dedicated to
+/// the backend, and resolves once a worker has run it. The pool size
+/// should match the amount of sessions the backend can use
+/// concurrently, for example [`crate::ImapBackendBuilder::pool_size`]
+/// for the IMAP backend. Workers stop when the facade is dropped,
+/// after finishing the pending operations.
+pub struct AsyncBackend<B: Backend + 'static> {
+    backend: Arc<B>,
+    jobs: mpsc::Sender<Job<B>>,
+}
+
+impl<B: Backend + 'static> AsyncBackend<B> {
+    /// Wraps the given backend, using a single worker thread.
+    pub fn new(backend: B) -> Self {
+        Self::with_workers(backend, 1)
+    }
+
+    /// Wraps the given backend, using the given amount of worker
+    /// threads (at least one).
+    pub fn with_workers(backend: B, workers: usize) -> Self {
+        let backend = Arc::new(backend);
+        let (jobs, queue) = mpsc::channel::<Job<B>>();
+        let queue = Arc::new(Mutex::new(queue));
+
+        for n in 0..workers.max(1) {
+            let backend = backend.clone();
+            let queue = queue.clone();
+            let spawned = thread::Builder::new()
+                .name(format!("{}-worker-{n}", backend.name()))
+                .spawn(move || loop {
+                    // the queue is unlocked before running the job,
+                    // so that other workers can pick the next ones
+                    let job = match lock(&queue).recv() {
+                        Ok(job) => job,
+                        Err(_) => break,
+                    };
+                    job(&backend);
+                });
+
+            if let Err(err) = spawned {
+                warn!("cannot spawn async backend worker {n}, skipping it: {err}");
+            }
+        }
+
+        Self { backend, jobs }
+    }
+
+    /// Returns the wrapped backend, for the operations not exposed by
+    /// the facade. Calling it from an async task blocks the task.
+    pub fn blocking(&self) -> &B {
+        &self.backend
+    }
+
+    /// Sends the given operation to the workers.
+    fn spawn<T, F>(&self, f: F) -> BackendFuture<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&B) -> Result<T> + Send + 'static,
+    {
+        let state = Arc::new(Mutex::new(FutureState {
+            result: None,
+            waker: None,
+        }));
+
+        let completer = Completer(state.clone());
+        let job: Job<B> = Box::new(move |backend| completer.complete(f(backend)));
+
+        // if no worker is left, the job is dropped along with its
+        // completer, which resolves the future with an error
+        if let Err(mpsc::SendError(job)) = self.jobs.send(job) {
+            debug!("no async backend worker left, dropping the operation");
+            drop(job);
+        }
+
+        BackendFuture(state)
+    }
+
+    pub fn list_envelopes(
+        &self,
+        folder: &str,
+        page_size: usize,
+        page: usize,
+    ) -> BackendFuture<Envelopes> {
+        let folder = folder.to_owned();
+        self.spawn(move |backend| backend.list_envelopes(&folder, page_size, page))
+    }
+
+    pub fn get_emails(&self, folder: &str, ids: Vec<&str>) -> BackendFuture<Emails> {
+        let folder = folder.to_owned();
+        let ids = to_owned_ids(ids);
+        self.spawn(move |backend| backend.get_emails(&folder, to_borrowed_ids(&ids)))
+    }
+
+    pub fn add_email(&self, folder: &str, email: &[u8], flags: &Flags) -> BackendFuture<String> {
+        let folder = folder.to_owned();
+        let email = email.to_vec();
+        let flags = flags.clone();
+        self.spawn(move |backend| backend.add_email(&folder, &email, &flags))
+    }
+
+    pub fn set_flags(&self, folder: &str, ids: Vec<&str>, flags: &Flags) -> BackendFuture<()> {
+        let folder = folder.to_owned();
+        let ids = to_owned_ids(ids);
+        let flags = flags.clone();
+        self.spawn(move |backend| backend.set_flags(&folder, to_borrowed_ids(&ids), &flags))
+    }
+
+    /// Synchronizes the given account with the wrapped backend as
+    /// remote, like [`BackendSyncBuilder::sync`] with the default
+    /// options. The synchronization occupies one worker until it is
+    /// done.
+    pub fn sync(&self, account_config: AccountConfig) -> BackendFuture<BackendSyncReport> {
+        self.spawn(move |backend| BackendSyncBuilder::new(&account_config).sync(backend))
+    }
+}
+
+/// Represents the result of an operation of [`AsyncBackend`],
+/// available once a worker has run it.
+pub struct BackendFuture<T>(Arc<Mutex<FutureState<T>>>);
+
+struct FutureState<T> {
+    result: Option<Result<T>>,
+    waker: Option<Waker>,
+}
+
+impl<T> Future for BackendFuture<T> {
+    type Output = Result<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = lock(&self.0);
+
+        match state.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Resolves a [`BackendFuture`]. If it is dropped without having
+/// resolved the future, for example because the operation panicked,
+/// the future resolves with [`Error::AsyncWorkerStoppedError`] so that
+/// it never hangs.
+struct Completer<T>(Arc<Mutex<FutureState<T>>>);
+
+impl<T> Completer<T> {
+    fn complete(self, result: Result<T>) {
+        self.resolve(result)
+    }
+
+    fn resolve(&self, result: Result<T>) {
+        let mut state = lock(&self.0);
+        if state.result.is_none() {
+            state.result = Some(result);
+        }
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Drop for Completer<T> {
+    fn drop(&mut self) {
+        self.resolve(Err(Error::AsyncWorkerStoppedError))
+    }
+}
+
+/// Locks the given mutex, ignoring poisoning: the guarded data stays
+/// consistent since the critical sections cannot panic.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+fn to_owned_ids(ids: Vec<&str>) -> Vec<String> {
+    ids.into_iter().map(ToOwned::to_owned).collect()
+}
+
+fn to_borrowed_ids(ids: &[String]) -> Vec<&str> {
+    ids.iter().map(String::as_str).collect()
+}
+
+#[cfg(all(test, feature = "memory-backend"))]
+mod async_backend {
+    use std::{
+        borrow::Cow,
+        future::Future,
+        sync::Arc,
+        task::{Context, Poll, Wake},
+        thread::{self, Thread},
+    };
+
+    use crate::{AccountConfig, CompilerBuilder, Flag, Flags, MemoryBackend, TplBuilder};
+
+    use super::AsyncBackend;
+
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark()
+        }
+    }
+
+    /// Runs the given future to completion on the current thread.
+    fn block_on<F: Future>(fut: F) -> F::Output {
+        let mut fut = Box::pin(fut);
+        let waker = Arc::new(ThreadWaker(thread::current())).into();
+        let mut cx = Context::from_waker(&waker);
+
+        loop {
+            match fut.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn operations() {
+        let account_config = AccountConfig::default();
+        let memory = MemoryBackend::new(Cow::Owned(account_config));
+        let backend = AsyncBackend::with_workers(memory, 2);
+
+        let email = TplBuilder::default()
+            .message_id("<a@localhost>")
+            .from("alice@localhost")
+            .to("bob@localhost")
+            .subject("A")
+            .text_plain_part("A")
+            .compile(CompilerBuilder::default())
+            .unwrap();
+
+        let id = block_on(backend.add_email("INBOX", &email, &Flags::default())).unwrap();
+        let envelopes = block_on(backend.list_envelopes("INBOX", 0, 0)).unwrap();
+        assert_eq!(1, envelopes.len());
+        assert_eq!("A", envelopes[0].subject);
+
+        let flags = Flags::from_iter([Flag::Flagged]);
+        block_on(backend.set_flags("INBOX", vec![&id], &flags)).unwrap();
+        let emails = block_on(backend.get_emails("INBOX", vec![&id])).unwrap();
+        assert_eq!(1, emails.to_vec().len());
+
+        // operations can run concurrently, their futures resolve
+        // independently
+        let futures: Vec<_> = (0..10)
+            .map(|_| backend.list_envelopes("INBOX", 0, 0))
+            .collect();
+        for fut in futures {
+            assert_eq!(1, block_on(fut).unwrap().len());
+        }
+    }
+}
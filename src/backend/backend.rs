@@ -3,14 +3,26 @@
 //! This module exposes the backend trait, which can be used to create
 //! custom backend implementations.
 
-use log::info;
-use proc_lock::{lock, LockPath};
-use std::{any::Any, borrow::Cow, fmt, io, result};
+use chrono::Utc;
+use log::{info, warn};
+use mailparse::MailHeaderMap;
+use proc_lock::{try_lock, LockGuard, LockPath};
+use serde::{Serialize, Serializer};
+use std::{
+    any::Any,
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    fmt, fs, io, iter,
+    path::{Path, PathBuf},
+    process, result, thread,
+    time::{Duration, Instant, SystemTime},
+};
 use thiserror::Error;
 
 use crate::{
-    account, backend, email, envelope, folder, id_mapper, AccountConfig, BackendConfig, Emails,
-    Envelope, Envelopes, Flags, Folders, ImapBackendBuilder, MaildirConfig,
+    account, backend, email, envelope, folder, id_mapper, process, trace, AccountConfig,
+    BackendConfig, Emails, Envelope, Envelopes, Flag, Flags, Folder, FolderPermission,
+    FolderStatus, Folders, ImapBackendBuilder, MaildirConfig,
 };
 
 #[cfg(feature = "maildir-backend")]
@@ -19,14 +31,43 @@ use crate::MaildirBackend;
 #[cfg(feature = "notmuch-backend")]
 use crate::NotmuchBackend;
 
+#[cfg(feature = "pop3-backend")]
+use crate::Pop3Backend;
+
+#[cfg(feature = "jmap-backend")]
+use crate::JmapBackend;
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("cannot build backend with an empty config")]
     BuildBackendError,
     #[error("cannot lock synchronization for account {1}")]
     SyncAccountLockError(io::Error, String),
+    #[error("cannot synchronize account {0}: another synchronization is running ({1})")]
+    SyncAlreadyRunningError(String, String),
+    #[error("cannot unlock synchronization for account {1}")]
+    SyncAccountUnlockError(io::Error, String),
+    #[error("cannot execute {1} hook")]
+    ExecuteSyncHookError(#[source] process::Error, String),
     #[error("synchronization not enabled for account {0}")]
     SyncNotEnabled(String),
+    #[error("operation not supported by the backend")]
+    NotSupported,
+    #[error("cannot find email by internal id {0}")]
+    FindEmailError(String),
+    #[error("cannot find remote email with message id {0}")]
+    FindRemoteEmailError(String),
+    #[error("cannot purge email with message id {0}: still found in {1} of folder {2}")]
+    PurgeMessageError(String, String, String),
+    #[error("cannot update flags of emails {}: emails {} left updated", failed.join(", "), updated.join(", "))]
+    PartialFlagsUpdateError {
+        /// The ids of the emails left with their previous flags,
+        /// starting with the one whose update failed.
+        failed: Vec<String>,
+        /// The ids of the emails left with their new flags, because
+        /// restoring their previous flags failed.
+        updated: Vec<String>,
+    },
     #[error(transparent)]
     EmailError(#[from] email::Error),
     #[error(transparent)]
@@ -43,16 +84,74 @@ pub enum Error {
     #[cfg(feature = "imap-backend")]
     #[error(transparent)]
     ImapBackendError(#[from] backend::imap::Error),
+    #[cfg(feature = "jmap-backend")]
+    #[error(transparent)]
+    JmapBackendError(#[from] backend::jmap::Error),
     #[cfg(feature = "maildir-backend")]
     #[error(transparent)]
     MaildirBackendError(#[from] backend::maildir::Error),
     #[cfg(feature = "notmuch-backend")]
     #[error(transparent)]
     NotmuchBackendError(#[from] backend::notmuch::Error),
+    #[cfg(feature = "memory-backend")]
+    #[error(transparent)]
+    MemoryBackendError(#[from] backend::memory::Error),
+    #[cfg(feature = "pop3-backend")]
+    #[error(transparent)]
+    Pop3BackendError(#[from] backend::pop3::Error),
+
+    #[cfg(feature = "async")]
+    #[error("cannot run backend operation: async backend worker stopped")]
+    AsyncWorkerStoppedError,
 }
 
 pub type Result<T> = result::Result<T, Error>;
 
+impl Error {
+    /// Returns `true` if the error comes from a write refused by the
+    /// backend, like a `STORE` in an IMAP folder selected read-only
+    /// or a Maildir directory without write permission.
+    pub fn is_permission_denied(&self) -> bool {
+        #[cfg(feature = "memory-backend")]
+        if let Self::MemoryBackendError(backend::memory::Error::ReadOnlyFolderError(_)) = self {
+            return true;
+        }
+
+        let mut source = std::error::Error::source(self);
+
+        while let Some(err) = source {
+            #[cfg(feature = "imap-backend")]
+            if let Some(imap::Error::No(no)) = err.downcast_ref::<imap::Error>() {
+                let info = no.information.to_lowercase();
+                if matches!(no.code, Some(imap_proto::ResponseCode::ReadOnly))
+                    || info.contains("read-only")
+                    || info.contains("permission denied")
+                {
+                    return true;
+                }
+            }
+
+            if let Some(err) = err.downcast_ref::<io::Error>() {
+                if err.kind() == io::ErrorKind::PermissionDenied {
+                    return true;
+                }
+            }
+
+            source = err.source();
+        }
+
+        false
+    }
+}
+
+/// Serializes the error as its display, so that the reports
+/// containing it can be serialized.
+impl Serialize for Error {
+    fn serialize<S: Serializer>(&self, s: S) -> result::Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
 pub trait Backend: Sync + Send {
     fn name(&self) -> String;
 
@@ -61,12 +160,101 @@ pub trait Backend: Sync + Send {
     fn purge_folder(&self, folder: &str) -> Result<()>;
     fn delete_folder(&self, folder: &str) -> Result<()>;
 
+    /// Renames the given folder, keeping its emails. Backends not
+    /// able to rename folders return [`Error::NotSupported`].
+    fn rename_folder(&self, _from_folder: &str, _to_folder: &str) -> Result<()> {
+        Err(Error::NotSupported)
+    }
+
+    /// Subscribes to the given folder, so that clients showing only
+    /// subscribed folders list it. Backends without subscriptions
+    /// return [`Error::NotSupported`].
+    fn subscribe_folder(&self, _folder: &str) -> Result<()> {
+        Err(Error::NotSupported)
+    }
+
+    /// Unsubscribes from the given folder, without deleting it.
+    /// Backends without subscriptions return [`Error::NotSupported`].
+    fn unsubscribe_folder(&self, _folder: &str) -> Result<()> {
+        Err(Error::NotSupported)
+    }
+
+    /// Gets the statistics of the given folder without listing its
+    /// envelopes, for example to display unread counts. Backends not
+    /// able to do it cheaply return [`Error::NotSupported`].
+    fn folder_status(&self, _folder: &str) -> Result<FolderStatus> {
+        Err(Error::NotSupported)
+    }
+
+    /// Tells whether the emails and flags of the given folder can be
+    /// changed. The synchronization skips the changes targeting
+    /// read-only folders (see
+    /// [`envelope::sync::ReadOnlyStrategy`]). The default
+    /// implementation considers every folder writable.
+    fn folder_permission(&self, _folder: &str) -> Result<FolderPermission> {
+        Ok(FolderPermission::ReadWrite)
+    }
+
+    /// Gets the statistics of all the folders, in the order of
+    /// [`Backend::list_folders`]. The default implementation gets
+    /// them folder by folder with [`Backend::folder_status`].
+    fn folders_status(&self) -> Result<Vec<(Folder, FolderStatus)>> {
+        self.list_folders()?
+            .iter()
+            .map(|folder| Ok((folder.clone(), self.folder_status(&folder.name)?)))
+            .collect()
+    }
+
     fn get_envelope(&self, folder: &str, id: &str) -> Result<Envelope>;
     fn get_envelope_internal(&self, folder: &str, internal_id: &str) -> Result<Envelope> {
         self.get_envelope(folder, internal_id)
     }
 
     fn list_envelopes(&self, folder: &str, page_size: usize, page: usize) -> Result<Envelopes>;
+
+    /// Lists the envelopes of the given folder page by page. Pages
+    /// are fetched lazily, when the returned iterator is advanced,
+    /// so that callers can stop early without listing the whole
+    /// folder. The iteration stops after the first error.
+    ///
+    /// The default implementation calls
+    /// [`Backend::list_envelopes`] with increasing page numbers.
+    fn envelope_pages(
+        &self,
+        folder: &str,
+        page_size: usize,
+    ) -> Result<Box<dyn Iterator<Item = Result<Envelopes>> + Send + '_>> {
+        let folder = folder.to_owned();
+        let mut page = 0;
+        let mut done = false;
+
+        let pages = iter::from_fn(move || {
+            if done {
+                return None;
+            }
+
+            let envelopes = match self.list_envelopes(&folder, page_size, page) {
+                Ok(envelopes) => envelopes,
+                Err(err) => {
+                    done = true;
+                    return Some(Err(err));
+                }
+            };
+
+            // a page size of 0 means that the first page contains
+            // all the envelopes
+            page += 1;
+            done = page_size == 0 || envelopes.len() < page_size;
+
+            if envelopes.is_empty() {
+                None
+            } else {
+                Some(Ok(envelopes))
+            }
+        });
+
+        Ok(Box::new(pages))
+    }
     fn search_envelopes(
         &self,
         folder: &str,
@@ -81,16 +269,59 @@ pub trait Backend: Sync + Send {
         self.add_email(folder, email, flags)
     }
 
+    /// Gets the emails matching the given ids without altering their
+    /// flags: previewing an email never marks it as seen. This is
+    /// what the synchronization uses to copy emails between
+    /// backends (IMAP backends fetch `BODY.PEEK[]`).
     fn preview_emails(&self, folder: &str, ids: Vec<&str>) -> Result<Emails>;
     fn preview_emails_internal(&self, folder: &str, internal_ids: Vec<&str>) -> Result<Emails> {
         self.preview_emails(folder, internal_ids)
     }
 
+    /// Gets the header block of the email matching the given internal
+    /// id. The default implementation downloads the whole email,
+    /// backends should override it when they can fetch headers only.
+    fn get_email_headers_internal(&self, folder: &str, internal_id: &str) -> Result<Vec<u8>> {
+        let emails = self.preview_emails_internal(folder, vec![internal_id])?;
+        let emails = emails.to_vec();
+        let email = emails
+            .first()
+            .ok_or_else(|| Error::FindEmailError(internal_id.to_owned()))?;
+        let headers = envelope::sync::partial::extract_headers(email.raw()?);
+        Ok(headers.to_vec())
+    }
+
+    /// Gets the emails matching the given ids, like a client reading
+    /// them would: unlike [`Backend::preview_emails`], the emails are
+    /// marked as seen.
     fn get_emails(&self, folder: &str, ids: Vec<&str>) -> Result<Emails>;
     fn get_emails_internal(&self, folder: &str, internal_ids: Vec<&str>) -> Result<Emails> {
         self.get_emails(folder, internal_ids)
     }
 
+    /// Gets the emails matching the given ids, marking them as seen
+    /// only if `mark_seen` is `true` (see [`Backend::get_emails`] and
+    /// [`Backend::preview_emails`]).
+    fn fetch_emails(&self, folder: &str, ids: Vec<&str>, mark_seen: bool) -> Result<Emails> {
+        if mark_seen {
+            self.get_emails(folder, ids)
+        } else {
+            self.preview_emails(folder, ids)
+        }
+    }
+    fn fetch_emails_internal(
+        &self,
+        folder: &str,
+        internal_ids: Vec<&str>,
+        mark_seen: bool,
+    ) -> Result<Emails> {
+        if mark_seen {
+            self.get_emails_internal(folder, internal_ids)
+        } else {
+            self.preview_emails_internal(folder, internal_ids)
+        }
+    }
+
     fn copy_emails(&self, from_folder: &str, to_folder: &str, ids: Vec<&str>) -> Result<()>;
     fn copy_emails_internal(
         &self,
@@ -116,6 +347,202 @@ pub trait Backend: Sync + Send {
         self.delete_emails(folder, internal_ids)
     }
 
+    /// Permanently removes the emails of the given folder flagged as
+    /// deleted. The default implementation does nothing, which suits
+    /// backends removing emails immediately.
+    fn expunge_folder(&self, _folder: &str) -> Result<()> {
+        Ok(())
+    }
+
+    /// Permanently removes the given emails of the given folder
+    /// flagged as deleted, leaving the other emails flagged as
+    /// deleted untouched. The default implementation does nothing,
+    /// which suits backends removing emails immediately.
+    fn expunge_emails_internal(&self, _folder: &str, _internal_ids: Vec<&str>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Returns the highest modification sequence of the given folder
+    /// along with its amount of emails, when the backend supports
+    /// [RFC 7162] (CONDSTORE). The default implementation returns
+    /// `None`.
+    ///
+    /// [RFC 7162]: https://www.rfc-editor.org/rfc/rfc7162
+    fn highest_modseq(&self, _folder: &str) -> Result<Option<(u64, usize)>> {
+        Ok(None)
+    }
+
+    /// Lists the envelopes of the given folder changed since the
+    /// given modification sequence (see [`Backend::highest_modseq`]),
+    /// new emails included. Removed emails are not reported. The
+    /// default implementation fails with [`Error::NotSupported`].
+    fn list_changed_envelopes_since_modseq(
+        &self,
+        _folder: &str,
+        _modseq: u64,
+    ) -> Result<Envelopes> {
+        Err(Error::NotSupported)
+    }
+
+    /// Lists all the envelopes of the given folders, keyed by folder.
+    /// The default implementation calls [`Backend::list_envelopes`]
+    /// for each folder, backends should override it when folders can
+    /// be listed in fewer round-trips.
+    fn list_envelopes_multi(&self, folders: &[&str]) -> Result<HashMap<String, Envelopes>> {
+        let mut envelopes_by_folder = HashMap::with_capacity(folders.len());
+        for folder in folders {
+            envelopes_by_folder.insert(folder.to_string(), self.list_envelopes(folder, 0, 0)?);
+        }
+        Ok(envelopes_by_folder)
+    }
+
+    /// Searches the ids of the emails of the given folder matching
+    /// the given Message-ID, with or without surrounding angle
+    /// brackets. The default implementation lists all the envelopes
+    /// of the folder, backends should override it with a native
+    /// search when possible.
+    fn search_ids_by_message_id(&self, folder: &str, message_id: &str) -> Result<Vec<String>> {
+        let ids = self
+            .list_envelopes(folder, 0, 0)?
+            .iter()
+            .filter(|envelope| envelope.has_message_id(message_id))
+            .map(|envelope| envelope.id.clone())
+            .collect();
+
+        Ok(ids)
+    }
+
+    /// Searches the ids of the emails of the given folder having a
+    /// header with the given name containing the given value. The default
+    /// implementation fetches the headers of all the emails of the
+    /// folder, backends should override it with a native search when
+    /// possible.
+    fn search_ids_by_header(&self, folder: &str, header: &str, value: &str) -> Result<Vec<String>> {
+        let mut ids = Vec::new();
+
+        for envelope in self.list_envelopes(folder, 0, 0)?.iter() {
+            let headers = match self.get_email_headers_internal(folder, &envelope.internal_id) {
+                Ok(headers) => headers,
+                Err(err) => {
+                    warn!(
+                        "cannot get headers of email {}, skipping it: {err}",
+                        envelope.id
+                    );
+                    continue;
+                }
+            };
+
+            let matches = mailparse::parse_headers(&headers)
+                .map(|(headers, _)| {
+                    headers
+                        .get_all_values(header)
+                        .iter()
+                        .any(|header_value| header_value.contains(value))
+                })
+                .unwrap_or_default();
+            if matches {
+                ids.push(envelope.id.clone());
+            }
+        }
+
+        Ok(ids)
+    }
+
+    /// Finds the first email matching the given Message-ID, with or
+    /// without surrounding angle brackets, and returns its folder
+    /// along with its envelope. When no folder is given, all folders
+    /// are searched in the order of [`Backend::list_folders`].
+    ///
+    /// The default implementation relies on
+    /// [`Backend::search_ids_by_message_id`], which IMAP backends
+    /// implement with a `UID SEARCH HEADER Message-ID`.
+    fn find_by_message_id(
+        &self,
+        folder: Option<&str>,
+        message_id: &str,
+    ) -> Result<Option<(String, Envelope)>> {
+        let folders = match folder {
+            Some(folder) => vec![folder.to_owned()],
+            None => self
+                .list_folders()?
+                .iter()
+                .map(|folder| folder.name.clone())
+                .collect(),
+        };
+
+        for folder in folders {
+            if let Some(id) = self.search_ids_by_message_id(&folder, message_id)?.first() {
+                let envelope = self.get_envelope(&folder, id)?;
+                return Ok(Some((folder, envelope)));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Marks the given emails as answered.
+    fn mark_answered(&self, folder: &str, ids: Vec<&str>) -> Result<()> {
+        self.add_flags(folder, ids, &Flags::from_iter([Flag::Answered]))
+    }
+
+    /// Marks as answered the emails matching the given Message-ID,
+    /// typically the In-Reply-To header of a reply that has just been
+    /// sent (see [`crate::Email::in_reply_to`]). When no folder is
+    /// given, all folders are searched. Returns the amount of emails
+    /// marked as answered.
+    fn mark_answered_by_message_id(&self, folder: Option<&str>, message_id: &str) -> Result<usize> {
+        let folders = match folder {
+            Some(folder) => vec![folder.to_owned()],
+            None => self
+                .list_folders()?
+                .iter()
+                .map(|folder| folder.name.clone())
+                .collect(),
+        };
+
+        let mut count = 0;
+        for folder in folders {
+            let ids = self.search_ids_by_message_id(&folder, message_id)?;
+            if !ids.is_empty() {
+                count += ids.len();
+                self.mark_answered(&folder, ids.iter().map(String::as_str).collect())?;
+            }
+        }
+
+        Ok(count)
+    }
+
+    /// Marks all the emails of the given folder as seen.
+    ///
+    /// The default implementation lists the envelopes, then adds the
+    /// [`Flag::Seen`] flag to the unseen ones. Backends override it
+    /// with a bulk operation: IMAP backends send a single `STORE`
+    /// command, Maildir backends rename the files and Notmuch
+    /// backends tag the emails matching the folder query. See
+    /// [`BackendSyncBuilder::mark_all_seen`] to keep the
+    /// synchronization cache up to date.
+    fn mark_all_seen(&self, folder: &str) -> Result<()> {
+        let envelopes = self.list_envelopes(folder, 0, 0)?;
+        let ids: Vec<&str> = envelopes
+            .iter()
+            .filter(|envelope| !envelope.flags.contains(&Flag::Seen))
+            .map(|envelope| envelope.id.as_str())
+            .collect();
+
+        if ids.is_empty() {
+            return Ok(());
+        }
+
+        self.add_flags(folder, ids, &Flags::from_iter([Flag::Seen]))
+    }
+
+    /// Adds the given flags to the emails, keeping their other flags.
+    ///
+    /// Unlike [`Backend::set_flags`], this does not require reading
+    /// the current flags first, which would race with other clients
+    /// changing flags concurrently: IMAP backends send a `+FLAGS`
+    /// store, Maildir backends add the flags to the file names and
+    /// Notmuch backends add the matching tags.
     fn add_flags(&self, folder: &str, ids: Vec<&str>, flags: &Flags) -> Result<()>;
     fn add_flags_internal(
         &self,
@@ -126,6 +553,7 @@ pub trait Backend: Sync + Send {
         self.add_flags(folder, internal_ids, flags)
     }
 
+    /// Replaces all the flags of the emails by the given ones.
     fn set_flags(&self, folder: &str, ids: Vec<&str>, flags: &Flags) -> Result<()>;
     fn set_flags_internal(
         &self,
@@ -136,6 +564,8 @@ pub trait Backend: Sync + Send {
         self.set_flags(folder, internal_ids, flags)
     }
 
+    /// Removes the given flags from the emails, keeping their other
+    /// flags. See [`Backend::add_flags`].
     fn remove_flags(&self, folder: &str, ids: Vec<&str>, flags: &Flags) -> Result<()>;
     fn remove_flags_internal(
         &self,
@@ -150,10 +580,113 @@ pub trait Backend: Sync + Send {
         Ok(())
     }
 
+    /// Subscribes to the backend events: new envelopes, flags
+    /// changes and folders creation. The returned stream blocks until
+    /// the next event is available.
+    fn subscribe(&self) -> Result<Box<dyn BackendEventStream + '_>> {
+        Err(Error::NotSupported)
+    }
+
     // INFO: for downcasting purpose
     fn as_any(&'static self) -> &(dyn Any);
 }
 
+/// Represents an event emitted by a backend subscription (see
+/// [`Backend::subscribe`]).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum BackendEvent {
+    NewEnvelope { folder: String, envelope: Envelope },
+    FlagsChanged { folder: String, envelope: Envelope },
+    FolderCreated(String),
+}
+
+/// Represents a blocking stream of backend events.
+pub trait BackendEventStream: Iterator<Item = Result<BackendEvent>> + Send {}
+
+impl<T: Iterator<Item = Result<BackendEvent>> + Send> BackendEventStream for T {}
+
+/// Represents the default amount of emails transferred between two
+/// progress reports (see [`transfer_emails`]).
+pub const DEFAULT_TRANSFER_BATCH_SIZE: usize = 50;
+
+/// Represents the report of [`transfer_emails`].
+#[derive(Debug, Default, Serialize)]
+pub struct TransferReport {
+    /// Represents the transferred emails, as pairs of source id and
+    /// destination id.
+    pub transferred: Vec<(String, String)>,
+    /// Represents the source ids of the emails which could not be
+    /// transferred, with the reason. They can be given back to
+    /// [`transfer_emails`] in order to retry the transfer.
+    pub failed: Vec<(String, Error)>,
+}
+
+impl TransferReport {
+    /// Returns the source ids of the emails which could not be
+    /// transferred.
+    pub fn failed_ids(&self) -> Vec<&str> {
+        self.failed.iter().map(|(id, _)| id.as_str()).collect()
+    }
+}
+
+/// Transfers the given emails from a folder of the source backend to
+/// a folder of the destination backend, which can be of different
+/// kinds (for example from an IMAP server to a Maildir archive).
+///
+/// Raw emails are appended to the destination with their flags. The
+/// Date header is kept as it is part of the raw email, the internal
+/// date of the destination email is up to the destination backend.
+/// Source emails are previewed, so they are not marked as seen, and
+/// they are never deleted: callers wanting to move emails can delete
+/// the transferred ones from the source afterwards.
+///
+/// Emails are processed by batches of `batch_size`, the given
+/// callback receiving the amount of processed emails and the total
+/// after each batch. A failure does not stop the transfer, failed
+/// ids are collected in the report instead.
+pub fn transfer_emails<'a>(
+    src: &dyn Backend,
+    src_folder: &str,
+    dst: &dyn Backend,
+    dst_folder: &str,
+    ids: impl IntoIterator<Item = &'a str>,
+    batch_size: usize,
+    on_progress: impl Fn(usize, usize),
+) -> TransferReport {
+    let ids: Vec<&str> = ids.into_iter().collect();
+    let total = ids.len();
+    let mut report = TransferReport::default();
+
+    let transfer_email = |id: &str| -> Result<String> {
+        let flags = src.get_envelope(src_folder, id)?.flags;
+        let emails = src.preview_emails(src_folder, vec![id])?;
+        let emails = emails.to_vec();
+        let email = emails
+            .first()
+            .ok_or_else(|| Error::FindEmailError(id.to_owned()))?;
+        dst.add_email(dst_folder, email.raw()?, &flags)
+    };
+
+    let mut processed = 0;
+    for batch in ids.chunks(batch_size.max(1)) {
+        for id in batch {
+            info!("transferring email {id} from {src_folder} to {dst_folder}");
+            match transfer_email(id) {
+                Ok(dst_id) => report.transferred.push((id.to_string(), dst_id)),
+                Err(err) => {
+                    warn!("cannot transfer email {id}, skipping it: {err}");
+                    report.failed.push((id.to_string(), err));
+                }
+            }
+        }
+
+        processed += batch.len();
+        on_progress(processed, total);
+    }
+
+    report
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum BackendSyncProgressEvent {
     GetLocalCachedFolders,
@@ -172,6 +705,12 @@ pub enum BackendSyncProgressEvent {
     BuildEnvelopesPatch,
     ProcessEnvelopesPatch(usize),
     ProcessEnvelopeHunk(String),
+    /// Represents the amount of remote emails about to be downloaded
+    /// and their estimated size in bytes.
+    StartEmailsDownload(usize, u64),
+    /// Represents the estimated amount of bytes downloaded so far,
+    /// out of the estimated total.
+    DownloadEmail(u64, u64),
 }
 
 impl fmt::Display for BackendSyncProgressEvent {
@@ -193,23 +732,137 @@ impl fmt::Display for BackendSyncProgressEvent {
             Self::BuildEnvelopesPatch => write!(f, "Building envelopes patch"),
             Self::ProcessEnvelopesPatch(n) => write!(f, "Processing {n} hunks of envelopes patch"),
             Self::ProcessEnvelopeHunk(s) => write!(f, "Processing envelope hunk: {s}"),
+            Self::StartEmailsDownload(n, bytes) => {
+                write!(f, "Downloading {n} emails (about {bytes} bytes)")
+            }
+            Self::DownloadEmail(done, total) => {
+                write!(f, "Downloaded about {done} of {total} bytes")
+            }
         }
     }
 }
 
-#[derive(Debug, Default)]
+/// Represents how the synchronization engines process their patches.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum SyncExecutor<'a> {
+    /// Processes hunks in parallel using the global rayon thread
+    /// pool.
+    #[default]
+    GlobalPool,
+    /// Processes hunks in parallel using the given rayon thread pool,
+    /// so that the synchronization does not compete with the other
+    /// users of the global pool.
+    Pool(&'a rayon::ThreadPool),
+    /// Processes hunks sequentially on the calling thread, without
+    /// rayon. Backends may still use rayon internally, for example
+    /// to list Maildir envelopes.
+    SingleThreaded,
+}
+
+#[derive(Debug, Default, Serialize)]
 pub struct BackendSyncReport {
     pub folders: folder::sync::FoldersName,
+    /// Represents the folders which could not be created on one side,
+    /// whose envelopes have not been synchronized (see
+    /// [`folder::sync::SyncReport::failed_folders`]).
+    pub folders_failed: folder::sync::FoldersName,
     pub folders_patch: Vec<(folder::sync::Hunk, Option<folder::sync::Error>)>,
     pub folders_cache_patch: (Vec<folder::sync::CacheHunk>, Option<folder::sync::Error>),
-    pub envelopes_patch: Vec<(envelope::sync::BackendHunk, Option<envelope::sync::Error>)>,
+    pub envelopes_patch: Vec<envelope::sync::BackendHunk>,
+    pub envelopes_failed_hunks: Vec<(envelope::sync::BackendHunk, envelope::sync::Error)>,
     pub envelopes_cache_patch: (Vec<envelope::sync::CacheHunk>, Vec<envelope::sync::Error>),
+    /// Represents the placeholders of the emails which could not be
+    /// parsed, see [`envelope::sync::SyncReport::unparsable_envelopes`].
+    pub envelopes_unparsable: Vec<(String, Envelope, envelope::sync::HunkKindRestricted)>,
+    /// Represents the hunks targeting read-only folders, see
+    /// [`envelope::sync::SyncReport::read_only`].
+    pub envelopes_read_only: Vec<(
+        String,
+        envelope::sync::HunkKindRestricted,
+        Vec<envelope::sync::BackendHunk>,
+    )>,
+    /// Represents the folders whose envelopes synchronization timed
+    /// out (see [`BackendSyncBuilder::folder_timeout`]). Their cache
+    /// is repaired by the next synchronization.
+    pub timed_out_folders: folder::sync::FoldersName,
+    /// Represents the errors of the synchronization hooks, which do
+    /// not abort the synchronization (see
+    /// [`BackendSyncBuilder::pre_sync`]).
+    pub hooks_errors: Vec<Error>,
+}
+
+/// Represents the report of [`BackendSyncBuilder::rebuild_cache`].
+#[derive(Debug, Default, Serialize)]
+pub struct RebuildCacheReport {
+    /// Represents the folders present on both sides, whose cache has
+    /// been rebuilt.
+    pub folders: folder::sync::FoldersName,
+    /// Represents the folders present local side only.
+    pub local_only_folders: folder::sync::FoldersName,
+    /// Represents the folders present remote side only.
+    pub remote_only_folders: folder::sync::FoldersName,
+    /// Represents the envelopes present local side only, with their
+    /// folder.
+    pub local_only_envelopes: Vec<(String, Envelope)>,
+    /// Represents the envelopes present remote side only, with their
+    /// folder.
+    pub remote_only_envelopes: Vec<(String, Envelope)>,
+}
+
+/// Represents the report of [`BackendSyncBuilder::purge_message`].
+/// Each entry is a folder with the internal id of the email found
+/// and removed.
+#[derive(Debug, Default, Serialize)]
+pub struct PurgeReport {
+    /// Represents the emails removed from the local Maildir.
+    pub local: Vec<(String, String)>,
+    /// Represents the envelopes removed from the local cache.
+    pub local_cache: Vec<(String, String)>,
+    /// Represents the emails removed from the remote backend.
+    pub remote: Vec<(String, String)>,
+    /// Represents the envelopes removed from the remote cache.
+    pub remote_cache: Vec<(String, String)>,
+}
+
+impl PurgeReport {
+    /// Returns `true` if the email has not been found anywhere.
+    pub fn is_empty(&self) -> bool {
+        self.local.is_empty()
+            && self.local_cache.is_empty()
+            && self.remote.is_empty()
+            && self.remote_cache.is_empty()
+    }
 }
 
+/// Represents the name of the file locking the synchronization of an
+/// account, relative to its sync directory.
+const SYNC_LOCK_FILE: &str = ".sync.lock";
+
+/// Represents the delay between two attempts to lock the
+/// synchronization of an account.
+const SYNC_LOCK_RETRY_DELAY: Duration = Duration::from_millis(100);
+
 pub struct BackendSyncBuilder<'a> {
     account_config: &'a AccountConfig,
     on_progress: Box<dyn Fn(BackendSyncProgressEvent) -> Result<()> + Sync + Send + 'a>,
     dry_run: bool,
+    batch_size: usize,
+    parallelism: Option<usize>,
+    max_message_size: Option<u64>,
+    newest_first: bool,
+    hunk_retry_policy: envelope::sync::HunkRetryPolicy,
+    sync_deleted: envelope::sync::SyncDeletedBehavior,
+    conflict_resolution: envelope::sync::ConflictResolution,
+    read_only_strategy: envelope::sync::ReadOnlyStrategy,
+    folders_rename_threshold: f64,
+    executor: SyncExecutor<'a>,
+    audit_log: Option<PathBuf>,
+    systemic_error_threshold: Option<f64>,
+    folder_timeout: Option<Duration>,
+    lock_timeout: Option<Duration>,
+    pre_sync: Box<dyn Fn() -> Result<()> + Sync + Send + 'a>,
+    post_sync: Box<dyn Fn(&BackendSyncReport) -> Result<()> + Sync + Send + 'a>,
+    on_new_email: Box<dyn Fn(&Path, &Envelope) -> Result<()> + Sync + Send + 'a>,
 }
 
 impl<'a> BackendSyncBuilder<'a> {
@@ -218,6 +871,23 @@ impl<'a> BackendSyncBuilder<'a> {
             account_config,
             on_progress: Box::new(|_| Ok(())),
             dry_run: false,
+            batch_size: envelope::sync::DEFAULT_BATCH_SIZE,
+            parallelism: None,
+            max_message_size: None,
+            newest_first: false,
+            hunk_retry_policy: Default::default(),
+            sync_deleted: Default::default(),
+            conflict_resolution: Default::default(),
+            read_only_strategy: Default::default(),
+            folders_rename_threshold: folder::sync::DEFAULT_RENAME_THRESHOLD,
+            executor: SyncExecutor::default(),
+            audit_log: None,
+            systemic_error_threshold: None,
+            folder_timeout: None,
+            lock_timeout: None,
+            pre_sync: Box::new(|| Ok(())),
+            post_sync: Box::new(|_| Ok(())),
+            on_new_email: Box::new(|_, _| Ok(())),
         }
     }
 
@@ -234,70 +904,1012 @@ impl<'a> BackendSyncBuilder<'a> {
         self
     }
 
+    /// Sets the envelopes synchronization batch size, see
+    /// [`envelope::SyncBuilder::batch_size`].
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// Sets the envelopes synchronization thread count, see
+    /// [`envelope::SyncBuilder::parallelism`].
+    pub fn parallelism(mut self, parallelism: usize) -> Self {
+        self.parallelism = Some(parallelism);
+        self
+    }
+
+    /// Sets the size limit above which only the headers of remote
+    /// emails are downloaded, see
+    /// [`envelope::SyncBuilder::max_message_size`].
+    pub fn max_message_size(mut self, max_message_size: u64) -> Self {
+        self.max_message_size = Some(max_message_size);
+        self
+    }
+
+    /// Downloads the newest remote emails first, see
+    /// [`envelope::SyncBuilder::newest_first`].
+    pub fn newest_first(mut self, newest_first: bool) -> Self {
+        self.newest_first = newest_first;
+        self
+    }
+
+    /// Sets the envelopes synchronization hunk retry policy, see
+    /// [`envelope::SyncBuilder::hunk_retry_policy`].
+    pub fn hunk_retry_policy(mut self, policy: envelope::sync::HunkRetryPolicy) -> Self {
+        self.hunk_retry_policy = policy;
+        self
+    }
+
+    /// Sets how emails flagged as deleted are synchronized, see
+    /// [`envelope::SyncBuilder::sync_deleted`].
+    pub fn sync_deleted(mut self, sync_deleted: envelope::sync::SyncDeletedBehavior) -> Self {
+        self.sync_deleted = sync_deleted;
+        self
+    }
+
+    /// Sets how flags changed on both sides are synchronized, see
+    /// [`envelope::SyncBuilder::conflict_resolution`].
+    pub fn conflict_resolution(
+        mut self,
+        conflict_resolution: envelope::sync::ConflictResolution,
+    ) -> Self {
+        self.conflict_resolution = conflict_resolution;
+        self
+    }
+
+    /// Sets how changes targeting read-only folders are handled, see
+    /// [`envelope::SyncBuilder::read_only_strategy`].
+    pub fn read_only_strategy(mut self, strategy: envelope::sync::ReadOnlyStrategy) -> Self {
+        self.read_only_strategy = strategy;
+        self
+    }
+
+    /// Sets the folders synchronization rename threshold, see
+    /// [`folder::SyncBuilder::rename_threshold`].
+    pub fn folders_rename_threshold(mut self, threshold: f64) -> Self {
+        self.folders_rename_threshold = threshold;
+        self
+    }
+
+    /// Sets how the folders and the envelopes patches are processed.
+    /// Defaults to [`SyncExecutor::GlobalPool`].
+    pub fn executor(mut self, executor: SyncExecutor<'a>) -> Self {
+        self.executor = executor;
+        self
+    }
+
+    /// Appends the envelopes patches to the given JSON Lines file, see
+    /// [`envelope::SyncBuilder::audit_log`].
+    pub fn audit_log(mut self, path: PathBuf) -> Self {
+        self.audit_log = Some(path);
+        self
+    }
+
+    /// Aborts the envelopes synchronization of a folder on systemic
+    /// errors, see [`envelope::SyncBuilder::abort_on_systemic_error`].
+    pub fn abort_on_systemic_error(mut self, threshold: f64) -> Self {
+        self.systemic_error_threshold = Some(threshold);
+        self
+    }
+
+    /// Aborts the envelopes synchronization of a folder taking longer
+    /// than the given duration, see
+    /// [`envelope::SyncBuilder::folder_timeout`]. The synchronization
+    /// goes on with the next folder, the timed out folders are
+    /// reported in [`BackendSyncReport::timed_out_folders`].
+    pub fn folder_timeout(mut self, timeout: Duration) -> Self {
+        self.folder_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets how long to wait for another synchronization of the same
+    /// account to finish before failing with
+    /// [`Error::SyncAlreadyRunningError`]. By default, it waits as
+    /// long as needed.
+    pub fn lock_timeout(mut self, timeout: Duration) -> Self {
+        self.lock_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the callback called before the synchronization starts,
+    /// after the `pre_sync` hook command of the account (see
+    /// [`account::SyncHooks`]).
+    ///
+    /// Like the hook commands, a failing callback does not abort the
+    /// synchronization: its error is logged and reported in
+    /// [`BackendSyncReport::hooks_errors`].
+    pub fn pre_sync<F>(mut self, f: F) -> Self
+    where
+        F: Fn() -> Result<()> + Sync + Send + 'a,
+    {
+        self.pre_sync = Box::new(f);
+        self
+    }
+
+    /// Sets the callback called once the synchronization is done,
+    /// after the `post_sync` hook command of the account. See
+    /// [`BackendSyncBuilder::pre_sync`] for the error handling.
+    pub fn post_sync<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&BackendSyncReport) -> Result<()> + Sync + Send + 'a,
+    {
+        self.post_sync = Box::new(f);
+        self
+    }
+
+    /// Sets the callback called for every email downloaded by the
+    /// synchronization, with the path of the local email file and
+    /// its local envelope, after the `on_new_email` hook command of
+    /// the account. It is called once the folder of the email is
+    /// synchronized, and never in dry run mode. See
+    /// [`BackendSyncBuilder::pre_sync`] for the error handling.
+    pub fn on_new_email<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&Path, &Envelope) -> Result<()> + Sync + Send + 'a,
+    {
+        self.on_new_email = Box::new(f);
+        self
+    }
+
     pub fn sync(&self, remote: &dyn Backend) -> Result<BackendSyncReport> {
+        self.sync_folders(remote, None)
+    }
+
+    /// Runs the given hook command, then the given hook callback. The
+    /// errors are logged and collected instead of being returned.
+    fn run_hook(
+        &self,
+        hook: &str,
+        cmd: Option<&str>,
+        input: &[u8],
+        callback: impl FnOnce() -> Result<()>,
+        errs: &mut Vec<Error>,
+    ) {
+        if let Some(cmd) = cmd {
+            if let Err(err) = process::run(cmd, input) {
+                warn!("cannot execute {hook} hook, skipping it: {err}");
+                errs.push(Error::ExecuteSyncHookError(err, hook.to_owned()));
+            }
+        }
+
+        if let Err(err) = callback() {
+            warn!("error in {hook} hook, skipping it: {err}");
+            errs.push(err);
+        }
+    }
+
+    /// Locks the synchronization of the account, so that concurrent
+    /// runs (for example a cron job overlapping a manual one) cannot
+    /// work on the same cache. The lock is an advisory lock on a file
+    /// of the sync directory, released when the returned guard is
+    /// dropped, including on panic, or by the OS when the process
+    /// dies. The file contains the PID of the holder and the time it
+    /// got the lock, which are reported to the runs waiting for it.
+    fn lock(&self, sync_dir: &Path) -> Result<LockGuard> {
         let account = &self.account_config.name;
-        if !self.account_config.sync {
-            return Err(Error::SyncNotEnabled(account.clone()));
+        let lock_path = sync_dir.join(SYNC_LOCK_FILE);
+        let start = Instant::now();
+
+        loop {
+            match try_lock(&LockPath::FullPath(&lock_path)) {
+                Ok(guard) => {
+                    let holder = format!("pid {} since {}", process::id(), Utc::now().to_rfc3339());
+                    if let Err(err) = fs::write(&lock_path, holder) {
+                        warn!("cannot write synchronization lock holder, skipping it: {err}");
+                    }
+                    return Ok(guard);
+                }
+                Err(err) if is_lock_contended(&err) => {
+                    if matches!(self.lock_timeout, Some(timeout) if start.elapsed() >= timeout) {
+                        let holder = fs::read_to_string(&lock_path).unwrap_or_default();
+                        return Err(Error::SyncAlreadyRunningError(
+                            account.clone(),
+                            holder.trim().to_owned(),
+                        ));
+                    }
+                    thread::sleep(SYNC_LOCK_RETRY_DELAY);
+                }
+                Err(err) => return Err(Error::SyncAccountLockError(err, account.clone())),
+            }
         }
+    }
 
-        info!("starting synchronization");
-        let progress = &self.on_progress;
-        let sync_dir = self.account_config.sync_dir()?;
-        let lock_path = LockPath::Tmp(format!("himalaya-sync-{}.lock", account));
-        let guard =
-            lock(&lock_path).map_err(|err| Error::SyncAccountLockError(err, account.to_owned()))?;
+    /// Removes the synchronization lock file of the account, for
+    /// example when it has been left on a network file system by a
+    /// crashed run. Local crashes do not need it, since the OS
+    /// releases the lock along with the process. A synchronization
+    /// still running keeps its lock on the removed file, so this must
+    /// only be used when no other synchronization is running.
+    pub fn force_unlock(&self) -> Result<()> {
+        let account = &self.account_config.name;
+        let lock_path = self.account_config.sync_dir()?.join(SYNC_LOCK_FILE);
 
-        // init SQLite cache
+        match fs::remove_file(lock_path) {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(Error::SyncAccountUnlockError(err, account.clone())),
+        }
+    }
 
+    /// Opens the SQLite cache of the given sync directory, creating
+    /// or migrating the folders and envelopes tables if needed.
+    fn open_cache(sync_dir: &Path) -> Result<rusqlite::Connection> {
         let mut conn = rusqlite::Connection::open(sync_dir.join(".sync.sqlite"))?;
-
         folder::sync::Cache::init(&mut conn)?;
         envelope::sync::Cache::init(&mut conn)?;
+        Ok(conn)
+    }
 
-        // init local Maildir
+    /// Locks the synchronization of the account (see [`Self::lock`])
+    /// then opens its cache.
+    fn lock_cache(&self, sync_dir: &Path) -> Result<(LockGuard, rusqlite::Connection)> {
+        let guard = self.lock(sync_dir)?;
+        let conn = Self::open_cache(sync_dir)?;
+        Ok((guard, conn))
+    }
 
-        let local = MaildirBackend::new(
+    /// Builds the Maildir backend of the given sync directory, which
+    /// holds the local copy of the account.
+    fn local_backend(&self, sync_dir: &Path) -> Result<MaildirBackend<'a>> {
+        Ok(MaildirBackend::new(
             Cow::Borrowed(self.account_config),
             Cow::Owned(MaildirConfig {
-                root_dir: sync_dir.clone(),
+                root_dir: sync_dir.to_owned(),
             }),
+        )?)
+    }
+
+    /// Lists the canonical names of the folders of the given backend
+    /// (see [`folder::canonical_folder_name`]).
+    fn folder_names(backend: &dyn Backend) -> Result<folder::sync::FoldersName> {
+        Ok(backend
+            .list_folders()?
+            .iter()
+            .map(|folder| folder::canonical_folder_name(&folder.name))
+            .collect())
+    }
+
+    /// Returns `true` if the time elapsed since the last successful
+    /// synchronization exceeds [`AccountConfig::sync_interval_secs`],
+    /// so that callers can poll it instead of implementing their own
+    /// timers. Folders are synchronized independently, the least
+    /// recently synchronized one decides. Accounts without sync
+    /// interval or never synchronized are always due.
+    pub fn should_sync(&self) -> Result<bool> {
+        let account = &self.account_config.name;
+        if !self.account_config.sync {
+            return Err(Error::SyncNotEnabled(account.clone()));
+        }
+
+        let interval = match self.account_config.sync_interval() {
+            Some(interval) => interval,
+            None => return Ok(true),
+        };
+
+        let sync_dir = self.account_config.sync_dir()?;
+        let mut conn = rusqlite::Connection::open(sync_dir.join(".sync.sqlite"))?;
+        envelope::sync::Cache::init(&mut conn)?;
+
+        let should_sync = match envelope::sync::Cache::get_last_synced_at(&conn, account)? {
+            // a last synchronization in the future (clock change)
+            // gives a negative duration, the account is then due
+            Some(last_synced_at) => Utc::now()
+                .signed_duration_since(last_synced_at)
+                .to_std()
+                .map(|elapsed| elapsed >= interval)
+                .unwrap_or(true),
+            None => true,
+        };
+
+        Ok(should_sync)
+    }
+
+    /// Replaces the partial local copy of the email matching the
+    /// given local internal id by the complete remote email (see
+    /// [`envelope::SyncBuilder::max_message_size`]). The flags of the
+    /// local copy are kept. Returns the new local internal id, which
+    /// is the given one if the local copy is not partial.
+    pub fn fetch_full(
+        &self,
+        remote: &dyn Backend,
+        folder: &str,
+        internal_id: &str,
+    ) -> Result<String> {
+        let account = &self.account_config.name;
+        let folder = &folder::canonical_folder_name(folder);
+        if !self.account_config.sync {
+            return Err(Error::SyncNotEnabled(account.clone()));
+        }
+
+        let sync_dir = self.account_config.sync_dir()?;
+        let guard = self.lock(&sync_dir)?;
+
+        let mut conn = rusqlite::Connection::open(sync_dir.join(".sync.sqlite"))?;
+        envelope::sync::Cache::init(&mut conn)?;
+
+        let local = self.local_backend(&sync_dir)?;
+
+        let local_envelope = local.get_envelope_internal(folder, internal_id)?;
+        let message_id = &local_envelope.message_id;
+
+        if !envelope::sync::Cache::is_local_partial(&conn, account, folder, message_id)? {
+            info!("local email {internal_id} is not partial, skipping it");
+            return Ok(internal_id.to_owned());
+        }
+
+        let remote_internal_id =
+            envelope::sync::Cache::list_remote_envelopes(&mut conn, account, folder)?
+                .iter()
+                .find(|envelope| &envelope.message_id == message_id)
+                .map(|envelope| envelope.internal_id.clone())
+                .ok_or_else(|| Error::FindRemoteEmailError(message_id.clone()))?;
+
+        let emails = remote.preview_emails_internal(folder, vec![&remote_internal_id])?;
+        let emails = emails.to_vec();
+        let email = emails
+            .first()
+            .ok_or_else(|| Error::FindRemoteEmailError(message_id.clone()))?;
+
+        let next_internal_id =
+            local.add_email_internal(folder, email.raw()?, &local_envelope.flags)?;
+        local.delete_emails_internal(folder, vec![internal_id])?;
+        let next_envelope = local.get_envelope_internal(folder, &next_internal_id)?;
+
+        let tx = conn.transaction()?;
+        envelope::sync::Cache::delete_local_envelope(&tx, account, folder, internal_id)?;
+        envelope::sync::Cache::insert_local_envelope(
+            &tx,
+            account,
+            folder,
+            next_envelope.clone_without_custom_flags(),
         )?;
+        envelope::sync::Cache::set_local_partial(&tx, account, folder, message_id, None)?;
+        tx.commit()?;
+
+        drop(guard);
+
+        Ok(next_internal_id)
+    }
+
+    /// Rebuilds the synchronization cache from the current state of
+    /// the local Maildir and of the given remote backend, for example
+    /// after the cache got corrupted.
+    ///
+    /// Folders and envelopes present on both sides are matched by
+    /// name and by message id, then cached as if they had just been
+    /// synchronized, without copying nor removing any email. Only the
+    /// flags set on both sides are cached, so that the next
+    /// synchronization adds the other ones to the other side instead
+    /// of removing them. Unmatched folders and envelopes are not
+    /// cached: they are reported so that the user can decide to run
+    /// a normal synchronization afterwards, which would copy them.
+    /// Nothing is written in dry run mode.
+    pub fn rebuild_cache(&self, remote: &dyn Backend) -> Result<RebuildCacheReport> {
+        let account = &self.account_config.name;
+        if !self.account_config.sync {
+            return Err(Error::SyncNotEnabled(account.clone()));
+        }
+
+        info!("rebuilding synchronization cache of account {account}");
+
+        let sync_dir = self.account_config.sync_dir()?;
+        let (guard, mut conn) = self.lock_cache(&sync_dir)?;
+
+        let local = self.local_backend(&sync_dir)?;
+
+        let local_folders = Self::folder_names(&local)?;
+        let remote_folders = Self::folder_names(remote)?;
+
+        let envelopes_by_message_id = |envelopes: Envelopes| -> envelope::sync::Envelopes {
+            envelopes
+                .iter()
+                .filter(|envelope| !envelope.is_unparsable())
+                .map(|envelope| {
+                    (
+                        envelope.message_id.clone(),
+                        envelope.clone_without_custom_flags(),
+                    )
+                })
+                .collect()
+        };
+
+        let mut report = RebuildCacheReport::default();
+        let tx = conn.transaction()?;
+
+        folder::sync::Cache::clear(&tx, account)?;
+        envelope::sync::Cache::clear(&tx, account)?;
+
+        for name in local_folders.intersection(&remote_folders) {
+            folder::sync::Cache::insert_local_folder(&tx, account, name)?;
+            folder::sync::Cache::insert_remote_folder(&tx, account, name)?;
+
+            let local_envelopes = envelopes_by_message_id(local.list_envelopes(name, 0, 0)?);
+            let remote_envelopes = envelopes_by_message_id(remote.list_envelopes(name, 0, 0)?);
+
+            for (message_id, local_envelope) in &local_envelopes {
+                match remote_envelopes.get(message_id) {
+                    Some(remote_envelope) => {
+                        let flags = local_envelope.flags.intersection(&remote_envelope.flags);
+                        let cached = |envelope: &Envelope| Envelope {
+                            flags: flags.clone(),
+                            ..envelope.clone()
+                        };
+                        envelope::sync::Cache::insert_local_envelope(
+                            &tx,
+                            account,
+                            name,
+                            cached(local_envelope),
+                        )?;
+                        envelope::sync::Cache::insert_remote_envelope(
+                            &tx,
+                            account,
+                            name,
+                            cached(remote_envelope),
+                        )?;
+                    }
+                    None => report
+                        .local_only_envelopes
+                        .push((name.clone(), local_envelope.clone())),
+                }
+            }
+
+            for (message_id, remote_envelope) in &remote_envelopes {
+                if !local_envelopes.contains_key(message_id) {
+                    report
+                        .remote_only_envelopes
+                        .push((name.clone(), remote_envelope.clone()));
+                }
+            }
+
+            report.folders.insert(name.clone());
+        }
+
+        report.local_only_folders = local_folders.difference(&remote_folders).cloned().collect();
+        report.remote_only_folders = remote_folders.difference(&local_folders).cloned().collect();
+
+        if self.dry_run {
+            info!("dry run enabled, skipping cache rebuild");
+        } else {
+            tx.commit()?;
+        }
+
+        drop(guard);
+
+        Ok(report)
+    }
+
+    /// Permanently removes the email matching the given Message-ID
+    /// from the remote backend, the local Maildir and both caches,
+    /// from the given folder or from all folders (for example when
+    /// the email has several Gmail labels).
+    ///
+    /// Unlike deleting the email on one side then synchronizing,
+    /// which may copy it back from the other side, the email is
+    /// removed everywhere at once. Remote emails are expunged one by
+    /// one (see [`Backend::expunge_emails_internal`]), other emails
+    /// flagged as deleted are left untouched. Once
+    /// removed, the email is searched again, and
+    /// [`Error::PurgeMessageError`] is returned if it is still found
+    /// somewhere. Nothing is removed in dry run mode, the report then
+    /// tells where the email would be removed from.
+    pub fn purge_message(
+        &self,
+        remote: &dyn Backend,
+        folder: &str,
+        message_id: &str,
+        all_folders: bool,
+    ) -> Result<PurgeReport> {
+        let account = &self.account_config.name;
+        if !self.account_config.sync {
+            return Err(Error::SyncNotEnabled(account.clone()));
+        }
+
+        info!("purging email {message_id} from account {account}");
 
-        let folders_sync_report = folder::SyncBuilder::new(self.account_config)
+        let sync_dir = self.account_config.sync_dir()?;
+        let (guard, mut conn) = self.lock_cache(&sync_dir)?;
+
+        let local = self.local_backend(&sync_dir)?;
+
+        let local_folders = Self::folder_names(&local)?;
+        let remote_folders = Self::folder_names(remote)?;
+
+        let folders: Vec<String> = if all_folders {
+            let mut folders: Vec<_> = local_folders.union(&remote_folders).cloned().collect();
+            folders.sort();
+            folders
+        } else {
+            vec![folder::canonical_folder_name(folder)]
+        };
+
+        let matching = |envelopes: Envelopes| -> Vec<String> {
+            envelopes
+                .iter()
+                .filter(|envelope| envelope.has_message_id(message_id))
+                .map(|envelope| envelope.internal_id.clone())
+                .collect()
+        };
+
+        let find = |conn: &mut rusqlite::Connection, folder: &str| -> Result<[Vec<String>; 4]> {
+            let local_ids = match local_folders.contains(folder) {
+                true => matching(local.list_envelopes(folder, 0, 0)?),
+                false => Vec::new(),
+            };
+            let remote_ids = match remote_folders.contains(folder) {
+                true => matching(remote.list_envelopes(folder, 0, 0)?),
+                false => Vec::new(),
+            };
+            let local_cache_ids = matching(envelope::sync::Cache::list_local_envelopes(
+                conn, account, folder,
+            )?);
+            let remote_cache_ids = matching(envelope::sync::Cache::list_remote_envelopes(
+                conn, account, folder,
+            )?);
+            Ok([local_ids, local_cache_ids, remote_ids, remote_cache_ids])
+        };
+
+        let mut report = PurgeReport::default();
+
+        for folder in &folders {
+            let [local_ids, local_cache_ids, remote_ids, remote_cache_ids] =
+                find(&mut conn, folder)?;
+
+            let entries = |ids: &[String]| -> Vec<(String, String)> {
+                ids.iter().map(|id| (folder.clone(), id.clone())).collect()
+            };
+            report.local.extend(entries(&local_ids));
+            report.local_cache.extend(entries(&local_cache_ids));
+            report.remote.extend(entries(&remote_ids));
+            report.remote_cache.extend(entries(&remote_cache_ids));
+
+            if self.dry_run {
+                continue;
+            }
+
+            // the remote email is removed first, so that a failure
+            // never leaves it remote side only, which would copy it
+            // back local side at the next synchronization
+            if !remote_ids.is_empty() {
+                let remote_ids: Vec<&str> = remote_ids.iter().map(String::as_str).collect();
+                remote.delete_emails_internal(folder, remote_ids.clone())?;
+                remote.expunge_emails_internal(folder, remote_ids)?;
+            }
+
+            if !local_ids.is_empty() {
+                local.delete_emails_internal(
+                    folder,
+                    local_ids.iter().map(String::as_str).collect(),
+                )?;
+            }
+
+            let tx = conn.transaction()?;
+            for id in &local_cache_ids {
+                envelope::sync::Cache::delete_local_envelope(&tx, account, folder, id)?;
+            }
+            for id in &remote_cache_ids {
+                envelope::sync::Cache::delete_remote_envelope(&tx, account, folder, id)?;
+            }
+            tx.commit()?;
+
+            let found = find(&mut conn, folder)?;
+            let places = [
+                "local maildir",
+                "local cache",
+                "remote backend",
+                "remote cache",
+            ];
+            if let Some((place, _)) = places.iter().zip(&found).find(|(_, ids)| !ids.is_empty()) {
+                return Err(Error::PurgeMessageError(
+                    message_id.to_owned(),
+                    place.to_string(),
+                    folder.clone(),
+                ));
+            }
+        }
+
+        if self.dry_run {
+            info!("dry run enabled, skipping purge");
+        }
+
+        drop(guard);
+
+        Ok(report)
+    }
+
+    /// Marks all the emails of the given folder as seen, on the
+    /// remote backend and in the local Maildir (see
+    /// [`Backend::mark_all_seen`]), then adds the [`Flag::Seen`] flag
+    /// to the cached envelopes of the folder.
+    ///
+    /// Without the cache update, the next synchronization would
+    /// compare the changed flags with the cached ones and could
+    /// propagate stale flags back, depending on the conflict
+    /// resolution. Nothing is changed in dry run mode.
+    pub fn mark_all_seen(&self, remote: &dyn Backend, folder: &str) -> Result<()> {
+        let account = &self.account_config.name;
+        if !self.account_config.sync {
+            return Err(Error::SyncNotEnabled(account.clone()));
+        }
+
+        info!("marking all emails of folder {folder} as seen for account {account}");
+
+        if self.dry_run {
+            info!("dry run enabled, skipping mark all seen");
+            return Ok(());
+        }
+
+        let sync_dir = self.account_config.sync_dir()?;
+        let (guard, mut conn) = self.lock_cache(&sync_dir)?;
+
+        let local = self.local_backend(&sync_dir)?;
+
+        let folder = folder::canonical_folder_name(folder);
+        remote.mark_all_seen(&folder)?;
+        local.mark_all_seen(&folder)?;
+
+        let local_cache = envelope::sync::Cache::list_local_envelopes(&mut conn, account, &folder)?;
+        let remote_cache =
+            envelope::sync::Cache::list_remote_envelopes(&mut conn, account, &folder)?;
+        let seen = |envelope: &Envelope| -> Flags {
+            let mut flags = envelope.flags.clone();
+            flags.insert(Flag::Seen);
+            flags
+        };
+
+        let tx = conn.transaction()?;
+        for envelope in local_cache.iter() {
+            if !envelope.flags.contains(&Flag::Seen) {
+                let flags = seen(envelope);
+                envelope::sync::Cache::set_local_flags(
+                    &tx,
+                    account,
+                    &folder,
+                    &envelope.internal_id,
+                    &flags,
+                )?;
+            }
+        }
+        for envelope in remote_cache.iter() {
+            if !envelope.flags.contains(&Flag::Seen) {
+                let flags = seen(envelope);
+                envelope::sync::Cache::set_remote_flags(
+                    &tx,
+                    account,
+                    &folder,
+                    &envelope.internal_id,
+                    &flags,
+                )?;
+            }
+        }
+        tx.commit()?;
+
+        drop(guard);
+
+        Ok(())
+    }
+
+    /// Watches the local Maildir used by [`BackendSyncBuilder::sync`]
+    /// and synchronizes the envelopes of the folders changed by
+    /// other programs (notmuch, mu4e…). Changes are detected by
+    /// polling the modification time of the `cur` and `new`
+    /// directories of every folder every `debounce`, and the
+    /// synchronization waits for the folders to be left untouched
+    /// for `debounce` before running.
+    ///
+    /// This function never returns, except on error.
+    pub fn sync_on_local_change(&self, remote: &dyn Backend, debounce: Duration) -> Result<()> {
+        let account = &self.account_config.name;
+        if !self.account_config.sync {
+            return Err(Error::SyncNotEnabled(account.clone()));
+        }
+
+        let local = self.local_backend(&self.account_config.sync_dir()?)?;
+
+        let mut mtimes = Self::local_mtimes(&local)?;
+
+        loop {
+            let report = self.sync_local_change(remote, &local, &mut mtimes, debounce)?;
+            for (hunk, err) in &report.envelopes_failed_hunks {
+                warn!("cannot process envelope hunk {hunk}: {err}");
+            }
+        }
+    }
+
+    /// Waits for folders of the given local Maildir to change (see
+    /// [`BackendSyncBuilder::wait_for_local_change`]), then
+    /// synchronizes their envelopes.
+    ///
+    /// The modification times are taken before the synchronization,
+    /// since changes made by other programs while it runs cannot be
+    /// told apart from its own writes. The folders changed during the
+    /// synchronization are then synchronized once more by the next
+    /// call, which writes nothing unless other programs changed them.
+    fn sync_local_change(
+        &self,
+        remote: &dyn Backend,
+        local: &MaildirBackend,
+        mtimes: &mut HashMap<String, [Option<SystemTime>; 2]>,
+        debounce: Duration,
+    ) -> Result<BackendSyncReport> {
+        let changed_folders = Self::wait_for_local_change(local, mtimes, debounce)?;
+        info!("local folders changed: {changed_folders:?}");
+
+        *mtimes = Self::local_mtimes(local)?;
+        self.sync_folders(remote, Some(&changed_folders))
+    }
+
+    /// Polls the modification times of the given local Maildir every
+    /// `debounce`, until folders changed then were left untouched for
+    /// `debounce`, and returns them. The given modification times are
+    /// updated along the way.
+    fn wait_for_local_change(
+        local: &MaildirBackend,
+        mtimes: &mut HashMap<String, [Option<SystemTime>; 2]>,
+        debounce: Duration,
+    ) -> Result<HashSet<String>> {
+        let mut changed_folders = HashSet::new();
+
+        loop {
+            thread::sleep(debounce);
+
+            let next_mtimes = Self::local_mtimes(local)?;
+            let changed = next_mtimes
+                .iter()
+                .filter(|(folder, mtime)| mtimes.get(*folder) != Some(mtime))
+                .map(|(folder, _)| folder.clone())
+                .collect::<Vec<_>>();
+            *mtimes = next_mtimes;
+
+            if changed.is_empty() && !changed_folders.is_empty() {
+                return Ok(changed_folders);
+            }
+
+            changed_folders.extend(changed);
+        }
+    }
+
+    /// Gets the modification times of the `cur` and `new` directories
+    /// of every folder of the given local Maildir.
+    fn local_mtimes(local: &MaildirBackend) -> Result<HashMap<String, [Option<SystemTime>; 2]>> {
+        let mut mtimes = HashMap::new();
+
+        for folder in local.list_folders()?.iter() {
+            let path = local.get_mdir_from_dir(&folder.name)?.path().to_owned();
+            let mtime = |dir| fs::metadata(path.join(dir)).and_then(|m| m.modified()).ok();
+            mtimes.insert(folder.name.clone(), [mtime("cur"), mtime("new")]);
+        }
+
+        Ok(mtimes)
+    }
+
+    /// Synchronizes the folders, then the envelopes of the given
+    /// folders, or of all folders if `None`.
+    fn sync_folders(
+        &self,
+        remote: &dyn Backend,
+        only_folders: Option<&HashSet<String>>,
+    ) -> Result<BackendSyncReport> {
+        let account = &self.account_config.name;
+        if !self.account_config.sync {
+            return Err(Error::SyncNotEnabled(account.clone()));
+        }
+
+        info!("starting synchronization");
+        let _span = trace::span!("sync", account = %account).entered();
+        let progress = &self.on_progress;
+        let sync_dir = self.account_config.sync_dir()?;
+        let guard = self.lock(&sync_dir)?;
+        let hooks = &self.account_config.sync_hooks;
+        let mut hooks_errors = Vec::new();
+
+        self.run_hook(
+            "pre-sync",
+            hooks.pre_sync.as_deref(),
+            &[],
+            || (self.pre_sync)(),
+            &mut hooks_errors,
+        );
+
+        // init SQLite cache
+
+        let mut conn = Self::open_cache(&sync_dir)?;
+
+        // init local Maildir
+
+        let local = self.local_backend(&sync_dir)?;
+
+        let folders_sync_report = trace::timed("sync folders", || {
+            folder::SyncBuilder::new(self.account_config)
+                .on_progress(|data| Ok(progress(data).map_err(Box::new)?))
+                .dry_run(self.dry_run)
+                .rename_threshold(self.folders_rename_threshold)
+                .executor(self.executor)
+                .sync(&mut conn, &local, remote)
+        })?;
+
+        // forget the synchronization state of deleted folders
+
+        if !self.dry_run {
+            let tx = conn.transaction()?;
+            for hunk in &folders_sync_report.cache_patch.0 {
+                if let folder::sync::CacheHunk::DeleteFolder(
+                    name,
+                    folder::sync::HunkKindRestricted::Remote,
+                ) = hunk
+                {
+                    envelope::sync::Cache::delete_last_synced_at(&tx, account, name)?;
+                    envelope::sync::Cache::delete_modseq(&tx, account, name)?;
+                }
+            }
+            tx.commit()?;
+        }
+
+        let mut envelopes = envelope::SyncBuilder::new(self.account_config)
             .on_progress(|data| Ok(progress(data).map_err(Box::new)?))
             .dry_run(self.dry_run)
-            .sync(&mut conn, &local, remote)?;
+            .batch_size(self.batch_size)
+            .newest_first(self.newest_first)
+            .hunk_retry_policy(self.hunk_retry_policy)
+            .sync_deleted(self.sync_deleted)
+            .conflict_resolution(self.conflict_resolution)
+            .read_only_strategy(self.read_only_strategy)
+            .executor(self.executor);
 
-        let envelopes = envelope::SyncBuilder::new(self.account_config)
-            .on_progress(|data| Ok(progress(data).map_err(Box::new)?))
-            .dry_run(self.dry_run);
+        if let Some(parallelism) = self.parallelism {
+            envelopes = envelopes.parallelism(parallelism);
+        }
+
+        if let Some(max_message_size) = self.max_message_size {
+            envelopes = envelopes.max_message_size(max_message_size);
+        }
+
+        if let Some(path) = &self.audit_log {
+            envelopes = envelopes.audit_log(path.clone());
+        }
+
+        if let Some(threshold) = self.systemic_error_threshold {
+            envelopes = envelopes.abort_on_systemic_error(threshold);
+        }
+
+        if let Some(timeout) = self.folder_timeout {
+            envelopes = envelopes.folder_timeout(timeout);
+        }
 
         let mut envelopes_patch = Vec::new();
+        let mut envelopes_failed_hunks = Vec::new();
         let mut envelopes_cache_patch = (Vec::new(), Vec::new());
+        let mut envelopes_unparsable = Vec::new();
+        let mut envelopes_read_only = Vec::new();
+        let mut timed_out_folders = folder::sync::FoldersName::default();
 
-        for (folder_num, folder) in folders_sync_report.folders.iter().enumerate() {
+        let folders = folders_sync_report
+            .folders
+            .iter()
+            .filter(|folder| match only_folders {
+                Some(only_folders) => only_folders.contains(*folder),
+                None => true,
+            })
+            .collect::<Vec<_>>();
+
+        // without parallelism, the remote envelopes of the folders
+        // which cannot be listed incrementally are listed at once,
+        // which saves round-trips on backends supporting it (see
+        // [`Backend::list_envelopes_multi`]). Their highest modseq is
+        // read beforehand, so that changes happening meanwhile are
+        // listed again next time.
+        let mut remote_envelopes = HashMap::new();
+        if self.parallelism == Some(1) {
+            let mut modseqs = HashMap::new();
+            for folder in &folders {
+                let (modseq, incremental) = envelopes.remote_modseq(&conn, remote, folder);
+                if !incremental {
+                    modseqs.insert(folder.as_str(), modseq);
+                }
+            }
+
+            if !modseqs.is_empty() {
+                let prefetched: Vec<&str> = modseqs.keys().copied().collect();
+                match remote.list_envelopes_multi(&prefetched) {
+                    Ok(envelopes) => {
+                        for (folder, envelopes) in envelopes {
+                            if let Some(modseq) = modseqs.get(folder.as_str()) {
+                                remote_envelopes.insert(folder, (envelopes, *modseq));
+                            }
+                        }
+                    }
+                    Err(err) => warn!("cannot list remote envelopes at once, skipping it: {err}"),
+                }
+            }
+        }
+
+        // the patches of all the folders are built before being
+        // applied, so that emails moved between remote folders are
+        // moved locally instead of being downloaded again
+        let mut plans = Vec::with_capacity(folders.len());
+        for (folder_num, folder) in folders.iter().enumerate() {
             progress(BackendSyncProgressEvent::StartEnvelopesSync(
-                folder.clone(),
+                (*folder).clone(),
                 folder_num + 1,
-                folders_sync_report.folders.len(),
+                folders.len(),
             ))?;
-            let report = envelopes.sync(folder, &mut conn, &local, remote)?;
+            let plan = match remote_envelopes.remove(folder.as_str()) {
+                Some((remote_envelopes, modseq)) => envelopes.plan_prefetched(
+                    folder,
+                    &mut conn,
+                    &local,
+                    remote,
+                    remote_envelopes,
+                    modseq,
+                )?,
+                None => envelopes.plan(folder, &mut conn, &local, remote)?,
+            };
+            plans.push(plan);
+        }
+
+        let moves = envelope::sync::detect_moves(&mut plans);
+        if moves > 0 {
+            info!("{moves} emails moved between remote folders");
+        }
+
+        for plan in plans {
+            let report = match envelopes.apply(plan, &mut conn, &local, remote) {
+                Ok(report) => report,
+                Err(envelope::sync::Error::FolderSyncTimeoutError(folder)) => {
+                    warn!("envelopes synchronization of folder {folder} timed out, skipping it");
+                    timed_out_folders.insert(folder);
+                    continue;
+                }
+                Err(err) => return Err(err.into()),
+            };
+            if !self.dry_run {
+                for (path, envelope) in new_emails(&local, &report) {
+                    self.run_hook(
+                        "new email",
+                        hooks.on_new_email.as_deref(),
+                        format!("{}\n", path.display()).as_bytes(),
+                        || (self.on_new_email)(&path, &envelope),
+                        &mut hooks_errors,
+                    );
+                }
+            }
             envelopes_patch.extend(report.patch);
+            envelopes_failed_hunks.extend(report.failed_hunks);
             envelopes_cache_patch.0.extend(report.cache_patch.0);
+            envelopes_unparsable.extend(report.unparsable_envelopes);
+            envelopes_read_only.extend(report.read_only);
             if let Some(err) = report.cache_patch.1 {
                 envelopes_cache_patch.1.push(err);
             }
         }
 
-        drop(guard);
-
-        Ok(BackendSyncReport {
+        let mut report = BackendSyncReport {
             folders: folders_sync_report.folders,
+            folders_failed: folders_sync_report.failed_folders,
             folders_patch: folders_sync_report.patch,
             folders_cache_patch: folders_sync_report.cache_patch,
             envelopes_patch,
+            envelopes_failed_hunks,
             envelopes_cache_patch,
-        })
+            envelopes_unparsable,
+            envelopes_read_only,
+            timed_out_folders,
+            hooks_errors: Vec::new(),
+        };
+
+        // the hook runs before unlocking, so that it sees the sync
+        // directory as the synchronization left it
+        self.run_hook(
+            "post-sync",
+            hooks.post_sync.as_deref(),
+            &[],
+            || (self.post_sync)(&report),
+            &mut hooks_errors,
+        );
+        report.hooks_errors = hooks_errors;
+
+        drop(guard);
+
+        Ok(report)
     }
 }
 
@@ -343,6 +1955,20 @@ impl<'a> BackendBuilder {
                     root_dir: account_config.sync_dir()?,
                 }),
             )?)),
+            #[cfg(feature = "jmap-backend")]
+            BackendConfig::Jmap(jmap_config) if !account_config.sync || self.disable_cache => {
+                Ok(Box::new(JmapBackend::new(
+                    Cow::Borrowed(account_config),
+                    Cow::Borrowed(jmap_config),
+                )?))
+            }
+            #[cfg(feature = "jmap-backend")]
+            BackendConfig::Jmap(_) => Ok(Box::new(MaildirBackend::new(
+                Cow::Borrowed(account_config),
+                Cow::Owned(MaildirConfig {
+                    root_dir: account_config.sync_dir()?,
+                }),
+            )?)),
             #[cfg(feature = "maildir-backend")]
             BackendConfig::Maildir(maildir_config) => Ok(Box::new(MaildirBackend::new(
                 Cow::Borrowed(account_config),
@@ -353,7 +1979,207 @@ impl<'a> BackendBuilder {
                 Cow::Borrowed(account_config),
                 Cow::Borrowed(notmuch_config),
             )?)),
+            #[cfg(feature = "pop3-backend")]
+            BackendConfig::Pop3(pop3_config) if !account_config.sync || self.disable_cache => {
+                Ok(Box::new(Pop3Backend::new(
+                    Cow::Borrowed(account_config),
+                    Cow::Borrowed(pop3_config),
+                )?))
+            }
+            #[cfg(feature = "pop3-backend")]
+            BackendConfig::Pop3(_) => Ok(Box::new(MaildirBackend::new(
+                Cow::Borrowed(account_config),
+                Cow::Owned(MaildirConfig {
+                    root_dir: account_config.sync_dir()?,
+                }),
+            )?)),
             BackendConfig::None => Err(Error::BuildBackendError),
         }
     }
 }
+
+/// Returns the path and the local envelope of the emails downloaded by
+/// the given envelopes synchronization report.
+fn new_emails(
+    local: &MaildirBackend,
+    report: &envelope::sync::SyncReport,
+) -> Vec<(PathBuf, Envelope)> {
+    use envelope::sync::{BackendHunk, CacheHunk, HunkKindRestricted};
+
+    let downloaded: HashSet<&str> = report
+        .patch
+        .iter()
+        .filter_map(|hunk| match hunk {
+            BackendHunk::CopyEmail(
+                _,
+                envelope,
+                HunkKindRestricted::Remote,
+                HunkKindRestricted::Local,
+                _,
+            ) => Some(envelope.message_id.as_str()),
+            _ => None,
+        })
+        .collect();
+
+    report
+        .cache_patch
+        .0
+        .iter()
+        .filter_map(|hunk| match hunk {
+            CacheHunk::InsertEnvelope(_, envelope, HunkKindRestricted::Local)
+                if downloaded.contains(envelope.message_id.as_str()) =>
+            {
+                let path = local.get_email_path_internal(&envelope.internal_id).ok()?;
+                Some((path, envelope.clone()))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Returns `true` if the given error has been caused by a lock held
+/// by another process.
+fn is_lock_contended(err: &io::Error) -> bool {
+    // ERROR_LOCK_VIOLATION
+    #[cfg(windows)]
+    if err.raw_os_error() == Some(33) {
+        return true;
+    }
+
+    err.kind() == io::ErrorKind::WouldBlock
+}
+
+#[cfg(all(test, feature = "memory-backend"))]
+mod backend {
+    use std::{
+        borrow::Cow,
+        collections::HashSet,
+        sync::atomic::{AtomicBool, Ordering},
+        time::Duration,
+    };
+
+    use crate::{AccountConfig, CompilerBuilder, Flags, MemoryBackend, TplBuilder};
+
+    use super::{Backend, BackendSyncBuilder};
+
+    fn email(message_id: &str, subject: &str) -> Vec<u8> {
+        TplBuilder::default()
+            .message_id(message_id)
+            .from("alice@localhost")
+            .to("bob@localhost")
+            .subject(subject)
+            .text_plain_part(subject)
+            .compile(CompilerBuilder::default())
+            .unwrap()
+    }
+
+    #[test]
+    fn find_by_message_id() {
+        let account_config = AccountConfig::default();
+        let backend = MemoryBackend::new(Cow::Borrowed(&account_config));
+        backend.add_folder("Archives").unwrap();
+        backend
+            .add_email("INBOX", &email("<a@localhost>", "A"), &Flags::default())
+            .unwrap();
+        backend
+            .add_email("Archives", &email("<b@localhost>", "B"), &Flags::default())
+            .unwrap();
+
+        // check that angle brackets are optional
+        let (folder, envelope) = backend
+            .find_by_message_id(None, "b@localhost")
+            .unwrap()
+            .unwrap();
+        assert_eq!("Archives", folder);
+        assert_eq!("B", envelope.subject);
+        let (folder, envelope) = backend
+            .find_by_message_id(Some("INBOX"), "<a@localhost>")
+            .unwrap()
+            .unwrap();
+        assert_eq!("INBOX", folder);
+        assert_eq!("A", envelope.subject);
+
+        // check that the search can be restricted to a folder
+        assert!(backend
+            .find_by_message_id(Some("INBOX"), "<b@localhost>")
+            .unwrap()
+            .is_none());
+        assert!(backend
+            .find_by_message_id(None, "<c@localhost>")
+            .unwrap()
+            .is_none());
+    }
+
+    #[cfg(feature = "maildir-backend")]
+    #[test]
+    fn sync_on_local_change() {
+        use crate::{MaildirBackend, MaildirConfig};
+
+        let sync_dir = tempfile::tempdir().unwrap();
+        let account_config = AccountConfig {
+            name: "account".into(),
+            sync: true,
+            sync_dir: Some(sync_dir.path().to_owned()),
+            ..AccountConfig::default()
+        };
+        let local_config = MaildirConfig {
+            root_dir: sync_dir.path().to_owned(),
+        };
+        let remote = MemoryBackend::new(Cow::Borrowed(&account_config));
+        BackendSyncBuilder::new(&account_config)
+            .sync(&remote)
+            .unwrap();
+
+        let local =
+            MaildirBackend::new(Cow::Borrowed(&account_config), Cow::Borrowed(&local_config))
+                .unwrap();
+        let mut mtimes = BackendSyncBuilder::local_mtimes(&local).unwrap();
+
+        // an email added by another program changes the local folder
+
+        local
+            .add_email("INBOX", &email("<a@localhost>", "A"), &Flags::default())
+            .unwrap();
+
+        let debounce = Duration::from_millis(50);
+        let changed =
+            BackendSyncBuilder::wait_for_local_change(&local, &mut mtimes, debounce).unwrap();
+        assert_eq!(HashSet::from_iter([String::from("INBOX")]), changed);
+
+        // only the changed folders are synchronized, and an email
+        // added by another program while the synchronization runs is
+        // synchronized by the next pass
+
+        let added = AtomicBool::new(false);
+        let sync_builder = BackendSyncBuilder::new(&account_config).post_sync(|_| {
+            if !added.swap(true, Ordering::SeqCst) {
+                let local = MaildirBackend::new(
+                    Cow::Borrowed(&account_config),
+                    Cow::Borrowed(&local_config),
+                )?;
+                local.add_email("INBOX", &email("<b@localhost>", "B"), &Flags::default())?;
+            }
+            Ok(())
+        });
+
+        let mut mtimes = BackendSyncBuilder::local_mtimes(&local).unwrap();
+        local
+            .add_email("INBOX", &email("<c@localhost>", "C"), &Flags::default())
+            .unwrap();
+
+        let report = sync_builder
+            .sync_local_change(&remote, &local, &mut mtimes, debounce)
+            .unwrap();
+        assert!(report.envelopes_failed_hunks.is_empty());
+        let envelopes = remote.list_envelopes("INBOX", 0, 0).unwrap();
+        assert_eq!(2, envelopes.len());
+
+        let report = sync_builder
+            .sync_local_change(&remote, &local, &mut mtimes, debounce)
+            .unwrap();
+        assert!(report.envelopes_failed_hunks.is_empty());
+        let envelopes = remote.list_envelopes("INBOX", 0, 0).unwrap();
+        assert_eq!(3, envelopes.len());
+        assert!(envelopes.iter().any(|envelope| envelope.subject == "B"));
+    }
+}
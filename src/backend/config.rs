@@ -6,22 +6,32 @@
 #[cfg(feature = "imap-backend")]
 use crate::ImapConfig;
 
+#[cfg(feature = "jmap-backend")]
+use crate::JmapConfig;
+
 #[cfg(feature = "maildir-backend")]
 use crate::MaildirConfig;
 
 #[cfg(feature = "notmuch-backend")]
 use crate::NotmuchConfig;
 
+#[cfg(feature = "pop3-backend")]
+use crate::Pop3Config;
+
 /// Represents the backend configuration of the user account.
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum BackendConfig {
     None,
     #[cfg(feature = "imap-backend")]
     Imap(ImapConfig),
+    #[cfg(feature = "jmap-backend")]
+    Jmap(JmapConfig),
     #[cfg(feature = "maildir-backend")]
     Maildir(MaildirConfig),
     #[cfg(feature = "notmuch-backend")]
     Notmuch(NotmuchConfig),
+    #[cfg(feature = "pop3-backend")]
+    Pop3(Pop3Config),
 }
 
 impl Default for BackendConfig {
@@ -3,28 +3,33 @@
 //! This module contains the definition of the IMAP backend.
 
 use imap::extensions::idle::{stop_on_any, SetReadTimeout};
-use imap_proto::{NameAttribute, UidSetMember};
-use log::{debug, info, log_enabled, trace, Level};
+use imap::types::{Mailbox, Names, UnsolicitedResponse};
+use imap_proto::{Capability, Response, ResponseCode, StatusAttribute, UidSetMember};
+use log::{debug, info, log_enabled, trace, warn, Level};
+use mailparse::MailHeaderMap;
 use native_tls::{TlsConnector, TlsStream};
 use rayon::prelude::*;
 use std::{
     any::Any,
     borrow::Cow,
-    collections::HashSet,
+    cell::RefCell,
+    collections::{HashMap, HashSet},
     convert::TryInto,
     io::{self, Read, Write},
     net::TcpStream,
     result, string,
-    sync::{Mutex, MutexGuard},
+    sync::{mpsc, Mutex, MutexGuard, TryLockError},
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 use thiserror::Error;
-use utf7_imap::{decode_utf7_imap as decode_utf7, encode_utf7_imap as encode_utf7};
 
 use crate::{
-    account, backend, email, envelope, process, AccountConfig, Backend, Emails, Envelope,
-    Envelopes, Flag, Flags, Folder, Folders, ImapConfig,
+    account, backend,
+    backend::imap::{event, ImapEventStream},
+    email, envelope, folder, process, proxy, tls, trace, AccountConfig, Backend, Emails, Envelope,
+    Envelopes, Flag, Flags, Folder, FolderPermission, FolderStatus, Folders, ImapConfig,
+    ProxyConfig, TlsVersion,
 };
 
 #[derive(Error, Debug)]
@@ -34,12 +39,28 @@ pub enum Error {
     CreateFolderError(#[source] imap::Error, String),
     #[error("cannot select imap folder {1}")]
     SelectFolderError(#[source] imap::Error, String),
+    #[error("cannot get status of imap folder {1}")]
+    GetFolderStatusError(#[source] imap::Error, String),
     #[error("cannot list imap folders")]
     ListFoldersError(#[source] imap::Error),
+    #[error("cannot list imap folders with their status")]
+    ListFoldersStatusError(#[source] imap::Error),
+    #[error("cannot list subscribed imap folders")]
+    ListSubscribedFoldersError(#[source] imap::Error),
+    #[error("cannot subscribe to imap folder {1}")]
+    SubscribeFolderError(#[source] imap::Error, String),
+    #[error("cannot unsubscribe from imap folder {1}")]
+    UnsubscribeFolderError(#[source] imap::Error, String),
     #[error("cannot examine folder {1}")]
     ExamineFolderError(#[source] imap::Error, String),
     #[error("cannot expunge imap folder {1}")]
     ExpungeFolderError(#[source] imap::Error, String),
+    #[error("cannot expunge imap emails {1} of folder {2}")]
+    ExpungeEmailsError(#[source] imap::Error, String, String),
+    #[error("cannot expunge imap emails of folder {0}: UIDPLUS unsupported")]
+    UidExpungeNotSupportedError(String),
+    #[error("cannot rename imap folder {1} to {2}")]
+    RenameFolderError(#[source] imap::Error, String, String),
     #[error("cannot delete imap folder {1}")]
     DeleteFolderError(#[source] imap::Error, String),
 
@@ -56,6 +77,10 @@ pub enum Error {
     SortEnvelopesError(#[source] imap::Error, String, String),
     #[error("cannot get next imap envelope uid of folder {0}")]
     GetNextEnvelopeUidError(String),
+    #[error("cannot count imap envelopes in folder {1} with query: {2}")]
+    CountEnvelopesError(#[source] imap::Error, String, String),
+    #[error("cannot parse imap esearch count from response: {0}")]
+    ParseEsearchCountError(String),
 
     // Flags
     #[error("cannot add flags {1} to imap email(s) {2}")]
@@ -64,6 +89,8 @@ pub enum Error {
     SetFlagsError(#[source] imap::Error, String, String),
     #[error("cannot remove flags {1} from email(s) {2}")]
     RemoveFlagsError(#[source] imap::Error, String, String),
+    #[error("cannot mark all imap emails of folder {1} as seen")]
+    MarkAllSeenError(#[source] imap::Error, String),
 
     // Emails
     #[error("cannot copy imap email(s) {1} from {2} to {3}")]
@@ -72,11 +99,15 @@ pub enum Error {
     MoveEmailError(#[source] imap::Error, String, String, String),
     #[error("cannot fetch imap email {1}")]
     FetchEmailsByUidError(#[source] imap::Error, String),
+    #[error("cannot get headers of imap email {0}")]
+    GetEmailHeadersError(String),
     #[error("cannot fetch imap emails within uid range {1}")]
     FetchEmailsByUidRangeError(#[source] imap::Error, String),
+    #[error("cannot fetch imap emails of folder {2} changed since modseq {1}")]
+    FetchChangedEmailsError(#[source] imap::Error, u64, String),
     #[error("cannot get added email uid from range {0}")]
     GetAddedEmailUidFromRangeError(String),
-    #[error("cannot get added email uid (extensions UIDPLUS not enabled on the server?)")]
+    #[error("cannot get added email uid: UIDPLUS unsupported and Message-ID not found")]
     GetAddedEmailUidError,
     #[error("cannot append email to folder {1}")]
     AppendEmailError(#[source] imap::Error, String),
@@ -114,14 +145,24 @@ pub enum Error {
     LockSessionsPoolCursorError(String),
     #[error("cannot create tls connector")]
     CreateTlsConnectorError(#[source] native_tls::Error),
+    #[error("cannot configure tls connection to imap server")]
+    TlsConfigurationError(#[source] tls::Error),
     #[error("cannot connect to imap server")]
     ConnectImapServerError(#[source] imap::Error),
+    #[error("cannot negotiate starttls with imap server")]
+    StartTlsError(#[source] io::Error),
+    #[error("cannot negotiate starttls with imap server: {0}")]
+    StartTlsRejectedError(String),
     #[error("cannot login to imap server")]
     LoginImapServerError(#[source] imap::Error),
     #[error("cannot start the idle mode")]
     StartIdleModeError(#[source] imap::Error),
     #[error("cannot close imap session")]
     CloseImapSessionError(#[source] imap::Error),
+    #[error("cannot lock imap server capabilities: {0}")]
+    LockCapabilitiesError(String),
+    #[error("cannot get imap server capabilities")]
+    GetCapabilitiesError(#[source] imap::Error),
 
     // Other error forwarding
     #[error(transparent)]
@@ -132,6 +173,8 @@ pub enum Error {
     EmailError(#[from] email::Error),
     #[error(transparent)]
     MaildirBackend(#[from] backend::maildir::Error),
+    #[error(transparent)]
+    ProxyError(#[from] proxy::Error),
 }
 
 pub type Result<T> = result::Result<T, Error>;
@@ -167,7 +210,15 @@ impl Write for ImapSessionStream {
         }
     }
 
+    /// Flushes the command written by the session, followed by the
+    /// pending non-synchronizing literal if any (see
+    /// [`ImapBackend::append_with_literal_plus`]).
     fn flush(&mut self) -> io::Result<()> {
+        if let Some(literal) = PENDING_LITERAL.with(|literal| literal.borrow_mut().take()) {
+            self.write_all(&literal)?;
+            self.write_all(b"\r\n")?;
+        }
+
         match self {
             Self::Tls(stream) => stream.flush(),
             Self::Tcp(stream) => stream.flush(),
@@ -175,8 +226,46 @@ impl Write for ImapSessionStream {
     }
 }
 
+thread_local! {
+    /// Holds the raw bytes of the non-synchronizing literal to send
+    /// right after the next command flushed by a session of the
+    /// current thread. The `imap` crate only takes commands as
+    /// strings, which cannot hold 8-bit or binary emails.
+    static PENDING_LITERAL: RefCell<Option<Vec<u8>>> = RefCell::new(None);
+}
+
 pub type ImapSession = imap::Session<ImapSessionStream>;
 
+/// Represents a session of the pool, along with the instant it was
+/// last handed out.
+struct ImapPooledSession {
+    session: Mutex<ImapSession>,
+    last_used: Mutex<Instant>,
+}
+
+impl ImapPooledSession {
+    fn new(session: ImapSession) -> Self {
+        Self {
+            session: Mutex::new(session),
+            last_used: Mutex::new(Instant::now()),
+        }
+    }
+
+    fn last_used(&self) -> Instant {
+        match self.last_used.lock() {
+            Ok(last_used) => *last_used,
+            Err(err) => *err.into_inner(),
+        }
+    }
+
+    fn touch(&self) {
+        match self.last_used.lock() {
+            Ok(mut last_used) => *last_used = Instant::now(),
+            Err(err) => *err.into_inner() = Instant::now(),
+        }
+    }
+}
+
 pub struct ImapBackendBuilder {
     sessions_pool_size: usize,
 }
@@ -204,19 +293,37 @@ impl<'a> ImapBackendBuilder {
         account_config: Cow<'a, AccountConfig>,
         imap_config: Cow<'a, ImapConfig>,
     ) -> Result<ImapBackend<'a>> {
+        // checks the config and the fetch attributes before
+        // connecting, so that a misconfiguration fails early
+        imap_config
+            .validate()
+            .map_err(backend::imap::config::Error::InvalidConfigError)?;
+        imap_config.envelope_fetch_query()?;
+
         let passwd = imap_config.passwd()?;
         let sessions_pool: Vec<_> = (0..=self.sessions_pool_size).collect();
         let backend = ImapBackend {
             account_config,
             imap_config: imap_config.clone(),
+            capabilities: Mutex::new(None),
+            folders_permission: Mutex::new(HashMap::new()),
             sessions_pool_size: self.sessions_pool_size.max(1),
             sessions_pool_cursor: Mutex::new(0),
             sessions_pool: sessions_pool
                 .par_iter()
-                .flat_map(|_| ImapBackend::create_session(&imap_config, &passwd).map(Mutex::new))
+                .flat_map(|_| {
+                    ImapBackend::create_session(&imap_config, &passwd).map(ImapPooledSession::new)
+                })
                 .collect(),
         };
 
+        let capabilities = ImapBackend::fetch_capabilities(&mut backend.session()?)?;
+        debug!("imap server capabilities: {capabilities:?}");
+        *backend
+            .capabilities
+            .lock()
+            .map_err(|err| Error::LockCapabilitiesError(err.to_string()))? = Some(capabilities);
+
         Ok(backend)
     }
 }
@@ -224,9 +331,13 @@ impl<'a> ImapBackendBuilder {
 pub struct ImapBackend<'a> {
     account_config: Cow<'a, AccountConfig>,
     imap_config: Cow<'a, ImapConfig>,
+    capabilities: Mutex<Option<HashSet<String>>>,
+    /// Represents the permission of the folders, as told by their
+    /// last `SELECT` (see [`Backend::folder_permission`]).
+    folders_permission: Mutex<HashMap<String, FolderPermission>>,
     sessions_pool_size: usize,
     sessions_pool_cursor: Mutex<usize>,
-    sessions_pool: Vec<Mutex<ImapSession>>,
+    sessions_pool: Vec<ImapPooledSession>,
 }
 
 impl<'a> ImapBackend<'a> {
@@ -241,26 +352,36 @@ impl<'a> ImapBackend<'a> {
     where
         P: AsRef<str>,
     {
+        tls::check_cipher_suites(config.allowed_cipher_suites.as_ref())
+            .map_err(Error::TlsConfigurationError)?;
+
         let builder = TlsConnector::builder()
             .danger_accept_invalid_certs(config.insecure())
             .danger_accept_invalid_hostnames(config.insecure())
+            .min_protocol_version(config.min_tls_version.map(TlsVersion::to_native_tls))
             .build()
             .map_err(Error::CreateTlsConnectorError)?;
 
-        let mut client_builder = imap::ClientBuilder::new(&config.host, config.port);
-        if config.starttls() {
-            client_builder.starttls();
-        }
+        let host = config.host()?;
+        let client = match &config.proxy {
+            Some(proxy) => Self::connect_through_proxy(config, &host, proxy, &builder)?,
+            None => {
+                let mut client_builder = imap::ClientBuilder::new(&host, config.port);
+                if config.starttls() {
+                    client_builder.starttls();
+                }
 
-        let client = if config.ssl() {
-            client_builder.connect(|domain, tcp| {
-                let connector = TlsConnector::connect(&builder, domain, tcp)?;
-                Ok(ImapSessionStream::Tls(connector))
-            })
-        } else {
-            client_builder.connect(|_, tcp| Ok(ImapSessionStream::Tcp(tcp)))
-        }
-        .map_err(Error::ConnectImapServerError)?;
+                if config.ssl() {
+                    client_builder.connect(|domain, tcp| {
+                        let connector = TlsConnector::connect(&builder, domain, tcp)?;
+                        Ok(ImapSessionStream::Tls(connector))
+                    })
+                } else {
+                    client_builder.connect(|_, tcp| Ok(ImapSessionStream::Tcp(tcp)))
+                }
+                .map_err(Error::ConnectImapServerError)?
+            }
+        };
 
         let mut session = client
             .login(&config.login, passwd.as_ref())
@@ -270,25 +391,178 @@ impl<'a> ImapBackend<'a> {
         Result::Ok(session)
     }
 
+    /// Connects to the IMAP server through the given proxy. Mirrors
+    /// what [`imap::ClientBuilder::connect`] does for direct
+    /// connections, since it cannot be given an existing stream.
+    fn connect_through_proxy(
+        config: &ImapConfig,
+        host: &str,
+        proxy: &ProxyConfig,
+        builder: &TlsConnector,
+    ) -> Result<imap::Client<ImapSessionStream>> {
+        let mut tcp = proxy.connect(host, config.port)?;
+
+        if config.starttls() {
+            Self::starttls(&mut tcp)?;
+        }
+
+        let stream = if config.ssl() {
+            let tls = TlsConnector::connect(builder, host, tcp)
+                .map_err(|err| Error::ConnectImapServerError(err.into()))?;
+            ImapSessionStream::Tls(tls)
+        } else {
+            ImapSessionStream::Tcp(tcp)
+        };
+
+        let mut client = imap::Client::new(stream);
+        if !config.starttls() {
+            client
+                .read_greeting()
+                .map_err(Error::ConnectImapServerError)?;
+        }
+
+        Ok(client)
+    }
+
+    /// Reads the server greeting then negotiates STARTTLS on a raw
+    /// stream. Lines are read byte by byte so that the TLS handshake
+    /// starts right after the tagged response.
+    fn starttls(tcp: &mut TcpStream) -> Result<()> {
+        proxy::read_line(tcp).map_err(Error::StartTlsError)?;
+        tcp.write_all(b"a0 STARTTLS\r\n")
+            .map_err(Error::StartTlsError)?;
+
+        loop {
+            let line = proxy::read_line(tcp).map_err(Error::StartTlsError)?;
+            if let Some(res) = line.strip_prefix("a0 ") {
+                if res.len() >= 2 && res[..2].eq_ignore_ascii_case("OK") {
+                    return Ok(());
+                }
+                return Err(Error::StartTlsRejectedError(res.to_owned()));
+            }
+        }
+    }
+
+    /// Fetches the capabilities advertised by the server. Capability
+    /// names are uppercased, since they are case-insensitive.
+    fn fetch_capabilities(session: &mut ImapSession) -> Result<HashSet<String>> {
+        let capabilities = session
+            .capabilities()
+            .map_err(Error::GetCapabilitiesError)?
+            .iter()
+            .map(|cap| match cap {
+                Capability::Imap4rev1 => String::from("IMAP4REV1"),
+                Capability::Auth(auth) => format!("AUTH={}", auth.to_uppercase()),
+                Capability::Atom(atom) => atom.to_uppercase(),
+            })
+            .collect();
+
+        Ok(capabilities)
+    }
+
+    /// Caches the permission of the given selected folder.
+    fn remember_permission(&self, folder: &str, mailbox: &Mailbox) {
+        let permission = if mailbox.is_read_only {
+            FolderPermission::ReadOnly
+        } else {
+            FolderPermission::ReadWrite
+        };
+        self.folders_permission
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .insert(folder.to_owned(), permission);
+    }
+
+    /// Returns `true` if the server advertises the given capability.
+    ///
+    /// Capabilities are fetched once after login then cached. If the
+    /// cache has been invalidated, they are fetched again using a
+    /// session from the pool: this function must not be called while
+    /// holding a session.
+    pub fn has_capability(&self, cap: &str) -> bool {
+        let mut capabilities = match self.capabilities.lock() {
+            Ok(capabilities) => capabilities,
+            Err(err) => {
+                warn!("cannot lock imap server capabilities: {err}");
+                return false;
+            }
+        };
+
+        if capabilities.is_none() {
+            match self
+                .session()
+                .and_then(|mut session| Self::fetch_capabilities(&mut session))
+            {
+                Ok(fetched) => {
+                    debug!("imap server capabilities: {fetched:?}");
+                    *capabilities = Some(fetched);
+                }
+                Err(err) => {
+                    warn!("cannot get imap server capabilities: {err}");
+                    return false;
+                }
+            }
+        }
+
+        capabilities
+            .as_ref()
+            .map(|capabilities| capabilities.contains(&cap.to_uppercase()))
+            .unwrap_or_default()
+    }
+
+    /// Clears the cached capabilities, so that they are fetched again
+    /// at the next [`ImapBackend::has_capability`] call. Capabilities
+    /// may change after a reconnection, for example when the server
+    /// has been upgraded.
+    pub fn invalidate_capabilities(&self) {
+        match self.capabilities.lock() {
+            Ok(mut capabilities) => *capabilities = None,
+            Err(err) => warn!("cannot lock imap server capabilities: {err}"),
+        }
+    }
+
+    /// Gets a session from the pool.
+    ///
+    /// The least recently used idle session is picked first. When
+    /// all sessions are busy, the next one in the pool is awaited,
+    /// round-robin.
     pub fn session(&self) -> Result<MutexGuard<ImapSession>> {
-        let session = {
+        let mut sessions: Vec<_> = self.sessions_pool.iter().collect();
+        sessions.sort_by_key(|session| session.last_used());
+
+        for pooled in sessions {
+            match pooled.session.try_lock() {
+                Ok(session) => {
+                    pooled.touch();
+                    return Ok(session);
+                }
+                Err(TryLockError::WouldBlock) => continue,
+                Err(TryLockError::Poisoned(err)) => {
+                    return Err(Error::LockSessionError(err.to_string()))
+                }
+            }
+        }
+
+        let pooled = {
             let mut cursor = self
                 .sessions_pool_cursor
                 .lock()
                 .map_err(|err| Error::LockSessionsPoolCursorError(err.to_string()))?;
-            let session = self
+            let pooled = self
                 .sessions_pool
                 .get(*cursor)
                 .ok_or(Error::FindSessionByCursorError(*cursor))?;
-            // TODO: find a way to get the next available connection
-            // instead of the next one in the list
             *cursor = (*cursor + 1) % self.sessions_pool_size;
-            session
+            pooled
         };
 
-        session
+        let session = pooled
+            .session
             .lock()
-            .map_err(|err| Error::LockSessionError(err.to_string()))
+            .map_err(|err| Error::LockSessionError(err.to_string()))?;
+        pooled.touch();
+
+        Ok(session)
     }
 
     fn search_new_msgs(&self, session: &mut ImapSession, query: &str) -> Result<Vec<u32>> {
@@ -303,16 +577,254 @@ impl<'a> ImapBackend<'a> {
         Ok(uids)
     }
 
+    /// Appends an email to the given folder using a non-synchronizing
+    /// literal ([RFC 7888]): the email is sent along with the command
+    /// instead of waiting for the server continuation request, which
+    /// saves one round-trip. The server must advertise `LITERAL+`.
+    /// The email is written as is by the session stream, so it does
+    /// not need to be valid UTF-8.
+    ///
+    /// Returns the UIDs from the APPENDUID response code, if any.
+    ///
+    /// [RFC 7888]: https://www.rfc-editor.org/rfc/rfc7888
+    fn append_with_literal_plus(
+        session: &mut ImapSession,
+        folder: &str,
+        email: &[u8],
+        flags: &Flags,
+    ) -> Result<Option<Vec<UidSetMember>>> {
+        let folder_quoted = folder::imap::quote(folder);
+        let flags = flags
+            .into_imap_flags_vec()
+            .into_iter()
+            .filter(|flag| *flag != imap::types::Flag::Recent)
+            .map(|flag| flag.to_string())
+            .collect::<Vec<_>>()
+            .join(" ");
+        let cmd = format!("APPEND {folder_quoted} ({flags}) {{{}+}}", email.len());
+
+        PENDING_LITERAL.with(|literal| *literal.borrow_mut() = Some(email.to_vec()));
+        let res = session.run(cmd);
+        // the literal is normally taken by the flush of the command,
+        // but must not leak to the next command if it failed before
+        PENDING_LITERAL.with(|literal| literal.borrow_mut().take());
+        let (lines, _) = res.map_err(|err| Error::AppendEmailError(err, folder.to_owned()))?;
+
+        let mut lines = lines.as_slice();
+        let mut uids = None;
+        while let Ok((rest, response)) = imap_proto::parser::parse_response(lines) {
+            lines = rest;
+            if let Response::Done {
+                code: Some(ResponseCode::AppendUid(_, appended_uids)),
+                ..
+            } = response
+            {
+                uids = Some(appended_uids);
+            }
+        }
+
+        Ok(uids)
+    }
+
+    /// Searches the UID of an email freshly appended to the given
+    /// folder using its Message-ID header. This is the fallback used
+    /// when the server does not support the UIDPLUS extension. If
+    /// multiple emails share the same Message-ID, the most recent
+    /// one (with the highest UID) is taken.
+    fn search_added_email_uid(
+        &self,
+        session: &mut ImapSession,
+        folder: &str,
+        email: &[u8],
+    ) -> Result<u32> {
+        let message_id = mailparse::parse_headers(email)
+            .ok()
+            .and_then(|(headers, _)| headers.get_first_value("Message-ID"))
+            .ok_or(Error::GetAddedEmailUidError)?;
+
+        self.search_uids_by_message_id(session, folder, &message_id)?
+            .into_iter()
+            .max()
+            .ok_or(Error::GetAddedEmailUidError)
+    }
+
+    /// Searches the UIDs of the emails of the given folder matching
+    /// the given Message-ID. Since the IMAP header search matches
+    /// substrings, surrounding angle brackets are stripped so that
+    /// both `<id@host>` and `id@host` match.
+    fn search_uids_by_message_id(
+        &self,
+        session: &mut ImapSession,
+        folder: &str,
+        message_id: &str,
+    ) -> Result<HashSet<u32>> {
+        let message_id = envelope::strip_message_id(message_id);
+        self.search_uids_by_header(session, folder, "Message-ID", message_id)
+    }
+
+    /// Searches the UIDs of the emails of the given folder having a
+    /// header containing the given value, with a `UID SEARCH HEADER`.
+    fn search_uids_by_header(
+        &self,
+        session: &mut ImapSession,
+        folder: &str,
+        header: &str,
+        value: &str,
+    ) -> Result<HashSet<u32>> {
+        let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+
+        let folder_encoded = folder::imap::encode(folder);
+        session
+            .select(&folder_encoded)
+            .map_err(|err| Error::SelectFolderError(err, folder.to_owned()))?;
+
+        let query = format!("HEADER {header} \"{value}\"");
+        session
+            .uid_search(&query)
+            .map_err(|err| Error::SearchEnvelopesError(err, folder.to_owned(), query.clone()))
+    }
+
+    /// Fetches the envelopes of the emails of the given folder whose
+    /// UID is greater than the given one. Unlike
+    /// [`ImapBackend::notify`], it does not rely on the IDLE mode,
+    /// which makes it usable for periodic polling.
+    pub fn poll_new_messages(&self, folder: &str, since_uid: u32) -> Result<Vec<Envelope>> {
+        let mut session = self.session()?;
+
+        let exists = session
+            .examine(folder::imap::encode(folder))
+            .map_err(|err| Error::ExamineFolderError(err, folder.to_owned()))?
+            .exists;
+        if exists == 0 {
+            return Ok(Vec::new());
+        }
+
+        // the range `n:*` always contains the last email of the
+        // folder, even if its UID is lower than n
+        let range = format!("{}:*", since_uid.saturating_add(1));
+        let fetches = session
+            .uid_fetch(&range, envelope::imap::FETCH_QUERY)
+            .map_err(Error::FetchNewEnvelopesError)?;
+
+        let mut envelopes = Vec::new();
+        for fetch in fetches.iter() {
+            if fetch.uid.filter(|uid| *uid > since_uid).is_some() {
+                envelopes.push(envelope::imap::from_raw(fetch)?);
+            }
+        }
+        debug!("found {} messages after uid {since_uid}", envelopes.len());
+
+        Ok(envelopes)
+    }
+
+    /// Counts the emails of the given folder matching the given
+    /// search query. When the server supports the ESEARCH extension
+    /// ([RFC 4731]), only the count is returned by the server instead
+    /// of all the matching UIDs.
+    ///
+    /// [RFC 4731]: https://www.rfc-editor.org/rfc/rfc4731
+    pub fn count_envelopes(&self, folder: &str, query: &str) -> Result<u64> {
+        info!("counting imap envelopes from folder {folder} matching {query}");
+
+        // capabilities are checked before taking a session, since
+        // fetching them may need one
+        let esearch = self.has_capability("ESEARCH");
+
+        let mut session = self.session()?;
+        session
+            .examine(folder::imap::encode(folder))
+            .map_err(|err| Error::ExamineFolderError(err, folder.to_owned()))?;
+
+        if !esearch {
+            let uids = session.uid_search(query).map_err(|err| {
+                Error::CountEnvelopesError(err, folder.to_owned(), query.to_owned())
+            })?;
+            return Ok(uids.len() as u64);
+        }
+
+        let (lines, _) = session
+            .run(format!("UID SEARCH RETURN (COUNT) {query}"))
+            .map_err(|err| Error::CountEnvelopesError(err, folder.to_owned(), query.to_owned()))?;
+
+        parse_esearch_count(&lines).ok_or_else(|| {
+            Error::ParseEsearchCountError(String::from_utf8_lossy(&lines).to_string())
+        })
+    }
+
+    /// Lists all the envelopes of the given folders, keyed by folder.
+    /// Unlike calling [`Backend::list_envelopes`] for each folder,
+    /// the `SELECT` and `FETCH` commands of all the folders are sent
+    /// back-to-back on the same session, which saves the round-trips
+    /// of taking a session per folder.
+    pub fn fetch_envelopes_multi(&self, folders: &[&str]) -> Result<HashMap<String, Envelopes>> {
+        info!("listing imap envelopes from {} folders", folders.len());
+
+        let query = envelope::imap::FETCH_QUERY;
+        trace!("fetch query: {query}");
+
+        let mut session = self.session()?;
+        let mut envelopes_by_folder = HashMap::with_capacity(folders.len());
+
+        for folder in folders {
+            let folder_encoded = folder::imap::encode(folder);
+            trace!("utf7 encoded folder: {folder_encoded}");
+
+            let mailbox = session
+                .select(&folder_encoded)
+                .map_err(|err| Error::SelectFolderError(err, folder.to_string()))?;
+            self.remember_permission(folder, &mailbox);
+            let folder_size = mailbox.exists;
+            trace!("folder {folder} size: {folder_size}");
+
+            let envelopes = if folder_size == 0 {
+                Envelopes::default()
+            } else {
+                let fetches = session
+                    .fetch("1:*", query)
+                    .map_err(|err| Error::FetchEmailsByUidRangeError(err, "1:*".into()))?;
+                envelope::imap::from_raws(fetches)?
+            };
+            debug!("found {} envelopes in folder {folder}", envelopes.len());
+
+            envelopes_by_folder.insert(folder.to_string(), envelopes);
+        }
+
+        Ok(envelopes_by_folder)
+    }
+
+    /// Watches the given folder for new messages using the IDLE mode,
+    /// and runs the notify command for each of them (see
+    /// [`ImapConfig::run_notify_cmd`]).
     pub fn notify(&self, keepalive: u64, folder: &str) -> Result<()> {
+        self.notify_with(keepalive, folder, None, None)
+    }
+
+    /// Watches the given folder for new messages like
+    /// [`ImapBackend::notify`]. New messages are searched with the
+    /// given query instead of [`ImapConfig::notify_query`], and their
+    /// envelopes are passed to the given callback instead of running
+    /// the notify command.
+    pub fn notify_with(
+        &self,
+        keepalive: u64,
+        folder: &str,
+        query: Option<&str>,
+        mut callback: Option<&mut dyn FnMut(&Envelope)>,
+    ) -> Result<()> {
+        let query = match query {
+            Some(query) => query.to_owned(),
+            None => self.imap_config.notify_query(),
+        };
+
         let mut session = self.session()?;
 
         session
-            .examine(folder)
+            .examine(folder::imap::encode(folder))
             .map_err(|err| Error::ExamineFolderError(err, folder.to_owned()))?;
 
         debug!("init messages hashset");
         let mut msgs_set: HashSet<u32> = self
-            .search_new_msgs(&mut session, &self.imap_config.notify_query())?
+            .search_new_msgs(&mut session, &query)?
             .iter()
             .cloned()
             .collect::<HashSet<_>>();
@@ -327,7 +839,7 @@ impl<'a> ImapBackend<'a> {
                 .map_err(Error::StartIdleModeError)?;
 
             let uids: Vec<u32> = self
-                .search_new_msgs(&mut session, &self.imap_config.notify_query())?
+                .search_new_msgs(&mut session, &query)?
                 .into_iter()
                 .filter(|uid| msgs_set.get(uid).is_none())
                 .collect();
@@ -341,15 +853,20 @@ impl<'a> ImapBackend<'a> {
                     .collect::<Vec<_>>()
                     .join(",");
                 let fetches = session
-                    .uid_fetch(uids, "(UID ENVELOPE)")
+                    .uid_fetch(uids, envelope::imap::FETCH_QUERY)
                     .map_err(Error::FetchNewEnvelopesError)?;
 
                 for fetch in fetches.iter() {
                     let msg = envelope::imap::from_raw(fetch)?;
                     let uid = fetch.uid.ok_or_else(|| Error::GetUidError(fetch.message))?;
 
-                    let from = msg.from.addr.clone();
-                    self.imap_config.run_notify_cmd(uid, &msg.subject, &from)?;
+                    match callback.as_mut() {
+                        Some(callback) => callback(&msg),
+                        None => {
+                            let from = msg.from.addr.clone();
+                            self.imap_config.run_notify_cmd(uid, &msg.subject, &from)?;
+                        }
+                    }
 
                     debug!("notify message: {}", uid);
                     trace!("message: {:?}", msg);
@@ -369,7 +886,7 @@ impl<'a> ImapBackend<'a> {
         let mut session = self.session()?;
 
         session
-            .examine(mbox)
+            .examine(folder::imap::encode(mbox))
             .map_err(|err| Error::ExamineFolderError(err, mbox.to_owned()))?;
 
         loop {
@@ -402,51 +919,76 @@ impl<'a> Backend for ImapBackend<'a> {
     }
 
     fn add_folder(&self, folder: &str) -> backend::Result<()> {
+        let _span = trace::span!("imap_backend", method = "add_folder", folder = %folder).entered();
         info!("adding imap folder {folder}");
 
-        let folder_encoded = encode_utf7(folder.to_owned());
+        let folder_encoded = folder::imap::encode(folder);
         trace!("utf7 encoded folder: {folder_encoded}");
 
         let mut session = self.session()?;
         session
-            .create(folder_encoded)
+            .create(&folder_encoded)
             .map_err(|err| Error::CreateFolderError(err, folder.to_owned()))?;
 
+        // otherwise the folder would not be listed back
+        if self.imap_config.list_only_subscribed {
+            session
+                .subscribe(&folder_encoded)
+                .map_err(|err| Error::SubscribeFolderError(err, folder.to_owned()))?;
+        }
+
         Ok(())
     }
 
     fn list_folders(&self) -> backend::Result<Folders> {
-        info!("listing imap folders");
+        let _span = trace::span!("imap_backend", method = "list_folders").entered();
+        // capabilities are checked before taking a session, see
+        // [`ImapBackend::has_capability`]
+        let special_use = self.has_capability("SPECIAL-USE");
+        let list_extended = self.has_capability("LIST-EXTENDED");
+        let xlist = self.has_capability("XLIST");
 
         let mut session = self.session()?;
-        let folders = session
-            .list(Some(""), Some("*"))
-            .map_err(Error::ListFoldersError)?;
-        let folders = Folders::from_iter(folders.iter().filter_map(|folder| {
-            if folder.attributes().contains(&NameAttribute::NoSelect) {
-                None
-            } else {
-                Some(Folder {
-                    delim: folder.delimiter().unwrap_or_default().into(),
-                    name: decode_utf7(folder.name().into()),
-                    desc: folder
-                        .attributes()
-                        .iter()
-                        .map(|attr| format!("{attr:?}"))
-                        .collect::<Vec<_>>()
-                        .join(", "),
-                })
-            }
-        }));
+        let folders = if self.imap_config.list_only_subscribed {
+            info!("listing subscribed imap folders");
+            session
+                .lsub(Some(""), Some("*"))
+                .map_err(Error::ListSubscribedFoldersError)?
+        } else if list_extended && special_use {
+            info!("listing imap folders with their special use");
+            session
+                .list(Some(""), Some("* RETURN (SPECIAL-USE)"))
+                .map_err(Error::ListFoldersError)?
+        } else if xlist && !special_use {
+            // XLIST is the Gmail ancestor of SPECIAL-USE, its
+            // responses are parsed as LIST ones
+            info!("listing imap folders using xlist");
+            let response = session
+                .run_command_and_read_response("XLIST \"\" \"*\"")
+                .map_err(Error::ListFoldersError)?;
+            let (mut unsolicited, _) = mpsc::channel();
+            Names::parse(folder::imap::xlist_to_list(&response), &mut unsolicited)
+                .map_err(Error::ListFoldersError)?
+        } else {
+            // servers supporting SPECIAL-USE without LIST-EXTENDED
+            // return the special use attributes anyway
+            info!("listing imap folders");
+            session
+                .list(Some(""), Some("*"))
+                .map_err(Error::ListFoldersError)?
+        };
+        let folders = Folders::from_iter(folders.iter().filter_map(folder::imap::from_raw));
         trace!("imap folders: {:?}", folders);
 
         Ok(folders)
     }
 
     fn purge_folder(&self, folder: &str) -> backend::Result<()> {
+        let _span =
+            trace::span!("imap_backend", method = "purge_folder", folder = %folder).entered();
         info!("purging imap folder {folder}");
 
-        let folder_encoded = encode_utf7(folder.to_owned());
+        let folder_encoded = folder::imap::encode(folder);
         trace!("utf7 encoded folder: {folder_encoded}");
 
         let flags = Flags::from_iter([Flag::Deleted]);
@@ -467,9 +1009,11 @@ impl<'a> Backend for ImapBackend<'a> {
     }
 
     fn delete_folder(&self, folder: &str) -> backend::Result<()> {
+        let _span =
+            trace::span!("imap_backend", method = "delete_folder", folder = %folder).entered();
         info!("deleting imap folder {folder}");
 
-        let folder_encoded = encode_utf7(folder.to_owned());
+        let folder_encoded = folder::imap::encode(folder);
         trace!("utf7 encoded folder: {folder_encoded}");
 
         let mut session = self.session()?;
@@ -480,10 +1024,69 @@ impl<'a> Backend for ImapBackend<'a> {
         Ok(())
     }
 
+    fn subscribe_folder(&self, folder: &str) -> backend::Result<()> {
+        let _span =
+            trace::span!("imap_backend", method = "subscribe_folder", folder = %folder).entered();
+        info!("subscribing to imap folder {folder}");
+
+        let folder_encoded = folder::imap::encode(folder);
+        trace!("utf7 encoded folder: {folder_encoded}");
+
+        let mut session = self.session()?;
+        session
+            .subscribe(&folder_encoded)
+            .map_err(|err| Error::SubscribeFolderError(err, folder.to_owned()))?;
+
+        Ok(())
+    }
+
+    fn unsubscribe_folder(&self, folder: &str) -> backend::Result<()> {
+        let _span =
+            trace::span!("imap_backend", method = "unsubscribe_folder", folder = %folder).entered();
+        info!("unsubscribing from imap folder {folder}");
+
+        let folder_encoded = folder::imap::encode(folder);
+        trace!("utf7 encoded folder: {folder_encoded}");
+
+        let mut session = self.session()?;
+        session
+            .unsubscribe(&folder_encoded)
+            .map_err(|err| Error::UnsubscribeFolderError(err, folder.to_owned()))?;
+
+        Ok(())
+    }
+
+    fn rename_folder(&self, from_folder: &str, to_folder: &str) -> backend::Result<()> {
+        let _span = trace::span!(
+            "imap_backend",
+            method = "rename_folder",
+            from_folder = %from_folder,
+            to_folder = %to_folder
+        )
+        .entered();
+        info!("renaming imap folder {from_folder} to {to_folder}");
+
+        let from_folder_encoded = folder::imap::encode(from_folder);
+        let to_folder_encoded = folder::imap::encode(to_folder);
+        trace!("utf7 encoded from folder: {from_folder_encoded}");
+        trace!("utf7 encoded to folder: {to_folder_encoded}");
+
+        let mut session = self.session()?;
+        session
+            .rename(&from_folder_encoded, &to_folder_encoded)
+            .map_err(|err| {
+                Error::RenameFolderError(err, from_folder.to_owned(), to_folder.to_owned())
+            })?;
+
+        Ok(())
+    }
+
     fn get_envelope(&self, folder: &str, uid: &str) -> backend::Result<Envelope> {
+        let _span =
+            trace::span!("imap_backend", method = "get_envelope", folder = %folder).entered();
         info!("getting imap envelope {uid} from folder {folder}");
 
-        let folder_encoded = encode_utf7(folder.to_owned());
+        let folder_encoded = folder::imap::encode(folder);
         trace!("utf7 encoded folder: {folder_encoded}");
 
         let mut session = self.session()?;
@@ -491,7 +1094,7 @@ impl<'a> Backend for ImapBackend<'a> {
             .select(&folder_encoded)
             .map_err(|err| Error::SelectFolderError(err, folder.to_owned()))?;
         let fetches = session
-            .uid_fetch(uid, "(UID FLAGS ENVELOPE)")
+            .uid_fetch(uid, envelope::imap::FETCH_QUERY)
             .map_err(|err| Error::FetchEmailsByUidError(err, uid.to_owned()))?;
         let fetch = fetches
             .get(0)
@@ -503,22 +1106,152 @@ impl<'a> Backend for ImapBackend<'a> {
         Ok(envelope)
     }
 
+    fn folder_status(&self, folder: &str) -> backend::Result<FolderStatus> {
+        let _span =
+            trace::span!("imap_backend", method = "folder_status", folder = %folder).entered();
+        info!("getting status of imap folder {folder}");
+
+        let folder_encoded = folder::imap::encode(folder);
+        trace!("utf7 encoded folder: {folder_encoded}");
+
+        let mut session = self.session()?;
+        let mailbox = session
+            .status(&folder_encoded, "(MESSAGES UNSEEN RECENT)")
+            .map_err(|err| Error::GetFolderStatusError(err, folder.to_owned()))?;
+
+        // servers omitting the UNSEEN status item are asked for the
+        // count of unseen emails instead
+        let unseen = match mailbox.unseen {
+            Some(unseen) => unseen as u64,
+            None => {
+                drop(session);
+                self.count_envelopes(folder, "UNSEEN")?
+            }
+        };
+
+        Ok(FolderStatus {
+            total: mailbox.exists as usize,
+            unseen: unseen as usize,
+            recent: mailbox.recent as usize,
+            size_bytes: None,
+        })
+    }
+
+    /// Tells whether the server opened the given folder read-only,
+    /// which happens when the user lacks the rights to change its
+    /// emails or flags. The answer of the last listing of the folder
+    /// is reused, otherwise the folder is selected.
+    fn folder_permission(&self, folder: &str) -> backend::Result<FolderPermission> {
+        let _span =
+            trace::span!("imap_backend", method = "folder_permission", folder = %folder).entered();
+        info!("getting permission of imap folder {folder}");
+
+        let permission = self
+            .folders_permission
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .get(folder)
+            .copied();
+        if let Some(permission) = permission {
+            return Ok(permission);
+        }
+
+        let folder_encoded = folder::imap::encode(folder);
+        trace!("utf7 encoded folder: {folder_encoded}");
+
+        let mut session = self.session()?;
+        let mailbox = session
+            .select(&folder_encoded)
+            .map_err(|err| Error::SelectFolderError(err, folder.to_owned()))?;
+        self.remember_permission(folder, &mailbox);
+
+        Ok(if mailbox.is_read_only {
+            FolderPermission::ReadOnly
+        } else {
+            FolderPermission::ReadWrite
+        })
+    }
+
+    /// Gets the status of all the folders in one round trip when the
+    /// server supports [RFC 5819] (LIST-STATUS), otherwise with one
+    /// STATUS command per folder. Folders missing from the LIST-STATUS
+    /// response are asked for their status separately.
+    ///
+    /// [RFC 5819]: https://www.rfc-editor.org/rfc/rfc5819
+    fn folders_status(&self) -> backend::Result<Vec<(Folder, FolderStatus)>> {
+        let _span = trace::span!("imap_backend", method = "folders_status").entered();
+        // LIST-STATUS lists all the folders, subscribed or not
+        if self.imap_config.list_only_subscribed || !self.has_capability("LIST-STATUS") {
+            return self
+                .list_folders()?
+                .iter()
+                .map(|folder| Ok((folder.clone(), self.folder_status(&folder.name)?)))
+                .collect();
+        }
+
+        info!("listing imap folders with their status");
+
+        // the status responses are not part of the list responses,
+        // the imap crate forwards them as unsolicited responses: they
+        // are sent to a channel of their own, so that the unsolicited
+        // responses of the session are left untouched
+        let (mut responses_tx, responses) = mpsc::channel();
+        let mut session = self.session()?;
+        let names = session
+            .run_command_and_read_response("LIST \"\" * RETURN (STATUS (MESSAGES UNSEEN RECENT))")
+            .and_then(|lines| Names::parse(lines, &mut responses_tx))
+            .map_err(Error::ListFoldersStatusError)?;
+        drop(session);
+
+        let folders = Folders::from_iter(names.iter().filter_map(folder::imap::from_raw));
+        trace!("imap folders: {:?}", folders);
+
+        let mut statuses: HashMap<String, FolderStatus> = HashMap::new();
+        for response in responses.try_iter() {
+            match response {
+                UnsolicitedResponse::Status {
+                    mailbox,
+                    attributes,
+                } => {
+                    if let Some(status) = folder_status_from_attributes(&attributes) {
+                        statuses.insert(mailbox, status);
+                    }
+                }
+                response => debug!("skipping unsolicited response {response:?}"),
+            }
+        }
+
+        folders
+            .iter()
+            .map(|folder| {
+                let status = match statuses.remove(&folder.raw_name) {
+                    Some(status) => status,
+                    None => self.folder_status(&folder.name)?,
+                };
+                Ok((folder.clone(), status))
+            })
+            .collect()
+    }
+
     fn list_envelopes(
         &self,
         folder: &str,
         page_size: usize,
         page: usize,
     ) -> backend::Result<Envelopes> {
+        let _span =
+            trace::span!("imap_backend", method = "list_envelopes", folder = %folder).entered();
         info!("listing imap envelopes from folder {folder}");
 
-        let folder_encoded = encode_utf7(folder.to_owned());
+        let folder_encoded = folder::imap::encode(folder);
         trace!("utf7 encoded folder: {folder_encoded}");
 
         let mut session = self.session()?;
-        let folder_size = session
+        let mailbox = session
             .select(&folder_encoded)
-            .map_err(|err| Error::SelectFolderError(err, folder.to_owned()))?
-            .exists as usize;
+            .map_err(|err| Error::SelectFolderError(err, folder.to_owned()))?;
+        self.remember_permission(folder, &mailbox);
+        let folder_size = mailbox.exists as usize;
         trace!("folder size: {folder_size}");
 
         if folder_size == 0 {
@@ -542,15 +1275,73 @@ impl<'a> Backend for ImapBackend<'a> {
         trace!("page size: {page_size}");
         trace!("seq range: {range}");
 
+        // the configured attributes only apply to pages, the whole
+        // folder is listed by the synchronization which needs the
+        // Message-ID and the flags of every email
+        let (query, with_envelope) = if page_size > 0 {
+            let query = self.imap_config.envelope_fetch_query()?;
+            (query, self.imap_config.fetches_envelope())
+        } else {
+            (envelope::imap::FETCH_QUERY.to_owned(), true)
+        };
+        trace!("fetch query: {query}");
+
         let fetches = session
-            .fetch(&range, "(UID FLAGS ENVELOPE)")
+            .fetch(&range, query)
             .map_err(|err| Error::FetchEmailsByUidRangeError(err, range))?;
-        let envelopes = envelope::imap::from_raws(fetches)?;
+        let envelopes = envelope::imap::from_raws_with(fetches, with_envelope)?;
         trace!("imap envelopes: {envelopes:#?}");
 
         Ok(envelopes)
     }
 
+    fn envelope_pages(
+        &self,
+        folder: &str,
+        page_size: usize,
+    ) -> backend::Result<Box<dyn Iterator<Item = backend::Result<Envelopes>> + Send + '_>> {
+        let _span =
+            trace::span!("imap_backend", method = "envelope_pages", folder = %folder).entered();
+        info!("listing imap envelopes pages from folder {folder}");
+
+        let folder = folder.to_owned();
+        let folder_encoded = folder::imap::encode(&folder);
+        trace!("utf7 encoded folder: {folder_encoded}");
+
+        let folder_size = self
+            .session()?
+            .select(&folder_encoded)
+            .map_err(|err| Error::SelectFolderError(err, folder.clone()))?
+            .exists as usize;
+        trace!("folder size: {folder_size}");
+
+        let page_size = if page_size == 0 {
+            folder_size.max(1)
+        } else {
+            page_size
+        };
+
+        // each page issues its own fetch, using the same sequence
+        // numbers as [`Backend::list_envelopes`]
+        let fetch_page = move |begin: usize| -> backend::Result<Envelopes> {
+            let range = format!("{}:{}", begin + 1, folder_size.min(begin + page_size));
+            trace!("seq range: {range}");
+
+            let mut session = self.session()?;
+            session
+                .select(&folder_encoded)
+                .map_err(|err| Error::SelectFolderError(err, folder.clone()))?;
+            let fetches = session
+                .fetch(&range, envelope::imap::FETCH_QUERY)
+                .map_err(|err| Error::FetchEmailsByUidRangeError(err, range))?;
+
+            Ok(envelope::imap::from_raws(fetches)?)
+        };
+        let pages = (0..folder_size).step_by(page_size).map(fetch_page);
+
+        Ok(Box::new(pages))
+    }
+
     fn search_envelopes(
         &self,
         folder: &str,
@@ -559,9 +1350,11 @@ impl<'a> Backend for ImapBackend<'a> {
         page_size: usize,
         page: usize,
     ) -> backend::Result<Envelopes> {
+        let _span =
+            trace::span!("imap_backend", method = "search_envelopes", folder = %folder).entered();
         info!("searching imap envelopes from folder {folder}");
 
-        let folder_encoded = encode_utf7(folder.to_owned());
+        let folder_encoded = folder::imap::encode(folder);
         trace!("utf7 encoded folder: {folder_encoded}");
 
         let mut session = self.session()?;
@@ -615,7 +1408,7 @@ impl<'a> Backend for ImapBackend<'a> {
         trace!("uid range: {uid_range}");
 
         let fetches = session
-            .uid_fetch(&uid_range, "(UID FLAGS ENVELOPE)")
+            .uid_fetch(&uid_range, envelope::imap::FETCH_QUERY)
             .map_err(|err| Error::FetchEmailsByUidRangeError(err, uid_range))?;
         let envelopes = envelope::imap::from_raws(fetches)?;
         trace!("imap envelopes: {envelopes:#?}");
@@ -624,22 +1417,33 @@ impl<'a> Backend for ImapBackend<'a> {
     }
 
     fn add_email(&self, folder: &str, email: &[u8], flags: &Flags) -> backend::Result<String> {
+        let _span = trace::span!("imap_backend", method = "add_email", folder = %folder).entered();
         info!(
             "adding imap email to folder {folder} with flags {flags}",
             flags = flags.to_string(),
         );
 
-        let folder_encoded = encode_utf7(folder.to_owned());
+        let folder_encoded = folder::imap::encode(folder);
         trace!("utf7 encoded folder: {folder_encoded}");
 
+        // checked before taking a session, since capabilities may
+        // need to be fetched again
+        let literal_plus = email.len() > self.imap_config.literal_plus_threshold_bytes()
+            && self.has_capability("LITERAL+");
         let mut session = self.session()?;
-        let appended = session
-            .append(&folder, email)
-            .flags(flags.into_imap_flags_vec())
-            .finish()
-            .map_err(|err| Error::AppendEmailError(err, folder.to_owned()))?;
+        let uids = if literal_plus {
+            debug!("appending email using non-synchronizing literal");
+            Self::append_with_literal_plus(&mut session, folder, email, flags)?
+        } else {
+            session
+                .append(&folder_encoded, email)
+                .flags(flags.into_imap_flags_vec())
+                .finish()
+                .map_err(|err| Error::AppendEmailError(err, folder.to_owned()))?
+                .uids
+        };
 
-        let uid = match appended.uids {
+        let uid = match uids {
             Some(mut uids) if uids.len() == 1 => match uids.get_mut(0).unwrap() {
                 UidSetMember::Uid(uid) => Ok(*uid),
                 UidSetMember::UidRange(uids) => Ok(uids.next().ok_or_else(|| {
@@ -653,9 +1457,8 @@ impl<'a> Backend for ImapBackend<'a> {
                 })?),
             },
             _ => {
-                // TODO: find a way to retrieve the UID of the added
-                // email (by Message-ID?)
-                Err(Error::GetAddedEmailUidError)
+                debug!("no APPENDUID response, searching uid by Message-ID");
+                self.search_added_email_uid(&mut session, folder, email)
             }
         }?;
         trace!("uid: {uid}");
@@ -664,10 +1467,12 @@ impl<'a> Backend for ImapBackend<'a> {
     }
 
     fn preview_emails(&self, folder: &str, uids: Vec<&str>) -> backend::Result<Emails> {
+        let _span =
+            trace::span!("imap_backend", method = "preview_emails", folder = %folder).entered();
         let uids = uids.join(",");
         info!("previewing imap emails {uids} from folder {folder}");
 
-        let folder_encoded = encode_utf7(folder.to_owned());
+        let folder_encoded = folder::imap::encode(folder);
         trace!("utf7 encoded folder: {folder_encoded}");
 
         let mut session = self.session()?;
@@ -681,11 +1486,36 @@ impl<'a> Backend for ImapBackend<'a> {
         Ok(Emails::try_from(fetches)?)
     }
 
+    fn get_email_headers_internal(&self, folder: &str, uid: &str) -> backend::Result<Vec<u8>> {
+        let _span =
+            trace::span!("imap_backend", method = "get_email_headers_internal", folder = %folder)
+                .entered();
+        info!("getting imap email {uid} headers from folder {folder}");
+
+        let folder_encoded = folder::imap::encode(folder);
+        trace!("utf7 encoded folder: {folder_encoded}");
+
+        let mut session = self.session()?;
+        session
+            .select(&folder_encoded)
+            .map_err(|err| Error::SelectFolderError(err, folder.to_owned()))?;
+        let fetches = session
+            .uid_fetch(uid, "BODY.PEEK[HEADER]")
+            .map_err(|err| Error::FetchEmailsByUidError(err, uid.to_owned()))?;
+        let headers = fetches
+            .iter()
+            .find_map(|fetch| fetch.header())
+            .ok_or_else(|| Error::GetEmailHeadersError(uid.to_owned()))?;
+
+        Ok(headers.to_vec())
+    }
+
     fn get_emails(&self, folder: &str, uids: Vec<&str>) -> backend::Result<Emails> {
+        let _span = trace::span!("imap_backend", method = "get_emails", folder = %folder).entered();
         let uids = uids.join(",");
         info!("getting imap emails {uids} from folder {folder}");
 
-        let folder_encoded = encode_utf7(folder.to_owned());
+        let folder_encoded = folder::imap::encode(folder);
         trace!("utf7 encoded folder: {folder_encoded}");
 
         let mut session = self.session()?;
@@ -693,8 +1523,15 @@ impl<'a> Backend for ImapBackend<'a> {
             .select(&folder_encoded)
             .map_err(|err| Error::SelectFolderError(err, folder.to_owned()))?;
         let fetches = session
-            .uid_fetch(&uids, "BODY[]")
-            .map_err(|err| Error::FetchEmailsByUidRangeError(err, uids))?;
+            .uid_fetch(&uids, "BODY.PEEK[]")
+            .map_err(|err| Error::FetchEmailsByUidRangeError(err, uids.clone()))?;
+
+        // emails are marked as seen explicitly rather than by fetching
+        // BODY[], so that no fetch alters flags by accident
+        let seen = Flags::from_iter([Flag::Seen]).to_imap_query();
+        session
+            .uid_store(&uids, format!("+FLAGS.SILENT ({seen})"))
+            .map_err(|err| Error::AddFlagsError(err, seen, uids))?;
 
         Ok(Emails::try_from(fetches)?)
     }
@@ -705,11 +1542,18 @@ impl<'a> Backend for ImapBackend<'a> {
         to_folder: &str,
         uids: Vec<&str>,
     ) -> backend::Result<()> {
+        let _span = trace::span!(
+            "imap_backend",
+            method = "copy_emails",
+            from_folder = %from_folder,
+            to_folder = %to_folder
+        )
+        .entered();
         let uids = uids.join(",");
         info!("copying imap emails {uids} from folder {from_folder} to folder {to_folder}");
 
-        let from_folder_encoded = encode_utf7(from_folder.to_owned());
-        let to_folder_encoded = encode_utf7(to_folder.to_owned());
+        let from_folder_encoded = folder::imap::encode(from_folder);
+        let to_folder_encoded = folder::imap::encode(to_folder);
         trace!("utf7 encoded from folder: {}", from_folder_encoded);
         trace!("utf7 encoded to folder: {}", to_folder_encoded);
 
@@ -730,11 +1574,18 @@ impl<'a> Backend for ImapBackend<'a> {
         to_folder: &str,
         uids: Vec<&str>,
     ) -> backend::Result<()> {
+        let _span = trace::span!(
+            "imap_backend",
+            method = "move_emails",
+            from_folder = %from_folder,
+            to_folder = %to_folder
+        )
+        .entered();
         let uids = uids.join(",");
         info!("moving imap emails {uids} from folder {from_folder} to folder {to_folder}");
 
-        let from_folder_encoded = encode_utf7(from_folder.to_owned());
-        let to_folder_encoded = encode_utf7(to_folder.to_owned());
+        let from_folder_encoded = folder::imap::encode(from_folder);
+        let to_folder_encoded = folder::imap::encode(to_folder);
         trace!("utf7 encoded from folder: {}", from_folder_encoded);
         trace!("utf7 encoded to folder: {}", to_folder_encoded);
 
@@ -750,17 +1601,178 @@ impl<'a> Backend for ImapBackend<'a> {
     }
 
     fn delete_emails(&self, folder: &str, uids: Vec<&str>) -> backend::Result<()> {
+        let _span =
+            trace::span!("imap_backend", method = "delete_emails", folder = %folder).entered();
         self.add_flags(folder, uids, &Flags::from_iter([Flag::Deleted]))
     }
 
+    fn expunge_folder(&self, folder: &str) -> backend::Result<()> {
+        let _span =
+            trace::span!("imap_backend", method = "expunge_folder", folder = %folder).entered();
+        info!("expunging imap folder {folder}");
+
+        let folder_encoded = folder::imap::encode(folder);
+        trace!("utf7 encoded folder: {folder_encoded}");
+
+        let mut session = self.session()?;
+        session
+            .select(folder_encoded)
+            .map_err(|err| Error::SelectFolderError(err, folder.to_owned()))?;
+        session
+            .expunge()
+            .map_err(|err| Error::ExpungeFolderError(err, folder.to_owned()))?;
+
+        Ok(())
+    }
+
+    fn expunge_emails_internal(&self, folder: &str, uids: Vec<&str>) -> backend::Result<()> {
+        let _span =
+            trace::span!("imap_backend", method = "expunge_emails_internal", folder = %folder)
+                .entered();
+        let uids = uids.join(",");
+        info!("expunging imap emails {uids} from folder {folder}");
+
+        // a plain EXPUNGE would also remove the other emails flagged
+        // as deleted
+        if !self.has_capability("UIDPLUS") {
+            return Err(Error::UidExpungeNotSupportedError(folder.to_owned()))?;
+        }
+
+        let folder_encoded = folder::imap::encode(folder);
+        trace!("utf7 encoded folder: {folder_encoded}");
+
+        let mut session = self.session()?;
+        session
+            .select(folder_encoded)
+            .map_err(|err| Error::SelectFolderError(err, folder.to_owned()))?;
+        session
+            .uid_expunge(&uids)
+            .map_err(|err| Error::ExpungeEmailsError(err, uids.clone(), folder.to_owned()))?;
+
+        Ok(())
+    }
+
+    fn highest_modseq(&self, folder: &str) -> backend::Result<Option<(u64, usize)>> {
+        let _span =
+            trace::span!("imap_backend", method = "highest_modseq", folder = %folder).entered();
+        if !self.has_capability("CONDSTORE") {
+            debug!("imap server does not support CONDSTORE, skipping highest modseq");
+            return Ok(None);
+        }
+
+        info!("getting highest modseq of imap folder {folder}");
+
+        let folder_encoded = folder::imap::encode(folder);
+        trace!("utf7 encoded folder: {folder_encoded}");
+
+        let mut session = self.session()?;
+        let mailbox = session
+            .status(&folder_encoded, "(MESSAGES HIGHESTMODSEQ)")
+            .map_err(|err| Error::GetFolderStatusError(err, folder.to_owned()))?;
+        trace!("imap folder status: {mailbox:#?}");
+
+        Ok(mailbox
+            .highest_mod_seq
+            .map(|modseq| (modseq, mailbox.exists as usize)))
+    }
+
+    fn list_changed_envelopes_since_modseq(
+        &self,
+        folder: &str,
+        modseq: u64,
+    ) -> backend::Result<Envelopes> {
+        let _span = trace::span!(
+            "imap_backend",
+            method = "list_changed_envelopes_since_modseq",
+            folder = %folder
+        )
+        .entered();
+        info!("listing imap envelopes from folder {folder} changed since modseq {modseq}");
+
+        let folder_encoded = folder::imap::encode(folder);
+        trace!("utf7 encoded folder: {folder_encoded}");
+
+        let mut session = self.session()?;
+        let mailbox = session
+            .select(&folder_encoded)
+            .map_err(|err| Error::SelectFolderError(err, folder.to_owned()))?;
+        self.remember_permission(folder, &mailbox);
+        let folder_size = mailbox.exists as usize;
+        trace!("folder size: {folder_size}");
+
+        if folder_size == 0 {
+            return Ok(Envelopes::default());
+        }
+
+        let fetches = session
+            .uid_fetch(
+                "1:*",
+                format!("{} (CHANGEDSINCE {modseq})", envelope::imap::FETCH_QUERY),
+            )
+            .map_err(|err| Error::FetchChangedEmailsError(err, modseq, folder.to_owned()))?;
+        let envelopes = envelope::imap::from_raws(fetches)?;
+        trace!("imap envelopes: {envelopes:#?}");
+
+        Ok(envelopes)
+    }
+
+    fn list_envelopes_multi(
+        &self,
+        folders: &[&str],
+    ) -> backend::Result<HashMap<String, Envelopes>> {
+        let _span = trace::span!("imap_backend", method = "list_envelopes_multi").entered();
+        Ok(self.fetch_envelopes_multi(folders)?)
+    }
+
+    fn search_ids_by_message_id(
+        &self,
+        folder: &str,
+        message_id: &str,
+    ) -> backend::Result<Vec<String>> {
+        let _span =
+            trace::span!("imap_backend", method = "search_ids_by_message_id", folder = %folder)
+                .entered();
+        info!("searching imap emails of folder {folder} matching Message-ID {message_id}");
+
+        let mut session = self.session()?;
+        let uids = self
+            .search_uids_by_message_id(&mut session, folder, message_id)?
+            .into_iter()
+            .map(|uid| uid.to_string())
+            .collect();
+
+        Ok(uids)
+    }
+
+    fn search_ids_by_header(
+        &self,
+        folder: &str,
+        header: &str,
+        value: &str,
+    ) -> backend::Result<Vec<String>> {
+        let _span = trace::span!("imap_backend", method = "search_ids_by_header", folder = %folder)
+            .entered();
+        info!("searching imap emails of folder {folder} with header {header} containing {value}");
+
+        let mut session = self.session()?;
+        let uids = self
+            .search_uids_by_header(&mut session, folder, header, value)?
+            .into_iter()
+            .map(|uid| uid.to_string())
+            .collect();
+
+        Ok(uids)
+    }
+
     fn add_flags(&self, folder: &str, uids: Vec<&str>, flags: &Flags) -> backend::Result<()> {
+        let _span = trace::span!("imap_backend", method = "add_flags", folder = %folder).entered();
         let uids = uids.join(",");
         info!(
             "addings flags {flags} to imap emails {uids} from folder {folder}",
             flags = flags.to_string(),
         );
 
-        let folder_encoded = encode_utf7(folder.to_owned());
+        let folder_encoded = folder::imap::encode(folder);
         debug!("utf7 encoded folder: {}", folder_encoded);
 
         let mut session = self.session()?;
@@ -778,13 +1790,14 @@ impl<'a> Backend for ImapBackend<'a> {
     }
 
     fn set_flags(&self, folder: &str, uids: Vec<&str>, flags: &Flags) -> backend::Result<()> {
+        let _span = trace::span!("imap_backend", method = "set_flags", folder = %folder).entered();
         let uids = uids.join(",");
         info!(
             "setting flags {flags} to imap emails {uids} from folder {folder}",
             flags = flags.to_string(),
         );
 
-        let folder_encoded = encode_utf7(folder.to_owned());
+        let folder_encoded = folder::imap::encode(folder);
         debug!("utf7 encoded folder: {}", folder_encoded);
 
         let mut session = self.session()?;
@@ -802,13 +1815,15 @@ impl<'a> Backend for ImapBackend<'a> {
     }
 
     fn remove_flags(&self, folder: &str, uids: Vec<&str>, flags: &Flags) -> backend::Result<()> {
+        let _span =
+            trace::span!("imap_backend", method = "remove_flags", folder = %folder).entered();
         let uids = uids.join(",");
         info!(
             "removing flags {flags} to imap emails {uids} from folder {folder}",
             flags = flags.to_string(),
         );
 
-        let folder_encoded = encode_utf7(folder.to_owned());
+        let folder_encoded = folder::imap::encode(folder);
         debug!("utf7 encoded folder: {}", folder_encoded);
 
         let mut session = self.session()?;
@@ -825,18 +1840,148 @@ impl<'a> Backend for ImapBackend<'a> {
         Ok(())
     }
 
+    /// Marks all the emails of the given folder as seen with a single
+    /// silent `STORE`, without fetching anything.
+    fn mark_all_seen(&self, folder: &str) -> backend::Result<()> {
+        let _span =
+            trace::span!("imap_backend", method = "mark_all_seen", folder = %folder).entered();
+        info!("marking all imap emails of folder {folder} as seen");
+
+        let folder_encoded = folder::imap::encode(folder);
+        debug!("utf7 encoded folder: {}", folder_encoded);
+
+        let mut session = self.session()?;
+        let mailbox = session
+            .select(&folder_encoded)
+            .map_err(|err| Error::SelectFolderError(err, folder.to_owned()))?;
+
+        // the sequence set 1:* is invalid for an empty folder
+        if mailbox.exists == 0 {
+            return Ok(());
+        }
+
+        session
+            .store("1:*", "+FLAGS.SILENT (\\Seen)")
+            .map_err(|err| Error::MarkAllSeenError(err, folder.to_owned()))?;
+
+        Ok(())
+    }
+
     fn close(&self) -> backend::Result<()> {
-        self.sessions_pool.par_iter().try_for_each(|session| {
-            let mut session = session
+        let _span = trace::span!("imap_backend", method = "close").entered();
+        self.sessions_pool.par_iter().try_for_each(|pooled| {
+            let mut session = pooled
+                .session
                 .lock()
                 .map_err(|err| Error::LockSessionError(err.to_string()))?;
             session.logout().map_err(Error::CloseImapSessionError)
         })?;
 
+        // a new connection may advertise different capabilities
+        self.invalidate_capabilities();
+
         Ok(())
     }
 
+    fn subscribe(&self) -> backend::Result<Box<dyn backend::BackendEventStream + '_>> {
+        let folder = self.account_config.inbox_folder_alias()?;
+        info!("subscribing to imap events of folder {folder}");
+
+        let passwd = self.imap_config.passwd().map_err(Error::from)?;
+        let session = ImapBackend::create_session(&self.imap_config, passwd)?;
+        let stream = ImapEventStream::new(session, folder, event::DEFAULT_IDLE_KEEPALIVE)?;
+
+        Ok(Box::new(stream))
+    }
+
     fn as_any(&'static self) -> &(dyn Any) {
         self
     }
 }
+
+/// Builds the folder status from the attributes of a STATUS response.
+/// Returns `None` if the amount of emails or of unseen emails is
+/// missing.
+fn folder_status_from_attributes(attributes: &[StatusAttribute]) -> Option<FolderStatus> {
+    let mut total = None;
+    let mut unseen = None;
+    let mut recent = 0;
+
+    for attribute in attributes {
+        match attribute {
+            StatusAttribute::Messages(count) => total = Some(*count as usize),
+            StatusAttribute::Unseen(count) => unseen = Some(*count as usize),
+            StatusAttribute::Recent(count) => recent = *count as usize,
+            _ => (),
+        }
+    }
+
+    Some(FolderStatus {
+        total: total?,
+        unseen: unseen?,
+        recent,
+        size_bytes: None,
+    })
+}
+
+/// Parses the count of an ESEARCH response, for example `* ESEARCH
+/// (TAG "A1") UID COUNT 3`.
+fn parse_esearch_count(lines: &[u8]) -> Option<u64> {
+    String::from_utf8_lossy(lines)
+        .lines()
+        .filter(|line| line.to_uppercase().starts_with("* ESEARCH"))
+        .find_map(|line| {
+            let mut tokens = line.split_whitespace();
+            tokens.find(|token| token.eq_ignore_ascii_case("COUNT"))?;
+            tokens.next()?.parse().ok()
+        })
+}
+
+#[cfg(test)]
+mod imap_backend {
+    use imap_proto::UidSetMember;
+    use std::{
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+    };
+
+    use crate::Flags;
+
+    use super::{ImapBackend, ImapSessionStream};
+
+    #[test]
+    fn append_non_utf8_email_with_literal_plus() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let tcp = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+        server
+            .write_all(b"* OK ready\r\na1 OK logged in\r\na2 OK [APPENDUID 1 42] appended\r\n")
+            .unwrap();
+
+        let mut client = imap::Client::new(ImapSessionStream::Tcp(tcp));
+        client.read_greeting().unwrap();
+        let mut session = client
+            .login("bob", "password")
+            .map_err(|(err, _)| err)
+            .unwrap();
+
+        // an 8-bit Latin-1 body, which is not valid UTF-8
+        let email = b"Subject: caf\xe9\r\n\r\ncaf\xe9\r\n";
+        let uids =
+            ImapBackend::append_with_literal_plus(&mut session, "INBOX", email, &Flags::default())
+                .unwrap();
+        assert_eq!(Some(vec![UidSetMember::Uid(42)]), uids);
+
+        drop(session);
+        let mut sent = Vec::new();
+        server.read_to_end(&mut sent).unwrap();
+
+        // the email follows the command without waiting for a
+        // continuation request, byte for byte
+        let login_end = sent.windows(2).position(|w| w == b"\r\n").unwrap() + 2;
+        let mut expected = format!("a2 APPEND \"INBOX\" () {{{}+}}\r\n", email.len()).into_bytes();
+        expected.extend_from_slice(email);
+        expected.extend_from_slice(b"\r\n");
+        assert_eq!(expected, sent[login_end..]);
+    }
+}
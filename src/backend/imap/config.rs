@@ -3,10 +3,14 @@
 //! This module contains the representation of the IMAP backend
 //! configuration of the user account.
 
-use std::result;
+use std::{fmt, result};
 use thiserror::Error;
 
-use crate::process;
+use crate::{account, envelope, process, ProxyConfig, TlsVersion, ValidationError};
+
+/// Default size above which emails are uploaded using non-synchronizing
+/// literals, when the server supports them.
+pub const DEFAULT_LITERAL_PLUS_THRESHOLD_BYTES: usize = 4096;
 
 #[cfg(feature = "imap-backend")]
 #[derive(Debug, Error)]
@@ -17,6 +21,12 @@ pub enum Error {
     GetPasswdEmptyError,
     #[error("cannot start the notify mode")]
     StartNotifyModeError(#[source] process::Error),
+    #[error("cannot fetch imap envelopes: fetch attributes must include UID")]
+    MissingUidFetchAttrError,
+    #[error("invalid imap config: {}", ValidationError::join(.0))]
+    InvalidConfigError(Vec<ValidationError>),
+    #[error(transparent)]
+    ConfigError(#[from] account::config::Error),
 }
 
 pub type Result<T> = result::Result<T, Error>;
@@ -25,7 +35,8 @@ pub type Result<T> = result::Result<T, Error>;
 #[cfg(feature = "imap-backend")]
 #[derive(Debug, Default, Clone, Eq, PartialEq)]
 pub struct ImapConfig {
-    /// Represents the IMAP server host.
+    /// Represents the IMAP server host. `${VAR}` references are
+    /// replaced with the matching environment variables.
     pub host: String,
     /// Represents the IMAP server port.
     pub port: u16,
@@ -35,10 +46,28 @@ pub struct ImapConfig {
     pub starttls: Option<bool>,
     /// Trusts any certificate.
     pub insecure: Option<bool>,
+    /// Represents the minimum TLS version accepted from the server.
+    pub min_tls_version: Option<TlsVersion>,
+    /// Represents the names of the cipher suites the TLS connection
+    /// is restricted to. The native TLS backend cannot restrict
+    /// cipher suites, so setting this option makes the connection
+    /// fail instead of silently ignoring it.
+    pub allowed_cipher_suites: Option<Vec<String>>,
     /// Represents the IMAP server login.
     pub login: String,
-    /// Represents the IMAP server password command.
+    /// Represents the IMAP server password command. A lone `${VAR}`
+    /// reference is the password itself, otherwise the command is
+    /// run by the shell, which expands its variables.
     pub passwd_cmd: String,
+    /// Represents the size (in bytes) above which emails are uploaded
+    /// using non-synchronizing literals, when the server advertises
+    /// the `LITERAL+` capability.
+    pub literal_plus_threshold_bytes: Option<usize>,
+    /// Represents the proxy the IMAP server is reached through, for
+    /// example a SOCKS5 proxy like Tor. The proxy handshake happens
+    /// before the TLS one, and the host name of the IMAP server is
+    /// resolved by the proxy.
+    pub proxy: Option<ProxyConfig>,
 
     /// Represents the IMAP notify command.
     pub notify_cmd: Option<String>,
@@ -47,15 +76,85 @@ pub struct ImapConfig {
     pub notify_query: Option<String>,
     /// Represents the watch commands.
     pub watch_cmds: Option<Vec<String>>,
+
+    /// Represents the attributes fetched when listing envelopes page
+    /// by page, to reduce bandwidth when only some of them are
+    /// needed. Envelope fields whose attribute is not fetched are
+    /// left empty. Defaults to all of them. Listing all the envelopes
+    /// of a folder, like the synchronization does, always fetches all
+    /// of them.
+    pub envelope_fetch_attrs: Option<Vec<ImapFetchAttr>>,
+
+    /// Lists only the subscribed folders, using `LSUB` instead of
+    /// `LIST`. Non-subscribed folders are then left out of the
+    /// synchronization, and folders created by the synchronization
+    /// are subscribed to.
+    pub list_only_subscribed: bool,
+}
+
+/// Represents an attribute fetched when listing IMAP envelopes (see
+/// [`ImapConfig::envelope_fetch_attrs`]).
+#[cfg(feature = "imap-backend")]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum ImapFetchAttr {
+    /// Represents the UID, used as internal id. Always required.
+    Uid,
+    /// Represents the IMAP envelope: Message-ID, subject, sender and
+    /// date.
+    Envelope,
+    Flags,
+    /// Represents the date the server received the email at, used as
+    /// date when the envelope is not fetched.
+    InternalDate,
+    Size,
+}
+
+#[cfg(feature = "imap-backend")]
+impl fmt::Display for ImapFetchAttr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Uid => write!(f, "UID"),
+            Self::Envelope => write!(f, "ENVELOPE"),
+            Self::Flags => write!(f, "FLAGS"),
+            Self::InternalDate => write!(f, "INTERNALDATE"),
+            Self::Size => write!(f, "RFC822.SIZE"),
+        }
+    }
 }
 
 #[cfg(feature = "imap-backend")]
 impl ImapConfig {
+    /// Validates the IMAP configuration. All the invalid fields are
+    /// reported at once, see [`crate::AccountConfig::validate`].
+    pub fn validate(&self) -> result::Result<(), Vec<ValidationError>> {
+        let mut errs = Vec::new();
+
+        if self.host.trim().is_empty() {
+            errs.push(ValidationError::new("imap.host", "host cannot be empty"));
+        }
+        if self.port == 0 {
+            errs.push(ValidationError::new("imap.port", "port cannot be 0"));
+        }
+        if self.login.trim().is_empty() {
+            errs.push(ValidationError::new("imap.login", "login cannot be empty"));
+        }
+
+        if errs.is_empty() {
+            Ok(())
+        } else {
+            Err(errs)
+        }
+    }
+
     /// Executes the IMAP password command in order to retrieve the
     /// IMAP server password.
     pub fn passwd(&self) -> Result<String> {
-        let passwd = process::run(&self.passwd_cmd, &[]).map_err(Error::GetPasswdError)?;
-        let passwd = String::from_utf8_lossy(&passwd).to_string();
+        let passwd = if account::config::is_lone_env_ref(&self.passwd_cmd) {
+            account::config::interpolate_env(&self.passwd_cmd)?
+        } else {
+            let passwd = process::run(&self.passwd_cmd, &[]).map_err(Error::GetPasswdError)?;
+            String::from_utf8_lossy(&passwd).to_string()
+        };
         let passwd = passwd
             .lines()
             .next()
@@ -63,6 +162,12 @@ impl ImapConfig {
         Ok(passwd.to_owned())
     }
 
+    /// Gets the IMAP server host, with its environment variables
+    /// interpolated.
+    pub fn host(&self) -> Result<String> {
+        Ok(account::config::interpolate_env(&self.host)?)
+    }
+
     /// Gets the SSL IMAP option.
     pub fn ssl(&self) -> bool {
         self.ssl.unwrap_or(true)
@@ -78,6 +183,45 @@ impl ImapConfig {
         self.insecure.unwrap_or_default()
     }
 
+    /// Builds the fetch query used to list envelopes from the
+    /// configured attributes, or returns the default one (see
+    /// [`envelope::imap::FETCH_QUERY`]).
+    pub fn envelope_fetch_query(&self) -> Result<String> {
+        let attrs = match &self.envelope_fetch_attrs {
+            None => return Ok(envelope::imap::FETCH_QUERY.to_owned()),
+            Some(attrs) => attrs,
+        };
+
+        if !attrs.contains(&ImapFetchAttr::Uid) {
+            return Err(Error::MissingUidFetchAttrError);
+        }
+
+        let mut query: Vec<String> = Vec::new();
+        for attr in attrs {
+            let attr = attr.to_string();
+            if !query.contains(&attr) {
+                query.push(attr);
+            }
+        }
+
+        Ok(format!("({})", query.join(" ")))
+    }
+
+    /// Returns `true` if the IMAP envelope is part of the configured
+    /// fetch attributes (see [`ImapConfig::envelope_fetch_attrs`]).
+    pub fn fetches_envelope(&self) -> bool {
+        match &self.envelope_fetch_attrs {
+            None => true,
+            Some(attrs) => attrs.contains(&ImapFetchAttr::Envelope),
+        }
+    }
+
+    /// Gets the LITERAL+ threshold IMAP option.
+    pub fn literal_plus_threshold_bytes(&self) -> usize {
+        self.literal_plus_threshold_bytes
+            .unwrap_or(DEFAULT_LITERAL_PLUS_THRESHOLD_BYTES)
+    }
+
     /// Runs the IMAP notify command.
     pub fn run_notify_cmd<S: AsRef<str>>(&self, id: u32, subject: S, sender: S) -> Result<()> {
         let cmd = self
@@ -109,3 +253,53 @@ impl ImapConfig {
             .unwrap_or_else(|| Vec::new())
     }
 }
+
+#[cfg(all(test, feature = "imap-backend"))]
+mod imap_config {
+    use crate::envelope;
+
+    use super::{Error, ImapConfig, ImapFetchAttr};
+
+    #[test]
+    fn validate() {
+        let config = ImapConfig {
+            host: "localhost".into(),
+            port: 993,
+            login: "alice".into(),
+            ..ImapConfig::default()
+        };
+        assert_eq!(Ok(()), config.validate());
+
+        let fields: Vec<_> = ImapConfig::default()
+            .validate()
+            .unwrap_err()
+            .into_iter()
+            .map(|err| err.field)
+            .collect();
+        assert_eq!(vec!["imap.host", "imap.port", "imap.login"], fields);
+    }
+
+    #[test]
+    fn envelope_fetch_query() {
+        let mut config = ImapConfig::default();
+        assert_eq!(
+            envelope::imap::FETCH_QUERY,
+            config.envelope_fetch_query().unwrap()
+        );
+
+        config.envelope_fetch_attrs = Some(vec![
+            ImapFetchAttr::Uid,
+            ImapFetchAttr::Flags,
+            ImapFetchAttr::Uid,
+        ]);
+        assert_eq!("(UID FLAGS)", config.envelope_fetch_query().unwrap());
+        assert!(!config.fetches_envelope());
+
+        config.envelope_fetch_attrs = Some(vec![ImapFetchAttr::Envelope]);
+        assert!(config.fetches_envelope());
+        assert!(matches!(
+            config.envelope_fetch_query(),
+            Err(Error::MissingUidFetchAttrError)
+        ));
+    }
+}
@@ -0,0 +1,160 @@
+//! IMAP event module.
+//!
+//! This module contains the IMAP implementation of the backend
+//! events subscription, based on the IDLE extension ([RFC 2177]).
+//!
+//! [RFC 2177]: https://www.rfc-editor.org/rfc/rfc2177
+
+use imap::extensions::idle::stop_on_any;
+use log::{debug, trace};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    time::Duration,
+};
+
+use crate::{
+    backend::{
+        self,
+        imap::{Error, ImapSession, Result},
+    },
+    envelope, folder, BackendEvent, Flags,
+};
+
+/// Represents the default IDLE keepalive. Servers may drop idling
+/// clients after 30 minutes of inactivity, the IDLE command is
+/// renewed before that.
+pub const DEFAULT_IDLE_KEEPALIVE: Duration = Duration::from_secs(500);
+
+/// Represents the stream of IMAP backend events. The stream owns a
+/// dedicated session, since an idling session cannot be used for
+/// anything else.
+pub struct ImapEventStream {
+    session: ImapSession,
+    folder: String,
+    keepalive: Duration,
+    folders: HashSet<String>,
+    flags: HashMap<u32, Flags>,
+    events: VecDeque<BackendEvent>,
+}
+
+impl ImapEventStream {
+    pub fn new(mut session: ImapSession, folder: String, keepalive: Duration) -> Result<Self> {
+        let folder_encoded = folder::imap::encode(&folder);
+        session
+            .examine(&folder_encoded)
+            .map_err(|err| Error::ExamineFolderError(err, folder.clone()))?;
+
+        let mut stream = Self {
+            session,
+            folder,
+            keepalive,
+            folders: HashSet::new(),
+            flags: HashMap::new(),
+            events: VecDeque::new(),
+        };
+
+        stream.poll(false)?;
+
+        Ok(stream)
+    }
+
+    /// Compares the current state of the folders and of the flags
+    /// with the previous one. Events are only emitted if `emit` is
+    /// `true`, which avoids fetching all the envelopes when building
+    /// the initial state.
+    fn poll(&mut self, emit: bool) -> Result<()> {
+        let folders: HashSet<String> = self
+            .session
+            .list(Some(""), Some("*"))
+            .map_err(Error::ListFoldersError)?
+            .iter()
+            .map(|folder| folder::imap::decode(folder.name()))
+            .collect();
+
+        if emit {
+            for folder in folders.difference(&self.folders) {
+                debug!("imap folder {folder} created");
+                self.events
+                    .push_back(BackendEvent::FolderCreated(folder.clone()));
+            }
+        }
+
+        self.folders = folders;
+
+        let mut flags = HashMap::with_capacity(self.flags.len());
+        let mut new_uids = HashSet::new();
+        let mut changed_uids = Vec::new();
+
+        let fetches = self
+            .session
+            .uid_fetch("1:*", "(UID FLAGS)")
+            .map_err(|err| Error::FetchEmailsByUidRangeError(err, "1:*".into()))?;
+
+        for fetch in fetches.iter() {
+            let uid = fetch.uid.ok_or(Error::GetUidError(fetch.message))?;
+            let fetch_flags = Flags::from(fetch.flags());
+
+            match self.flags.get(&uid) {
+                None => {
+                    new_uids.insert(uid);
+                    changed_uids.push(uid.to_string());
+                }
+                Some(prev_flags) if *prev_flags != fetch_flags => {
+                    changed_uids.push(uid.to_string());
+                }
+                Some(_) => (),
+            }
+
+            flags.insert(uid, fetch_flags);
+        }
+
+        self.flags = flags;
+
+        if !emit || changed_uids.is_empty() {
+            return Ok(());
+        }
+
+        let fetches = self
+            .session
+            .uid_fetch(changed_uids.join(","), envelope::imap::FETCH_QUERY)
+            .map_err(Error::FetchNewEnvelopesError)?;
+
+        for fetch in fetches.iter() {
+            let uid = fetch.uid.ok_or(Error::GetUidError(fetch.message))?;
+            let envelope = envelope::imap::from_raw(fetch)?;
+            trace!("imap envelope event: {envelope:?}");
+
+            let folder = self.folder.clone();
+            self.events.push_back(if new_uids.contains(&uid) {
+                BackendEvent::NewEnvelope { folder, envelope }
+            } else {
+                BackendEvent::FlagsChanged { folder, envelope }
+            });
+        }
+
+        Ok(())
+    }
+}
+
+impl Iterator for ImapEventStream {
+    type Item = backend::Result<BackendEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Some(Ok(event));
+            }
+
+            let idle = self
+                .session
+                .idle()
+                .timeout(self.keepalive)
+                .wait_while(stop_on_any)
+                .map_err(Error::StartIdleModeError);
+
+            if let Err(err) = idle.and_then(|_| self.poll(true)) {
+                return Some(Err(err.into()));
+            }
+        }
+    }
+}
@@ -1,5 +1,8 @@
 pub mod config;
-pub use config::ImapConfig;
+pub use config::{ImapConfig, ImapFetchAttr};
+
+pub mod event;
+pub use event::ImapEventStream;
 
 pub mod backend;
 pub use backend::*;
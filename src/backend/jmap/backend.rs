@@ -0,0 +1,801 @@
+//! JMAP backend module.
+//!
+//! This module contains the definition of the JMAP backend ([RFC
+//! 8621]). JMAP mailboxes are exposed as folders, named after their
+//! path, and JMAP keywords as flags.
+//!
+//! Emails are identified by their JMAP email id, which never changes,
+//! so ids and internal ids are the same. A JMAP email can belong to
+//! several mailboxes at once: copying an email adds the target
+//! mailbox to it instead of duplicating it, and deleting an email
+//! from a folder only destroys it when it belongs to no other
+//! mailbox.
+//!
+//! JMAP has no keyword for deleted emails: flagging emails as
+//! deleted moves them to the mailbox having the trash role, or
+//! destroys them if they already are in it or if there is no such
+//! mailbox.
+//!
+//! Searching is not supported yet, nor is the state-based change
+//! tracking of JMAP: the synchronization lists envelopes like it
+//! does for other backends.
+//!
+//! [RFC 8621]: https://www.rfc-editor.org/rfc/rfc8621
+
+use log::{debug, info, trace};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Map, Value};
+use std::{any::Any, borrow::Cow, collections::HashMap, result};
+use thiserror::Error;
+
+use crate::{
+    account,
+    backend::{
+        self,
+        jmap::{config, session::result_of, JmapSession},
+    },
+    envelope, flag, AccountConfig, Backend, Emails, Envelope, Envelopes, Flag, Flags, Folder,
+    FolderSpecialUse, Folders, JmapConfig, DEFAULT_INBOX_FOLDER,
+};
+
+/// Represents the maximum number of envelopes listed per request
+/// when listing all the envelopes of a folder. Servers may return
+/// less.
+const ENVELOPES_CHUNK_SIZE: usize = 256;
+
+/// Represents the maximum number of times the listing of all the
+/// envelopes of a folder restarts because the mailbox changed in
+/// between two chunks.
+const MAX_LISTING_RESTARTS: usize = 3;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("cannot build jmap http client")]
+    BuildHttpClientError(#[source] reqwest::Error),
+    #[error("cannot get jmap session at {1}")]
+    GetSessionError(#[source] reqwest::Error, String),
+    #[error("cannot find jmap mail account in session at {0}")]
+    FindMailAccountError(String),
+    #[error("cannot send jmap request {1}")]
+    SendRequestError(#[source] reqwest::Error, String),
+    #[error("jmap method {0} failed: {1} {2}")]
+    MethodError(String, String, String),
+    #[error("cannot find responses to jmap request {0}")]
+    MissingResponseError(String),
+    #[error("cannot parse jmap response to method {1}")]
+    ParseResponseError(#[source] serde_json::Error, String),
+    #[error("cannot download jmap blob {1}")]
+    DownloadBlobError(#[source] reqwest::Error, String),
+    #[error("cannot upload jmap blob")]
+    UploadBlobError(#[source] reqwest::Error),
+    #[error("cannot parse jmap email")]
+    ParseEmailError(#[source] serde_json::Error),
+    #[error("cannot find jmap mailbox {0}")]
+    FindMailboxError(String),
+    #[error("cannot find jmap email {0}")]
+    FindEmailError(String),
+    #[error("cannot import jmap email to mailbox {0}: {1}")]
+    ImportEmailError(String, String),
+    #[error("cannot update jmap emails: {0}")]
+    UpdateEmailsError(String),
+    #[error("cannot destroy jmap emails: {0}")]
+    DestroyEmailsError(String),
+    #[error("cannot create jmap mailbox {0}: {1}")]
+    CreateMailboxError(String, String),
+    #[error("cannot delete jmap mailbox {0}: {1}")]
+    DeleteMailboxError(String, String),
+    #[error("cannot list jmap envelopes of mailbox {0}: mailbox keeps changing")]
+    ListEnvelopesChangedError(String),
+
+    #[error(transparent)]
+    JmapConfigError(#[from] config::Error),
+    #[error(transparent)]
+    ConfigError(#[from] account::config::Error),
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+/// Represents a JMAP mailbox, with its path computed from its
+/// parents.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JmapMailbox {
+    id: String,
+    name: String,
+    parent_id: Option<String>,
+    role: Option<String>,
+    #[serde(skip)]
+    path: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GetResponse<T> {
+    list: Vec<T>,
+    #[serde(default)]
+    not_found: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct QueryResponse {
+    ids: Vec<String>,
+    query_state: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct SetError {
+    #[serde(rename = "type")]
+    kind: String,
+    description: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SetResponse {
+    #[serde(default)]
+    created: Option<HashMap<String, Value>>,
+    #[serde(default)]
+    not_created: Option<HashMap<String, SetError>>,
+    #[serde(default)]
+    not_updated: Option<HashMap<String, SetError>>,
+    #[serde(default)]
+    not_destroyed: Option<HashMap<String, SetError>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EmailBlob {
+    id: String,
+    blob_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EmailMailboxes {
+    id: String,
+    mailbox_ids: HashMap<String, bool>,
+}
+
+/// Parses the arguments of the response to the given method.
+fn parse<T: DeserializeOwned>(method: &str, res: Value) -> Result<T> {
+    serde_json::from_value(res).map_err(|err| Error::ParseResponseError(err, method.to_owned()))
+}
+
+/// Describes the given set errors, by id.
+fn describe(errors: HashMap<String, SetError>) -> String {
+    errors
+        .into_iter()
+        .map(|(id, err)| match err.description {
+            Some(desc) => format!("{id}: {} ({desc})", err.kind),
+            None => format!("{id}: {}", err.kind),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Escapes the given string to be used as a JSON pointer segment in
+/// a patch object.
+fn escape_pointer(segment: &str) -> String {
+    segment.replace('~', "~0").replace('/', "~1")
+}
+
+/// Builds a patch object applying the same patch to all the given
+/// emails.
+fn patch_all(ids: &[&str], patch: Map<String, Value>) -> Map<String, Value> {
+    ids.iter()
+        .map(|id| (id.to_string(), Value::Object(patch.clone())))
+        .collect()
+}
+
+pub struct JmapBackend<'a> {
+    account_config: Cow<'a, AccountConfig>,
+    session: JmapSession,
+}
+
+impl<'a> JmapBackend<'a> {
+    pub fn new(
+        account_config: Cow<'a, AccountConfig>,
+        jmap_config: Cow<'a, JmapConfig>,
+    ) -> Result<Self> {
+        let token = jmap_config.token()?;
+        let session = JmapSession::discover(&jmap_config, token)?;
+
+        Ok(Self {
+            account_config,
+            session,
+        })
+    }
+
+    /// Sends a single method call, and returns the arguments of its
+    /// response.
+    fn call<T: DeserializeOwned>(&self, method: &str, args: Value) -> Result<T> {
+        let res = self.session.call(vec![(method, args)])?.remove(0);
+        parse(method, res)
+    }
+
+    /// Lists the mailboxes of the account, with their path.
+    fn mailboxes(&self) -> Result<Vec<JmapMailbox>> {
+        let res: GetResponse<JmapMailbox> = self.call(
+            "Mailbox/get",
+            json!({ "ids": null, "properties": ["id", "name", "parentId", "role"] }),
+        )?;
+        let mut mailboxes = res.list;
+
+        let parents: HashMap<String, (String, Option<String>)> = mailboxes
+            .iter()
+            .map(|mbox| (mbox.id.clone(), (mbox.name.clone(), mbox.parent_id.clone())))
+            .collect();
+
+        for mbox in mailboxes.iter_mut() {
+            let mut path = mbox.name.clone();
+            let mut parent_id = mbox.parent_id.clone();
+
+            // the depth is bounded in case of a cycle
+            for _ in 0..parents.len() {
+                match parent_id.and_then(|id| parents.get(&id)) {
+                    Some((name, next_parent_id)) => {
+                        path = format!("{name}/{path}");
+                        parent_id = next_parent_id.clone();
+                    }
+                    None => break,
+                }
+            }
+
+            mbox.path = path;
+        }
+
+        trace!("jmap mailboxes: {mailboxes:#?}");
+        Ok(mailboxes)
+    }
+
+    /// Finds the mailbox matching the given folder, by path first,
+    /// then case-insensitively. The inbox folder also matches the
+    /// mailbox having the inbox role.
+    fn find_mailbox(&self, mailboxes: &[JmapMailbox], folder: &str) -> Result<String> {
+        let folder = self.account_config.folder_alias(folder)?;
+
+        mailboxes
+            .iter()
+            .find(|mbox| mbox.path == folder)
+            .or_else(|| {
+                mailboxes
+                    .iter()
+                    .find(|mbox| mbox.path.eq_ignore_ascii_case(&folder))
+            })
+            .or_else(|| {
+                if folder.eq_ignore_ascii_case(DEFAULT_INBOX_FOLDER) {
+                    mailboxes
+                        .iter()
+                        .find(|mbox| mbox.role.as_deref() == Some("inbox"))
+                } else {
+                    None
+                }
+            })
+            .map(|mbox| mbox.id.clone())
+            .ok_or_else(|| Error::FindMailboxError(folder.clone()))
+    }
+
+    /// Returns the id of the mailbox matching the given folder.
+    fn mailbox_id(&self, folder: &str) -> Result<String> {
+        let mailboxes = self.mailboxes()?;
+        self.find_mailbox(&mailboxes, folder)
+    }
+
+    /// Lists the envelopes of the given mailbox, from the most
+    /// recently received, in one request: the ids returned by
+    /// `Email/query` are passed to `Email/get` by back-reference.
+    /// The query state is returned along with the envelopes, so that
+    /// chunks of the same listing can be checked against each other.
+    fn query_envelopes(
+        &self,
+        mailbox_id: &str,
+        position: usize,
+        limit: usize,
+    ) -> Result<(Envelopes, String)> {
+        let mut res = self
+            .session
+            .call(vec![
+                (
+                    "Email/query",
+                    json!({
+                        "filter": { "inMailbox": mailbox_id },
+                        "sort": [{ "property": "receivedAt", "isAscending": false }],
+                        "position": position,
+                        "limit": limit,
+                    }),
+                ),
+                (
+                    "Email/get",
+                    json!({
+                        "#ids": result_of(0, "Email/query", "/ids"),
+                        "properties": envelope::jmap::PROPERTIES,
+                    }),
+                ),
+            ])?
+            .into_iter();
+        let query: QueryResponse = parse("Email/query", res.next().unwrap_or_default())?;
+        let res: GetResponse<Value> = parse("Email/get", res.next().unwrap_or_default())?;
+
+        let envelopes = res
+            .list
+            .into_iter()
+            .map(envelope::jmap::from_json)
+            .collect::<Result<Envelopes>>()?;
+
+        Ok((envelopes, query.query_state))
+    }
+
+    /// Lists all the envelopes of the given mailbox, chunk by chunk.
+    ///
+    /// Chunks are fetched by position, which shifts when emails are
+    /// added to or removed from the mailbox in between. The listing
+    /// restarts when the query state changes from one chunk to the
+    /// next, so that no envelope is skipped nor listed twice.
+    fn query_all_envelopes(&self, folder: &str, mailbox_id: &str) -> Result<Envelopes> {
+        for _ in 0..=MAX_LISTING_RESTARTS {
+            let mut envelopes = Envelopes::default();
+            let mut query_state = None;
+
+            // servers may return less envelopes than requested, the
+            // listing stops at the first empty chunk
+            let changed = loop {
+                let (chunk, state) =
+                    self.query_envelopes(mailbox_id, envelopes.len(), ENVELOPES_CHUNK_SIZE)?;
+                match &query_state {
+                    Some(query_state) if *query_state != state => break true,
+                    Some(_) => (),
+                    None => query_state = Some(state),
+                }
+                if chunk.is_empty() {
+                    break false;
+                }
+                envelopes.extend(chunk);
+            };
+
+            if !changed {
+                return Ok(envelopes);
+            }
+            debug!("jmap mailbox {folder} changed while listing its envelopes, restarting");
+        }
+
+        Err(Error::ListEnvelopesChangedError(folder.to_owned()))
+    }
+
+    /// Lists the ids of all the emails of the given mailbox.
+    fn query_ids(&self, mailbox_id: &str) -> Result<Vec<String>> {
+        let mut ids = Vec::new();
+
+        loop {
+            let res: QueryResponse = self.call(
+                "Email/query",
+                json!({
+                    "filter": { "inMailbox": mailbox_id },
+                    "position": ids.len(),
+                    "limit": ENVELOPES_CHUNK_SIZE,
+                }),
+            )?;
+
+            if res.ids.is_empty() {
+                break;
+            }
+            ids.extend(res.ids);
+        }
+
+        Ok(ids)
+    }
+
+    /// Applies the given patch objects to emails, by id.
+    fn update_emails(&self, update: Map<String, Value>) -> Result<()> {
+        trace!("jmap email updates: {update:?}");
+        let res: SetResponse = self.call("Email/set", json!({ "update": update }))?;
+
+        match res.not_updated {
+            Some(errors) if !errors.is_empty() => Err(Error::UpdateEmailsError(describe(errors))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Moves the given emails to the mailbox having the trash role,
+    /// since JMAP has no keyword for deleted emails. Emails already
+    /// in the trash, or of accounts without trash, are destroyed.
+    fn trash_emails(&self, folder: &str, ids: Vec<&str>) -> backend::Result<()> {
+        let mailboxes = self.mailboxes()?;
+        let mailbox_id = self.find_mailbox(&mailboxes, folder)?;
+        let trash = mailboxes
+            .iter()
+            .find(|mbox| mbox.role.as_deref() == Some("trash"));
+
+        match trash {
+            Some(trash) if trash.id != mailbox_id => {
+                debug!(
+                    "moving deleted jmap emails {ids:?} to mailbox {}",
+                    trash.path
+                );
+                let patch = Map::from_iter([
+                    (
+                        format!("mailboxIds/{}", escape_pointer(&mailbox_id)),
+                        Value::Null,
+                    ),
+                    (
+                        format!("mailboxIds/{}", escape_pointer(&trash.id)),
+                        Value::Bool(true),
+                    ),
+                ]);
+                self.update_emails(patch_all(&ids, patch))?;
+                Ok(())
+            }
+            _ => self.delete_emails(folder, ids),
+        }
+    }
+
+    /// Builds a patch setting the given keywords to the given value,
+    /// keeping the other keywords.
+    fn keywords_patch(flags: &Flags, value: Value) -> Map<String, Value> {
+        flags
+            .iter()
+            .filter_map(flag::jmap::to_keyword)
+            .map(|keyword| {
+                (
+                    format!("keywords/{}", escape_pointer(&keyword)),
+                    value.clone(),
+                )
+            })
+            .collect()
+    }
+}
+
+impl<'a> Backend for JmapBackend<'a> {
+    fn name(&self) -> String {
+        self.account_config.name.clone()
+    }
+
+    fn add_folder(&self, folder: &str) -> backend::Result<()> {
+        info!("adding jmap mailbox {folder}");
+
+        let folder = self.account_config.folder_alias(folder)?;
+        let (parent_id, name) = match folder.rsplit_once('/') {
+            Some((parent, name)) => (Some(self.mailbox_id(parent)?), name),
+            None => (None, folder.as_str()),
+        };
+
+        let res: SetResponse = self.call(
+            "Mailbox/set",
+            json!({ "create": { "0": { "name": name, "parentId": parent_id } } }),
+        )?;
+
+        match res.not_created {
+            Some(errors) if !errors.is_empty() => {
+                Err(Error::CreateMailboxError(folder, describe(errors)))?
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn list_folders(&self) -> backend::Result<Folders> {
+        info!("listing jmap mailboxes");
+
+        let folders = self
+            .mailboxes()?
+            .into_iter()
+            .map(|mbox| Folder {
+                delim: String::from("/"),
+                name: mbox.path.clone(),
+                raw_name: mbox.path,
+                desc: mbox.role.clone().unwrap_or_default(),
+                special_use: match mbox.role.as_deref() {
+                    Some("inbox") => Some(FolderSpecialUse::Inbox),
+                    Some("sent") => Some(FolderSpecialUse::Sent),
+                    Some("trash") => Some(FolderSpecialUse::Trash),
+                    Some("drafts") => Some(FolderSpecialUse::Drafts),
+                    Some("junk") => Some(FolderSpecialUse::Spam),
+                    _ => None,
+                },
+            })
+            .collect();
+
+        Ok(folders)
+    }
+
+    fn purge_folder(&self, folder: &str) -> backend::Result<()> {
+        info!("purging jmap mailbox {folder}");
+
+        let mailbox_id = self.mailbox_id(folder)?;
+        let ids = self.query_ids(&mailbox_id)?;
+        debug!("purging {} jmap emails", ids.len());
+
+        if !ids.is_empty() {
+            self.delete_emails(folder, ids.iter().map(String::as_str).collect())?;
+        }
+
+        Ok(())
+    }
+
+    fn delete_folder(&self, folder: &str) -> backend::Result<()> {
+        info!("deleting jmap mailbox {folder}");
+
+        let mailbox_id = self.mailbox_id(folder)?;
+        let res: SetResponse = self.call(
+            "Mailbox/set",
+            json!({ "destroy": [mailbox_id], "onDestroyRemoveEmails": true }),
+        )?;
+
+        match res.not_destroyed {
+            Some(errors) if !errors.is_empty() => Err(Error::DeleteMailboxError(
+                folder.to_owned(),
+                describe(errors),
+            ))?,
+            _ => Ok(()),
+        }
+    }
+
+    fn get_envelope(&self, folder: &str, id: &str) -> backend::Result<Envelope> {
+        info!("getting jmap envelope {id} from mailbox {folder}");
+
+        let res: GetResponse<Value> = self.call(
+            "Email/get",
+            json!({ "ids": [id], "properties": envelope::jmap::PROPERTIES }),
+        )?;
+        let email = res
+            .list
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::FindEmailError(id.to_owned()))?;
+
+        Ok(envelope::jmap::from_json(email)?)
+    }
+
+    fn list_envelopes(
+        &self,
+        folder: &str,
+        page_size: usize,
+        page: usize,
+    ) -> backend::Result<Envelopes> {
+        info!("listing jmap envelopes of mailbox {folder}");
+        trace!("page size: {}", page_size);
+        trace!("page: {}", page);
+
+        let mailbox_id = self.mailbox_id(folder)?;
+
+        let envelopes = if page_size > 0 {
+            self.query_envelopes(&mailbox_id, page * page_size, page_size)?
+                .0
+        } else {
+            self.query_all_envelopes(folder, &mailbox_id)?
+        };
+
+        Ok(envelopes)
+    }
+
+    fn search_envelopes(
+        &self,
+        _folder: &str,
+        _query: &str,
+        _sort: &str,
+        _page_size: usize,
+        _page: usize,
+    ) -> backend::Result<Envelopes> {
+        Err(backend::Error::NotSupported)
+    }
+
+    /// Uploads the email as a blob, then imports it into the mailbox
+    /// matching the given folder.
+    fn add_email(&self, folder: &str, email: &[u8], flags: &Flags) -> backend::Result<String> {
+        info!(
+            "adding jmap email to mailbox {folder} with flags {flags}",
+            flags = flags.to_string()
+        );
+
+        let mailbox_id = self.mailbox_id(folder)?;
+        let blob_id = self.session.upload(email)?;
+
+        let res: SetResponse = self.call(
+            "Email/import",
+            json!({
+                "emails": {
+                    "0": {
+                        "blobId": blob_id,
+                        "mailboxIds": { mailbox_id: true },
+                        "keywords": flag::jmap::to_keywords(flags),
+                    }
+                }
+            }),
+        )?;
+
+        let id = res
+            .created
+            .and_then(|mut created| created.remove("0"))
+            .and_then(|email| email["id"].as_str().map(ToOwned::to_owned));
+
+        let id = match id {
+            Some(id) => id,
+            None => {
+                let desc = res.not_created.map(describe).unwrap_or_default();
+                return Err(Error::ImportEmailError(folder.to_owned(), desc))?;
+            }
+        };
+
+        if flags.contains(&Flag::Deleted) {
+            self.trash_emails(folder, vec![&id])?;
+        }
+
+        Ok(id)
+    }
+
+    /// Downloads the blobs of the emails, which does not change their
+    /// keywords.
+    fn preview_emails(&self, folder: &str, ids: Vec<&str>) -> backend::Result<Emails> {
+        info!("previewing jmap emails {ids:?} from mailbox {folder}");
+
+        let res: GetResponse<EmailBlob> = self.call(
+            "Email/get",
+            json!({ "ids": ids, "properties": ["id", "blobId"] }),
+        )?;
+        trace!("jmap emails not found: {:?}", res.not_found);
+
+        let emails = ids
+            .iter()
+            .map(|id| {
+                let email = res
+                    .list
+                    .iter()
+                    .find(|email| email.id == *id)
+                    .ok_or_else(|| Error::FindEmailError(id.to_string()))?;
+                self.session.download(&email.blob_id)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Emails::from(emails))
+    }
+
+    fn get_emails(&self, folder: &str, ids: Vec<&str>) -> backend::Result<Emails> {
+        info!("getting jmap emails {ids:?} from mailbox {folder}");
+
+        let emails = self.preview_emails(folder, ids.clone())?;
+        self.add_flags(folder, ids, &Flags::from_iter([Flag::Seen]))?;
+
+        Ok(emails)
+    }
+
+    /// Adds the target mailbox to the emails, since JMAP emails can
+    /// belong to several mailboxes.
+    fn copy_emails(
+        &self,
+        from_folder: &str,
+        to_folder: &str,
+        ids: Vec<&str>,
+    ) -> backend::Result<()> {
+        info!("copying jmap emails {ids:?} from mailbox {from_folder} to mailbox {to_folder}");
+
+        let to_mailbox_id = self.mailbox_id(to_folder)?;
+        let patch = Map::from_iter([(
+            format!("mailboxIds/{}", escape_pointer(&to_mailbox_id)),
+            Value::Bool(true),
+        )]);
+        self.update_emails(patch_all(&ids, patch))?;
+
+        Ok(())
+    }
+
+    fn move_emails(
+        &self,
+        from_folder: &str,
+        to_folder: &str,
+        ids: Vec<&str>,
+    ) -> backend::Result<()> {
+        info!("moving jmap emails {ids:?} from mailbox {from_folder} to mailbox {to_folder}");
+
+        let mailboxes = self.mailboxes()?;
+        let from_mailbox_id = self.find_mailbox(&mailboxes, from_folder)?;
+        let to_mailbox_id = self.find_mailbox(&mailboxes, to_folder)?;
+        let patch = Map::from_iter([
+            (
+                format!("mailboxIds/{}", escape_pointer(&from_mailbox_id)),
+                Value::Null,
+            ),
+            (
+                format!("mailboxIds/{}", escape_pointer(&to_mailbox_id)),
+                Value::Bool(true),
+            ),
+        ]);
+        self.update_emails(patch_all(&ids, patch))?;
+
+        Ok(())
+    }
+
+    /// Destroys the emails belonging to the given folder only, and
+    /// removes the others from the folder.
+    fn delete_emails(&self, folder: &str, ids: Vec<&str>) -> backend::Result<()> {
+        info!("deleting jmap emails {ids:?} from mailbox {folder}");
+
+        let mailbox_id = self.mailbox_id(folder)?;
+        let res: GetResponse<EmailMailboxes> = self.call(
+            "Email/get",
+            json!({ "ids": ids, "properties": ["id", "mailboxIds"] }),
+        )?;
+
+        let mut destroy = Vec::new();
+        let mut update = Map::new();
+        for email in res.list {
+            if email.mailbox_ids.keys().all(|id| *id == mailbox_id) {
+                destroy.push(email.id);
+            } else {
+                let patch = Map::from_iter([(
+                    format!("mailboxIds/{}", escape_pointer(&mailbox_id)),
+                    Value::Null,
+                )]);
+                update.insert(email.id, Value::Object(patch));
+            }
+        }
+        debug!("destroying jmap emails {destroy:?}, removing from mailbox {update:?}");
+
+        let res: SetResponse =
+            self.call("Email/set", json!({ "destroy": destroy, "update": update }))?;
+
+        if let Some(errors) = res.not_destroyed.filter(|errors| !errors.is_empty()) {
+            return Err(Error::DestroyEmailsError(describe(errors)))?;
+        }
+        if let Some(errors) = res.not_updated.filter(|errors| !errors.is_empty()) {
+            return Err(Error::UpdateEmailsError(describe(errors)))?;
+        }
+
+        Ok(())
+    }
+
+    fn add_flags(&self, folder: &str, ids: Vec<&str>, flags: &Flags) -> backend::Result<()> {
+        info!(
+            "adding flags {flags} to jmap emails {ids:?} from mailbox {folder}",
+            flags = flags.to_string(),
+        );
+
+        let patch = Self::keywords_patch(flags, Value::Bool(true));
+        if !patch.is_empty() {
+            self.update_emails(patch_all(&ids, patch))?;
+        }
+
+        if flags.contains(&Flag::Deleted) {
+            self.trash_emails(folder, ids)?;
+        }
+
+        Ok(())
+    }
+
+    fn set_flags(&self, folder: &str, ids: Vec<&str>, flags: &Flags) -> backend::Result<()> {
+        info!(
+            "setting flags {flags} to jmap emails {ids:?} from mailbox {folder}",
+            flags = flags.to_string(),
+        );
+
+        let patch = Map::from_iter([(
+            String::from("keywords"),
+            Value::Object(flag::jmap::to_keywords(flags)),
+        )]);
+        self.update_emails(patch_all(&ids, patch))?;
+
+        if flags.contains(&Flag::Deleted) {
+            self.trash_emails(folder, ids)?;
+        }
+
+        Ok(())
+    }
+
+    /// Removing the deleted flag does nothing, since deleted emails
+    /// are not kept in their mailbox.
+    fn remove_flags(&self, folder: &str, ids: Vec<&str>, flags: &Flags) -> backend::Result<()> {
+        info!(
+            "removing flags {flags} from jmap emails {ids:?} from mailbox {folder}",
+            flags = flags.to_string(),
+        );
+
+        let patch = Self::keywords_patch(flags, Value::Null);
+        if !patch.is_empty() {
+            self.update_emails(patch_all(&ids, patch))?;
+        }
+
+        Ok(())
+    }
+
+    fn as_any(&'static self) -> &(dyn Any) {
+        self
+    }
+}
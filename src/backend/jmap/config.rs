@@ -0,0 +1,51 @@
+//! JMAP backend config module.
+//!
+//! This module contains the representation of the JMAP backend
+//! configuration of the user account.
+
+use std::result;
+use thiserror::Error;
+
+use crate::process;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("cannot get jmap token")]
+    GetTokenError(#[source] process::Error),
+    #[error("cannot get jmap token: token is empty")]
+    GetTokenEmptyError,
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+/// Represents the JMAP backend configuration.
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct JmapConfig {
+    /// Represents the JMAP server URL, for example
+    /// `https://api.fastmail.com`. The session resource is discovered
+    /// at its `/.well-known/jmap` path.
+    pub url: String,
+    /// Represents the JMAP bearer token command.
+    pub token_cmd: String,
+}
+
+impl JmapConfig {
+    /// Executes the JMAP token command in order to retrieve the
+    /// bearer token used to authenticate requests.
+    pub fn token(&self) -> Result<String> {
+        let token = process::run(&self.token_cmd, &[]).map_err(Error::GetTokenError)?;
+        let token = String::from_utf8_lossy(&token).to_string();
+        let token = token
+            .lines()
+            .next()
+            .map(str::trim)
+            .filter(|token| !token.is_empty())
+            .ok_or_else(|| Error::GetTokenEmptyError)?;
+        Ok(token.to_owned())
+    }
+
+    /// Gets the well-known URL of the JMAP session resource.
+    pub fn session_url(&self) -> String {
+        format!("{}/.well-known/jmap", self.url.trim_end_matches('/'))
+    }
+}
@@ -0,0 +1,8 @@
+pub mod config;
+pub use config::JmapConfig;
+
+pub mod session;
+pub use session::JmapSession;
+
+pub mod backend;
+pub use backend::*;
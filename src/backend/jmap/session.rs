@@ -0,0 +1,229 @@
+//! JMAP session module.
+//!
+//! This module contains a minimal JMAP client ([RFC 8620]), covering
+//! the session discovery, the batched method calls and the blobs
+//! upload and download needed by the JMAP backend.
+//!
+//! [RFC 8620]: https://www.rfc-editor.org/rfc/rfc8620
+
+use log::{debug, trace};
+use reqwest::{blocking::Client, header::CONTENT_TYPE};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+use crate::backend::jmap::{Error, JmapConfig, Result};
+
+/// Represents the capabilities used by the JMAP backend.
+const USING: [&str; 2] = ["urn:ietf:params:jmap:core", "urn:ietf:params:jmap:mail"];
+
+/// Represents the capability identifying the mail account.
+const MAIL_CAPABILITY: &str = "urn:ietf:params:jmap:mail";
+
+/// Represents the session resource, as returned by the well-known
+/// URL.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SessionResource {
+    api_url: String,
+    download_url: String,
+    upload_url: String,
+    primary_accounts: HashMap<String, String>,
+}
+
+/// Represents the response to a batch of method calls.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Response {
+    method_responses: Vec<(String, Value, String)>,
+}
+
+/// Represents the response to a blob upload.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UploadResponse {
+    blob_id: String,
+}
+
+/// Represents an authenticated JMAP session.
+///
+/// Unlike IMAP sessions, no connection is kept open: every method
+/// call is a stateless HTTP request authenticated with the bearer
+/// token.
+pub struct JmapSession {
+    client: Client,
+    token: String,
+    api_url: String,
+    download_url: String,
+    upload_url: String,
+    account_id: String,
+}
+
+impl JmapSession {
+    /// Discovers the session resource from the well-known URL of the
+    /// given config, and keeps the primary mail account.
+    pub fn discover(config: &JmapConfig, token: String) -> Result<Self> {
+        let client = Client::builder()
+            .build()
+            .map_err(Error::BuildHttpClientError)?;
+
+        let url = config.session_url();
+        debug!("discovering jmap session at {url}");
+
+        let session: SessionResource = client
+            .get(&url)
+            .bearer_auth(&token)
+            .send()
+            .and_then(|res| res.error_for_status())
+            .and_then(|res| res.json())
+            .map_err(|err| Error::GetSessionError(err, url.clone()))?;
+        trace!("jmap session: {session:?}");
+
+        let account_id = session
+            .primary_accounts
+            .get(MAIL_CAPABILITY)
+            .cloned()
+            .ok_or(Error::FindMailAccountError(url))?;
+
+        Ok(Self {
+            client,
+            token,
+            api_url: session.api_url,
+            download_url: session.download_url,
+            upload_url: session.upload_url,
+            account_id,
+        })
+    }
+
+    /// Returns the id of the mail account.
+    pub fn account_id(&self) -> &str {
+        &self.account_id
+    }
+
+    /// Sends the given method calls in one request, and returns the
+    /// arguments of their responses in the same order. The account id
+    /// is added to the arguments of every call.
+    ///
+    /// Calls are identified by their position, so that a call can
+    /// reference the result of a previous one with `resultOf` set to
+    /// its position (see [`result_of`]). A method error fails the
+    /// whole batch.
+    pub fn call(&self, calls: Vec<(&str, Value)>) -> Result<Vec<Value>> {
+        let names: Vec<String> = calls.iter().map(|(name, _)| name.to_string()).collect();
+
+        let method_calls: Vec<Value> = calls
+            .into_iter()
+            .enumerate()
+            .map(|(n, (name, mut args))| {
+                args["accountId"] = json!(self.account_id);
+                json!([name, args, n.to_string()])
+            })
+            .collect();
+        let req = json!({ "using": USING, "methodCalls": method_calls });
+        trace!("jmap request: {req}");
+
+        let res: Response = self
+            .client
+            .post(&self.api_url)
+            .bearer_auth(&self.token)
+            .json(&req)
+            .send()
+            .and_then(|res| res.error_for_status())
+            .and_then(|res| res.json())
+            .map_err(|err| Error::SendRequestError(err, names.join(", ")))?;
+        trace!("jmap response: {res:?}");
+
+        let mut responses = Vec::with_capacity(names.len());
+        for (name, args, call_id) in res.method_responses {
+            let method = call_id
+                .parse::<usize>()
+                .ok()
+                .and_then(|n| names.get(n))
+                .cloned()
+                .unwrap_or(call_id);
+
+            if name == "error" {
+                let kind = args["type"].as_str().unwrap_or("unknown").to_owned();
+                let desc = args["description"].as_str().unwrap_or_default().to_owned();
+                return Err(Error::MethodError(method, kind, desc));
+            }
+
+            responses.push(args);
+        }
+
+        if responses.len() != names.len() {
+            return Err(Error::MissingResponseError(names.join(", ")));
+        }
+
+        Ok(responses)
+    }
+
+    /// Downloads the content of the given blob.
+    pub fn download(&self, blob_id: &str) -> Result<Vec<u8>> {
+        let url = download_url(&self.download_url, &self.account_id, blob_id);
+        debug!("downloading jmap blob {blob_id}");
+
+        let bytes = self
+            .client
+            .get(&url)
+            .bearer_auth(&self.token)
+            .send()
+            .and_then(|res| res.error_for_status())
+            .and_then(|res| res.bytes())
+            .map_err(|err| Error::DownloadBlobError(err, blob_id.to_owned()))?;
+
+        Ok(bytes.to_vec())
+    }
+
+    /// Uploads the given raw email, and returns the id of the
+    /// created blob.
+    pub fn upload(&self, email: &[u8]) -> Result<String> {
+        let url = self
+            .upload_url
+            .replace("{accountId}", &urlencoding::encode(&self.account_id));
+        debug!("uploading jmap blob of {} bytes", email.len());
+
+        let res: UploadResponse = self
+            .client
+            .post(&url)
+            .bearer_auth(&self.token)
+            .header(CONTENT_TYPE, "message/rfc822")
+            .body(email.to_vec())
+            .send()
+            .and_then(|res| res.error_for_status())
+            .and_then(|res| res.json())
+            .map_err(Error::UploadBlobError)?;
+
+        Ok(res.blob_id)
+    }
+}
+
+/// Builds a back-reference to the given path of the result of the
+/// call at the given position (see [`JmapSession::call`]).
+pub fn result_of(call: usize, name: &str, path: &str) -> Value {
+    json!({ "resultOf": call.to_string(), "name": name, "path": path })
+}
+
+/// Expands the download URL template of the session resource.
+fn download_url(template: &str, account_id: &str, blob_id: &str) -> String {
+    template
+        .replace("{accountId}", &urlencoding::encode(account_id))
+        .replace("{blobId}", &urlencoding::encode(blob_id))
+        .replace("{name}", "email.eml")
+        .replace("{type}", &urlencoding::encode("message/rfc822"))
+}
+
+#[cfg(test)]
+mod jmap_session {
+    #[test]
+    fn download_url() {
+        assert_eq!(
+            "https://example.com/download/u1/b%2F1/email.eml?accept=message%2Frfc822",
+            super::download_url(
+                "https://example.com/download/{accountId}/{blobId}/{name}?accept={type}",
+                "u1",
+                "b/1",
+            )
+        );
+    }
+}
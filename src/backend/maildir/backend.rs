@@ -3,37 +3,53 @@
 //! This module contains the definition of the maildir backend and its
 //! traits implementation.
 
-use log::{info, trace, warn};
+use log::{error, info, trace, warn};
 use maildir::Maildir;
+use rayon::prelude::*;
 use std::{
     any::Any,
     borrow::Cow,
     env,
     ffi::OsStr,
-    fs, io,
-    path::{self, PathBuf},
-    result,
+    fs,
+    io::{self, Write},
+    iter,
+    path::{self, Path, PathBuf},
+    process, result,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{SystemTime, UNIX_EPOCH},
 };
 use thiserror::Error;
 
 use crate::{
-    account, backend, email,
+    account, backend,
+    backend::maildir::{event, uid_map, MaildirEventStream, SearchQuery, UidMap},
+    email,
     envelope::maildir::{envelope, envelopes},
     flag::maildir::flags,
-    AccountConfig, Backend, Emails, Envelope, Envelopes, Flag, Flags, Folder, Folders, IdMapper,
-    MaildirConfig, DEFAULT_INBOX_FOLDER,
+    folder, trace, AccountConfig, Backend, Emails, Envelope, Envelopes, Flag, Flags, Folder,
+    FolderPermission, FolderSpecialUse, FolderStatus, Folders, IdMapper, MaildirConfig,
+    ValidationError, DEFAULT_INBOX_FOLDER,
 };
 
 #[derive(Debug, Error)]
 pub enum Error {
+    #[error("invalid maildir config: {}", ValidationError::join(.0))]
+    InvalidConfigError(Vec<ValidationError>),
     #[error("cannot open maildir database at {1}")]
     OpenDatabaseError(#[source] rusqlite::Error, PathBuf),
     #[error("cannot init maildir folders structure at {1}")]
     InitFoldersStructureError(#[source] io::Error, PathBuf),
     #[error("cannot delete folder at {1}")]
     DeleteFolderError(#[source] io::Error, PathBuf),
+    #[error("cannot rename folder at {1} to {2}")]
+    RenameFolderError(#[source] io::Error, PathBuf, PathBuf),
+    #[error("cannot rename inbox folder")]
+    RenameInboxFolderError,
     #[error(transparent)]
     IdMapperError(#[from] backend::id_mapper::Error),
+    #[error(transparent)]
+    UidMapError(#[from] uid_map::Error),
 
     #[error("cannot parse timestamp from maildir envelope: {1}")]
     ParseTimestampFromMaildirEnvelopeError(mailparse::MailParseError, String),
@@ -52,8 +68,16 @@ pub enum Error {
     ParseSubdirError(path::PathBuf),
     #[error("cannot get maildir envelopes at page {0}")]
     GetEnvelopesOutOfBoundsError(usize),
-    #[error("cannot search maildir envelopes: feature not implemented")]
-    SearchEnvelopesUnimplementedError,
+    #[error("cannot parse maildir search query {0}")]
+    ParseSearchQueryError(String),
+    #[error("cannot parse maildir search date {1}")]
+    ParseSearchDateError(#[source] chrono::ParseError, String),
+    #[error("cannot read maildir message at {1}")]
+    ReadEmailError(#[source] io::Error, PathBuf),
+    #[error("cannot parse maildir message headers at {1}")]
+    ParseHeadersError(#[source] mailparse::MailParseError, PathBuf),
+    #[error("cannot parse maildir message at {1}")]
+    ParseEmailError(#[source] mailparse::MailParseError, PathBuf),
     #[error("cannot get maildir message {0}")]
     GetMsgError(String),
     #[error("cannot decode maildir entry")]
@@ -68,10 +92,12 @@ pub enum Error {
     CreateSubdirError(#[source] io::Error, String),
     #[error("cannot decode maildir subdirectory")]
     GetSubdirEntryError(#[source] io::Error),
+    #[error("cannot get metadata of maildir directory {1}")]
+    GetDirMetadataError(#[source] io::Error, PathBuf),
     #[error("cannot get current directory")]
     GetCurrentDirError(#[source] io::Error),
-    #[error("cannot store maildir message with flags")]
-    StoreWithFlagsError(#[source] maildir::MaildirError),
+    #[error("cannot write maildir message atomically at {1}")]
+    AtomicWriteFailed(#[source] io::Error, PathBuf),
     #[error("cannot copy maildir message")]
     CopyEmailError(#[source] io::Error),
     #[error("cannot move maildir message")]
@@ -93,11 +119,78 @@ pub enum Error {
 
 pub type Result<T> = result::Result<T, Error>;
 
+/// Returns a new unique maildir file name, without info part.
+fn unique_internal_id() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    format!(
+        "{}.M{}P{}R{}.himalaya",
+        now.as_secs(),
+        now.subsec_micros(),
+        process::id(),
+        uuid::Uuid::new_v4().to_simple(),
+    )
+}
+
+/// Returns `true` if the given file and directory are on the same
+/// filesystem, which is needed to hard link the file into the
+/// directory.
+#[cfg(unix)]
+fn same_filesystem(path: &Path, dir: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    match (fs::metadata(path), fs::metadata(dir)) {
+        (Ok(file), Ok(dir)) => file.dev() == dir.dev(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn same_filesystem(_path: &Path, _dir: &Path) -> bool {
+    false
+}
+
+/// Returns `true` if the current user can add, rename and remove
+/// files in the given directory, according to its owner and mode as
+/// well as to the mount options.
+#[cfg(unix)]
+fn is_dir_writable(dir: &Path) -> Result<bool> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let path = CString::new(dir.as_os_str().as_bytes())
+        .map_err(|err| Error::GetDirMetadataError(err.into(), dir.to_owned()))?;
+
+    // SAFETY: the path is a valid nul-terminated string
+    if unsafe { libc::access(path.as_ptr(), libc::W_OK) } == 0 {
+        return Ok(true);
+    }
+
+    let err = io::Error::last_os_error();
+    match err.raw_os_error() {
+        Some(libc::EACCES) | Some(libc::EROFS) => Ok(false),
+        _ => Err(Error::GetDirMetadataError(err, dir.to_owned())),
+    }
+}
+
+#[cfg(not(unix))]
+fn is_dir_writable(dir: &Path) -> Result<bool> {
+    let metadata =
+        fs::metadata(dir).map_err(|err| Error::GetDirMetadataError(err, dir.to_owned()))?;
+    Ok(!metadata.permissions().readonly())
+}
+
 /// Represents the maildir backend.
+///
+/// Internal ids of emails are UIDs, assigned by the [`UidMap`] stored
+/// in the id mapper database, so that they survive flag changes and
+/// moves between folders. The map can be shared by several backends
+/// managing the same Maildir, even from different processes.
 pub struct MaildirBackend<'a> {
     account_config: Cow<'a, AccountConfig>,
     mdir: maildir::Maildir,
     db_path: PathBuf,
+    uid_map: Arc<Mutex<UidMap>>,
 }
 
 const ID_MAPPER_DB_FILE_NAME: &str = ".id-mapper.sqlite";
@@ -107,6 +200,10 @@ impl<'a> MaildirBackend<'a> {
         account_config: Cow<'a, AccountConfig>,
         backend_config: Cow<'a, MaildirConfig>,
     ) -> Result<Self> {
+        backend_config
+            .validate()
+            .map_err(Error::InvalidConfigError)?;
+
         let path = &backend_config.root_dir;
         let mdir = Maildir::from(path.clone());
 
@@ -129,18 +226,162 @@ impl<'a> MaildirBackend<'a> {
         mdir.create_dirs()
             .map_err(|err| Error::InitFoldersStructureError(err, path.clone()))?;
 
+        let uid_map = UidMap::open(&db_path, mdir.path())?;
+        let uid_map_created = uid_map.is_created();
+
         let maildir_backend = Self {
             account_config,
             mdir,
             db_path,
+            uid_map: Arc::new(Mutex::new(uid_map)),
         };
 
         // spawns a fake id mapper to init the database
         maildir_backend.id_mapper(DEFAULT_INBOX_FOLDER)?;
 
+        // emails present before the map existed get their UIDs in
+        // delivery order, the others get them when first listed
+        if uid_map_created {
+            maildir_backend.reconcile_uids()?;
+        }
+
         Ok(maildir_backend)
     }
 
+    /// Returns the UIDVALIDITY of the Maildir, which changes when the
+    /// UIDs are reset (see [`UidMap`]).
+    pub fn uid_validity(&self) -> u64 {
+        self.lock_uid_map().uid_validity()
+    }
+
+    fn lock_uid_map(&self) -> MutexGuard<'_, UidMap> {
+        self.uid_map.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Reconciles the UID map with the emails found in all the
+    /// folders, so that emails removed by other programs lose their
+    /// UID. Emails added by other programs get their UID when they
+    /// are listed, so this is only needed to clean the map up.
+    pub fn reconcile_uids(&self) -> Result<()> {
+        let mut mdirs = vec![Maildir::from(self.mdir.path().to_owned())];
+        for entry in self.mdir.list_subdirs() {
+            mdirs.push(entry.map_err(Error::GetSubdirEntryError)?);
+        }
+
+        let mut keys = Vec::new();
+        for mdir in &mdirs {
+            for entry in mdir.list_new().chain(mdir.list_cur()) {
+                match entry {
+                    Ok(entry) => keys.push(self.uid_key(mdir, entry.id())),
+                    Err(err) => warn!("skipping invalid maildir entry: {err}"),
+                }
+            }
+        }
+
+        self.lock_uid_map().reconcile(keys)?;
+        Ok(())
+    }
+
+    /// Returns the path of the given maildir relative to the root.
+    fn uid_folder<'b>(&self, mdir: &'b Maildir) -> &'b Path {
+        mdir.path()
+            .strip_prefix(self.mdir.path())
+            .unwrap_or(mdir.path())
+    }
+
+    /// Returns the key of the given email in the UID map.
+    fn uid_key(&self, mdir: &Maildir, maildir_id: &str) -> PathBuf {
+        self.uid_folder(mdir).join(maildir_id)
+    }
+
+    /// Returns the UIDs of the given emails of the given maildir,
+    /// assigning new UIDs to the unknown ones.
+    fn uids(&self, mdir: &Maildir, maildir_ids: &[&str]) -> Result<Vec<String>> {
+        let keys = maildir_ids
+            .iter()
+            .map(|maildir_id| self.uid_key(mdir, maildir_id))
+            .collect();
+        let uids = self.lock_uid_map().get_or_insert(keys)?;
+        Ok(uids.into_iter().map(|uid| uid.to_string()).collect())
+    }
+
+    /// Returns the UID of the given email of the given maildir.
+    pub(crate) fn uid(&self, mdir: &Maildir, maildir_id: &str) -> Result<String> {
+        Ok(self.uids(mdir, &[maildir_id])?.remove(0))
+    }
+
+    /// Replaces the Maildir unique ids of the given envelopes by
+    /// their UIDs.
+    fn with_uids(&self, mdir: &Maildir, mut envelopes: Envelopes) -> Result<Envelopes> {
+        let maildir_ids: Vec<&str> = envelopes
+            .iter()
+            .map(|envelope| envelope.internal_id.as_str())
+            .collect();
+        let uids = self.uids(mdir, &maildir_ids)?;
+
+        for (envelope, uid) in envelopes.iter_mut().zip(uids) {
+            envelope.internal_id = uid;
+        }
+
+        Ok(envelopes)
+    }
+
+    /// Returns the Maildir unique ids of the given internal ids of the
+    /// given maildir. Internal ids which are not UIDs of this maildir
+    /// are kept as they are, so that Maildir unique ids, used as
+    /// internal ids before UIDs, are still accepted.
+    fn maildir_ids(&self, mdir: &Maildir, internal_ids: &[&str]) -> Result<Vec<String>> {
+        let folder = self.uid_folder(mdir);
+        let uid_map = self.lock_uid_map();
+
+        let mut maildir_ids = Vec::with_capacity(internal_ids.len());
+        for internal_id in internal_ids {
+            let key = match internal_id.parse() {
+                Ok(uid) => uid_map.key(uid)?,
+                Err(_) => None,
+            };
+            let maildir_id = match key {
+                Some(key) if key.parent() == Some(folder) => key
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_else(|| internal_id.to_string()),
+                _ => internal_id.to_string(),
+            };
+            maildir_ids.push(maildir_id);
+        }
+
+        Ok(maildir_ids)
+    }
+
+    /// Moves the UIDs of the given emails from a maildir to another,
+    /// so that moved emails keep their UID.
+    fn move_uids(
+        &self,
+        from_mdir: &Maildir,
+        to_mdir: &Maildir,
+        maildir_ids: &[&str],
+    ) -> Result<()> {
+        let mut uid_map = self.lock_uid_map();
+        let mut moved = Vec::with_capacity(maildir_ids.len());
+        for maildir_id in maildir_ids {
+            if let Some(uid) = uid_map.uid(&self.uid_key(from_mdir, maildir_id))? {
+                moved.push((uid, self.uid_key(to_mdir, maildir_id)));
+            }
+        }
+        uid_map.update(moved)?;
+        Ok(())
+    }
+
+    /// Removes the UIDs of the given emails of the given maildir.
+    fn remove_uids(&self, mdir: &Maildir, maildir_ids: &[&str]) -> Result<()> {
+        let keys: Vec<PathBuf> = maildir_ids
+            .iter()
+            .map(|maildir_id| self.uid_key(mdir, maildir_id))
+            .collect();
+        self.lock_uid_map().remove(&keys)?;
+        Ok(())
+    }
+
     fn validate_mdir_path(&self, mdir_path: PathBuf) -> Result<PathBuf> {
         if mdir_path.is_dir() {
             Ok(mdir_path)
@@ -186,6 +427,97 @@ impl<'a> MaildirBackend<'a> {
             .map(Maildir::from)
     }
 
+    /// Sorts the given envelopes by date, keeps the given page and
+    /// maps their internal ids to ids.
+    fn paginate_envelopes(
+        &self,
+        folder: &str,
+        mut envelopes: Envelopes,
+        page_size: usize,
+        page: usize,
+    ) -> Result<Envelopes> {
+        let id_mapper = self.id_mapper(folder)?;
+        let page_begin = page * page_size;
+        trace!("page begin: {}", page_begin);
+        if page_begin > envelopes.len() {
+            return Err(Error::GetEnvelopesOutOfBoundsError(page_begin + 1))?;
+        }
+
+        let page_end = envelopes.len().min(if page_size == 0 {
+            envelopes.len()
+        } else {
+            page_begin + page_size
+        });
+        trace!("page end: {}", page_end);
+
+        envelopes.sort_by(|a, b| b.date.partial_cmp(&a.date).unwrap());
+        *envelopes = envelopes[page_begin..page_end]
+            .iter()
+            .map(|envelope| {
+                Ok(Envelope {
+                    id: id_mapper.get_id(&envelope.internal_id)?,
+                    ..envelope.clone()
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(envelopes)
+    }
+
+    /// Applies the given flags update to the given emails, all or
+    /// nothing.
+    ///
+    /// Maildir flags are part of the file names, so updating them
+    /// renames the files one by one. If one update fails, the emails
+    /// already updated get their previous flags back and the error is
+    /// returned. If the rollback fails too, the emails left updated
+    /// are reported by [`backend::Error::PartialFlagsUpdateError`].
+    fn update_flags<F>(mdir: &Maildir, internal_ids: &[&str], update: F) -> backend::Result<()>
+    where
+        F: Fn(&str) -> Result<()>,
+    {
+        let mut updated: Vec<(&str, String)> = Vec::with_capacity(internal_ids.len());
+
+        for (n, internal_id) in internal_ids.iter().copied().enumerate() {
+            let prev_flags = mdir.find(internal_id).map(|entry| entry.flags().to_owned());
+
+            let err = match update(internal_id) {
+                Ok(()) => {
+                    updated.extend(prev_flags.map(|flags| (internal_id, flags)));
+                    continue;
+                }
+                Err(err) => err,
+            };
+
+            warn!("cannot update flags of maildir email {internal_id}, rolling back: {err}");
+
+            let mut failed: Vec<String> =
+                internal_ids[n..].iter().map(|id| id.to_string()).collect();
+            let mut left_updated = Vec::new();
+
+            for (internal_id, flags) in updated.into_iter().rev() {
+                match mdir.set_flags(internal_id, &flags) {
+                    Ok(()) => failed.push(internal_id.to_owned()),
+                    Err(err) => {
+                        error!("cannot restore flags of maildir email {internal_id}: {err}");
+                        left_updated.push(internal_id.to_owned());
+                    }
+                }
+            }
+
+            return if left_updated.is_empty() {
+                Err(err.into())
+            } else {
+                Err(backend::Error::PartialFlagsUpdateError {
+                    failed,
+                    updated: left_updated,
+                })
+            };
+        }
+
+        Ok(())
+    }
+
     pub fn get_email_path<F, I>(&self, folder: F, id: I) -> Result<PathBuf>
     where
         F: AsRef<str> + ToString,
@@ -195,18 +527,141 @@ impl<'a> MaildirBackend<'a> {
         self.get_email_path_internal(internal_id)
     }
 
+    /// Returns the path of the email matching the given internal id,
+    /// whatever its folder. Internal ids which are not UIDs are
+    /// searched in the inbox.
     pub fn get_email_path_internal<I>(&self, internal_id: I) -> Result<PathBuf>
     where
         I: AsRef<str> + ToString,
     {
-        Ok(self
-            .mdir
-            .find(internal_id.as_ref())
+        let key = match internal_id.as_ref().parse() {
+            Ok(uid) => self.lock_uid_map().key(uid)?,
+            Err(_) => None,
+        };
+
+        let (mdir, maildir_id) = match key {
+            Some(key) => (
+                Maildir::from(
+                    self.mdir
+                        .path()
+                        .join(key.parent().unwrap_or_else(|| Path::new(""))),
+                ),
+                key.file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+            ),
+            None => (
+                Maildir::from(self.mdir.path().to_owned()),
+                internal_id.to_string(),
+            ),
+        };
+
+        Ok(mdir
+            .find(&maildir_id)
             .ok_or_else(|| Error::GetEnvelopeError(internal_id.to_string()))?
             .path()
             .to_owned())
     }
 
+    /// Writes an email to the given destination path of the given
+    /// maildir, following the [delivery] procedure of the spec: the
+    /// email is first fully written by the given function to a file
+    /// named after the given internal id inside `tmp`, then renamed
+    /// to its destination. This way other readers (or watchers)
+    /// never see a partially written email.
+    ///
+    /// If the rename fails, for example because `tmp` is not on the
+    /// same filesystem as the destination, it falls back to a copy
+    /// followed by a removal of the temporary file.
+    ///
+    /// [delivery]: https://cr.yp.to/proto/maildir.html
+    fn deliver<W>(mdir: &Maildir, internal_id: &str, dst_path: &Path, write: W) -> Result<()>
+    where
+        W: FnOnce(&mut fs::File) -> io::Result<()>,
+    {
+        let tmp_path = mdir.path().join("tmp").join(internal_id);
+        fs::File::create(&tmp_path)
+            .and_then(|mut file| {
+                write(&mut file)?;
+                file.sync_all()
+            })
+            .map_err(|err| {
+                let _ = fs::remove_file(&tmp_path);
+                Error::AtomicWriteFailed(err, tmp_path.clone())
+            })?;
+
+        if let Err(err) = fs::rename(&tmp_path, dst_path) {
+            error!("cannot rename {tmp_path:?} to {dst_path:?}, falling back to copy: {err}");
+            fs::copy(&tmp_path, dst_path)
+                .and_then(|_| fs::remove_file(&tmp_path))
+                .map_err(|err| Error::AtomicWriteFailed(err, dst_path.to_owned()))?;
+        }
+
+        Ok(())
+    }
+
+    /// Stores the given email in the `cur` directory of the given
+    /// maildir with the given flags (see [`Self::deliver`]).
+    fn store_cur_with_flags(&self, mdir: &Maildir, email: &[u8], flags: &Flags) -> Result<String> {
+        let internal_id = unique_internal_id();
+
+        let mut flags: Vec<char> = flags::to_normalized_string(flags).chars().collect();
+        flags.sort_unstable();
+        let flags = String::from_iter(flags);
+        let cur_path = mdir
+            .path()
+            .join("cur")
+            .join(format!("{internal_id}:2,{flags}"));
+
+        Self::deliver(mdir, &internal_id, &cur_path, |file| file.write_all(email))?;
+
+        Ok(internal_id)
+    }
+
+    /// Copies the given email to the given maildir, in the same
+    /// subdirectory (`new` or `cur`) and with the same flags, under a
+    /// fresh unique file name. Returns the internal id of the copy.
+    ///
+    /// When both maildirs are on the same filesystem, the copy is a
+    /// hard link to the original, which avoids reading and writing
+    /// the email. Otherwise, or if the link fails, the email is
+    /// copied through `tmp` (see [`Self::deliver`]).
+    fn copy_email(from_mdir: &Maildir, to_mdir: &Maildir, internal_id: &str) -> Result<String> {
+        let entry = from_mdir
+            .find(internal_id)
+            .ok_or_else(|| Error::GetEnvelopeError(internal_id.to_owned()))?;
+        let src_path = entry.path();
+        let subdir = src_path
+            .parent()
+            .and_then(Path::file_name)
+            .unwrap_or_else(|| OsStr::new("cur"));
+        let file_name = src_path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        // keeps the info part of the file name, holding the flags
+        let info = file_name.strip_prefix(internal_id).unwrap_or_default();
+
+        let copy_id = unique_internal_id();
+        let dst_path = to_mdir.path().join(subdir).join(format!("{copy_id}{info}"));
+
+        if same_filesystem(src_path, to_mdir.path()) {
+            match fs::hard_link(src_path, &dst_path) {
+                Ok(()) => return Ok(copy_id),
+                Err(err) => {
+                    warn!("cannot hard link {src_path:?} to {dst_path:?}, falling back to copy: {err}")
+                }
+            }
+        }
+
+        Self::deliver(to_mdir, &copy_id, &dst_path, |file| {
+            io::copy(&mut fs::File::open(src_path)?, file)?;
+            Ok(())
+        })?;
+
+        Ok(copy_id)
+    }
+
     pub fn encode_folder<F>(&self, folder: F) -> String
     where
         F: AsRef<str> + ToString,
@@ -242,6 +697,8 @@ impl<'a> Backend for MaildirBackend<'a> {
     }
 
     fn add_folder(&self, folder: &str) -> backend::Result<()> {
+        let _span =
+            trace::span!("maildir_backend", method = "add_folder", folder = %folder).entered();
         info!("adding maildir folder {}", folder);
 
         let path = match self.account_config.folder_alias(folder)?.as_str() {
@@ -262,6 +719,7 @@ impl<'a> Backend for MaildirBackend<'a> {
     }
 
     fn list_folders(&self) -> backend::Result<Folders> {
+        let _span = trace::span!("maildir_backend", method = "list_folders").entered();
         info!("listing maildir folders");
 
         let mut folders = Folders::default();
@@ -269,7 +727,9 @@ impl<'a> Backend for MaildirBackend<'a> {
         folders.push(Folder {
             delim: String::from("/"),
             name: self.account_config.inbox_folder_alias()?,
+            raw_name: DEFAULT_INBOX_FOLDER.into(),
             desc: DEFAULT_INBOX_FOLDER.into(),
+            special_use: Some(FolderSpecialUse::Inbox),
         });
 
         for entry in self.mdir.list_subdirs() {
@@ -280,11 +740,22 @@ impl<'a> Backend for MaildirBackend<'a> {
                 .and_then(|s| if s.len() < 2 { None } else { Some(&s[1..]) })
                 .ok_or_else(|| Error::ParseSubdirError(dir.path().to_owned()))?
                 .to_string();
+            let decoded_name = self.decode_folder(&name);
+
+            // the inbox lives at the root of the Maildir, so a
+            // subdirectory named after it with another casing cannot
+            // be reached (see [`folder::canonical_folder_name`])
+            if folder::canonical_folder_name(&decoded_name) == DEFAULT_INBOX_FOLDER {
+                warn!("skipping maildir folder {decoded_name} shadowed by the inbox");
+                continue;
+            }
 
             folders.push(Folder {
                 delim: String::from("/"),
-                name: self.decode_folder(&name),
+                name: decoded_name,
+                raw_name: name.clone(),
                 desc: name,
+                special_use: None,
             });
         }
 
@@ -294,6 +765,8 @@ impl<'a> Backend for MaildirBackend<'a> {
     }
 
     fn purge_folder(&self, folder: &str) -> backend::Result<()> {
+        let _span =
+            trace::span!("maildir_backend", method = "purge_folder", folder = %folder).entered();
         info!("purging maildir folder {}", folder);
 
         let mdir = self.get_mdir_from_dir(folder)?;
@@ -311,6 +784,8 @@ impl<'a> Backend for MaildirBackend<'a> {
     }
 
     fn delete_folder(&self, folder: &str) -> backend::Result<()> {
+        let _span =
+            trace::span!("maildir_backend", method = "delete_folder", folder = %folder).entered();
         info!("deleting maildir folder {}", folder);
 
         let path = match self.account_config.folder_alias(folder)?.as_str() {
@@ -323,12 +798,56 @@ impl<'a> Backend for MaildirBackend<'a> {
 
         trace!("maildir folder path: {:?}", path);
 
-        fs::remove_dir_all(&path).map_err(|err| Error::DeleteFolderError(err, path))?;
+        fs::remove_dir_all(&path).map_err(|err| Error::DeleteFolderError(err, path.clone()))?;
+
+        let folder = self.uid_folder(&Maildir::from(path)).to_owned();
+        self.lock_uid_map()
+            .remove_folder(&folder)
+            .map_err(Error::from)?;
+
+        Ok(())
+    }
+
+    fn rename_folder(&self, from_folder: &str, to_folder: &str) -> backend::Result<()> {
+        let _span = trace::span!(
+            "maildir_backend",
+            method = "rename_folder",
+            from_folder = %from_folder,
+            to_folder = %to_folder
+        )
+        .entered();
+        info!("renaming maildir folder {} to {}", from_folder, to_folder);
+
+        let path = |folder: &str| -> backend::Result<PathBuf> {
+            match self.account_config.folder_alias(folder)?.as_str() {
+                DEFAULT_INBOX_FOLDER => Err(Error::RenameInboxFolderError)?,
+                folder => {
+                    let folder = self.encode_folder(folder);
+                    Ok(self.mdir.path().join(format!(".{}", folder)))
+                }
+            }
+        };
+        let from_path = path(from_folder)?;
+        let to_path = path(to_folder)?;
+
+        trace!("maildir folder paths: {:?} → {:?}", from_path, to_path);
+
+        fs::rename(&from_path, &to_path)
+            .map_err(|err| Error::RenameFolderError(err, from_path.clone(), to_path.clone()))?;
+
+        // emails of the renamed folder keep their UID
+        let from_folder = self.uid_folder(&Maildir::from(from_path)).to_owned();
+        let to_folder = self.uid_folder(&Maildir::from(to_path)).to_owned();
+        self.lock_uid_map()
+            .rename_folder(&from_folder, &to_folder)
+            .map_err(Error::from)?;
 
         Ok(())
     }
 
     fn get_envelope(&self, folder: &str, id: &str) -> backend::Result<Envelope> {
+        let _span =
+            trace::span!("maildir_backend", method = "get_envelope", folder = %folder).entered();
         info!(
             "getting maildir envelope by id {} from folder {}",
             id, folder
@@ -336,93 +855,231 @@ impl<'a> Backend for MaildirBackend<'a> {
 
         let mdir = self.get_mdir_from_dir(folder)?;
         let internal_id = self.id_mapper(folder)?.get_internal_id(id)?;
+        let maildir_id = self.maildir_ids(&mdir, &[internal_id.as_str()])?.remove(0);
         let mut envelope = envelope::from_raw(
-            mdir.find(&internal_id)
+            mdir.find(&maildir_id)
                 .ok_or_else(|| Error::GetEnvelopeError(id.to_owned()))?,
         )?;
+        envelope.internal_id = self.uid(&mdir, &maildir_id)?;
         envelope.id = id.to_string();
 
         Ok(envelope)
     }
 
     fn get_envelope_internal(&self, folder: &str, internal_id: &str) -> backend::Result<Envelope> {
+        let _span =
+            trace::span!("maildir_backend", method = "get_envelope_internal", folder = %folder)
+                .entered();
         info!(
             "getting maildir envelope by internal id {} from folder {}",
             internal_id, folder
         );
 
         let mdir = self.get_mdir_from_dir(folder)?;
+        let maildir_id = self.maildir_ids(&mdir, &[internal_id])?.remove(0);
         let mut envelope = envelope::from_raw(
-            mdir.find(internal_id)
+            mdir.find(&maildir_id)
                 .ok_or_else(|| Error::GetEnvelopeError(internal_id.to_owned()))?,
         )?;
-        envelope.id = self.id_mapper(folder)?.get_id(internal_id)?;
+        envelope.internal_id = self.uid(&mdir, &maildir_id)?;
+        envelope.id = self.id_mapper(folder)?.get_id(&envelope.internal_id)?;
 
         Ok(envelope)
     }
 
+    /// Gets the status of the given folder from the file names of its
+    /// emails only: emails in `new/` are recent, and emails in `cur/`
+    /// are unseen unless their file name holds the `S` flag.
+    fn folder_status(&self, folder: &str) -> backend::Result<FolderStatus> {
+        let _span =
+            trace::span!("maildir_backend", method = "folder_status", folder = %folder).entered();
+        info!("getting status of maildir folder {}", folder);
+
+        let mdir = self.get_mdir_from_dir(folder)?;
+        let mut status = FolderStatus {
+            size_bytes: Some(0),
+            ..FolderStatus::default()
+        };
+
+        let entries = mdir
+            .list_new()
+            .map(|entry| (true, entry))
+            .chain(mdir.list_cur().map(|entry| (false, entry)));
+
+        for (new, entry) in entries {
+            let entry = entry.map_err(Error::GetSubdirEntryError)?;
+
+            status.total += 1;
+            if new {
+                status.recent += 1;
+                status.unseen += 1;
+            } else if !entry.is_seen() {
+                status.unseen += 1;
+            }
+
+            status.size_bytes = match (status.size_bytes, fs::metadata(entry.path())) {
+                (Some(size), Ok(metadata)) => Some(size + metadata.len()),
+                _ => None,
+            };
+        }
+
+        Ok(status)
+    }
+
+    /// Considers the given folder read-only when its `cur/`
+    /// directory, where emails are stored and renamed to change their
+    /// flags, is not writable by the current user.
+    fn folder_permission(&self, folder: &str) -> backend::Result<FolderPermission> {
+        let _span = trace::span!("maildir_backend", method = "folder_permission", folder = %folder)
+            .entered();
+        info!("getting permission of maildir folder {}", folder);
+
+        let cur = self.get_mdir_from_dir(folder)?.path().join("cur");
+
+        Ok(if is_dir_writable(&cur)? {
+            FolderPermission::ReadWrite
+        } else {
+            FolderPermission::ReadOnly
+        })
+    }
+
     fn list_envelopes(
         &self,
         folder: &str,
         page_size: usize,
         page: usize,
     ) -> backend::Result<Envelopes> {
+        let _span =
+            trace::span!("maildir_backend", method = "list_envelopes", folder = %folder).entered();
         info!("listing maildir envelopes of folder {}", folder);
         trace!("page size: {}", page_size);
         trace!("page: {}", page);
 
         let mdir = self.get_mdir_from_dir(folder)?;
-        let id_mapper = self.id_mapper(folder)?;
-        let mut envelopes = envelopes::from_raws(mdir.list_cur())?;
+        let envelopes = self.with_uids(&mdir, envelopes::from_raws(mdir.list_cur())?)?;
+        let envelopes = self.paginate_envelopes(folder, envelopes, page_size, page)?;
 
-        let page_begin = page * page_size;
-        trace!("page begin: {}", page_begin);
-        if page_begin > envelopes.len() {
-            return Err(Error::GetEnvelopesOutOfBoundsError(page_begin + 1))?;
-        }
+        Ok(envelopes)
+    }
 
-        let page_end = envelopes.len().min(if page_size == 0 {
-            envelopes.len()
+    fn envelope_pages(
+        &self,
+        folder: &str,
+        page_size: usize,
+    ) -> backend::Result<Box<dyn Iterator<Item = backend::Result<Envelopes>> + Send + '_>> {
+        let _span =
+            trace::span!("maildir_backend", method = "envelope_pages", folder = %folder).entered();
+        info!("listing maildir envelopes pages of folder {folder}");
+        trace!("page size: {}", page_size);
+
+        let mdir = self.get_mdir_from_dir(folder)?;
+        let mut entries = mdir.list_cur();
+        let id_mapper = self.id_mapper(folder)?;
+        let page_size = if page_size == 0 {
+            usize::MAX
         } else {
-            page_begin + page_size
+            page_size
+        };
+
+        // entries are read from the directory one page at a time, so
+        // pages follow the directory order and are sorted by date
+        // individually
+        let pages = iter::from_fn(move || {
+            let entries: Vec<_> = entries.by_ref().take(page_size).collect();
+            if entries.is_empty() {
+                return None;
+            }
+
+            let envelopes = envelopes::from_entries(entries).and_then(|envelopes| {
+                let mut envelopes = self.with_uids(&mdir, envelopes)?;
+                for envelope in envelopes.iter_mut() {
+                    envelope.id = id_mapper.get_id(&envelope.internal_id)?;
+                }
+                envelopes.sort_by(|a, b| b.date.partial_cmp(&a.date).unwrap());
+                Ok(envelopes)
+            });
+
+            Some(envelopes.map_err(backend::Error::from))
         });
-        trace!("page end: {}", page_end);
 
-        envelopes.sort_by(|a, b| b.date.partial_cmp(&a.date).unwrap());
-        *envelopes = envelopes[page_begin..page_end]
-            .iter()
-            .map(|envelope| {
-                Ok(Envelope {
-                    id: id_mapper.get_id(&envelope.internal_id)?,
-                    ..envelope.clone()
-                })
-            })
-            .collect::<Result<Vec<_>>>()?;
+        Ok(Box::new(pages))
+    }
 
-        Ok(envelopes)
+    /// Scans the envelopes of the folders, built from the headers of
+    /// their emails, instead of searching the ids first.
+    fn find_by_message_id(
+        &self,
+        folder: Option<&str>,
+        message_id: &str,
+    ) -> backend::Result<Option<(String, Envelope)>> {
+        let _span = trace::span!("maildir_backend", method = "find_by_message_id").entered();
+        info!("finding maildir email matching Message-ID {message_id}");
+
+        let folders = match folder {
+            Some(folder) => vec![folder.to_owned()],
+            None => self
+                .list_folders()?
+                .iter()
+                .map(|folder| folder.name.clone())
+                .collect(),
+        };
+
+        for folder in folders {
+            let envelope = self
+                .list_envelopes(&folder, 0, 0)?
+                .into_iter()
+                .find(|envelope| envelope.has_message_id(message_id));
+
+            if let Some(envelope) = envelope {
+                return Ok(Some((folder, envelope)));
+            }
+        }
+
+        Ok(None)
     }
 
     fn search_envelopes(
         &self,
-        _folder: &str,
-        _query: &str,
+        folder: &str,
+        query: &str,
         _sort: &str,
-        _page_size: usize,
-        _page: usize,
+        page_size: usize,
+        page: usize,
     ) -> backend::Result<Envelopes> {
-        Err(Error::SearchEnvelopesUnimplementedError)?
+        let _span = trace::span!("maildir_backend", method = "search_envelopes", folder = %folder)
+            .entered();
+        info!("searching maildir envelopes of folder {folder} matching {query}");
+        trace!("page size: {}", page_size);
+        trace!("page: {}", page);
+
+        let query = SearchQuery::parse(query)?;
+        trace!("parsed query: {query:?}");
+
+        let mdir = self.get_mdir_from_dir(folder)?;
+        let envelopes = mdir
+            .list_cur()
+            .collect::<Vec<_>>()
+            .into_par_iter()
+            .map(|entry| query.filter_entry(&entry.map_err(Error::DecodeEntryError)?))
+            .collect::<Result<Vec<_>>>()?;
+        let envelopes = Envelopes::from_iter(envelopes.into_iter().flatten());
+        let envelopes = self.with_uids(&mdir, envelopes)?;
+        let envelopes = self.paginate_envelopes(folder, envelopes, page_size, page)?;
+
+        Ok(envelopes)
     }
 
     fn add_email(&self, folder: &str, email: &[u8], flags: &Flags) -> backend::Result<String> {
+        let _span =
+            trace::span!("maildir_backend", method = "add_email", folder = %folder).entered();
         info!(
             "adding email to folder {folder} with flags {flags}",
             flags = flags.to_string()
         );
 
         let mdir = self.get_mdir_from_dir(folder)?;
-        let internal_id = mdir
-            .store_cur_with_flags(email, &flags::to_normalized_string(&flags))
-            .map_err(Error::StoreWithFlagsError)?;
+        let maildir_id = self.store_cur_with_flags(&mdir, email, flags)?;
+        let internal_id = self.uid(&mdir, &maildir_id)?;
         let id = self.id_mapper(folder)?.insert(internal_id)?;
 
         Ok(id)
@@ -434,21 +1091,25 @@ impl<'a> Backend for MaildirBackend<'a> {
         email: &[u8],
         flags: &Flags,
     ) -> backend::Result<String> {
+        let _span =
+            trace::span!("maildir_backend", method = "add_email_internal", folder = %folder)
+                .entered();
         info!(
             "adding email to folder {folder} with flags {flags}",
             flags = flags.to_string()
         );
 
         let mdir = self.get_mdir_from_dir(folder)?;
-        let internal_id = mdir
-            .store_cur_with_flags(email, &flags::to_normalized_string(&flags))
-            .map_err(Error::StoreWithFlagsError)?;
+        let maildir_id = self.store_cur_with_flags(&mdir, email, flags)?;
+        let internal_id = self.uid(&mdir, &maildir_id)?;
         self.id_mapper(folder)?.insert(&internal_id)?;
 
         Ok(internal_id)
     }
 
     fn preview_emails(&self, folder: &str, ids: Vec<&str>) -> backend::Result<Emails> {
+        let _span =
+            trace::span!("maildir_backend", method = "preview_emails", folder = %folder).entered();
         info!(
             "previewing maildir emails by ids {ids} from folder {folder}",
             ids = ids.join(", "),
@@ -462,11 +1123,13 @@ impl<'a> Backend for MaildirBackend<'a> {
             .collect::<Result<_>>()?;
         let internal_ids: Vec<&str> = internal_ids.iter().map(String::as_str).collect();
         trace!("internal ids: {:#?}", internal_ids);
+        let maildir_ids = self.maildir_ids(&mdir, &internal_ids)?;
+        let maildir_ids: Vec<&str> = maildir_ids.iter().map(String::as_str).collect();
 
         let mut emails: Vec<(usize, maildir::MailEntry)> = mdir
             .list_cur()
             .filter_map(|entry| match entry {
-                Ok(entry) => internal_ids
+                Ok(entry) => maildir_ids
                     .iter()
                     .position(|id| *id == entry.id())
                     .map(|pos| (pos, entry)),
@@ -492,17 +1155,21 @@ impl<'a> Backend for MaildirBackend<'a> {
         folder: &str,
         internal_ids: Vec<&str>,
     ) -> backend::Result<Emails> {
+        let _span =
+            trace::span!("maildir_backend", method = "preview_emails_internal", folder = %folder)
+                .entered();
         info!(
             "previewing maildir emails by internal ids {ids} from folder {folder}",
             ids = internal_ids.join(", "),
         );
 
         let mdir = self.get_mdir_from_dir(folder)?;
+        let maildir_ids = self.maildir_ids(&mdir, &internal_ids)?;
 
         let mut emails: Vec<(usize, maildir::MailEntry)> = mdir
             .list_cur()
             .filter_map(|entry| match entry {
-                Ok(entry) => internal_ids
+                Ok(entry) => maildir_ids
                     .iter()
                     .position(|id| *id == entry.id())
                     .map(|pos| (pos, entry)),
@@ -524,6 +1191,8 @@ impl<'a> Backend for MaildirBackend<'a> {
     }
 
     fn get_emails(&self, folder: &str, ids: Vec<&str>) -> backend::Result<Emails> {
+        let _span =
+            trace::span!("maildir_backend", method = "get_emails", folder = %folder).entered();
         info!(
             "getting maildir emails by ids {ids} from folder {folder}",
             ids = ids.join(", "),
@@ -540,6 +1209,9 @@ impl<'a> Backend for MaildirBackend<'a> {
         folder: &str,
         internal_ids: Vec<&str>,
     ) -> backend::Result<Emails> {
+        let _span =
+            trace::span!("maildir_backend", method = "get_emails_internal", folder = %folder)
+                .entered();
         info!(
             "getting maildir emails by internal ids {ids} from folder {folder}",
             ids = internal_ids.join(", "),
@@ -557,6 +1229,13 @@ impl<'a> Backend for MaildirBackend<'a> {
         to_folder: &str,
         ids: Vec<&str>,
     ) -> backend::Result<()> {
+        let _span = trace::span!(
+            "maildir_backend",
+            method = "copy_emails",
+            from_folder = %from_folder,
+            to_folder = %to_folder
+        )
+        .entered();
         info!(
             "copying ids {ids} from folder {from_folder} to folder {to_folder}",
             ids = ids.join(", "),
@@ -571,12 +1250,15 @@ impl<'a> Backend for MaildirBackend<'a> {
             .collect::<Result<_>>()?;
         let internal_ids: Vec<&str> = internal_ids.iter().map(String::as_str).collect();
         trace!("internal ids: {:#?}", internal_ids);
+        let maildir_ids = self.maildir_ids(&from_mdir, &internal_ids)?;
+        let maildir_ids: Vec<&str> = maildir_ids.iter().map(String::as_str).collect();
 
-        internal_ids.iter().try_for_each(|internal_id| {
-            from_mdir
-                .copy_to(&internal_id, &to_mdir)
-                .map_err(Error::CopyEmailError)
-        })?;
+        let mut copy_ids = Vec::with_capacity(maildir_ids.len());
+        for maildir_id in maildir_ids {
+            copy_ids.push(Self::copy_email(&from_mdir, &to_mdir, maildir_id)?);
+        }
+        let copy_ids: Vec<&str> = copy_ids.iter().map(String::as_str).collect();
+        self.uids(&to_mdir, &copy_ids)?;
 
         Ok(())
     }
@@ -587,6 +1269,13 @@ impl<'a> Backend for MaildirBackend<'a> {
         to_folder: &str,
         internal_ids: Vec<&str>,
     ) -> backend::Result<()> {
+        let _span = trace::span!(
+            "maildir_backend",
+            method = "copy_emails_internal",
+            from_folder = %from_folder,
+            to_folder = %to_folder
+        )
+        .entered();
         info!(
             "copying internal ids {ids} from folder {from_folder} to folder {to_folder}",
             ids = internal_ids.join(", "),
@@ -594,12 +1283,14 @@ impl<'a> Backend for MaildirBackend<'a> {
 
         let from_mdir = self.get_mdir_from_dir(from_folder)?;
         let to_mdir = self.get_mdir_from_dir(to_folder)?;
+        let maildir_ids = self.maildir_ids(&from_mdir, &internal_ids)?;
 
-        internal_ids.iter().try_for_each(|internal_id| {
-            from_mdir
-                .copy_to(&internal_id, &to_mdir)
-                .map_err(Error::CopyEmailError)
-        })?;
+        let mut copy_ids = Vec::with_capacity(maildir_ids.len());
+        for maildir_id in &maildir_ids {
+            copy_ids.push(Self::copy_email(&from_mdir, &to_mdir, maildir_id)?);
+        }
+        let copy_ids: Vec<&str> = copy_ids.iter().map(String::as_str).collect();
+        self.uids(&to_mdir, &copy_ids)?;
 
         Ok(())
     }
@@ -610,6 +1301,13 @@ impl<'a> Backend for MaildirBackend<'a> {
         to_folder: &str,
         ids: Vec<&str>,
     ) -> backend::Result<()> {
+        let _span = trace::span!(
+            "maildir_backend",
+            method = "move_emails",
+            from_folder = %from_folder,
+            to_folder = %to_folder
+        )
+        .entered();
         info!(
             "moving ids {ids} from folder {from_folder} to folder {to_folder}",
             ids = ids.join(", "),
@@ -624,12 +1322,15 @@ impl<'a> Backend for MaildirBackend<'a> {
             .collect::<Result<_>>()?;
         let internal_ids: Vec<&str> = internal_ids.iter().map(String::as_str).collect();
         trace!("internal ids: {:#?}", internal_ids);
+        let maildir_ids = self.maildir_ids(&from_mdir, &internal_ids)?;
+        let maildir_ids: Vec<&str> = maildir_ids.iter().map(String::as_str).collect();
 
-        internal_ids.iter().try_for_each(|internal_id| {
+        maildir_ids.iter().try_for_each(|maildir_id| {
             from_mdir
-                .move_to(&internal_id, &to_mdir)
+                .move_to(maildir_id, &to_mdir)
                 .map_err(Error::CopyEmailError)
         })?;
+        self.move_uids(&from_mdir, &to_mdir, &maildir_ids)?;
 
         Ok(())
     }
@@ -640,6 +1341,13 @@ impl<'a> Backend for MaildirBackend<'a> {
         to_folder: &str,
         internal_ids: Vec<&str>,
     ) -> backend::Result<()> {
+        let _span = trace::span!(
+            "maildir_backend",
+            method = "move_emails_internal",
+            from_folder = %from_folder,
+            to_folder = %to_folder
+        )
+        .entered();
         info!(
             "moving internal ids {ids} from folder {from_folder} to folder {to_folder}",
             ids = internal_ids.join(", "),
@@ -647,17 +1355,22 @@ impl<'a> Backend for MaildirBackend<'a> {
 
         let from_mdir = self.get_mdir_from_dir(from_folder)?;
         let to_mdir = self.get_mdir_from_dir(to_folder)?;
+        let maildir_ids = self.maildir_ids(&from_mdir, &internal_ids)?;
+        let maildir_ids: Vec<&str> = maildir_ids.iter().map(String::as_str).collect();
 
-        internal_ids.iter().try_for_each(|internal_id| {
+        maildir_ids.iter().try_for_each(|maildir_id| {
             from_mdir
-                .move_to(&internal_id, &to_mdir)
+                .move_to(maildir_id, &to_mdir)
                 .map_err(Error::CopyEmailError)
         })?;
+        self.move_uids(&from_mdir, &to_mdir, &maildir_ids)?;
 
         Ok(())
     }
 
     fn delete_emails(&self, folder: &str, ids: Vec<&str>) -> backend::Result<()> {
+        let _span =
+            trace::span!("maildir_backend", method = "delete_emails", folder = %folder).entered();
         info!(
             "deleting ids {ids} from folder {folder}",
             ids = ids.join(", "),
@@ -671,30 +1384,41 @@ impl<'a> Backend for MaildirBackend<'a> {
             .collect::<Result<_>>()?;
         let internal_ids: Vec<&str> = internal_ids.iter().map(String::as_str).collect();
         trace!("internal ids: {:#?}", internal_ids);
+        let maildir_ids = self.maildir_ids(&mdir, &internal_ids)?;
+        let maildir_ids: Vec<&str> = maildir_ids.iter().map(String::as_str).collect();
 
-        internal_ids.iter().try_for_each(|internal_id| {
-            mdir.delete(&internal_id).map_err(Error::DeleteEmailError)
-        })?;
+        maildir_ids
+            .iter()
+            .try_for_each(|maildir_id| mdir.delete(maildir_id).map_err(Error::DeleteEmailError))?;
+        self.remove_uids(&mdir, &maildir_ids)?;
 
         Ok(())
     }
 
     fn delete_emails_internal(&self, folder: &str, internal_ids: Vec<&str>) -> backend::Result<()> {
+        let _span =
+            trace::span!("maildir_backend", method = "delete_emails_internal", folder = %folder)
+                .entered();
         info!(
             "deleting internal ids {ids} from folder {folder}",
             ids = internal_ids.join(", "),
         );
 
         let mdir = self.get_mdir_from_dir(folder)?;
+        let maildir_ids = self.maildir_ids(&mdir, &internal_ids)?;
+        let maildir_ids: Vec<&str> = maildir_ids.iter().map(String::as_str).collect();
 
-        internal_ids.iter().try_for_each(|internal_id| {
-            mdir.delete(&internal_id).map_err(Error::DeleteEmailError)
-        })?;
+        maildir_ids
+            .iter()
+            .try_for_each(|maildir_id| mdir.delete(maildir_id).map_err(Error::DeleteEmailError))?;
+        self.remove_uids(&mdir, &maildir_ids)?;
 
         Ok(())
     }
 
     fn add_flags(&self, folder: &str, ids: Vec<&str>, flags: &Flags) -> backend::Result<()> {
+        let _span =
+            trace::span!("maildir_backend", method = "add_flags", folder = %folder).entered();
         info!(
             "adding flags {flags} to ids {ids} from folder {folder}",
             flags = flags.to_string(),
@@ -709,13 +1433,14 @@ impl<'a> Backend for MaildirBackend<'a> {
             .collect::<Result<_>>()?;
         let internal_ids: Vec<&str> = internal_ids.iter().map(String::as_str).collect();
         trace!("internal ids: {:#?}", internal_ids);
+        let maildir_ids = self.maildir_ids(&mdir, &internal_ids)?;
+        let maildir_ids: Vec<&str> = maildir_ids.iter().map(String::as_str).collect();
 
-        internal_ids.iter().try_for_each(|internal_id| {
-            mdir.add_flags(&internal_id, &flags::to_normalized_string(&flags))
+        let flags = flags::to_normalized_string(&flags);
+        Self::update_flags(&mdir, &maildir_ids, |internal_id| {
+            mdir.add_flags(internal_id, &flags)
                 .map_err(Error::AddFlagsError)
-        })?;
-
-        Ok(())
+        })
     }
 
     fn add_flags_internal(
@@ -724,6 +1449,9 @@ impl<'a> Backend for MaildirBackend<'a> {
         internal_ids: Vec<&str>,
         flags: &Flags,
     ) -> backend::Result<()> {
+        let _span =
+            trace::span!("maildir_backend", method = "add_flags_internal", folder = %folder)
+                .entered();
         info!(
             "adding flags {flags} to internal ids {ids} from folder {folder}",
             flags = flags.to_string(),
@@ -731,16 +1459,19 @@ impl<'a> Backend for MaildirBackend<'a> {
         );
 
         let mdir = self.get_mdir_from_dir(folder)?;
+        let maildir_ids = self.maildir_ids(&mdir, &internal_ids)?;
+        let maildir_ids: Vec<&str> = maildir_ids.iter().map(String::as_str).collect();
 
-        internal_ids.iter().try_for_each(|internal_id| {
-            mdir.add_flags(&internal_id, &flags::to_normalized_string(&flags))
+        let flags = flags::to_normalized_string(&flags);
+        Self::update_flags(&mdir, &maildir_ids, |internal_id| {
+            mdir.add_flags(internal_id, &flags)
                 .map_err(Error::AddFlagsError)
-        })?;
-
-        Ok(())
+        })
     }
 
     fn set_flags(&self, folder: &str, ids: Vec<&str>, flags: &Flags) -> backend::Result<()> {
+        let _span =
+            trace::span!("maildir_backend", method = "set_flags", folder = %folder).entered();
         info!(
             "setting flags {flags} to ids {ids} from folder {folder}",
             flags = flags.to_string(),
@@ -755,13 +1486,14 @@ impl<'a> Backend for MaildirBackend<'a> {
             .collect::<Result<_>>()?;
         let internal_ids: Vec<&str> = internal_ids.iter().map(String::as_str).collect();
         trace!("internal ids: {:#?}", internal_ids);
+        let maildir_ids = self.maildir_ids(&mdir, &internal_ids)?;
+        let maildir_ids: Vec<&str> = maildir_ids.iter().map(String::as_str).collect();
 
-        internal_ids.iter().try_for_each(|internal_id| {
-            mdir.set_flags(&internal_id, &flags::to_normalized_string(&flags))
+        let flags = flags::to_normalized_string(&flags);
+        Self::update_flags(&mdir, &maildir_ids, |internal_id| {
+            mdir.set_flags(internal_id, &flags)
                 .map_err(Error::SetFlagsError)
-        })?;
-
-        Ok(())
+        })
     }
 
     fn set_flags_internal(
@@ -770,6 +1502,9 @@ impl<'a> Backend for MaildirBackend<'a> {
         internal_ids: Vec<&str>,
         flags: &Flags,
     ) -> backend::Result<()> {
+        let _span =
+            trace::span!("maildir_backend", method = "set_flags_internal", folder = %folder)
+                .entered();
         info!(
             "setting flags {flags} to internal ids {ids} from folder {folder}",
             flags = flags.to_string(),
@@ -777,16 +1512,19 @@ impl<'a> Backend for MaildirBackend<'a> {
         );
 
         let mdir = self.get_mdir_from_dir(folder)?;
+        let maildir_ids = self.maildir_ids(&mdir, &internal_ids)?;
+        let maildir_ids: Vec<&str> = maildir_ids.iter().map(String::as_str).collect();
 
-        internal_ids.iter().try_for_each(|internal_id| {
-            mdir.set_flags(&internal_id, &flags::to_normalized_string(&flags))
+        let flags = flags::to_normalized_string(&flags);
+        Self::update_flags(&mdir, &maildir_ids, |internal_id| {
+            mdir.set_flags(internal_id, &flags)
                 .map_err(Error::SetFlagsError)
-        })?;
-
-        Ok(())
+        })
     }
 
     fn remove_flags(&self, folder: &str, ids: Vec<&str>, flags: &Flags) -> backend::Result<()> {
+        let _span =
+            trace::span!("maildir_backend", method = "remove_flags", folder = %folder).entered();
         info!(
             "removing flags {flags} to ids {ids} from folder {folder}",
             flags = flags.to_string(),
@@ -801,13 +1539,14 @@ impl<'a> Backend for MaildirBackend<'a> {
             .collect::<Result<_>>()?;
         let internal_ids: Vec<&str> = internal_ids.iter().map(String::as_str).collect();
         trace!("internal ids: {:#?}", internal_ids);
+        let maildir_ids = self.maildir_ids(&mdir, &internal_ids)?;
+        let maildir_ids: Vec<&str> = maildir_ids.iter().map(String::as_str).collect();
 
-        internal_ids.iter().try_for_each(|internal_id| {
-            mdir.remove_flags(&internal_id, &flags::to_normalized_string(&flags))
+        let flags = flags::to_normalized_string(&flags);
+        Self::update_flags(&mdir, &maildir_ids, |internal_id| {
+            mdir.remove_flags(internal_id, &flags)
                 .map_err(Error::RemoveFlagsError)
-        })?;
-
-        Ok(())
+        })
     }
 
     fn remove_flags_internal(
@@ -816,6 +1555,9 @@ impl<'a> Backend for MaildirBackend<'a> {
         internal_ids: Vec<&str>,
         flags: &Flags,
     ) -> backend::Result<()> {
+        let _span =
+            trace::span!("maildir_backend", method = "remove_flags_internal", folder = %folder)
+                .entered();
         info!(
             "removing flags {flags} to internal ids {ids} from folder {folder}",
             flags = flags.to_string(),
@@ -823,13 +1565,56 @@ impl<'a> Backend for MaildirBackend<'a> {
         );
 
         let mdir = self.get_mdir_from_dir(folder)?;
+        let maildir_ids = self.maildir_ids(&mdir, &internal_ids)?;
+        let maildir_ids: Vec<&str> = maildir_ids.iter().map(String::as_str).collect();
 
-        internal_ids.iter().try_for_each(|internal_id| {
-            mdir.remove_flags(&internal_id, &flags::to_normalized_string(&flags))
+        let flags = flags::to_normalized_string(&flags);
+        Self::update_flags(&mdir, &maildir_ids, |internal_id| {
+            mdir.remove_flags(internal_id, &flags)
                 .map_err(Error::RemoveFlagsError)
-        })?;
+        })
+    }
 
-        Ok(())
+    /// Marks all the emails of the given folder as seen: emails of
+    /// `new/` are moved to `cur/`, then the `S` flag is added to the
+    /// file names of the unseen ones.
+    fn mark_all_seen(&self, folder: &str) -> backend::Result<()> {
+        let _span =
+            trace::span!("maildir_backend", method = "mark_all_seen", folder = %folder).entered();
+        info!("marking all maildir emails of folder {folder} as seen");
+
+        let mdir = self.get_mdir_from_dir(folder)?;
+
+        let new_ids: Vec<String> = mdir
+            .list_new()
+            .map(|entry| Ok(entry.map_err(Error::GetSubdirEntryError)?.id().to_owned()))
+            .collect::<Result<_>>()?;
+        for id in &new_ids {
+            mdir.move_new_to_cur(id).map_err(Error::MoveMsgError)?;
+        }
+
+        let mut unseen_ids = Vec::new();
+        for entry in mdir.list_cur() {
+            let entry = entry.map_err(Error::GetSubdirEntryError)?;
+            if !entry.is_seen() {
+                unseen_ids.push(entry.id().to_owned());
+            }
+        }
+        let unseen_ids: Vec<&str> = unseen_ids.iter().map(String::as_str).collect();
+        trace!("unseen internal ids: {:#?}", unseen_ids);
+
+        Self::update_flags(&mdir, &unseen_ids, |internal_id| {
+            mdir.add_flags(internal_id, "S")
+                .map_err(Error::AddFlagsError)
+        })
+    }
+
+    fn subscribe(&self) -> backend::Result<Box<dyn backend::BackendEventStream + '_>> {
+        info!("subscribing to maildir events");
+
+        let stream = MaildirEventStream::new(self, event::DEFAULT_POLL_INTERVAL)?;
+
+        Ok(Box::new(stream))
     }
 
     fn as_any(&'static self) -> &(dyn Any) {
@@ -3,7 +3,9 @@
 //! This module contains the representation of the Maildir backend
 //! configuration of the user account.
 
-use std::path::PathBuf;
+use std::{path::PathBuf, result};
+
+use crate::ValidationError;
 
 /// Represents the Maildir backend config.
 #[cfg(feature = "maildir-backend")]
@@ -12,3 +14,19 @@ pub struct MaildirConfig {
     /// Represents the Maildir root directory.
     pub root_dir: PathBuf,
 }
+
+#[cfg(feature = "maildir-backend")]
+impl MaildirConfig {
+    /// Validates the Maildir configuration, see
+    /// [`crate::AccountConfig::validate`].
+    pub fn validate(&self) -> result::Result<(), Vec<ValidationError>> {
+        if self.root_dir.as_os_str().is_empty() {
+            return Err(vec![ValidationError::new(
+                "maildir.root_dir",
+                "root directory cannot be empty",
+            )]);
+        }
+
+        Ok(())
+    }
+}
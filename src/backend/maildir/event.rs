@@ -0,0 +1,123 @@
+//! Maildir event module.
+//!
+//! This module contains the Maildir implementation of the backend
+//! events subscription. Maildir has no notification mechanism, so
+//! folders are polled at a regular interval and compared with the
+//! previous state.
+
+use log::{debug, trace};
+use std::{
+    collections::{HashMap, VecDeque},
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    backend::{self, maildir::Error},
+    envelope::maildir::envelope,
+    flag::maildir::flags,
+    Backend, BackendEvent, Flags, MaildirBackend,
+};
+
+/// Represents the default interval between two folders polls.
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Represents the stream of Maildir backend events.
+pub struct MaildirEventStream<'a> {
+    backend: &'a MaildirBackend<'a>,
+    interval: Duration,
+    /// Flags of the envelopes, indexed by folder then by internal id.
+    state: HashMap<String, HashMap<String, Flags>>,
+    events: VecDeque<BackendEvent>,
+}
+
+impl<'a> MaildirEventStream<'a> {
+    pub fn new(backend: &'a MaildirBackend<'a>, interval: Duration) -> backend::Result<Self> {
+        let mut stream = Self {
+            backend,
+            interval,
+            state: HashMap::new(),
+            events: VecDeque::new(),
+        };
+
+        stream.poll(false)?;
+
+        Ok(stream)
+    }
+
+    /// Compares the current state of the folders with the previous
+    /// one. Events are only emitted if `emit` is `true`, which avoids
+    /// parsing all the emails when building the initial state.
+    fn poll(&mut self, emit: bool) -> backend::Result<()> {
+        let mut state = HashMap::new();
+
+        for folder in self.backend.list_folders()?.iter() {
+            let folder = &folder.name;
+
+            if emit && !self.state.contains_key(folder) {
+                debug!("maildir folder {folder} created");
+                self.events
+                    .push_back(BackendEvent::FolderCreated(folder.clone()));
+            }
+
+            let mdir = self.backend.get_mdir_from_dir(folder)?;
+            let id_mapper = self.backend.id_mapper(folder)?;
+            let prev_state = self.state.remove(folder).unwrap_or_default();
+            let mut next_state = HashMap::with_capacity(prev_state.len());
+
+            for entry in mdir.list_cur() {
+                let entry = entry.map_err(Error::DecodeEntryError)?;
+                let internal_id = entry.id().to_owned();
+                let entry_flags = flags::from_raw(&entry);
+
+                let event = match prev_state.get(&internal_id) {
+                    None => Some(true),
+                    Some(prev_flags) if *prev_flags != entry_flags => Some(false),
+                    Some(_) => None,
+                };
+
+                next_state.insert(internal_id.clone(), entry_flags);
+
+                if let Some(is_new) = event.filter(|_| emit) {
+                    let mut envelope = envelope::from_raw(entry)?;
+                    envelope.internal_id = self.backend.uid(&mdir, &internal_id)?;
+                    envelope.id = id_mapper
+                        .get_id(&envelope.internal_id)
+                        .or_else(|_| id_mapper.insert(&envelope.internal_id))?;
+                    trace!("maildir envelope event: {envelope:?}");
+
+                    let folder = folder.clone();
+                    self.events.push_back(if is_new {
+                        BackendEvent::NewEnvelope { folder, envelope }
+                    } else {
+                        BackendEvent::FlagsChanged { folder, envelope }
+                    });
+                }
+            }
+
+            state.insert(folder.clone(), next_state);
+        }
+
+        self.state = state;
+
+        Ok(())
+    }
+}
+
+impl<'a> Iterator for MaildirEventStream<'a> {
+    type Item = backend::Result<BackendEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Some(Ok(event));
+            }
+
+            thread::sleep(self.interval);
+
+            if let Err(err) = self.poll(true) {
+                return Some(Err(err));
+            }
+        }
+    }
+}
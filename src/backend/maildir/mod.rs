@@ -1,5 +1,14 @@
 pub mod config;
 pub use config::MaildirConfig;
 
+pub mod event;
+pub use event::MaildirEventStream;
+
+pub mod search;
+pub use search::SearchQuery;
+
+pub mod uid_map;
+pub use uid_map::UidMap;
+
 pub mod backend;
 pub use backend::*;
@@ -0,0 +1,317 @@
+//! Maildir search module.
+//!
+//! This module contains the query language used to search Maildir
+//! envelopes. A query is a list of `Key: pattern` conditions that
+//! all need to match, for example `Subject: *meeting* From: *boss*`.
+//!
+//! - Any header name can be used as key. Patterns are matched
+//!   case-insensitively against the header value, and `*` matches
+//!   any sequence of characters.
+//! - `Since: YYYY-MM-DD` and `Before: YYYY-MM-DD` filter on the
+//!   Date header.
+//! - `Body: pattern` matches the text parts of the email. It
+//!   requires the whole email to be read, which is slower than
+//!   header conditions.
+//!
+//! Patterns containing `Word:` sequences can be surrounded by double
+//! quotes.
+
+use chrono::{Local, NaiveDate};
+use log::trace;
+use mailparse::{MailHeader, MailHeaderMap};
+use std::{
+    fs,
+    io::{BufRead, BufReader},
+    path::Path,
+};
+
+use crate::{
+    backend::maildir::{Error, Result},
+    domain::flag::maildir::flags,
+    envelope::maildir::{envelope, RawEnvelope},
+    Envelope,
+};
+
+/// Represents a single search condition.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Condition {
+    Header(String, String),
+    Since(NaiveDate),
+    Before(NaiveDate),
+    Body(String),
+}
+
+/// Represents a parsed search query. All conditions need to match.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SearchQuery {
+    pub conditions: Vec<Condition>,
+}
+
+impl SearchQuery {
+    pub fn parse(query: &str) -> Result<Self> {
+        let mut pairs: Vec<(String, Vec<String>)> = Vec::new();
+
+        for (token, quoted) in tokenize(query)? {
+            match split_key(&token).filter(|_| !quoted) {
+                Some((key, val)) => {
+                    let words = if val.is_empty() {
+                        vec![]
+                    } else {
+                        vec![val.to_owned()]
+                    };
+                    pairs.push((key.to_owned(), words));
+                }
+                None => match pairs.last_mut() {
+                    Some((_, words)) => words.push(token),
+                    None => return Err(Error::ParseSearchQueryError(query.to_owned())),
+                },
+            }
+        }
+
+        let conditions = pairs
+            .into_iter()
+            .map(|(key, words)| {
+                let val = words.join(" ");
+                match key.to_lowercase().as_str() {
+                    "since" => Ok(Condition::Since(parse_date(&val)?)),
+                    "before" => Ok(Condition::Before(parse_date(&val)?)),
+                    "body" => Ok(Condition::Body(val)),
+                    _ => Ok(Condition::Header(key, val)),
+                }
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self { conditions })
+    }
+
+    /// Returns `true` if at least one condition requires the body of
+    /// the email.
+    pub fn needs_body(&self) -> bool {
+        self.conditions
+            .iter()
+            .any(|cond| matches!(cond, Condition::Body(_)))
+    }
+
+    /// Checks the header conditions against the given headers. Body
+    /// conditions are ignored.
+    pub fn matches_headers(&self, headers: &[MailHeader]) -> bool {
+        let date = headers
+            .get_first_value("Date")
+            .and_then(|date| mailparse::dateparse(&date).ok());
+
+        self.conditions.iter().all(|cond| match cond {
+            Condition::Header(key, pattern) => headers
+                .get_all_values(key)
+                .iter()
+                .any(|val| glob_match(pattern, val)),
+            Condition::Since(since) => match (date, timestamp(since)) {
+                (Some(date), Some(since)) => date >= since,
+                _ => false,
+            },
+            Condition::Before(before) => match (date, timestamp(before)) {
+                (Some(date), Some(before)) => date < before,
+                _ => false,
+            },
+            Condition::Body(_) => true,
+        })
+    }
+
+    /// Checks the body conditions against the text parts of the
+    /// email located at the given path.
+    pub fn matches_body(&self, path: &Path) -> Result<bool> {
+        let bytes = fs::read(path).map_err(|err| Error::ReadEmailError(err, path.to_owned()))?;
+        let parsed = mailparse::parse_mail(&bytes)
+            .map_err(|err| Error::ParseEmailError(err, path.to_owned()))?;
+
+        let texts: Vec<String> = parsed
+            .parts()
+            .filter(|part| part.subparts.is_empty())
+            .filter(|part| part.ctype.mimetype.starts_with("text/"))
+            .filter_map(|part| part.get_body().ok())
+            .collect();
+
+        let matches = self.conditions.iter().all(|cond| match cond {
+            Condition::Body(pattern) => texts.iter().any(|text| glob_match(pattern, text)),
+            _ => true,
+        });
+
+        Ok(matches)
+    }
+
+    /// Returns the envelope of the given entry if it matches the
+    /// query, `None` otherwise. Only the header block of the email is
+    /// read, unless the query contains body conditions.
+    pub fn filter_entry(&self, entry: &RawEnvelope) -> Result<Option<Envelope>> {
+        let path = entry.path();
+        let header_bytes = read_header_bytes(path)?;
+        let (headers, _) = mailparse::parse_headers(&header_bytes)
+            .map_err(|err| Error::ParseHeadersError(err, path.to_owned()))?;
+
+        if !self.matches_headers(&headers) {
+            return Ok(None);
+        }
+
+        if self.needs_body() && !self.matches_body(path)? {
+            return Ok(None);
+        }
+
+        let mut envelope =
+            envelope::from_headers(entry.id().to_owned(), flags::from_raw(entry), &headers)?;
+        envelope.size = fs::metadata(path).ok().map(|m| m.len());
+        trace!("matching envelope: {envelope:?}");
+
+        Ok(Some(envelope))
+    }
+}
+
+/// Reads the header block of the email located at the given path,
+/// up to the first empty line.
+fn read_header_bytes(path: &Path) -> Result<Vec<u8>> {
+    let file = fs::File::open(path).map_err(|err| Error::ReadEmailError(err, path.to_owned()))?;
+    let mut reader = BufReader::new(file);
+    let mut bytes = Vec::new();
+
+    loop {
+        let mut line = Vec::new();
+        let len = reader
+            .read_until(b'\n', &mut line)
+            .map_err(|err| Error::ReadEmailError(err, path.to_owned()))?;
+        if len == 0 || line == b"\n" || line == b"\r\n" {
+            break;
+        }
+        bytes.extend(line);
+    }
+
+    Ok(bytes)
+}
+
+/// Splits the query into whitespace-separated tokens. Double quoted
+/// sections are kept together and flagged as quoted.
+fn tokenize(query: &str) -> Result<Vec<(String, bool)>> {
+    let mut tokens = Vec::new();
+    let mut token = String::new();
+    let mut quoted = false;
+    let mut in_quotes = false;
+
+    for c in query.chars() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                quoted = true;
+            }
+            c if c.is_whitespace() && !in_quotes => {
+                if !token.is_empty() || quoted {
+                    tokens.push((token.clone(), quoted));
+                }
+                token.clear();
+                quoted = false;
+            }
+            c => token.push(c),
+        }
+    }
+
+    if in_quotes {
+        return Err(Error::ParseSearchQueryError(query.to_owned()));
+    }
+
+    if !token.is_empty() || quoted {
+        tokens.push((token, quoted));
+    }
+
+    Ok(tokens)
+}
+
+/// Splits a `Key:value` token into its key and its value. Returns
+/// `None` if the token does not start with a key.
+fn split_key(token: &str) -> Option<(&str, &str)> {
+    let (key, val) = token.split_once(':')?;
+    let is_key = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    is_key.then_some((key, val))
+}
+
+fn parse_date(date: &str) -> Result<NaiveDate> {
+    NaiveDate::parse_from_str(date.trim(), "%Y-%m-%d")
+        .map_err(|err| Error::ParseSearchDateError(err, date.to_owned()))
+}
+
+fn timestamp(date: &NaiveDate) -> Option<i64> {
+    date.and_hms_opt(0, 0, 0)?
+        .and_local_timezone(Local)
+        .earliest()
+        .map(|date| date.timestamp())
+}
+
+/// Matches the given value against the given pattern,
+/// case-insensitively. The `*` wildcard matches any sequence of
+/// characters, including an empty one.
+fn glob_match(pattern: &str, val: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let val: Vec<char> = val.to_lowercase().chars().collect();
+
+    let (mut p, mut v) = (0, 0);
+    let mut backtrack = None;
+
+    while v < val.len() {
+        if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, v));
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == val[v] {
+            p += 1;
+            v += 1;
+        } else if let Some((bp, bv)) = backtrack {
+            p = bp + 1;
+            v = bv + 1;
+            backtrack = Some((bp, bv + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod maildir_search {
+    use chrono::NaiveDate;
+
+    use super::{glob_match, Condition, SearchQuery};
+
+    #[test]
+    fn parse_query() {
+        assert_eq!(
+            SearchQuery::parse("Subject: *weekly meeting* From:*boss* Since: 2024-01-01").unwrap(),
+            SearchQuery {
+                conditions: vec![
+                    Condition::Header("Subject".into(), "*weekly meeting*".into()),
+                    Condition::Header("From".into(), "*boss*".into()),
+                    Condition::Since(NaiveDate::from_ymd_opt(2024, 1, 1).unwrap()),
+                ]
+            }
+        );
+
+        assert_eq!(
+            SearchQuery::parse("subject: \"Re: hello\" body: *invoice*").unwrap(),
+            SearchQuery {
+                conditions: vec![
+                    Condition::Header("subject".into(), "Re: hello".into()),
+                    Condition::Body("*invoice*".into()),
+                ]
+            }
+        );
+
+        assert!(SearchQuery::parse("meeting").is_err());
+        assert!(SearchQuery::parse("Since: yesterday").is_err());
+    }
+
+    #[test]
+    fn match_glob() {
+        assert!(glob_match("*meeting*", "Weekly MEETING notes"));
+        assert!(glob_match("hello", "Hello"));
+        assert!(glob_match("a*c", "abbbc"));
+        assert!(!glob_match("hello", "hello world"));
+        assert!(!glob_match("*boss*", "alice@localhost"));
+    }
+}
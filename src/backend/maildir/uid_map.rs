@@ -0,0 +1,414 @@
+//! Maildir UID map module.
+//!
+//! This module contains the map assigning stable integer UIDs to the
+//! emails of a Maildir, the way IMAP servers do. Emails are keyed by
+//! the path of their folder relative to the Maildir root joined with
+//! their Maildir unique id, so that neither flag changes nor moves
+//! between `new` and `cur` change their UID.
+//!
+//! The map is stored in the id mapper database of the Maildir, in a
+//! table per Maildir root. UIDs come from an `AUTOINCREMENT` column,
+//! so that several processes opening the same Maildir, for example a
+//! synchronization and a client reading emails, never assign the same
+//! UID twice nor reuse a removed one.
+
+use log::debug;
+use rusqlite::{params, OptionalExtension, TransactionBehavior};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    result,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("cannot open maildir uid map at {1}")]
+    OpenUidMapError(#[source] rusqlite::Error, PathBuf),
+    #[error(transparent)]
+    SqliteError(#[from] rusqlite::Error),
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+/// Represents the time a write waits for another process to release
+/// the database.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(10);
+
+const CREATE_UID_VALIDITY_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS maildir_uid_validity (
+        uid_table    TEXT    PRIMARY KEY,
+        uid_validity INTEGER NOT NULL
+    )
+";
+
+/// Represents the UID map of a Maildir.
+pub struct UidMap {
+    db: rusqlite::Connection,
+    table: String,
+    uid_validity: u64,
+    created: bool,
+}
+
+impl UidMap {
+    /// Opens the UID map of the Maildir at the given root, stored in
+    /// the given database. The map is created with a new UIDVALIDITY
+    /// if it does not exist yet (see [`UidMap::is_created`]).
+    pub fn open(db_path: &Path, root: &Path) -> Result<Self> {
+        let db = rusqlite::Connection::open(db_path)
+            .map_err(|err| Error::OpenUidMapError(err, db_path.to_owned()))?;
+        db.busy_timeout(BUSY_TIMEOUT)?;
+
+        let hash = md5::compute(root.to_string_lossy().as_bytes());
+        let table = format!("maildir_uids_{hash:x}");
+
+        db.execute(CREATE_UID_VALIDITY_TABLE, [])?;
+        db.execute(
+            &format!(
+                "CREATE TABLE IF NOT EXISTS {table} (
+                    uid        INTEGER PRIMARY KEY AUTOINCREMENT,
+                    folder     TEXT    NOT NULL,
+                    maildir_id TEXT    NOT NULL,
+                    UNIQUE(folder, maildir_id)
+                )"
+            ),
+            [],
+        )?;
+
+        // only the process inserting the UIDVALIDITY creates the map
+        let created = db.execute(
+            "INSERT OR IGNORE INTO maildir_uid_validity (uid_table, uid_validity) VALUES (?, ?)",
+            params![table, new_uid_validity()],
+        )? > 0;
+
+        let uid_validity = db.query_row(
+            "SELECT uid_validity FROM maildir_uid_validity WHERE uid_table = ?",
+            [&table],
+            |row| row.get(0),
+        )?;
+
+        Ok(Self {
+            db,
+            table,
+            uid_validity,
+            created,
+        })
+    }
+
+    /// Returns the UIDVALIDITY of the map, which changes when the
+    /// UIDs are reset.
+    pub fn uid_validity(&self) -> u64 {
+        self.uid_validity
+    }
+
+    /// Returns `true` if the map was created when opening it, in
+    /// which case it should be reconciled with the emails on disk
+    /// (see [`UidMap::reconcile`]).
+    pub fn is_created(&self) -> bool {
+        self.created
+    }
+
+    /// Returns the UID of the email matching the given key.
+    pub fn uid(&self, key: &Path) -> Result<Option<u64>> {
+        let (folder, maildir_id) = split_key(key);
+        let uid = self
+            .db
+            .query_row(
+                &format!(
+                    "SELECT uid FROM {} WHERE folder = ? AND maildir_id = ?",
+                    self.table
+                ),
+                [folder, maildir_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(uid)
+    }
+
+    /// Returns the key of the email matching the given UID.
+    pub fn key(&self, uid: u64) -> Result<Option<PathBuf>> {
+        let key = self
+            .db
+            .query_row(
+                &format!(
+                    "SELECT folder, maildir_id FROM {} WHERE uid = ?",
+                    self.table
+                ),
+                [uid],
+                |row| {
+                    Ok(join_key(
+                        &row.get::<_, String>(0)?,
+                        &row.get::<_, String>(1)?,
+                    ))
+                },
+            )
+            .optional()?;
+        Ok(key)
+    }
+
+    /// Returns the UIDs of the emails matching the given keys,
+    /// assigning new UIDs to the unknown ones.
+    pub fn get_or_insert(&mut self, keys: Vec<PathBuf>) -> Result<Vec<u64>> {
+        let tx = self
+            .db
+            .transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        let mut uids = Vec::with_capacity(keys.len());
+        {
+            let mut insert = tx.prepare(&format!(
+                "INSERT OR IGNORE INTO {} (folder, maildir_id) VALUES (?, ?)",
+                self.table
+            ))?;
+            let mut select = tx.prepare(&format!(
+                "SELECT uid FROM {} WHERE folder = ? AND maildir_id = ?",
+                self.table
+            ))?;
+
+            for key in &keys {
+                let (folder, maildir_id) = split_key(key);
+                insert.execute([&folder, &maildir_id])?;
+                uids.push(select.query_row([folder, maildir_id], |row| row.get(0))?);
+            }
+        }
+
+        tx.commit()?;
+        Ok(uids)
+    }
+
+    /// Changes the keys of the given UIDs, for example after their
+    /// emails moved to another folder.
+    pub fn update(&mut self, uids: Vec<(u64, PathBuf)>) -> Result<()> {
+        let tx = self
+            .db
+            .transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        {
+            // an email reaching a key known under another UID keeps
+            // the UID it moves with
+            let mut delete = tx.prepare(&format!(
+                "DELETE FROM {} WHERE folder = ? AND maildir_id = ? AND uid != ?",
+                self.table
+            ))?;
+            let mut update = tx.prepare(&format!(
+                "UPDATE {} SET folder = ?, maildir_id = ? WHERE uid = ?",
+                self.table
+            ))?;
+
+            for (uid, key) in &uids {
+                let (folder, maildir_id) = split_key(key);
+                delete.execute(params![folder, maildir_id, uid])?;
+                update.execute(params![folder, maildir_id, uid])?;
+            }
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Removes the UIDs of the emails matching the given keys.
+    pub fn remove(&mut self, keys: &[PathBuf]) -> Result<()> {
+        let tx = self
+            .db
+            .transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        {
+            let mut delete = tx.prepare(&format!(
+                "DELETE FROM {} WHERE folder = ? AND maildir_id = ?",
+                self.table
+            ))?;
+            for key in keys {
+                delete.execute(split_key(key))?;
+            }
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Removes the UIDs of all the emails of the given folder,
+    /// relative to the Maildir root.
+    pub fn remove_folder(&mut self, folder: &Path) -> Result<()> {
+        self.db.execute(
+            &format!("DELETE FROM {} WHERE folder = ?", self.table),
+            [folder.to_string_lossy()],
+        )?;
+        Ok(())
+    }
+
+    /// Moves the UIDs of all the emails of a folder to another,
+    /// relative to the Maildir root.
+    pub fn rename_folder(&mut self, from: &Path, to: &Path) -> Result<()> {
+        self.db.execute(
+            &format!("UPDATE {} SET folder = ? WHERE folder = ?", self.table),
+            [to.to_string_lossy(), from.to_string_lossy()],
+        )?;
+        Ok(())
+    }
+
+    /// Makes the map match the emails matching the given keys: UIDs
+    /// of missing emails are removed, new emails get a UID. This
+    /// needs all the emails of the Maildir, so it is meant to run
+    /// once when the map is created or as an explicit maintenance.
+    pub fn reconcile(&mut self, keys: impl IntoIterator<Item = PathBuf>) -> Result<()> {
+        let keys: HashSet<(String, String)> = keys
+            .into_iter()
+            .map(|key| {
+                let (folder, maildir_id) = split_key(&key);
+                (folder, maildir_id)
+            })
+            .collect();
+
+        let tx = self
+            .db
+            .transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        let known: HashSet<(String, String)> = {
+            let mut stmt = tx.prepare(&format!("SELECT folder, maildir_id FROM {}", self.table))?;
+            let known = stmt
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<rusqlite::Result<_>>()?;
+            known
+        };
+
+        let mut removed = 0;
+        {
+            let mut delete = tx.prepare(&format!(
+                "DELETE FROM {} WHERE folder = ? AND maildir_id = ?",
+                self.table
+            ))?;
+            for (folder, maildir_id) in known.difference(&keys) {
+                removed += delete.execute([folder, maildir_id])?;
+            }
+        }
+
+        // new emails are sorted so that UIDs follow the delivery
+        // order within each folder, since Maildir unique ids start
+        // with a timestamp
+        let mut added: Vec<&(String, String)> = keys.difference(&known).collect();
+        added.sort();
+        {
+            let mut insert = tx.prepare(&format!(
+                "INSERT OR IGNORE INTO {} (folder, maildir_id) VALUES (?, ?)",
+                self.table
+            ))?;
+            for (folder, maildir_id) in &added {
+                insert.execute([folder, maildir_id])?;
+            }
+        }
+
+        tx.commit()?;
+
+        debug!(
+            "reconciled maildir uid map: {} uids added, {removed} uids removed",
+            added.len()
+        );
+
+        Ok(())
+    }
+}
+
+/// Splits the given key into the folder, relative to the Maildir
+/// root, and the Maildir unique id.
+fn split_key(key: &Path) -> (String, String) {
+    let folder = key
+        .parent()
+        .map(|folder| folder.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let maildir_id = key
+        .file_name()
+        .map(|id| id.to_string_lossy().to_string())
+        .unwrap_or_default();
+    (folder, maildir_id)
+}
+
+/// Joins the given folder, relative to the Maildir root, and Maildir
+/// unique id into a key.
+fn join_key(folder: &str, maildir_id: &str) -> PathBuf {
+    Path::new(folder).join(maildir_id)
+}
+
+/// Returns a new UIDVALIDITY, based on the current time like IMAP
+/// servers usually do.
+fn new_uid_validity() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or(1)
+}
+
+#[cfg(test)]
+mod uid_map {
+    use std::path::{Path, PathBuf};
+
+    use super::UidMap;
+
+    #[test]
+    fn uid_map() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("uids.sqlite");
+        let root = Path::new("/maildir");
+        let key = |key: &str| PathBuf::from(key);
+
+        let mut map = UidMap::open(&db_path, root).unwrap();
+        assert!(map.is_created());
+        let uid_validity = map.uid_validity();
+        map.reconcile([key("a"), key(".Sent/b")]).unwrap();
+        assert_eq!(Some(key(".Sent/b")), map.key(1).unwrap());
+        assert_eq!(Some(key("a")), map.key(2).unwrap());
+
+        let uids = map.get_or_insert(vec![key("a"), key("c")]).unwrap();
+        assert_eq!(vec![2, 3], uids);
+        map.update(vec![(2, key(".Archives/a"))]).unwrap();
+        map.remove(&[key("c")]).unwrap();
+
+        // changes are persisted, and removed UIDs are not reused
+        let mut map = UidMap::open(&db_path, root).unwrap();
+        assert!(!map.is_created());
+        assert_eq!(uid_validity, map.uid_validity());
+        assert_eq!(Some(key(".Archives/a")), map.key(2).unwrap());
+        assert_eq!(Some(2), map.uid(&key(".Archives/a")).unwrap());
+        assert_eq!(None, map.key(3).unwrap());
+        assert_eq!(vec![4], map.get_or_insert(vec![key("d")]).unwrap());
+
+        // folders are moved and removed as a whole
+        map.rename_folder(Path::new(".Archives"), Path::new(".Old"))
+            .unwrap();
+        assert_eq!(Some(key(".Old/a")), map.key(2).unwrap());
+        map.remove_folder(Path::new(".Sent")).unwrap();
+        assert_eq!(None, map.key(1).unwrap());
+
+        // missing emails lose their UID
+        map.reconcile([key(".Old/a"), key("e")]).unwrap();
+        assert_eq!(None, map.key(4).unwrap());
+        assert_eq!(Some(5), map.uid(&key("e")).unwrap());
+
+        // maildirs sharing the database have their own UIDs
+        let mut other = UidMap::open(&db_path, Path::new("/other")).unwrap();
+        assert!(other.is_created());
+        assert_eq!(vec![1], other.get_or_insert(vec![key("a")]).unwrap());
+    }
+
+    #[test]
+    fn concurrent_uid_maps() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("uids.sqlite");
+        let root = Path::new("/maildir");
+        let key = |key: &str| PathBuf::from(key);
+
+        // two instances of the same maildir, like a synchronization
+        // and a client, never give the same UID to different emails
+        let mut map1 = UidMap::open(&db_path, root).unwrap();
+        let mut map2 = UidMap::open(&db_path, root).unwrap();
+        assert!(!map2.is_created());
+
+        assert_eq!(vec![1], map1.get_or_insert(vec![key("a")]).unwrap());
+        assert_eq!(vec![2], map2.get_or_insert(vec![key("b")]).unwrap());
+        assert_eq!(
+            vec![1, 3],
+            map1.get_or_insert(vec![key("a"), key("c")]).unwrap()
+        );
+        assert_eq!(Some(key("b")), map1.key(2).unwrap());
+        assert_eq!(Some(key("c")), map2.key(3).unwrap());
+    }
+}
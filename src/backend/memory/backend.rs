@@ -0,0 +1,657 @@
+//! Memory backend module.
+//!
+//! This module contains the definition of the in-memory backend. It
+//! keeps folders and raw emails in memory, which makes it suitable
+//! for testing code built on top of the [`Backend`] trait without
+//! any IMAP server, Maildir or Notmuch database.
+
+use chrono::{DateTime, Local, NaiveDateTime};
+use log::{info, trace};
+use mailparse::{MailAddr, MailHeaderMap};
+use std::{
+    any::Any,
+    borrow::Cow,
+    collections::{BTreeMap, HashMap, HashSet},
+    result,
+    sync::{Mutex, MutexGuard},
+};
+use thiserror::Error;
+
+use crate::{
+    account, backend, envelope::Mailbox, AccountConfig, Backend, Emails, Envelope, Envelopes, Flag,
+    Flags, Folder, FolderPermission, Folders, MessagePriority, DEFAULT_INBOX_FOLDER,
+};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("cannot add memory folder {0}: folder already exists")]
+    AddFolderError(String),
+    #[error("cannot find memory folder {0}")]
+    FindFolderError(String),
+    #[error("cannot find memory email {1} in folder {0}")]
+    FindEmailError(String, String),
+    #[error("cannot get memory envelopes at page {0}")]
+    GetEnvelopesOutOfBoundsError(usize),
+    #[error("cannot parse headers of memory email {1}")]
+    ParseHeadersError(#[source] mailparse::MailParseError, String),
+    #[error("cannot lock memory backend store: {0}")]
+    LockStoreError(String),
+    #[error("cannot execute memory operation {0:?}: failure injected")]
+    InjectedFailureError(Operation),
+    #[error("cannot write to memory folder {0}: folder is read-only")]
+    ReadOnlyFolderError(String),
+
+    #[error(transparent)]
+    ConfigError(#[from] account::config::Error),
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+/// Represents the operations of the memory backend. Used to inject
+/// failures, see [`MemoryBackend::fail_next`].
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Operation {
+    AddFolder,
+    ListFolders,
+    PurgeFolder,
+    DeleteFolder,
+    RenameFolder,
+    GetEnvelope,
+    ListEnvelopes,
+    SearchEnvelopes,
+    AddEmail,
+    PreviewEmails,
+    GetEmails,
+    CopyEmails,
+    MoveEmails,
+    DeleteEmails,
+    AddFlags,
+    SetFlags,
+    RemoveFlags,
+    HighestModseq,
+    ListChangedEnvelopes,
+}
+
+#[derive(Clone, Debug)]
+struct MemoryEmail {
+    raw: Vec<u8>,
+    flags: Flags,
+    modseq: u64,
+}
+
+#[derive(Debug, Default)]
+struct Store {
+    folders: HashMap<String, BTreeMap<usize, MemoryEmail>>,
+    last_id: usize,
+    failures: HashSet<Operation>,
+    read_only: HashSet<String>,
+    modseq: Option<u64>,
+}
+
+impl Store {
+    fn folder(&mut self, folder: &str) -> Result<&mut BTreeMap<usize, MemoryEmail>> {
+        self.folders
+            .get_mut(folder)
+            .ok_or_else(|| Error::FindFolderError(folder.to_owned()))
+    }
+
+    fn writable_folder(&mut self, folder: &str) -> Result<&mut BTreeMap<usize, MemoryEmail>> {
+        if self.read_only.contains(folder) {
+            return Err(Error::ReadOnlyFolderError(folder.to_owned()));
+        }
+        self.folder(folder)
+    }
+
+    fn email(&mut self, folder: &str, id: &str) -> Result<&mut MemoryEmail> {
+        let not_found = || Error::FindEmailError(folder.to_owned(), id.to_owned());
+        let id: usize = id.parse().map_err(|_| not_found())?;
+        self.folder(folder)?.get_mut(&id).ok_or_else(not_found)
+    }
+
+    fn next_id(&mut self) -> usize {
+        self.last_id += 1;
+        self.last_id
+    }
+
+    /// Returns the modification sequence of the next change, or 0
+    /// when modification sequences are disabled.
+    fn next_modseq(&mut self) -> u64 {
+        match &mut self.modseq {
+            Some(modseq) => {
+                *modseq += 1;
+                *modseq
+            }
+            None => 0,
+        }
+    }
+}
+
+/// Represents the memory backend.
+///
+/// Ids are generated from a counter shared by all folders, starting
+/// from 1, which makes them deterministic. Since ids and internal ids
+/// are the same, the `*_internal` functions of the [`Backend`] trait
+/// rely on their default implementation.
+///
+/// Like IMAP servers, getting emails marks them as seen whereas
+/// previewing them leaves their flags untouched.
+pub struct MemoryBackend<'a> {
+    account_config: Cow<'a, AccountConfig>,
+    store: Mutex<Store>,
+}
+
+impl<'a> MemoryBackend<'a> {
+    /// Creates a new memory backend containing only the inbox folder.
+    pub fn new(account_config: Cow<'a, AccountConfig>) -> Self {
+        let mut store = Store::default();
+        store
+            .folders
+            .insert(DEFAULT_INBOX_FOLDER.to_owned(), BTreeMap::new());
+
+        Self {
+            account_config,
+            store: Mutex::new(store),
+        }
+    }
+
+    /// Makes the next call of the given operation fail with
+    /// [`Error::InjectedFailureError`]. Useful to test error paths.
+    pub fn fail_next(&self, op: Operation) -> Result<()> {
+        self.store
+            .lock()
+            .map_err(|err| Error::LockStoreError(err.to_string()))?
+            .failures
+            .insert(op);
+        Ok(())
+    }
+
+    /// Makes the given folder read-only, or writable again: changing
+    /// its emails or their flags then fails with
+    /// [`Error::ReadOnlyFolderError`]. Useful to test shared folders.
+    pub fn set_read_only(&self, folder: &str, read_only: bool) -> Result<()> {
+        let folder = self.account_config.folder_alias(folder)?;
+        let mut store = self
+            .store
+            .lock()
+            .map_err(|err| Error::LockStoreError(err.to_string()))?;
+
+        if read_only {
+            store.read_only.insert(folder);
+        } else {
+            store.read_only.remove(&folder);
+        }
+
+        Ok(())
+    }
+
+    /// Makes the backend track a modification sequence per email,
+    /// like IMAP servers supporting CONDSTORE (see
+    /// [`Backend::highest_modseq`]). Useful to test incremental
+    /// synchronizations.
+    pub fn enable_modseq(&self) -> Result<()> {
+        let mut store = self
+            .store
+            .lock()
+            .map_err(|err| Error::LockStoreError(err.to_string()))?;
+        store.modseq.get_or_insert(1);
+        Ok(())
+    }
+
+    fn store(&self, op: Operation) -> Result<MutexGuard<Store>> {
+        let mut store = self
+            .store
+            .lock()
+            .map_err(|err| Error::LockStoreError(err.to_string()))?;
+
+        if store.failures.remove(&op) {
+            return Err(Error::InjectedFailureError(op));
+        }
+
+        Ok(store)
+    }
+
+    fn envelope(id: usize, email: &MemoryEmail) -> Result<Envelope> {
+        let id = id.to_string();
+        let (headers, _) = mailparse::parse_headers(&email.raw)
+            .map_err(|err| Error::ParseHeadersError(err, id.clone()))?;
+
+        let from = headers
+            .get_all_headers("From")
+            .first()
+            .and_then(|header| mailparse::addrparse_header(header).ok())
+            .and_then(|addrs| match addrs.first() {
+                Some(MailAddr::Single(single)) => Some(Mailbox::new(
+                    single.display_name.clone(),
+                    single.addr.clone(),
+                )),
+                _ => None,
+            })
+            .unwrap_or_default();
+
+        let date = headers
+            .get_first_value("Date")
+            .and_then(|date| mailparse::dateparse(&date).ok())
+            .and_then(|timestamp| NaiveDateTime::from_timestamp_opt(timestamp, 0))
+            .and_then(|date| date.and_local_timezone(Local).earliest())
+            .unwrap_or_else(DateTime::default);
+
+        Ok(Envelope {
+            id: id.clone(),
+            internal_id: id,
+            message_id: headers
+                .get_first_value("Message-ID")
+                .unwrap_or_default()
+                .trim()
+                .to_owned(),
+            flags: email.flags.clone(),
+            from,
+            subject: headers.get_first_value("Subject").unwrap_or_default(),
+            date,
+            priority: MessagePriority::from_headers(
+                headers.get_first_value("X-Priority"),
+                headers.get_first_value("Importance"),
+            ),
+            size: Some(email.raw.len() as u64),
+            parse_error: None,
+        })
+    }
+
+    fn list_envelopes_matching<F>(
+        &self,
+        op: Operation,
+        folder: &str,
+        page_size: usize,
+        page: usize,
+        filter: F,
+    ) -> Result<Envelopes>
+    where
+        F: Fn(&Envelope) -> bool,
+    {
+        let folder = self.account_config.folder_alias(folder)?;
+        let mut store = self.store(op)?;
+        let mut envelopes = store
+            .folder(&folder)?
+            .iter()
+            .map(|(id, email)| Self::envelope(*id, email))
+            .collect::<Result<Envelopes>>()?;
+        envelopes.retain(filter);
+
+        let page_begin = page * page_size;
+        trace!("page begin: {}", page_begin);
+        if page_begin > envelopes.len() {
+            return Err(Error::GetEnvelopesOutOfBoundsError(page_begin + 1));
+        }
+
+        let page_end = envelopes.len().min(if page_size == 0 {
+            envelopes.len()
+        } else {
+            page_begin + page_size
+        });
+        trace!("page end: {}", page_end);
+
+        envelopes.sort_by(|a, b| b.date.cmp(&a.date));
+        *envelopes = envelopes[page_begin..page_end].to_vec();
+
+        Ok(envelopes)
+    }
+
+    fn update_flags<F>(&self, op: Operation, folder: &str, ids: Vec<&str>, f: F) -> Result<()>
+    where
+        F: Fn(&mut Flags),
+    {
+        let folder = self.account_config.folder_alias(folder)?;
+        let mut store = self.store(op)?;
+        store.writable_folder(&folder)?;
+        for id in ids {
+            let modseq = store.next_modseq();
+            let email = store.email(&folder, id)?;
+            f(&mut email.flags);
+            email.modseq = modseq;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Backend for MemoryBackend<'a> {
+    fn name(&self) -> String {
+        self.account_config.name.clone()
+    }
+
+    fn add_folder(&self, folder: &str) -> backend::Result<()> {
+        info!("adding memory folder {folder}");
+
+        let folder = self.account_config.folder_alias(folder)?;
+        let mut store = self.store(Operation::AddFolder)?;
+        if store.folders.contains_key(&folder) {
+            return Err(Error::AddFolderError(folder))?;
+        }
+        store.folders.insert(folder, BTreeMap::new());
+
+        Ok(())
+    }
+
+    fn list_folders(&self) -> backend::Result<Folders> {
+        info!("listing memory folders");
+
+        let store = self.store(Operation::ListFolders)?;
+        let mut names: Vec<&String> = store.folders.keys().collect();
+        names.sort();
+
+        let folders = names
+            .into_iter()
+            .map(|name| Folder {
+                delim: String::from("/"),
+                name: name.clone(),
+                raw_name: name.clone(),
+                desc: String::new(),
+                special_use: None,
+            })
+            .collect();
+
+        Ok(folders)
+    }
+
+    fn purge_folder(&self, folder: &str) -> backend::Result<()> {
+        info!("purging memory folder {folder}");
+
+        let folder = self.account_config.folder_alias(folder)?;
+        self.store(Operation::PurgeFolder)?
+            .writable_folder(&folder)?
+            .clear();
+
+        Ok(())
+    }
+
+    fn delete_folder(&self, folder: &str) -> backend::Result<()> {
+        info!("deleting memory folder {folder}");
+
+        let folder = self.account_config.folder_alias(folder)?;
+        self.store(Operation::DeleteFolder)?
+            .folders
+            .remove(&folder)
+            .ok_or(Error::FindFolderError(folder))?;
+
+        Ok(())
+    }
+
+    fn rename_folder(&self, from_folder: &str, to_folder: &str) -> backend::Result<()> {
+        info!("renaming memory folder {from_folder} to {to_folder}");
+
+        let from_folder = self.account_config.folder_alias(from_folder)?;
+        let to_folder = self.account_config.folder_alias(to_folder)?;
+        let mut store = self.store(Operation::RenameFolder)?;
+        if store.folders.contains_key(&to_folder) {
+            return Err(Error::AddFolderError(to_folder))?;
+        }
+        let emails = store
+            .folders
+            .remove(&from_folder)
+            .ok_or(Error::FindFolderError(from_folder))?;
+        store.folders.insert(to_folder, emails);
+
+        Ok(())
+    }
+
+    fn folder_permission(&self, folder: &str) -> backend::Result<FolderPermission> {
+        let folder = self.account_config.folder_alias(folder)?;
+        let store = self
+            .store
+            .lock()
+            .map_err(|err| Error::LockStoreError(err.to_string()))?;
+
+        Ok(if store.read_only.contains(&folder) {
+            FolderPermission::ReadOnly
+        } else {
+            FolderPermission::ReadWrite
+        })
+    }
+
+    fn get_envelope(&self, folder: &str, id: &str) -> backend::Result<Envelope> {
+        info!("getting memory envelope {id} from folder {folder}");
+
+        let folder = self.account_config.folder_alias(folder)?;
+        let mut store = self.store(Operation::GetEnvelope)?;
+        let email = store.email(&folder, id)?;
+        let envelope = Self::envelope(id.parse().unwrap_or_default(), email)?;
+
+        Ok(envelope)
+    }
+
+    fn list_envelopes(
+        &self,
+        folder: &str,
+        page_size: usize,
+        page: usize,
+    ) -> backend::Result<Envelopes> {
+        info!("listing memory envelopes of folder {folder}");
+
+        let envelopes = self.list_envelopes_matching(
+            Operation::ListEnvelopes,
+            folder,
+            page_size,
+            page,
+            |_| true,
+        )?;
+
+        Ok(envelopes)
+    }
+
+    /// Searches envelopes whose subject or sender contains the given
+    /// query (case insensitive). The sort argument is ignored.
+    fn search_envelopes(
+        &self,
+        folder: &str,
+        query: &str,
+        _sort: &str,
+        page_size: usize,
+        page: usize,
+    ) -> backend::Result<Envelopes> {
+        info!("searching memory envelopes of folder {folder} matching {query}");
+
+        let query = query.to_lowercase();
+        let envelopes = self.list_envelopes_matching(
+            Operation::SearchEnvelopes,
+            folder,
+            page_size,
+            page,
+            |envelope| {
+                envelope.subject.to_lowercase().contains(&query)
+                    || envelope.from.addr.to_lowercase().contains(&query)
+            },
+        )?;
+
+        Ok(envelopes)
+    }
+
+    /// Returns `None` unless modification sequences are enabled (see
+    /// [`MemoryBackend::enable_modseq`]).
+    fn highest_modseq(&self, folder: &str) -> backend::Result<Option<(u64, usize)>> {
+        let folder = self.account_config.folder_alias(folder)?;
+        let mut store = self.store(Operation::HighestModseq)?;
+        let size = store.folder(&folder)?.len();
+        Ok(store.modseq.map(|modseq| (modseq, size)))
+    }
+
+    fn list_changed_envelopes_since_modseq(
+        &self,
+        folder: &str,
+        modseq: u64,
+    ) -> backend::Result<Envelopes> {
+        info!("listing memory envelopes of folder {folder} changed since modseq {modseq}");
+
+        let folder = self.account_config.folder_alias(folder)?;
+        let mut store = self.store(Operation::ListChangedEnvelopes)?;
+        if store.modseq.is_none() {
+            return Err(backend::Error::NotSupported);
+        }
+
+        let envelopes = store
+            .folder(&folder)?
+            .iter()
+            .filter(|(_, email)| email.modseq > modseq)
+            .map(|(id, email)| Self::envelope(*id, email))
+            .collect::<Result<Envelopes>>()?;
+
+        Ok(envelopes)
+    }
+
+    fn add_email(&self, folder: &str, email: &[u8], flags: &Flags) -> backend::Result<String> {
+        info!(
+            "adding memory email to folder {folder} with flags {flags}",
+            flags = flags.to_string(),
+        );
+
+        let folder = self.account_config.folder_alias(folder)?;
+        let mut store = self.store(Operation::AddEmail)?;
+        store.writable_folder(&folder)?;
+        let id = store.next_id();
+        let email = MemoryEmail {
+            raw: email.to_vec(),
+            flags: flags.clone(),
+            modseq: store.next_modseq(),
+        };
+        store.folder(&folder)?.insert(id, email);
+
+        Ok(id.to_string())
+    }
+
+    fn preview_emails(&self, folder: &str, ids: Vec<&str>) -> backend::Result<Emails> {
+        info!("previewing memory emails {ids:?} from folder {folder}");
+
+        let folder = self.account_config.folder_alias(folder)?;
+        let mut store = self.store(Operation::PreviewEmails)?;
+        let emails = ids
+            .into_iter()
+            .map(|id| Ok(store.email(&folder, id)?.raw.clone()))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Emails::from(emails))
+    }
+
+    fn get_emails(&self, folder: &str, ids: Vec<&str>) -> backend::Result<Emails> {
+        info!("getting memory emails {ids:?} from folder {folder}");
+
+        let folder = self.account_config.folder_alias(folder)?;
+        let mut store = self.store(Operation::GetEmails)?;
+        let emails = ids
+            .into_iter()
+            .map(|id| {
+                let modseq = store.next_modseq();
+                let email = store.email(&folder, id)?;
+                if email.flags.insert(Flag::Seen) {
+                    email.modseq = modseq;
+                }
+                Ok(email.raw.clone())
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Emails::from(emails))
+    }
+
+    fn copy_emails(
+        &self,
+        from_folder: &str,
+        to_folder: &str,
+        ids: Vec<&str>,
+    ) -> backend::Result<()> {
+        info!("copying memory emails {ids:?} from folder {from_folder} to folder {to_folder}");
+
+        let from_folder = self.account_config.folder_alias(from_folder)?;
+        let to_folder = self.account_config.folder_alias(to_folder)?;
+        let mut store = self.store(Operation::CopyEmails)?;
+        store.writable_folder(&to_folder)?;
+        for id in ids {
+            let mut email = store.email(&from_folder, id)?.clone();
+            email.modseq = store.next_modseq();
+            let id = store.next_id();
+            store.folder(&to_folder)?.insert(id, email);
+        }
+
+        Ok(())
+    }
+
+    fn move_emails(
+        &self,
+        from_folder: &str,
+        to_folder: &str,
+        ids: Vec<&str>,
+    ) -> backend::Result<()> {
+        info!("moving memory emails {ids:?} from folder {from_folder} to folder {to_folder}");
+
+        let from_folder = self.account_config.folder_alias(from_folder)?;
+        let to_folder = self.account_config.folder_alias(to_folder)?;
+        let mut store = self.store(Operation::MoveEmails)?;
+        store.writable_folder(&from_folder)?;
+        store.writable_folder(&to_folder)?;
+        for id in ids {
+            let mut email = store.email(&from_folder, id)?.clone();
+            email.modseq = store.next_modseq();
+            store
+                .folder(&from_folder)?
+                .retain(|email_id, _| email_id.to_string() != id);
+            let id = store.next_id();
+            store.folder(&to_folder)?.insert(id, email);
+        }
+
+        Ok(())
+    }
+
+    fn delete_emails(&self, folder: &str, ids: Vec<&str>) -> backend::Result<()> {
+        info!("deleting memory emails {ids:?} from folder {folder}");
+
+        let folder = self.account_config.folder_alias(folder)?;
+        let mut store = self.store(Operation::DeleteEmails)?;
+        store.writable_folder(&folder)?;
+        for id in ids {
+            store.email(&folder, id)?;
+            store
+                .folder(&folder)?
+                .retain(|email_id, _| email_id.to_string() != id);
+        }
+
+        Ok(())
+    }
+
+    fn add_flags(&self, folder: &str, ids: Vec<&str>, flags: &Flags) -> backend::Result<()> {
+        info!(
+            "adding flags {flags} to memory emails {ids:?} from folder {folder}",
+            flags = flags.to_string(),
+        );
+
+        self.update_flags(Operation::AddFlags, folder, ids, |email_flags| {
+            email_flags.extend(flags.iter().cloned())
+        })?;
+
+        Ok(())
+    }
+
+    fn set_flags(&self, folder: &str, ids: Vec<&str>, flags: &Flags) -> backend::Result<()> {
+        info!(
+            "setting flags {flags} to memory emails {ids:?} from folder {folder}",
+            flags = flags.to_string(),
+        );
+
+        self.update_flags(Operation::SetFlags, folder, ids, |email_flags| {
+            *email_flags = flags.clone()
+        })?;
+
+        Ok(())
+    }
+
+    fn remove_flags(&self, folder: &str, ids: Vec<&str>, flags: &Flags) -> backend::Result<()> {
+        info!(
+            "removing flags {flags} from memory emails {ids:?} from folder {folder}",
+            flags = flags.to_string(),
+        );
+
+        self.update_flags(Operation::RemoveFlags, folder, ids, |email_flags| {
+            email_flags.retain(|flag| !flags.contains(flag))
+        })?;
+
+        Ok(())
+    }
+
+    fn as_any(&'static self) -> &(dyn Any) {
+        self
+    }
+}
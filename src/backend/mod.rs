@@ -1,22 +1,40 @@
+#[cfg(feature = "async")]
+mod async_backend;
 mod backend;
 mod config;
 pub mod id_mapper;
 
 #[cfg(feature = "imap-backend")]
 pub mod imap;
+#[cfg(feature = "jmap-backend")]
+pub mod jmap;
 #[cfg(feature = "maildir-backend")]
 pub mod maildir;
+#[cfg(feature = "memory-backend")]
+pub mod memory;
 #[cfg(feature = "notmuch-backend")]
 pub mod notmuch;
+#[cfg(feature = "pop3-backend")]
+pub mod pop3;
 
+#[cfg(feature = "async")]
+pub use self::async_backend::{AsyncBackend, BackendFuture};
 pub use self::backend::{
-    Backend, BackendBuilder, BackendSyncBuilder, BackendSyncProgressEvent, Error, Result,
+    transfer_emails, Backend, BackendBuilder, BackendEvent, BackendEventStream, BackendSyncBuilder,
+    BackendSyncProgressEvent, BackendSyncReport, Error, PurgeReport, RebuildCacheReport, Result,
+    SyncExecutor, TransferReport, DEFAULT_TRANSFER_BATCH_SIZE,
 };
 pub use self::config::BackendConfig;
 pub use self::id_mapper::IdMapper;
 #[cfg(feature = "imap-backend")]
-pub use self::imap::{ImapBackend, ImapBackendBuilder, ImapConfig};
+pub use self::imap::{ImapBackend, ImapBackendBuilder, ImapConfig, ImapFetchAttr};
+#[cfg(feature = "jmap-backend")]
+pub use self::jmap::{JmapBackend, JmapConfig};
 #[cfg(feature = "maildir-backend")]
 pub use self::maildir::{MaildirBackend, MaildirConfig};
+#[cfg(feature = "memory-backend")]
+pub use self::memory::MemoryBackend;
 #[cfg(feature = "notmuch-backend")]
 pub use self::notmuch::{NotmuchBackend, NotmuchConfig};
+#[cfg(feature = "pop3-backend")]
+pub use self::pop3::{Pop3Backend, Pop3Config};
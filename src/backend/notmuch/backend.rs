@@ -1,13 +1,26 @@
 use lettre::address::AddressError;
 use log::{info, trace};
-use std::{any::Any, borrow::Cow, fs, io, path::PathBuf, result};
+use std::{
+    any::Any,
+    borrow::Cow,
+    fs,
+    io::{self, Read},
+    panic,
+    path::PathBuf,
+    process::{Command, Stdio},
+    result, thread,
+    time::{Duration, Instant},
+};
 use thiserror::Error;
 
 use crate::{
     account, backend, email,
-    envelope::notmuch::{envelope, envelopes},
-    id_mapper, AccountConfig, Backend, Emails, Envelope, Envelopes, Flag, Flags, Folder, Folders,
-    IdMapper, NotmuchConfig,
+    envelope::{
+        notmuch::{envelope, envelopes},
+        strip_message_id,
+    },
+    id_mapper, trace, AccountConfig, Backend, Emails, Envelope, Envelopes, Flag, Flags, Folder,
+    FolderStatus, Folders, IdMapper, NotmuchConfig,
 };
 
 #[derive(Debug, Error)]
@@ -28,6 +41,12 @@ pub enum Error {
     FindEmailError(#[source] notmuch::Error),
     #[error("cannot remove tags from notmuch email {1}")]
     RemoveAllTagsError(#[source] notmuch::Error, String),
+    #[error("cannot run notmuch new on database {1}")]
+    RunNewCommandError(#[source] io::Error, PathBuf),
+    #[error("cannot run notmuch new on database {1}: {0}")]
+    NewCommandFailedError(String, PathBuf),
+    #[error("cannot run notmuch new on database {1}: timed out after {0:?}")]
+    NewCommandTimeoutError(Duration, PathBuf),
 
     #[error("cannot get notmuch backend from config")]
     GetBackendFromConfigError,
@@ -51,6 +70,8 @@ pub enum Error {
     CloseDatabaseError(#[source] notmuch::Error),
     #[error("cannot build notmuch query")]
     BuildQueryError(#[source] notmuch::Error),
+    #[error("cannot count notmuch envelopes matching {1}")]
+    CountEnvelopesError(#[source] notmuch::Error, String),
     #[error("cannot search notmuch envelopes")]
     SearchEnvelopesError(#[source] notmuch::Error),
     #[error("cannot get notmuch envelopes at page {0}")]
@@ -145,6 +166,24 @@ impl<'a> NotmuchBackend<'a> {
         Ok(id_mapper)
     }
 
+    /// Indexes the emails added to the notmuch root since the last
+    /// indexation by running `notmuch new`, and returns the amount of
+    /// newly indexed emails. The command is killed if it runs longer
+    /// than [`NotmuchConfig::new_command_timeout_secs`].
+    pub fn index_new(&self) -> backend::Result<usize> {
+        Ok(run_new_command(&self.backend_config)?)
+    }
+
+    fn count_envelopes(&self, query: &str) -> Result<usize> {
+        let count = self.with_db(|db| {
+            db.create_query(query)
+                .map_err(Error::BuildQueryError)?
+                .count_messages()
+                .map_err(|err| Error::CountEnvelopesError(err, query.to_owned()))
+        })?;
+        Ok(count as usize)
+    }
+
     fn _search_envelopes(&self, query: &str, page_size: usize, page: usize) -> Result<Envelopes> {
         let id_mapper = self.id_mapper()?;
         let mut envelopes = self.with_db(|db| {
@@ -187,14 +226,17 @@ impl<'a> Backend for NotmuchBackend<'a> {
     }
 
     fn add_folder(&self, _folder: &str) -> backend::Result<()> {
+        let _span = trace::span!("notmuch_backend", method = "add_folder").entered();
         Err(Error::AddMboxUnimplementedError)?
     }
 
     fn list_folders(&self) -> backend::Result<Folders> {
+        let _span = trace::span!("notmuch_backend", method = "list_folders").entered();
         let mut mboxes = Folders::default();
         for (name, desc) in &self.account_config.folder_aliases {
             mboxes.push(Folder {
                 name: name.into(),
+                raw_name: name.into(),
                 desc: desc.into(),
                 ..Folder::default()
             })
@@ -206,14 +248,40 @@ impl<'a> Backend for NotmuchBackend<'a> {
     }
 
     fn purge_folder(&self, _folder: &str) -> backend::Result<()> {
+        let _span = trace::span!("notmuch_backend", method = "purge_folder").entered();
         Err(Error::PurgeFolderUnimplementedError)?
     }
 
     fn delete_folder(&self, _folder: &str) -> backend::Result<()> {
+        let _span = trace::span!("notmuch_backend", method = "delete_folder").entered();
         Err(Error::DeleteFolderUnimplementedError)?
     }
 
+    /// Gets the status of the given virtual folder with count queries.
+    /// Emails are unseen unless they have the `seen` tag. Notmuch has
+    /// no notion of recent emails, so none is reported.
+    fn folder_status(&self, virtual_folder: &str) -> backend::Result<FolderStatus> {
+        let _span =
+            trace::span!("notmuch_backend", method = "folder_status", folder = %virtual_folder)
+                .entered();
+        info!("getting status of notmuch virtual folder {virtual_folder}");
+
+        let query = self
+            .account_config
+            .folder_alias(virtual_folder)
+            .unwrap_or_else(|_| String::from("all"));
+        trace!("query: {query}");
+
+        Ok(FolderStatus {
+            total: self.count_envelopes(&query)?,
+            unseen: self.count_envelopes(&format!("({query}) and not tag:seen"))?,
+            recent: 0,
+            size_bytes: None,
+        })
+    }
+
     fn get_envelope(&self, _folder: &str, id: &str) -> backend::Result<Envelope> {
+        let _span = trace::span!("notmuch_backend", method = "get_envelope").entered();
         info!("getting notmuch envelope by id {id}");
 
         let internal_id = self.id_mapper()?.get_internal_id(id)?;
@@ -232,6 +300,7 @@ impl<'a> Backend for NotmuchBackend<'a> {
     }
 
     fn get_envelope_internal(&self, _folder: &str, internal_id: &str) -> backend::Result<Envelope> {
+        let _span = trace::span!("notmuch_backend", method = "get_envelope_internal").entered();
         info!("getting notmuch envelope by internal id {internal_id}");
 
         let envelope = self.with_db(|db| {
@@ -252,6 +321,9 @@ impl<'a> Backend for NotmuchBackend<'a> {
         page_size: usize,
         page: usize,
     ) -> backend::Result<Envelopes> {
+        let _span =
+            trace::span!("notmuch_backend", method = "list_envelopes", folder = %virtual_folder)
+                .entered();
         info!("listing notmuch envelopes from virtual folder {virtual_folder}");
 
         let query = self
@@ -274,6 +346,9 @@ impl<'a> Backend for NotmuchBackend<'a> {
         page_size: usize,
         page: usize,
     ) -> backend::Result<Envelopes> {
+        let _span =
+            trace::span!("notmuch_backend", method = "search_envelopes", folder = %virtual_folder)
+                .entered();
         info!("searching notmuch envelopes from virtual folder {virtual_folder}");
 
         let query = if query.is_empty() {
@@ -291,7 +366,38 @@ impl<'a> Backend for NotmuchBackend<'a> {
         Ok(envelopes)
     }
 
+    /// Finds the email natively with an `id:` query, restricted to the
+    /// query of the given virtual folder if any. Without folder, the
+    /// email is returned along with the `all` virtual folder.
+    fn find_by_message_id(
+        &self,
+        virtual_folder: Option<&str>,
+        message_id: &str,
+    ) -> backend::Result<Option<(String, Envelope)>> {
+        let _span = trace::span!("notmuch_backend", method = "find_by_message_id").entered();
+        info!("finding notmuch email matching Message-ID {message_id}");
+
+        let id = strip_message_id(message_id).replace('"', "\"\"");
+        let query = match virtual_folder {
+            Some(virtual_folder) => {
+                let folder_query = self
+                    .account_config
+                    .folder_alias(virtual_folder)
+                    .unwrap_or_else(|_| String::from("all"));
+                format!("id:\"{id}\" and ({folder_query})")
+            }
+            None => format!("id:\"{id}\""),
+        };
+        trace!("query: {query}");
+
+        let envelope = self._search_envelopes(&query, 1, 0)?.first().cloned();
+        let folder = virtual_folder.unwrap_or("all").to_owned();
+
+        Ok(envelope.map(|envelope| (folder, envelope)))
+    }
+
     fn add_email(&self, _folder: &str, email: &[u8], flags: &Flags) -> backend::Result<String> {
+        let _span = trace::span!("notmuch_backend", method = "add_email").entered();
         info!(
             "adding notmuch email with flags {flags}",
             flags = flags.to_string()
@@ -324,6 +430,7 @@ impl<'a> Backend for NotmuchBackend<'a> {
         email: &[u8],
         flags: &Flags,
     ) -> backend::Result<String> {
+        let _span = trace::span!("notmuch_backend", method = "add_email_internal").entered();
         info!(
             "adding notmuch email with flags {flags}",
             flags = flags.to_string()
@@ -351,6 +458,7 @@ impl<'a> Backend for NotmuchBackend<'a> {
     }
 
     fn preview_emails(&self, _folder: &str, ids: Vec<&str>) -> backend::Result<Emails> {
+        let _span = trace::span!("notmuch_backend", method = "preview_emails").entered();
         info!(
             "previewing notmuch emails by ids {ids}",
             ids = ids.join(", ")
@@ -388,6 +496,7 @@ impl<'a> Backend for NotmuchBackend<'a> {
         _folder: &str,
         internal_ids: Vec<&str>,
     ) -> backend::Result<Emails> {
+        let _span = trace::span!("notmuch_backend", method = "preview_emails_internal").entered();
         info!(
             "previewing notmuch emails by internal ids {ids}",
             ids = internal_ids.join(", ")
@@ -414,6 +523,8 @@ impl<'a> Backend for NotmuchBackend<'a> {
     }
 
     fn get_emails(&self, folder: &str, ids: Vec<&str>) -> backend::Result<Emails> {
+        let _span =
+            trace::span!("notmuch_backend", method = "get_emails", folder = %folder).entered();
         info!("getting notmuch emails by ids {ids}", ids = ids.join(", "));
         let emails = self.preview_emails(folder, ids.clone())?;
         self.add_flags("INBOX", ids, &Flags::from_iter([Flag::Seen]))?;
@@ -425,6 +536,9 @@ impl<'a> Backend for NotmuchBackend<'a> {
         folder: &str,
         internal_ids: Vec<&str>,
     ) -> backend::Result<Emails> {
+        let _span =
+            trace::span!("notmuch_backend", method = "get_emails_internal", folder = %folder)
+                .entered();
         info!(
             "getting notmuch emails by internal ids {ids}",
             ids = internal_ids.join(", ")
@@ -440,6 +554,7 @@ impl<'a> Backend for NotmuchBackend<'a> {
         _to_dir: &str,
         _short_hashes: Vec<&str>,
     ) -> backend::Result<()> {
+        let _span = trace::span!("notmuch_backend", method = "copy_emails").entered();
         // How to deal with duplicate Message-ID?
         Err(Error::CopyMsgUnimplementedError)?
     }
@@ -450,6 +565,7 @@ impl<'a> Backend for NotmuchBackend<'a> {
         _to_dir: &str,
         _internal_ids: Vec<&str>,
     ) -> backend::Result<()> {
+        let _span = trace::span!("notmuch_backend", method = "copy_emails_internal").entered();
         // How to deal with duplicate Message-ID?
         Err(Error::CopyMsgUnimplementedError)?
     }
@@ -460,6 +576,7 @@ impl<'a> Backend for NotmuchBackend<'a> {
         _to_dir: &str,
         _short_hashes: Vec<&str>,
     ) -> backend::Result<()> {
+        let _span = trace::span!("notmuch_backend", method = "move_emails").entered();
         Err(Error::MoveMsgUnimplementedError)?
     }
 
@@ -469,10 +586,12 @@ impl<'a> Backend for NotmuchBackend<'a> {
         _to_dir: &str,
         _internal_ids: Vec<&str>,
     ) -> backend::Result<()> {
+        let _span = trace::span!("notmuch_backend", method = "move_emails_internal").entered();
         Err(Error::MoveMsgUnimplementedError)?
     }
 
     fn delete_emails(&self, _folder: &str, ids: Vec<&str>) -> backend::Result<()> {
+        let _span = trace::span!("notmuch_backend", method = "delete_emails").entered();
         info!("deleting notmuch emails by ids {ids}", ids = ids.join(", "));
 
         let id_mapper = self.id_mapper()?;
@@ -502,6 +621,7 @@ impl<'a> Backend for NotmuchBackend<'a> {
         _folder: &str,
         internal_ids: Vec<&str>,
     ) -> backend::Result<()> {
+        let _span = trace::span!("notmuch_backend", method = "delete_emails_internal").entered();
         info!(
             "deleting notmuch emails by internal ids {ids}",
             ids = internal_ids.join(", ")
@@ -528,6 +648,7 @@ impl<'a> Backend for NotmuchBackend<'a> {
         ids: Vec<&str>,
         flags: &Flags,
     ) -> backend::Result<()> {
+        let _span = trace::span!("notmuch_backend", method = "add_flags").entered();
         info!(
             "adding notmuch flags {flags} by ids {ids}",
             flags = flags.to_string(),
@@ -570,6 +691,7 @@ impl<'a> Backend for NotmuchBackend<'a> {
         internal_ids: Vec<&str>,
         flags: &Flags,
     ) -> backend::Result<()> {
+        let _span = trace::span!("notmuch_backend", method = "add_flags_internal").entered();
         info!(
             "adding notmuch flags {flags} by internal_ids {ids}",
             flags = flags.to_string(),
@@ -600,6 +722,7 @@ impl<'a> Backend for NotmuchBackend<'a> {
     }
 
     fn set_flags(&self, _folder: &str, ids: Vec<&str>, flags: &Flags) -> backend::Result<()> {
+        let _span = trace::span!("notmuch_backend", method = "set_flags").entered();
         info!(
             "setting notmuch flags {flags} by ids {ids}",
             flags = flags.to_string(),
@@ -646,6 +769,7 @@ impl<'a> Backend for NotmuchBackend<'a> {
         internal_ids: Vec<&str>,
         flags: &Flags,
     ) -> backend::Result<()> {
+        let _span = trace::span!("notmuch_backend", method = "set_flags_internal").entered();
         info!(
             "setting notmuch flags {flags} by internal_ids {ids}",
             flags = flags.to_string(),
@@ -685,6 +809,7 @@ impl<'a> Backend for NotmuchBackend<'a> {
         ids: Vec<&str>,
         flags: &Flags,
     ) -> backend::Result<()> {
+        let _span = trace::span!("notmuch_backend", method = "remove_flags").entered();
         info!(
             "removing notmuch flags {flags} by ids {ids}",
             flags = flags.to_string(),
@@ -727,6 +852,7 @@ impl<'a> Backend for NotmuchBackend<'a> {
         internal_ids: Vec<&str>,
         flags: &Flags,
     ) -> backend::Result<()> {
+        let _span = trace::span!("notmuch_backend", method = "remove_flags_internal").entered();
         info!(
             "removing notmuch flags {flags} by internal_ids {ids}",
             flags = flags.to_string(),
@@ -756,6 +882,39 @@ impl<'a> Backend for NotmuchBackend<'a> {
         Ok(())
     }
 
+    /// Marks all the emails matching the query of the given virtual
+    /// folder as seen, tagging them while the database is opened
+    /// once.
+    fn mark_all_seen(&self, virtual_folder: &str) -> backend::Result<()> {
+        let _span =
+            trace::span!("notmuch_backend", method = "mark_all_seen", folder = %virtual_folder)
+                .entered();
+        info!("marking all notmuch emails of virtual folder {virtual_folder} as seen");
+
+        let folder_query = self
+            .account_config
+            .folder_alias(virtual_folder)
+            .unwrap_or_else(|_| String::from("all"));
+        let tag = Flag::Seen.to_string();
+        let query = format!("({folder_query}) and not tag:{tag}");
+        trace!("query: {query}");
+
+        self.with_db(|db| {
+            let query_builder = db.create_query(&query).map_err(Error::BuildQueryError)?;
+            let emails = query_builder
+                .search_messages()
+                .map_err(Error::SearchEnvelopesError)?;
+
+            for email in emails {
+                email.add_tag(&tag).map_err(Error::AddTagError)?;
+            }
+
+            Ok(())
+        })?;
+
+        Ok(())
+    }
+
     fn as_any(&self) -> &(dyn Any + 'a) {
         self
     }
@@ -784,6 +943,14 @@ impl NotmuchBackendBuilder {
         account_config: Cow<'a, AccountConfig>,
         backend_config: Cow<'a, NotmuchConfig>,
     ) -> Result<NotmuchBackend<'a>> {
+        if backend_config.auto_init && !backend_config.db_path.join(".notmuch").is_dir() {
+            info!(
+                "initializing notmuch database at {path:?}",
+                path = backend_config.db_path
+            );
+            run_new_command(&backend_config)?;
+        }
+
         let mdir = maildir::Maildir::from(backend_config.db_path.clone());
 
         let db_path = self
@@ -798,3 +965,104 @@ impl NotmuchBackendBuilder {
         })
     }
 }
+
+/// Runs `notmuch new` on the database of the given config, creating
+/// the database if it does not exist, and returns the amount of newly
+/// indexed emails. The database path takes precedence over the user
+/// notmuch configuration file, which is not loaded.
+fn run_new_command(config: &NotmuchConfig) -> Result<usize> {
+    let db_path = &config.db_path;
+    let mut child = Command::new("notmuch")
+        .arg("--config=")
+        .arg("new")
+        .env("NOTMUCH_DATABASE", db_path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| Error::RunNewCommandError(err, db_path.clone()))?;
+
+    // the pipes are drained while the command runs, otherwise it
+    // blocks once their buffer is full
+    let stdout = read_pipe(child.stdout.take());
+    let stderr = read_pipe(child.stderr.take());
+
+    let started_at = Instant::now();
+    let status = loop {
+        match child
+            .try_wait()
+            .map_err(|err| Error::RunNewCommandError(err, db_path.clone()))?
+        {
+            Some(status) => break status,
+            None => match config.new_command_timeout() {
+                Some(timeout) if started_at.elapsed() >= timeout => {
+                    // the child may have exited in the meantime
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return Err(Error::NewCommandTimeoutError(timeout, db_path.clone()));
+                }
+                _ => thread::sleep(Duration::from_millis(50)),
+            },
+        }
+    };
+
+    let join_pipe = |pipe: thread::JoinHandle<io::Result<String>>| {
+        pipe.join()
+            .unwrap_or_else(|err| panic::resume_unwind(err))
+            .map_err(|err| Error::RunNewCommandError(err, db_path.clone()))
+    };
+
+    let stdout = join_pipe(stdout)?;
+    let stderr = join_pipe(stderr)?;
+    if !status.success() {
+        return Err(Error::NewCommandFailedError(
+            stderr.trim().to_owned(),
+            db_path.clone(),
+        ));
+    }
+
+    Ok(parse_new_command_count(&stdout))
+}
+
+/// Reads the given pipe to the end on a dedicated thread.
+fn read_pipe<R: Read + Send + 'static>(pipe: Option<R>) -> thread::JoinHandle<io::Result<String>> {
+    thread::spawn(move || {
+        let mut output = String::new();
+        if let Some(mut pipe) = pipe {
+            pipe.read_to_string(&mut output)?;
+        }
+        Ok(output)
+    })
+}
+
+/// Parses the amount of newly indexed emails from the output of
+/// `notmuch new`, for example `Added 3 new messages to the
+/// database.`. Outputs without such line, like `No new mail.`, count
+/// as zero.
+fn parse_new_command_count(stdout: &str) -> usize {
+    stdout
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("Added "))
+        .filter_map(|line| line.split_whitespace().next())
+        .find_map(|count| count.parse().ok())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod notmuch_backend {
+    #[test]
+    fn parse_new_command_count() {
+        assert_eq!(0, super::parse_new_command_count("No new mail.\n"));
+        assert_eq!(
+            1,
+            super::parse_new_command_count("Added 1 new message to the database.\n")
+        );
+        assert_eq!(
+            42,
+            super::parse_new_command_count(concat!(
+                "Processed 42 total files in almost no time.\n",
+                "Added 42 new messages to the database.\n",
+            ))
+        );
+    }
+}
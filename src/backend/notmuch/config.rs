@@ -3,7 +3,7 @@
 //! This module contains the representation of the notmuch backend
 //! configuration of the user account.
 
-use std::path::PathBuf;
+use std::{path::PathBuf, time::Duration};
 
 /// Represents the Notmuch backend config.
 #[cfg(feature = "notmuch-backend")]
@@ -11,4 +11,20 @@ use std::path::PathBuf;
 pub struct NotmuchConfig {
     /// Represents the notmuch database path.
     pub db_path: PathBuf,
+    /// Initializes the notmuch database with `notmuch new` when it
+    /// does not exist yet, instead of failing.
+    pub auto_init: bool,
+    /// Represents the maximum amount of seconds `notmuch new` can run
+    /// before being killed. Defaults to no timeout.
+    pub new_command_timeout_secs: u64,
+}
+
+#[cfg(feature = "notmuch-backend")]
+impl NotmuchConfig {
+    pub fn new_command_timeout(&self) -> Option<Duration> {
+        match self.new_command_timeout_secs {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        }
+    }
 }
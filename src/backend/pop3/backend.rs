@@ -0,0 +1,373 @@
+//! POP3 backend module.
+//!
+//! This module contains the definition of the POP3 backend. POP3 only
+//! gives access to the inbox of the user, without flags: the backend
+//! exposes a single `INBOX` folder, and operations that cannot be
+//! expressed with POP3 return [`backend::Error::NotSupported`].
+//!
+//! Emails are identified by their unique id (`UIDL` command), since
+//! message numbers change between sessions. Servers not supporting
+//! `UIDL` fall back to message numbers.
+
+use log::{debug, info, trace, warn};
+use native_tls::TlsConnector;
+use std::{any::Any, borrow::Cow, io, net::TcpStream, result};
+use thiserror::Error;
+
+use crate::{
+    account,
+    backend::{
+        self,
+        pop3::{config, Pop3Session, Pop3SessionStream},
+    },
+    envelope, AccountConfig, Backend, Emails, Envelope, Envelopes, Flags, Folder, FolderSpecialUse,
+    Folders, Pop3Config,
+};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("cannot connect to pop3 server {1}:{2}")]
+    ConnectError(#[source] io::Error, String, u16),
+    #[error("cannot create tls connector")]
+    CreateTlsConnectorError(#[source] native_tls::Error),
+    #[error("cannot establish tls connection with pop3 server {1}")]
+    TlsHandshakeError(#[source] native_tls::HandshakeError<TcpStream>, String),
+    #[error("cannot send pop3 command {1}")]
+    WriteCommandError(#[source] io::Error, String),
+    #[error("cannot read pop3 response")]
+    ReadResponseError(#[source] io::Error),
+    #[error("cannot read pop3 response: connection closed by the server")]
+    ConnectionClosedError,
+    #[error("pop3 command {0} failed: {1}")]
+    NegativeResponseError(String, String),
+    #[error("cannot parse pop3 response to command {0}: {1}")]
+    ParseResponseError(String, String),
+    #[error("cannot parse headers of pop3 email {1}")]
+    ParseHeadersError(#[source] mailparse::MailParseError, String),
+    #[error("cannot find pop3 email {0}")]
+    FindEmailError(String),
+    #[error("cannot find pop3 folder {0}: only the inbox is available")]
+    FindFolderError(String),
+    #[error("cannot get pop3 envelopes at page {0}")]
+    GetEnvelopesOutOfBoundsError(usize),
+
+    #[error(transparent)]
+    Pop3ConfigError(#[from] config::Error),
+    #[error(transparent)]
+    ConfigError(#[from] account::config::Error),
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+pub struct Pop3Backend<'a> {
+    account_config: Cow<'a, AccountConfig>,
+    pop3_config: Cow<'a, Pop3Config>,
+    passwd: String,
+}
+
+impl<'a> Pop3Backend<'a> {
+    pub fn new(
+        account_config: Cow<'a, AccountConfig>,
+        pop3_config: Cow<'a, Pop3Config>,
+    ) -> Result<Self> {
+        let passwd = pop3_config.passwd()?;
+
+        Ok(Self {
+            account_config,
+            pop3_config,
+            passwd,
+        })
+    }
+
+    /// Opens a new authenticated session. Sessions are not kept
+    /// between operations, since the server locks the mailbox for
+    /// their whole duration.
+    fn session(&self) -> Result<Pop3Session<Pop3SessionStream>> {
+        let config = &self.pop3_config;
+        let tcp = TcpStream::connect((config.host.as_str(), config.port))
+            .map_err(|err| Error::ConnectError(err, config.host.clone(), config.port))?;
+
+        let stream = if config.ssl() {
+            let connector = TlsConnector::builder()
+                .danger_accept_invalid_certs(config.insecure())
+                .danger_accept_invalid_hostnames(config.insecure())
+                .build()
+                .map_err(Error::CreateTlsConnectorError)?;
+            let tls = connector
+                .connect(&config.host, tcp)
+                .map_err(|err| Error::TlsHandshakeError(err, config.host.clone()))?;
+            Pop3SessionStream::Tls(tls)
+        } else {
+            Pop3SessionStream::Tcp(tcp)
+        };
+
+        let mut session = Pop3Session::new(stream)?;
+        session.login(&config.login, &self.passwd)?;
+
+        Ok(session)
+    }
+
+    /// Ensures that the given folder is the inbox, the only folder
+    /// available through POP3.
+    fn check_folder(&self, folder: &str) -> Result<()> {
+        let folder = self.account_config.folder_alias(folder)?;
+        if folder != self.account_config.inbox_folder_alias()? {
+            return Err(Error::FindFolderError(folder));
+        }
+        Ok(())
+    }
+
+    /// Lists the message numbers and their unique id.
+    fn ids(session: &mut Pop3Session<Pop3SessionStream>) -> Result<Vec<(u32, String)>> {
+        match session.uidl() {
+            Ok(ids) => Ok(ids),
+            Err(Error::NegativeResponseError(_, msg)) => {
+                warn!("pop3 server does not support unique ids, using message numbers: {msg}");
+                let ids = session
+                    .list()?
+                    .into_iter()
+                    .map(|(n, _)| (n, n.to_string()))
+                    .collect();
+                Ok(ids)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Finds the message numbers matching the given unique ids.
+    fn msg_numbers(session: &mut Pop3Session<Pop3SessionStream>, ids: &[&str]) -> Result<Vec<u32>> {
+        let all_ids = Self::ids(session)?;
+        ids.iter()
+            .map(|id| {
+                all_ids
+                    .iter()
+                    .find(|(_, uid)| uid == id)
+                    .map(|(n, _)| *n)
+                    .ok_or_else(|| Error::FindEmailError(id.to_string()))
+            })
+            .collect()
+    }
+
+    fn list_all_envelopes(&self) -> Result<Envelopes> {
+        let mut session = self.session()?;
+        let ids = Self::ids(&mut session)?;
+        let sizes = session.list()?;
+
+        let envelopes = ids
+            .into_iter()
+            .map(|(n, id)| {
+                let size = sizes
+                    .iter()
+                    .find(|(size_n, _)| *size_n == n)
+                    .map(|(_, size)| *size)
+                    .unwrap_or_default();
+                let headers = session.top(n, 0)?;
+                envelope::pop3::from_raw(id, size, &headers)
+            })
+            .collect::<Result<Envelopes>>()?;
+
+        session.quit()?;
+
+        Ok(envelopes)
+    }
+
+    fn retr_emails(&self, folder: &str, ids: Vec<&str>) -> Result<Emails> {
+        self.check_folder(folder)?;
+
+        let mut session = self.session()?;
+        let emails = Self::msg_numbers(&mut session, &ids)?
+            .into_iter()
+            .map(|n| session.retr(n))
+            .collect::<Result<Vec<_>>>()?;
+        session.quit()?;
+
+        Ok(Emails::from(emails))
+    }
+
+    fn dele_emails(&self, ids: Option<Vec<&str>>) -> Result<()> {
+        let mut session = self.session()?;
+        let numbers = match ids {
+            Some(ids) => Self::msg_numbers(&mut session, &ids)?,
+            None => Self::ids(&mut session)?
+                .into_iter()
+                .map(|(n, _)| n)
+                .collect(),
+        };
+        debug!("deleting pop3 messages {numbers:?}");
+
+        for n in numbers {
+            session.dele(n)?;
+        }
+
+        // deletions are only committed once the session is closed
+        session.quit()?;
+
+        Ok(())
+    }
+}
+
+impl<'a> Backend for Pop3Backend<'a> {
+    fn name(&self) -> String {
+        self.account_config.name.clone()
+    }
+
+    fn add_folder(&self, _folder: &str) -> backend::Result<()> {
+        Err(backend::Error::NotSupported)
+    }
+
+    fn list_folders(&self) -> backend::Result<Folders> {
+        info!("listing pop3 folders");
+
+        let inbox = self.account_config.inbox_folder_alias()?;
+        let folders = Folders::from_iter([Folder {
+            delim: String::from("/"),
+            name: inbox.clone(),
+            raw_name: inbox,
+            desc: String::new(),
+            special_use: Some(FolderSpecialUse::Inbox),
+        }]);
+
+        Ok(folders)
+    }
+
+    fn purge_folder(&self, folder: &str) -> backend::Result<()> {
+        info!("purging pop3 folder {folder}");
+
+        self.check_folder(folder)?;
+        self.dele_emails(None)?;
+
+        Ok(())
+    }
+
+    fn delete_folder(&self, _folder: &str) -> backend::Result<()> {
+        Err(backend::Error::NotSupported)
+    }
+
+    fn rename_folder(&self, _from_folder: &str, _to_folder: &str) -> backend::Result<()> {
+        Err(backend::Error::NotSupported)
+    }
+
+    fn get_envelope(&self, folder: &str, id: &str) -> backend::Result<Envelope> {
+        info!("getting pop3 envelope {id} from folder {folder}");
+
+        self.check_folder(folder)?;
+
+        let mut session = self.session()?;
+        let n = Self::msg_numbers(&mut session, &[id])?[0];
+        let size = session
+            .list()?
+            .into_iter()
+            .find(|(size_n, _)| *size_n == n)
+            .map(|(_, size)| size)
+            .unwrap_or_default();
+        let headers = session.top(n, 0)?;
+        session.quit()?;
+
+        let envelope = envelope::pop3::from_raw(id.to_owned(), size, &headers)?;
+
+        Ok(envelope)
+    }
+
+    fn list_envelopes(
+        &self,
+        folder: &str,
+        page_size: usize,
+        page: usize,
+    ) -> backend::Result<Envelopes> {
+        info!("listing pop3 envelopes of folder {folder}");
+
+        self.check_folder(folder)?;
+
+        let mut envelopes = self.list_all_envelopes()?;
+
+        let page_begin = page * page_size;
+        trace!("page begin: {}", page_begin);
+        if page_begin > envelopes.len() {
+            return Err(Error::GetEnvelopesOutOfBoundsError(page_begin + 1))?;
+        }
+
+        let page_end = envelopes.len().min(if page_size == 0 {
+            envelopes.len()
+        } else {
+            page_begin + page_size
+        });
+        trace!("page end: {}", page_end);
+
+        envelopes.sort_by(|a, b| b.date.cmp(&a.date));
+        *envelopes = envelopes[page_begin..page_end].to_vec();
+
+        Ok(envelopes)
+    }
+
+    fn search_envelopes(
+        &self,
+        _folder: &str,
+        _query: &str,
+        _sort: &str,
+        _page_size: usize,
+        _page: usize,
+    ) -> backend::Result<Envelopes> {
+        Err(backend::Error::NotSupported)
+    }
+
+    fn add_email(&self, _folder: &str, _email: &[u8], _flags: &Flags) -> backend::Result<String> {
+        Err(backend::Error::NotSupported)
+    }
+
+    fn preview_emails(&self, folder: &str, ids: Vec<&str>) -> backend::Result<Emails> {
+        info!("previewing pop3 emails {ids:?} from folder {folder}");
+
+        // POP3 has no seen flag, previewing and getting emails are
+        // equivalent
+        Ok(self.retr_emails(folder, ids)?)
+    }
+
+    fn get_emails(&self, folder: &str, ids: Vec<&str>) -> backend::Result<Emails> {
+        info!("getting pop3 emails {ids:?} from folder {folder}");
+
+        Ok(self.retr_emails(folder, ids)?)
+    }
+
+    fn copy_emails(
+        &self,
+        _from_folder: &str,
+        _to_folder: &str,
+        _ids: Vec<&str>,
+    ) -> backend::Result<()> {
+        Err(backend::Error::NotSupported)
+    }
+
+    fn move_emails(
+        &self,
+        _from_folder: &str,
+        _to_folder: &str,
+        _ids: Vec<&str>,
+    ) -> backend::Result<()> {
+        Err(backend::Error::NotSupported)
+    }
+
+    fn delete_emails(&self, folder: &str, ids: Vec<&str>) -> backend::Result<()> {
+        info!("deleting pop3 emails {ids:?} from folder {folder}");
+
+        self.check_folder(folder)?;
+        self.dele_emails(Some(ids))?;
+
+        Ok(())
+    }
+
+    fn add_flags(&self, _folder: &str, _ids: Vec<&str>, _flags: &Flags) -> backend::Result<()> {
+        Err(backend::Error::NotSupported)
+    }
+
+    fn set_flags(&self, _folder: &str, _ids: Vec<&str>, _flags: &Flags) -> backend::Result<()> {
+        Err(backend::Error::NotSupported)
+    }
+
+    fn remove_flags(&self, _folder: &str, _ids: Vec<&str>, _flags: &Flags) -> backend::Result<()> {
+        Err(backend::Error::NotSupported)
+    }
+
+    fn as_any(&'static self) -> &(dyn Any) {
+        self
+    }
+}
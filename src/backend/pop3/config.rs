@@ -0,0 +1,60 @@
+//! POP3 backend config module.
+//!
+//! This module contains the representation of the POP3 backend
+//! configuration of the user account.
+
+use std::result;
+use thiserror::Error;
+
+use crate::process;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("cannot get pop3 password")]
+    GetPasswdError(#[source] process::Error),
+    #[error("cannot get pop3 password: password is empty")]
+    GetPasswdEmptyError,
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+/// Represents the POP3 backend configuration.
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct Pop3Config {
+    /// Represents the POP3 server host.
+    pub host: String,
+    /// Represents the POP3 server port.
+    pub port: u16,
+    /// Enables SSL.
+    pub ssl: Option<bool>,
+    /// Trusts any certificate.
+    pub insecure: Option<bool>,
+    /// Represents the POP3 server login.
+    pub login: String,
+    /// Represents the POP3 server password command.
+    pub passwd_cmd: String,
+}
+
+impl Pop3Config {
+    /// Executes the POP3 password command in order to retrieve the
+    /// POP3 server password.
+    pub fn passwd(&self) -> Result<String> {
+        let passwd = process::run(&self.passwd_cmd, &[]).map_err(Error::GetPasswdError)?;
+        let passwd = String::from_utf8_lossy(&passwd).to_string();
+        let passwd = passwd
+            .lines()
+            .next()
+            .ok_or_else(|| Error::GetPasswdEmptyError)?;
+        Ok(passwd.to_owned())
+    }
+
+    /// Gets the SSL POP3 option.
+    pub fn ssl(&self) -> bool {
+        self.ssl.unwrap_or(true)
+    }
+
+    /// Gets the insecure POP3 option.
+    pub fn insecure(&self) -> bool {
+        self.insecure.unwrap_or_default()
+    }
+}
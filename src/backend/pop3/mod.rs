@@ -0,0 +1,8 @@
+pub mod config;
+pub use config::Pop3Config;
+
+pub mod session;
+pub use session::{Pop3Session, Pop3SessionStream};
+
+pub mod backend;
+pub use backend::*;
@@ -0,0 +1,283 @@
+//! POP3 session module.
+//!
+//! This module contains a minimal POP3 client ([RFC 1939]), covering
+//! the commands needed by the POP3 backend.
+//!
+//! [RFC 1939]: https://www.rfc-editor.org/rfc/rfc1939
+
+use log::trace;
+use native_tls::TlsStream;
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::TcpStream,
+};
+
+use crate::backend::pop3::{Error, Result};
+
+pub enum Pop3SessionStream {
+    Tls(TlsStream<TcpStream>),
+    Tcp(TcpStream),
+}
+
+impl Read for Pop3SessionStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Tls(stream) => stream.read(buf),
+            Self::Tcp(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Pop3SessionStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Tls(stream) => stream.write(buf),
+            Self::Tcp(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Tls(stream) => stream.flush(),
+            Self::Tcp(stream) => stream.flush(),
+        }
+    }
+}
+
+/// Represents an authenticated POP3 session.
+///
+/// The server locks the mailbox for the duration of the session, and
+/// deletions only take effect once the session is closed with
+/// [`Pop3Session::quit`].
+pub struct Pop3Session<S: Read + Write> {
+    stream: BufReader<S>,
+}
+
+impl<S: Read + Write> Pop3Session<S> {
+    /// Creates a session from the given stream and reads the server
+    /// greeting.
+    pub fn new(stream: S) -> Result<Self> {
+        let mut session = Self {
+            stream: BufReader::new(stream),
+        };
+        session.read_status("greeting")?;
+        Ok(session)
+    }
+
+    pub fn login(&mut self, login: &str, passwd: &str) -> Result<()> {
+        self.command(&format!("USER {login}"))?;
+        self.command(&format!("PASS {passwd}"))?;
+        Ok(())
+    }
+
+    /// Lists the message numbers and their size in bytes.
+    pub fn list(&mut self) -> Result<Vec<(u32, u64)>> {
+        self.multiline_pairs("LIST")?
+            .into_iter()
+            .map(|(n, size)| {
+                let size = size
+                    .parse()
+                    .map_err(|_| Error::ParseResponseError("LIST".into(), size))?;
+                Ok((n, size))
+            })
+            .collect()
+    }
+
+    /// Lists the message numbers and their unique id, which, unlike
+    /// message numbers, are stable across sessions.
+    pub fn uidl(&mut self) -> Result<Vec<(u32, String)>> {
+        self.multiline_pairs("UIDL")
+    }
+
+    /// Gets the header block of the given message, followed by the
+    /// given amount of body lines.
+    pub fn top(&mut self, n: u32, lines: u32) -> Result<Vec<u8>> {
+        self.multiline(&format!("TOP {n} {lines}"))
+    }
+
+    pub fn retr(&mut self, n: u32) -> Result<Vec<u8>> {
+        self.multiline(&format!("RETR {n}"))
+    }
+
+    /// Marks the given message as deleted. The message is removed
+    /// when the session is closed.
+    pub fn dele(&mut self, n: u32) -> Result<()> {
+        self.command(&format!("DELE {n}"))?;
+        Ok(())
+    }
+
+    /// Closes the session, which commits the deletions.
+    pub fn quit(mut self) -> Result<()> {
+        self.command("QUIT")?;
+        Ok(())
+    }
+
+    /// Sends the given command and reads its single line response.
+    fn command(&mut self, cmd: &str) -> Result<String> {
+        // only the command name is kept, in order not to leak the
+        // password in logs and errors
+        let name = cmd.split_whitespace().next().unwrap_or_default();
+        if name == "PASS" {
+            trace!("pop3 command: PASS ***");
+        } else {
+            trace!("pop3 command: {cmd}");
+        }
+
+        let stream = self.stream.get_mut();
+        stream
+            .write_all(format!("{cmd}\r\n").as_bytes())
+            .and_then(|()| stream.flush())
+            .map_err(|err| Error::WriteCommandError(err, name.to_owned()))?;
+
+        self.read_status(name)
+    }
+
+    /// Sends the given command and reads its multi-line response,
+    /// without the termination line. Dot-stuffed lines are restored.
+    fn multiline(&mut self, cmd: &str) -> Result<Vec<u8>> {
+        self.command(cmd)?;
+
+        let mut data = Vec::new();
+        loop {
+            let line = self.read_line()?;
+            if line == b".\r\n" || line == b".\n" {
+                break;
+            }
+            match line.strip_prefix(b".") {
+                Some(line) => data.extend_from_slice(line),
+                None => data.extend_from_slice(&line),
+            }
+        }
+
+        Ok(data)
+    }
+
+    /// Sends the given command and parses its multi-line response as
+    /// a list of message numbers followed by a value.
+    fn multiline_pairs(&mut self, cmd: &str) -> Result<Vec<(u32, String)>> {
+        let data = self.multiline(cmd)?;
+        String::from_utf8_lossy(&data)
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                let parse_error = || Error::ParseResponseError(cmd.to_owned(), line.to_owned());
+                let (n, val) = line.trim().split_once(' ').ok_or_else(parse_error)?;
+                let n = n.parse().map_err(|_| parse_error())?;
+                Ok((n, val.trim().to_owned()))
+            })
+            .collect()
+    }
+
+    fn read_line(&mut self) -> Result<Vec<u8>> {
+        let mut line = Vec::new();
+        let len = self
+            .stream
+            .read_until(b'\n', &mut line)
+            .map_err(Error::ReadResponseError)?;
+        if len == 0 {
+            return Err(Error::ConnectionClosedError);
+        }
+        Ok(line)
+    }
+
+    fn read_status(&mut self, cmd: &str) -> Result<String> {
+        let line = self.read_line()?;
+        let line = String::from_utf8_lossy(&line).trim_end().to_owned();
+        trace!("pop3 response: {line}");
+
+        if let Some(msg) = line.strip_prefix("+OK") {
+            Ok(msg.trim().to_owned())
+        } else if let Some(msg) = line.strip_prefix("-ERR") {
+            Err(Error::NegativeResponseError(
+                cmd.to_owned(),
+                msg.trim().to_owned(),
+            ))
+        } else {
+            Err(Error::ParseResponseError(cmd.to_owned(), line))
+        }
+    }
+}
+
+#[cfg(test)]
+mod pop3_session {
+    use std::io::{self, Cursor, Read, Write};
+
+    use super::Pop3Session;
+    use crate::backend::pop3::Error;
+
+    struct MockStream {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl MockStream {
+        fn new(input: &str) -> Self {
+            Self {
+                input: Cursor::new(input.as_bytes().to_vec()),
+                output: Vec::new(),
+            }
+        }
+    }
+
+    impl Read for MockStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for MockStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn commands() {
+        let stream = MockStream::new(concat!(
+            "+OK POP3 server ready\r\n",
+            "+OK\r\n",
+            "+OK logged in\r\n",
+            "+OK 2 messages\r\n",
+            "1 120\r\n",
+            "2 2048\r\n",
+            ".\r\n",
+            "+OK\r\n",
+            "1 uid-a\r\n",
+            "2 uid-b\r\n",
+            ".\r\n",
+            "+OK message follows\r\n",
+            "Subject: dots\r\n",
+            "\r\n",
+            "..hidden\r\n",
+            "text\r\n",
+            ".\r\n",
+            "-ERR no such message\r\n",
+        ));
+
+        let mut session = Pop3Session::new(stream).unwrap();
+        session.login("alice", "secret").unwrap();
+
+        assert_eq!(vec![(1, 120), (2, 2048)], session.list().unwrap());
+        assert_eq!(
+            vec![(1, "uid-a".to_owned()), (2, "uid-b".to_owned())],
+            session.uidl().unwrap()
+        );
+        assert_eq!(
+            b"Subject: dots\r\n\r\n.hidden\r\ntext\r\n".to_vec(),
+            session.retr(1).unwrap()
+        );
+        assert!(matches!(
+            session.dele(3),
+            Err(Error::NegativeResponseError(cmd, msg)) if cmd == "DELE" && msg == "no such message"
+        ));
+
+        assert_eq!(
+            "USER alice\r\nPASS secret\r\nLIST\r\nUIDL\r\nRETR 1\r\nDELE 3\r\n",
+            String::from_utf8(session.stream.into_inner().output).unwrap()
+        );
+    }
+}
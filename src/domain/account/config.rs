@@ -4,13 +4,19 @@
 //! account configuration.
 
 use dirs::data_dir;
-use lettre::{address::AddressError, message::Mailbox};
+use lettre::{
+    address::{Address, AddressError},
+    message::Mailbox,
+};
 use log::warn;
 use shellexpand;
-use std::{collections::HashMap, env, ffi::OsStr, fs, io, path::PathBuf, result};
+use std::{collections::HashMap, env, ffi::OsStr, fs, io, path::PathBuf, result, time::Duration};
 use thiserror::Error;
 
-use crate::{process, EmailHooks, EmailSender, EmailTextPlainFormat};
+use crate::{
+    envelope::sync::ConflictResolution, folder, process, EmailHooks, EmailSender,
+    EmailTextPlainFormat,
+};
 
 pub const DEFAULT_PAGE_SIZE: usize = 10;
 pub const DEFAULT_SIGNATURE_DELIM: &str = "-- \n";
@@ -18,6 +24,7 @@ pub const DEFAULT_SIGNATURE_DELIM: &str = "-- \n";
 pub const DEFAULT_INBOX_FOLDER: &str = "INBOX";
 pub const DEFAULT_SENT_FOLDER: &str = "Sent";
 pub const DEFAULT_DRAFTS_FOLDER: &str = "Drafts";
+pub const DEFAULT_TRASH_FOLDER: &str = "Trash";
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -42,10 +49,57 @@ pub enum Error {
     GetXdgDataDirError,
     #[error("cannot create sync directories")]
     CreateXdgDataDirsError(#[source] io::Error),
+    #[error("cannot interpolate environment variable {0}: variable not set")]
+    MissingEnvVarError(String),
 }
 
 pub type Result<T> = result::Result<T, Error>;
 
+/// Represents an invalid field of the account configuration (see
+/// [`AccountConfig::validate`]).
+#[derive(Debug, Error, Clone, Eq, PartialEq)]
+#[error("invalid account config field {field}: {message}")]
+pub struct ValidationError {
+    pub field: String,
+    pub message: String,
+}
+
+impl ValidationError {
+    pub fn new(field: impl ToString, message: impl ToString) -> Self {
+        Self {
+            field: field.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    /// Joins the fields and messages of the given errors, for errors
+    /// reporting several invalid fields at once.
+    pub fn join(errs: &[Self]) -> String {
+        errs.iter()
+            .map(|err| format!("{}: {}", err.field, err.message))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// Represents the synchronization hooks, commands run around the
+/// synchronization of the account (see
+/// [`crate::BackendSyncBuilder::sync`]). A failing hook does not
+/// abort the synchronization.
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct SyncHooks {
+    /// Represents the hook called before the synchronization starts,
+    /// for example to unlock credentials.
+    pub pre_sync: Option<String>,
+    /// Represents the hook called once the synchronization is done,
+    /// for example to index the synchronized Maildir.
+    pub post_sync: Option<String>,
+    /// Represents the hook called for every email downloaded by the
+    /// synchronization, which receives the path of the email file on
+    /// its standard input.
+    pub on_new_email: Option<String>,
+}
+
 /// Represents the configuration of the user account.
 #[derive(Debug, Default, Clone, Eq, PartialEq)]
 pub struct AccountConfig {
@@ -66,6 +120,9 @@ pub struct AccountConfig {
     pub folder_listing_page_size: Option<usize>,
     /// Represents the folder aliases hash map.
     pub folder_aliases: HashMap<String, String>,
+    /// Represents the folder where drafts are saved. Takes precedence
+    /// over the `drafts` folder alias. Defaults to `Drafts`.
+    pub drafts_folder: Option<String>,
 
     /// Represents the page size when listing emails.
     pub email_listing_page_size: Option<usize>,
@@ -88,6 +145,10 @@ pub struct AccountConfig {
     pub email_writing_headers: Option<Vec<String>>,
     /// Represents the email sender provider.
     pub email_sender: EmailSender,
+    /// Saves a copy of sent emails to the sent folder. Defaults to
+    /// `true`. Should be disabled for providers saving them
+    /// server-side (like Gmail), otherwise they end up duplicated.
+    pub email_sending_save_copy: Option<bool>,
     /// Represents the email hooks.
     pub email_hooks: EmailHooks,
 
@@ -97,9 +158,138 @@ pub struct AccountConfig {
     /// Customizes the root directory where the Maildir cache is
     /// saved. Defaults to `$XDG_DATA_HOME/himalaya/<account-name>`.
     pub sync_dir: Option<PathBuf>,
+    /// Represents the minimum amount of seconds between two
+    /// synchronizations of this account (see
+    /// [`crate::BackendSyncBuilder::should_sync`]). Defaults to no
+    /// minimum.
+    pub sync_interval_secs: Option<u64>,
+    /// Removes the envelopes sharing the same message id before
+    /// synchronizing a folder, for backends listing the same email
+    /// several times (see [`crate::Envelopes::dedup_by_message_id`]).
+    pub sync_dedup: bool,
+    /// Represents the conflict resolution strategies overriding the
+    /// one of the synchronization for some folders (see
+    /// [`crate::envelope::sync::SyncBuilder::conflict_resolution`]).
+    /// Keys are folder names or aliases, matched case-insensitively.
+    pub folder_conflict_overrides: HashMap<String, ConflictResolution>,
+    /// Represents the synchronization hooks.
+    pub sync_hooks: SyncHooks,
 }
 
 impl AccountConfig {
+    /// Validates the account configuration. All the invalid fields
+    /// are reported at once.
+    ///
+    /// The backend configuration is not part of the account
+    /// configuration, it is validated when building the backend.
+    pub fn validate(&self) -> result::Result<(), Vec<ValidationError>> {
+        let mut errs = Vec::new();
+
+        if self.name.trim().is_empty() {
+            errs.push(ValidationError::new("name", "name cannot be empty"));
+        }
+
+        if let Err(err) = self.email.parse::<Address>() {
+            errs.push(ValidationError::new(
+                "email",
+                format!("invalid address {}: {err}", self.email),
+            ));
+        }
+
+        if let Some(display_name) = self.display_name.as_ref() {
+            if display_name.chars().any(char::is_control) {
+                errs.push(ValidationError::new(
+                    "display_name",
+                    "display name cannot contain control characters",
+                ));
+            }
+        }
+
+        // aliases are sorted so that collisions are reported in a
+        // stable order
+        let mut aliases: Vec<_> = self.folder_aliases.iter().collect();
+        aliases.sort();
+        let mut targets: HashMap<String, &String> = HashMap::new();
+        for (alias, target) in aliases {
+            let key = target.trim().to_lowercase();
+            match targets.get(&key) {
+                Some(other) => errs.push(ValidationError::new(
+                    "folder_aliases",
+                    format!("aliases {other} and {alias} both target folder {target}"),
+                )),
+                None => {
+                    targets.insert(key, alias);
+                }
+            }
+        }
+
+        match &self.email_sender {
+            EmailSender::None => {
+                errs.push(ValidationError::new(
+                    "email_sender",
+                    "sender is not defined",
+                ));
+            }
+            #[cfg(feature = "smtp-sender")]
+            EmailSender::Smtp(config) => {
+                if config.host.trim().is_empty() {
+                    errs.push(ValidationError::new(
+                        "email_sender.smtp.host",
+                        "host cannot be empty",
+                    ));
+                }
+                if config.port == 0 {
+                    errs.push(ValidationError::new(
+                        "email_sender.smtp.port",
+                        "port cannot be 0",
+                    ));
+                }
+                if config.login.trim().is_empty() {
+                    errs.push(ValidationError::new(
+                        "email_sender.smtp.login",
+                        "login cannot be empty",
+                    ));
+                }
+            }
+            EmailSender::Sendmail(config) => {
+                if config.cmd.trim().is_empty() {
+                    errs.push(ValidationError::new(
+                        "email_sender.sendmail.cmd",
+                        "command cannot be empty",
+                    ));
+                }
+            }
+        }
+
+        if let Some(dir) = self.sync_dir.as_ref().filter(|dir| !dir.is_absolute()) {
+            errs.push(ValidationError::new(
+                "sync_dir",
+                format!("{} is not absolute", dir.display()),
+            ));
+        } else if let Some(dir) = self.sync_dir.as_ref() {
+            match fs::metadata(dir) {
+                Ok(metadata) if !metadata.is_dir() => errs.push(ValidationError::new(
+                    "sync_dir",
+                    format!("{} is not a directory", dir.display()),
+                )),
+                Ok(metadata) if metadata.permissions().readonly() => errs.push(
+                    ValidationError::new("sync_dir", format!("{} is read-only", dir.display())),
+                ),
+                Ok(_) => (),
+                Err(err) => errs.push(ValidationError::new(
+                    "sync_dir",
+                    format!("cannot access {}: {err}", dir.display()),
+                )),
+            }
+        }
+
+        if errs.is_empty() {
+            Ok(())
+        } else {
+            Err(errs)
+        }
+    }
+
     /// Builds the full [RFC 2822] compliant user email address.
     ///
     /// [RFC 2822]: https://www.rfc-editor.org/rfc/rfc2822
@@ -157,7 +347,8 @@ impl AccountConfig {
 
     /// Gets the alias of the given folder if exists, otherwise
     /// returns the folder itself. Also tries to expand shell
-    /// variables.
+    /// variables. The result is canonical (see
+    /// [`folder::canonical_folder_name`]).
     pub fn folder_alias(&self, folder: &str) -> Result<String> {
         let lowercase_folder = folder.trim().to_lowercase();
 
@@ -176,7 +367,7 @@ impl AccountConfig {
             Ok(alias.to_string())
         })?;
 
-        Ok(alias)
+        Ok(folder::canonical_folder_name(&alias))
     }
 
     pub fn inbox_folder_alias(&self) -> Result<String> {
@@ -184,17 +375,29 @@ impl AccountConfig {
     }
 
     pub fn drafts_folder_alias(&self) -> Result<String> {
-        self.folder_alias(DEFAULT_DRAFTS_FOLDER)
+        self.folder_alias(
+            self.drafts_folder
+                .as_deref()
+                .unwrap_or(DEFAULT_DRAFTS_FOLDER),
+        )
     }
 
     pub fn sent_folder_alias(&self) -> Result<String> {
         self.folder_alias(DEFAULT_SENT_FOLDER)
     }
 
+    pub fn trash_folder_alias(&self) -> Result<String> {
+        self.folder_alias(DEFAULT_TRASH_FOLDER)
+    }
+
     pub fn email_listing_page_size(&self) -> usize {
         self.email_listing_page_size.unwrap_or(DEFAULT_PAGE_SIZE)
     }
 
+    pub fn email_sending_save_copy(&self) -> bool {
+        self.email_sending_save_copy.unwrap_or(true)
+    }
+
     pub fn email_reading_headers(&self) -> Vec<String> {
         self.email_reading_headers
             .as_ref()
@@ -246,6 +449,10 @@ impl AccountConfig {
             }
     }
 
+    pub fn sync_interval(&self) -> Option<Duration> {
+        self.sync_interval_secs.map(Duration::from_secs)
+    }
+
     pub fn sync_dir_exists(&self) -> bool {
         match self.sync_dir.as_ref() {
             Some(dir) => dir.is_dir(),
@@ -269,13 +476,310 @@ impl AccountConfig {
             }
         }
     }
+
+    /// Gets the conflict resolution strategy overriding the one of
+    /// the synchronization for the given folder, if any. Keys of
+    /// [`AccountConfig::folder_conflict_overrides`] match the folder
+    /// name, or resolve to it through the folder aliases, ignoring
+    /// the case. Keys matching the folder name take precedence.
+    pub fn folder_conflict_resolution(&self, folder: &str) -> Option<ConflictResolution> {
+        let find = |matches: &dyn Fn(&str) -> bool| {
+            self.folder_conflict_overrides
+                .iter()
+                .find(|(name, _)| matches(name))
+                .map(|(_, conflict_resolution)| *conflict_resolution)
+        };
+
+        find(&|name| name.eq_ignore_ascii_case(folder)).or_else(|| {
+            find(&|name| {
+                self.folder_alias(name)
+                    .map(|alias| alias.eq_ignore_ascii_case(folder))
+                    .unwrap_or_default()
+            })
+        })
+    }
+}
+
+/// Represents the builder of the [`AccountConfig`], which validates
+/// the configuration and applies the defaults in one place instead of
+/// relying on a struct literal.
+#[derive(Debug, Default, Clone)]
+pub struct AccountConfigBuilder {
+    config: AccountConfig,
+}
+
+impl AccountConfigBuilder {
+    pub fn new(name: impl ToString) -> Self {
+        Self::default().name(name)
+    }
+
+    /// Sets the name of the account.
+    pub fn name(mut self, name: impl ToString) -> Self {
+        self.config.name = name.to_string();
+        self
+    }
+
+    /// Sets the email address of the user.
+    pub fn email(mut self, email: impl ToString) -> Self {
+        self.config.email = email.to_string();
+        self
+    }
+
+    /// Sets the display name of the user.
+    pub fn display_name(mut self, display_name: impl ToString) -> Self {
+        self.config.display_name = Some(display_name.to_string());
+        self
+    }
+
+    /// Sets the email signature delimiter.
+    pub fn signature_delim(mut self, signature_delim: impl ToString) -> Self {
+        self.config.signature_delim = Some(signature_delim.to_string());
+        self
+    }
+
+    /// Sets the email signature, or the path of the file containing it.
+    pub fn signature(mut self, signature: impl ToString) -> Self {
+        self.config.signature = Some(signature.to_string());
+        self
+    }
+
+    /// Sets the downloads directory.
+    pub fn downloads_dir(mut self, downloads_dir: impl Into<PathBuf>) -> Self {
+        self.config.downloads_dir = Some(downloads_dir.into());
+        self
+    }
+
+    /// Sets the page size when listing folders.
+    pub fn folder_listing_page_size(mut self, folder_listing_page_size: usize) -> Self {
+        self.config.folder_listing_page_size = Some(folder_listing_page_size);
+        self
+    }
+
+    /// Sets the folder aliases, replacing the previous ones.
+    pub fn folder_aliases(mut self, folder_aliases: HashMap<String, String>) -> Self {
+        self.config.folder_aliases = folder_aliases;
+        self
+    }
+
+    /// Adds an alias of the given folder.
+    pub fn folder_alias(mut self, alias: impl ToString, folder: impl ToString) -> Self {
+        // aliases are looked up in lowercase
+        self.config
+            .folder_aliases
+            .insert(alias.to_string().trim().to_lowercase(), folder.to_string());
+        self
+    }
+
+    /// Sets the folder where drafts are saved.
+    pub fn drafts_folder(mut self, drafts_folder: impl ToString) -> Self {
+        self.config.drafts_folder = Some(drafts_folder.to_string());
+        self
+    }
+
+    /// Sets the page size when listing emails.
+    pub fn email_listing_page_size(mut self, email_listing_page_size: usize) -> Self {
+        self.config.email_listing_page_size = Some(email_listing_page_size);
+        self
+    }
+
+    /// Sets the headers visible when reading emails.
+    pub fn email_reading_headers(mut self, email_reading_headers: Vec<String>) -> Self {
+        self.config.email_reading_headers = Some(email_reading_headers);
+        self
+    }
+
+    /// Sets the text/plain format used when reading emails.
+    pub fn email_reading_format(mut self, email_reading_format: EmailTextPlainFormat) -> Self {
+        self.config.email_reading_format = email_reading_format;
+        self
+    }
+
+    /// Sets the command used to verify an email.
+    pub fn email_reading_verify_cmd(mut self, email_reading_verify_cmd: impl ToString) -> Self {
+        self.config.email_reading_verify_cmd = Some(email_reading_verify_cmd.to_string());
+        self
+    }
+
+    /// Sets the command used to decrypt an email.
+    pub fn email_reading_decrypt_cmd(mut self, email_reading_decrypt_cmd: impl ToString) -> Self {
+        self.config.email_reading_decrypt_cmd = Some(email_reading_decrypt_cmd.to_string());
+        self
+    }
+
+    /// Sets the command used to sign an email.
+    pub fn email_writing_sign_cmd(mut self, email_writing_sign_cmd: impl ToString) -> Self {
+        self.config.email_writing_sign_cmd = Some(email_writing_sign_cmd.to_string());
+        self
+    }
+
+    /// Sets the command used to encrypt an email.
+    pub fn email_writing_encrypt_cmd(mut self, email_writing_encrypt_cmd: impl ToString) -> Self {
+        self.config.email_writing_encrypt_cmd = Some(email_writing_encrypt_cmd.to_string());
+        self
+    }
+
+    /// Sets the headers visible when writing emails.
+    pub fn email_writing_headers(mut self, email_writing_headers: Vec<String>) -> Self {
+        self.config.email_writing_headers = Some(email_writing_headers);
+        self
+    }
+
+    /// Sets the email sender provider.
+    pub fn email_sender(mut self, email_sender: EmailSender) -> Self {
+        self.config.email_sender = email_sender;
+        self
+    }
+
+    /// Saves a copy of sent emails to the sent folder.
+    pub fn email_sending_save_copy(mut self, email_sending_save_copy: bool) -> Self {
+        self.config.email_sending_save_copy = Some(email_sending_save_copy);
+        self
+    }
+
+    /// Sets the email hooks.
+    pub fn email_hooks(mut self, email_hooks: EmailHooks) -> Self {
+        self.config.email_hooks = email_hooks;
+        self
+    }
+
+    /// Enables the synchronization of the account.
+    pub fn sync(mut self, sync: bool) -> Self {
+        self.config.sync = sync;
+        self
+    }
+
+    /// Sets the root directory of the synchronization Maildir.
+    pub fn sync_dir(mut self, sync_dir: impl Into<PathBuf>) -> Self {
+        self.config.sync_dir = Some(sync_dir.into());
+        self
+    }
+
+    /// Sets the minimum amount of seconds between two synchronizations.
+    pub fn sync_interval_secs(mut self, sync_interval_secs: u64) -> Self {
+        self.config.sync_interval_secs = Some(sync_interval_secs);
+        self
+    }
+
+    /// Removes the duplicate envelopes before synchronizing a folder.
+    pub fn sync_dedup(mut self, sync_dedup: bool) -> Self {
+        self.config.sync_dedup = sync_dedup;
+        self
+    }
+
+    /// Sets the conflict resolution overrides, replacing the previous
+    /// ones.
+    pub fn folder_conflict_overrides(
+        mut self,
+        folder_conflict_overrides: HashMap<String, ConflictResolution>,
+    ) -> Self {
+        self.config.folder_conflict_overrides = folder_conflict_overrides;
+        self
+    }
+
+    /// Overrides the conflict resolution strategy of the given
+    /// folder.
+    pub fn folder_conflict_override(
+        mut self,
+        folder: impl ToString,
+        conflict_resolution: ConflictResolution,
+    ) -> Self {
+        self.config
+            .folder_conflict_overrides
+            .insert(folder.to_string(), conflict_resolution);
+        self
+    }
+
+    /// Sets the synchronization hooks.
+    pub fn sync_hooks(mut self, sync_hooks: SyncHooks) -> Self {
+        self.config.sync_hooks = sync_hooks;
+        self
+    }
+
+    /// Validates the configuration built so far, see
+    /// [`AccountConfig::validate`].
+    pub fn validate(&self) -> result::Result<(), Vec<ValidationError>> {
+        self.config.validate()
+    }
+
+    /// Validates the configuration, then builds it. The unset options
+    /// having a default get it explicitly: the signature delimiter
+    /// defaults to [`DEFAULT_SIGNATURE_DELIM`], the downloads
+    /// directory to the temporary directory of the system, the page
+    /// sizes to [`DEFAULT_PAGE_SIZE`] and sent emails are saved.
+    pub fn build(self) -> result::Result<AccountConfig, Vec<ValidationError>> {
+        self.validate()?;
+
+        let mut config = self.config;
+        config
+            .signature_delim
+            .get_or_insert_with(|| DEFAULT_SIGNATURE_DELIM.to_owned());
+        config.downloads_dir.get_or_insert_with(env::temp_dir);
+        config
+            .folder_listing_page_size
+            .get_or_insert(DEFAULT_PAGE_SIZE);
+        config
+            .email_listing_page_size
+            .get_or_insert(DEFAULT_PAGE_SIZE);
+        config.email_sending_save_copy.get_or_insert(true);
+
+        Ok(config)
+    }
+}
+
+/// Replaces the `${VAR}` references of the given config value with
+/// the value of the matching environment variables, at resolution
+/// time. Only names made of ASCII letters, digits and underscores,
+/// not starting with a digit, are references: anything else, like
+/// `${VAR:-default}` or an unterminated `${`, is kept as is.
+pub fn interpolate_env(value: &str) -> Result<String> {
+    let mut interpolated = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(start) = rest.find("${") {
+        let len = match rest[start + 2..].find('}') {
+            Some(len) => len,
+            None => break,
+        };
+        let name = &rest[start + 2..start + 2 + len];
+        if is_env_var_name(name) {
+            let var = env::var(name).map_err(|_| Error::MissingEnvVarError(name.to_owned()))?;
+            interpolated.push_str(&rest[..start]);
+            interpolated.push_str(&var);
+        } else {
+            interpolated.push_str(&rest[..start + 3 + len]);
+        }
+        rest = &rest[start + 3 + len..];
+    }
+
+    interpolated.push_str(rest);
+    Ok(interpolated)
+}
+
+/// Returns `true` if the given config value is a lone `${VAR}`
+/// reference. Password commands made of a lone reference are not
+/// run: the variable holds the password itself. Other commands are
+/// left to the shell, which expands their variables.
+pub fn is_lone_env_ref(value: &str) -> bool {
+    value
+        .trim()
+        .strip_prefix("${")
+        .and_then(|value| value.strip_suffix('}'))
+        .map(is_env_var_name)
+        .unwrap_or_default()
+}
+
+fn is_env_var_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
 }
 
 #[cfg(test)]
 mod account_config {
-    use std::path::PathBuf;
+    use std::{collections::HashMap, path::PathBuf};
+    use tempfile::tempdir;
 
-    use crate::AccountConfig;
+    use crate::{AccountConfig, AccountConfigBuilder, EmailSender, SendmailConfig};
 
     #[test]
     fn unique_download_file_path() {
@@ -314,4 +818,168 @@ mod account_config {
             Ok(path) if path == PathBuf::from("downloads/file.ext_5.ext2")
         ));
     }
+
+    #[test]
+    fn validate() {
+        let sync_dir = tempdir().unwrap();
+
+        let config = AccountConfig {
+            name: "account".into(),
+            email: "alice@localhost".into(),
+            email_sender: EmailSender::Sendmail(SendmailConfig {
+                cmd: "msmtp".into(),
+                ..SendmailConfig::default()
+            }),
+            sync_dir: Some(sync_dir.path().to_owned()),
+            ..AccountConfig::default()
+        };
+        assert_eq!(Ok(()), config.validate());
+
+        let config = AccountConfig {
+            name: " ".into(),
+            email: "alice".into(),
+            email_sender: EmailSender::None,
+            sync_dir: Some(sync_dir.path().join("missing")),
+            ..AccountConfig::default()
+        };
+        let fields: Vec<_> = config
+            .validate()
+            .unwrap_err()
+            .into_iter()
+            .map(|err| err.field)
+            .collect();
+        assert_eq!(vec!["name", "email", "email_sender", "sync_dir"], fields);
+    }
+
+    #[test]
+    fn folder_alias() {
+        let config = AccountConfig {
+            folder_aliases: HashMap::from_iter([
+                ("inbox".into(), "Inbox".into()),
+                ("archive".into(), "Archives".into()),
+            ]),
+            ..AccountConfig::default()
+        };
+
+        // the inbox is canonical whatever its alias casing
+        assert_eq!("INBOX", config.folder_alias("inbox").unwrap());
+        assert_eq!("INBOX", config.inbox_folder_alias().unwrap());
+        assert_eq!("Archives", config.folder_alias("Archive").unwrap());
+        assert_eq!("Sent", config.folder_alias("sent").unwrap());
+        assert_eq!("Other", config.folder_alias("Other").unwrap());
+    }
+
+    #[test]
+    fn builder() {
+        let sync_dir = tempdir().unwrap();
+
+        let config = AccountConfigBuilder::new("account")
+            .email("alice@localhost")
+            .email_sender(EmailSender::Sendmail(SendmailConfig {
+                cmd: "msmtp".into(),
+                ..SendmailConfig::default()
+            }))
+            .folder_alias("Sent", "Sent Items")
+            .sync(true)
+            .sync_dir(sync_dir.path())
+            .build()
+            .unwrap();
+
+        assert_eq!("account", config.name);
+        assert_eq!(
+            Some("Sent Items"),
+            config.folder_aliases.get("sent").map(String::as_str)
+        );
+        assert_eq!(
+            Some(super::DEFAULT_SIGNATURE_DELIM),
+            config.signature_delim.as_deref()
+        );
+        assert_eq!(Some(std::env::temp_dir()), config.downloads_dir);
+        assert_eq!(
+            Some(super::DEFAULT_PAGE_SIZE),
+            config.email_listing_page_size
+        );
+        assert_eq!(Some(true), config.email_sending_save_copy);
+
+        let errs = AccountConfigBuilder::new("")
+            .email("alice@localhost")
+            .display_name("Alice\nBcc: eve@localhost")
+            .folder_alias("sent", "Sent")
+            .folder_alias("outbox", "sent")
+            .email_sender(EmailSender::Sendmail(SendmailConfig {
+                cmd: "msmtp".into(),
+                ..SendmailConfig::default()
+            }))
+            .sync_dir("relative/dir")
+            .build()
+            .unwrap_err();
+        let fields: Vec<_> = errs.iter().map(|err| err.field.as_str()).collect();
+        assert_eq!(
+            vec!["name", "display_name", "folder_aliases", "sync_dir"],
+            fields
+        );
+        assert_eq!(
+            "aliases outbox and sent both target folder Sent",
+            errs[2].message
+        );
+    }
+
+    #[test]
+    fn interpolate_env() {
+        std::env::set_var("HIMALAYA_TEST_LOGIN", "alice");
+        std::env::set_var("HIMALAYA_TEST_HOST", "localhost");
+
+        assert_eq!(
+            "pass show alice@localhost",
+            super::interpolate_env("pass show ${HIMALAYA_TEST_LOGIN}@${HIMALAYA_TEST_HOST}")
+                .unwrap()
+        );
+        assert_eq!(
+            "echo $HOME ${",
+            super::interpolate_env("echo $HOME ${").unwrap()
+        );
+        assert_eq!(
+            "alice ${HIMALAYA_TEST_MISSING:-bob} ${1}",
+            super::interpolate_env("${HIMALAYA_TEST_LOGIN} ${HIMALAYA_TEST_MISSING:-bob} ${1}")
+                .unwrap()
+        );
+        assert!(matches!(
+            super::interpolate_env("${HIMALAYA_TEST_MISSING}"),
+            Err(super::Error::MissingEnvVarError(name)) if name == "HIMALAYA_TEST_MISSING"
+        ));
+
+        assert!(super::is_lone_env_ref(" ${HIMALAYA_TEST_LOGIN} "));
+        assert!(!super::is_lone_env_ref("echo ${HIMALAYA_TEST_LOGIN}"));
+        assert!(!super::is_lone_env_ref("${A}${B}"));
+        assert!(!super::is_lone_env_ref("${A:-b}"));
+    }
+
+    #[test]
+    fn folder_conflict_resolution() {
+        use crate::envelope::sync::ConflictResolution;
+
+        let config = AccountConfig {
+            folder_aliases: HashMap::from_iter([("archive".into(), "Archives".into())]),
+            folder_conflict_overrides: HashMap::from_iter([
+                ("inbox".into(), ConflictResolution::PreferRemote),
+                ("sent".into(), ConflictResolution::PreferLocal),
+                ("ARCHIVE".into(), ConflictResolution::PreferRemote),
+            ]),
+            ..AccountConfig::default()
+        };
+
+        assert_eq!(
+            Some(ConflictResolution::PreferRemote),
+            config.folder_conflict_resolution("INBOX")
+        );
+        assert_eq!(
+            Some(ConflictResolution::PreferLocal),
+            config.folder_conflict_resolution("Sent")
+        );
+        assert_eq!(
+            Some(ConflictResolution::PreferRemote),
+            config.folder_conflict_resolution("archives")
+        );
+        assert_eq!(None, config.folder_conflict_resolution("Drafts"));
+    }
 }
@@ -1,6 +1,7 @@
 pub mod config;
 
 pub use config::{
-    AccountConfig, DEFAULT_DRAFTS_FOLDER, DEFAULT_INBOX_FOLDER, DEFAULT_PAGE_SIZE,
-    DEFAULT_SENT_FOLDER, DEFAULT_SIGNATURE_DELIM,
+    AccountConfig, AccountConfigBuilder, SyncHooks, ValidationError, DEFAULT_DRAFTS_FOLDER,
+    DEFAULT_INBOX_FOLDER, DEFAULT_PAGE_SIZE, DEFAULT_SENT_FOLDER, DEFAULT_SIGNATURE_DELIM,
+    DEFAULT_TRASH_FOLDER,
 };
@@ -1,6 +1,171 @@
+use log::debug;
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+
+use crate::email::{Error, Result};
+
+/// Represents the file name used for attachments without a usable
+/// file name.
+const DEFAULT_FILENAME: &str = "attachment";
+
 #[derive(Clone, Debug, Default, Eq, PartialEq)]
 pub struct Attachment {
     pub filename: Option<String>,
     pub mime: String,
     pub body: Vec<u8>,
 }
+
+impl Attachment {
+    /// Saves the attachment in the given directory, and returns the
+    /// path of the written file.
+    ///
+    /// The file name comes from [`sanitize_filename`], so that a
+    /// malicious file name cannot write outside of the directory.
+    /// Existing files are never overwritten: ` (1)`, ` (2)`… are
+    /// appended to the file stem until a free name is found. The body
+    /// is already decoded from its transfer encoding (see
+    /// [`crate::Email::attachments`]). On Unix, the file is only
+    /// readable by its owner.
+    pub fn save_to(&self, dir: &Path) -> Result<PathBuf> {
+        let filename = sanitize_filename(self.filename.as_deref().unwrap_or_default());
+        let original_path = dir.join(&filename);
+        let mut path = original_path.clone();
+        let mut count = 0;
+
+        // the file is created with create_new, so that a file created
+        // meanwhile by someone else is not overwritten either
+        let mut file = loop {
+            let mut opts = OpenOptions::new();
+            opts.write(true).create_new(true);
+            #[cfg(unix)]
+            opts.mode(0o600);
+
+            match opts.open(&path) {
+                Ok(file) => break file,
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                    count += 1;
+                    path = with_counter(&original_path, count);
+                }
+                Err(err) => return Err(Error::SaveAttachmentError(err, path)),
+            }
+        };
+
+        debug!("saving attachment {filename} to {}", path.display());
+        file.write_all(&self.body)
+            .map_err(|err| Error::SaveAttachmentError(err, path.clone()))?;
+
+        Ok(path)
+    }
+}
+
+/// Returns a file name safe to join to a directory: path separators,
+/// drive separators and control characters are replaced by
+/// underscores, and leading and trailing dots and spaces are trimmed,
+/// which prevents both parent directory references and hidden files.
+/// Falls back to [`DEFAULT_FILENAME`] when nothing is left.
+pub fn sanitize_filename(filename: &str) -> String {
+    let filename: String = filename
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+
+    let filename = filename.trim_matches(|c| c == '.' || c == ' ');
+
+    if filename.is_empty() {
+        DEFAULT_FILENAME.to_owned()
+    } else {
+        filename.to_owned()
+    }
+}
+
+/// Returns the given path with ` (count)` appended to its file stem.
+fn with_counter(path: &Path, count: usize) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_else(|| DEFAULT_FILENAME.to_owned());
+    let filename = match path.extension() {
+        Some(ext) => format!("{stem} ({count}).{}", ext.to_string_lossy()),
+        None => format!("{stem} ({count})"),
+    };
+    path.with_file_name(filename)
+}
+
+/// Creates the given directory if needed, then saves the given
+/// attachments in it (see [`Attachment::save_to`]). Returns the paths
+/// of the written files, in the order of the attachments.
+pub(crate) fn save_all(attachments: &[Attachment], dir: &Path) -> Result<Vec<PathBuf>> {
+    fs::create_dir_all(dir).map_err(|err| Error::CreateAttachmentsDirError(err, dir.into()))?;
+    attachments
+        .iter()
+        .map(|attachment| attachment.save_to(dir))
+        .collect()
+}
+
+#[cfg(test)]
+mod attachment {
+    use std::fs;
+
+    use super::{sanitize_filename, Attachment};
+
+    #[test]
+    fn sanitize() {
+        assert_eq!("report.pdf", sanitize_filename("report.pdf"));
+        assert_eq!("_.._evil", sanitize_filename("../../evil"));
+        assert_eq!("C__evil.exe", sanitize_filename("C:\\evil.exe"));
+        assert_eq!("a_b", sanitize_filename("a\nb"));
+        assert_eq!("bashrc", sanitize_filename(".bashrc"));
+        assert_eq!("attachment", sanitize_filename(".."));
+        assert_eq!("attachment", sanitize_filename(""));
+    }
+
+    #[test]
+    fn save_to() {
+        let dir = tempfile::tempdir().unwrap();
+        let attachment = |filename: &str, body: &str| Attachment {
+            filename: Some(filename.into()),
+            mime: "text/plain".into(),
+            body: body.as_bytes().to_vec(),
+        };
+
+        let path = attachment("notes.txt", "first")
+            .save_to(dir.path())
+            .unwrap();
+        assert_eq!(dir.path().join("notes.txt"), path);
+
+        // existing files are kept
+        let path = attachment("notes.txt", "second")
+            .save_to(dir.path())
+            .unwrap();
+        assert_eq!(dir.path().join("notes (1).txt"), path);
+        let path = attachment("notes.txt", "third")
+            .save_to(dir.path())
+            .unwrap();
+        assert_eq!(dir.path().join("notes (2).txt"), path);
+        assert_eq!(
+            "first",
+            fs::read_to_string(dir.path().join("notes.txt")).unwrap()
+        );
+
+        // malicious file names stay in the directory
+        let path = attachment("../evil", "evil").save_to(dir.path()).unwrap();
+        assert_eq!(dir.path().join("_evil"), path);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(0o600, mode & 0o777);
+        }
+    }
+}
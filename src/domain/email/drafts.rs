@@ -0,0 +1,101 @@
+//! Drafts module.
+//!
+//! This module contains helpers to save work-in-progress emails to the
+//! drafts folder of an account, to list them and to resume them.
+//! Successive revisions of the same draft share the same
+//! [`DRAFT_ID_HEADER`], so that saving a revision replaces the
+//! previous one instead of piling up copies.
+
+use log::debug;
+use mailparse::MailHeaderMap;
+use mime_msg_builder::tpl::Tpl;
+use std::borrow::Cow;
+use uuid::Uuid;
+
+use crate::{
+    email::{Error, Result},
+    AccountConfig, Backend, Email, Envelopes,
+};
+
+/// Represents the header identifying the revisions of a draft.
+pub const DRAFT_ID_HEADER: &str = "X-Draft-Id";
+
+/// Returns the draft id of the given raw email, if any.
+pub fn draft_id(email: &[u8]) -> Option<String> {
+    let (headers, _) = mailparse::parse_headers(email).ok()?;
+    headers
+        .get_first_value(DRAFT_ID_HEADER)
+        .map(|id| id.trim().to_owned())
+        .filter(|id| !id.is_empty())
+}
+
+/// Prepends a newly generated draft id to the raw email, unless it
+/// already has one. Returns the email with its draft id.
+fn ensure_draft_id(email: &[u8]) -> (Cow<[u8]>, String) {
+    match draft_id(email) {
+        Some(id) => (Cow::Borrowed(email), id),
+        None => {
+            let id = Uuid::new_v4().to_string();
+            let mut with_draft_id = format!("{DRAFT_ID_HEADER}: {id}\r\n").into_bytes();
+            with_draft_id.extend_from_slice(email);
+            (Cow::Owned(with_draft_id), id)
+        }
+    }
+}
+
+/// Saves the given raw email, typically compiled from a template, to
+/// the drafts folder of the account with the draft flag (see
+/// [`Email::save_draft`]), and returns its id.
+///
+/// If previous revisions of the draft exist, sharing the same
+/// [`DRAFT_ID_HEADER`], they are deleted once the new revision is
+/// saved. A draft id is generated for emails without one. On every
+/// backend the new revision is added before the previous ones are
+/// deleted, so that a failure never loses the draft.
+pub fn save_draft(backend: &dyn Backend, config: &AccountConfig, email: &[u8]) -> Result<String> {
+    let folder = config.drafts_folder_alias()?;
+    let (email, draft_id) = ensure_draft_id(email);
+
+    // backends search headers natively when they can, instead of
+    // fetching the headers of every draft
+    let revisions = backend
+        .search_ids_by_header(&folder, DRAFT_ID_HEADER, &draft_id)
+        .map_err(|err| Error::ListDraftsError(Box::new(err), folder.clone()))?;
+
+    let id = Email::from(email.as_ref()).save_draft(&folder, backend)?;
+
+    if !revisions.is_empty() {
+        debug!("deleting previous revisions of draft {draft_id}: {revisions:?}");
+        backend
+            .delete_emails(&folder, revisions.iter().map(String::as_str).collect())
+            .map_err(|err| Error::DeleteDraftError(Box::new(err), folder.clone()))?;
+    }
+
+    Ok(id)
+}
+
+/// Lists the drafts of the account, from its drafts folder.
+pub fn list_drafts(backend: &dyn Backend, config: &AccountConfig) -> Result<Envelopes> {
+    let folder = config.drafts_folder_alias()?;
+    backend
+        .list_envelopes(&folder, 0, 0)
+        .map_err(|err| Error::ListDraftsError(Box::new(err), folder))
+}
+
+/// Fetches the draft matching the given id and builds a template
+/// containing all its headers, including its draft id, and its text
+/// parts (see [`Email::from_draft`]), so that the composition can be
+/// resumed then saved again as a new revision.
+pub fn resume_draft(backend: &dyn Backend, config: &AccountConfig, id: &str) -> Result<Tpl> {
+    let folder = config.drafts_folder_alias()?;
+    Ok(Email::from_draft(config, id, &folder, backend)?.build())
+}
+
+/// Deletes the draft matching the given id, typically once the final
+/// version of the email has been sent.
+pub fn delete_draft(backend: &dyn Backend, config: &AccountConfig, id: &str) -> Result<()> {
+    let folder = config.drafts_folder_alias()?;
+    backend
+        .delete_emails(&folder, vec![id])
+        .map_err(|err| Error::DeleteDraftError(Box::new(err), folder))
+}
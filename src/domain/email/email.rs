@@ -9,14 +9,24 @@ use mailparse::{
 };
 use mime_msg_builder::TplBuilder;
 use ouroboros::self_referencing;
-use std::{fmt::Debug, io, path::PathBuf, result};
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    io,
+    path::{Path, PathBuf},
+    result,
+};
 use thiserror::Error;
 use tree_magic;
 
 #[cfg(feature = "maildir-backend")]
 use maildir::{MailEntry, MailEntryError};
 
-use crate::{account, process, AccountConfig, Attachment};
+use crate::{
+    account, backend,
+    email::{attachment, split_raw_email},
+    process, AccountConfig, Attachment, Backend, Flag, Flags, ParsedEmail, Part,
+};
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -36,6 +46,24 @@ pub enum Error {
     ParseEmailAddressError(#[from] AddressError),
     #[error("cannot delete local draft at {1}")]
     DeleteLocalDraftError(#[source] io::Error, PathBuf),
+    #[error("cannot save draft to folder {1}")]
+    SaveDraftError(#[source] Box<backend::Error>, String),
+    #[error("cannot get draft {1} from folder {2}")]
+    GetDraftError(#[source] Box<backend::Error>, String, String),
+    #[error("cannot find draft {0} in folder {1}")]
+    FindDraftError(String, String),
+    #[error("cannot list drafts from folder {1}")]
+    ListDraftsError(#[source] Box<backend::Error>, String),
+    #[error("cannot delete draft from folder {1}")]
+    DeleteDraftError(#[source] Box<backend::Error>, String),
+    #[error("cannot create attachments directory {1}")]
+    CreateAttachmentsDirError(#[source] io::Error, PathBuf),
+    #[error("cannot save attachment to {1}")]
+    SaveAttachmentError(#[source] io::Error, PathBuf),
+    #[error("cannot add header {0}: invalid name")]
+    InvalidHeaderName(String),
+    #[error("cannot add header {0}: invalid value {1:?}")]
+    InvalidHeaderValue(String, String),
 
     #[cfg(feature = "imap-backend")]
     #[error("cannot parse email from imap fetches: empty fetches")]
@@ -120,10 +148,85 @@ impl Email<'_> {
             .map_err(|err| Error::GetParsedEmailError(err.to_string()))
     }
 
+    /// Returns the Message-ID the email replies to, taken from the
+    /// In-Reply-To header.
+    pub fn in_reply_to(&self) -> Result<Option<String>> {
+        let in_reply_to = self
+            .parsed()?
+            .headers
+            .get_first_value("In-Reply-To")
+            .map(|id| id.trim().to_owned())
+            .filter(|id| !id.is_empty());
+        Ok(in_reply_to)
+    }
+
+    /// Returns all the headers of the email, indexed by their
+    /// lowercased name. The values of headers appearing several
+    /// times, like `Received`, are collected in order. Values are
+    /// decoded (RFC 2047).
+    pub fn headers(&self) -> Result<HashMap<String, Vec<String>>> {
+        let (parsed_headers, _) =
+            mailparse::parse_headers(self.raw_headers()?).map_err(Error::ParseEmailError)?;
+
+        let mut headers: HashMap<String, Vec<String>> = HashMap::new();
+        for header in parsed_headers {
+            headers
+                .entry(header.get_key().to_lowercase())
+                .or_default()
+                .push(header.get_value());
+        }
+
+        Ok(headers)
+    }
+
+    /// Returns the first value of the given header. The name is case
+    /// insensitive.
+    pub fn header(&self, name: &str) -> Result<Option<String>> {
+        let (headers, _) =
+            mailparse::parse_headers(self.raw_headers()?).map_err(Error::ParseEmailError)?;
+        Ok(headers.get_first_value(name))
+    }
+
+    /// Parses the email into a high-level view containing its decoded
+    /// headers, its text bodies and its attachments (see
+    /// [`ParsedEmail`]). The text/plain body is formatted according
+    /// to the account email reading format.
+    pub fn parse(&self, config: &AccountConfig) -> Result<ParsedEmail> {
+        Ok(ParsedEmail::from_parsed_mail(
+            self.parsed()?,
+            &config.email_reading_format,
+        ))
+    }
+
     pub fn raw(&self) -> Result<&[u8]> {
         self.parsed().map(|parsed| parsed.raw_bytes)
     }
 
+    /// Returns the raw header block of the email, split from the body
+    /// on the first blank line (see [`split_raw_email`]).
+    pub fn raw_headers(&self) -> Result<&[u8]> {
+        Ok(split_raw_email(self.raw()?).0)
+    }
+
+    /// Returns the raw body of the email, without any decoding, split
+    /// from the headers on the first blank line (see
+    /// [`split_raw_email`]).
+    pub fn raw_body(&self) -> Result<&[u8]> {
+        Ok(split_raw_email(self.raw()?).1)
+    }
+
+    /// Returns the leaf parts of the email, in order. The content of
+    /// a part is decoded on demand with [`Part::decoded_bytes`].
+    pub fn parts(&self) -> Result<Vec<Part>> {
+        let parts = self
+            .parsed()?
+            .parts()
+            .filter(|part| part.subparts.is_empty())
+            .map(Part::new)
+            .collect();
+        Ok(parts)
+    }
+
     pub fn attachments(&self) -> Result<Vec<Attachment>> {
         let attachments = self.parsed()?.parts().filter_map(|part| {
             let cdisp = part.get_content_disposition();
@@ -189,6 +292,13 @@ impl Email<'_> {
         Ok(attachments.collect())
     }
 
+    /// Saves the attachments of the email in the given directory,
+    /// created if needed, and returns the paths of the written files
+    /// (see [`Attachment::save_to`]).
+    pub fn save_attachments(&self, dir: &Path) -> Result<Vec<PathBuf>> {
+        attachment::save_all(&self.attachments()?, dir)
+    }
+
     fn tpl_builder_from_parsed(config: &AccountConfig, parsed: &ParsedMail) -> Result<TplBuilder> {
         Self::tpl_builder_from_parsed_rec(config, TplBuilder::default(), parsed, true)
     }
@@ -494,6 +604,38 @@ impl Email<'_> {
 
         Ok(tpl)
     }
+
+    /// Saves the email to the given folder with the draft flag, and
+    /// returns its id. See [`AccountConfig::drafts_folder_alias`] for
+    /// the default drafts folder.
+    pub fn save_draft(&self, folder: &str, backend: &dyn Backend) -> Result<String> {
+        let flags = Flags::from_iter([Flag::Draft]);
+        let id = backend
+            .add_email(folder, self.raw()?, &flags)
+            .map_err(|err| Error::SaveDraftError(Box::new(err), folder.to_owned()))?;
+
+        Ok(id)
+    }
+
+    /// Fetches the draft matching the given id and builds a template
+    /// builder containing all its headers and its text parts, so that
+    /// the composition can be resumed. Encrypted parts are decrypted
+    /// with the command of the given account config.
+    pub fn from_draft(
+        config: &AccountConfig,
+        id: &str,
+        folder: &str,
+        backend: &dyn Backend,
+    ) -> Result<TplBuilder> {
+        let emails = backend
+            .preview_emails(folder, vec![id])
+            .map_err(|err| Error::GetDraftError(Box::new(err), id.to_owned(), folder.to_owned()))?;
+        let draft = emails
+            .first()
+            .ok_or_else(|| Error::FindDraftError(id.to_owned(), folder.to_owned()))?;
+
+        Self::tpl_builder_from_parsed(config, draft.parsed()?)
+    }
 }
 
 impl<'a> From<Vec<u8>> for Email<'a> {
@@ -640,6 +782,31 @@ mod email {
 
     use crate::{AccountConfig, Email};
 
+    #[test]
+    fn headers() {
+        let email = Email::from(concat_line!(
+            "Received: from a.localhost",
+            "Received: from b.localhost",
+            "From: alice@localhost",
+            "Subject: =?utf-8?q?h=C3=A9llo?=",
+            "List-Id: <list.localhost>",
+            "",
+            "hello",
+        ));
+
+        let headers = email.headers().unwrap();
+        assert_eq!(
+            &vec!["from a.localhost", "from b.localhost"],
+            headers.get("received").unwrap()
+        );
+        assert_eq!(Some("héllo".to_owned()), email.header("subject").unwrap());
+        assert_eq!(
+            Some("<list.localhost>".to_owned()),
+            email.header("LIST-ID").unwrap()
+        );
+        assert_eq!(None, email.header("Cc").unwrap());
+    }
+
     #[test]
     fn new_tpl_builder() {
         let config = AccountConfig {
@@ -4,10 +4,15 @@
 
 pub mod attachment;
 pub mod config;
+pub mod drafts;
 pub mod email;
+pub mod parsed;
+pub mod tpl;
 pub mod utils;
 
 pub use attachment::Attachment;
 pub use config::{EmailHooks, EmailSender, EmailTextPlainFormat};
 pub use email::*;
+pub use parsed::{AttachmentPart, ParsedEmail, Part};
+pub use tpl::TplBuilderExt;
 pub use utils::*;
@@ -0,0 +1,526 @@
+//! Parsed email module.
+//!
+//! This module contains a high-level view of an email, gathering its
+//! decoded headers, its text bodies and its attachments in one pass
+//! (see [`crate::Email::parse`]).
+
+use log::{trace, warn};
+use mailparse::{DispositionType, MailHeaderMap, ParsedMail};
+
+use crate::{
+    email::{split_raw_email, EmailTextPlainFormat, Error, Result},
+    Attachment,
+};
+
+/// Represents the high-level view of an email.
+#[derive(Debug)]
+pub struct ParsedEmail<'a> {
+    /// Represents the headers in their original order. Values are
+    /// decoded, including RFC 2047 encoded words.
+    pub headers: Vec<(String, String)>,
+    /// Represents the first text/plain part, if any, formatted
+    /// according to the [`EmailTextPlainFormat`].
+    pub text_body: Option<String>,
+    /// Represents the first text/html part, if any.
+    pub html_body: Option<String>,
+    /// Represents the attachments, whose content is only decoded on
+    /// demand.
+    pub attachments: Vec<AttachmentPart<'a>>,
+}
+
+impl<'a> ParsedEmail<'a> {
+    /// Builds the view from the given parsed email. The text/plain
+    /// body is formatted according to the given format (see
+    /// [`crate::AccountConfig::email_reading_format`]).
+    ///
+    /// Malformed MIME structures degrade to a best-effort text body:
+    /// a multipart without boundary is read as a single text part,
+    /// and text parts with an unknown charset are decoded lossily.
+    pub fn from_parsed_mail(parsed: &'a ParsedMail<'a>, format: &EmailTextPlainFormat) -> Self {
+        let mut email = Self {
+            headers: parsed
+                .headers
+                .iter()
+                .map(|header| (header.get_key(), header.get_value()))
+                .collect(),
+            text_body: None,
+            html_body: None,
+            attachments: Vec::new(),
+        };
+
+        for part in parsed.parts().filter(|part| part.subparts.is_empty()) {
+            if let Some(attachment) = AttachmentPart::from_part(part) {
+                email.attachments.push(attachment);
+                continue;
+            }
+
+            match part.ctype.mimetype.as_str() {
+                "text/plain" if email.text_body.is_none() => {
+                    email.text_body = Some(format_text_plain(part, body_text(part), format));
+                }
+                "text/html" if email.html_body.is_none() => {
+                    email.html_body = Some(body_text(part));
+                }
+                // a multipart without subparts means that the
+                // boundary is missing or wrong
+                mime if mime.starts_with("multipart/") && email.text_body.is_none() => {
+                    warn!("cannot find parts of {mime} email, reading it as text");
+                    email.text_body = Some(format_text_plain(part, body_text(part), format));
+                }
+                mime => trace!("skipping {mime} part"),
+            }
+        }
+
+        email
+    }
+
+    /// Returns the value of the first header matching the given key,
+    /// case-insensitively.
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn subject(&self) -> Option<&str> {
+        self.header("Subject")
+    }
+
+    pub fn from(&self) -> Option<&str> {
+        self.header("From")
+    }
+}
+
+/// Represents an attachment of a [`ParsedEmail`]. The content is not
+/// decoded until [`AttachmentPart::body`] is called.
+#[derive(Debug)]
+pub struct AttachmentPart<'a> {
+    pub filename: Option<String>,
+    /// Represents the MIME type declared by the content type.
+    pub mime: String,
+    /// Represents the size in bytes of the encoded part, headers
+    /// included.
+    pub size: usize,
+    part: &'a ParsedMail<'a>,
+}
+
+impl<'a> AttachmentPart<'a> {
+    /// Builds an attachment from the given part, if its disposition
+    /// is attachment, or inline with a filename.
+    fn from_part(part: &'a ParsedMail<'a>) -> Option<Self> {
+        if !is_attachment(part) {
+            return None;
+        }
+
+        Some(Self {
+            filename: filename(part),
+            mime: part.ctype.mimetype.clone(),
+            size: part.raw_bytes.len(),
+            part,
+        })
+    }
+
+    /// Decodes the content of the attachment.
+    pub fn body(&self) -> Result<Vec<u8>> {
+        self.part.get_body_raw().map_err(Error::ParseEmailBodyError)
+    }
+
+    /// Decodes the content of the attachment and converts it to an
+    /// [`Attachment`].
+    pub fn load(&self) -> Result<Attachment> {
+        Ok(Attachment {
+            filename: self.filename.clone(),
+            mime: self.mime.clone(),
+            body: self.body()?,
+        })
+    }
+}
+
+/// Represents a leaf part of an email with its MIME metadata, for
+/// example to view its source or to pick its text bodies (see
+/// [`crate::Email::parts`]).
+#[derive(Debug)]
+pub struct Part<'a> {
+    /// Represents the MIME type declared by the content type.
+    pub mime: String,
+    /// Represents the charset declared by the content type, if any.
+    pub charset: Option<String>,
+    /// Represents the disposition declared by the content
+    /// disposition, like `inline` or `attachment`, if any.
+    pub disposition: Option<String>,
+    /// Represents the filename declared by the content disposition,
+    /// or by the content type as a fallback.
+    pub filename: Option<String>,
+    part: &'a ParsedMail<'a>,
+}
+
+impl<'a> Part<'a> {
+    pub(crate) fn new(part: &'a ParsedMail<'a>) -> Self {
+        let disposition = part
+            .headers
+            .get_first_value("Content-Disposition")
+            .map(|_| match part.get_content_disposition().disposition {
+                DispositionType::Inline => String::from("inline"),
+                DispositionType::Attachment => String::from("attachment"),
+                DispositionType::FormData => String::from("form-data"),
+                DispositionType::Extension(disposition) => disposition.to_lowercase(),
+            });
+
+        Self {
+            mime: part.ctype.mimetype.clone(),
+            charset: part.ctype.params.get("charset").cloned(),
+            disposition,
+            filename: filename(part),
+            part,
+        }
+    }
+
+    /// Returns `true` if the part is a text/plain part which is not an
+    /// attachment.
+    pub fn is_text_plain(&self) -> bool {
+        self.mime == "text/plain" && !self.is_attachment()
+    }
+
+    /// Returns `true` if the part is a text/html part which is not an
+    /// attachment.
+    pub fn is_text_html(&self) -> bool {
+        self.mime == "text/html" && !self.is_attachment()
+    }
+
+    /// Returns `true` if the part is an attachment, in other words if
+    /// its disposition is attachment, or inline with a filename (see
+    /// [`ParsedEmail::attachments`]).
+    pub fn is_attachment(&self) -> bool {
+        is_attachment(self.part)
+    }
+
+    /// Returns the raw part, headers included.
+    pub fn raw(&self) -> &[u8] {
+        self.part.raw_bytes
+    }
+
+    /// Returns the raw header block of the part.
+    pub fn raw_headers(&self) -> &[u8] {
+        split_raw_email(self.part.raw_bytes).0
+    }
+
+    /// Decodes the content of the part from its transfer encoding
+    /// (base64 or quoted-printable). The content of text parts is
+    /// also converted from its charset to UTF-8.
+    ///
+    /// Decoding never fails: unknown charsets are read as latin-1,
+    /// invalid text is decoded lossily and a content that cannot be
+    /// decoded from its transfer encoding is returned as is.
+    pub fn decoded_bytes(&self) -> Vec<u8> {
+        let decoded = || {
+            self.part.get_body_raw().unwrap_or_else(|err| {
+                warn!("cannot decode {} part, keeping it as is: {err}", self.mime);
+                split_raw_email(self.part.raw_bytes).1.to_vec()
+            })
+        };
+
+        if !self.mime.starts_with("text/") {
+            return decoded();
+        }
+
+        match self.part.get_body() {
+            Ok(text) => text.into_bytes(),
+            Err(err) => {
+                warn!(
+                    "cannot decode {} part, decoding it lossily: {err}",
+                    self.mime
+                );
+                String::from_utf8_lossy(&decoded())
+                    .into_owned()
+                    .into_bytes()
+            }
+        }
+    }
+}
+
+/// Returns the filename of the given part, from its content
+/// disposition or from its content type as a fallback.
+fn filename(part: &ParsedMail) -> Option<String> {
+    part.get_content_disposition()
+        .params
+        .get("filename")
+        .or_else(|| part.ctype.params.get("name"))
+        .cloned()
+}
+
+/// Returns `true` if the disposition of the given part is attachment,
+/// or inline with a filename.
+fn is_attachment(part: &ParsedMail) -> bool {
+    match part.get_content_disposition().disposition {
+        DispositionType::Attachment => true,
+        DispositionType::Inline => filename(part).is_some(),
+        _ => false,
+    }
+}
+
+/// Decodes the body of the given text part. Falls back to a lossy
+/// UTF-8 conversion when the charset cannot be decoded.
+fn body_text(part: &ParsedMail) -> String {
+    part.get_body().unwrap_or_else(|err| {
+        warn!("cannot decode text part, decoding it lossily: {err}");
+        let body = part
+            .get_body_raw()
+            .unwrap_or_else(|_| part.raw_bytes.to_vec());
+        String::from_utf8_lossy(&body).into_owned()
+    })
+}
+
+/// Formats the given text/plain body according to the given format.
+///
+/// Bodies sent with `format=flowed` are unwrapped first (see
+/// [RFC3676]), unless the format is [`EmailTextPlainFormat::Auto`]
+/// which keeps the body as is.
+///
+/// [RFC3676]: https://www.rfc-editor.org/rfc/rfc3676
+fn format_text_plain(part: &ParsedMail, body: String, format: &EmailTextPlainFormat) -> String {
+    let unflow = |body: String| {
+        let params = &part.ctype.params;
+        let is_flowed = matches!(params.get("format"), Some(f) if f.eq_ignore_ascii_case("flowed"));
+        let del_sp = matches!(params.get("delsp"), Some(d) if d.eq_ignore_ascii_case("yes"));
+        if is_flowed {
+            unflow_text(&body, del_sp)
+        } else {
+            body
+        }
+    };
+
+    match format {
+        EmailTextPlainFormat::Auto => body,
+        EmailTextPlainFormat::Flowed => unflow(body),
+        EmailTextPlainFormat::Fixed(width) => wrap_text(&unflow(body), *width),
+    }
+}
+
+/// Joins the lines of a `format=flowed` body ending with a soft line
+/// break, in other words with a trailing space. The signature
+/// separator `-- ` is kept as is.
+fn unflow_text(body: &str, del_sp: bool) -> String {
+    let mut text = String::with_capacity(body.len());
+
+    for line in body.lines() {
+        if line.ends_with(' ') && line != "-- " {
+            text.push_str(if del_sp {
+                &line[..line.len() - 1]
+            } else {
+                line
+            });
+        } else {
+            text.push_str(line);
+            text.push('\n');
+        }
+    }
+
+    text
+}
+
+/// Wraps the lines of the given text at the given width, breaking on
+/// spaces. Words longer than the width are kept on their own line.
+fn wrap_text(body: &str, width: usize) -> String {
+    let mut text = String::with_capacity(body.len());
+
+    for line in body.lines() {
+        let mut len = 0;
+
+        for (i, word) in line.split(' ').enumerate() {
+            let word_len = word.chars().count();
+
+            if i > 0 {
+                if len > 0 && len + 1 + word_len > width {
+                    text.push('\n');
+                    len = 0;
+                } else {
+                    text.push(' ');
+                    len += 1;
+                }
+            }
+
+            text.push_str(word);
+            len += word_len;
+        }
+
+        text.push('\n');
+    }
+
+    text
+}
+
+#[cfg(test)]
+mod parsed_email {
+    use crate::EmailTextPlainFormat;
+
+    use super::{ParsedEmail, Part};
+
+    #[test]
+    fn parse_multipart() {
+        let raw = concat!(
+            "Subject: =?utf-8?q?caf=C3=A9?=\r\n",
+            "From: alice@localhost\r\n",
+            "Content-Type: multipart/mixed; boundary=\"mixed\"\r\n",
+            "\r\n",
+            "--mixed\r\n",
+            "Content-Type: multipart/alternative; boundary=\"alt\"\r\n",
+            "\r\n",
+            "--alt\r\n",
+            "Content-Type: text/plain; charset=utf-8\r\n",
+            "\r\n",
+            "Hello!\r\n",
+            "--alt\r\n",
+            "Content-Type: text/html; charset=utf-8\r\n",
+            "\r\n",
+            "<p>Hello!</p>\r\n",
+            "--alt--\r\n",
+            "--mixed\r\n",
+            "Content-Type: application/pdf\r\n",
+            "Content-Disposition: attachment; filename=\"invoice.pdf\"\r\n",
+            "Content-Transfer-Encoding: base64\r\n",
+            "\r\n",
+            "JVBERg==\r\n",
+            "--mixed--\r\n",
+        );
+        let parsed = mailparse::parse_mail(raw.as_bytes()).unwrap();
+        let email = ParsedEmail::from_parsed_mail(&parsed, &EmailTextPlainFormat::Auto);
+
+        assert_eq!(Some("café"), email.subject());
+        assert_eq!(Some("alice@localhost"), email.from());
+        assert_eq!(Some("Hello!\r\n"), email.text_body.as_deref());
+        assert_eq!(Some("<p>Hello!</p>\r\n"), email.html_body.as_deref());
+
+        assert_eq!(1, email.attachments.len());
+        let attachment = &email.attachments[0];
+        assert_eq!(Some("invoice.pdf"), attachment.filename.as_deref());
+        assert_eq!("application/pdf", attachment.mime);
+        assert_eq!(b"%PDF".to_vec(), attachment.body().unwrap());
+    }
+
+    #[test]
+    fn parse_malformed() {
+        let raw = concat!(
+            "Subject: broken\r\n",
+            "Content-Type: multipart/mixed\r\n",
+            "\r\n",
+            "--unknown\r\n",
+            "Hello!\r\n",
+        );
+        let parsed = mailparse::parse_mail(raw.as_bytes()).unwrap();
+        let email = ParsedEmail::from_parsed_mail(&parsed, &EmailTextPlainFormat::Auto);
+        assert!(email.text_body.unwrap().contains("Hello!"));
+
+        let raw = concat!(
+            "Subject: bad charset\r\n",
+            "Content-Type: text/plain; charset=unknown-charset\r\n",
+            "\r\n",
+            "Hello!\r\n",
+        );
+        let parsed = mailparse::parse_mail(raw.as_bytes()).unwrap();
+        let email = ParsedEmail::from_parsed_mail(&parsed, &EmailTextPlainFormat::Auto);
+        assert!(email.text_body.unwrap().contains("Hello!"));
+    }
+
+    #[test]
+    fn parse_text_plain_format() {
+        let raw = concat!(
+            "Content-Type: text/plain; charset=utf-8; format=flowed\r\n",
+            "\r\n",
+            "The quick brown \r\n",
+            "fox jumps.\r\n",
+            "-- \r\n",
+            "Alice\r\n",
+        );
+        let parsed = mailparse::parse_mail(raw.as_bytes()).unwrap();
+
+        let email = ParsedEmail::from_parsed_mail(&parsed, &EmailTextPlainFormat::Auto);
+        assert_eq!(
+            Some("The quick brown \r\nfox jumps.\r\n-- \r\nAlice\r\n"),
+            email.text_body.as_deref()
+        );
+
+        let email = ParsedEmail::from_parsed_mail(&parsed, &EmailTextPlainFormat::Flowed);
+        assert_eq!(
+            Some("The quick brown fox jumps.\n-- \nAlice\n"),
+            email.text_body.as_deref()
+        );
+
+        let email = ParsedEmail::from_parsed_mail(&parsed, &EmailTextPlainFormat::Fixed(10));
+        assert_eq!(
+            Some("The quick\nbrown fox\njumps.\n-- \nAlice\n"),
+            email.text_body.as_deref()
+        );
+    }
+
+    #[test]
+    fn part_decoded_bytes() {
+        let raw = concat!(
+            "Content-Type: multipart/mixed; boundary=\"mixed\"\r\n",
+            "\r\n",
+            "--mixed\r\n",
+            "Content-Type: text/plain; charset=iso-8859-1\r\n",
+            "Content-Transfer-Encoding: quoted-printable\r\n",
+            "\r\n",
+            "caf=E9\r\n",
+            "--mixed\r\n",
+            "Content-Type: text/plain; charset=unknown-charset\r\n",
+            "Content-Transfer-Encoding: base64\r\n",
+            "\r\n",
+            "Y2Fm6Q==\r\n",
+            "--mixed\r\n",
+            "Content-Type: application/octet-stream\r\n",
+            "Content-Transfer-Encoding: base64\r\n",
+            "\r\n",
+            "AP8=\r\n",
+            "--mixed--\r\n",
+        );
+        let parsed = mailparse::parse_mail(raw.as_bytes()).unwrap();
+        let parts: Vec<Part> = parsed.parts().skip(1).map(Part::new).collect();
+
+        assert_eq!(b"caf\xc3\xa9\r\n".to_vec(), parts[0].decoded_bytes());
+        assert_eq!("café".as_bytes(), parts[1].decoded_bytes());
+        assert_eq!(vec![0x00, 0xff], parts[2].decoded_bytes());
+        assert!(parts[2].raw_headers().ends_with(b"base64\r\n"));
+    }
+
+    #[test]
+    fn part_metadata() {
+        let raw = concat!(
+            "Content-Type: multipart/mixed; boundary=\"mixed\"\r\n",
+            "\r\n",
+            "--mixed\r\n",
+            "Content-Type: text/plain; charset=utf-8\r\n",
+            "\r\n",
+            "Hello!\r\n",
+            "--mixed\r\n",
+            "Content-Type: text/html\r\n",
+            "Content-Disposition: inline\r\n",
+            "\r\n",
+            "<p>Hello!</p>\r\n",
+            "--mixed\r\n",
+            "Content-Type: text/plain; name=\"notes.txt\"\r\n",
+            "Content-Disposition: attachment\r\n",
+            "\r\n",
+            "Notes\r\n",
+            "--mixed--\r\n",
+        );
+        let parsed = mailparse::parse_mail(raw.as_bytes()).unwrap();
+        let parts: Vec<Part> = parsed.parts().skip(1).map(Part::new).collect();
+
+        assert_eq!(Some("utf-8"), parts[0].charset.as_deref());
+        assert_eq!(None, parts[0].disposition);
+        assert!(parts[0].is_text_plain());
+        assert!(!parts[0].is_attachment());
+
+        assert_eq!(None, parts[1].charset);
+        assert_eq!(Some("inline"), parts[1].disposition.as_deref());
+        assert!(parts[1].is_text_html());
+        assert!(!parts[1].is_attachment());
+
+        assert_eq!(Some("attachment"), parts[2].disposition.as_deref());
+        assert_eq!(Some("notes.txt"), parts[2].filename.as_deref());
+        assert!(parts[2].is_attachment());
+        assert!(!parts[2].is_text_plain());
+    }
+}
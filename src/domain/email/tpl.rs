@@ -0,0 +1,182 @@
+//! Template module.
+//!
+//! This module contains helpers around the [`TplBuilder`] of the
+//! `mime-msg-builder` crate.
+
+use mime_msg_builder::TplBuilder;
+
+use crate::email::{Error, Result};
+
+/// Represents the headers set by the [`TplBuilder`] helpers, with the
+/// casing they use.
+const BUILDER_HEADERS: [&str; 10] = [
+    "Message-ID",
+    "In-Reply-To",
+    "Date",
+    "From",
+    "Sender",
+    "Reply-To",
+    "To",
+    "Cc",
+    "Bcc",
+    "Subject",
+];
+
+/// Extends the [`TplBuilder`] with validated custom headers.
+pub trait TplBuilderExt: Sized {
+    /// Adds an arbitrary header to the template, like `X-Mailer` or
+    /// `List-Unsubscribe`. The name must be made of printable ASCII
+    /// characters without spaces nor colon, and the value must not
+    /// contain line breaks, except folding ones (a CRLF followed by
+    /// a space or a tab).
+    ///
+    /// Header names are case-insensitive, so the header replaces any
+    /// header of the same name already set, whatever its casing.
+    fn add_custom_header<N, V>(self, name: N, value: V) -> Result<Self>
+    where
+        N: AsRef<str>,
+        V: AsRef<str>;
+}
+
+impl TplBuilderExt for TplBuilder {
+    fn add_custom_header<N, V>(self, name: N, value: V) -> Result<Self>
+    where
+        N: AsRef<str>,
+        V: AsRef<str>,
+    {
+        let (name, value) = (name.as_ref(), value.as_ref());
+
+        if !is_valid_header_name(name) {
+            return Err(Error::InvalidHeaderName(name.to_owned()));
+        }
+
+        if !is_valid_header_value(value) {
+            return Err(Error::InvalidHeaderValue(name.to_owned(), value.to_owned()));
+        }
+
+        Ok(self.set_header(canonical_header_name(name), value.to_owned()))
+    }
+}
+
+/// Returns the casing of the given header name used by the
+/// [`TplBuilder`], so that setting the header replaces the previous
+/// one instead of adding another header differing by its casing.
+/// Headers unknown to the builder get each word capitalized, like
+/// `X-Mailer`.
+fn canonical_header_name(name: &str) -> String {
+    if let Some(name) = BUILDER_HEADERS
+        .iter()
+        .find(|header| header.eq_ignore_ascii_case(name))
+    {
+        return name.to_string();
+    }
+
+    name.split('-')
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => {
+                    first.to_ascii_uppercase().to_string() + &chars.as_str().to_ascii_lowercase()
+                }
+                None => String::new(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Checks that the header name is made of printable ASCII characters
+/// except colon ([RFC 5322 section 2.2]).
+///
+/// [RFC 5322 section 2.2]: https://www.rfc-editor.org/rfc/rfc5322#section-2.2
+fn is_valid_header_name(name: &str) -> bool {
+    !name.is_empty() && name.bytes().all(|b| (33..=126).contains(&b) && b != b':')
+}
+
+/// Checks that the header value does not contain line breaks, except
+/// folding ones.
+fn is_valid_header_value(value: &str) -> bool {
+    let mut bytes = value.bytes().peekable();
+
+    while let Some(b) = bytes.next() {
+        match b {
+            b'\r' => {
+                if bytes.next() != Some(b'\n') {
+                    return false;
+                }
+                if !matches!(bytes.peek(), Some(b' ' | b'\t')) {
+                    return false;
+                }
+            }
+            b'\n' => return false,
+            _ => (),
+        }
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tpl {
+    use mime_msg_builder::TplBuilder;
+
+    use super::{
+        canonical_header_name, is_valid_header_name, is_valid_header_value, TplBuilderExt,
+    };
+
+    #[test]
+    fn add_custom_header() {
+        let tpl = TplBuilder::default()
+            .subject("Subject")
+            .add_custom_header("x-mailer", "himalaya")
+            .unwrap()
+            .add_custom_header("X-MAILER", "himalaya-lib")
+            .unwrap()
+            .add_custom_header("subject", "Replaced")
+            .unwrap()
+            .build();
+
+        let headers = |name: &str| -> Vec<String> {
+            tpl.lines()
+                .filter(|line| line.to_lowercase().starts_with(&format!("{name}:")))
+                .map(ToOwned::to_owned)
+                .collect()
+        };
+        assert_eq!(vec!["X-Mailer: himalaya-lib"], headers("x-mailer"));
+        assert_eq!(vec!["Subject: Replaced"], headers("subject"));
+    }
+
+    #[test]
+    fn header_name_casing() {
+        assert_eq!("Message-ID", canonical_header_name("message-id"));
+        assert_eq!("Subject", canonical_header_name("SUBJECT"));
+        assert_eq!("X-Mailer", canonical_header_name("x-mailer"));
+        assert_eq!(
+            "List-Unsubscribe",
+            canonical_header_name("LIST-UNSUBSCRIBE")
+        );
+    }
+
+    #[test]
+    fn validate_header_name() {
+        assert!(is_valid_header_name("X-Mailer"));
+        assert!(is_valid_header_name("List-Unsubscribe"));
+        assert!(!is_valid_header_name(""));
+        assert!(!is_valid_header_name("X Mailer"));
+        assert!(!is_valid_header_name("X-Mailer:"));
+        assert!(!is_valid_header_name("X-Mailér"));
+    }
+
+    #[test]
+    fn validate_header_value() {
+        assert!(is_valid_header_value("himalaya"));
+        assert!(is_valid_header_value(
+            "<mailto:a@localhost>,\r\n <https://localhost>"
+        ));
+        assert!(!is_valid_header_value(
+            "auto-generated\r\nBcc: eve@localhost"
+        ));
+        assert!(!is_valid_header_value("auto-generated\nBcc: eve@localhost"));
+        assert!(!is_valid_header_value("auto-generated\r"));
+    }
+}
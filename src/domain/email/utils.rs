@@ -1,5 +1,12 @@
 use log::debug;
-use std::{env, fs, path::PathBuf};
+use mailparse::MailHeaderMap;
+use std::{
+    borrow::Cow,
+    env, fs,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use uuid::Uuid;
 
 use crate::email::{Error, Result};
 
@@ -14,3 +21,106 @@ pub fn remove_local_draft() -> Result<()> {
     fs::remove_file(&path).map_err(|err| Error::DeleteLocalDraftError(err, path))?;
     Ok(())
 }
+
+/// Generates a new [RFC 5322] compliant Message-ID for the given
+/// domain, made of the current timestamp in milliseconds and of a
+/// random part, for example `<1672531200000.5e4c3a1f9b2d8e07@localhost>`.
+///
+/// [RFC 5322]: https://www.rfc-editor.org/rfc/rfc5322#section-3.6.4
+pub fn generate_message_id(domain: &str) -> String {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis())
+        .unwrap_or_default();
+    let random = Uuid::new_v4().as_u128() as u64;
+    format!("<{timestamp}.{random:016x}@{domain}>")
+}
+
+/// Prepends a Message-ID generated for the given domain to the raw
+/// email (see [`generate_message_id`]), unless it already has one or
+/// its headers cannot be parsed.
+pub fn ensure_message_id<'a>(email: &'a [u8], domain: &str) -> Cow<'a, [u8]> {
+    match mailparse::parse_headers(email) {
+        Ok((headers, _)) if headers.get_first_header("Message-ID").is_none() => {
+            let mut with_message_id =
+                format!("Message-ID: {}\r\n", generate_message_id(domain)).into_bytes();
+            with_message_id.extend_from_slice(email);
+            Cow::Owned(with_message_id)
+        }
+        _ => Cow::Borrowed(email),
+    }
+}
+
+/// Splits the raw email on the first blank line, returning the
+/// header block and the body. Lines can end with CRLF or LF, even
+/// mixed. The blank line belongs to neither part. An email without
+/// blank line is made of headers only.
+pub fn split_raw_email(email: &[u8]) -> (&[u8], &[u8]) {
+    let mut len = 0;
+
+    for line in email.split_inclusive(|b| *b == b'\n') {
+        if line == b"\n" || line == b"\r\n" {
+            return (&email[..len], &email[len + line.len()..]);
+        }
+        len += line.len();
+    }
+
+    (email, &[])
+}
+
+#[cfg(test)]
+mod utils {
+    use mailparse::MailHeaderMap;
+    use std::borrow::Cow;
+
+    use super::{ensure_message_id, generate_message_id, split_raw_email};
+
+    #[test]
+    fn generate_message_id_format() {
+        let id = generate_message_id("localhost");
+        let (left, right) = id
+            .strip_prefix('<')
+            .and_then(|id| id.strip_suffix('>'))
+            .and_then(|id| id.split_once('@'))
+            .unwrap();
+        let (timestamp, random) = left.split_once('.').unwrap();
+
+        assert_eq!("localhost", right);
+        assert!(timestamp.parse::<u128>().is_ok());
+        assert_eq!(16, random.len());
+        assert!(random.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(id, generate_message_id("localhost"));
+    }
+
+    #[test]
+    fn ensure_message_id_when_missing() {
+        let email = b"From: alice@localhost\r\nSubject: Hello\r\n\r\nHello!\r\n";
+        let with_message_id = ensure_message_id(email, "localhost");
+        let (headers, _) = mailparse::parse_headers(&with_message_id).unwrap();
+        let message_id = headers.get_first_value("Message-ID").unwrap();
+
+        assert!(message_id.ends_with("@localhost>"));
+        assert!(with_message_id.ends_with(email));
+
+        let email = b"Message-ID: <a@localhost>\r\n\r\nHello!\r\n";
+        assert_eq!(
+            Cow::Borrowed(&email[..]),
+            ensure_message_id(email, "localhost")
+        );
+    }
+
+    #[test]
+    fn split_raw_email_line_endings() {
+        let (headers, body) = split_raw_email(b"Subject: a\r\nFrom: b\n\r\nbody\n\nmore\r\n");
+        assert_eq!(b"Subject: a\r\nFrom: b\n", headers);
+        assert_eq!(b"body\n\nmore\r\n", body);
+
+        let (headers, body) = split_raw_email(b"Subject: a\n\n");
+        assert_eq!(b"Subject: a\n", headers);
+        assert!(body.is_empty());
+
+        let (headers, body) = split_raw_email(b"Subject: a\r\n");
+        assert_eq!(b"Subject: a\r\n", headers);
+        assert!(body.is_empty());
+    }
+}
@@ -1,5 +1,7 @@
 use chrono::{DateTime, Local};
-use serde::{Serialize, Serializer};
+use log::debug;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
 
 use crate::Flags;
 
@@ -7,7 +9,14 @@ fn date<S: Serializer>(date: &DateTime<Local>, s: S) -> Result<S::Ok, S::Error>
     s.serialize_str(&date.to_rfc3339())
 }
 
-#[derive(Clone, Debug, Default, Eq, Serialize)]
+fn parse_date<'de, D: Deserializer<'de>>(d: D) -> Result<DateTime<Local>, D::Error> {
+    let date = String::deserialize(d)?;
+    DateTime::parse_from_rfc3339(&date)
+        .map(|date| date.with_timezone(&Local))
+        .map_err(de::Error::custom)
+}
+
+#[derive(Clone, Debug, Default, Eq, Serialize, Deserialize)]
 pub struct Mailbox {
     pub name: Option<String>,
     pub addr: String,
@@ -40,11 +49,159 @@ impl Mailbox {
             addr: address.to_string(),
         }
     }
+
+    /// Returns the display name of the mailbox if present, otherwise
+    /// its email address.
+    pub fn display_name(&self) -> &str {
+        match self.name.as_deref().map(str::trim) {
+            Some(name) if !name.is_empty() => name,
+            _ => match split_addr(&self.addr) {
+                (Some(name), _) => name,
+                (None, addr) => addr,
+            },
+        }
+    }
+
+    /// Parses a raw address like `"John Doe" <john@localhost>` into
+    /// a mailbox with its display name and its bare email address.
+    pub fn parse<R: AsRef<str>>(raw: R) -> Self {
+        let (name, addr) = split_addr(raw.as_ref());
+        Self::new(name, addr)
+    }
+
+    /// Returns the bare email address of the mailbox, without the
+    /// display name nor the angle brackets.
+    pub fn email(&self) -> &str {
+        split_addr(&self.addr).1
+    }
+
+    /// Returns the bare email address of the mailbox, with its
+    /// internationalized domain name (`xn--` labels) decoded to
+    /// Unicode. The address is kept as is if its domain cannot be
+    /// decoded.
+    pub fn unicode_email(&self) -> String {
+        let email = self.email();
+        match email.rsplit_once('@') {
+            None => email.to_owned(),
+            Some((local, domain)) => match idna::domain_to_unicode(domain) {
+                (domain, Ok(())) => format!("{local}@{domain}"),
+                (_, Err(err)) => {
+                    debug!("cannot decode domain {domain}, keeping it as is: {err}");
+                    email.to_owned()
+                }
+            },
+        }
+    }
+}
+
+impl fmt::Display for Mailbox {
+    /// Formats the mailbox as `"John Doe" <john@localhost>`, or as
+    /// the bare email address if it has no display name. The display
+    /// name is quoted only when it contains special characters.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (raw_name, email) = split_addr(&self.addr);
+        let name = self
+            .name
+            .as_deref()
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .or(raw_name);
+
+        match name {
+            None => write!(f, "{email}"),
+            Some(name) if name.contains(|c| "()<>[]:;@\\,.\"".contains(c)) => {
+                let name = name.replace('\\', "\\\\").replace('"', "\\\"");
+                write!(f, "\"{name}\" <{email}>")
+            }
+            Some(name) => write!(f, "{name} <{email}>"),
+        }
+    }
+}
+
+/// Splits a raw address like `"John Doe" <john@localhost>` into its
+/// display name and its bare email address, without allocating.
+/// Angle brackets inside a quoted display name are ignored.
+fn split_addr(raw: &str) -> (Option<&str>, &str) {
+    let mut in_quotes = false;
+    let mut escaped = false;
+    let mut lt = None;
+
+    for (i, c) in raw.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_quotes => escaped = true,
+            '"' => in_quotes = !in_quotes,
+            '<' if !in_quotes => lt = Some(i),
+            '>' if !in_quotes => {
+                if let Some(lt) = lt {
+                    let name = raw[..lt].trim().trim_matches('"').trim();
+                    let addr = raw[lt + 1..i].trim();
+                    return (Some(name).filter(|name| !name.is_empty()), addr);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    (None, raw.trim())
+}
+
+/// Represents the priority of a message, as set by the `X-Priority`
+/// or the `Importance` header.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
+pub enum MessagePriority {
+    Low,
+    Normal,
+    High,
+}
+
+impl MessagePriority {
+    /// Parses the priority from an `X-Priority` header value. Values
+    /// 1 and 2 are high, 3 is normal, 4 and 5 are low. Extra
+    /// comments like in `1 (Highest)` are ignored.
+    pub fn from_x_priority<V: AsRef<str>>(val: V) -> Option<Self> {
+        match val.as_ref().trim().chars().next()? {
+            '1' | '2' => Some(Self::High),
+            '3' => Some(Self::Normal),
+            '4' | '5' => Some(Self::Low),
+            _ => None,
+        }
+    }
+
+    /// Parses the priority from an `Importance` header value.
+    pub fn from_importance<V: AsRef<str>>(val: V) -> Option<Self> {
+        match val.as_ref().trim().to_lowercase().as_str() {
+            "high" => Some(Self::High),
+            "normal" => Some(Self::Normal),
+            "low" => Some(Self::Low),
+            _ => None,
+        }
+    }
+
+    /// Builds the priority from the `X-Priority` and the
+    /// `Importance` header values. `X-Priority` takes precedence.
+    pub fn from_headers<V: AsRef<str>>(
+        x_priority: Option<V>,
+        importance: Option<V>,
+    ) -> Option<Self> {
+        x_priority
+            .and_then(Self::from_x_priority)
+            .or_else(|| importance.and_then(Self::from_importance))
+    }
+
+    /// Converts the priority to its `X-Priority` numeric value.
+    pub fn to_x_priority(&self) -> u8 {
+        match self {
+            Self::High => 1,
+            Self::Normal => 3,
+            Self::Low => 5,
+        }
+    }
 }
 
 /// Represents the message envelope. The envelope is just a message
 /// subset, and is mostly used for listings.
-#[derive(Clone, Debug, Default, Eq, Serialize)]
+#[derive(Clone, Debug, Default, Eq, Serialize, Deserialize)]
 pub struct Envelope {
     /// Represents the identifier.
     pub id: String,
@@ -58,18 +215,94 @@ pub struct Envelope {
     pub from: Mailbox,
     /// Represents the Subject header.
     pub subject: String,
-    #[serde(serialize_with = "date")]
+    #[serde(serialize_with = "date", deserialize_with = "parse_date")]
     /// Represents the Date header.
     pub date: DateTime<Local>,
+    /// Represents the priority, from the X-Priority or the Importance
+    /// header.
+    pub priority: Option<MessagePriority>,
+    /// Represents the size of the email in bytes, if known.
+    pub size: Option<u64>,
+    /// Represents the error raised while parsing the email, in which
+    /// case the envelope is a placeholder (see
+    /// [`Envelope::unparsable`]).
+    #[serde(default)]
+    pub parse_error: Option<String>,
 }
 
 impl Envelope {
+    /// Returns the display name of the sender if present, otherwise
+    /// its email address. Useful for listings.
+    pub fn sender_display_name(&self) -> &str {
+        self.from.display_name()
+    }
+
+    /// Returns the bare email address of the sender.
+    pub fn sender_email(&self) -> &str {
+        self.from.email()
+    }
+
+    /// Builds the placeholder envelope of an email which cannot be
+    /// parsed, so that a single corrupted email does not make the
+    /// whole listing fail.
+    ///
+    /// The message id is synthesized from the given seed, which
+    /// should identify the email inside its folder. Since it does not
+    /// match the real one, placeholders are skipped by the
+    /// synchronization.
+    pub fn unparsable<E: ToString>(internal_id: String, flags: Flags, seed: &str, err: E) -> Self {
+        Self {
+            id: internal_id.clone(),
+            internal_id,
+            message_id: format!("<{seed}@unparsable.himalaya>"),
+            flags,
+            parse_error: Some(err.to_string()),
+            ..Self::default()
+        }
+    }
+
+    /// Returns `true` if the envelope has the given Message-ID, with
+    /// or without surrounding angle brackets.
+    pub fn has_message_id(&self, message_id: &str) -> bool {
+        strip_message_id(&self.message_id) == strip_message_id(message_id)
+    }
+
+    /// Returns `true` if the envelope is the placeholder of an email
+    /// which cannot be parsed.
+    pub fn is_unparsable(&self) -> bool {
+        self.parse_error.is_some()
+    }
+
     pub fn clone_without_custom_flags(&self) -> Self {
         Self {
             flags: self.flags.clone_without_customs(),
             ..self.clone()
         }
     }
+
+    /// Removes the custom flags of the envelope, like
+    /// [`Envelope::clone_without_custom_flags`] without cloning the
+    /// rest of the envelope.
+    pub fn without_custom_flags(self) -> Self {
+        Self {
+            flags: self.flags.clone_without_customs(),
+            ..self
+        }
+    }
+
+    /// Clones the envelope, keeping only the standard flags. Alias of
+    /// [`Envelope::clone_without_custom_flags`].
+    pub fn clone_with_only_standard_flags(&self) -> Self {
+        self.clone_without_custom_flags()
+    }
+
+    /// Clones the envelope, keeping only the custom flags.
+    pub fn clone_with_only_custom_flags(&self) -> Self {
+        Self {
+            flags: self.flags.clone_only_customs(),
+            ..self.clone()
+        }
+    }
 }
 
 impl PartialEq for Envelope {
@@ -77,3 +310,150 @@ impl PartialEq for Envelope {
         self.message_id == other.message_id
     }
 }
+
+/// Strips the whitespaces and the angle brackets surrounding the given
+/// Message-ID, so that `<id@localhost>` and `id@localhost` can be
+/// compared.
+pub fn strip_message_id(message_id: &str) -> &str {
+    message_id
+        .trim()
+        .trim_start_matches('<')
+        .trim_end_matches('>')
+}
+
+#[cfg(test)]
+mod envelope {
+    use proptest::prelude::*;
+
+    use crate::{Flag, Flags};
+
+    use super::{Envelope, Mailbox, MessagePriority};
+
+    fn flag() -> impl Strategy<Value = Flag> {
+        prop_oneof![
+            Just(Flag::Seen),
+            Just(Flag::Answered),
+            Just(Flag::Flagged),
+            Just(Flag::Deleted),
+            Just(Flag::Draft),
+            Just(Flag::Recent),
+            Just(Flag::custom("seen")),
+            any::<String>().prop_map(Flag::Custom),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn split_flags(flags in prop::collection::vec(flag(), 0..8)) {
+            let envelope = Envelope {
+                flags: Flags::from_iter(flags),
+                ..Envelope::default()
+            };
+            let standard = envelope.clone_with_only_standard_flags().flags;
+            let custom = envelope.clone_with_only_custom_flags().flags;
+
+            prop_assert_eq!(&envelope.flags, &standard.union(&custom));
+            prop_assert!(standard.intersection(&custom).is_empty());
+            prop_assert!(standard.iter().all(|flag| !matches!(flag, Flag::Custom(_))));
+            prop_assert!(custom.iter().all(|flag| matches!(flag, Flag::Custom(_))));
+        }
+    }
+
+    #[test]
+    fn has_message_id() {
+        let envelope = Envelope {
+            message_id: "<id@localhost>".into(),
+            ..Envelope::default()
+        };
+
+        assert!(envelope.has_message_id("<id@localhost>"));
+        assert!(envelope.has_message_id("id@localhost"));
+        assert!(envelope.has_message_id(" <id@localhost> "));
+        assert!(!envelope.has_message_id("<other@localhost>"));
+        assert_eq!("id@localhost", super::strip_message_id("<id@localhost>"));
+    }
+
+    #[test]
+    fn sender_display_name_and_email() {
+        let envelope = |from| Envelope {
+            from,
+            ..Envelope::default()
+        };
+
+        let named = envelope(Mailbox::new(Some("John Doe"), "john@localhost"));
+        assert_eq!("John Doe", named.sender_display_name());
+        assert_eq!("john@localhost", named.sender_email());
+
+        let nameless = envelope(Mailbox::new_nameless("john@localhost"));
+        assert_eq!("john@localhost", nameless.sender_display_name());
+        assert_eq!("john@localhost", nameless.sender_email());
+
+        let raw = envelope(Mailbox::new_nameless("\"Doe, <John>\" <john@localhost>"));
+        assert_eq!("Doe, <John>", raw.sender_display_name());
+        assert_eq!("john@localhost", raw.sender_email());
+
+        let bracketed = envelope(Mailbox::new(Some(" "), "<john@localhost>"));
+        assert_eq!("john@localhost", bracketed.sender_display_name());
+        assert_eq!("john@localhost", bracketed.sender_email());
+    }
+
+    #[test]
+    fn mailbox_display_and_parse() {
+        let named = Mailbox::new(Some("John Doe"), "john@localhost");
+        assert_eq!("John Doe <john@localhost>", named.to_string());
+        assert_eq!(named.name, Mailbox::parse(named.to_string()).name);
+
+        let quoted = Mailbox::new(Some("Doe, \"John\""), "john@localhost");
+        assert_eq!(r#""Doe, \"John\"" <john@localhost>"#, quoted.to_string());
+
+        let nameless = Mailbox::new_nameless("<john@localhost>");
+        assert_eq!("john@localhost", nameless.to_string());
+
+        let parsed = Mailbox::parse("\"Doe, <John>\" <john@localhost>");
+        assert_eq!(Some("Doe, <John>".into()), parsed.name);
+        assert_eq!("john@localhost", parsed.addr);
+    }
+
+    #[test]
+    fn mailbox_unicode_email() {
+        let mbox = Mailbox::new_nameless("john@xn--mnchen-3ya.XN--bcher-kva.example");
+        assert_eq!("john@münchen.bücher.example", mbox.unicode_email());
+
+        let invalid = Mailbox::new_nameless("john@xn--!.localhost");
+        assert_eq!("john@xn--!.localhost", invalid.unicode_email());
+    }
+
+    #[test]
+    fn unparsable() {
+        let flags = Flags::from_iter([Flag::Seen]);
+        let envelope = Envelope::unparsable("42".into(), flags.clone(), "42.0", "bad header");
+
+        assert!(envelope.is_unparsable());
+        assert_eq!("42", envelope.id);
+        assert_eq!("<42.0@unparsable.himalaya>", envelope.message_id);
+        assert_eq!(flags, envelope.flags);
+        assert_eq!(Some("bad header".into()), envelope.parse_error);
+        assert!(!Envelope::default().is_unparsable());
+    }
+
+    #[test]
+    fn message_priority_from_headers() {
+        assert_eq!(
+            Some(MessagePriority::High),
+            MessagePriority::from_headers(Some("1 (Highest)"), None)
+        );
+        assert_eq!(
+            Some(MessagePriority::Low),
+            MessagePriority::from_headers(Some("5"), Some("high"))
+        );
+        assert_eq!(
+            Some(MessagePriority::Normal),
+            MessagePriority::from_headers(None, Some("Normal"))
+        );
+        assert_eq!(
+            Some(MessagePriority::High),
+            MessagePriority::from_headers(Some("unknown"), Some("HIGH"))
+        );
+        assert_eq!(None, MessagePriority::from_headers::<&str>(None, None));
+    }
+}
@@ -1,12 +1,64 @@
-use serde::Serialize;
-use std::ops::{Deref, DerefMut};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    ops::{Deref, DerefMut},
+    vec,
+};
 
-use crate::Envelope;
+use crate::{Envelope, MessagePriority};
 
 /// Represents the list of envelopes.
-#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Envelopes(Vec<Envelope>);
 
+impl Envelopes {
+    /// Returns the envelopes matching the given priority.
+    pub fn filter_by_priority(&self, priority: MessagePriority) -> Envelopes {
+        self.iter()
+            .filter(|envelope| envelope.priority == Some(priority))
+            .cloned()
+            .collect()
+    }
+
+    /// Removes the envelopes sharing the message id of a previous
+    /// one, like some backends return when an email belongs to several
+    /// folders or tags. The envelope with the most flags is kept, in
+    /// place of the first occurrence, and receives the flags of all
+    /// its duplicates.
+    pub fn dedup_by_message_id(&mut self) {
+        let mut positions: HashMap<String, usize> = HashMap::new();
+        let mut deduped: Vec<Envelope> = Vec::with_capacity(self.len());
+
+        for envelope in self.0.drain(..) {
+            match positions.entry(envelope.message_id.clone()) {
+                Entry::Vacant(entry) => {
+                    entry.insert(deduped.len());
+                    deduped.push(envelope);
+                }
+                Entry::Occupied(entry) => {
+                    let kept = &mut deduped[*entry.get()];
+                    let flags = kept.flags.union(&envelope.flags);
+                    if envelope.flags.len() > kept.flags.len() {
+                        *kept = envelope;
+                    }
+                    kept.flags = flags;
+                }
+            }
+        }
+
+        self.0 = deduped;
+    }
+
+    /// Returns `true` if at least two envelopes share the same message
+    /// id.
+    pub fn has_duplicate_message_ids(&self) -> bool {
+        let mut message_ids = HashSet::new();
+        !self
+            .iter()
+            .all(|envelope| message_ids.insert(envelope.message_id.as_str()))
+    }
+}
+
 impl Deref for Envelopes {
     type Target = Vec<Envelope>;
 
@@ -21,6 +73,17 @@ impl DerefMut for Envelopes {
     }
 }
 
+/// Moves the envelopes out of the list, so that they can be stored
+/// elsewhere without being cloned.
+impl IntoIterator for Envelopes {
+    type Item = Envelope;
+    type IntoIter = vec::IntoIter<Envelope>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
 impl FromIterator<Envelope> for Envelopes {
     fn from_iter<T: IntoIterator<Item = Envelope>>(iter: T) -> Self {
         let mut envelopes = Envelopes::default();
@@ -28,3 +91,49 @@ impl FromIterator<Envelope> for Envelopes {
         envelopes
     }
 }
+
+#[cfg(test)]
+mod envelopes {
+    use crate::{Envelope, Flag, Flags};
+
+    use super::Envelopes;
+
+    fn envelope(id: &str, message_id: &str, flags: Flags) -> Envelope {
+        Envelope {
+            id: id.into(),
+            internal_id: id.into(),
+            message_id: message_id.into(),
+            flags,
+            ..Envelope::default()
+        }
+    }
+
+    #[test]
+    fn dedup_by_message_id() {
+        let mut envelopes = Envelopes::from_iter([
+            envelope("1", "<a@localhost>", Flags::from_iter([Flag::Seen])),
+            envelope("2", "<b@localhost>", Flags::default()),
+            envelope(
+                "3",
+                "<a@localhost>",
+                Flags::from_iter([Flag::Flagged, Flag::Answered]),
+            ),
+            envelope("4", "<a@localhost>", Flags::default()),
+        ]);
+        assert!(envelopes.has_duplicate_message_ids());
+
+        envelopes.dedup_by_message_id();
+        assert!(!envelopes.has_duplicate_message_ids());
+        assert_eq!(
+            *envelopes,
+            vec![
+                envelope(
+                    "3",
+                    "<a@localhost>",
+                    Flags::from_iter([Flag::Seen, Flag::Flagged, Flag::Answered]),
+                ),
+                envelope("2", "<b@localhost>", Flags::default()),
+            ]
+        );
+    }
+}
@@ -6,16 +6,31 @@
 use chrono::{DateTime, Local, NaiveDateTime};
 use imap::{self, types::Fetch};
 use log::trace;
+use mailparse::MailHeaderMap;
 use rfc2047_decoder;
 use std::borrow::Cow;
 
 use crate::{
     backend::imap::{Error, Result},
     envelope::Mailbox,
-    Envelope, Flags,
+    Envelope, Flags, MessagePriority,
 };
 
+/// Represents the fetch query used to build envelopes from IMAP
+/// fetches. Priority headers are not part of the IMAP envelope, they
+/// need to be fetched separately. The internal date identifies the
+/// emails which cannot be parsed (see [`Envelope::unparsable`]).
+pub const FETCH_QUERY: &str = "(UID FLAGS ENVELOPE RFC822.SIZE INTERNALDATE BODY.PEEK[HEADER.FIELDS (X-PRIORITY IMPORTANCE)])";
+
+/// Builds an envelope from the given fetch of the [`FETCH_QUERY`].
 pub fn from_raw(fetch: &Fetch) -> Result<Envelope> {
+    from_raw_with(fetch, true)
+}
+
+/// Builds an envelope from the given fetch. When the IMAP envelope
+/// was not requested (see [`crate::ImapConfig::envelope_fetch_attrs`]),
+/// fields it holds are left empty instead of failing.
+pub fn from_raw_with(fetch: &Fetch, with_envelope: bool) -> Result<Envelope> {
     let decode = |input: &Cow<[u8]>| {
         rfc2047_decoder::Decoder::new()
             .skip_encoded_word_length(true)
@@ -27,12 +42,26 @@ pub fn from_raw(fetch: &Fetch) -> Result<Envelope> {
         .ok_or_else(|| Error::GetUidError(fetch.message))?
         .to_string();
 
-    let envelope = fetch
-        .envelope()
-        .ok_or_else(|| Error::GetEnvelopeError(id.clone()))?;
-
     let internal_id = id.clone();
 
+    let envelope = match fetch.envelope() {
+        Some(envelope) => envelope,
+        None if with_envelope => return Err(Error::GetEnvelopeError(id)),
+        None => {
+            return Ok(Envelope {
+                id,
+                internal_id,
+                flags: Flags::from(fetch.flags()),
+                date: fetch
+                    .internal_date()
+                    .map(|date| date.with_timezone(&Local))
+                    .unwrap_or_default(),
+                size: fetch.size.map(u64::from),
+                ..Envelope::default()
+            })
+        }
+    };
+
     let message_id = String::from_utf8(envelope.message_id.clone().unwrap_or_default().to_vec())
         .map_err(|err| Error::ParseMessageIdError(err, id.clone()))?
         .trim()
@@ -89,6 +118,16 @@ pub fn from_raw(fetch: &Fetch) -> Result<Envelope> {
         None => DateTime::default(),
     };
 
+    let priority = fetch
+        .header()
+        .and_then(|header| mailparse::parse_headers(header).ok())
+        .and_then(|(headers, _)| {
+            MessagePriority::from_headers(
+                headers.get_first_value("X-Priority"),
+                headers.get_first_value("Importance"),
+            )
+        });
+
     let envelope = Envelope {
         id,
         internal_id,
@@ -97,6 +136,9 @@ pub fn from_raw(fetch: &Fetch) -> Result<Envelope> {
         subject,
         from,
         date,
+        priority,
+        size: fetch.size.map(u64::from),
+        parse_error: None,
     };
 
     trace!("imap envelope: {:?}", envelope);
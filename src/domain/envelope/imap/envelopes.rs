@@ -1,14 +1,43 @@
-use crate::{backend::imap::Result, Envelopes};
+use chrono::Local;
+use log::warn;
+
+use crate::{backend::imap::Result, Envelope, Envelopes, Flags};
 
 use super::envelope;
 
 /// Represents the list of raw envelopes returned by the `imap` crate.
 pub type RawEnvelopes = imap::types::Fetches;
 
+/// Builds the envelopes from the given fetches. Fetches which cannot
+/// be parsed give a placeholder envelope (see
+/// [`Envelope::unparsable`]), identified by their UID and their
+/// internal date, instead of making the whole listing fail.
 pub fn from_raws(raws: RawEnvelopes) -> Result<Envelopes> {
+    from_raws_with(raws, true)
+}
+
+/// Builds the envelopes from the given fetches like [`from_raws`],
+/// with or without their IMAP envelope (see
+/// [`envelope::from_raw_with`]).
+pub fn from_raws_with(raws: RawEnvelopes, with_envelope: bool) -> Result<Envelopes> {
     let mut envelopes = Envelopes::default();
     for fetch in raws.iter().rev() {
-        envelopes.push(envelope::from_raw(fetch)?);
+        let envelope = match (envelope::from_raw_with(fetch, with_envelope), fetch.uid) {
+            (Ok(envelope), _) => envelope,
+            (Err(err), Some(uid)) => {
+                warn!("cannot parse imap envelope {uid}, using a placeholder: {err}");
+                let date = fetch.internal_date();
+                let seed = format!("{uid}.{}", date.map(|d| d.timestamp()).unwrap_or_default());
+                let flags = Flags::from(fetch.flags());
+                Envelope {
+                    date: date.map(|d| d.with_timezone(&Local)).unwrap_or_default(),
+                    size: fetch.size.map(u64::from),
+                    ..Envelope::unparsable(uid.to_string(), flags, &seed, err)
+                }
+            }
+            (Err(err), None) => return Err(err),
+        };
+        envelopes.push(envelope);
     }
     Ok(envelopes)
 }
@@ -0,0 +1,140 @@
+//! JMAP envelope module.
+//!
+//! This module contains the function to build envelopes from the
+//! email objects returned by the JMAP `Email/get` method.
+
+use chrono::{DateTime, Local};
+use log::trace;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+
+use crate::{
+    backend::jmap::{Error, Result},
+    envelope::Mailbox,
+    flag::jmap::flags,
+    Envelope, MessagePriority,
+};
+
+/// Represents the email properties needed to build an envelope, to
+/// request with `Email/get`.
+pub const PROPERTIES: [&str; 10] = [
+    "id",
+    "messageId",
+    "keywords",
+    "from",
+    "subject",
+    "sentAt",
+    "receivedAt",
+    "size",
+    "header:X-Priority:asText",
+    "header:Importance:asText",
+];
+
+#[derive(Debug, Deserialize)]
+struct EmailAddress {
+    name: Option<String>,
+    email: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Email {
+    id: String,
+    message_id: Option<Vec<String>>,
+    #[serde(default)]
+    keywords: HashMap<String, bool>,
+    from: Option<Vec<EmailAddress>>,
+    subject: Option<String>,
+    sent_at: Option<String>,
+    received_at: Option<String>,
+    size: Option<u64>,
+    #[serde(rename = "header:X-Priority:asText")]
+    x_priority: Option<String>,
+    #[serde(rename = "header:Importance:asText")]
+    importance: Option<String>,
+}
+
+/// Builds an envelope from the given JMAP email object, holding the
+/// [`PROPERTIES`]. The JMAP email id is used as both id and internal
+/// id, since it never changes.
+pub fn from_json(email: Value) -> Result<Envelope> {
+    let email: Email = serde_json::from_value(email).map_err(Error::ParseEmailError)?;
+
+    let from = email
+        .from
+        .and_then(|from| from.into_iter().next())
+        .map(|addr| Mailbox::new(addr.name, addr.email))
+        .unwrap_or_default();
+
+    // the Date header is preferred over the reception date, like
+    // other backends do
+    let date = email
+        .sent_at
+        .or(email.received_at)
+        .and_then(|date| DateTime::parse_from_rfc3339(&date).ok())
+        .map(|date| date.with_timezone(&Local))
+        .unwrap_or_default();
+
+    let envelope = Envelope {
+        id: email.id.clone(),
+        internal_id: email.id,
+        message_id: email
+            .message_id
+            .and_then(|ids| ids.into_iter().next())
+            .map(|id| format!("<{id}>"))
+            .unwrap_or_default(),
+        flags: flags::from_keywords(&email.keywords),
+        from,
+        subject: email.subject.unwrap_or_default(),
+        date,
+        priority: MessagePriority::from_headers(
+            email.x_priority.as_deref().map(str::trim),
+            email.importance.as_deref().map(str::trim),
+        ),
+        size: email.size,
+        parse_error: None,
+    };
+    trace!("jmap envelope: {envelope:?}");
+
+    Ok(envelope)
+}
+
+#[cfg(test)]
+mod jmap_envelope {
+    use serde_json::json;
+
+    use crate::{envelope::Mailbox, Flag, Flags};
+
+    #[test]
+    fn from_json() {
+        let envelope = super::from_json(json!({
+            "id": "M1",
+            "messageId": ["1@localhost"],
+            "keywords": { "$seen": true, "$Flagged": true, "work": true, "$draft": false },
+            "from": [{ "name": "Alice", "email": "alice@localhost" }],
+            "subject": "Hello",
+            "sentAt": "2023-01-02T10:00:00+01:00",
+            "receivedAt": "2023-01-02T09:00:05Z",
+            "size": 42,
+            "header:X-Priority:asText": null,
+            "header:Importance:asText": null,
+        }))
+        .unwrap();
+
+        assert_eq!("M1", envelope.id);
+        assert_eq!("M1", envelope.internal_id);
+        assert_eq!("<1@localhost>", envelope.message_id);
+        assert_eq!(
+            Flags::from_iter([Flag::Seen, Flag::Flagged, Flag::custom("work")]),
+            envelope.flags
+        );
+        assert_eq!(
+            Mailbox::new(Some("Alice"), "alice@localhost"),
+            envelope.from
+        );
+        assert_eq!("Hello", envelope.subject);
+        assert_eq!(1672650000, envelope.date.timestamp());
+        assert_eq!(Some(42), envelope.size);
+    }
+}
@@ -1,26 +1,40 @@
 use chrono::{Local, NaiveDateTime};
 use log::trace;
-use mailparse::MailAddr;
+use mailparse::{MailAddr, MailHeader};
+use std::fs;
 
 use crate::{
     backend::maildir::{Error, Result},
     domain::flag::maildir::flags,
     envelope::Mailbox,
-    Envelope,
+    Envelope, Flags, MessagePriority,
 };
 
 /// Represents the raw envelope returned by the `maildir` crate.
 pub type RawEnvelope = maildir::MailEntry;
 
 pub fn from_raw(mut entry: RawEnvelope) -> Result<Envelope> {
+    let internal_id = entry.id().to_owned();
+    let flags = flags::from_raw(&entry);
+    let size = fs::metadata(entry.path()).ok().map(|m| m.len());
+    let parsed_mail = entry.parsed().map_err(Error::ParseMsgError)?;
+    let mut envelope = from_headers(internal_id, flags, &parsed_mail.headers)?;
+    envelope.size = size;
+    Ok(envelope)
+}
+
+/// Builds the envelope from already parsed headers, which allows
+/// callers to build envelopes without loading the email body.
+pub fn from_headers(internal_id: String, flags: Flags, headers: &[MailHeader]) -> Result<Envelope> {
     let mut envelope = Envelope::default();
 
-    envelope.internal_id = entry.id().to_owned();
-    envelope.flags = flags::from_raw(&entry);
+    envelope.internal_id = internal_id;
+    envelope.flags = flags;
 
-    let parsed_mail = entry.parsed().map_err(Error::ParseMsgError)?;
+    let mut x_priority = None;
+    let mut importance = None;
 
-    for header in parsed_mail.get_headers() {
+    for header in headers {
         let key = header.get_key();
         trace!("header key: {}", key);
 
@@ -56,10 +70,18 @@ pub fn from_raw(mut entry: RawEnvelope) -> Result<Envelope> {
                     .and_then(|date| date.and_local_timezone(Local).earliest());
                 envelope.date = date.unwrap_or_default()
             }
+            "x-priority" => {
+                x_priority = Some(val);
+            }
+            "importance" => {
+                importance = Some(val);
+            }
             _ => (),
         }
     }
 
+    envelope.priority = MessagePriority::from_headers(x_priority, importance);
+
     trace!("maildir envelope: {:?}", envelope);
 
     Ok(envelope)
@@ -2,11 +2,15 @@
 //!
 //! This module provides Maildir types and conversion utilities
 //! related to the envelope.
+use log::warn;
+use maildir::MailEntry;
 use rayon::prelude::*;
+use std::io;
 
 use crate::{
     backend::maildir::{Error, Result},
-    Envelopes,
+    domain::flag::maildir::flags,
+    Envelope, Envelopes,
 };
 
 use super::envelope;
@@ -15,16 +19,39 @@ use super::envelope;
 /// crate.
 pub type RawEnvelopes = maildir::MailEntries;
 
+/// Builds the envelopes from the given entries. Entries which cannot
+/// be parsed give a placeholder envelope (see
+/// [`Envelope::unparsable`]), identified by their Maildir id, instead
+/// of making the whole listing fail.
 pub fn from_raws(entries: RawEnvelopes) -> Result<Envelopes> {
+    from_entries(entries)
+}
+
+/// Builds the envelopes from the given entries like [`from_raws`],
+/// for example from a chunk of [`RawEnvelopes`].
+pub fn from_entries<I>(entries: I) -> Result<Envelopes>
+where
+    I: IntoIterator<Item = io::Result<MailEntry>>,
+{
     Ok(Envelopes::from_iter(
         // TODO: clean me please
         entries
+            .into_iter()
             .collect::<Vec<_>>()
             .into_par_iter()
             .map(|entry| entry.map_err(Error::DecodeEntryError))
             .collect::<Result<Vec<_>>>()?
             .into_par_iter()
-            .map(|entry| envelope::from_raw(entry))
-            .collect::<Result<Vec<_>>>()?,
+            .map(|entry| {
+                let internal_id = entry.id().to_owned();
+                let flags = flags::from_raw(&entry);
+                envelope::from_raw(entry).unwrap_or_else(|err| {
+                    warn!(
+                        "cannot parse maildir envelope {internal_id}, using a placeholder: {err}"
+                    );
+                    Envelope::unparsable(internal_id.clone(), flags, &internal_id, err)
+                })
+            })
+            .collect::<Vec<_>>(),
     ))
 }
@@ -2,10 +2,14 @@ pub mod envelope;
 pub mod envelopes;
 #[cfg(feature = "imap-backend")]
 pub mod imap;
+#[cfg(feature = "jmap-backend")]
+pub mod jmap;
 #[cfg(feature = "maildir-backend")]
 pub mod maildir;
 #[cfg(feature = "notmuch-backend")]
 pub mod notmuch;
+#[cfg(feature = "pop3-backend")]
+pub mod pop3;
 pub mod sync;
 
 pub use self::envelope::*;
@@ -6,11 +6,12 @@
 use chrono::{Local, NaiveDateTime};
 use log::{info, trace};
 use notmuch;
+use std::fs;
 
 use crate::{
     backend::notmuch::{Error, Result},
     envelope::Mailbox,
-    Envelope, Flag,
+    Envelope, Flag, MessagePriority,
 };
 
 /// Represents the raw envelope returned by the `notmuch` crate.
@@ -61,6 +62,13 @@ pub fn from_raw(raw: RawEnvelope) -> Result<Envelope> {
         date.unwrap_or_default()
     };
 
+    let priority = MessagePriority::from_headers(
+        raw.header("x-priority")
+            .map_err(|err| Error::ParseMsgHeaderError(err, String::from("x-priority")))?,
+        raw.header("importance")
+            .map_err(|err| Error::ParseMsgHeaderError(err, String::from("importance")))?,
+    );
+
     let envelope = Envelope {
         id: String::new(),
         internal_id,
@@ -69,6 +77,9 @@ pub fn from_raw(raw: RawEnvelope) -> Result<Envelope> {
         subject,
         from,
         date,
+        priority,
+        size: fs::metadata(raw.filename()).ok().map(|m| m.len()),
+        parse_error: None,
     };
     trace!("envelope: {:?}", envelope);
 
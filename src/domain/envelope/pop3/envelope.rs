@@ -0,0 +1,65 @@
+//! POP3 envelope module.
+//!
+//! This module contains the function to build envelopes from the
+//! header block returned by the POP3 `TOP` command.
+
+use chrono::{DateTime, Local, NaiveDateTime};
+use log::trace;
+use mailparse::{MailAddr, MailHeaderMap};
+
+use crate::{
+    backend::pop3::{Error, Result},
+    envelope::Mailbox,
+    Envelope, Flags, MessagePriority,
+};
+
+/// Builds the envelope of the message matching the given unique id
+/// from its header block. POP3 has no flags, so envelopes are built
+/// without any.
+pub fn from_raw(id: String, size: u64, headers: &[u8]) -> Result<Envelope> {
+    let (headers, _) = mailparse::parse_headers(headers)
+        .map_err(|err| Error::ParseHeadersError(err, id.clone()))?;
+
+    let from = headers
+        .get_all_headers("From")
+        .first()
+        .and_then(|header| mailparse::addrparse_header(header).ok())
+        .and_then(|addrs| match addrs.first() {
+            Some(MailAddr::Single(single)) => Some(Mailbox::new(
+                single.display_name.clone(),
+                single.addr.clone(),
+            )),
+            _ => None,
+        })
+        .unwrap_or_default();
+
+    let date = headers
+        .get_first_value("Date")
+        .and_then(|date| mailparse::dateparse(&date).ok())
+        .and_then(|timestamp| NaiveDateTime::from_timestamp_opt(timestamp, 0))
+        .and_then(|date| date.and_local_timezone(Local).earliest())
+        .unwrap_or_else(DateTime::default);
+
+    let envelope = Envelope {
+        id: id.clone(),
+        internal_id: id,
+        message_id: headers
+            .get_first_value("Message-ID")
+            .unwrap_or_default()
+            .trim()
+            .to_owned(),
+        flags: Flags::default(),
+        from,
+        subject: headers.get_first_value("Subject").unwrap_or_default(),
+        date,
+        priority: MessagePriority::from_headers(
+            headers.get_first_value("X-Priority"),
+            headers.get_first_value("Importance"),
+        ),
+        size: Some(size),
+        parse_error: None,
+    };
+    trace!("pop3 envelope: {envelope:?}");
+
+    Ok(envelope)
+}
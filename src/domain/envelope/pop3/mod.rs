@@ -0,0 +1,3 @@
+pub mod envelope;
+
+pub use envelope::*;
@@ -1,10 +1,11 @@
-use chrono::{DateTime, Local};
-use log::warn;
-use rusqlite::types::Value;
+use chrono::{DateTime, Local, Utc};
+use log::{debug, warn};
+use rusqlite::{types::Value, OptionalExtension};
+use std::{collections::HashSet, fs, path::Path, str::FromStr};
 
-use crate::{envelope::Mailbox, Envelope, Envelopes};
+use crate::{envelope::Mailbox, Envelope, Envelopes, Flags, MessagePriority};
 
-use super::Result;
+use super::{CacheHunk, Error, HunkKindRestricted, Result};
 
 const CREATE_ENVELOPES_TABLE: &str = "
     CREATE TABLE IF NOT EXISTS envelopes (
@@ -21,9 +22,132 @@ const CREATE_ENVELOPES_TABLE: &str = "
     )
 ";
 
+/// Schema migrations of the envelopes table, the index in the array
+/// being the version the migration upgrades from. The current version
+/// is stored in the SQLite `user_version` pragma.
+const MIGRATIONS: [&str; 9] = [
+    // v0 → v1: add indices matching the select and delete queries
+    "
+    CREATE INDEX IF NOT EXISTS envelopes_account_folder_message_id
+    ON envelopes (account, folder, message_id);
+    CREATE INDEX IF NOT EXISTS envelopes_account_folder_internal_id
+    ON envelopes (account, folder, internal_id);
+    ",
+    // v1 → v2: add the priority, stored as its X-Priority value
+    "
+    ALTER TABLE envelopes ADD COLUMN priority INTEGER DEFAULT NULL;
+    ",
+    // v2 → v3: add the partial envelopes, stored apart so that flags
+    // changes do not lose them
+    "
+    CREATE TABLE IF NOT EXISTS partial_envelopes (
+        account    TEXT    NOT NULL,
+        folder     TEXT    NOT NULL,
+        message_id TEXT    NOT NULL,
+        size       INTEGER NOT NULL,
+        UNIQUE(account, folder, message_id)
+    );
+    ",
+    // v3 → v4: add the synchronization state of the folders
+    "
+    CREATE TABLE IF NOT EXISTS sync_state (
+        account        TEXT NOT NULL,
+        folder         TEXT NOT NULL,
+        last_synced_at TEXT NOT NULL,
+        UNIQUE(account, folder)
+    );
+    ",
+    // v4 → v5: add the size of the emails, in bytes
+    "
+    ALTER TABLE envelopes ADD COLUMN size INTEGER DEFAULT NULL;
+    ",
+    // v5 → v6: merge the rows of the inbox stored with a non canonical
+    // casing (see [`crate::canonical_folder_name`]), rows conflicting
+    // with canonical ones being dropped. Envelopes without flags are
+    // stored with a NULL flag, which the unique constraint never
+    // considers as duplicate, so they are deduplicated by hand. The
+    // folders cache has no version of its own, so its table is
+    // merged here as well.
+    "
+    DELETE FROM envelopes
+    WHERE folder <> 'INBOX' AND UPPER(folder) = 'INBOX'
+    AND EXISTS (
+        SELECT 1 FROM envelopes AS canonical
+        WHERE canonical.account = envelopes.account
+        AND canonical.folder = 'INBOX'
+        AND canonical.internal_id = envelopes.internal_id
+        AND canonical.message_id = envelopes.message_id
+    );
+    UPDATE OR IGNORE envelopes SET folder = 'INBOX'
+    WHERE folder <> 'INBOX' AND UPPER(folder) = 'INBOX';
+    DELETE FROM envelopes
+    WHERE folder <> 'INBOX' AND UPPER(folder) = 'INBOX';
+    DELETE FROM envelopes
+    WHERE folder = 'INBOX' AND flag IS NULL
+    AND rowid NOT IN (
+        SELECT MIN(rowid) FROM envelopes
+        WHERE folder = 'INBOX' AND flag IS NULL
+        GROUP BY account, internal_id, message_id
+    );
+    UPDATE OR IGNORE partial_envelopes SET folder = 'INBOX'
+    WHERE folder <> 'INBOX' AND UPPER(folder) = 'INBOX';
+    DELETE FROM partial_envelopes
+    WHERE folder <> 'INBOX' AND UPPER(folder) = 'INBOX';
+    UPDATE OR IGNORE sync_state SET folder = 'INBOX'
+    WHERE folder <> 'INBOX' AND UPPER(folder) = 'INBOX';
+    DELETE FROM sync_state
+    WHERE folder <> 'INBOX' AND UPPER(folder) = 'INBOX';
+    CREATE TABLE IF NOT EXISTS folders (
+        account TEXT NOT NULL,
+        name    TEXT NOT NULL,
+        UNIQUE(name, account)
+    );
+    UPDATE OR IGNORE folders SET name = 'INBOX'
+    WHERE name <> 'INBOX' AND UPPER(name) = 'INBOX';
+    DELETE FROM folders
+    WHERE name <> 'INBOX' AND UPPER(name) = 'INBOX';
+    ",
+    // v6 → v7: add the journal of the cache hunks of the hunk groups
+    // processed by an unfinished synchronization
+    "
+    CREATE TABLE IF NOT EXISTS sync_journal (
+        account     TEXT    NOT NULL,
+        folder      TEXT    NOT NULL,
+        cache_hunks TEXT    NOT NULL,
+        applied     INTEGER NOT NULL DEFAULT 0
+    );
+    ",
+    // v7 → v8: store the display name of the sender next to its
+    // address
+    "
+    ALTER TABLE envelopes ADD COLUMN sender_name TEXT DEFAULT NULL;
+    ",
+    // v8 → v9: add the highest modification sequence of the remote
+    // folders at their last synchronization
+    "
+    CREATE TABLE IF NOT EXISTS sync_modseq (
+        account TEXT    NOT NULL,
+        folder  TEXT    NOT NULL,
+        modseq  INTEGER NOT NULL,
+        UNIQUE(account, folder)
+    );
+    ",
+];
+
 const INSERT_ENVELOPE: &str = "
     INSERT INTO envelopes
-    VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+    VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+";
+
+const DELETE_ENVELOPES: &str = "
+    DELETE FROM envelopes
+    WHERE account IN (?, ?)
+";
+
+const DELETE_FOLDER_ENVELOPES: &str = "
+    DELETE FROM envelopes
+    WHERE account = ?
+    AND folder = ?
 ";
 
 const DELETE_ENVELOPE: &str = "
@@ -33,25 +157,189 @@ const DELETE_ENVELOPE: &str = "
     AND internal_id = ?
 ";
 
+/// Queries moving the rows of a folder to another folder, in every
+/// table of the cache.
+const RENAME_FOLDER: [&str; 4] = [
+    "UPDATE envelopes SET folder = ?3 WHERE account = ?1 AND folder = ?2",
+    "UPDATE partial_envelopes SET folder = ?3 WHERE account = ?1 AND folder = ?2",
+    "UPDATE sync_state SET folder = ?3 WHERE account = ?1 AND folder = ?2",
+    "UPDATE sync_journal SET folder = ?3 WHERE account = ?1 AND folder = ?2",
+];
+
+/// Queries moving the rows of an envelope to another folder, the
+/// partial envelope first since it is matched by message id.
+const MOVE_ENVELOPE: [&str; 2] = [
+    "UPDATE partial_envelopes SET folder = ?3 WHERE account = ?1 AND folder = ?2 AND message_id IN (SELECT message_id FROM envelopes WHERE account = ?1 AND folder = ?2 AND internal_id = ?4)",
+    "UPDATE envelopes SET folder = ?3 WHERE account = ?1 AND folder = ?2 AND internal_id = ?4",
+];
+
+const INSERT_PARTIAL_ENVELOPE: &str = "
+    INSERT OR REPLACE INTO partial_envelopes
+    VALUES (?, ?, ?, ?)
+";
+
+const DELETE_PARTIAL_ENVELOPE: &str = "
+    DELETE FROM partial_envelopes
+    WHERE account = ?
+    AND folder = ?
+    AND message_id = ?
+";
+
+const SELECT_PARTIAL_ENVELOPE: &str = "
+    SELECT COUNT(*)
+    FROM partial_envelopes
+    WHERE account = ?
+    AND folder = ?
+    AND message_id = ?
+";
+
+const INSERT_SYNC_STATE: &str = "
+    INSERT OR REPLACE INTO sync_state
+    VALUES (?, ?, ?)
+";
+
+const DELETE_SYNC_STATE: &str = "
+    DELETE FROM sync_state
+    WHERE account = ?
+    AND folder = ?
+";
+
+const SELECT_SYNC_STATE: &str = "
+    SELECT last_synced_at
+    FROM sync_state
+    WHERE account = ?
+";
+
+const INSERT_MODSEQ: &str = "
+    INSERT OR REPLACE INTO sync_modseq
+    VALUES (?, ?, ?)
+";
+
+const DELETE_MODSEQ: &str = "
+    DELETE FROM sync_modseq
+    WHERE account = ?
+    AND folder = ?
+";
+
+const SELECT_MODSEQ: &str = "
+    SELECT modseq
+    FROM sync_modseq
+    WHERE account = ?
+    AND folder = ?
+";
+
+const INSERT_JOURNAL_ENTRY: &str = "
+    INSERT INTO sync_journal (account, folder, cache_hunks)
+    VALUES (?, ?, ?)
+";
+
+const UPDATE_JOURNAL_ENTRY_APPLIED: &str = "
+    UPDATE sync_journal
+    SET applied = 1
+    WHERE rowid = ?
+";
+
+const DELETE_JOURNAL: &str = "
+    DELETE FROM sync_journal
+    WHERE account = ?
+    AND folder = ?
+";
+
+const SELECT_JOURNAL: &str = "
+    SELECT rowid, cache_hunks
+    FROM sync_journal
+    WHERE account = ?
+    AND folder = ?
+    AND applied = 0
+    ORDER BY rowid
+";
+
 const SELECT_ENVELOPES: &str = "
-    SELECT id, internal_id, message_id, account, folder, GROUP_CONCAT(flag, ' ') AS flags, sender, subject, date
+    SELECT id, internal_id, message_id, account, folder, GROUP_CONCAT(flag, ' ') AS flags, sender, subject, date, priority, size, sender_name
     FROM envelopes
     WHERE account = ?
     AND folder = ?
     GROUP BY message_id
-    ORDER BY date DESC
+    ORDER BY datetime(date) IS NULL, datetime(date) DESC, MIN(rowid)
 ";
 
+const SELECT_ENVELOPES_SINCE: &str = "
+    SELECT id, internal_id, message_id, account, folder, GROUP_CONCAT(flag, ' ') AS flags, sender, subject, date, priority, size, sender_name
+    FROM envelopes
+    WHERE account = ?
+    AND folder = ?
+    AND datetime(date) >= datetime(?)
+    GROUP BY message_id
+    ORDER BY datetime(date) DESC, MIN(rowid)
+";
+
+const SELECT_ENVELOPE: &str = "
+    SELECT id, internal_id, message_id, account, folder, GROUP_CONCAT(flag, ' ') AS flags, sender, subject, date, priority, size, sender_name
+    FROM envelopes
+    WHERE account = ?
+    AND folder = ?
+    AND internal_id = ?
+    GROUP BY message_id
+";
+
+const SELECT_ALL_ENVELOPES: &str = "
+    SELECT folder, internal_id, message_id, sender, subject, date, GROUP_CONCAT(flag, ' ') AS flags, sender_name
+    FROM envelopes
+    WHERE account = ?
+    GROUP BY folder, message_id
+    ORDER BY folder, date DESC
+";
+
+/// Columns of the CSV files written by [`Cache::export_csv`].
+const CSV_HEADER: [&str; 7] = [
+    "folder",
+    "internal_id",
+    "message_id",
+    "sender",
+    "subject",
+    "date",
+    "flags",
+];
+
 pub struct Cache;
 
 impl Cache {
     const LOCAL_SUFFIX: &str = ":cache";
 
+    /// Creates the envelopes table if it does not exist yet, then
+    /// applies pending schema migrations.
     pub fn init(conn: &mut rusqlite::Connection) -> Result<()> {
         conn.execute(CREATE_ENVELOPES_TABLE, ())?;
+
+        let version: usize = conn.query_row("PRAGMA user_version", (), |row| row.get(0))?;
+        if version < MIGRATIONS.len() {
+            debug!(
+                "migrating envelopes cache from v{version} to v{}",
+                MIGRATIONS.len()
+            );
+            let tx = conn.transaction()?;
+            for migration in &MIGRATIONS[version..] {
+                tx.execute_batch(migration)?;
+            }
+            tx.pragma_update(None, "user_version", MIGRATIONS.len())?;
+            tx.commit()?;
+        }
+
         Ok(())
     }
 
+    /// Rebuilds the database file, reclaiming the space left by
+    /// deleted envelopes.
+    pub fn vacuum(conn: &mut rusqlite::Connection) -> Result<()> {
+        Ok(crate::sqlite::vacuum(conn)?)
+    }
+
+    /// Checks the integrity of the database and returns the list of
+    /// problems found. An empty list means the database is sane.
+    pub fn integrity_check(conn: &mut rusqlite::Connection) -> Result<Vec<String>> {
+        Ok(crate::sqlite::integrity_check(conn)?)
+    }
+
     fn list_envelopes<A, F>(
         conn: &mut rusqlite::Connection,
         account: A,
@@ -63,35 +351,15 @@ impl Cache {
     {
         let mut stmt = conn.prepare(SELECT_ENVELOPES)?;
         let envelopes: Vec<Envelope> = stmt
-            .query_map([account.as_ref(), folder.as_ref()], |row| {
-                Ok(Envelope {
-                    id: row.get(0)?,
-                    internal_id: row.get(1)?,
-                    message_id: row.get(2)?,
-                    flags: row
-                        .get::<usize, Option<String>>(5)?
-                        .unwrap_or_default()
-                        .as_str()
-                        .into(),
-                    from: Mailbox::new_nameless(row.get::<usize, String>(6)?),
-                    subject: row.get(7)?,
-                    date: {
-                        let date: String = row.get(8)?;
-                        match DateTime::parse_from_rfc3339(&date) {
-                            Ok(date) => date.with_timezone(&Local),
-                            Err(err) => {
-                                warn!("invalid date {}, skipping it: {}", date, err);
-                                DateTime::default()
-                            }
-                        }
-                    },
-                })
-            })?
+            .query_map([account.as_ref(), folder.as_ref()], envelope_from_row)?
             .collect::<rusqlite::Result<_>>()?;
 
         Ok(Envelopes::from_iter(envelopes))
     }
 
+    /// Lists the cached envelopes of the given folder, the most
+    /// recent first. Envelopes sharing the same date, or whose date
+    /// cannot be read, are listed in insertion order.
     pub fn list_local_envelopes<N, F>(
         conn: &mut rusqlite::Connection,
         name: N,
@@ -104,6 +372,8 @@ impl Cache {
         Self::list_envelopes(conn, name.to_string() + Self::LOCAL_SUFFIX, folder)
     }
 
+    /// Lists the cached envelopes of the given folder, like
+    /// [`Cache::list_local_envelopes`].
     pub fn list_remote_envelopes<N, F>(
         conn: &mut rusqlite::Connection,
         name: N,
@@ -116,6 +386,36 @@ impl Cache {
         Self::list_envelopes(conn, name, folder)
     }
 
+    /// Lists the cached envelopes of the given folder and side whose
+    /// date is not older than the given one, the most recent first.
+    /// This allows incremental reads of the cache.
+    pub fn list_envelopes_since<N, F>(
+        conn: &mut rusqlite::Connection,
+        name: N,
+        folder: F,
+        side: HunkKindRestricted,
+        since: DateTime<Utc>,
+    ) -> Result<Envelopes>
+    where
+        N: ToString,
+        F: AsRef<str>,
+    {
+        let account = match side {
+            HunkKindRestricted::Local => name.to_string() + Self::LOCAL_SUFFIX,
+            HunkKindRestricted::Remote => name.to_string(),
+        };
+
+        let mut stmt = conn.prepare(SELECT_ENVELOPES_SINCE)?;
+        let envelopes: Vec<Envelope> = stmt
+            .query_map(
+                [account.as_str(), folder.as_ref(), &since.to_rfc3339()],
+                envelope_from_row,
+            )?
+            .collect::<rusqlite::Result<_>>()?;
+
+        Ok(Envelopes::from_iter(envelopes))
+    }
+
     fn insert_envelope<A, F>(
         transaction: &rusqlite::Transaction,
         account: A,
@@ -139,6 +439,9 @@ impl Cache {
                     &envelope.from.addr,
                     &envelope.subject,
                     envelope.date.to_rfc3339(),
+                    envelope.priority.map(|p| p.to_x_priority()),
+                    envelope.size,
+                    &envelope.from.name,
                 ),
             )?;
         } else {
@@ -151,10 +454,13 @@ impl Cache {
                         &envelope.message_id,
                         account.as_ref(),
                         folder.as_ref(),
-                        flag.to_string(),
+                        flag.to_token(),
                         &envelope.from.addr,
                         &envelope.subject,
                         envelope.date.to_rfc3339(),
+                        envelope.priority.map(|p| p.to_x_priority()),
+                        envelope.size,
+                        &envelope.from.name,
                     ),
                 )?;
             }
@@ -239,4 +545,1094 @@ impl Cache {
     {
         Self::delete_envelope(tx, name, folder, internal_id)
     }
+
+    /// Replaces the flags of the cached envelope matching the given
+    /// internal id. Does nothing if the envelope is not cached.
+    fn set_flags<A, F, I>(
+        tx: &rusqlite::Transaction,
+        account: A,
+        folder: F,
+        internal_id: I,
+        flags: &Flags,
+    ) -> Result<()>
+    where
+        A: AsRef<str>,
+        F: AsRef<str>,
+        I: AsRef<str>,
+    {
+        let params = [account.as_ref(), folder.as_ref(), internal_id.as_ref()];
+        let envelope = tx
+            .query_row(SELECT_ENVELOPE, params, envelope_from_row)
+            .optional()?;
+
+        if let Some(envelope) = envelope {
+            tx.execute(DELETE_ENVELOPE, params)?;
+            let envelope = Envelope {
+                flags: flags.clone(),
+                ..envelope
+            };
+            Self::insert_envelope(tx, account, folder, envelope)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn set_local_flags<N, F, I>(
+        tx: &rusqlite::Transaction,
+        name: N,
+        folder: F,
+        internal_id: I,
+        flags: &Flags,
+    ) -> Result<()>
+    where
+        N: ToString,
+        F: AsRef<str>,
+        I: AsRef<str>,
+    {
+        Self::set_flags(
+            tx,
+            name.to_string() + Self::LOCAL_SUFFIX,
+            folder,
+            internal_id,
+            flags,
+        )
+    }
+
+    pub fn set_remote_flags<N, F, I>(
+        tx: &rusqlite::Transaction,
+        name: N,
+        folder: F,
+        internal_id: I,
+        flags: &Flags,
+    ) -> Result<()>
+    where
+        N: AsRef<str>,
+        F: AsRef<str>,
+        I: AsRef<str>,
+    {
+        Self::set_flags(tx, name, folder, internal_id, flags)
+    }
+
+    /// Deletes both the local and the remote envelopes of the given
+    /// account, for example before rebuilding the cache. The partial
+    /// envelopes and the synchronization state are kept.
+    pub fn clear<N>(tx: &rusqlite::Transaction, name: N) -> Result<()>
+    where
+        N: ToString,
+    {
+        let name = name.to_string();
+        let local_name = name.clone() + Self::LOCAL_SUFFIX;
+        tx.execute(DELETE_ENVELOPES, [&local_name, &name])?;
+        Ok(())
+    }
+
+    fn rename_folder<A, F, T>(
+        tx: &rusqlite::Transaction,
+        account: A,
+        from_folder: F,
+        to_folder: T,
+    ) -> Result<()>
+    where
+        A: AsRef<str>,
+        F: AsRef<str>,
+        T: AsRef<str>,
+    {
+        let params = [account.as_ref(), from_folder.as_ref(), to_folder.as_ref()];
+        for query in RENAME_FOLDER {
+            tx.execute(query, params)?;
+        }
+        Ok(())
+    }
+
+    /// Moves the local envelopes of the given folder to another
+    /// folder, typically after a folder rename.
+    pub fn rename_local_folder<N, F, T>(
+        tx: &rusqlite::Transaction,
+        name: N,
+        from_folder: F,
+        to_folder: T,
+    ) -> Result<()>
+    where
+        N: ToString,
+        F: AsRef<str>,
+        T: AsRef<str>,
+    {
+        Self::rename_folder(
+            tx,
+            name.to_string() + Self::LOCAL_SUFFIX,
+            from_folder,
+            to_folder,
+        )
+    }
+
+    /// Moves the remote envelopes of the given folder to another
+    /// folder, typically after a folder rename.
+    pub fn rename_remote_folder<N, F, T>(
+        tx: &rusqlite::Transaction,
+        name: N,
+        from_folder: F,
+        to_folder: T,
+    ) -> Result<()>
+    where
+        N: AsRef<str>,
+        F: AsRef<str>,
+        T: AsRef<str>,
+    {
+        Self::rename_folder(tx, name, from_folder, to_folder)
+    }
+
+    fn move_envelope<A, F, T, I>(
+        tx: &rusqlite::Transaction,
+        account: A,
+        from_folder: F,
+        to_folder: T,
+        internal_id: I,
+    ) -> Result<()>
+    where
+        A: AsRef<str>,
+        F: AsRef<str>,
+        T: AsRef<str>,
+        I: AsRef<str>,
+    {
+        let params = [
+            account.as_ref(),
+            from_folder.as_ref(),
+            to_folder.as_ref(),
+            internal_id.as_ref(),
+        ];
+        for query in MOVE_ENVELOPE {
+            tx.execute(query, params)?;
+        }
+        Ok(())
+    }
+
+    /// Moves the local envelope matching the given internal id to
+    /// another folder, typically after the email has been moved.
+    pub fn move_local_envelope<N, F, T, I>(
+        tx: &rusqlite::Transaction,
+        name: N,
+        from_folder: F,
+        to_folder: T,
+        internal_id: I,
+    ) -> Result<()>
+    where
+        N: ToString,
+        F: AsRef<str>,
+        T: AsRef<str>,
+        I: AsRef<str>,
+    {
+        Self::move_envelope(
+            tx,
+            name.to_string() + Self::LOCAL_SUFFIX,
+            from_folder,
+            to_folder,
+            internal_id,
+        )
+    }
+
+    /// Moves the remote envelope matching the given internal id to
+    /// another folder, typically after the email has been moved.
+    pub fn move_remote_envelope<N, F, T, I>(
+        tx: &rusqlite::Transaction,
+        name: N,
+        from_folder: F,
+        to_folder: T,
+        internal_id: I,
+    ) -> Result<()>
+    where
+        N: AsRef<str>,
+        F: AsRef<str>,
+        T: AsRef<str>,
+        I: AsRef<str>,
+    {
+        Self::move_envelope(tx, name, from_folder, to_folder, internal_id)
+    }
+
+    /// Marks the local envelope matching the given message id as
+    /// partial, `size` being the size of the original email, or
+    /// unmarks it if `size` is `None`.
+    pub fn set_local_partial<N, F, M>(
+        tx: &rusqlite::Transaction,
+        name: N,
+        folder: F,
+        message_id: M,
+        size: Option<u64>,
+    ) -> Result<()>
+    where
+        N: ToString,
+        F: AsRef<str>,
+        M: AsRef<str>,
+    {
+        let account = name.to_string() + Self::LOCAL_SUFFIX;
+        match size {
+            Some(size) => tx.execute(
+                INSERT_PARTIAL_ENVELOPE,
+                (&account, folder.as_ref(), message_id.as_ref(), size),
+            )?,
+            None => tx.execute(
+                DELETE_PARTIAL_ENVELOPE,
+                [account.as_str(), folder.as_ref(), message_id.as_ref()],
+            )?,
+        };
+        Ok(())
+    }
+
+    /// Returns `true` if the local envelope matching the given
+    /// message id is partial.
+    pub fn is_local_partial<N, F, M>(
+        conn: &rusqlite::Connection,
+        name: N,
+        folder: F,
+        message_id: M,
+    ) -> Result<bool>
+    where
+        N: ToString,
+        F: AsRef<str>,
+        M: AsRef<str>,
+    {
+        let account = name.to_string() + Self::LOCAL_SUFFIX;
+        let count: usize = conn.query_row(
+            SELECT_PARTIAL_ENVELOPE,
+            [account.as_str(), folder.as_ref(), message_id.as_ref()],
+            |row| row.get(0),
+        )?;
+        Ok(count > 0)
+    }
+
+    /// Saves the date of the last successful synchronization of the
+    /// given folder.
+    pub fn set_last_synced_at<A, F>(
+        tx: &rusqlite::Transaction,
+        account: A,
+        folder: F,
+        date: DateTime<Utc>,
+    ) -> Result<()>
+    where
+        A: AsRef<str>,
+        F: AsRef<str>,
+    {
+        tx.execute(
+            INSERT_SYNC_STATE,
+            [account.as_ref(), folder.as_ref(), &date.to_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    /// Forgets the date of the last synchronization of the given
+    /// folder, typically after its deletion.
+    pub fn delete_last_synced_at<A, F>(
+        tx: &rusqlite::Transaction,
+        account: A,
+        folder: F,
+    ) -> Result<()>
+    where
+        A: AsRef<str>,
+        F: AsRef<str>,
+    {
+        tx.execute(DELETE_SYNC_STATE, [account.as_ref(), folder.as_ref()])?;
+        Ok(())
+    }
+
+    /// Gets the date of the last successful synchronization of the
+    /// given account, which is the one of its least recently
+    /// synchronized folder. Returns `None` if no folder has been
+    /// synchronized yet.
+    pub fn get_last_synced_at<A>(
+        conn: &rusqlite::Connection,
+        account: A,
+    ) -> Result<Option<DateTime<Utc>>>
+    where
+        A: AsRef<str>,
+    {
+        let mut stmt = conn.prepare(SELECT_SYNC_STATE)?;
+        let dates: Vec<String> = stmt
+            .query_map([account.as_ref()], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+
+        let date = dates
+            .iter()
+            .filter_map(|date| match DateTime::parse_from_rfc3339(date) {
+                Ok(date) => Some(date.with_timezone(&Utc)),
+                Err(err) => {
+                    warn!("invalid sync date {}, skipping it: {}", date, err);
+                    None
+                }
+            })
+            .min();
+
+        Ok(date)
+    }
+
+    /// Saves the highest modification sequence of the given remote
+    /// folder at its last successful synchronization (see
+    /// [`crate::Backend::highest_modseq`]).
+    pub fn set_modseq<A, F>(
+        tx: &rusqlite::Transaction,
+        account: A,
+        folder: F,
+        modseq: u64,
+    ) -> Result<()>
+    where
+        A: AsRef<str>,
+        F: AsRef<str>,
+    {
+        tx.execute(
+            INSERT_MODSEQ,
+            rusqlite::params![account.as_ref(), folder.as_ref(), modseq as i64],
+        )?;
+        Ok(())
+    }
+
+    /// Forgets the highest modification sequence of the given remote
+    /// folder, so that its next synchronization lists all its
+    /// envelopes.
+    pub fn delete_modseq<A, F>(tx: &rusqlite::Transaction, account: A, folder: F) -> Result<()>
+    where
+        A: AsRef<str>,
+        F: AsRef<str>,
+    {
+        tx.execute(DELETE_MODSEQ, [account.as_ref(), folder.as_ref()])?;
+        Ok(())
+    }
+
+    /// Gets the highest modification sequence of the given remote
+    /// folder at its last successful synchronization, if any.
+    pub fn get_modseq<A, F>(
+        conn: &rusqlite::Connection,
+        account: A,
+        folder: F,
+    ) -> Result<Option<u64>>
+    where
+        A: AsRef<str>,
+        F: AsRef<str>,
+    {
+        let modseq: Option<i64> = conn
+            .query_row(SELECT_MODSEQ, [account.as_ref(), folder.as_ref()], |row| {
+                row.get(0)
+            })
+            .optional()?;
+        Ok(modseq.map(|modseq| modseq as u64))
+    }
+
+    /// Records the cache hunks of a fully processed hunk group of the
+    /// given folder, so that they survive an interrupted
+    /// synchronization (see [`Cache::list_journal`]).
+    pub fn journal<A, F>(
+        tx: &rusqlite::Transaction,
+        account: A,
+        folder: F,
+        cache_hunks: &[CacheHunk],
+    ) -> Result<()>
+    where
+        A: AsRef<str>,
+        F: AsRef<str>,
+    {
+        let cache_hunks = serde_json::to_string(cache_hunks).map_err(Error::SerializePatchError)?;
+
+        tx.execute(
+            INSERT_JOURNAL_ENTRY,
+            (account.as_ref(), folder.as_ref(), cache_hunks),
+        )?;
+
+        Ok(())
+    }
+
+    /// Lists the journaled hunk groups of the given folder whose
+    /// cache hunks have not been applied yet, along with their id, in
+    /// the order they were recorded. A non-empty journal means that
+    /// the last synchronization of the folder did not complete.
+    pub fn list_journal<A, F>(
+        conn: &rusqlite::Connection,
+        account: A,
+        folder: F,
+    ) -> Result<Vec<(i64, Vec<CacheHunk>)>>
+    where
+        A: AsRef<str>,
+        F: AsRef<str>,
+    {
+        let mut stmt = conn.prepare(SELECT_JOURNAL)?;
+        let entries: Vec<(i64, String)> = stmt
+            .query_map([account.as_ref(), folder.as_ref()], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+
+        entries
+            .into_iter()
+            .map(|(id, cache_hunks)| {
+                let cache_hunks =
+                    serde_json::from_str(&cache_hunks).map_err(Error::DeserializeJournalError)?;
+                Ok((id, cache_hunks))
+            })
+            .collect()
+    }
+
+    /// Marks the given journaled hunk group as applied, so that a
+    /// repair interrupted in turn does not apply it twice.
+    pub fn set_journal_applied(tx: &rusqlite::Transaction, id: i64) -> Result<()> {
+        tx.execute(UPDATE_JOURNAL_ENTRY_APPLIED, [id])?;
+        Ok(())
+    }
+
+    /// Clears the journal of the given folder, once its cache hunks
+    /// have been applied.
+    pub fn clear_journal<A, F>(tx: &rusqlite::Transaction, account: A, folder: F) -> Result<()>
+    where
+        A: AsRef<str>,
+        F: AsRef<str>,
+    {
+        tx.execute(DELETE_JOURNAL, [account.as_ref(), folder.as_ref()])?;
+        Ok(())
+    }
+
+    fn account<N: ToString>(name: N, side: &HunkKindRestricted) -> String {
+        match side {
+            HunkKindRestricted::Local => name.to_string() + Self::LOCAL_SUFFIX,
+            HunkKindRestricted::Remote => name.to_string(),
+        }
+    }
+
+    /// Writes the local or remote cached envelopes of all folders to
+    /// a human-readable CSV file, for debugging purpose. Returns the
+    /// number of exported envelopes.
+    pub fn export_csv<N>(
+        conn: &mut rusqlite::Connection,
+        name: N,
+        path: &Path,
+        side: HunkKindRestricted,
+    ) -> Result<usize>
+    where
+        N: ToString,
+    {
+        let account = Self::account(name, &side);
+        let tx = conn.transaction()?;
+
+        let rows: Vec<[String; 7]> = {
+            let mut stmt = tx.prepare(SELECT_ALL_ENVELOPES)?;
+            let rows = stmt
+                .query_map([&account], |row| {
+                    // the sender is exported with its display name,
+                    // like in the From header
+                    let sender = Mailbox::new(
+                        row.get::<usize, Option<String>>(7)?,
+                        row.get::<usize, String>(3)?,
+                    );
+                    Ok([
+                        row.get(0)?,
+                        row.get(1)?,
+                        row.get(2)?,
+                        sender.to_string(),
+                        row.get(4)?,
+                        row.get(5)?,
+                        row.get::<usize, Option<String>>(6)?.unwrap_or_default(),
+                    ])
+                })?
+                .collect::<rusqlite::Result<_>>()?;
+            rows
+        };
+
+        tx.commit()?;
+
+        let mut csv = csv_record(CSV_HEADER);
+        for row in &rows {
+            csv.push_str(&csv_record(row));
+        }
+        fs::write(path, csv).map_err(|err| Error::WriteCsvError(err, path.to_owned()))?;
+
+        Ok(rows.len())
+    }
+
+    /// Restores the local or remote cached envelopes from a CSV file
+    /// written by [`Cache::export_csv`]. Returns the number of
+    /// imported envelopes.
+    ///
+    /// The existing envelopes of each folder found in the file are
+    /// deleted first, for the given side only. Other folders are left
+    /// untouched. The priority and the size of the envelopes are not
+    /// part of the export, they are restored empty.
+    pub fn import_csv<N>(
+        conn: &mut rusqlite::Connection,
+        name: N,
+        path: &Path,
+        side: HunkKindRestricted,
+    ) -> Result<usize>
+    where
+        N: ToString,
+    {
+        let account = Self::account(name, &side);
+        let content =
+            fs::read_to_string(path).map_err(|err| Error::ReadCsvError(err, path.to_owned()))?;
+        let parse_err =
+            |reason: String, line: usize| Error::ParseCsvError(reason, line, path.into());
+
+        let mut records = parse_csv(&content).into_iter().enumerate();
+        match records.next() {
+            Some((_, header)) if header == CSV_HEADER => (),
+            _ => return Err(parse_err("invalid header".into(), 1)),
+        }
+
+        let mut envelopes = Vec::new();
+        for (index, record) in records {
+            let line = index + 1;
+            let [folder, internal_id, message_id, sender, subject, date, flags]: [String; 7] =
+                record.try_into().map_err(|record: Vec<String>| {
+                    parse_err(format!("expected 7 fields, got {}", record.len()), line)
+                })?;
+            let date = DateTime::parse_from_rfc3339(&date)
+                .map_err(|err| parse_err(format!("invalid date {date}: {err}"), line))?;
+            let flags = Flags::from_str(&flags)
+                .map_err(|err| parse_err(format!("invalid flags {flags}: {err}"), line))?;
+
+            let envelope = Envelope {
+                id: internal_id.clone(),
+                internal_id,
+                message_id,
+                flags,
+                from: Mailbox::parse(sender),
+                subject,
+                date: date.with_timezone(&Local),
+                ..Envelope::default()
+            };
+            envelopes.push((folder, envelope));
+        }
+
+        let tx = conn.transaction()?;
+
+        let folders: HashSet<&String> = envelopes.iter().map(|(folder, _)| folder).collect();
+        for folder in folders {
+            tx.execute(DELETE_FOLDER_ENVELOPES, [&account, folder])?;
+        }
+
+        let count = envelopes.len();
+        for (folder, envelope) in envelopes {
+            Self::insert_envelope(&tx, &account, folder, envelope)?;
+        }
+
+        tx.commit()?;
+
+        Ok(count)
+    }
+}
+
+/// Builds an envelope from a row selected by [`SELECT_ENVELOPES`] or
+/// [`SELECT_ENVELOPE`].
+fn envelope_from_row(row: &rusqlite::Row) -> rusqlite::Result<Envelope> {
+    Ok(Envelope {
+        id: row.get(0)?,
+        internal_id: row.get(1)?,
+        message_id: row.get(2)?,
+        flags: {
+            // flags are stored as canonical tokens, one per row, and
+            // concatenated with spaces
+            let flags = row.get::<usize, Option<String>>(5)?.unwrap_or_default();
+            match Flags::from_str(&flags) {
+                Ok(flags) => flags,
+                Err(err) => {
+                    warn!("invalid flags {}, parsing them leniently: {}", flags, err);
+                    flags.as_str().into()
+                }
+            }
+        },
+        from: Mailbox::new(
+            row.get::<usize, Option<String>>(11)?,
+            row.get::<usize, String>(6)?,
+        ),
+        subject: row.get(7)?,
+        date: {
+            let date: String = row.get(8)?;
+            match DateTime::parse_from_rfc3339(&date) {
+                Ok(date) => date.with_timezone(&Local),
+                Err(err) => {
+                    warn!("invalid date {}, skipping it: {}", date, err);
+                    DateTime::default()
+                }
+            }
+        },
+        priority: row
+            .get::<usize, Option<u8>>(9)?
+            .and_then(|p| MessagePriority::from_x_priority(p.to_string())),
+        size: row.get(10)?,
+        parse_error: None,
+    })
+}
+
+/// Builds a CSV record, quoting the fields when needed.
+fn csv_record<I, S>(fields: I) -> String
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    let fields: Vec<String> = fields
+        .into_iter()
+        .map(|field| {
+            let field = field.as_ref();
+            if field.contains(['"', ',', '\r', '\n']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.to_owned()
+            }
+        })
+        .collect();
+
+    fields.join(",") + "\r\n"
+}
+
+/// Parses CSV records. Quoted fields may contain commas, line breaks
+/// and escaped double quotes.
+fn parse_csv(content: &str) -> Vec<Vec<String>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = content.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => record.push(std::mem::take(&mut field)),
+            '\r' if !quoted => (),
+            '\n' if !quoted => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            c => field.push(c),
+        }
+    }
+
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+
+    records
+}
+
+#[cfg(test)]
+mod envelopes_cache {
+    use chrono::{DateTime, Local, TimeZone, Utc};
+
+    use crate::{envelope::Mailbox, Envelope, Flag, Flags};
+
+    use super::{Cache, CacheHunk, HunkKindRestricted, MIGRATIONS};
+
+    #[test]
+    fn flags_round_trip() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        Cache::init(&mut conn).unwrap();
+
+        let flags = Flags::from_iter([
+            Flag::Seen,
+            Flag::custom("my flag"),
+            Flag::custom("a,b"),
+            Flag::custom("seen"),
+        ]);
+        let envelope = Envelope {
+            id: "1".into(),
+            internal_id: "1".into(),
+            message_id: "<a@localhost>".into(),
+            flags: flags.clone(),
+            ..Envelope::default()
+        };
+
+        let tx = conn.transaction().unwrap();
+        Cache::insert_remote_envelope(&tx, "account", "INBOX", envelope).unwrap();
+        tx.commit().unwrap();
+
+        let envelopes = Cache::list_remote_envelopes(&mut conn, "account", "INBOX").unwrap();
+        assert_eq!(1, envelopes.len());
+        assert_eq!(flags, envelopes[0].flags);
+    }
+
+    #[test]
+    fn list_envelopes_order() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        Cache::init(&mut conn).unwrap();
+
+        let envelope = |id: &str, date: &str| Envelope {
+            id: id.into(),
+            internal_id: id.into(),
+            message_id: format!("<{id}@localhost>"),
+            date: DateTime::parse_from_rfc3339(date)
+                .unwrap()
+                .with_timezone(&Local),
+            ..Envelope::default()
+        };
+
+        // dates in different timezones are compared as instants,
+        // equal dates keep the insertion order
+        let tx = conn.transaction().unwrap();
+        for (id, date) in [
+            ("1", "2023-01-01T10:00:00+00:00"),
+            ("2", "2023-01-01T12:00:00+01:00"),
+            ("3", "2023-01-01T10:30:00-02:00"),
+            ("4", "2023-01-01T10:00:00+00:00"),
+        ] {
+            Cache::insert_local_envelope(&tx, "account", "INBOX", envelope(id, date)).unwrap();
+        }
+        tx.commit().unwrap();
+
+        let ids = |envelopes: crate::Envelopes| {
+            envelopes
+                .iter()
+                .map(|envelope| envelope.id.clone())
+                .collect::<Vec<_>>()
+        };
+
+        let envelopes = Cache::list_local_envelopes(&mut conn, "account", "INBOX").unwrap();
+        assert_eq!(vec!["3", "2", "1", "4"], ids(envelopes));
+
+        let since = DateTime::parse_from_rfc3339("2023-01-01T11:00:00+00:00")
+            .unwrap()
+            .with_timezone(&Utc);
+        let envelopes = Cache::list_envelopes_since(
+            &mut conn,
+            "account",
+            "INBOX",
+            HunkKindRestricted::Local,
+            since,
+        )
+        .unwrap();
+        assert_eq!(vec!["3", "2"], ids(envelopes));
+        assert!(Cache::list_envelopes_since(
+            &mut conn,
+            "account",
+            "INBOX",
+            HunkKindRestricted::Remote,
+            since,
+        )
+        .unwrap()
+        .is_empty());
+    }
+
+    #[test]
+    fn set_flags() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        Cache::init(&mut conn).unwrap();
+
+        let envelope = Envelope {
+            id: "1".into(),
+            internal_id: "1".into(),
+            message_id: "<a@localhost>".into(),
+            flags: Flags::from_iter([Flag::Seen]),
+            subject: "Hello".into(),
+            size: Some(42),
+            ..Envelope::default()
+        };
+
+        let flags = Flags::from_iter([Flag::Answered, Flag::Flagged]);
+        let tx = conn.transaction().unwrap();
+        Cache::insert_remote_envelope(&tx, "account", "INBOX", envelope).unwrap();
+        Cache::set_remote_flags(&tx, "account", "INBOX", "1", &flags).unwrap();
+        Cache::set_remote_flags(&tx, "account", "INBOX", "unknown", &flags).unwrap();
+        tx.commit().unwrap();
+
+        let envelopes = Cache::list_remote_envelopes(&mut conn, "account", "INBOX").unwrap();
+        assert_eq!(1, envelopes.len());
+        assert_eq!(flags, envelopes[0].flags);
+        assert_eq!("Hello", envelopes[0].subject);
+        assert_eq!(Some(42), envelopes[0].size);
+    }
+
+    #[test]
+    fn size_round_trip() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        Cache::init(&mut conn).unwrap();
+
+        let envelope = |message_id: &str, size| Envelope {
+            id: message_id.into(),
+            internal_id: message_id.into(),
+            message_id: message_id.into(),
+            flags: Flags::from_iter([Flag::Seen, Flag::Flagged]),
+            size,
+            ..Envelope::default()
+        };
+
+        let tx = conn.transaction().unwrap();
+        Cache::insert_remote_envelope(&tx, "account", "INBOX", envelope("<a@localhost>", Some(42)))
+            .unwrap();
+        Cache::insert_remote_envelope(&tx, "account", "INBOX", envelope("<b@localhost>", None))
+            .unwrap();
+        tx.commit().unwrap();
+
+        let envelopes = Cache::list_remote_envelopes(&mut conn, "account", "INBOX").unwrap();
+        let size = |message_id: &str| {
+            envelopes
+                .iter()
+                .find(|envelope| envelope.message_id == message_id)
+                .unwrap()
+                .size
+        };
+        assert_eq!(Some(42), size("<a@localhost>"));
+        assert_eq!(None, size("<b@localhost>"));
+    }
+
+    #[test]
+    fn init_merges_inbox_casings() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        Cache::init(&mut conn).unwrap();
+
+        let envelope = |message_id: &str| Envelope {
+            id: message_id.into(),
+            internal_id: message_id.into(),
+            message_id: message_id.into(),
+            ..Envelope::default()
+        };
+
+        let tx = conn.transaction().unwrap();
+        Cache::insert_remote_envelope(&tx, "account", "INBOX", envelope("<a@localhost>")).unwrap();
+        Cache::insert_remote_envelope(&tx, "account", "Inbox", envelope("<a@localhost>")).unwrap();
+        Cache::insert_remote_envelope(&tx, "account", "inbox", envelope("<b@localhost>")).unwrap();
+        Cache::insert_remote_envelope(&tx, "account", "Inbox", envelope("<c@localhost>")).unwrap();
+        Cache::insert_remote_envelope(&tx, "account", "inbox", envelope("<c@localhost>")).unwrap();
+        tx.execute_batch("INSERT INTO folders VALUES ('account', 'INBOX'), ('account', 'Inbox')")
+            .unwrap();
+        tx.commit().unwrap();
+
+        // replay the migration merging the inbox casings, envelopes
+        // without flags being stored with a NULL flag
+        conn.execute_batch(MIGRATIONS[5]).unwrap();
+
+        let envelopes = Cache::list_remote_envelopes(&mut conn, "account", "INBOX").unwrap();
+        assert_eq!(3, envelopes.len());
+        assert!(Cache::list_remote_envelopes(&mut conn, "account", "Inbox")
+            .unwrap()
+            .is_empty());
+        let rows: usize = conn
+            .query_row("SELECT COUNT(*) FROM envelopes", (), |row| row.get(0))
+            .unwrap();
+        assert_eq!(3, rows);
+        let folders: Vec<String> = conn
+            .prepare("SELECT name FROM folders")
+            .unwrap()
+            .query_map((), |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(vec!["INBOX"], folders);
+    }
+
+    #[test]
+    fn init_migrates_schema() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+
+        Cache::init(&mut conn).unwrap();
+        Cache::init(&mut conn).unwrap();
+
+        let version: usize = conn
+            .query_row("PRAGMA user_version", (), |row| row.get(0))
+            .unwrap();
+        assert_eq!(MIGRATIONS.len(), version);
+        assert!(Cache::integrity_check(&mut conn).unwrap().is_empty());
+        Cache::vacuum(&mut conn).unwrap();
+    }
+
+    #[test]
+    fn set_local_partial() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        Cache::init(&mut conn).unwrap();
+
+        let tx = conn.transaction().unwrap();
+        Cache::set_local_partial(&tx, "account", "INBOX", "<a@localhost>", Some(42)).unwrap();
+        Cache::set_local_partial(&tx, "account", "INBOX", "<a@localhost>", Some(42)).unwrap();
+        tx.commit().unwrap();
+        assert!(Cache::is_local_partial(&conn, "account", "INBOX", "<a@localhost>").unwrap());
+        assert!(!Cache::is_local_partial(&conn, "account", "INBOX", "<b@localhost>").unwrap());
+
+        let tx = conn.transaction().unwrap();
+        Cache::set_local_partial(&tx, "account", "INBOX", "<a@localhost>", None).unwrap();
+        tx.commit().unwrap();
+        assert!(!Cache::is_local_partial(&conn, "account", "INBOX", "<a@localhost>").unwrap());
+    }
+
+    #[test]
+    fn move_local_envelope() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        Cache::init(&mut conn).unwrap();
+
+        let envelope = Envelope {
+            id: "1".into(),
+            internal_id: "1".into(),
+            message_id: "<a@localhost>".into(),
+            flags: Flags::from_iter([Flag::Seen, Flag::Flagged]),
+            ..Envelope::default()
+        };
+
+        let tx = conn.transaction().unwrap();
+        Cache::insert_local_envelope(&tx, "account", "INBOX", envelope.clone()).unwrap();
+        Cache::set_local_partial(&tx, "account", "INBOX", "<a@localhost>", Some(42)).unwrap();
+        Cache::move_local_envelope(&tx, "account", "INBOX", "Archives", "1").unwrap();
+        tx.commit().unwrap();
+
+        let envelopes = Cache::list_local_envelopes(&mut conn, "account", "INBOX").unwrap();
+        assert!(envelopes.is_empty());
+        let envelopes = Cache::list_local_envelopes(&mut conn, "account", "Archives").unwrap();
+        assert_eq!(1, envelopes.len());
+        assert_eq!(envelope.flags, envelopes[0].flags);
+        assert!(Cache::is_local_partial(&conn, "account", "Archives", "<a@localhost>").unwrap());
+        assert!(!Cache::is_local_partial(&conn, "account", "INBOX", "<a@localhost>").unwrap());
+    }
+
+    #[test]
+    fn last_synced_at() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        Cache::init(&mut conn).unwrap();
+        assert_eq!(None, Cache::get_last_synced_at(&conn, "account").unwrap());
+
+        let inbox_date = Utc.with_ymd_and_hms(2023, 1, 2, 0, 0, 0).unwrap();
+        let sent_date = Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, 0).unwrap();
+
+        let tx = conn.transaction().unwrap();
+        Cache::set_last_synced_at(&tx, "account", "INBOX", sent_date).unwrap();
+        Cache::set_last_synced_at(&tx, "account", "INBOX", inbox_date).unwrap();
+        Cache::set_last_synced_at(&tx, "account", "Sent", sent_date).unwrap();
+        tx.commit().unwrap();
+        assert_eq!(
+            Some(sent_date),
+            Cache::get_last_synced_at(&conn, "account").unwrap()
+        );
+
+        let tx = conn.transaction().unwrap();
+        Cache::delete_last_synced_at(&tx, "account", "Sent").unwrap();
+        tx.commit().unwrap();
+        assert_eq!(
+            Some(inbox_date),
+            Cache::get_last_synced_at(&conn, "account").unwrap()
+        );
+    }
+
+    #[test]
+    fn modseq() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        Cache::init(&mut conn).unwrap();
+        assert_eq!(None, Cache::get_modseq(&conn, "account", "INBOX").unwrap());
+
+        let tx = conn.transaction().unwrap();
+        Cache::set_modseq(&tx, "account", "INBOX", 42).unwrap();
+        Cache::set_modseq(&tx, "account", "INBOX", u64::MAX >> 1).unwrap();
+        Cache::set_modseq(&tx, "account", "Sent", 7).unwrap();
+        tx.commit().unwrap();
+        assert_eq!(
+            Some(u64::MAX >> 1),
+            Cache::get_modseq(&conn, "account", "INBOX").unwrap()
+        );
+
+        let tx = conn.transaction().unwrap();
+        Cache::delete_modseq(&tx, "account", "INBOX").unwrap();
+        tx.commit().unwrap();
+        assert_eq!(None, Cache::get_modseq(&conn, "account", "INBOX").unwrap());
+        assert_eq!(
+            Some(7),
+            Cache::get_modseq(&conn, "account", "Sent").unwrap()
+        );
+    }
+
+    #[test]
+    fn journal_round_trip() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        Cache::init(&mut conn).unwrap();
+
+        let envelope = Envelope {
+            internal_id: "1".into(),
+            message_id: "<a@localhost>".into(),
+            flags: Flags::from_iter([Flag::Seen]),
+            ..Envelope::default()
+        };
+        let group1 = vec![
+            CacheHunk::InsertEnvelope("INBOX".into(), envelope, HunkKindRestricted::Local),
+            CacheHunk::SetLocalPartial("INBOX".into(), "<a@localhost>".into(), Some(42)),
+        ];
+        let group2 = vec![CacheHunk::DeleteEnvelope(
+            "INBOX".into(),
+            "2".into(),
+            HunkKindRestricted::Remote,
+        )];
+
+        let tx = conn.transaction().unwrap();
+        Cache::journal(&tx, "account", "INBOX", &group1).unwrap();
+        Cache::journal(&tx, "account", "INBOX", &group2).unwrap();
+        tx.commit().unwrap();
+
+        let journal = Cache::list_journal(&conn, "account", "INBOX").unwrap();
+        assert_eq!(2, journal.len());
+        assert_eq!(group1, journal[0].1);
+        assert_eq!(group2, journal[1].1);
+        assert!(Cache::list_journal(&conn, "account", "Sent")
+            .unwrap()
+            .is_empty());
+
+        // applied groups are not listed anymore
+
+        let tx = conn.transaction().unwrap();
+        Cache::set_journal_applied(&tx, journal[0].0).unwrap();
+        tx.commit().unwrap();
+
+        let journal = Cache::list_journal(&conn, "account", "INBOX").unwrap();
+        assert_eq!(vec![(journal[0].0, group2)], journal);
+
+        let tx = conn.transaction().unwrap();
+        Cache::clear_journal(&tx, "account", "INBOX").unwrap();
+        tx.commit().unwrap();
+
+        assert!(Cache::list_journal(&conn, "account", "INBOX")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn csv_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache.csv");
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        Cache::init(&mut conn).unwrap();
+
+        let envelope = Envelope {
+            id: "1".into(),
+            internal_id: "1".into(),
+            message_id: "<a@localhost>".into(),
+            flags: Flags::from_iter([Flag::Seen, Flag::custom("my flag")]),
+            from: Mailbox::new(Some("Doe, John"), "john@localhost"),
+            subject: "Hello, \"world\"\nagain".into(),
+            date: Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, 0).unwrap().into(),
+            ..Envelope::default()
+        };
+
+        let tx = conn.transaction().unwrap();
+        Cache::insert_local_envelope(&tx, "account", "INBOX", envelope.clone()).unwrap();
+        Cache::insert_remote_envelope(&tx, "account", "INBOX", Envelope::default()).unwrap();
+        tx.commit().unwrap();
+
+        let count =
+            Cache::export_csv(&mut conn, "account", &path, HunkKindRestricted::Local).unwrap();
+        assert_eq!(1, count);
+
+        // import replaces the envelopes of the folder, on the given
+        // side only
+        let tx = conn.transaction().unwrap();
+        let other = Envelope {
+            message_id: "<b@localhost>".into(),
+            ..envelope.clone()
+        };
+        Cache::insert_local_envelope(&tx, "account", "INBOX", other).unwrap();
+        tx.commit().unwrap();
+
+        let count =
+            Cache::import_csv(&mut conn, "account", &path, HunkKindRestricted::Local).unwrap();
+        assert_eq!(1, count);
+
+        let envelopes = Cache::list_local_envelopes(&mut conn, "account", "INBOX").unwrap();
+        assert_eq!(1, envelopes.len());
+        assert_eq!("<a@localhost>", envelopes[0].message_id);
+        assert_eq!(envelope.subject, envelopes[0].subject);
+        assert_eq!(envelope.flags, envelopes[0].flags);
+        assert_eq!(envelope.date, envelopes[0].date);
+        assert_eq!(envelope.from.name, envelopes[0].from.name);
+        assert_eq!(envelope.from.addr, envelopes[0].from.addr);
+
+        let envelopes = Cache::list_remote_envelopes(&mut conn, "account", "INBOX").unwrap();
+        assert_eq!(1, envelopes.len());
+    }
 }
@@ -1,5 +1,6 @@
 use rusqlite;
-use std::result;
+use serde::{Serialize, Serializer};
+use std::{error, io, path::PathBuf, result};
 use thiserror::Error;
 
 use crate::{account, backend, email};
@@ -14,6 +15,28 @@ pub enum Error {
     FindConnectionByCursorError(usize),
     #[error("cannot find email by internal id {0}")]
     LockConnectionError(String),
+    #[error("cannot upload partial email {0}")]
+    UploadPartialEmailError(String),
+    #[error("skipping hunk depending on failed hunk: {0}")]
+    SkipDependentHunkError(String),
+    #[error("cannot synchronize envelopes of folder {0}: {2} out of {3} hunks failed with {1}")]
+    SystemicSyncError(String, String, usize, usize),
+    #[error("cannot synchronize envelopes of folder {0}: synchronization timed out")]
+    FolderSyncTimeoutError(String),
+    #[error("cannot build envelopes synchronization thread pool")]
+    BuildThreadPoolError(#[source] rayon::ThreadPoolBuildError),
+    #[error("cannot serialize envelopes patch")]
+    SerializePatchError(#[source] serde_json::Error),
+    #[error("cannot deserialize envelopes sync journal")]
+    DeserializeJournalError(#[source] serde_json::Error),
+    #[error("cannot write envelopes patch to audit log {1}")]
+    WriteAuditLogError(#[source] io::Error, PathBuf),
+    #[error("cannot write envelopes cache to csv file {1}")]
+    WriteCsvError(#[source] io::Error, PathBuf),
+    #[error("cannot read envelopes cache from csv file {1}")]
+    ReadCsvError(#[source] io::Error, PathBuf),
+    #[error("cannot parse line {1} of csv file {2}: {0}")]
+    ParseCsvError(String, usize, PathBuf),
 
     #[error(transparent)]
     SqliteError(#[from] rusqlite::Error),
@@ -26,3 +49,43 @@ pub enum Error {
 }
 
 pub type Result<T> = result::Result<T, Error>;
+
+/// Serializes the error as its display, so that the reports
+/// containing it can be serialized.
+impl Serialize for Error {
+    fn serialize<S: Serializer>(&self, s: S) -> result::Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
+impl Error {
+    /// Returns `true` if the error is likely to be temporary, like a
+    /// lost connection to the IMAP server, in which case the failing
+    /// operation can be retried. Logical errors, like an email that
+    /// cannot be found, are never transient.
+    pub fn is_transient(&self) -> bool {
+        let mut source = error::Error::source(self);
+
+        while let Some(err) = source {
+            #[cfg(feature = "imap-backend")]
+            if let Some(imap::Error::Io(_) | imap::Error::ConnectionLost | imap::Error::Bye(_)) =
+                err.downcast_ref::<imap::Error>()
+            {
+                return true;
+            }
+
+            source = err.source();
+        }
+
+        false
+    }
+
+    /// Returns `true` if the error comes from a write refused by a
+    /// read-only folder (see [`backend::Error::is_permission_denied`]).
+    pub fn is_permission_denied(&self) -> bool {
+        match self {
+            Self::BackendError(err) => err.is_permission_denied(),
+            _ => false,
+        }
+    }
+}
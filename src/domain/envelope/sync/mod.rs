@@ -1,5 +1,6 @@
 pub mod cache;
 mod error;
+pub mod partial;
 pub mod sync;
 
 pub use self::cache::Cache;
@@ -0,0 +1,121 @@
+//! Partial email module.
+//!
+//! This module contains helpers to build and detect partial emails,
+//! which are local copies of remote emails too big to be fully
+//! downloaded during the synchronization (see
+//! [`super::SyncBuilder::max_message_size`]). A partial email keeps
+//! the original headers, but its body is replaced by a plain text
+//! placeholder.
+
+use mailparse::MailHeaderMap;
+
+/// Represents the header added to partial emails. Its value is the
+/// size in bytes of the original email.
+pub const PARTIAL_HEADER: &str = "X-Himalaya-Partial";
+
+/// Headers describing the original body, which do not apply to the
+/// placeholder body.
+const BODY_HEADERS: [&str; 4] = [
+    "Content-Type",
+    "Content-Transfer-Encoding",
+    "Content-Disposition",
+    "MIME-Version",
+];
+
+/// Builds a partial email from the header block of the original
+/// email and its size in bytes.
+pub fn build_partial_email(headers: &[u8], size: u64) -> Vec<u8> {
+    let mut email = Vec::with_capacity(headers.len() + 256);
+    let mut skip = false;
+
+    for line in headers.split_inclusive(|b| *b == b'\n') {
+        if line == b"\n" || line == b"\r\n" {
+            break;
+        }
+
+        // folded lines belong to the previous header
+        if !line.starts_with(b" ") && !line.starts_with(b"\t") {
+            let name = line.split(|b| *b == b':').next().unwrap_or_default();
+            let name = String::from_utf8_lossy(name);
+            skip = BODY_HEADERS
+                .iter()
+                .any(|header| header.eq_ignore_ascii_case(name.trim()));
+        }
+
+        if !skip {
+            email.extend_from_slice(line);
+            if !line.ends_with(b"\n") {
+                email.extend_from_slice(b"\r\n");
+            }
+        }
+    }
+
+    email.extend_from_slice(b"MIME-Version: 1.0\r\n");
+    email.extend_from_slice(b"Content-Type: text/plain; charset=utf-8\r\n");
+    email.extend_from_slice(b"Content-Transfer-Encoding: 8bit\r\n");
+    email.extend_from_slice(format!("{PARTIAL_HEADER}: {size}\r\n\r\n").as_bytes());
+    email.extend_from_slice(
+        format!(
+            "This email is {size} bytes large, which exceeds the synchronization size limit: only its headers have been downloaded.\r\n"
+        )
+        .as_bytes(),
+    );
+
+    email
+}
+
+/// Returns the size of the original email if the given email is a
+/// partial one, `None` otherwise.
+pub fn partial_size(email: &[u8]) -> Option<u64> {
+    let (headers, _) = mailparse::parse_headers(email).ok()?;
+    headers.get_first_value(PARTIAL_HEADER)?.trim().parse().ok()
+}
+
+/// Extracts the header block of the given email, up to the first
+/// empty line.
+pub fn extract_headers(email: &[u8]) -> &[u8] {
+    let mut len = 0;
+
+    for line in email.split_inclusive(|b| *b == b'\n') {
+        if line == b"\n" || line == b"\r\n" {
+            break;
+        }
+        len += line.len();
+    }
+
+    &email[..len]
+}
+
+#[cfg(test)]
+mod partial_email {
+    use mailparse::MailHeaderMap;
+
+    use super::{build_partial_email, extract_headers, partial_size};
+
+    #[test]
+    fn build_partial() {
+        let email = concat!(
+            "Message-ID: <a@localhost>\r\n",
+            "Subject: big\r\n",
+            "Content-Type: multipart/mixed;\r\n",
+            "\tboundary=\"abc\"\r\n",
+            "MIME-Version: 1.0\r\n",
+            "\r\n",
+            "--abc\r\n",
+        )
+        .as_bytes();
+
+        let partial = build_partial_email(extract_headers(email), 42_000_000);
+        let parsed = mailparse::parse_mail(&partial).unwrap();
+
+        assert_eq!(
+            Some("<a@localhost>".into()),
+            parsed.headers.get_first_value("Message-ID")
+        );
+        assert_eq!("text/plain", parsed.ctype.mimetype);
+        assert_eq!(1, parsed.headers.get_all_values("MIME-Version").len());
+        assert!(parsed.get_body().unwrap().contains("42000000 bytes"));
+        assert_eq!(Some(42_000_000), partial_size(&partial));
+        assert_eq!(None, partial_size(email));
+    }
+}
@@ -1,17 +1,35 @@
+use chrono::Utc;
 use log::{debug, info, trace, warn};
-use rayon::prelude::*;
+use rayon::{prelude::*, ThreadPoolBuilder};
+use serde::{Deserialize, Serialize};
 use std::{
+    cmp::Reverse,
     collections::{HashMap, HashSet},
     fmt,
+    fs::OpenOptions,
+    io::Write,
+    iter, mem, ops,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
 };
 
-use crate::{flag, AccountConfig, Backend, BackendSyncProgressEvent, Envelope, MaildirBackend};
+use crate::{
+    flag, folder,
+    trace::{span, timed, Span},
+    AccountConfig, Backend, BackendSyncProgressEvent, Envelope, Flag, Flags, FolderPermission,
+    MaildirBackend, SyncExecutor,
+};
 
-use super::{Cache, Error, Result};
+use super::{partial, Cache, Error, Result};
 
 pub type Envelopes = HashMap<String, Envelope>;
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub enum HunkKind {
     LocalCache,
     Local,
@@ -30,7 +48,7 @@ impl fmt::Display for HunkKind {
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, Hash, PartialEq, Serialize, Deserialize)]
 pub enum HunkKindRestricted {
     Local,
     Remote,
@@ -39,20 +57,22 @@ pub enum HunkKindRestricted {
 impl fmt::Display for HunkKindRestricted {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::Local => write!(f, "local"),
-            Self::Remote => write!(f, "remote"),
+            Self::Local => write!(f, "local backend"),
+            Self::Remote => write!(f, "remote backend"),
         }
     }
 }
 
 type FolderName = String;
 type InternalId = String;
+type MessageId = String;
+type EmailSize = u64;
 type SourceRestricted = HunkKindRestricted;
 type Target = HunkKind;
 type TargetRestricted = HunkKindRestricted;
 type RefreshSourceCache = bool;
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub enum BackendHunk {
     CacheEnvelope(FolderName, InternalId, SourceRestricted),
     CopyEmail(
@@ -63,53 +83,651 @@ pub enum BackendHunk {
         RefreshSourceCache,
     ),
     RemoveEmail(FolderName, InternalId, Target),
-    SetFlags(FolderName, Envelope, Target),
+    /// Replaces the flags of the email. Only the internal id is
+    /// carried, since the rest of the envelope does not change.
+    SetFlags(FolderName, InternalId, Flags, Target),
+    /// Moves the email to the trash folder, without its
+    /// [`Flag::Deleted`] flag (see [`SyncDeletedBehavior::MoveToTrash`]).
+    MoveEmailToTrash(FolderName, Envelope, TargetRestricted),
+    /// Moves the local email from the first folder to the second one,
+    /// following a move on the remote side (see [`detect_moves`]).
+    /// Carries the internal id of the local cache envelope, the local
+    /// envelope then the remote one, whose flags are applied to the
+    /// moved email.
+    MoveEmail(FolderName, FolderName, InternalId, Envelope, Envelope),
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub enum CacheHunk {
     InsertEnvelope(FolderName, Envelope, TargetRestricted),
     DeleteEnvelope(FolderName, InternalId, TargetRestricted),
+    /// Replaces the flags of the cached envelope.
+    SetFlags(FolderName, InternalId, Flags, TargetRestricted),
+    /// Marks the local envelope as partial, or unmarks it if the size
+    /// is `None` (see [`SyncBuilder::max_message_size`]).
+    SetLocalPartial(FolderName, MessageId, Option<EmailSize>),
+    /// Moves the cached envelope from the first folder to the second
+    /// one.
+    MoveEnvelope(FolderName, FolderName, InternalId, TargetRestricted),
+}
+
+/// Represents the maximum amount of characters of the subjects shown
+/// by the [`BackendHunk`] display.
+const HUNK_SUBJECT_MAX_CHARS: usize = 40;
+
+impl BackendHunk {
+    /// Describes the hunk like its display, truncated to the given
+    /// amount of characters for compact user interfaces.
+    pub fn short_description(&self, max_chars: usize) -> String {
+        truncate(&self.to_string(), max_chars)
+    }
 }
 
 impl fmt::Display for BackendHunk {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // emails are shown by subject when they have one
+        let email = |envelope: &Envelope| match envelope.subject.trim() {
+            "" => format!("email {}", envelope.id),
+            subject => format!("email \"{}\"", truncate(subject, HUNK_SUBJECT_MAX_CHARS)),
+        };
+
         match self {
             Self::CacheEnvelope(folder, id, source) => {
-                write!(f, "Adding envelope {id} to {source} cache folder {folder}")
+                write!(f, "Caching email {id} from {source} {folder}")
             }
             Self::CopyEmail(folder, envelope, source, target, _) => {
                 write!(
                     f,
-                    "Copying {source} envelope {id} to {target} folder {folder}",
-                    id = envelope.id,
+                    "Copying {email} from {source} {folder} to {target} {folder}",
+                    email = email(envelope),
                 )
             }
             Self::RemoveEmail(folder, id, target) => {
-                write!(f, "Removing envelope {id} from {target} folder {folder}")
+                write!(f, "Removing email {id} from {target} {folder}")
+            }
+            Self::SetFlags(folder, id, flags, target) => {
+                write!(
+                    f,
+                    "Setting flags {flags} of email {id} in {target} {folder}"
+                )
+            }
+            Self::MoveEmailToTrash(folder, envelope, target) => {
+                write!(
+                    f,
+                    "Moving {email} from {target} {folder} to trash",
+                    email = email(envelope),
+                )
             }
-            Self::SetFlags(folder, envelope, target) => {
+            Self::MoveEmail(from, to, _, envelope, _) => {
                 write!(
                     f,
-                    "Setting flags {flags} to {target} envelope from folder {folder}",
-                    flags = envelope.flags.to_string(),
+                    "Moving {email} from local backend {from} to local backend {to}",
+                    email = email(envelope),
                 )
             }
         }
     }
 }
 
+/// Truncates the given text to the given amount of characters, an
+/// ellipsis included.
+fn truncate(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_owned();
+    }
+
+    let mut truncated: String = text.chars().take(max_chars.saturating_sub(1)).collect();
+    truncated.push('…');
+    truncated
+}
+
+/// Represents the estimated cost of a hunk, used to order hunks
+/// before processing them (see [`BackendHunk::estimated_cost`]).
+#[derive(Clone, Copy, Debug, Default, Eq, Ord, PartialEq, PartialOrd)]
+pub struct HunkCost {
+    /// Represents the amount of bytes transferred over the network.
+    pub network_bytes: u64,
+    /// Represents the amount of I/O operations.
+    pub iops: u32,
+}
+
+impl ops::Add for HunkCost {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Self {
+            network_bytes: self.network_bytes + rhs.network_bytes,
+            iops: self.iops + rhs.iops,
+        }
+    }
+}
+
+impl iter::Sum for HunkCost {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::default(), ops::Add::add)
+    }
+}
+
+impl BackendHunk {
+    /// Size assumed for emails of unknown size.
+    const DEFAULT_EMAIL_SIZE: u64 = 50_000;
+
+    /// Estimates the cost of the hunk. Only email copies transfer
+    /// email contents, other hunks are considered lightweight.
+    pub fn estimated_cost(&self) -> HunkCost {
+        match self {
+            Self::CopyEmail(_, envelope, _, _, _) => HunkCost {
+                network_bytes: envelope.size.unwrap_or(Self::DEFAULT_EMAIL_SIZE),
+                iops: 4,
+            },
+            Self::RemoveEmail(_, _, HunkKind::LocalCache | HunkKind::RemoteCache)
+            | Self::SetFlags(_, _, _, HunkKind::LocalCache | HunkKind::RemoteCache)
+            | Self::CacheEnvelope(..) => HunkCost {
+                network_bytes: 0,
+                iops: 2,
+            },
+            Self::RemoveEmail(..)
+            | Self::SetFlags(..)
+            | Self::MoveEmailToTrash(..)
+            | Self::MoveEmail(..) => HunkCost {
+                network_bytes: 0,
+                iops: 4,
+            },
+        }
+    }
+
+    /// Returns the name of the hunk variant, in snake case.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::CacheEnvelope(..) => "cache_envelope",
+            Self::CopyEmail(..) => "copy_email",
+            Self::RemoveEmail(..) => "remove_email",
+            Self::SetFlags(..) => "set_flags",
+            Self::MoveEmailToTrash(..) => "move_email_to_trash",
+            Self::MoveEmail(..) => "move_email",
+        }
+    }
+
+    /// Returns the internal id of the email the hunk applies to.
+    pub fn internal_id(&self) -> &str {
+        match self {
+            Self::CacheEnvelope(_, internal_id, _)
+            | Self::RemoveEmail(_, internal_id, _)
+            | Self::SetFlags(_, internal_id, _, _) => internal_id,
+            Self::CopyEmail(_, envelope, _, _, _)
+            | Self::MoveEmailToTrash(_, envelope, _)
+            | Self::MoveEmail(_, _, _, envelope, _) => &envelope.internal_id,
+        }
+    }
+
+    /// Returns the side whose backend the hunk writes to, if any.
+    /// Hunks updating the caches only, or caching an envelope, do not
+    /// write to any backend.
+    pub fn target(&self) -> Option<TargetRestricted> {
+        match self {
+            Self::CopyEmail(_, _, _, target, _) | Self::MoveEmailToTrash(_, _, target) => {
+                Some(target.clone())
+            }
+            Self::RemoveEmail(_, _, HunkKind::Local)
+            | Self::SetFlags(_, _, _, HunkKind::Local)
+            | Self::MoveEmail(..) => Some(TargetRestricted::Local),
+            Self::RemoveEmail(_, _, HunkKind::Remote)
+            | Self::SetFlags(_, _, _, HunkKind::Remote) => Some(TargetRestricted::Remote),
+            Self::RemoveEmail(..) | Self::SetFlags(..) | Self::CacheEnvelope(..) => None,
+        }
+    }
+
+    /// Returns the hunk updating the cache the way this hunk would
+    /// update the backend, if any (see [`ReadOnlyStrategy::CacheOnly`]).
+    /// Copies cannot be downgraded, only the cache of their source
+    /// is refreshed.
+    pub fn to_cache_only(&self) -> Option<BackendHunk> {
+        let cache = |target: &TargetRestricted| match target {
+            TargetRestricted::Local => HunkKind::LocalCache,
+            TargetRestricted::Remote => HunkKind::RemoteCache,
+        };
+
+        match self {
+            Self::CopyEmail(folder, envelope, source, _, true) => Some(Self::CacheEnvelope(
+                folder.clone(),
+                envelope.internal_id.clone(),
+                source.clone(),
+            )),
+            Self::MoveEmailToTrash(folder, envelope, target) => Some(Self::RemoveEmail(
+                folder.clone(),
+                envelope.internal_id.clone(),
+                cache(target),
+            )),
+            Self::RemoveEmail(folder, internal_id, HunkKind::Local) => Some(Self::RemoveEmail(
+                folder.clone(),
+                internal_id.clone(),
+                HunkKind::LocalCache,
+            )),
+            Self::RemoveEmail(folder, internal_id, HunkKind::Remote) => Some(Self::RemoveEmail(
+                folder.clone(),
+                internal_id.clone(),
+                HunkKind::RemoteCache,
+            )),
+            Self::SetFlags(folder, internal_id, flags, HunkKind::Local) => Some(Self::SetFlags(
+                folder.clone(),
+                internal_id.clone(),
+                flags.clone(),
+                HunkKind::LocalCache,
+            )),
+            Self::SetFlags(folder, internal_id, flags, HunkKind::Remote) => Some(Self::SetFlags(
+                folder.clone(),
+                internal_id.clone(),
+                flags.clone(),
+                HunkKind::RemoteCache,
+            )),
+            _ => None,
+        }
+    }
+
+    /// Returns `true` if the hunk downloads a remote email to the
+    /// local backend.
+    pub fn is_download(&self) -> bool {
+        matches!(
+            self,
+            Self::CopyEmail(
+                _,
+                _,
+                HunkKindRestricted::Remote,
+                HunkKindRestricted::Local,
+                _
+            )
+        )
+    }
+}
+
+/// Sorts the groups of each batch of the given patch by ascending
+/// network cost, so that lightweight hunks do not wait behind large
+/// email downloads. Hunks inside a group are never reordered, since
+/// they may depend on each other.
+pub fn sort_patch_by_cost(patch: &mut Patch, batch_size: usize) {
+    for batch in patch.chunks_mut(batch_size.max(1)) {
+        batch.sort_by_key(|hunks| {
+            hunks
+                .iter()
+                .map(BackendHunk::estimated_cost)
+                .sum::<HunkCost>()
+                .network_bytes
+        });
+    }
+}
+
+/// Sorts the groups of the given patch by descending date of the
+/// emails they download, so that the newest emails arrive first.
+/// Groups without download come first, since they are lightweight.
+/// Hunks inside a group are never reordered, since they may depend
+/// on each other.
+pub fn sort_patch_by_date(patch: &mut Patch) {
+    patch.sort_by_key(|hunks| {
+        hunks
+            .iter()
+            .filter_map(|hunk| match hunk {
+                BackendHunk::CopyEmail(_, envelope, ..) if hunk.is_download() => {
+                    Some(envelope.date)
+                }
+                _ => None,
+            })
+            .max()
+            .map(Reverse)
+    });
+}
+
+/// Represents an entry of the patches audit log (see [`log_patch`]).
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PatchLogEntry {
+    /// Represents the date of the synchronization, in RFC 3339
+    /// format.
+    pub timestamp: String,
+    pub account: String,
+    pub folder: String,
+    pub patch: Patch,
+}
+
+/// Appends the given patch to the audit log at the given path, as a
+/// JSON Lines entry (see [`PatchLogEntry`]). The file is created if
+/// it does not exist.
+pub fn log_patch(path: &Path, account: &str, folder: &str, patch: &Patch) -> Result<()> {
+    let entry = PatchLogEntry {
+        timestamp: Utc::now().to_rfc3339(),
+        account: account.to_owned(),
+        folder: folder.to_owned(),
+        patch: patch.clone(),
+    };
+    let mut line = serde_json::to_vec(&entry).map_err(Error::SerializePatchError)?;
+    line.push(b'\n');
+
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(&line))
+        .map_err(|err| Error::WriteAuditLogError(err, path.to_owned()))?;
+
+    Ok(())
+}
+
+/// Represents the envelopes patch, as a list of groups of hunks.
+/// Groups are processed in parallel, but hunks of the same group are
+/// processed in order: when a hunk fails, the following hunks of its
+/// group are skipped since they may depend on it.
 pub type Patch = Vec<Vec<BackendHunk>>;
 
-#[derive(Debug, Default)]
+/// Default amount of hunk groups processed sequentially by a single
+/// worker thread.
+pub const DEFAULT_BATCH_SIZE: usize = 10;
+
+/// Represents the retry strategy applied to hunks failing with a
+/// transient error (see [`Error::is_transient`]). Other errors are
+/// never retried.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum HunkRetryPolicy {
+    /// Never retries failing hunks.
+    #[default]
+    NoRetry,
+    /// Retries failing hunks at most N times.
+    RetryN(u8),
+    /// Retries failing hunks until they succeed, waiting at most
+    /// `max_delay_secs` between two attempts.
+    RetryUntilSuccess { max_delay_secs: u64 },
+}
+
+impl HunkRetryPolicy {
+    /// Maximum delay between two attempts of the [`Self::RetryN`]
+    /// policy.
+    const MAX_DELAY_SECS: u64 = 60;
+
+    /// Returns the delay to wait before the given retry attempt
+    /// (starting from 0), or `None` if the hunk should not be retried
+    /// anymore. The delay doubles at each attempt, starting from 1
+    /// second.
+    pub fn delay(&self, attempt: u32) -> Option<Duration> {
+        let backoff = |max_delay_secs: u64| {
+            let delay = 1u64.checked_shl(attempt).unwrap_or(u64::MAX);
+            Duration::from_secs(delay.min(max_delay_secs))
+        };
+
+        match self {
+            Self::NoRetry => None,
+            Self::RetryN(n) if attempt < *n as u32 => Some(backoff(Self::MAX_DELAY_SECS)),
+            Self::RetryN(_) => None,
+            Self::RetryUntilSuccess { max_delay_secs } => Some(backoff(*max_delay_secs)),
+        }
+    }
+}
+
+/// Represents how emails flagged with [`Flag::Deleted`] are
+/// synchronized.
+///
+/// A deletion is considered stable once the flag is present in both
+/// the cache and the backend of the same side, which means it has
+/// been seen by a previous synchronization.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum SyncDeletedBehavior {
+    /// Synchronizes the flag like any other flag: emails are never
+    /// removed. Conflicting flags are dropped in order not to lose
+    /// data.
+    #[default]
+    PropagateFlag,
+    /// Moves emails stably flagged as deleted to the trash folder of
+    /// their side and removes them from the other side. The trash
+    /// folder synchronization then copies them back to the other
+    /// side. Since trashed emails can be restored, conflicting flags
+    /// are kept.
+    MoveToTrash,
+    /// Removes emails stably flagged as deleted from both sides, then
+    /// expunges the folder. Conflicting flags are dropped in order not
+    /// to lose data.
+    Expunge,
+}
+
+impl SyncDeletedBehavior {
+    /// Returns `true` if a [`Flag::Deleted`] flag present on one side
+    /// only should be kept when both sides changed.
+    pub fn keeps_conflicting_flag(&self) -> bool {
+        matches!(self, Self::MoveToTrash)
+    }
+}
+
+/// Represents how a flag changed on both sides since the last
+/// synchronization, for example added local side but removed remote
+/// side, is synchronized.
+///
+/// Conflicts on [`Flag::Deleted`] are resolved by the
+/// [`SyncDeletedBehavior`]. When an email stably flagged as deleted
+/// on one side changed on the other side since the last
+/// synchronization, for example because the deletion was undone
+/// there, the email is only removed if this strategy prefers the
+/// deleted side. Otherwise the change wins and the email is kept.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ConflictResolution {
+    /// Keeps the conflicting flag on both sides, in order not to lose
+    /// data.
+    #[default]
+    KeepFlag,
+    /// Takes the flag from the local side.
+    PreferLocal,
+    /// Takes the flag from the remote side.
+    PreferRemote,
+}
+
+/// Represents how the synchronization handles the changes targeting
+/// a read-only folder, like an IMAP folder shared without write
+/// rights (see [`Backend::folder_permission`]).
+///
+/// In both cases, the skipped hunks are reported once per folder and
+/// side (see [`SyncReport::read_only`]) instead of failing one by
+/// one.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ReadOnlyStrategy {
+    /// Skips the hunks writing to the read-only side, as well as the
+    /// flags changes of its cache. The changes made on the other
+    /// side are kept, and skipped again by the next
+    /// synchronizations.
+    #[default]
+    Skip,
+    /// Applies the hunks writing to the read-only side to its cache
+    /// only (see [`BackendHunk::to_cache_only`]). The next
+    /// synchronization then sees the read-only side unchanged
+    /// compared to the other side, and reverts the changes made on
+    /// the other side, so that both sides converge.
+    CacheOnly,
+}
+
+#[derive(Debug, Default, Serialize)]
 pub struct SyncReport {
-    pub patch: Vec<(BackendHunk, Option<Error>)>,
+    /// Represents the hunks of the patch that have been applied.
+    pub patch: Vec<BackendHunk>,
+    /// Represents the hunks that failed, even after retries.
+    pub failed_hunks: Vec<(BackendHunk, Error)>,
     pub cache_patch: (Vec<CacheHunk>, Option<Error>),
+    /// Represents the placeholders of the emails which could not be
+    /// parsed, with their folder and their side. They are skipped by
+    /// the synchronization (see [`Envelope::unparsable`]).
+    pub unparsable_envelopes: Vec<(FolderName, Envelope, SourceRestricted)>,
+    /// Represents the hunks targeting a read-only folder, grouped by
+    /// folder and side, which have been skipped or applied to the
+    /// cache only (see [`ReadOnlyStrategy`]).
+    pub read_only: Vec<(FolderName, TargetRestricted, Vec<BackendHunk>)>,
+}
+
+impl SyncReport {
+    /// Appends the given report to this one. The first cache error is
+    /// kept.
+    pub fn merge(&mut self, report: SyncReport) {
+        self.patch.extend(report.patch);
+        self.failed_hunks.extend(report.failed_hunks);
+        self.cache_patch.0.extend(report.cache_patch.0);
+        self.unparsable_envelopes
+            .extend(report.unparsable_envelopes);
+        for (folder, target, hunks) in report.read_only {
+            self.push_read_only(folder, target, hunks);
+        }
+        if self.cache_patch.1.is_none() {
+            self.cache_patch.1 = report.cache_patch.1;
+        }
+    }
+
+    /// Adds the given hunks to the read-only entry of the given folder
+    /// and side, creating it if needed.
+    fn push_read_only(
+        &mut self,
+        folder: FolderName,
+        target: TargetRestricted,
+        hunks: Vec<BackendHunk>,
+    ) {
+        let entry = self
+            .read_only
+            .iter_mut()
+            .find(|(f, t, _)| *f == folder && *t == target);
+
+        match entry {
+            Some((_, _, read_only_hunks)) => read_only_hunks.extend(hunks),
+            None => self.read_only.push((folder, target, hunks)),
+        }
+    }
+
+    /// Counts the failed hunks by class of error, the class being the
+    /// display of the error up to its first colon, in order to leave
+    /// out details like ids.
+    pub fn error_summary(&self) -> HashMap<String, usize> {
+        let mut summary = HashMap::new();
+        for (_, err) in &self.failed_hunks {
+            *summary.entry(error_class(err)).or_default() += 1;
+        }
+        summary
+    }
+
+    /// Returns `true` if a single class of error (see
+    /// [`SyncReport::error_summary`]) concerns more than the given
+    /// fraction of all the hunks of the report, like an expired
+    /// password making every hunk fail. Hunks skipped because they
+    /// depend on a failed hunk do not form a class on their own.
+    pub fn has_systemic_error(&self, threshold: f64) -> bool {
+        let hunks = self.patch.len() + self.failed_hunks.len();
+        find_systemic_error(&self.error_summary(), hunks, threshold).is_some()
+    }
+}
+
+/// Returns the class of the given error, see
+/// [`SyncReport::error_summary`].
+fn error_class(err: &Error) -> String {
+    let err = err.to_string();
+    match err.split_once(':') {
+        Some((class, _)) => class.to_owned(),
+        None => err,
+    }
+}
+
+/// Returns the class of error concerning more than the given fraction
+/// of the given amount of hunks, with its amount of failed hunks.
+fn find_systemic_error(
+    summary: &HashMap<String, usize>,
+    hunks: usize,
+    threshold: f64,
+) -> Option<(String, usize)> {
+    let skipped = error_class(&Error::SkipDependentHunkError(String::new()));
+    summary
+        .iter()
+        .filter(|(class, _)| **class != skipped)
+        .max_by_key(|(_, count)| **count)
+        .filter(|(_, count)| **count as f64 > threshold * hunks as f64)
+        .map(|(class, count)| (class.clone(), *count))
+}
+
+/// Represents the hunks processed so far by a synchronization aborting
+/// on systemic errors, see [`SyncBuilder::abort_on_systemic_error`].
+#[derive(Default)]
+struct SystemicErrorCheck {
+    hunks: usize,
+    summary: HashMap<String, usize>,
+    systemic: Option<(String, usize, usize)>,
+}
+
+/// Represents a flag telling a running synchronization to stop as
+/// soon as possible. Clones share the same flag, so that the
+/// synchronization can be cancelled from another thread.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels the synchronizations watching this token.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Represents the watchdog of a folder synchronization, see
+/// [`SyncBuilder::folder_timeout`]. Its thread cancels the token once
+/// the timeout elapses, unless the watchdog is dropped before.
+struct Watchdog {
+    token: CancellationToken,
+    // dropping the sender wakes the watchdog thread up
+    _done: mpsc::Sender<()>,
+}
+
+impl Watchdog {
+    fn spawn(folder: &str, timeout: Duration) -> Self {
+        let token = CancellationToken::new();
+        let (done, finished) = mpsc::channel::<()>();
+
+        let cancel = token.clone();
+        let watched = folder.to_owned();
+        let spawned = thread::Builder::new()
+            .name(format!("sync-watchdog-{folder}"))
+            .spawn(move || {
+                if let Err(mpsc::RecvTimeoutError::Timeout) = finished.recv_timeout(timeout) {
+                    warn!("synchronization of folder {watched} timed out after {timeout:?}, aborting it");
+                    cancel.cancel();
+                }
+            });
+
+        if let Err(err) = spawned {
+            warn!("cannot spawn watchdog of folder {folder}, skipping it: {err}");
+        }
+
+        Self { token, _done: done }
+    }
+}
+
+/// Returns the key under which the cache of the remote backend at the
+/// given index is stored (see [`SyncBuilder::sync_remotes`]). The
+/// first remote uses the account name, so that its cache is shared
+/// with single remote synchronizations.
+pub fn remote_cache_key(account: &str, index: usize) -> String {
+    if index == 0 {
+        account.to_owned()
+    } else {
+        format!("{account}#{index}")
+    }
 }
 
 pub struct SyncBuilder<'a> {
     account_config: &'a AccountConfig,
     dry_run: bool,
+    batch_size: usize,
+    parallelism: Option<usize>,
+    max_message_size: Option<u64>,
+    newest_first: bool,
+    hunk_retry_policy: HunkRetryPolicy,
+    sync_deleted: SyncDeletedBehavior,
+    conflict_resolution: ConflictResolution,
+    read_only_strategy: ReadOnlyStrategy,
+    executor: SyncExecutor<'a>,
+    audit_log: Option<PathBuf>,
+    systemic_error_threshold: Option<f64>,
+    folder_timeout: Option<Duration>,
     on_progress: Box<dyn Fn(BackendSyncProgressEvent) -> Result<()> + Sync + Send + 'a>,
 }
 
@@ -118,6 +736,18 @@ impl<'a> SyncBuilder<'a> {
         Self {
             account_config,
             dry_run: false,
+            batch_size: DEFAULT_BATCH_SIZE,
+            parallelism: None,
+            max_message_size: None,
+            newest_first: false,
+            hunk_retry_policy: HunkRetryPolicy::default(),
+            sync_deleted: SyncDeletedBehavior::default(),
+            conflict_resolution: ConflictResolution::default(),
+            read_only_strategy: ReadOnlyStrategy::default(),
+            executor: SyncExecutor::default(),
+            audit_log: None,
+            systemic_error_threshold: None,
+            folder_timeout: None,
             on_progress: Box::new(|_| Ok(())),
         }
     }
@@ -127,6 +757,146 @@ impl<'a> SyncBuilder<'a> {
         self
     }
 
+    /// Sets the amount of hunk groups processed sequentially by a
+    /// single worker thread.
+    ///
+    /// A small batch size spreads the patch over more workers and
+    /// keeps fewer pending requests per connection, which is safer
+    /// on high-latency connections. A bigger batch size reduces the
+    /// scheduling overhead, which is better for local Maildir
+    /// synchronization, but every worker keeps more emails in memory
+    /// at the same time. Defaults to [`DEFAULT_BATCH_SIZE`], a zero
+    /// value is treated as 1.
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// Sets the amount of threads used to process the patch.
+    ///
+    /// When set, a dedicated thread pool is built for the duration
+    /// of the synchronization instead of using the global rayon
+    /// pool. More threads means more concurrent requests to the
+    /// backends, which only helps if the remote backend has enough
+    /// sessions to serve them (see the IMAP sessions pool size).
+    /// Only applies to the [`SyncExecutor::GlobalPool`] executor.
+    pub fn parallelism(mut self, parallelism: usize) -> Self {
+        self.parallelism = Some(parallelism);
+        self
+    }
+
+    /// Sets the size in bytes above which remote emails are not fully
+    /// downloaded.
+    ///
+    /// Only the headers of such emails are copied to the local
+    /// Maildir, together with a plain text placeholder body (see
+    /// [`partial`]), and their local envelope is marked as partial
+    /// in the cache. The complete email can then be fetched on
+    /// demand with [`crate::BackendSyncBuilder::fetch_full`]. Since
+    /// envelopes are compared by message id and flags, partial copies
+    /// are synchronized like complete ones, but they are never
+    /// uploaded back to the remote backend.
+    pub fn max_message_size(mut self, max_message_size: u64) -> Self {
+        self.max_message_size = Some(max_message_size);
+        self
+    }
+
+    /// Returns the size of the given envelope if it exceeds the
+    /// maximum message size.
+    fn exceeding_size(&self, envelope: &Envelope) -> Option<u64> {
+        let max = self.max_message_size?;
+        envelope.size.filter(|size| *size > max)
+    }
+
+    /// Downloads the newest remote emails first.
+    ///
+    /// Useful for the initial synchronization of a large account:
+    /// the patch is sorted with [`sort_patch_by_date`] instead of
+    /// [`sort_patch_by_cost`], and batches are handed to the workers
+    /// in order. Only the processing order changes, hunks depending
+    /// on a download still run after it. Defaults to `false`.
+    pub fn newest_first(mut self, newest_first: bool) -> Self {
+        self.newest_first = newest_first;
+        self
+    }
+
+    /// Sets the retry policy applied to hunks failing with a
+    /// transient error. Defaults to [`HunkRetryPolicy::NoRetry`].
+    pub fn hunk_retry_policy(mut self, policy: HunkRetryPolicy) -> Self {
+        self.hunk_retry_policy = policy;
+        self
+    }
+
+    /// Sets how emails flagged as deleted are synchronized. Defaults
+    /// to [`SyncDeletedBehavior::PropagateFlag`].
+    pub fn sync_deleted(mut self, sync_deleted: SyncDeletedBehavior) -> Self {
+        self.sync_deleted = sync_deleted;
+        self
+    }
+
+    /// Sets how the flags changed on both sides are synchronized.
+    /// Defaults to [`ConflictResolution::KeepFlag`]. Folders listed in
+    /// [`AccountConfig::folder_conflict_overrides`] use their own
+    /// strategy instead.
+    pub fn conflict_resolution(mut self, conflict_resolution: ConflictResolution) -> Self {
+        self.conflict_resolution = conflict_resolution;
+        self
+    }
+
+    /// Sets how the changes targeting read-only folders are handled.
+    /// Defaults to [`ReadOnlyStrategy::Skip`].
+    pub fn read_only_strategy(mut self, strategy: ReadOnlyStrategy) -> Self {
+        self.read_only_strategy = strategy;
+        self
+    }
+
+    /// Sets how the patch is processed. Defaults to
+    /// [`SyncExecutor::GlobalPool`].
+    pub fn executor(mut self, executor: SyncExecutor<'a>) -> Self {
+        self.executor = executor;
+        self
+    }
+
+    /// Appends every envelopes patch to the given JSON Lines file
+    /// before applying it (see [`log_patch`]), so that the decisions
+    /// of the synchronization can be inspected afterwards. Patches
+    /// are logged even in dry run mode.
+    pub fn audit_log(mut self, path: PathBuf) -> Self {
+        self.audit_log = Some(path);
+        self
+    }
+
+    /// Aborts the synchronization of a folder with
+    /// [`Error::SystemicSyncError`] as soon as a single class of error
+    /// concerns more than the given fraction of the hunks processed so
+    /// far (see [`SyncReport::has_systemic_error`]), instead of
+    /// hitting the same error for every remaining hunk.
+    ///
+    /// The check runs after each batch, so the batches already handed
+    /// to the workers are still processed. The cache hunks of the
+    /// processed hunks are journaled, they are applied by the next
+    /// synchronization. Disabled by default.
+    pub fn abort_on_systemic_error(mut self, threshold: f64) -> Self {
+        self.systemic_error_threshold = Some(threshold);
+        self
+    }
+
+    /// Aborts the synchronization of a folder with
+    /// [`Error::FolderSyncTimeoutError`] when it takes longer than the
+    /// given duration, for example because the connection stalled
+    /// without the TCP timeout kicking in.
+    ///
+    /// A watchdog thread is spawned per folder, which cancels the
+    /// synchronization once the timeout elapses. Hunks are cancelled
+    /// between each other, a backend call blocking forever cannot be
+    /// interrupted. Like for [`SyncBuilder::abort_on_systemic_error`],
+    /// the cache hunks of the processed hunks are journaled. Disabled
+    /// by default.
+    pub fn folder_timeout(mut self, timeout: Duration) -> Self {
+        self.folder_timeout = Some(timeout);
+        self
+    }
+
     pub fn on_progress<F>(mut self, f: F) -> Self
     where
         F: Fn(BackendSyncProgressEvent) -> Result<()> + Sync + Send + 'a,
@@ -149,42 +919,255 @@ impl<'a> SyncBuilder<'a> {
         local: &MaildirBackend,
         remote: &dyn Backend,
     ) -> Result<SyncReport>
+    where
+        F: ToString,
+    {
+        self.sync_with_cache_key(folder, conn, local, remote, &self.account_config.name)
+    }
+
+    /// Synchronizes the given folder between the local backend and
+    /// several remote backends, and returns one result per remote.
+    ///
+    /// The local backend acts as a hub: it is synchronized with each
+    /// remote in turn, then with all remotes but the last one again,
+    /// so that changes coming from any remote reach all the others.
+    /// Each remote has its own cache (see [`remote_cache_key`]), the
+    /// first remote sharing the cache of [`SyncBuilder::sync`].
+    /// Emails present on several remotes are matched by message id,
+    /// and are downloaded only once.
+    ///
+    /// Conflicts are resolved pair by pair, like for
+    /// [`SyncBuilder::sync`]: an email removed from one remote is
+    /// removed from the others, even if its flags changed there
+    /// meanwhile. A remote failing to synchronize is skipped by the
+    /// following passes and its result is replaced by the error, the
+    /// other remotes are still synchronized. Its cache only records
+    /// what has been applied, so its remaining changes are picked up
+    /// by the next synchronization.
+    pub fn sync_remotes<F>(
+        &self,
+        folder: F,
+        conn: &mut rusqlite::Connection,
+        local: &MaildirBackend,
+        remotes: &[Arc<dyn Backend + Sync + Send + '_>],
+    ) -> Vec<Result<SyncReport>>
     where
         F: ToString,
     {
         let account = &self.account_config.name;
         let folder = folder.to_string();
+        let mut reports: Vec<Result<SyncReport>> =
+            remotes.iter().map(|_| Ok(SyncReport::default())).collect();
+
+        // forward passes bring the changes of each remote to the
+        // local hub, backward passes spread the changes of the last
+        // remotes to the first ones
+        let passes = (0..remotes.len()).chain((0..remotes.len().saturating_sub(1)).rev());
+
+        for index in passes {
+            if reports[index].is_err() {
+                continue;
+            }
+
+            let key = remote_cache_key(account, index);
+            let remote = remotes[index].as_ref();
+            match self.sync_with_cache_key(&folder, conn, local, remote, &key) {
+                Ok(report) => {
+                    if let Ok(merged) = &mut reports[index] {
+                        merged.merge(report)
+                    }
+                }
+                Err(err) => {
+                    warn!("skipping remote {index} of folder {folder}: {err}");
+                    reports[index] = Err(err);
+                }
+            }
+        }
+
+        reports
+    }
+
+    /// Builds the envelopes patch of the given folder, without
+    /// applying it (see [`SyncBuilder::apply`]). The cache of the
+    /// folder is repaired first if the previous synchronization was
+    /// interrupted (see [`Cache::list_journal`]).
+    pub fn plan<F>(
+        &self,
+        folder: F,
+        conn: &mut rusqlite::Connection,
+        local: &MaildirBackend,
+        remote: &dyn Backend,
+    ) -> Result<FolderPlan>
+    where
+        F: ToString,
+    {
+        self.plan_with_cache_key(folder, conn, local, remote, &self.account_config.name, None)
+    }
+
+    /// Builds the envelopes patch of the given folder like
+    /// [`SyncBuilder::plan`], using the given remote envelopes instead
+    /// of listing them (see [`Backend::list_envelopes_multi`]). The
+    /// given modseq must have been read before the envelopes were
+    /// listed (see [`SyncBuilder::remote_modseq`]).
+    pub fn plan_prefetched<F>(
+        &self,
+        folder: F,
+        conn: &mut rusqlite::Connection,
+        local: &MaildirBackend,
+        remote: &dyn Backend,
+        remote_envelopes: crate::Envelopes,
+        modseq: Option<(u64, usize)>,
+    ) -> Result<FolderPlan>
+    where
+        F: ToString,
+    {
+        self.plan_with_cache_key(
+            folder,
+            conn,
+            local,
+            remote,
+            &self.account_config.name,
+            Some((remote_envelopes, modseq)),
+        )
+    }
+
+    /// Reads the highest modseq of the given remote folder (see
+    /// [`Backend::highest_modseq`]), and returns it along with `true`
+    /// if the folder can be listed incrementally since its last
+    /// synchronization. Only the other folders are worth listing
+    /// beforehand (see [`SyncBuilder::plan_prefetched`]).
+    pub fn remote_modseq<F>(
+        &self,
+        conn: &rusqlite::Connection,
+        remote: &dyn Backend,
+        folder: F,
+    ) -> (Option<(u64, usize)>, bool)
+    where
+        F: ToString,
+    {
+        let folder = folder::canonical_folder_name(&folder.to_string());
+        let modseq = highest_modseq(remote, &folder);
+        let incremental = match modseq {
+            Some((highest_modseq, _)) => {
+                last_modseq(conn, &self.account_config.name, &folder, highest_modseq).is_some()
+            }
+            None => false,
+        };
+        (modseq, incremental)
+    }
+
+    /// Synchronizes the given folder between the local backend and
+    /// one remote backend, reading and writing the cache under the
+    /// given key instead of the account name. This is the pairwise
+    /// step of [`SyncBuilder::sync`], which uses the account name as
+    /// key, and of the hub of [`SyncBuilder::sync_remotes`], which
+    /// calls it once per pass with the key of the remote (see
+    /// [`remote_cache_key`]), so that each local-remote pair keeps
+    /// its own view of the folder.
+    fn sync_with_cache_key<F>(
+        &self,
+        folder: F,
+        conn: &mut rusqlite::Connection,
+        local: &MaildirBackend,
+        remote: &dyn Backend,
+        account: &str,
+    ) -> Result<SyncReport>
+    where
+        F: ToString,
+    {
+        let plan = self.plan_with_cache_key(folder, conn, local, remote, account, None)?;
+        self.apply(plan, conn, local, remote)
+    }
+
+    fn plan_with_cache_key<F>(
+        &self,
+        folder: F,
+        conn: &mut rusqlite::Connection,
+        local: &MaildirBackend,
+        remote: &dyn Backend,
+        account: &str,
+        prefetched: Option<(crate::Envelopes, Option<(u64, usize)>)>,
+    ) -> Result<FolderPlan>
+    where
+        F: ToString,
+    {
+        let start = Instant::now();
+
+        // the folder is used as cache key, so it needs to be
+        // canonical (see [`folder::canonical_folder_name`])
+        let folder = folder::canonical_folder_name(&folder.to_string());
         info!("synchronizing {folder} envelopes of account {account}");
+        let _span = span!("sync_folder", account = %account, folder = %folder).entered();
+
+        // the cache hunks of the groups processed by an interrupted
+        // synchronization are applied first, otherwise the emails
+        // they copied or updated would look like user changes
+        if !self.dry_run {
+            let journal = Cache::list_journal(conn, account, &folder)?;
+            if !journal.is_empty() {
+                info!(
+                    "repairing cache of folder {folder} with {} hunk groups from an interrupted synchronization",
+                    journal.len()
+                );
+                // each group is marked as applied along with its
+                // cache hunks, so that only the unfinished groups are
+                // replayed if the repair is interrupted in turn
+                for (id, cache_hunks) in &journal {
+                    let tx = conn.transaction()?;
+                    for hunk in cache_hunks {
+                        apply_cache_hunk(&tx, account, hunk)?;
+                    }
+                    Cache::set_journal_applied(&tx, *id)?;
+                    tx.commit()?;
+                }
+                let tx = conn.transaction()?;
+                Cache::clear_journal(&tx, account, &folder)?;
+                tx.commit()?;
+            }
+        }
 
         self.try_progress(BackendSyncProgressEvent::GetLocalCachedEnvelopes);
 
         let local_envelopes_cached: Envelopes = HashMap::from_iter(
             Cache::list_local_envelopes(conn, account, &folder)?
-                .iter()
-                .map(|envelope| (envelope.message_id.clone(), envelope.clone())),
+                .into_iter()
+                .map(|envelope| (envelope.message_id.clone(), envelope)),
         );
 
         trace!("local envelopes cached: {:#?}", local_envelopes_cached);
 
         self.try_progress(BackendSyncProgressEvent::GetLocalEnvelopes);
 
+        // placeholders of unparsable emails cannot be matched between
+        // backends, see [`Envelope::unparsable`]
+        let mut unparsable_envelopes = Vec::new();
+
         let local_envelopes: Envelopes = HashMap::from_iter(
-            local
-                .list_envelopes(&folder, 0, 0)
+            self.dedup(
+                timed("list local envelopes", || {
+                    local.list_envelopes(&folder, 0, 0)
+                })
                 .or_else(|err| {
                     if self.dry_run {
                         Ok(Default::default())
                     } else {
                         Err(Box::new(err))
                     }
-                })?
-                .iter()
-                .map(|envelope| {
-                    (
-                        envelope.message_id.clone(),
-                        envelope.clone_without_custom_flags(),
-                    )
-                }),
+                })?,
+            )
+            .into_iter()
+            .filter_map(|envelope| match &envelope.parse_error {
+                Some(err) => {
+                    warn!("skipping unparsable local envelope {}: {err}", envelope.id);
+                    unparsable_envelopes.push((
+                        folder.clone(),
+                        envelope,
+                        HunkKindRestricted::Local,
+                    ));
+                    None
+                }
+                None => Some((envelope.message_id.clone(), envelope.without_custom_flags())),
+            }),
         );
 
         trace!("local envelopes: {:#?}", local_envelopes);
@@ -193,58 +1176,162 @@ impl<'a> SyncBuilder<'a> {
 
         let remote_envelopes_cached: Envelopes = HashMap::from_iter(
             Cache::list_remote_envelopes(conn, account, &folder)?
-                .iter()
-                .map(|envelope| (envelope.message_id.clone(), envelope.clone())),
+                .into_iter()
+                .map(|envelope| (envelope.message_id.clone(), envelope)),
         );
 
         trace!("remote envelopes cached: {:#?}", remote_envelopes_cached);
 
         self.try_progress(BackendSyncProgressEvent::GetRemoteEnvelopes);
 
-        let remote_envelopes: Envelopes = HashMap::from_iter(
-            remote
-                .list_envelopes(&folder, 0, 0)
-                .or_else(|err| {
+        // the highest modseq is read before listing the envelopes, so
+        // that changes happening meanwhile are listed again next time
+        let (modseq, remote_envelopes_prefetched) = match prefetched {
+            Some((envelopes, modseq)) => (modseq, Some(envelopes)),
+            None => (highest_modseq(remote, &folder), None),
+        };
+
+        let remote_envelopes = timed("list remote envelopes", || {
+            if let Some(envelopes) = remote_envelopes_prefetched {
+                return Ok(envelopes);
+            }
+            match self.list_changed_remote_envelopes(
+                conn,
+                remote,
+                account,
+                &folder,
+                modseq,
+                &remote_envelopes_cached,
+            ) {
+                Some(envelopes) => Ok(envelopes),
+                None => remote.list_envelopes(&folder, 0, 0).or_else(|err| {
                     if self.dry_run {
                         Ok(Default::default())
                     } else {
                         Err(Box::new(err))
                     }
-                })?
-                .iter()
-                .map(|envelope| {
-                    (
-                        envelope.message_id.clone(),
-                        envelope.clone_without_custom_flags(),
-                    )
                 }),
-        );
+            }
+        })?;
+
+        let remote_envelopes = self.dedup(remote_envelopes);
+        let remote_envelopes: Envelopes =
+            HashMap::from_iter(remote_envelopes.into_iter().filter_map(|envelope| {
+                match &envelope.parse_error {
+                    Some(err) => {
+                        warn!("skipping unparsable remote envelope {}: {err}", envelope.id);
+                        unparsable_envelopes.push((
+                            folder.clone(),
+                            envelope,
+                            HunkKindRestricted::Remote,
+                        ));
+                        None
+                    }
+                    None => Some((envelope.message_id.clone(), envelope.without_custom_flags())),
+                }
+            }));
 
         trace!("remote envelopes: {:#?}", remote_envelopes);
 
         self.try_progress(BackendSyncProgressEvent::BuildEnvelopesPatch);
 
-        let patch = build_patch(
-            &folder,
-            local_envelopes_cached,
-            local_envelopes,
-            remote_envelopes_cached,
-            remote_envelopes,
+        let conflict_resolution = self
+            .account_config
+            .folder_conflict_resolution(&folder)
+            .unwrap_or(self.conflict_resolution);
+        debug!("conflict resolution of folder {folder}: {conflict_resolution:?}");
+
+        let (departures, arrivals) = move_candidates(
+            &local_envelopes_cached,
+            &local_envelopes,
+            &remote_envelopes_cached,
+            &remote_envelopes,
         );
 
+        let mut patch = timed("build envelopes patch", || {
+            build_patch_with(
+                &folder,
+                local_envelopes_cached,
+                local_envelopes,
+                remote_envelopes_cached,
+                remote_envelopes,
+                self.sync_deleted,
+                conflict_resolution,
+            )
+        });
+
+        let read_only = self.filter_read_only_hunks(&folder, local, remote, &mut patch);
+
+        Ok(FolderPlan {
+            folder,
+            account: account.to_owned(),
+            patch,
+            read_only,
+            unparsable_envelopes,
+            modseq,
+            departures,
+            arrivals,
+            planning: start.elapsed(),
+        })
+    }
+
+    /// Applies the given envelopes patch, built by
+    /// [`SyncBuilder::plan`], and updates the cache accordingly.
+    pub fn apply(
+        &self,
+        plan: FolderPlan,
+        conn: &mut rusqlite::Connection,
+        local: &MaildirBackend,
+        remote: &dyn Backend,
+    ) -> Result<SyncReport> {
+        let FolderPlan {
+            folder,
+            account,
+            mut patch,
+            read_only,
+            unparsable_envelopes,
+            modseq,
+            planning,
+            ..
+        } = plan;
+        let account = account.as_str();
+        let _span = span!("sync_folder", account = %account, folder = %folder).entered();
+
+        // the watchdog lives until the end of the folder
+        // synchronization, the time spent planning included (see
+        // [`SyncBuilder::folder_timeout`])
+        let watchdog = self
+            .folder_timeout
+            .map(|timeout| Watchdog::spawn(&folder, timeout.saturating_sub(planning)));
+        let cancellation = watchdog
+            .as_ref()
+            .map(|watchdog| watchdog.token.clone())
+            .unwrap_or_default();
+
+        if self.newest_first {
+            sort_patch_by_date(&mut patch);
+        } else {
+            sort_patch_by_cost(&mut patch, self.batch_size);
+        }
+
         self.try_progress(BackendSyncProgressEvent::ProcessEnvelopesPatch(patch.len()));
 
         debug!("envelopes patch: {:#?}", patch);
 
+        if let Some(path) = &self.audit_log {
+            if let Err(err) = log_patch(path, account, &folder, &patch) {
+                warn!("cannot log envelopes patch, skipping it: {err}");
+            }
+        }
+
         let mut report = SyncReport::default();
+        for (target, hunks) in read_only {
+            report.push_read_only(folder.clone(), target, hunks);
+        }
 
         if self.dry_run {
             info!("dry run enabled, skipping envelopes patch");
-            report.patch = patch
-                .into_iter()
-                .flatten()
-                .map(|patch| (patch, None))
-                .collect();
+            report.patch = patch.into_iter().flatten().collect();
         } else {
             let process_hunk = |hunk: &BackendHunk| {
                 Result::Ok(match hunk {
@@ -268,6 +1355,44 @@ impl<'a> SyncBuilder<'a> {
                             TargetRestricted::Remote,
                         )]
                     }
+                    BackendHunk::CopyEmail(
+                        folder,
+                        envelope,
+                        HunkKindRestricted::Remote,
+                        HunkKindRestricted::Local,
+                        refresh_source_cache,
+                    ) if self.exceeding_size(envelope).is_some() => {
+                        let size = self.exceeding_size(envelope).unwrap_or_default();
+                        let mut cache_hunks = vec![];
+                        if *refresh_source_cache {
+                            cache_hunks.push(CacheHunk::InsertEnvelope(
+                                folder.clone(),
+                                envelope.clone_without_custom_flags(),
+                                TargetRestricted::Remote,
+                            ))
+                        };
+                        let headers = remote
+                            .get_email_headers_internal(folder, &envelope.internal_id)
+                            .map_err(Box::new)?;
+                        let email = partial::build_partial_email(&headers, size);
+                        let internal_id = local
+                            .add_email_internal(folder, &email, &envelope.flags)
+                            .map_err(Box::new)?;
+                        let envelope = local
+                            .get_envelope_internal(folder, &internal_id)
+                            .map_err(Box::new)?;
+                        cache_hunks.push(CacheHunk::SetLocalPartial(
+                            folder.clone(),
+                            envelope.message_id.clone(),
+                            Some(size),
+                        ));
+                        cache_hunks.push(CacheHunk::InsertEnvelope(
+                            folder.clone(),
+                            envelope.clone_without_custom_flags(),
+                            TargetRestricted::Local,
+                        ));
+                        cache_hunks
+                    }
                     BackendHunk::CopyEmail(
                         folder,
                         envelope,
@@ -313,6 +1438,11 @@ impl<'a> SyncBuilder<'a> {
                                 let envelope = local
                                     .get_envelope_internal(folder, &internal_id)
                                     .map_err(Box::new)?;
+                                cache_hunks.push(CacheHunk::SetLocalPartial(
+                                    folder.clone(),
+                                    envelope.message_id.clone(),
+                                    None,
+                                ));
                                 cache_hunks.push(CacheHunk::InsertEnvelope(
                                     folder.clone(),
                                     envelope.clone_without_custom_flags(),
@@ -320,6 +1450,11 @@ impl<'a> SyncBuilder<'a> {
                                 ));
                             }
                             HunkKindRestricted::Remote => {
+                                if partial::partial_size(email.raw()?).is_some() {
+                                    return Err(Error::UploadPartialEmailError(
+                                        envelope.message_id.clone(),
+                                    ));
+                                }
                                 let internal_id = remote
                                     .add_email_internal(&folder, email.raw()?, &envelope.flags)
                                     .map_err(Box::new)?;
@@ -361,122 +1496,839 @@ impl<'a> SyncBuilder<'a> {
                             .map_err(Box::new)?;
                         vec![]
                     }
-                    BackendHunk::SetFlags(folder, envelope, HunkKind::LocalCache) => {
-                        vec![
-                            CacheHunk::DeleteEnvelope(
-                                folder.clone(),
-                                envelope.internal_id.clone(),
-                                TargetRestricted::Local,
-                            ),
-                            CacheHunk::InsertEnvelope(
-                                folder.clone(),
-                                envelope.clone(),
-                                TargetRestricted::Local,
-                            ),
-                        ]
+                    BackendHunk::SetFlags(folder, internal_id, flags, HunkKind::LocalCache) => {
+                        vec![CacheHunk::SetFlags(
+                            folder.clone(),
+                            internal_id.clone(),
+                            flags.clone(),
+                            TargetRestricted::Local,
+                        )]
                     }
-                    BackendHunk::SetFlags(folder, envelope, HunkKind::Local) => {
+                    BackendHunk::SetFlags(folder, internal_id, flags, HunkKind::Local) => {
                         local
-                            .set_flags_internal(
-                                folder,
-                                vec![&envelope.internal_id],
-                                &envelope.flags,
-                            )
+                            .set_flags_internal(folder, vec![internal_id], flags)
                             .map_err(Box::new)?;
                         vec![]
                     }
-                    BackendHunk::SetFlags(folder, envelope, HunkKind::RemoteCache) => {
+                    BackendHunk::SetFlags(folder, internal_id, flags, HunkKind::RemoteCache) => {
+                        vec![CacheHunk::SetFlags(
+                            folder.clone(),
+                            internal_id.clone(),
+                            flags.clone(),
+                            TargetRestricted::Remote,
+                        )]
+                    }
+                    BackendHunk::SetFlags(folder, internal_id, flags, HunkKind::Remote) => {
+                        remote
+                            .set_flags_internal(folder, vec![internal_id], flags)
+                            .map_err(Box::new)?;
+                        vec![]
+                    }
+                    BackendHunk::MoveEmailToTrash(folder, envelope, target) => {
+                        let backend: &dyn Backend = match target {
+                            HunkKindRestricted::Local => local,
+                            HunkKindRestricted::Remote => remote,
+                        };
+                        let internal_ids = vec![envelope.internal_id.as_str()];
+                        let trash = self.account_config.trash_folder_alias()?;
+
+                        if *folder == trash {
+                            backend
+                                .delete_emails_internal(folder, internal_ids)
+                                .map_err(Box::new)?;
+                        } else {
+                            let mut flags = envelope.flags.clone();
+                            flags.remove(&Flag::Deleted);
+                            backend
+                                .set_flags_internal(folder, internal_ids.clone(), &flags)
+                                .map_err(Box::new)?;
+                            backend
+                                .move_emails_internal(folder, &trash, internal_ids)
+                                .map_err(Box::new)?;
+                        }
+                        vec![]
+                    }
+                    BackendHunk::MoveEmail(
+                        from,
+                        to,
+                        cached_internal_id,
+                        envelope,
+                        remote_envelope,
+                    ) => {
+                        let internal_id = envelope.internal_id.as_str();
+                        local
+                            .move_emails_internal(from, to, vec![internal_id])
+                            .map_err(Box::new)?;
+                        // the remote side is the one which moved the
+                        // email, its flags win
+                        if envelope.flags != remote_envelope.flags {
+                            local
+                                .set_flags_internal(to, vec![internal_id], &remote_envelope.flags)
+                                .map_err(Box::new)?;
+                        }
                         vec![
-                            CacheHunk::DeleteEnvelope(
-                                folder.clone(),
-                                envelope.internal_id.clone(),
-                                TargetRestricted::Remote,
+                            CacheHunk::MoveEnvelope(
+                                from.clone(),
+                                to.clone(),
+                                cached_internal_id.clone(),
+                                TargetRestricted::Local,
+                            ),
+                            CacheHunk::SetFlags(
+                                to.clone(),
+                                cached_internal_id.clone(),
+                                remote_envelope.flags.clone(),
+                                TargetRestricted::Local,
                             ),
                             CacheHunk::InsertEnvelope(
-                                folder.clone(),
-                                envelope.clone(),
+                                to.clone(),
+                                remote_envelope.clone_without_custom_flags(),
                                 TargetRestricted::Remote,
                             ),
                         ]
                     }
-                    BackendHunk::SetFlags(folder, envelope, HunkKind::Remote) => {
-                        remote
-                            .set_flags_internal(
-                                folder,
-                                vec![&envelope.internal_id],
-                                &envelope.flags,
-                            )
-                            .map_err(Box::new)?;
-                        vec![]
+                })
+            };
+
+            let process_hunk_with_retry = |hunk: &BackendHunk| {
+                let mut attempt = 0;
+                loop {
+                    match process_hunk(hunk) {
+                        Err(err) if err.is_transient() => {
+                            match self.hunk_retry_policy.delay(attempt) {
+                                Some(delay) => {
+                                    warn!("transient error while processing hunk {hunk}, retrying in {delay:?}: {err:?}");
+                                    thread::sleep(delay);
+                                    attempt += 1;
+                                }
+                                None => break Err(err),
+                            }
+                        }
+                        res => break res,
+                    }
+                }
+            };
+
+            // the download size is estimated from the envelopes, see
+            // [`BackendHunk::estimated_cost`]
+            let downloads: Vec<&BackendHunk> = patch
+                .iter()
+                .flatten()
+                .filter(|hunk| hunk.is_download())
+                .collect();
+            let download_bytes: u64 = downloads
+                .iter()
+                .map(|hunk| hunk.estimated_cost().network_bytes)
+                .sum();
+            let downloaded_bytes = AtomicU64::new(0);
+
+            if !downloads.is_empty() {
+                self.try_progress(BackendSyncProgressEvent::StartEmailsDownload(
+                    downloads.len(),
+                    download_bytes,
+                ));
+            }
+
+            let process_hunks = |mut report: SyncReport, hunks: &Vec<BackendHunk>| {
+                let mut failed_hunk: Option<String> = None;
+
+                for hunk in hunks {
+                    if cancellation.is_cancelled() {
+                        debug!("folder synchronization cancelled, skipping hunk {hunk}");
+                        break;
+                    }
+
+                    if let Some(failed_hunk) = &failed_hunk {
+                        debug!("skipping hunk {hunk} depending on failed hunk {failed_hunk}");
+                        report.failed_hunks.push((
+                            hunk.clone(),
+                            Error::SkipDependentHunkError(failed_hunk.clone()),
+                        ));
+                        continue;
+                    }
+
+                    let hunk_str = hunk.to_string();
+                    let span = span!(
+                        "sync_hunk",
+                        hunk = %hunk_str,
+                        kind = hunk.name(),
+                        internal_id = hunk.internal_id(),
+                        elapsed_ms = tracing::field::Empty
+                    )
+                    .entered();
+
+                    trace!("processing hunk: {hunk:#?}");
+                    debug!("{hunk_str}");
+
+                    self.try_progress(BackendSyncProgressEvent::ProcessEnvelopeHunk(
+                        hunk_str.clone(),
+                    ));
+
+                    let start = Instant::now();
+                    let res = process_hunk_with_retry(hunk);
+                    span.record("elapsed_ms", start.elapsed().as_millis() as u64);
+
+                    match res {
+                        Ok(cache_hunks) => {
+                            if hunk.is_download() {
+                                let bytes = hunk.estimated_cost().network_bytes;
+                                let done = downloaded_bytes.fetch_add(bytes, Ordering::Relaxed);
+                                self.try_progress(BackendSyncProgressEvent::DownloadEmail(
+                                    done + bytes,
+                                    download_bytes,
+                                ));
+                            }
+                            report.patch.push(hunk.clone());
+                            report.cache_patch.0.extend(cache_hunks);
+                        }
+                        // the folder turned out to be read-only, which
+                        // is reported once per folder
+                        Err(err) if err.is_permission_denied() && hunk.target().is_some() => {
+                            debug!("folder is read-only, skipping hunk {hunk}: {err}");
+                            let cache_only = match self.read_only_strategy {
+                                ReadOnlyStrategy::Skip => None,
+                                ReadOnlyStrategy::CacheOnly => hunk.to_cache_only(),
+                            };
+                            if let Some(cache_only) = cache_only {
+                                match process_hunk(&cache_only) {
+                                    Ok(cache_hunks) => {
+                                        report.patch.push(cache_only);
+                                        report.cache_patch.0.extend(cache_hunks);
+                                    }
+                                    Err(err) => {
+                                        warn!("error while processing hunk {cache_only}, skipping it: {err:?}");
+                                        report.failed_hunks.push((cache_only, err));
+                                    }
+                                }
+                            }
+                            if let Some(target) = hunk.target() {
+                                report.push_read_only(folder.clone(), target, vec![hunk.clone()]);
+                            }
+                        }
+                        Err(err) => {
+                            warn!("error while processing hunk {hunk}, skipping it: {err:?}");
+                            report.failed_hunks.push((hunk.clone(), err));
+                            failed_hunk = Some(hunk_str);
+                        }
+                    };
+                }
+
+                report
+            };
+
+            let merge_reports = |mut r1: SyncReport, r2: SyncReport| {
+                r1.patch.extend(r2.patch);
+                r1.failed_hunks.extend(r2.failed_hunks);
+                r1.cache_patch.0.extend(r2.cache_patch.0);
+                for (folder, target, hunks) in r2.read_only {
+                    r1.push_read_only(folder, target, hunks);
+                }
+                r1
+            };
+
+            // the cache patch is only applied once the whole patch is
+            // processed, so the cache hunks of the fully processed
+            // groups are journaled after each batch in case the
+            // synchronization is interrupted
+            let journal = Mutex::new(&mut *conn);
+            let journal_batch = |reports: &[SyncReport]| {
+                let groups: Vec<&[CacheHunk]> = reports
+                    .iter()
+                    .filter(|report| report.failed_hunks.is_empty())
+                    .map(|report| report.cache_patch.0.as_slice())
+                    .filter(|cache_hunks| !cache_hunks.is_empty())
+                    .collect();
+                if groups.is_empty() {
+                    return Result::Ok(());
+                }
+
+                let mut conn = journal
+                    .lock()
+                    .map_err(|err| Error::LockConnectionError(err.to_string()))?;
+                let tx = conn.transaction()?;
+                for cache_hunks in groups {
+                    Cache::journal(&tx, account, &folder, cache_hunks)?;
+                }
+                tx.commit()?;
+                Result::Ok(())
+            };
+
+            // once a systemic error is found, the remaining batches
+            // are skipped
+            let systemic_errors = Mutex::new(SystemicErrorCheck::default());
+            let check_batch = |reports: &[SyncReport]| {
+                let threshold = match self.systemic_error_threshold {
+                    Some(threshold) => threshold,
+                    None => return false,
+                };
+
+                let mut check = systemic_errors
+                    .lock()
+                    .unwrap_or_else(|err| err.into_inner());
+                if check.systemic.is_none() {
+                    for report in reports {
+                        check.hunks += report.patch.len() + report.failed_hunks.len();
+                        for (class, count) in report.error_summary() {
+                            *check.summary.entry(class).or_default() += count;
+                        }
+                    }
+                    if let Some((class, count)) =
+                        find_systemic_error(&check.summary, check.hunks, threshold)
+                    {
+                        warn!(
+                            "{count} out of {} hunks failed with {class}, aborting",
+                            check.hunks
+                        );
+                        check.systemic = Some((class, count, check.hunks));
+                    }
+                }
+                check.systemic.is_some()
+            };
+
+            // rayon workers do not inherit the current span
+            let folder_span = Span::current();
+            let process_batch = |report: SyncReport, groups: &[Vec<BackendHunk>]| {
+                let _span =
+                    span!(parent: folder_span, "sync_batch", groups = groups.len()).entered();
+                if check_batch(&[]) {
+                    debug!("skipping envelopes batch after systemic error");
+                    return report;
+                }
+                let reports: Vec<SyncReport> = groups
+                    .iter()
+                    .map(|hunks| process_hunks(SyncReport::default(), hunks))
+                    .collect();
+                if let Err(err) = journal_batch(&reports) {
+                    warn!("cannot journal envelopes batch, skipping it: {err}");
+                }
+                check_batch(&reports);
+                reports.into_iter().fold(report, &merge_reports)
+            };
+
+            let process_patch = || {
+                let batch_size = self.batch_size.max(1);
+                if self.newest_first {
+                    // bridging the sequential iterator hands batches
+                    // to the workers in the patch order, whereas
+                    // parallel chunks are split recursively
+                    patch
+                        .chunks(batch_size)
+                        .par_bridge()
+                        .fold(SyncReport::default, &process_batch)
+                        .reduce(SyncReport::default, &merge_reports)
+                } else {
+                    patch
+                        .par_chunks(batch_size)
+                        .fold(SyncReport::default, &process_batch)
+                        .reduce(SyncReport::default, &merge_reports)
+                }
+            };
+
+            report = timed("apply envelopes patch", || {
+                Result::Ok(match (self.executor, self.parallelism) {
+                    (SyncExecutor::GlobalPool, Some(num_threads)) => ThreadPoolBuilder::new()
+                        .num_threads(num_threads)
+                        .build()
+                        .map_err(Error::BuildThreadPoolError)?
+                        .install(process_patch),
+                    (SyncExecutor::GlobalPool, None) => process_patch(),
+                    (SyncExecutor::Pool(pool), _) => pool.install(process_patch),
+                    (SyncExecutor::SingleThreaded, _) => patch
+                        .chunks(self.batch_size.max(1))
+                        .fold(SyncReport::default(), &process_batch),
+                })
+            })?;
+
+            drop(journal);
+
+            if cancellation.is_cancelled() {
+                return Err(Error::FolderSyncTimeoutError(folder));
+            }
+
+            let systemic_errors = systemic_errors
+                .into_inner()
+                .unwrap_or_else(|err| err.into_inner());
+            if let Some((class, count, hunks)) = systemic_errors.systemic {
+                return Err(Error::SystemicSyncError(folder, class, count, hunks));
+            }
+
+            if self.sync_deleted == SyncDeletedBehavior::Expunge {
+                let removed = |kind: HunkKind| {
+                    report.patch.iter().any(|hunk| {
+                        matches!(hunk, BackendHunk::RemoveEmail(_, _, target) if *target == kind)
+                    })
+                };
+
+                if removed(HunkKind::Local) {
+                    if let Err(err) = local.expunge_folder(&folder) {
+                        warn!("cannot expunge local folder {folder}, skipping it: {err}");
+                    }
+                }
+
+                if removed(HunkKind::Remote) {
+                    if let Err(err) = remote.expunge_folder(&folder) {
+                        warn!("cannot expunge remote folder {folder}, skipping it: {err}");
+                    }
+                }
+            }
+
+            let synced = report.failed_hunks.is_empty();
+            let mut process_cache_patch = || {
+                let tx = conn.transaction()?;
+                for hunk in &report.cache_patch.0 {
+                    apply_cache_hunk(&tx, account, hunk)?;
+                }
+                // the journaled cache hunks are part of the cache
+                // patch, they are not needed anymore
+                Cache::clear_journal(&tx, account, &folder)?;
+                if synced {
+                    Cache::set_last_synced_at(&tx, account, &folder, Utc::now())?;
+                    if let Some((modseq, _)) = modseq {
+                        Cache::set_modseq(&tx, account, &folder, modseq)?;
+                    }
+                }
+                tx.commit()?;
+                Result::Ok(())
+            };
+
+            if let Err(err) = process_cache_patch() {
+                warn!("error while processing cache patch: {err}");
+                report.cache_patch.1 = Some(err);
+            }
+        }
+
+        report.unparsable_envelopes = unparsable_envelopes;
+
+        for (folder, target, hunks) in &report.read_only {
+            warn!(
+                "{target} folder {folder} is read-only, {} changes not applied",
+                hunks.len()
+            );
+        }
+
+        trace!("sync report: {:#?}", report);
+
+        Ok(report)
+    }
+
+    /// Removes from the patch the hunks writing to the sides of the
+    /// given folder which are read-only, according to the read-only
+    /// strategy, and returns them by side.
+    fn filter_read_only_hunks(
+        &self,
+        folder: &str,
+        local: &dyn Backend,
+        remote: &dyn Backend,
+        patch: &mut Patch,
+    ) -> Vec<(TargetRestricted, Vec<BackendHunk>)> {
+        let is_read_only = |backend: &dyn Backend, target: TargetRestricted| {
+            let permission = backend.folder_permission(folder).unwrap_or_else(|err| {
+                warn!("cannot get permission of {target} folder {folder}, assuming it is writable: {err}");
+                FolderPermission::ReadWrite
+            });
+            permission.is_read_only()
+        };
+
+        let mut skipped: Vec<(TargetRestricted, Vec<BackendHunk>)> = Vec::new();
+        if is_read_only(local, TargetRestricted::Local) {
+            skipped.push((TargetRestricted::Local, Vec::new()));
+        }
+        if is_read_only(remote, TargetRestricted::Remote) {
+            skipped.push((TargetRestricted::Remote, Vec::new()));
+        }
+        if skipped.is_empty() {
+            return skipped;
+        }
+
+        for hunks in patch.iter_mut() {
+            *hunks = mem::take(hunks)
+                .into_iter()
+                .filter_map(|hunk| {
+                    let target = hunk.target();
+                    let skipped_hunks =
+                        match skipped.iter_mut().find(|(t, _)| Some(t) == target.as_ref()) {
+                            Some((_, skipped_hunks)) => skipped_hunks,
+                            None => return Some(hunk),
+                        };
+                    let cache_only = match self.read_only_strategy {
+                        ReadOnlyStrategy::Skip => None,
+                        ReadOnlyStrategy::CacheOnly => hunk.to_cache_only(),
+                    };
+                    skipped_hunks.push(hunk);
+                    cache_only
+                })
+                .collect();
+        }
+
+        // the cache of a read-only side must not record flags which
+        // have not been written to its backend, otherwise the next
+        // synchronization would revert them
+        if self.read_only_strategy == ReadOnlyStrategy::Skip {
+            let mut skipped_flags = HashSet::new();
+            for (target, hunks) in &skipped {
+                for hunk in hunks {
+                    if let BackendHunk::SetFlags(_, internal_id, _, _) = hunk {
+                        skipped_flags.insert((target.clone(), internal_id.clone()));
+                    }
+                }
+            }
+
+            for hunks in patch.iter_mut() {
+                hunks.retain(|hunk| match hunk {
+                    BackendHunk::SetFlags(_, internal_id, _, HunkKind::LocalCache) => {
+                        !skipped_flags.contains(&(TargetRestricted::Local, internal_id.clone()))
                     }
-                })
-            };
+                    BackendHunk::SetFlags(_, internal_id, _, HunkKind::RemoteCache) => {
+                        !skipped_flags.contains(&(TargetRestricted::Remote, internal_id.clone()))
+                    }
+                    _ => true,
+                });
+            }
+        }
 
-            report = patch
-                .par_iter()
-                .fold(SyncReport::default, |report, hunks| {
-                    hunks.iter().fold(report, |mut report, hunk| {
-                        let hunk_str = hunk.to_string();
+        patch.retain(|hunks| !hunks.is_empty());
+        skipped.retain(|(_, hunks)| !hunks.is_empty());
+        skipped
+    }
 
-                        trace!("processing hunk: {hunk:#?}");
-                        debug!("{hunk_str}");
+    /// Removes the listed envelopes sharing the same message id when
+    /// [`AccountConfig::sync_dedup`] is enabled.
+    fn dedup(&self, mut envelopes: crate::Envelopes) -> crate::Envelopes {
+        if self.account_config.sync_dedup && envelopes.has_duplicate_message_ids() {
+            debug!("removing listed envelopes sharing the same message id");
+            envelopes.dedup_by_message_id();
+        }
+        envelopes
+    }
 
-                        self.try_progress(BackendSyncProgressEvent::ProcessEnvelopeHunk(hunk_str));
+    /// Lists the remote envelopes of the given folder by merging the
+    /// envelopes changed since the last synchronization into the
+    /// cached ones, when the remote supports modification sequences
+    /// (see [`Backend::highest_modseq`]).
+    ///
+    /// Returns `None` when all the remote envelopes need to be listed
+    /// instead: on the first synchronization, when the modseq went
+    /// backwards, or when the merged envelopes do not add up to the
+    /// folder size, which happens when emails have been expunged.
+    fn list_changed_remote_envelopes(
+        &self,
+        conn: &rusqlite::Connection,
+        remote: &dyn Backend,
+        account: &str,
+        folder: &str,
+        modseq: Option<(u64, usize)>,
+        remote_envelopes_cached: &Envelopes,
+    ) -> Option<crate::Envelopes> {
+        let (highest_modseq, folder_size) = modseq?;
+        let last_modseq = last_modseq(conn, account, folder, highest_modseq)?;
 
-                        match process_hunk(hunk) {
-                            Ok(cache_hunks) => {
-                                report.patch.push((hunk.clone(), None));
-                                report.cache_patch.0.extend(cache_hunks);
-                            }
-                            Err(err) => {
-                                warn!("error while processing hunk {hunk:?}, skipping it: {err:?}");
-                                report.patch.push((hunk.clone(), Some(err)));
-                            }
-                        };
+        let changed = match remote.list_changed_envelopes_since_modseq(folder, last_modseq) {
+            Ok(changed) => changed,
+            Err(err) => {
+                warn!(
+                    "cannot list changed envelopes of remote folder {folder}, skipping it: {err}"
+                );
+                return None;
+            }
+        };
 
-                        report
-                    })
-                })
-                .reduce(SyncReport::default, |mut r1, r2| {
-                    r1.patch.extend(r2.patch);
-                    r1.cache_patch.0.extend(r2.cache_patch.0);
-                    r1
-                });
+        debug!(
+            "{} remote envelopes of folder {folder} changed since modseq {last_modseq}",
+            changed.len()
+        );
 
-            let mut process_cache_patch = || {
-                let tx = conn.transaction()?;
-                for hunk in &report.cache_patch.0 {
-                    match hunk {
-                        CacheHunk::InsertEnvelope(folder, envelope, TargetRestricted::Local) => {
-                            Cache::insert_local_envelope(&tx, account, folder, envelope.clone())?
-                        }
-                        CacheHunk::InsertEnvelope(folder, envelope, TargetRestricted::Remote) => {
-                            Cache::insert_remote_envelope(&tx, account, folder, envelope.clone())?
-                        }
-                        CacheHunk::DeleteEnvelope(folder, internal_id, TargetRestricted::Local) => {
-                            Cache::delete_local_envelope(&tx, account, folder, internal_id)?
-                        }
-                        CacheHunk::DeleteEnvelope(
-                            folder,
-                            internal_id,
-                            TargetRestricted::Remote,
-                        ) => Cache::delete_remote_envelope(&tx, account, folder, internal_id)?,
-                    }
+        let mut envelopes = remote_envelopes_cached.clone();
+        for envelope in changed {
+            envelopes.insert(envelope.message_id.clone(), envelope);
+        }
+
+        let internal_ids: HashSet<&str> = envelopes
+            .values()
+            .map(|envelope| envelope.internal_id.as_str())
+            .collect();
+
+        if envelopes.len() != folder_size || internal_ids.len() != folder_size {
+            debug!(
+                "{} merged remote envelopes of folder {folder} for {folder_size} emails, listing all envelopes",
+                envelopes.len()
+            );
+            return None;
+        }
+
+        Some(envelopes.into_values().collect())
+    }
+}
+
+/// Reads the highest modseq of the given remote folder, `None` when
+/// the remote does not support modification sequences.
+fn highest_modseq(remote: &dyn Backend, folder: &str) -> Option<(u64, usize)> {
+    remote.highest_modseq(folder).unwrap_or_else(|err| {
+        warn!("cannot get highest modseq of remote folder {folder}, skipping it: {err}");
+        None
+    })
+}
+
+/// Returns the highest modseq of the given remote folder at its last
+/// synchronization, `None` on the first synchronization or when the
+/// modseq went backwards since then.
+fn last_modseq(
+    conn: &rusqlite::Connection,
+    account: &str,
+    folder: &str,
+    highest_modseq: u64,
+) -> Option<u64> {
+    let last_modseq = match Cache::get_modseq(conn, account, folder) {
+        Ok(last_modseq) => last_modseq?,
+        Err(err) => {
+            warn!("cannot get last modseq of remote folder {folder}, skipping it: {err}");
+            return None;
+        }
+    };
+
+    if last_modseq > highest_modseq {
+        debug!("remote folder {folder} modseq went backwards, listing all envelopes");
+        return None;
+    }
+
+    Some(last_modseq)
+}
+
+fn apply_cache_hunk(tx: &rusqlite::Transaction, account: &str, hunk: &CacheHunk) -> Result<()> {
+    match hunk {
+        CacheHunk::InsertEnvelope(folder, envelope, TargetRestricted::Local) => {
+            Cache::insert_local_envelope(tx, account, folder, envelope.clone())
+        }
+        CacheHunk::InsertEnvelope(folder, envelope, TargetRestricted::Remote) => {
+            Cache::insert_remote_envelope(tx, account, folder, envelope.clone())
+        }
+        CacheHunk::DeleteEnvelope(folder, internal_id, TargetRestricted::Local) => {
+            Cache::delete_local_envelope(tx, account, folder, internal_id)
+        }
+        CacheHunk::DeleteEnvelope(folder, internal_id, TargetRestricted::Remote) => {
+            Cache::delete_remote_envelope(tx, account, folder, internal_id)
+        }
+        CacheHunk::SetFlags(folder, internal_id, flags, TargetRestricted::Local) => {
+            Cache::set_local_flags(tx, account, folder, internal_id, flags)
+        }
+        CacheHunk::SetFlags(folder, internal_id, flags, TargetRestricted::Remote) => {
+            Cache::set_remote_flags(tx, account, folder, internal_id, flags)
+        }
+        CacheHunk::SetLocalPartial(folder, message_id, size) => {
+            Cache::set_local_partial(tx, account, folder, message_id, *size)
+        }
+        CacheHunk::MoveEnvelope(from, to, internal_id, TargetRestricted::Local) => {
+            Cache::move_local_envelope(tx, account, from, to, internal_id)
+        }
+        CacheHunk::MoveEnvelope(from, to, internal_id, TargetRestricted::Remote) => {
+            Cache::move_remote_envelope(tx, account, from, to, internal_id)
+        }
+    }
+}
+
+/// Represents the envelopes patch of a folder, built but not applied
+/// yet (see [`SyncBuilder::plan`]).
+#[derive(Debug)]
+pub struct FolderPlan {
+    pub folder: FolderName,
+    pub patch: Patch,
+    account: String,
+    read_only: Vec<(TargetRestricted, Vec<BackendHunk>)>,
+    unparsable_envelopes: Vec<(FolderName, Envelope, SourceRestricted)>,
+    modseq: Option<(u64, usize)>,
+    /// Represents the emails removed from the remote folder, as
+    /// their local cache, local and remote cache envelopes.
+    departures: Vec<(Envelope, Envelope, Envelope)>,
+    /// Represents the emails added to the remote folder, as their
+    /// remote envelopes.
+    arrivals: Vec<Envelope>,
+    /// Represents the time spent building the plan, deducted from
+    /// the folder timeout (see [`SyncBuilder::folder_timeout`]).
+    planning: Duration,
+}
+
+/// Returns the emails which may have been moved to or from another
+/// folder on the remote side (see [`detect_moves`]): the ones removed
+/// from the remote folder only, with their local cache, local and
+/// remote cache envelopes, and the ones added to the remote folder
+/// only.
+fn move_candidates(
+    local_cache: &Envelopes,
+    local: &Envelopes,
+    remote_cache: &Envelopes,
+    remote: &Envelopes,
+) -> (Vec<(Envelope, Envelope, Envelope)>, Vec<Envelope>) {
+    let departures = local
+        .values()
+        .filter_map(|envelope| {
+            let cached = local_cache.get(&envelope.message_id)?;
+            let remote_cached = remote_cache.get(&envelope.message_id)?;
+            if remote.contains_key(&envelope.message_id) {
+                None
+            } else {
+                Some((cached.clone(), envelope.clone(), remote_cached.clone()))
+            }
+        })
+        .collect();
+
+    let arrivals = remote
+        .values()
+        .filter(|envelope| {
+            !local_cache.contains_key(&envelope.message_id)
+                && !local.contains_key(&envelope.message_id)
+                && !remote_cache.contains_key(&envelope.message_id)
+        })
+        .cloned()
+        .collect();
+
+    (departures, arrivals)
+}
+
+/// Represents the key of the hunk groups replaced by a move (see
+/// [`detect_moves`]).
+#[derive(Debug, Eq, Hash, PartialEq)]
+enum MoveKey {
+    LocalCacheRemoval(InternalId),
+    LocalRemoval(InternalId),
+    RemoteCacheRemoval(InternalId),
+    Download(MessageId),
+}
+
+impl MoveKey {
+    fn from_hunks(hunks: &[BackendHunk]) -> Option<Self> {
+        match hunks {
+            [BackendHunk::RemoveEmail(_, internal_id, HunkKind::LocalCache)] => {
+                Some(Self::LocalCacheRemoval(internal_id.clone()))
+            }
+            [BackendHunk::RemoveEmail(_, internal_id, HunkKind::Local)] => {
+                Some(Self::LocalRemoval(internal_id.clone()))
+            }
+            [BackendHunk::RemoveEmail(_, internal_id, HunkKind::RemoteCache)] => {
+                Some(Self::RemoteCacheRemoval(internal_id.clone()))
+            }
+            [BackendHunk::CopyEmail(
+                _,
+                envelope,
+                HunkKindRestricted::Remote,
+                HunkKindRestricted::Local,
+                true,
+            )] => Some(Self::Download(envelope.message_id.clone())),
+            _ => None,
+        }
+    }
+}
+
+/// Replaces, in the given folder plans, the emails removed from a
+/// remote folder and added to another one by local moves, instead of
+/// removing the local copy then downloading the email again.
+///
+/// Emails are matched by message id, and only when they leave exactly
+/// one folder and reach exactly one other folder, otherwise they are
+/// synchronized as usual. Only moves made on the remote side are
+/// detected: a local move still uploads the email to the new remote
+/// folder and removes it from the old one. The removal of the source
+/// remote cache envelope is grouped with the move, so that the email
+/// is not uploaded back to the source folder when the move fails.
+/// Returns the amount of moves found.
+pub fn detect_moves(plans: &mut [FolderPlan]) -> usize {
+    let mut departures: HashMap<&str, Vec<(usize, &(Envelope, Envelope, Envelope))>> =
+        HashMap::new();
+    let mut arrivals: HashMap<&str, Vec<(usize, &Envelope)>> = HashMap::new();
+    for (index, plan) in plans.iter().enumerate() {
+        for departure in &plan.departures {
+            departures
+                .entry(departure.1.message_id.as_str())
+                .or_default()
+                .push((index, departure));
+        }
+        for arrival in &plan.arrivals {
+            arrivals
+                .entry(arrival.message_id.as_str())
+                .or_default()
+                .push((index, arrival));
+        }
+    }
+
+    // the groups of the patches which can be replaced by a move
+    let keys: Vec<HashSet<MoveKey>> = plans
+        .iter()
+        .map(|plan| {
+            plan.patch
+                .iter()
+                .filter_map(|hunks| MoveKey::from_hunks(hunks))
+                .collect()
+        })
+        .collect();
+
+    let mut moves = Vec::new();
+    for (message_id, departures) in departures {
+        let arrivals = match arrivals.get(message_id) {
+            Some(arrivals) => arrivals,
+            None => continue,
+        };
+
+        let ((from, (local_cache, local, remote_cache)), (to, remote)) =
+            match (departures.as_slice(), arrivals.as_slice()) {
+                ([departure], [arrival]) if departure.0 != arrival.0 => (*departure, *arrival),
+                _ => {
+                    debug!("email {message_id} left or reached several folders, skipping move");
+                    continue;
                 }
-                tx.commit()?;
-                Result::Ok(())
             };
 
-            if let Err(err) = process_cache_patch() {
-                warn!("error while processing cache patch: {err}");
-                report.cache_patch.1 = Some(err);
-            }
+        let local_cache_removal = MoveKey::LocalCacheRemoval(local_cache.internal_id.clone());
+        let local_removal = MoveKey::LocalRemoval(local.internal_id.clone());
+        let remote_cache_removal = MoveKey::RemoteCacheRemoval(remote_cache.internal_id.clone());
+        let download = MoveKey::Download(remote.message_id.clone());
+        if !keys[from].contains(&local_cache_removal)
+            || !keys[from].contains(&local_removal)
+            || !keys[from].contains(&remote_cache_removal)
+            || !keys[to].contains(&download)
+        {
+            debug!("email {message_id} is not both removed and downloaded, skipping move");
+            continue;
         }
 
-        trace!("sync report: {:#?}", report);
+        moves.push((
+            from,
+            to,
+            [local_cache_removal, local_removal, remote_cache_removal],
+            download,
+            local_cache.internal_id.clone(),
+            remote_cache.internal_id.clone(),
+            local.clone(),
+            remote.clone(),
+        ));
+    }
 
-        Ok(report)
+    let mut removed: Vec<HashSet<MoveKey>> = plans.iter().map(|_| HashSet::new()).collect();
+    let mut added: Vec<Patch> = plans.iter().map(|_| Vec::new()).collect();
+    let count = moves.len();
+
+    for (from, to, removals, download, cached_id, remote_cached_id, local, remote) in moves {
+        let from_folder = plans[from].folder.clone();
+        let to_folder = plans[to].folder.clone();
+        debug!(
+            "email {} moved from {from_folder} to {to_folder}",
+            local.message_id
+        );
+        removed[from].extend(removals);
+        removed[to].insert(download);
+        added[to].push(vec![
+            BackendHunk::MoveEmail(from_folder.clone(), to_folder, cached_id, local, remote),
+            BackendHunk::RemoveEmail(from_folder, remote_cached_id, HunkKind::RemoteCache),
+        ]);
+    }
+
+    for ((plan, removed), added) in plans.iter_mut().zip(removed).zip(added) {
+        if !removed.is_empty() {
+            plan.patch.retain(|hunks| match MoveKey::from_hunks(hunks) {
+                Some(key) => !removed.contains(&key),
+                None => true,
+            });
+        }
+        plan.patch.extend(added);
     }
+
+    count
 }
 
 pub fn build_patch<F>(
@@ -489,6 +2341,33 @@ pub fn build_patch<F>(
 where
     F: Clone + ToString,
 {
+    build_patch_with(
+        folder,
+        local_cache,
+        local,
+        remote_cache,
+        remote,
+        SyncDeletedBehavior::default(),
+        ConflictResolution::default(),
+    )
+}
+
+/// Builds the envelopes patch like [`build_patch`], handling emails
+/// flagged as deleted according to the given behavior and flags
+/// changed on both sides according to the given strategy.
+pub fn build_patch_with<F>(
+    folder: F,
+    local_cache: Envelopes,
+    local: Envelopes,
+    remote_cache: Envelopes,
+    remote: Envelopes,
+    sync_deleted: SyncDeletedBehavior,
+    conflict_resolution: ConflictResolution,
+) -> Patch
+where
+    F: Clone + ToString,
+{
+    let folder: FolderName = folder.to_string();
     let mut patch: Patch = vec![];
     let mut message_ids = HashSet::new();
 
@@ -498,6 +2377,16 @@ where
     message_ids.extend(remote_cache.iter().map(|(id, _)| id.as_str()));
     message_ids.extend(remote.iter().map(|(id, _)| id.as_str()));
 
+    // When both caches are empty but both sides are populated, the
+    // folder is synchronized for the first time against an existing
+    // local folder (for example one filled by another synchronization
+    // tool). Emails present on both sides are then the same emails,
+    // which only need to be cached.
+    let adopting = local_cache.is_empty()
+        && remote_cache.is_empty()
+        && !local.is_empty()
+        && !remote.is_empty();
+
     // Given the matrice local_cache × local × remote_cache × remote,
     // checks every 2⁴ = 16 possibilities:
     for message_id in message_ids {
@@ -506,6 +2395,19 @@ where
         let remote_cache = remote_cache.get(message_id);
         let remote = remote.get(message_id);
 
+        if let Some(hunks) = build_deleted_hunks(
+            &folder,
+            local_cache,
+            local,
+            remote_cache,
+            remote,
+            sync_deleted,
+            conflict_resolution,
+        ) {
+            patch.push(hunks);
+            continue;
+        }
+
         match (local_cache, local, remote_cache, remote) {
             // 0000
             //
@@ -519,7 +2421,7 @@ where
             // new email has been added remote side and needs to be
             // cached remote side + copied local side.
             (None, None, None, Some(remote)) => patch.push(vec![BackendHunk::CopyEmail(
-                folder.to_string(),
+                folder.clone(),
                 remote.clone(),
                 HunkKindRestricted::Remote,
                 HunkKindRestricted::Local,
@@ -532,7 +2434,7 @@ where
             // an email is outdated and needs to be removed from the
             // remote cache.
             (None, None, Some(remote_cache), None) => patch.push(vec![BackendHunk::RemoveEmail(
-                folder.to_string(),
+                folder.clone(),
                 remote_cache.internal_id.clone(),
                 HunkKind::RemoteCache,
             )]),
@@ -548,7 +2450,7 @@ where
             // TODO: make this behaviour customizable.
             (None, None, Some(remote_cache), Some(remote)) => {
                 patch.push(vec![BackendHunk::CopyEmail(
-                    folder.to_string(),
+                    folder.clone(),
                     remote.clone(),
                     HunkKindRestricted::Remote,
                     HunkKindRestricted::Local,
@@ -557,11 +2459,9 @@ where
 
                 if remote_cache.flags != remote.flags {
                     patch.push(vec![BackendHunk::SetFlags(
-                        folder.to_string(),
-                        Envelope {
-                            flags: remote.flags.clone(),
-                            ..remote_cache.clone()
-                        },
+                        folder.clone(),
+                        remote_cache.internal_id.clone(),
+                        remote.flags.clone(),
                         HunkKind::RemoteCache,
                     )])
                 }
@@ -573,7 +2473,7 @@ where
             // new email has been added local side and needs to be
             // added cached local side + added remote sides.
             (None, Some(local), None, None) => patch.push(vec![BackendHunk::CopyEmail(
-                folder.to_string(),
+                folder.clone(),
                 local.clone(),
                 HunkKindRestricted::Local,
                 HunkKindRestricted::Remote,
@@ -589,17 +2489,33 @@ where
             // NOTE: this case should never happen: new emails
             // internal identifier are unique and should (in theory)
             // never conflict, but we implement this case for the sake
-            // of exhaustiveness.
+            // of exhaustiveness. The exception is the first
+            // synchronization of an existing local folder, where both
+            // envelopes are just cached: their flags are synchronized
+            // by the next synchronization.
             (None, Some(local), None, Some(remote)) => {
-                if local.date > remote.date {
+                if adopting {
+                    patch.push(vec![
+                        BackendHunk::CacheEnvelope(
+                            folder.clone(),
+                            local.internal_id.clone(),
+                            HunkKindRestricted::Local,
+                        ),
+                        BackendHunk::CacheEnvelope(
+                            folder.clone(),
+                            remote.internal_id.clone(),
+                            HunkKindRestricted::Remote,
+                        ),
+                    ])
+                } else if local.date > remote.date {
                     patch.push(vec![
                         BackendHunk::RemoveEmail(
-                            folder.to_string(),
+                            folder.clone(),
                             remote.internal_id.clone(),
                             HunkKind::Remote,
                         ),
                         BackendHunk::CopyEmail(
-                            folder.to_string(),
+                            folder.clone(),
                             local.clone(),
                             HunkKindRestricted::Local,
                             HunkKindRestricted::Remote,
@@ -609,12 +2525,12 @@ where
                 } else {
                     patch.push(vec![
                         BackendHunk::RemoveEmail(
-                            folder.to_string(),
+                            folder.clone(),
                             local.internal_id.clone(),
                             HunkKind::Local,
                         ),
                         BackendHunk::CopyEmail(
-                            folder.to_string(),
+                            folder.clone(),
                             remote.clone(),
                             HunkKindRestricted::Remote,
                             HunkKindRestricted::Local,
@@ -637,12 +2553,12 @@ where
             // TODO: make this behaviour customizable.
             (None, Some(local), Some(remote_cache), None) => patch.push(vec![
                 BackendHunk::RemoveEmail(
-                    folder.to_string(),
+                    folder.clone(),
                     remote_cache.internal_id.clone(),
                     HunkKind::RemoteCache,
                 ),
                 BackendHunk::CopyEmail(
-                    folder.to_string(),
+                    folder.clone(),
                     local.clone(),
                     HunkKindRestricted::Local,
                     HunkKindRestricted::Remote,
@@ -657,42 +2573,43 @@ where
             // to be updated. Flags also need to be synchronized.
             (None, Some(local), Some(remote_cache), Some(remote)) => {
                 patch.push(vec![BackendHunk::CacheEnvelope(
-                    folder.to_string(),
+                    folder.clone(),
                     local.internal_id.clone(),
                     HunkKindRestricted::Local,
                 )]);
 
-                let flags = flag::sync_all(None, Some(local), Some(remote_cache), Some(remote));
+                let flags = flag::sync_all_with(
+                    None,
+                    Some(local),
+                    Some(remote_cache),
+                    Some(remote),
+                    sync_deleted,
+                    conflict_resolution,
+                );
 
                 if local.flags != flags {
                     patch.push(vec![BackendHunk::SetFlags(
-                        folder.to_string(),
-                        Envelope {
-                            flags: flags.clone(),
-                            ..local.clone()
-                        },
+                        folder.clone(),
+                        local.internal_id.clone(),
+                        flags.clone(),
                         HunkKind::Local,
                     )]);
                 }
 
                 if remote_cache.flags != flags {
                     patch.push(vec![BackendHunk::SetFlags(
-                        folder.to_string(),
-                        Envelope {
-                            flags: flags.clone(),
-                            ..remote_cache.clone()
-                        },
+                        folder.clone(),
+                        remote_cache.internal_id.clone(),
+                        flags.clone(),
                         HunkKind::RemoteCache,
                     )]);
                 }
 
                 if remote.flags != flags {
                     patch.push(vec![BackendHunk::SetFlags(
-                        folder.to_string(),
-                        Envelope {
-                            flags: flags.clone(),
-                            ..remote.clone()
-                        },
+                        folder.clone(),
+                        remote.internal_id.clone(),
+                        flags.clone(),
                         HunkKind::Remote,
                     )]);
                 }
@@ -704,7 +2621,7 @@ where
             // the local cache has an outdated email and need to be
             // cleaned.
             (Some(local_cache), None, None, None) => patch.push(vec![BackendHunk::RemoveEmail(
-                folder.to_string(),
+                folder.clone(),
                 local_cache.internal_id.clone(),
                 HunkKind::LocalCache,
             )]),
@@ -721,12 +2638,12 @@ where
             // TODO: make this behaviour customizable.
             (Some(local_cache), None, None, Some(remote)) => patch.push(vec![
                 BackendHunk::RemoveEmail(
-                    folder.to_string(),
+                    folder.clone(),
                     local_cache.internal_id.clone(),
                     HunkKind::LocalCache,
                 ),
                 BackendHunk::CopyEmail(
-                    folder.to_string(),
+                    folder.clone(),
                     remote.clone(),
                     HunkKindRestricted::Remote,
                     HunkKindRestricted::Local,
@@ -740,12 +2657,12 @@ where
             // have an outdated email and need to be cleaned up.
             (Some(local_cache), None, Some(remote_cache), None) => patch.extend([
                 vec![BackendHunk::RemoveEmail(
-                    folder.to_string(),
+                    folder.clone(),
                     local_cache.internal_id.clone(),
                     HunkKind::LocalCache,
                 )],
                 vec![BackendHunk::RemoveEmail(
-                    folder.to_string(),
+                    folder.clone(),
                     remote_cache.internal_id.clone(),
                     HunkKind::RemoteCache,
                 )],
@@ -758,17 +2675,17 @@ where
             // be removed everywhere else.
             (Some(local_cache), None, Some(remote_cache), Some(remote)) => patch.extend([
                 vec![BackendHunk::RemoveEmail(
-                    folder.to_string(),
+                    folder.clone(),
                     local_cache.internal_id.clone(),
                     HunkKind::LocalCache,
                 )],
                 vec![BackendHunk::RemoveEmail(
-                    folder.to_string(),
+                    folder.clone(),
                     remote_cache.internal_id.clone(),
                     HunkKind::RemoteCache,
                 )],
                 vec![BackendHunk::RemoveEmail(
-                    folder.to_string(),
+                    folder.clone(),
                     remote.internal_id.clone(),
                     HunkKind::Remote,
                 )],
@@ -786,7 +2703,7 @@ where
             // TODO: make this behaviour customizable.
             (Some(local_cache), Some(local), None, None) => {
                 patch.push(vec![BackendHunk::CopyEmail(
-                    folder.to_string(),
+                    folder.clone(),
                     local.clone(),
                     HunkKindRestricted::Local,
                     HunkKindRestricted::Remote,
@@ -795,11 +2712,9 @@ where
 
                 if local_cache.flags != local.flags {
                     patch.push(vec![BackendHunk::SetFlags(
-                        folder.to_string(),
-                        Envelope {
-                            flags: local.flags.clone(),
-                            ..local_cache.clone()
-                        },
+                        folder.clone(),
+                        local_cache.internal_id.clone(),
+                        local.flags.clone(),
                         HunkKind::LocalCache,
                     )]);
                 }
@@ -812,43 +2727,44 @@ where
             // needs to be updated. Flags also need to be
             // synchronized.
             (Some(local_cache), Some(local), None, Some(remote)) => {
-                let flags = flag::sync_all(Some(local_cache), Some(local), None, Some(remote));
+                let flags = flag::sync_all_with(
+                    Some(local_cache),
+                    Some(local),
+                    None,
+                    Some(remote),
+                    sync_deleted,
+                    conflict_resolution,
+                );
 
                 if local_cache.flags != flags {
                     patch.push(vec![BackendHunk::SetFlags(
-                        folder.to_string(),
-                        Envelope {
-                            flags: flags.clone(),
-                            ..local_cache.clone()
-                        },
+                        folder.clone(),
+                        local_cache.internal_id.clone(),
+                        flags.clone(),
                         HunkKind::LocalCache,
                     )]);
                 }
 
                 if local.flags != flags {
                     patch.push(vec![BackendHunk::SetFlags(
-                        folder.to_string(),
-                        Envelope {
-                            flags: flags.clone(),
-                            ..local.clone()
-                        },
+                        folder.clone(),
+                        local.internal_id.clone(),
+                        flags.clone(),
                         HunkKind::Local,
                     )]);
                 }
 
                 if remote.flags != flags {
                     patch.push(vec![BackendHunk::SetFlags(
-                        folder.to_string(),
-                        Envelope {
-                            flags: flags.clone(),
-                            ..remote.clone()
-                        },
+                        folder.clone(),
+                        remote.internal_id.clone(),
+                        flags.clone(),
                         HunkKind::Remote,
                     )]);
                 }
 
                 patch.push(vec![BackendHunk::CacheEnvelope(
-                    folder.to_string(),
+                    folder.clone(),
                     remote.internal_id.clone(),
                     HunkKindRestricted::Remote,
                 )]);
@@ -861,89 +2777,479 @@ where
             // to be removed everywhere else.
             (Some(local_cache), Some(local), Some(remote_cache), None) => patch.extend([
                 vec![BackendHunk::RemoveEmail(
-                    folder.to_string(),
+                    folder.clone(),
                     local_cache.internal_id.clone(),
                     HunkKind::LocalCache,
                 )],
                 vec![BackendHunk::RemoveEmail(
-                    folder.to_string(),
+                    folder.clone(),
                     local.internal_id.clone(),
                     HunkKind::Local,
                 )],
                 vec![BackendHunk::RemoveEmail(
-                    folder.to_string(),
+                    folder.clone(),
                     remote_cache.internal_id.clone(),
                     HunkKind::RemoteCache,
                 )],
             ]),
 
-            // 1111
-            //
-            // The message_id exists everywhere, which means all flags need
-            // to be synchronized.
-            (Some(local_cache), Some(local), Some(remote_cache), Some(remote)) => {
-                let flags = flag::sync_all(
-                    Some(local_cache),
-                    Some(local),
-                    Some(remote_cache),
-                    Some(remote),
-                );
+            // 1111
+            //
+            // The message_id exists everywhere, which means all flags need
+            // to be synchronized.
+            (Some(local_cache), Some(local), Some(remote_cache), Some(remote)) => {
+                let flags = flag::sync_all_with(
+                    Some(local_cache),
+                    Some(local),
+                    Some(remote_cache),
+                    Some(remote),
+                    sync_deleted,
+                    conflict_resolution,
+                );
+
+                if local_cache.flags != flags {
+                    patch.push(vec![BackendHunk::SetFlags(
+                        folder.clone(),
+                        local_cache.internal_id.clone(),
+                        flags.clone(),
+                        HunkKind::LocalCache,
+                    )]);
+                }
+
+                if local.flags != flags {
+                    patch.push(vec![BackendHunk::SetFlags(
+                        folder.clone(),
+                        local.internal_id.clone(),
+                        flags.clone(),
+                        HunkKind::Local,
+                    )]);
+                }
+
+                if remote_cache.flags != flags {
+                    patch.push(vec![BackendHunk::SetFlags(
+                        folder.clone(),
+                        remote_cache.internal_id.clone(),
+                        flags.clone(),
+                        HunkKind::RemoteCache,
+                    )]);
+                }
+
+                if remote.flags != flags {
+                    patch.push(vec![BackendHunk::SetFlags(
+                        folder.clone(),
+                        remote.internal_id.clone(),
+                        flags.clone(),
+                        HunkKind::Remote,
+                    )]);
+                }
+            }
+        }
+    }
+
+    patch
+}
+
+/// Builds the hunks removing an email stably flagged as deleted (see
+/// [`SyncDeletedBehavior`]). Returns `None` if the email is not
+/// stably flagged as deleted, if deletions are propagated as flags,
+/// or if the other side changed since the last synchronization and
+/// the conflict is not resolved in favor of the deleted side (see
+/// [`ConflictResolution`]).
+fn build_deleted_hunks(
+    folder: &FolderName,
+    local_cache: Option<&Envelope>,
+    local: Option<&Envelope>,
+    remote_cache: Option<&Envelope>,
+    remote: Option<&Envelope>,
+    sync_deleted: SyncDeletedBehavior,
+    conflict_resolution: ConflictResolution,
+) -> Option<Vec<BackendHunk>> {
+    let deleted = |envelope: Option<&Envelope>| {
+        envelope
+            .map(|envelope| envelope.flags.contains(&Flag::Deleted))
+            .unwrap_or_default()
+    };
+
+    let local_deleted = deleted(local_cache) && deleted(local);
+    let remote_deleted = deleted(remote_cache) && deleted(remote);
+
+    if sync_deleted == SyncDeletedBehavior::PropagateFlag || !(local_deleted || remote_deleted) {
+        return None;
+    }
+
+    let changed = |cache: Option<&Envelope>, envelope: Option<&Envelope>| match (cache, envelope) {
+        (Some(cache), Some(envelope)) => cache.flags != envelope.flags,
+        _ => false,
+    };
+
+    // the deletion of one side conflicts with the changes of the
+    // other side, like clearing the deleted flag
+    let conflict = (local_deleted && !remote_deleted && changed(remote_cache, remote))
+        || (remote_deleted && !local_deleted && changed(local_cache, local));
+
+    if conflict {
+        let deleted_side_wins = match conflict_resolution {
+            ConflictResolution::KeepFlag => false,
+            ConflictResolution::PreferLocal => local_deleted,
+            ConflictResolution::PreferRemote => remote_deleted,
+        };
+
+        if !deleted_side_wins {
+            return None;
+        }
+    }
+
+    let remove = |envelope: Option<&Envelope>, target: HunkKind| {
+        envelope.map(|envelope| {
+            BackendHunk::RemoveEmail(folder.clone(), envelope.internal_id.clone(), target)
+        })
+    };
+
+    let mut hunks = vec![];
+
+    // the remote side takes precedence when both sides are deleted
+    match (sync_deleted, remote_deleted) {
+        (SyncDeletedBehavior::MoveToTrash, true) => {
+            hunks.extend(remote.map(|remote| {
+                BackendHunk::MoveEmailToTrash(
+                    folder.clone(),
+                    remote.clone(),
+                    HunkKindRestricted::Remote,
+                )
+            }));
+            hunks.extend(remove(local, HunkKind::Local));
+        }
+        (SyncDeletedBehavior::MoveToTrash, false) => {
+            hunks.extend(local.map(|local| {
+                BackendHunk::MoveEmailToTrash(
+                    folder.clone(),
+                    local.clone(),
+                    HunkKindRestricted::Local,
+                )
+            }));
+            hunks.extend(remove(remote, HunkKind::Remote));
+        }
+        _ => {
+            hunks.extend(remove(remote, HunkKind::Remote));
+            hunks.extend(remove(local, HunkKind::Local));
+        }
+    }
+
+    hunks.extend(remove(local_cache, HunkKind::LocalCache));
+    hunks.extend(remove(remote_cache, HunkKind::RemoteCache));
+
+    Some(hunks)
+}
+
+#[cfg(test)]
+mod envelopes_sync {
+    use chrono::{Local, TimeZone};
+    use std::{thread, time::Duration};
+
+    use crate::{Envelope, Flag, Flags};
+
+    use super::{
+        BackendHunk, ConflictResolution, Envelopes, FolderPlan, HunkCost, HunkKind,
+        HunkKindRestricted, HunkRetryPolicy, Patch, PatchLogEntry, SyncDeletedBehavior, Watchdog,
+    };
+
+    /// Builds the plan of the given folder, like
+    /// [`super::SyncBuilder::plan`] does.
+    fn folder_plan(
+        folder: &str,
+        local_cache: Envelopes,
+        local: Envelopes,
+        remote_cache: Envelopes,
+        remote: Envelopes,
+    ) -> FolderPlan {
+        let (departures, arrivals) =
+            super::move_candidates(&local_cache, &local, &remote_cache, &remote);
+        FolderPlan {
+            folder: folder.into(),
+            patch: super::build_patch(folder, local_cache, local, remote_cache, remote),
+            account: "account".into(),
+            read_only: Vec::new(),
+            unparsable_envelopes: Vec::new(),
+            modseq: None,
+            departures,
+            arrivals,
+            planning: Duration::ZERO,
+        }
+    }
+
+    #[test]
+    fn detect_moves() {
+        let envelope = |internal_id: &str, message_id: &str, flags: Flags| Envelope {
+            id: internal_id.into(),
+            internal_id: internal_id.into(),
+            message_id: message_id.into(),
+            flags,
+            ..Envelope::default()
+        };
+        let envelopes = |envelopes: Vec<Envelope>| {
+            Envelopes::from_iter(
+                envelopes
+                    .into_iter()
+                    .map(|envelope| (envelope.message_id.clone(), envelope)),
+            )
+        };
+
+        // <a> moved from INBOX to Archives, and flagged meanwhile
+        let a_cached = envelope("cached-a", "<a>", Flags::from_iter([Flag::Seen]));
+        let a_local = envelope("local-a", "<a>", Flags::from_iter([Flag::Seen]));
+        let a_remote = envelope("1", "<a>", Flags::from_iter([Flag::Seen]));
+        let a_moved = envelope("7", "<a>", Flags::from_iter([Flag::Seen, Flag::Flagged]));
+        // <b> removed from INBOX
+        let b_local = envelope("local-b", "<b>", Flags::default());
+        let b_remote = envelope("2", "<b>", Flags::default());
+        // <c> removed from INBOX, and added to both Archives and Sent
+        let c_local = envelope("local-c", "<c>", Flags::default());
+        let c_remote = envelope("3", "<c>", Flags::default());
+        let c_copied = envelope("8", "<c>", Flags::default());
+
+        let mut plans = vec![
+            folder_plan(
+                "INBOX",
+                envelopes(vec![a_cached, b_local.clone(), c_local.clone()]),
+                envelopes(vec![a_local.clone(), b_local.clone(), c_local.clone()]),
+                envelopes(vec![a_remote, b_remote, c_remote]),
+                envelopes(vec![]),
+            ),
+            folder_plan(
+                "Archives",
+                envelopes(vec![]),
+                envelopes(vec![]),
+                envelopes(vec![]),
+                envelopes(vec![a_moved.clone(), c_copied.clone()]),
+            ),
+            folder_plan(
+                "Sent",
+                envelopes(vec![]),
+                envelopes(vec![]),
+                envelopes(vec![]),
+                envelopes(vec![c_copied.clone()]),
+            ),
+        ];
+
+        assert_eq!(1, super::detect_moves(&mut plans));
+
+        let sorted = |patch: &Patch| {
+            let mut hunks: Vec<String> = patch.iter().flatten().map(|h| h.to_string()).collect();
+            hunks.sort();
+            hunks
+        };
+
+        // the remote cache of <a> is cleaned up along with the move
+        assert_eq!(
+            sorted(&vec![
+                vec![BackendHunk::RemoveEmail(
+                    "INBOX".into(),
+                    "local-b".into(),
+                    HunkKind::LocalCache
+                )],
+                vec![BackendHunk::RemoveEmail(
+                    "INBOX".into(),
+                    "local-b".into(),
+                    HunkKind::Local
+                )],
+                vec![BackendHunk::RemoveEmail(
+                    "INBOX".into(),
+                    "2".into(),
+                    HunkKind::RemoteCache
+                )],
+                vec![BackendHunk::RemoveEmail(
+                    "INBOX".into(),
+                    "local-c".into(),
+                    HunkKind::LocalCache
+                )],
+                vec![BackendHunk::RemoveEmail(
+                    "INBOX".into(),
+                    "local-c".into(),
+                    HunkKind::Local
+                )],
+                vec![BackendHunk::RemoveEmail(
+                    "INBOX".into(),
+                    "3".into(),
+                    HunkKind::RemoteCache
+                )],
+            ]),
+            sorted(&plans[0].patch),
+        );
+
+        // the move of <a> matches the local cache envelope, and
+        // fails along with the removal of its remote cache
+        let a_move = vec![
+            BackendHunk::MoveEmail(
+                "INBOX".into(),
+                "Archives".into(),
+                "cached-a".into(),
+                a_local,
+                a_moved,
+            ),
+            BackendHunk::RemoveEmail("INBOX".into(), "1".into(), HunkKind::RemoteCache),
+        ];
+        assert!(plans[1].patch.contains(&a_move));
+
+        // <c> reached two folders, it is removed then downloaded twice
+        assert_eq!(
+            sorted(&vec![
+                a_move,
+                vec![BackendHunk::CopyEmail(
+                    "Archives".into(),
+                    c_copied,
+                    HunkKindRestricted::Remote,
+                    HunkKindRestricted::Local,
+                    true,
+                )],
+            ]),
+            sorted(&plans[1].patch),
+        );
+        assert_eq!(1, plans[2].patch.len());
+    }
+
+    #[test]
+    fn watchdog() {
+        let watchdog = Watchdog::spawn("inbox", Duration::from_millis(10));
+        thread::sleep(Duration::from_millis(200));
+        assert!(watchdog.token.is_cancelled());
+
+        // a watchdog dropped before the timeout never cancels
+        let watchdog = Watchdog::spawn("inbox", Duration::from_millis(100));
+        let token = watchdog.token.clone();
+        drop(watchdog);
+        thread::sleep(Duration::from_millis(200));
+        assert!(!token.is_cancelled());
+    }
 
-                if local_cache.flags != flags {
-                    patch.push(vec![BackendHunk::SetFlags(
-                        folder.to_string(),
-                        Envelope {
-                            flags: flags.clone(),
-                            ..local_cache.clone()
-                        },
-                        HunkKind::LocalCache,
-                    )]);
-                }
+    #[test]
+    fn hunk_retry_policy_delay() {
+        assert_eq!(None, HunkRetryPolicy::NoRetry.delay(0));
 
-                if local.flags != flags {
-                    patch.push(vec![BackendHunk::SetFlags(
-                        folder.to_string(),
-                        Envelope {
-                            flags: flags.clone(),
-                            ..local.clone()
-                        },
-                        HunkKind::Local,
-                    )]);
-                }
+        let policy = HunkRetryPolicy::RetryN(2);
+        assert_eq!(Some(Duration::from_secs(1)), policy.delay(0));
+        assert_eq!(Some(Duration::from_secs(2)), policy.delay(1));
+        assert_eq!(None, policy.delay(2));
 
-                if remote_cache.flags != flags {
-                    patch.push(vec![BackendHunk::SetFlags(
-                        folder.to_string(),
-                        Envelope {
-                            flags: flags.clone(),
-                            ..remote_cache.clone()
-                        },
-                        HunkKind::RemoteCache,
-                    )]);
-                }
+        let policy = HunkRetryPolicy::RetryUntilSuccess { max_delay_secs: 5 };
+        assert_eq!(Some(Duration::from_secs(4)), policy.delay(2));
+        assert_eq!(Some(Duration::from_secs(5)), policy.delay(3));
+        assert_eq!(Some(Duration::from_secs(5)), policy.delay(100));
+    }
 
-                if remote.flags != flags {
-                    patch.push(vec![BackendHunk::SetFlags(
-                        folder.to_string(),
-                        Envelope {
-                            flags: flags.clone(),
-                            ..remote.clone()
-                        },
-                        HunkKind::Remote,
-                    )]);
-                }
-            }
-        }
+    #[test]
+    fn sort_patch_by_cost() {
+        let copy = |id: &str, size: Option<u64>| {
+            BackendHunk::CopyEmail(
+                "inbox".into(),
+                Envelope {
+                    id: id.into(),
+                    message_id: id.into(),
+                    size,
+                    ..Envelope::default()
+                },
+                HunkKindRestricted::Remote,
+                HunkKindRestricted::Local,
+                true,
+            )
+        };
+        let cache = |id: &str| {
+            BackendHunk::CacheEnvelope("inbox".into(), id.into(), HunkKindRestricted::Local)
+        };
+        let remove =
+            |id: &str| BackendHunk::RemoveEmail("inbox".into(), id.into(), HunkKind::Remote);
+
+        assert_eq!(
+            HunkCost {
+                network_bytes: 50_000,
+                iops: 4
+            },
+            copy("1", None).estimated_cost()
+        );
+        assert_eq!(
+            HunkCost {
+                network_bytes: 0,
+                iops: 2
+            },
+            cache("1").estimated_cost()
+        );
+
+        let mut patch: Patch = vec![
+            vec![copy("1", Some(1_000_000))],
+            vec![copy("2", None)],
+            vec![cache("3"), remove("3")],
+            vec![copy("4", Some(10))],
+            vec![cache("5")],
+        ];
+        super::sort_patch_by_cost(&mut patch, 3);
+
+        assert_eq!(
+            patch,
+            vec![
+                // first batch
+                vec![cache("3"), remove("3")],
+                vec![copy("2", None)],
+                vec![copy("1", Some(1_000_000))],
+                // second batch
+                vec![cache("5")],
+                vec![copy("4", Some(10))],
+            ]
+        );
     }
 
-    patch
-}
+    #[test]
+    fn sort_patch_by_date() {
+        let copy = |id: &str, day: u32, source, target| {
+            BackendHunk::CopyEmail(
+                "inbox".into(),
+                Envelope {
+                    id: id.into(),
+                    message_id: id.into(),
+                    date: Local.with_ymd_and_hms(2023, 1, day, 0, 0, 0).unwrap(),
+                    ..Envelope::default()
+                },
+                source,
+                target,
+                true,
+            )
+        };
+        let download = |id: &str, day: u32| {
+            copy(
+                id,
+                day,
+                HunkKindRestricted::Remote,
+                HunkKindRestricted::Local,
+            )
+        };
+        let upload = |id: &str, day: u32| {
+            copy(
+                id,
+                day,
+                HunkKindRestricted::Local,
+                HunkKindRestricted::Remote,
+            )
+        };
+        let cache = |id: &str| {
+            BackendHunk::CacheEnvelope("inbox".into(), id.into(), HunkKindRestricted::Local)
+        };
 
-#[cfg(test)]
-mod envelopes_sync {
-    use crate::{Envelope, Flag, Flags};
+        let mut patch: Patch = vec![
+            vec![download("1", 1), cache("1")],
+            vec![upload("2", 31)],
+            vec![download("3", 3), cache("3")],
+            vec![download("4", 2)],
+        ];
+        super::sort_patch_by_date(&mut patch);
 
-    use super::{BackendHunk, Envelopes, HunkKind, HunkKindRestricted, Patch};
+        assert_eq!(
+            patch,
+            vec![
+                vec![upload("2", 31)],
+                vec![download("3", 3), cache("3")],
+                vec![download("4", 2)],
+                vec![download("1", 1), cache("1")],
+            ]
+        );
+    }
 
     #[test]
     fn build_patch_0000() {
@@ -1086,11 +3392,8 @@ mod envelopes_sync {
                 )],
                 vec![BackendHunk::SetFlags(
                     "inbox".into(),
-                    Envelope {
-                        internal_id: "remote-cache-id".into(),
-                        flags: Flags::from_iter([Flag::Seen, Flag::Flagged, Flag::Deleted]),
-                        ..Envelope::default()
-                    },
+                    "remote-cache-id".into(),
+                    Flags::from_iter([Flag::Seen, Flag::Flagged, Flag::Deleted]),
                     HunkKind::RemoteCache,
                 )]
             ]
@@ -1129,8 +3432,22 @@ mod envelopes_sync {
 
     #[test]
     fn build_patch_0101() {
-        let local_cache = Envelopes::default();
+        // an unchanged email keeps the caches populated, so that the
+        // folder is not considered as adopted
+        let unchanged = || {
+            (
+                "message_id-6".to_owned(),
+                Envelope {
+                    internal_id: "id-6".into(),
+                    flags: "seen".into(),
+                    ..Envelope::default()
+                },
+            )
+        };
+
+        let local_cache = Envelopes::from_iter([unchanged()]);
         let local = Envelopes::from_iter([
+            unchanged(),
             (
                 "message_id-1".into(),
                 Envelope {
@@ -1175,8 +3492,9 @@ mod envelopes_sync {
                 },
             ),
         ]);
-        let remote_cache = Envelopes::default();
+        let remote_cache = Envelopes::from_iter([unchanged()]);
         let remote = Envelopes::from_iter([
+            unchanged(),
             (
                 "message_id-1".into(),
                 Envelope {
@@ -1314,6 +3632,83 @@ mod envelopes_sync {
         )));
     }
 
+    #[test]
+    fn build_patch_adopt_existing_folder() {
+        let envelope = |internal_id: &str, flags: &str| Envelope {
+            internal_id: internal_id.into(),
+            flags: flags.into(),
+            ..Envelope::default()
+        };
+
+        let local = Envelopes::from_iter([
+            ("message_id-1".into(), envelope("local-id-1", "seen")),
+            (
+                "message_id-2".into(),
+                envelope("local-id-2", "seen flagged"),
+            ),
+            ("message_id-3".into(), envelope("local-id-3", "seen")),
+        ]);
+        let remote = Envelopes::from_iter([
+            ("message_id-1".into(), envelope("remote-id-1", "seen")),
+            ("message_id-2".into(), envelope("remote-id-2", "seen")),
+            ("message_id-4".into(), envelope("remote-id-4", "seen")),
+        ]);
+
+        let mut patch = super::build_patch(
+            "inbox",
+            Envelopes::default(),
+            local,
+            Envelopes::default(),
+            remote,
+        );
+        patch.sort_by_key(|hunks| format!("{hunks:?}"));
+
+        // matched emails are only cached, unmatched ones are copied
+        assert_eq!(
+            patch,
+            vec![
+                vec![
+                    BackendHunk::CacheEnvelope(
+                        "inbox".into(),
+                        "local-id-1".into(),
+                        HunkKindRestricted::Local,
+                    ),
+                    BackendHunk::CacheEnvelope(
+                        "inbox".into(),
+                        "remote-id-1".into(),
+                        HunkKindRestricted::Remote,
+                    ),
+                ],
+                vec![
+                    BackendHunk::CacheEnvelope(
+                        "inbox".into(),
+                        "local-id-2".into(),
+                        HunkKindRestricted::Local,
+                    ),
+                    BackendHunk::CacheEnvelope(
+                        "inbox".into(),
+                        "remote-id-2".into(),
+                        HunkKindRestricted::Remote,
+                    ),
+                ],
+                vec![BackendHunk::CopyEmail(
+                    "inbox".into(),
+                    envelope("local-id-3", "seen"),
+                    HunkKindRestricted::Local,
+                    HunkKindRestricted::Remote,
+                    true,
+                )],
+                vec![BackendHunk::CopyEmail(
+                    "inbox".into(),
+                    envelope("remote-id-4", "seen"),
+                    HunkKindRestricted::Remote,
+                    HunkKindRestricted::Local,
+                    true,
+                )],
+            ]
+        );
+    }
+
     #[test]
     fn build_patch_0110() {
         let local_cache = Envelopes::default();
@@ -1622,11 +4017,8 @@ mod envelopes_sync {
                 )],
                 vec![BackendHunk::SetFlags(
                     "inbox".into(),
-                    Envelope {
-                        internal_id: "local-cache-id".into(),
-                        flags: Flags::from_iter([Flag::Flagged]),
-                        ..Envelope::default()
-                    },
+                    "local-cache-id".into(),
+                    Flags::from_iter([Flag::Flagged]),
                     HunkKind::LocalCache,
                 )]
             ]
@@ -1720,4 +4112,380 @@ mod envelopes_sync {
             ]
         );
     }
+
+    #[test]
+    fn log_patch() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+
+        let patch: Patch = vec![
+            vec![BackendHunk::CopyEmail(
+                "inbox".into(),
+                Envelope {
+                    id: "1".into(),
+                    message_id: "<1@localhost>".into(),
+                    flags: Flags::from_iter([Flag::Seen]),
+                    date: Local.timestamp_opt(1_700_000_000, 0).unwrap(),
+                    ..Envelope::default()
+                },
+                HunkKindRestricted::Remote,
+                HunkKindRestricted::Local,
+                false,
+            )],
+            vec![BackendHunk::RemoveEmail(
+                "inbox".into(),
+                "2".into(),
+                HunkKind::LocalCache,
+            )],
+        ];
+
+        super::log_patch(&path, "account", "inbox", &patch).unwrap();
+        super::log_patch(&path, "account", "sent", &vec![]).unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let entries: Vec<PatchLogEntry> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(2, entries.len());
+        assert_eq!("account", entries[0].account);
+        assert_eq!("inbox", entries[0].folder);
+        assert_eq!(patch, entries[0].patch);
+        match &entries[0].patch[0][0] {
+            BackendHunk::CopyEmail(_, envelope, _, _, _) => {
+                assert_eq!(Flags::from_iter([Flag::Seen]), envelope.flags);
+                assert_eq!(1_700_000_000, envelope.date.timestamp());
+            }
+            hunk => panic!("unexpected hunk {hunk:?}"),
+        }
+        assert_eq!("sent", entries[1].folder);
+        assert!(entries[1].patch.is_empty());
+    }
+
+    #[test]
+    fn build_patch_ignores_size() {
+        let envelope = |internal_id: &str, size| Envelope {
+            internal_id: internal_id.into(),
+            flags: "seen".into(),
+            size,
+            ..Envelope::default()
+        };
+
+        // sizes may differ between backends, or be missing from the
+        // cache of previous versions
+        assert_eq!(
+            super::build_patch(
+                "inbox",
+                Envelopes::from_iter([("id".into(), envelope("local-cache-id", None))]),
+                Envelopes::from_iter([("id".into(), envelope("local-id", Some(42)))]),
+                Envelopes::from_iter([("id".into(), envelope("remote-cache-id", Some(40)))]),
+                Envelopes::from_iter([("id".into(), envelope("remote-id", Some(41)))]),
+            ),
+            Patch::default()
+        );
+    }
+
+    #[test]
+    fn build_patch_with_stable_deleted() {
+        let envelope = |internal_id: &str, flags: Flags| Envelope {
+            internal_id: internal_id.into(),
+            flags,
+            ..Envelope::default()
+        };
+        let deleted = || Flags::from_iter([Flag::Seen, Flag::Deleted]);
+
+        // deleted remote side, then propagated to the local side by a
+        // previous synchronization
+        let build_patch = |sync_deleted| {
+            super::build_patch_with(
+                "inbox",
+                Envelopes::from_iter([("id".into(), envelope("local-cache-id", deleted()))]),
+                Envelopes::from_iter([("id".into(), envelope("local-id", deleted()))]),
+                Envelopes::from_iter([("id".into(), envelope("remote-cache-id", deleted()))]),
+                Envelopes::from_iter([("id".into(), envelope("remote-id", deleted()))]),
+                sync_deleted,
+                ConflictResolution::default(),
+            )
+        };
+
+        assert_eq!(
+            build_patch(SyncDeletedBehavior::PropagateFlag),
+            Patch::default()
+        );
+
+        assert_eq!(
+            build_patch(SyncDeletedBehavior::Expunge),
+            vec![vec![
+                BackendHunk::RemoveEmail("inbox".into(), "remote-id".into(), HunkKind::Remote),
+                BackendHunk::RemoveEmail("inbox".into(), "local-id".into(), HunkKind::Local),
+                BackendHunk::RemoveEmail(
+                    "inbox".into(),
+                    "local-cache-id".into(),
+                    HunkKind::LocalCache,
+                ),
+                BackendHunk::RemoveEmail(
+                    "inbox".into(),
+                    "remote-cache-id".into(),
+                    HunkKind::RemoteCache,
+                ),
+            ]],
+        );
+
+        assert_eq!(
+            build_patch(SyncDeletedBehavior::MoveToTrash),
+            vec![vec![
+                BackendHunk::MoveEmailToTrash(
+                    "inbox".into(),
+                    envelope("remote-id", deleted()),
+                    HunkKindRestricted::Remote,
+                ),
+                BackendHunk::RemoveEmail("inbox".into(), "local-id".into(), HunkKind::Local),
+                BackendHunk::RemoveEmail(
+                    "inbox".into(),
+                    "local-cache-id".into(),
+                    HunkKind::LocalCache,
+                ),
+                BackendHunk::RemoveEmail(
+                    "inbox".into(),
+                    "remote-cache-id".into(),
+                    HunkKind::RemoteCache,
+                ),
+            ]],
+        );
+    }
+
+    #[test]
+    fn build_patch_with_undeleted() {
+        let envelope = |internal_id: &str, flags: Flags| Envelope {
+            internal_id: internal_id.into(),
+            flags,
+            ..Envelope::default()
+        };
+        let deleted = || Flags::from_iter([Flag::Seen, Flag::Deleted]);
+        let seen = || Flags::from_iter([Flag::Seen]);
+
+        // deleted remote side, then undeleted local side before the
+        // deletion got removed
+        let build_patch = |conflict_resolution| {
+            super::build_patch_with(
+                "inbox",
+                Envelopes::from_iter([("id".into(), envelope("local-cache-id", deleted()))]),
+                Envelopes::from_iter([("id".into(), envelope("local-id", seen()))]),
+                Envelopes::from_iter([("id".into(), envelope("remote-cache-id", deleted()))]),
+                Envelopes::from_iter([("id".into(), envelope("remote-id", deleted()))]),
+                SyncDeletedBehavior::Expunge,
+                conflict_resolution,
+            )
+        };
+
+        for conflict_resolution in [
+            ConflictResolution::KeepFlag,
+            ConflictResolution::PreferLocal,
+        ] {
+            let patch = build_patch(conflict_resolution);
+            assert!(
+                patch.iter().flatten().all(|hunk| !matches!(
+                    hunk,
+                    BackendHunk::RemoveEmail(..) | BackendHunk::MoveEmailToTrash(..)
+                )),
+                "{conflict_resolution:?}: {patch:?}"
+            );
+        }
+
+        let patch = build_patch(ConflictResolution::PreferRemote);
+        assert!(patch.iter().flatten().any(|hunk| matches!(
+            hunk,
+            BackendHunk::RemoveEmail(_, id, HunkKind::Local) if id == "local-id"
+        )));
+    }
+
+    #[test]
+    fn hunk_display() {
+        let envelope = Envelope {
+            id: "1".into(),
+            subject: "A very long subject that does not fit in a progress bar".into(),
+            ..Envelope::default()
+        };
+
+        let hunk = BackendHunk::CopyEmail(
+            "INBOX".into(),
+            envelope.clone(),
+            HunkKindRestricted::Remote,
+            HunkKindRestricted::Local,
+            false,
+        );
+        assert_eq!(
+            "Copying email \"A very long subject that does not fit i…\" from remote backend INBOX to local backend INBOX",
+            hunk.to_string(),
+        );
+        assert_eq!("Copying email \"A very…", hunk.short_description(22));
+
+        let hunk = BackendHunk::MoveEmailToTrash(
+            "INBOX".into(),
+            Envelope {
+                subject: String::new(),
+                ..envelope
+            },
+            HunkKindRestricted::Remote,
+        );
+        assert_eq!(
+            "Moving email 1 from remote backend INBOX to trash",
+            hunk.to_string()
+        );
+    }
+
+    #[cfg(feature = "memory-backend")]
+    #[test]
+    fn list_changed_remote_envelopes() {
+        use std::{borrow::Cow, collections::HashMap};
+
+        use crate::{AccountConfig, Backend, MemoryBackend};
+
+        use super::{Cache, SyncBuilder};
+
+        let account_config = AccountConfig {
+            name: "account".into(),
+            ..AccountConfig::default()
+        };
+        let remote = MemoryBackend::new(Cow::Borrowed(&account_config));
+        remote.enable_modseq().unwrap();
+        let email = |id: &str| format!("Message-ID: <{id}@localhost>\r\nSubject: {id}\r\n\r\n");
+        remote
+            .add_email("INBOX", email("a").as_bytes(), &Flags::default())
+            .unwrap();
+        remote
+            .add_email("INBOX", email("b").as_bytes(), &Flags::default())
+            .unwrap();
+
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        Cache::init(&mut conn).unwrap();
+        let builder = SyncBuilder::new(&account_config);
+        let cached: Envelopes = HashMap::from_iter(
+            remote
+                .list_envelopes("INBOX", 0, 0)
+                .unwrap()
+                .iter()
+                .map(|envelope| (envelope.message_id.clone(), envelope.clone())),
+        );
+        let list_changed = |conn: &rusqlite::Connection| {
+            let modseq = remote.highest_modseq("INBOX").unwrap();
+            builder
+                .list_changed_remote_envelopes(conn, &remote, "account", "INBOX", modseq, &cached)
+        };
+
+        // without modseq saved by a previous synchronization, all the
+        // envelopes need to be listed
+
+        assert!(list_changed(&conn).is_none());
+
+        let (modseq, _) = remote.highest_modseq("INBOX").unwrap().unwrap();
+        let tx = conn.transaction().unwrap();
+        Cache::set_modseq(&tx, "account", "INBOX", modseq).unwrap();
+        tx.commit().unwrap();
+
+        // changed and new envelopes are merged into the cached ones
+
+        remote
+            .add_flags("INBOX", vec!["1"], &Flags::from_iter([Flag::Seen]))
+            .unwrap();
+        remote
+            .add_email("INBOX", email("c").as_bytes(), &Flags::default())
+            .unwrap();
+
+        let envelopes = list_changed(&conn).unwrap();
+        assert_eq!(3, envelopes.len());
+        let envelope = |message_id: &str| {
+            envelopes
+                .iter()
+                .find(|envelope| envelope.message_id == message_id)
+                .unwrap()
+        };
+        assert!(envelope("<a@localhost>").flags.contains(&Flag::Seen));
+        assert!(!envelope("<b@localhost>").flags.contains(&Flag::Seen));
+        assert!(!envelope("<c@localhost>").flags.contains(&Flag::Seen));
+
+        // expunged emails are not reported as changed, the merged
+        // envelopes do not add up to the folder size anymore and all
+        // the envelopes need to be listed
+
+        remote.delete_emails("INBOX", vec!["2"]).unwrap();
+        assert!(list_changed(&conn).is_none());
+    }
+
+    #[cfg(feature = "memory-backend")]
+    #[test]
+    fn filter_read_only_hunks() {
+        use std::borrow::Cow;
+
+        use crate::{AccountConfig, MemoryBackend};
+
+        use super::{ReadOnlyStrategy, SyncBuilder};
+
+        let account_config = AccountConfig::default();
+        let local = MemoryBackend::new(Cow::Borrowed(&account_config));
+        let remote = MemoryBackend::new(Cow::Borrowed(&account_config));
+        remote.set_read_only("INBOX", true).unwrap();
+
+        let flags = || Flags::from_iter([Flag::Flagged]);
+        let set_flags = |kind| BackendHunk::SetFlags("INBOX".into(), "id".into(), flags(), kind);
+        let patch = || -> Patch {
+            vec![
+                vec![
+                    set_flags(HunkKind::Remote),
+                    set_flags(HunkKind::RemoteCache),
+                ],
+                vec![set_flags(HunkKind::Local), set_flags(HunkKind::LocalCache)],
+            ]
+        };
+        let filter = |strategy| {
+            let mut patch = patch();
+            let read_only = SyncBuilder::new(&account_config)
+                .read_only_strategy(strategy)
+                .filter_read_only_hunks("INBOX", &local, &remote, &mut patch);
+            (patch, read_only)
+        };
+
+        // skipped changes are not recorded by the cache of the
+        // read-only side
+
+        assert_eq!(
+            filter(ReadOnlyStrategy::Skip),
+            (
+                vec![vec![
+                    set_flags(HunkKind::Local),
+                    set_flags(HunkKind::LocalCache)
+                ]],
+                vec![(
+                    HunkKindRestricted::Remote,
+                    vec![set_flags(HunkKind::Remote)]
+                )],
+            )
+        );
+
+        // cached changes are still reported, but recorded by the
+        // cache of the read-only side
+
+        assert_eq!(
+            filter(ReadOnlyStrategy::CacheOnly),
+            (
+                vec![
+                    vec![
+                        set_flags(HunkKind::RemoteCache),
+                        set_flags(HunkKind::RemoteCache)
+                    ],
+                    vec![set_flags(HunkKind::Local), set_flags(HunkKind::LocalCache)],
+                ],
+                vec![(
+                    HunkKindRestricted::Remote,
+                    vec![set_flags(HunkKind::Remote)]
+                )],
+            )
+        );
+
+        // nothing is filtered when both sides are writable
+
+        remote.set_read_only("INBOX", false).unwrap();
+        assert_eq!(filter(ReadOnlyStrategy::Skip), (patch(), Vec::new()));
+    }
 }
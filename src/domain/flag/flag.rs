@@ -1,7 +1,21 @@
-use serde::Serialize;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::Flags;
+
+/// Represents the names the standard flags used to be serialized
+/// with, still found in the synchronization journals written by
+/// previous versions.
+const LEGACY_FLAGS: [(&str, Flag); 6] = [
+    ("Seen", Flag::Seen),
+    ("Answered", Flag::Answered),
+    ("Flagged", Flag::Flagged),
+    ("Deleted", Flag::Deleted),
+    ("Draft", Flag::Draft),
+    ("Recent", Flag::Recent),
+];
 
 /// Represents the flag variants.
-#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub enum Flag {
     Seen,
     Answered,
@@ -16,6 +30,34 @@ impl Flag {
     pub fn custom<F: ToString>(flag: F) -> Self {
         Self::Custom(flag.to_string())
     }
+
+    /// Serializes the flag as a token of the canonical flags
+    /// serialization (see [`crate::Flags::to_string`]).
+    ///
+    /// Custom flags are double quoted when they could not be parsed
+    /// back as is: when they are empty, when they contain whitespaces,
+    /// double quotes or backslashes, or when they match a standard
+    /// flag name. Double quotes and backslashes are then escaped with
+    /// a backslash.
+    pub fn to_token(&self) -> String {
+        match self {
+            Flag::Custom(flag) => {
+                let is_plain = !flag.is_empty()
+                    && !flag
+                        .chars()
+                        .any(|c| c.is_whitespace() || c == '"' || c == '\\')
+                    && matches!(Flag::from(flag.as_str()), Flag::Custom(_));
+
+                if is_plain {
+                    flag.clone()
+                } else {
+                    let flag = flag.replace('\\', "\\\\").replace('"', "\\\"");
+                    format!("\"{flag}\"")
+                }
+            }
+            flag => flag.to_string(),
+        }
+    }
 }
 
 impl From<&str> for Flag {
@@ -51,3 +93,55 @@ impl ToString for Flag {
         }
     }
 }
+
+/// Serializes the flag as a string, its token (see
+/// [`Flag::to_token`]). Custom flags named like the legacy names of
+/// the standard flags are double quoted, so that they are not
+/// mistaken for them.
+impl Serialize for Flag {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Flag::Custom(flag) if LEGACY_FLAGS.iter().any(|(name, _)| *name == flag.as_str()) => {
+                s.serialize_str(&format!("\"{flag}\""))
+            }
+            flag => s.serialize_str(&flag.to_token()),
+        }
+    }
+}
+
+/// Deserializes the flag from its token, or from the enum
+/// representation used by previous versions.
+impl<'de> Deserialize<'de> for Flag {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Token(String),
+            LegacyCustom {
+                #[serde(rename = "Custom")]
+                flag: String,
+            },
+        }
+
+        let token = match Repr::deserialize(d)? {
+            Repr::LegacyCustom { flag } => return Ok(Flag::Custom(flag)),
+            Repr::Token(token) => token,
+        };
+
+        if let Some((_, flag)) = LEGACY_FLAGS.iter().find(|(name, _)| *name == token) {
+            return Ok(flag.clone());
+        }
+
+        let mut flags = token
+            .parse::<Flags>()
+            .map_err(de::Error::custom)?
+            .0
+            .into_iter();
+        match (flags.next(), flags.next()) {
+            (Some(flag), None) => Ok(flag),
+            _ => Err(de::Error::custom(format!(
+                "cannot parse flag {token}: expected exactly one flag"
+            ))),
+        }
+    }
+}
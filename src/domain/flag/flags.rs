@@ -1,10 +1,23 @@
-use serde::Serialize;
-use std::{collections::HashSet, ops};
+use serde::{Deserialize, Serialize, Serializer};
+use std::{collections::HashSet, ops, result, str::FromStr};
+use thiserror::Error;
 
 use crate::Flag;
 
+#[derive(Debug, Error, Clone, Eq, PartialEq)]
+pub enum Error {
+    #[error("cannot parse flags {0}: unterminated quoted flag")]
+    ParseUnterminatedQuoteError(String),
+    #[error("cannot parse flags {0}: invalid escape sequence")]
+    ParseInvalidEscapeError(String),
+    #[error("cannot parse flags {0}: unexpected character {1}")]
+    ParseUnexpectedCharError(String, char),
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
 /// Represents the list of flags.
-#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize)]
 pub struct Flags(pub HashSet<Flag>);
 
 impl Flags {
@@ -19,6 +32,43 @@ impl Flags {
         )
     }
 
+    /// Clones the flags, keeping only the custom ones. This is the
+    /// complement of [`Flags::clone_without_customs`].
+    pub fn clone_only_customs(&self) -> Self {
+        Self::from_iter(
+            self.iter()
+                .filter(|f| matches!(f, Flag::Custom(_)))
+                .cloned(),
+        )
+    }
+
+    /// Returns the flags contained in `self` or in `other`.
+    pub fn union(&self, other: &Flags) -> Flags {
+        self.0.union(&other.0).cloned().collect()
+    }
+
+    /// Returns the flags contained in both `self` and `other`.
+    pub fn intersection(&self, other: &Flags) -> Flags {
+        self.0.intersection(&other.0).cloned().collect()
+    }
+
+    /// Returns the flags contained in `self` but not in `other`.
+    pub fn difference(&self, other: &Flags) -> Flags {
+        self.0.difference(&other.0).cloned().collect()
+    }
+
+    /// Returns `true` if all the flags of `self` are contained in
+    /// `other`.
+    pub fn is_subset_of(&self, other: &Flags) -> bool {
+        self.0.is_subset(&other.0)
+    }
+
+    /// Returns `true` if all the flags of `other` are contained in
+    /// `self`.
+    pub fn is_superset_of(&self, other: &Flags) -> bool {
+        self.0.is_superset(&other.0)
+    }
+
     /// Builds a symbols string.
     pub fn to_symbols_string(&self) -> String {
         let mut flags = String::new();
@@ -41,18 +91,24 @@ impl Flags {
     }
 }
 
+/// Builds the canonical serialization of the flags: the tokens of
+/// the flags (see [`Flag::to_token`]), sorted and separated by a
+/// space. It can be parsed back with [`Flags::from_str`].
 impl ToString for Flags {
     fn to_string(&self) -> String {
-        let mut flags = String::default();
-        let mut glue = "";
-
-        for flag in &self.0 {
-            flags.push_str(glue);
-            flags.push_str(&flag.to_string());
-            glue = " ";
-        }
+        let mut tokens: Vec<String> = self.iter().map(Flag::to_token).collect();
+        tokens.sort();
+        tokens.join(" ")
+    }
+}
 
-        flags
+/// Serializes the flags as a list of strings, sorted like their
+/// canonical serialization (see [`Flags::to_string`]).
+impl Serialize for Flags {
+    fn serialize<S: Serializer>(&self, s: S) -> result::Result<S::Ok, S::Error> {
+        let mut flags: Vec<&Flag> = self.iter().collect();
+        flags.sort_by_key(|flag| flag.to_token());
+        s.collect_seq(flags)
     }
 }
 
@@ -70,14 +126,94 @@ impl ops::DerefMut for Flags {
     }
 }
 
+impl ops::BitOr<&Flags> for &Flags {
+    type Output = Flags;
+
+    fn bitor(self, rhs: &Flags) -> Self::Output {
+        self.union(rhs)
+    }
+}
+
+impl ops::BitAnd<&Flags> for &Flags {
+    type Output = Flags;
+
+    fn bitand(self, rhs: &Flags) -> Self::Output {
+        self.intersection(rhs)
+    }
+}
+
+impl ops::Sub<&Flags> for &Flags {
+    type Output = Flags;
+
+    fn sub(self, rhs: &Flags) -> Self::Output {
+        self.difference(rhs)
+    }
+}
+
+/// Parses flags strictly from their canonical serialization (see
+/// [`Flags::to_string`]).
+impl FromStr for Flags {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut flags = Flags::default();
+        let mut chars = s.chars().peekable();
+
+        loop {
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+
+            match chars.next() {
+                None => break,
+                Some('"') => {
+                    let mut flag = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('"') => break,
+                            Some('\\') => match chars.next() {
+                                Some(c @ ('"' | '\\')) => flag.push(c),
+                                _ => return Err(Error::ParseInvalidEscapeError(s.to_owned())),
+                            },
+                            Some(c) => flag.push(c),
+                            None => return Err(Error::ParseUnterminatedQuoteError(s.to_owned())),
+                        }
+                    }
+                    if let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                        return Err(Error::ParseUnexpectedCharError(s.to_owned(), c));
+                    }
+                    flags.insert(Flag::Custom(flag));
+                }
+                Some(c) => {
+                    let mut token = String::new();
+                    let mut next = Some(c);
+                    while let Some(c) = next {
+                        if c == '"' || c == '\\' {
+                            return Err(Error::ParseUnexpectedCharError(s.to_owned(), c));
+                        }
+                        token.push(c);
+                        next = chars.next_if(|c| !c.is_whitespace());
+                    }
+                    flags.insert(token.as_str().into());
+                }
+            }
+        }
+
+        Ok(flags)
+    }
+}
+
+/// Parses flags leniently: the canonical serialization is tried
+/// first (see [`Flags::from_str`]), then the string is split on
+/// whitespaces.
 impl From<&str> for Flags {
     fn from(flags: &str) -> Self {
-        Flags(
-            flags
-                .split_whitespace()
-                .map(|flag| flag.trim().into())
-                .collect(),
-        )
+        flags.parse().unwrap_or_else(|_| {
+            Flags(
+                flags
+                    .split_whitespace()
+                    .map(|flag| flag.trim().into())
+                    .collect(),
+            )
+        })
     }
 }
 
@@ -88,3 +224,123 @@ impl FromIterator<Flag> for Flags {
         flags
     }
 }
+
+#[cfg(test)]
+mod flags {
+    use proptest::prelude::*;
+    use std::str::FromStr;
+
+    use crate::{Flag, Flags};
+
+    fn flag() -> impl Strategy<Value = Flag> {
+        prop_oneof![
+            Just(Flag::Seen),
+            Just(Flag::Answered),
+            Just(Flag::Flagged),
+            Just(Flag::Deleted),
+            Just(Flag::Draft),
+            Just(Flag::Recent),
+            Just(Flag::custom("seen")),
+            Just(Flag::custom("")),
+            "[a-z ,\"\\\\]{0,8}".prop_map(Flag::Custom),
+            any::<String>().prop_map(Flag::Custom),
+        ]
+    }
+
+    #[test]
+    fn set_operations() {
+        let a = Flags::from_iter([Flag::Seen, Flag::Flagged, Flag::custom("work")]);
+        let b = Flags::from_iter([Flag::Seen, Flag::custom("work"), Flag::custom("todo")]);
+        let empty = Flags::default();
+
+        let union = Flags::from_iter([
+            Flag::Seen,
+            Flag::Flagged,
+            Flag::custom("work"),
+            Flag::custom("todo"),
+        ]);
+        assert_eq!(union, a.union(&b));
+        assert_eq!(union, &a | &b);
+        assert_eq!(a, a.union(&empty));
+
+        let intersection = Flags::from_iter([Flag::Seen, Flag::custom("work")]);
+        assert_eq!(intersection, a.intersection(&b));
+        assert_eq!(intersection, &a & &b);
+        assert_eq!(empty, a.intersection(&empty));
+
+        assert_eq!(Flags::from_iter([Flag::Flagged]), a.difference(&b));
+        assert_eq!(Flags::from_iter([Flag::custom("todo")]), &b - &a);
+        assert_eq!(a, &a - &empty);
+        assert_eq!(empty, &empty - &a);
+
+        // custom flags named like standard ones are distinct
+        let custom_seen = Flags::from_iter([Flag::custom("seen")]);
+        assert_eq!(empty, a.intersection(&custom_seen));
+
+        assert!(intersection.is_subset_of(&a));
+        assert!(intersection.is_subset_of(&b));
+        assert!(!a.is_subset_of(&b));
+        assert!(empty.is_subset_of(&a));
+        assert!(union.is_superset_of(&a));
+        assert!(union.is_superset_of(&b));
+        assert!(!b.is_superset_of(&a));
+        assert!(a.is_superset_of(&a));
+    }
+
+    #[test]
+    fn parse_canonical() {
+        assert_eq!(
+            Flags::from_iter([
+                Flag::Seen,
+                Flag::Answered,
+                Flag::custom("my flag"),
+                Flag::custom("seen"),
+                Flag::custom("a\"b\\c"),
+            ]),
+            Flags::from_str(r#"seen replied "my flag" "seen" "a\"b\\c""#).unwrap()
+        );
+        assert!(Flags::from_str("\"unterminated").is_err());
+        assert!(Flags::from_str("\"a\"b").is_err());
+        assert!(Flags::from_str("a\"b").is_err());
+        assert!(Flags::from_str(r#""\n""#).is_err());
+        assert_eq!(
+            Flags::from_iter([Flag::custom("a\"b")]),
+            Flags::from("a\"b")
+        );
+    }
+
+    #[test]
+    fn serde() {
+        let flags = Flags::from_iter([Flag::Seen, Flag::custom("work"), Flag::custom("Seen")]);
+        let json = serde_json::to_string(&flags).unwrap();
+        assert_eq!(r#"["\"Seen\"","seen","work"]"#, json);
+        assert_eq!(flags, serde_json::from_str(&json).unwrap());
+
+        // flags serialized by previous versions can still be read
+        let legacy = r#"["Seen",{"Custom":"work"},{"Custom":"Seen"}]"#;
+        assert_eq!(flags, serde_json::from_str(legacy).unwrap());
+    }
+
+    proptest! {
+        #[test]
+        fn serde_round_trip(flags in prop::collection::vec(flag(), 0..8)) {
+            let flags = Flags::from_iter(flags);
+            let json = serde_json::to_string(&flags).unwrap();
+            prop_assert_eq!(&flags, &serde_json::from_str::<Flags>(&json).unwrap());
+        }
+
+        #[test]
+        fn round_trip(flags in prop::collection::vec(flag(), 0..8)) {
+            let flags = Flags::from_iter(flags);
+            prop_assert_eq!(&flags, &Flags::from_str(&flags.to_string()).unwrap());
+        }
+
+        #[test]
+        fn canonical_round_trip(s in any::<String>()) {
+            if let Ok(flags) = Flags::from_str(&s) {
+                let canonical = flags.to_string();
+                prop_assert_eq!(&canonical, &Flags::from_str(&canonical).unwrap().to_string());
+            }
+        }
+    }
+}
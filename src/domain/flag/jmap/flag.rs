@@ -0,0 +1,28 @@
+use crate::Flag;
+
+/// Builds a flag from the given JMAP keyword. Keywords are case
+/// insensitive, unknown keywords become custom flags.
+pub fn from_keyword(keyword: &str) -> Flag {
+    match keyword.to_lowercase().as_str() {
+        "$seen" => Flag::Seen,
+        "$answered" => Flag::Answered,
+        "$flagged" => Flag::Flagged,
+        "$draft" => Flag::Draft,
+        _ => Flag::Custom(keyword.to_owned()),
+    }
+}
+
+/// Returns the JMAP keyword of the given flag. JMAP has no keyword
+/// for deleted and recent emails: the JMAP backend moves emails
+/// flagged as deleted to the trash instead, and the recent flag is
+/// ignored.
+pub fn to_keyword(flag: &Flag) -> Option<String> {
+    match flag {
+        Flag::Seen => Some(String::from("$seen")),
+        Flag::Answered => Some(String::from("$answered")),
+        Flag::Flagged => Some(String::from("$flagged")),
+        Flag::Draft => Some(String::from("$draft")),
+        Flag::Deleted | Flag::Recent => None,
+        Flag::Custom(keyword) => Some(keyword.clone()),
+    }
+}
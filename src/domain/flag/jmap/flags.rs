@@ -0,0 +1,25 @@
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+use crate::Flags;
+
+use super::flag;
+
+/// Builds flags from the keywords of a JMAP email. Only keywords set
+/// to `true` are kept.
+pub fn from_keywords(keywords: &HashMap<String, bool>) -> Flags {
+    keywords
+        .iter()
+        .filter(|(_, set)| **set)
+        .map(|(keyword, _)| flag::from_keyword(keyword))
+        .collect()
+}
+
+/// Builds the keywords object of a JMAP email from the given flags.
+pub fn to_keywords(flags: &Flags) -> Map<String, Value> {
+    flags
+        .iter()
+        .filter_map(flag::to_keyword)
+        .map(|keyword| (keyword, Value::Bool(true)))
+        .collect()
+}
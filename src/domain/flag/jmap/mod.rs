@@ -0,0 +1,5 @@
+pub mod flag;
+pub mod flags;
+
+pub use flag::*;
+pub use flags::*;
@@ -2,10 +2,12 @@ pub mod flag;
 pub mod flags;
 #[cfg(feature = "imap-backend")]
 pub mod imap;
+#[cfg(feature = "jmap-backend")]
+pub mod jmap;
 #[cfg(feature = "maildir-backend")]
 pub mod maildir;
 pub mod sync;
 
 pub use self::flag::*;
 pub use self::flags::*;
-pub use self::sync::sync_all;
+pub use self::sync::{sync_all, sync_all_with};
@@ -1,6 +1,9 @@
 use std::collections::HashSet;
 
-use crate::{Envelope, Flag, Flags};
+use crate::{
+    envelope::sync::{ConflictResolution, SyncDeletedBehavior},
+    Envelope, Flag, Flags,
+};
 
 pub fn sync_all(
     local_cache: Option<&Envelope>,
@@ -8,20 +11,59 @@ pub fn sync_all(
     remote_cache: Option<&Envelope>,
     remote: Option<&Envelope>,
 ) -> Flags {
+    sync_all_with(
+        local_cache,
+        local,
+        remote_cache,
+        remote,
+        SyncDeletedBehavior::default(),
+        ConflictResolution::default(),
+    )
+}
+
+/// Synchronizes the flags like [`sync_all`], resolving the conflicts
+/// according to the given strategy, except the conflicts on
+/// [`Flag::Deleted`] which are resolved according to the given
+/// behavior (see [`SyncDeletedBehavior::keeps_conflicting_flag`]).
+pub fn sync_all_with(
+    local_cache: Option<&Envelope>,
+    local: Option<&Envelope>,
+    remote_cache: Option<&Envelope>,
+    remote: Option<&Envelope>,
+    sync_deleted: SyncDeletedBehavior,
+    conflict_resolution: ConflictResolution,
+) -> Flags {
+    // Tells if a flag changed on both sides since the last
+    // synchronization should be kept, given its presence local side
+    // and remote side.
+    let keeps_conflicting = |flag: &Flag, local: bool, remote: bool| {
+        if *flag == Flag::Deleted {
+            return sync_deleted.keeps_conflicting_flag();
+        }
+
+        match conflict_resolution {
+            ConflictResolution::KeepFlag => true,
+            ConflictResolution::PreferLocal => local,
+            ConflictResolution::PreferRemote => remote,
+        }
+    };
+
     let mut synchronized_flags: HashSet<Flag> = HashSet::default();
 
-    let mut all_flags: HashSet<Flag> = HashSet::default();
-    all_flags.extend(local_cache.map(|e| e.flags.clone().0).unwrap_or_default());
-    all_flags.extend(local.map(|e| e.flags.clone().0).unwrap_or_default());
-    all_flags.extend(remote_cache.map(|e| e.flags.clone().0).unwrap_or_default());
-    all_flags.extend(remote.map(|e| e.flags.clone().0).unwrap_or_default());
+    let mut all_flags: HashSet<&Flag> = HashSet::default();
+    for envelope in [local_cache, local, remote_cache, remote]
+        .into_iter()
+        .flatten()
+    {
+        all_flags.extend(envelope.flags.iter());
+    }
 
     for flag in all_flags {
         match (
-            local_cache.and_then(|e| e.flags.get(&flag)),
-            local.and_then(|e| e.flags.get(&flag)),
-            remote_cache.and_then(|e| e.flags.get(&flag)),
-            remote.and_then(|e| e.flags.get(&flag)),
+            local_cache.and_then(|e| e.flags.get(flag)),
+            local.and_then(|e| e.flags.get(flag)),
+            remote_cache.and_then(|e| e.flags.get(flag)),
+            remote.and_then(|e| e.flags.get(flag)),
         ) {
             // The flag exists nowhere, which cannot happen since the
             // flags hashset is built from envelopes flags.
@@ -36,22 +78,20 @@ pub fn sync_all(
             // The flag only exists in remote cache, which means an
             // outdated flag needs to be removed.
             (None, None, Some(_), None) => {
-                synchronized_flags.remove(&flag);
+                synchronized_flags.remove(flag);
             }
 
             // The flag exists in remote side but not in local side,
             // which means there is a conflict. Since we cannot
             // determine which side (local removed or remote added) is
-            // the most up-to-date, it is safer to consider the remote
-            // added side up-to-date (or local removed in case of
-            // [`Flag::Deleted`]) in order not to lose data.
-            //
-            // TODO: make this behaviour customizable.
-            (None, None, Some(_), Some(_)) if flag == Flag::Deleted => {
-                synchronized_flags.remove(&flag);
+            // the most up-to-date, it is resolved by the conflict
+            // resolution strategy, which keeps the flag by default in
+            // order not to lose data.
+            (None, None, Some(_), Some(_)) if keeps_conflicting(flag, false, true) => {
+                synchronized_flags.insert(flag.clone());
             }
             (None, None, Some(_), Some(_)) => {
-                synchronized_flags.insert(flag.clone());
+                synchronized_flags.remove(flag);
             }
 
             // The flag only exists in local side, which means a new
@@ -71,16 +111,13 @@ pub fn sync_all(
             // which means a new (same) flag has been added local side
             // but removed remote side. Since we cannot determine
             // which side (local added or remote removed) is the most
-            // up-to-date, it is safer to consider the local added
-            // side up-to-date (or remote removed in case of
-            // [`Flag::Deleted`]) in order not to lose data.
-            //
-            // TODO: make this behaviour customizable.
-            (None, Some(_), Some(_), None) if flag == Flag::Deleted => {
-                synchronized_flags.remove(&flag);
+            // up-to-date, it is resolved by the conflict resolution
+            // strategy.
+            (None, Some(_), Some(_), None) if keeps_conflicting(flag, true, false) => {
+                synchronized_flags.insert(flag.clone());
             }
             (None, Some(_), Some(_), None) => {
-                synchronized_flags.insert(flag.clone());
+                synchronized_flags.remove(flag);
             }
 
             // The flag exists everywhere except in local cache, which
@@ -92,51 +129,44 @@ pub fn sync_all(
             // The flag only exists in local cache side, which means
             // the local cache has an outdated flag.
             (Some(_), None, None, None) => {
-                synchronized_flags.remove(&flag);
+                synchronized_flags.remove(flag);
             }
 
             // The flag exists in local cache side and remote side,
             // which means a new (same) flag has been removed local
             // cache side but added remote side. Since we cannot
             // determine which side (local removed or remote added) is
-            // the most up-to-date, it is safer to consider the remote
-            // added side up-to-date (or local removed in case of
-            // [`Flag::Deleted`]) in order not to lose data.
-            //
-            // TODO: make this behaviour customizable.
-            (Some(_), None, None, Some(_)) if flag == Flag::Deleted => {
-                synchronized_flags.remove(&flag);
+            // the most up-to-date, it is resolved by the conflict
+            // resolution strategy.
+            (Some(_), None, None, Some(_)) if keeps_conflicting(flag, false, true) => {
+                synchronized_flags.insert(flag.clone());
             }
             (Some(_), None, None, Some(_)) => {
-                synchronized_flags.insert(flag.clone());
+                synchronized_flags.remove(flag);
             }
 
             // The flag exists in both caches, which means a old flag
             // needs to be removed everywhere.
             (Some(_), None, Some(_), None) => {
-                synchronized_flags.remove(&flag);
+                synchronized_flags.remove(flag);
             }
 
             // The flag exists everywhere except in local side, which
             // means a flag has been removed local side and needs to
             // be removed everywhere else.
             (Some(_), None, Some(_), Some(_)) => {
-                synchronized_flags.remove(&flag);
+                synchronized_flags.remove(flag);
             }
 
             // The flag exists in the local sides but not in remote
             // sides, which means there is a conflict. Since we cannot
             // determine which side is the most up-to-date, it is
-            // safer to consider the local side side up-to-date (or
-            // remote side in case of [`Flag::Deleted`]) in order not
-            // to lose data.
-            //
-            // TODO: make this behaviour customizable.
-            (Some(_), Some(_), None, None) if flag == Flag::Deleted => {
-                synchronized_flags.remove(&flag);
+            // resolved by the conflict resolution strategy.
+            (Some(_), Some(_), None, None) if keeps_conflicting(flag, true, false) => {
+                synchronized_flags.insert(flag.clone());
             }
             (Some(_), Some(_), None, None) => {
-                synchronized_flags.insert(flag.clone());
+                synchronized_flags.remove(flag);
             }
 
             // The flag exists everywhere except in remote cache side,
@@ -149,7 +179,7 @@ pub fn sync_all(
             // means a flag has been removed remote side and needs to
             // be removed everywhere else.
             (Some(_), Some(_), Some(_), None) => {
-                synchronized_flags.remove(&flag);
+                synchronized_flags.remove(flag);
             }
 
             // The flag exists everywhere, which means the flag needs
@@ -165,7 +195,10 @@ pub fn sync_all(
 
 #[cfg(test)]
 mod sync_flags {
-    use crate::{Envelope, Flag, Flags};
+    use crate::{
+        envelope::sync::{ConflictResolution, SyncDeletedBehavior},
+        Envelope, Flag, Flags,
+    };
 
     #[test]
     fn sync_all() {
@@ -430,4 +463,71 @@ mod sync_flags {
             Flags::from_iter([Flag::Seen, Flag::Flagged]),
         );
     }
+
+    #[test]
+    fn sync_all_with_deleted_conflict() {
+        let deleted = Envelope {
+            flags: Flags::from_iter([Flag::Seen, Flag::Deleted]),
+            ..Envelope::default()
+        };
+        let seen = Envelope {
+            flags: Flags::from_iter([Flag::Seen]),
+            ..Envelope::default()
+        };
+
+        // deleted in the local sides but not in the remote side, the
+        // conflict being resolved by the deleted behavior only
+        let sync = |sync_deleted| {
+            super::sync_all_with(
+                Some(&deleted),
+                Some(&deleted),
+                None,
+                Some(&seen),
+                sync_deleted,
+                ConflictResolution::PreferRemote,
+            )
+        };
+
+        assert_eq!(
+            sync(SyncDeletedBehavior::PropagateFlag),
+            Flags::from_iter([Flag::Seen]),
+        );
+        assert_eq!(
+            sync(SyncDeletedBehavior::Expunge),
+            Flags::from_iter([Flag::Seen]),
+        );
+        assert_eq!(
+            sync(SyncDeletedBehavior::MoveToTrash),
+            Flags::from_iter([Flag::Seen, Flag::Deleted]),
+        );
+    }
+
+    #[test]
+    fn sync_all_with_conflict_resolution() {
+        let flagged = Envelope {
+            flags: Flags::from_iter([Flag::Seen, Flag::Flagged]),
+            ..Envelope::default()
+        };
+        let seen = Envelope {
+            flags: Flags::from_iter([Flag::Seen]),
+            ..Envelope::default()
+        };
+
+        // flagged local side, unflagged remote side since the last
+        // synchronization
+        let sync = |conflict_resolution| {
+            super::sync_all_with(
+                None,
+                Some(&flagged),
+                Some(&flagged),
+                Some(&seen),
+                SyncDeletedBehavior::default(),
+                conflict_resolution,
+            )
+        };
+
+        assert_eq!(sync(ConflictResolution::KeepFlag), flagged.flags);
+        assert_eq!(sync(ConflictResolution::PreferLocal), flagged.flags);
+        assert_eq!(sync(ConflictResolution::PreferRemote), seen.flags);
+    }
 }
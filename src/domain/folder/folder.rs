@@ -2,18 +2,43 @@
 //!
 //! This module contains the representation of the email folder.
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::fmt;
 
+use crate::DEFAULT_INBOX_FOLDER;
+
 /// Represents the folder.
-#[derive(Clone, Debug, Default, Eq, Hash, Serialize)]
+#[derive(Clone, Debug, Default, Eq, Hash, Serialize, Deserialize)]
 pub struct Folder {
     /// Represents the folder hierarchie delimiter.
     pub delim: String,
     /// Represents the folder name.
     pub name: String,
+    /// Represents the folder name as stored by the backend, for
+    /// example the modified UTF-7 encoded name for IMAP or the
+    /// directory name for Maildir.
+    pub raw_name: String,
     /// Represents the folder description.
     pub desc: String,
+    /// Represents the special use of the folder, when the backend
+    /// advertises it.
+    pub special_use: Option<FolderSpecialUse>,
+}
+
+/// Represents the special use of a folder, as advertised by IMAP
+/// servers supporting [RFC 6154] (SPECIAL-USE) or the older XLIST
+/// extension.
+///
+/// [RFC 6154]: https://www.rfc-editor.org/rfc/rfc6154
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
+pub enum FolderSpecialUse {
+    Inbox,
+    Sent,
+    Trash,
+    Drafts,
+    /// Represents the folder holding junk emails, advertised as
+    /// `\Junk` by SPECIAL-USE and as `\Spam` by XLIST.
+    Spam,
 }
 
 impl PartialEq for Folder {
@@ -27,3 +52,67 @@ impl fmt::Display for Folder {
         write!(f, "{}", self.name)
     }
 }
+
+/// Represents the statistics of a folder, which can be retrieved
+/// without listing its envelopes.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct FolderStatus {
+    /// Represents the number of emails.
+    pub total: usize,
+    /// Represents the number of emails not flagged as seen.
+    pub unseen: usize,
+    /// Represents the number of emails flagged as recent.
+    pub recent: usize,
+    /// Represents the total size of the emails, in bytes, when the
+    /// backend can compute it cheaply.
+    pub size_bytes: Option<u64>,
+}
+
+/// Represents the permission of the user on a folder, as far as the
+/// backend can tell (see [`crate::Backend::folder_permission`]).
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub enum FolderPermission {
+    /// Emails and flags of the folder can be changed.
+    #[default]
+    ReadWrite,
+    /// The folder can only be read, like an IMAP folder shared with
+    /// lookup and read rights only.
+    ReadOnly,
+}
+
+impl FolderPermission {
+    pub fn is_read_only(&self) -> bool {
+        matches!(self, Self::ReadOnly)
+    }
+}
+
+/// Returns the canonical form of the given folder name, which should
+/// be used everywhere a folder name becomes a cache key or a local
+/// Maildir directory name.
+///
+/// The inbox name is case-insensitive ([RFC 3501]), so any casing of
+/// it gives [`DEFAULT_INBOX_FOLDER`]. Other names are kept as they
+/// are.
+///
+/// [RFC 3501]: https://www.rfc-editor.org/rfc/rfc3501#section-5.1
+pub fn canonical_folder_name(name: &str) -> String {
+    if name.eq_ignore_ascii_case(DEFAULT_INBOX_FOLDER) {
+        DEFAULT_INBOX_FOLDER.to_owned()
+    } else {
+        name.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod folder_name {
+    use super::canonical_folder_name;
+
+    #[test]
+    fn canonical_name() {
+        assert_eq!("INBOX", canonical_folder_name("INBOX"));
+        assert_eq!("INBOX", canonical_folder_name("Inbox"));
+        assert_eq!("INBOX", canonical_folder_name("inbox"));
+        assert_eq!("Inbox/Archives", canonical_folder_name("Inbox/Archives"));
+        assert_eq!("Sent", canonical_folder_name("Sent"));
+    }
+}
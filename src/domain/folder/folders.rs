@@ -2,13 +2,13 @@
 //!
 //! This module contains the representation of the email folders.
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::ops::{Deref, DerefMut};
 
-use crate::Folder;
+use crate::{Folder, FolderSpecialUse};
 
 /// Represents the list of folders.
-#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Folders(Vec<Folder>);
 
 impl Deref for Folders {
@@ -32,3 +32,245 @@ impl FromIterator<Folder> for Folders {
         folders
     }
 }
+
+impl Folders {
+    /// Finds the folder matching the given name.
+    pub fn find_by_name(&self, name: &str) -> Option<&Folder> {
+        self.iter().find(|folder| folder.name == name)
+    }
+
+    /// Finds the folder having the given special use, see
+    /// [`Folder::special_use`].
+    pub fn find_special(&self, special_use: FolderSpecialUse) -> Option<&Folder> {
+        self.iter()
+            .find(|folder| folder.special_use == Some(special_use))
+    }
+
+    /// Returns the folders matching the given predicate.
+    pub fn filter<F: Fn(&Folder) -> bool>(&self, f: F) -> Folders {
+        self.iter().filter(|folder| f(folder)).cloned().collect()
+    }
+
+    /// Builds the folders hierarchy, splitting folder names with
+    /// their delimiter.
+    pub fn hierarchy(&self) -> FolderTree {
+        self.clone().into_tree()
+    }
+
+    /// Builds the folders hierarchy, splitting folder names with
+    /// their own delimiter. Folders whose parent is not listed get a
+    /// placeholder parent node without folder, and folders with an
+    /// empty delimiter are all top-level nodes.
+    pub fn into_tree(self) -> FolderTree {
+        let mut tree = FolderTree::default();
+
+        for folder in self.0 {
+            let segments: Vec<&str> = if folder.delim.is_empty() {
+                vec![folder.name.as_str()]
+            } else {
+                folder.name.split(folder.delim.as_str()).collect()
+            };
+
+            let mut node = &mut tree;
+            for (i, segment) in segments.iter().enumerate() {
+                let pos = match node.children.iter().position(|n| n.name == *segment) {
+                    Some(pos) => pos,
+                    None => {
+                        node.children.push(FolderTree {
+                            path: segments[..=i].join(&folder.delim),
+                            name: segment.to_string(),
+                            ..FolderTree::default()
+                        });
+                        node.children.len() - 1
+                    }
+                };
+                node = &mut node.children[pos];
+            }
+
+            node.folder = Some(folder);
+        }
+
+        tree
+    }
+}
+
+/// Represents a node of the folders hierarchy. The root node has an
+/// empty path and no folder.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct FolderTree {
+    /// Represents the full name of the node, for example `a/b`.
+    pub path: String,
+    /// Represents the last segment of the node name, for example
+    /// `b` for `a/b`.
+    pub name: String,
+    /// Represents the folder of the node. It is `None` for the root
+    /// node and for intermediate nodes without folder, for example
+    /// `a` when only `a/b` exists.
+    pub folder: Option<Folder>,
+    /// Represents the children nodes, in the folders order.
+    pub children: Vec<FolderTree>,
+}
+
+impl FolderTree {
+    /// Finds the node matching the given full name.
+    pub fn find(&self, path: &str) -> Option<&FolderTree> {
+        if self.path == path {
+            return Some(self);
+        }
+
+        self.children.iter().find_map(|node| node.find(path))
+    }
+
+    /// Returns an iterator over the nodes of the tree in depth-first
+    /// order, parents before their children. The root node is not
+    /// part of the iteration.
+    pub fn iter(&self) -> FolderTreeIter<'_> {
+        FolderTreeIter {
+            stack: self.children.iter().rev().collect(),
+        }
+    }
+
+    /// Returns the folders of the tree in depth-first order, skipping
+    /// placeholder nodes.
+    pub fn flatten(&self) -> Folders {
+        self.iter().filter_map(|node| node.folder.clone()).collect()
+    }
+
+    /// Returns the direct children folders of the node matching the
+    /// given full name. An empty name returns the top-level folders.
+    pub fn children_of(&self, name: &str) -> Vec<&Folder> {
+        self.find(name)
+            .map(|node| {
+                node.children
+                    .iter()
+                    .filter_map(|node| node.folder.as_ref())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// Iterates over the nodes of a [`FolderTree`] in depth-first order.
+pub struct FolderTreeIter<'a> {
+    stack: Vec<&'a FolderTree>,
+}
+
+impl<'a> Iterator for FolderTreeIter<'a> {
+    type Item = &'a FolderTree;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        self.stack.extend(node.children.iter().rev());
+        Some(node)
+    }
+}
+
+impl<'a> IntoIterator for &'a FolderTree {
+    type Item = &'a FolderTree;
+    type IntoIter = FolderTreeIter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod folders {
+    use crate::{Folder, FolderSpecialUse};
+
+    use super::Folders;
+
+    fn folder(name: &str) -> Folder {
+        Folder {
+            delim: "/".into(),
+            name: name.into(),
+            ..Folder::default()
+        }
+    }
+
+    #[test]
+    fn find_special() {
+        let sent = Folder {
+            special_use: Some(FolderSpecialUse::Sent),
+            ..folder("[Gmail]/Sent Mail")
+        };
+        let folders = Folders::from_iter([folder("INBOX"), sent, folder("Sent")]);
+
+        let found = folders.find_special(FolderSpecialUse::Sent);
+        assert_eq!(Some("[Gmail]/Sent Mail"), found.map(|f| f.name.as_str()));
+        assert_eq!(None, folders.find_special(FolderSpecialUse::Trash));
+    }
+
+    #[test]
+    fn hierarchy() {
+        let folders = Folders::from_iter([
+            folder("INBOX"),
+            folder("Archives"),
+            folder("Archives/2022"),
+            folder("Archives/2023"),
+            folder("Projects/a"),
+        ]);
+
+        assert_eq!(Some(&folder("Archives")), folders.find_by_name("Archives"));
+        assert_eq!(None, folders.find_by_name("Trash"));
+        assert_eq!(3, folders.filter(|f| f.name.starts_with("Archives")).len());
+
+        let tree = folders.hierarchy();
+        assert_eq!(
+            vec!["INBOX", "Archives"],
+            tree.children_of("")
+                .iter()
+                .map(|f| f.name.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec!["Archives/2022", "Archives/2023"],
+            tree.children_of("Archives")
+                .iter()
+                .map(|f| f.name.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(None, tree.find("Projects").unwrap().folder);
+        assert_eq!(1, tree.children_of("Projects").len());
+        assert!(tree.children_of("Trash").is_empty());
+    }
+
+    #[test]
+    fn into_tree() {
+        let dotted = |name: &str| Folder {
+            delim: ".".into(),
+            ..folder(name)
+        };
+        let flat = |name: &str| Folder {
+            delim: "".into(),
+            ..folder(name)
+        };
+        let folders = Folders::from_iter([
+            folder("INBOX"),
+            folder("INBOX/Sub"),
+            dotted("shared.team.news"),
+            flat("a/b"),
+        ]);
+
+        let tree = folders.clone().into_tree();
+        assert_eq!(
+            vec![
+                "INBOX",
+                "INBOX/Sub",
+                "shared",
+                "shared.team",
+                "shared.team.news",
+                "a/b"
+            ],
+            tree.iter()
+                .map(|node| node.path.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(None, tree.find("shared.team").unwrap().folder);
+        assert_eq!(
+            Some(&flat("a/b")),
+            tree.find("a/b").unwrap().folder.as_ref()
+        );
+        assert_eq!(folders, tree.flatten());
+    }
+}
@@ -0,0 +1,155 @@
+//! IMAP folder module.
+//!
+//! This module provides IMAP types and conversion utilities related
+//! to the folder. IMAP folder names are encoded using the modified
+//! UTF-7 encoding defined in the [RFC 3501].
+//!
+//! [RFC 3501]: https://www.rfc-editor.org/rfc/rfc3501#section-5.1.3
+
+use imap::types::Name;
+use imap_proto::NameAttribute;
+use utf7_imap::{decode_utf7_imap, encode_utf7_imap};
+
+use crate::{Folder, FolderSpecialUse, DEFAULT_INBOX_FOLDER};
+
+/// Represents the raw folder returned by the `imap` crate.
+pub type RawFolder<'a> = Name<'a>;
+
+/// Encodes the given folder name so that it can be sent to the
+/// server. Every IMAP command taking a folder name should use this
+/// function, and the name should always be a decoded one (for
+/// example [`Folder::name`], never [`Folder::raw_name`]), otherwise
+/// it ends up encoded twice.
+pub fn encode(folder: &str) -> String {
+    encode_utf7_imap(folder.to_owned())
+}
+
+/// Encodes the given folder name then quotes it, for the commands
+/// written by hand instead of being built by the `imap` crate.
+/// Backslashes and double quotes are escaped, like the `imap` crate
+/// does.
+pub fn quote(folder: &str) -> String {
+    let folder = encode(folder).replace('\\', "\\\\").replace('"', "\\\"");
+    format!("\"{folder}\"")
+}
+
+/// Decodes the given folder name returned by the server.
+pub fn decode(raw_folder: &str) -> String {
+    decode_utf7_imap(raw_folder.to_owned())
+}
+
+/// Builds the folder from the given raw folder. Returns `None` if the
+/// folder cannot be selected.
+pub fn from_raw(raw_folder: &RawFolder) -> Option<Folder> {
+    if raw_folder.attributes().contains(&NameAttribute::NoSelect) {
+        return None;
+    }
+
+    Some(Folder {
+        delim: raw_folder.delimiter().unwrap_or_default().into(),
+        name: decode(raw_folder.name()),
+        raw_name: raw_folder.name().into(),
+        desc: raw_folder
+            .attributes()
+            .iter()
+            .map(|attr| format!("{attr:?}"))
+            .collect::<Vec<_>>()
+            .join(", "),
+        special_use: special_use(raw_folder),
+    })
+}
+
+/// Returns the special use of the given raw folder, from its
+/// SPECIAL-USE or XLIST attributes. The inbox is recognized by its
+/// name when the server does not mark it.
+fn special_use(raw_folder: &RawFolder) -> Option<FolderSpecialUse> {
+    let special_use = raw_folder.attributes().iter().find_map(|attr| match attr {
+        NameAttribute::Sent => Some(FolderSpecialUse::Sent),
+        NameAttribute::Trash => Some(FolderSpecialUse::Trash),
+        NameAttribute::Drafts => Some(FolderSpecialUse::Drafts),
+        NameAttribute::Junk => Some(FolderSpecialUse::Spam),
+        // XLIST attributes unknown to RFC 6154
+        NameAttribute::Extension(attr) if attr.eq_ignore_ascii_case("\\Inbox") => {
+            Some(FolderSpecialUse::Inbox)
+        }
+        NameAttribute::Extension(attr) if attr.eq_ignore_ascii_case("\\Spam") => {
+            Some(FolderSpecialUse::Spam)
+        }
+        _ => None,
+    });
+
+    special_use.or_else(|| {
+        if raw_folder.name().eq_ignore_ascii_case(DEFAULT_INBOX_FOLDER) {
+            Some(FolderSpecialUse::Inbox)
+        } else {
+            None
+        }
+    })
+}
+
+/// Turns the untagged responses of an XLIST command into LIST ones,
+/// which share the same syntax, so that they can be parsed as such.
+pub fn xlist_to_list(response: &[u8]) -> Vec<u8> {
+    let mut list = Vec::with_capacity(response.len());
+
+    for line in response.split_inclusive(|byte| *byte == b'\n') {
+        match line.strip_prefix(b"* XLIST ".as_slice()) {
+            Some(rest) => {
+                list.extend_from_slice(b"* LIST ");
+                list.extend_from_slice(rest);
+            }
+            None => list.extend_from_slice(line),
+        }
+    }
+
+    list
+}
+
+#[cfg(test)]
+mod imap_folder {
+    use super::{decode, encode, quote, xlist_to_list};
+
+    #[test]
+    fn xlist() {
+        let response = concat!(
+            "* XLIST (\\HasNoChildren \\Inbox) \"/\" \"Inbox\"\r\n",
+            "* XLIST (\\HasNoChildren \\Sent) \"/\" \"[Gmail]/Sent Mail\"\r\n",
+            "* 3 EXISTS\r\n",
+        );
+
+        assert_eq!(
+            concat!(
+                "* LIST (\\HasNoChildren \\Inbox) \"/\" \"Inbox\"\r\n",
+                "* LIST (\\HasNoChildren \\Sent) \"/\" \"[Gmail]/Sent Mail\"\r\n",
+                "* 3 EXISTS\r\n",
+            )
+            .as_bytes(),
+            xlist_to_list(response.as_bytes()),
+        );
+    }
+
+    #[test]
+    fn encode_decode() {
+        let folders = [
+            ("INBOX", "INBOX"),
+            ("Entwürfe", "Entw&APw-rfe"),
+            ("Gelöschte Elemente", "Gel&APY-schte Elemente"),
+            ("已发送", "&XfJT0ZAB-"),
+            ("Tom & Jerry", "Tom &- Jerry"),
+            ("&", "&-"),
+        ];
+
+        for (folder, raw_folder) in folders {
+            assert_eq!(raw_folder, encode(folder));
+            assert_eq!(folder, decode(raw_folder));
+            assert_eq!(folder, decode(&encode(folder)));
+        }
+    }
+
+    #[test]
+    fn quote_folder() {
+        assert_eq!("\"INBOX\"", quote("INBOX"));
+        assert_eq!("\"Entw&APw-rfe\"", quote("Entwürfe"));
+        assert_eq!("\"a \\\"b\\\" \\\\ c\"", quote("a \"b\" \\ c"));
+    }
+}
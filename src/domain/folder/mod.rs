@@ -4,6 +4,8 @@
 
 pub mod folder;
 pub mod folders;
+#[cfg(feature = "imap-backend")]
+pub mod imap;
 pub mod sync;
 
 pub use self::folder::*;
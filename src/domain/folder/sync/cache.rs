@@ -15,6 +15,11 @@ const INSERT_FOLDER: &str = "
     VALUES (?, ?)
 ";
 
+const DELETE_FOLDERS: &str = "
+    DELETE FROM folders
+    WHERE account IN (?, ?)
+";
+
 const DELETE_FOLDER: &str = "
     DELETE FROM folders
     WHERE account = ?
@@ -32,11 +37,30 @@ pub struct Cache;
 impl Cache {
     const LOCAL_SUFFIX: &str = ":cache";
 
+    /// Creates the folders table if it does not exist yet.
+    ///
+    /// The table is small enough not to need any index other than
+    /// the one created by its unique constraint, so it does not take
+    /// part in the schema versioning (the SQLite `user_version` is
+    /// owned by the envelopes cache, whose migrations also take care
+    /// of this table).
     pub fn init(conn: &mut rusqlite::Connection) -> Result<()> {
         conn.execute(CREATE_FOLDERS_TABLE, ())?;
         Ok(())
     }
 
+    /// Rebuilds the database file, reclaiming the space left by
+    /// deleted folders.
+    pub fn vacuum(conn: &mut rusqlite::Connection) -> Result<()> {
+        Ok(crate::sqlite::vacuum(conn)?)
+    }
+
+    /// Checks the integrity of the database and returns the list of
+    /// problems found. An empty list means the database is sane.
+    pub fn integrity_check(conn: &mut rusqlite::Connection) -> Result<Vec<String>> {
+        Ok(crate::sqlite::integrity_check(conn)?)
+    }
+
     fn list_folders<A>(conn: &mut rusqlite::Connection, account: A) -> Result<FoldersName>
     where
         A: AsRef<str>,
@@ -131,4 +155,16 @@ impl Cache {
     {
         Self::delete_folder(tx, account, folder)
     }
+
+    /// Deletes both the local and the remote folders of the given
+    /// account, for example before rebuilding the cache.
+    pub fn clear<A>(tx: &rusqlite::Transaction, account: A) -> Result<()>
+    where
+        A: ToString,
+    {
+        let account = account.to_string();
+        let local_account = account.clone() + Self::LOCAL_SUFFIX;
+        tx.execute(DELETE_FOLDERS, [&local_account, &account])?;
+        Ok(())
+    }
 }
@@ -1,8 +1,9 @@
 use rusqlite;
+use serde::{Serialize, Serializer};
 use std::result;
 use thiserror::Error;
 
-use crate::{account, backend};
+use crate::{account, backend, envelope};
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -12,6 +13,29 @@ pub enum Error {
     ConfigError(#[from] account::config::Error),
     #[error(transparent)]
     BackendError(#[from] Box<backend::Error>),
+    #[error("cannot synchronize folder {1}")]
+    SyncFolderError(#[source] Box<backend::Error>, String),
+    #[error(transparent)]
+    EnvelopesCacheError(#[from] Box<envelope::sync::Error>),
+}
+
+impl Error {
+    /// Returns the folder the error is specific to, or `None` if the
+    /// error concerns the whole folders synchronization.
+    pub fn folder(&self) -> Option<&str> {
+        match self {
+            Self::SyncFolderError(_, folder) => Some(folder),
+            _ => None,
+        }
+    }
 }
 
 pub type Result<T> = result::Result<T, Error>;
+
+/// Serializes the error as its display, so that the reports
+/// containing it can be serialized.
+impl Serialize for Error {
+    fn serialize<S: Serializer>(&self, s: S) -> result::Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
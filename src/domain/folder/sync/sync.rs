@@ -1,8 +1,12 @@
 use log::{debug, info, trace, warn};
 use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 use std::{collections::HashSet, fmt};
 
-use crate::{AccountConfig, Backend, BackendSyncProgressEvent, MaildirBackend};
+use crate::{
+    envelope, folder, AccountConfig, Backend, BackendSyncProgressEvent, MaildirBackend,
+    SyncExecutor,
+};
 
 use super::{Cache, Error, Result};
 
@@ -12,7 +16,7 @@ pub type Patch = Vec<Hunk>;
 pub type Target = HunkKind;
 pub type TargetRestricted = HunkKindRestricted;
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub enum HunkKind {
     LocalCache,
     Local,
@@ -20,7 +24,7 @@ pub enum HunkKind {
     Remote,
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub enum HunkKindRestricted {
     Local,
     Remote,
@@ -37,10 +41,13 @@ impl fmt::Display for HunkKind {
     }
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub enum Hunk {
     CreateFolder(FolderName, Target),
     DeleteFolder(FolderName, Target),
+    /// Renames the first folder to the second one, keeping its
+    /// emails (see [`SyncBuilder::rename_threshold`]).
+    RenameFolder(FolderName, FolderName, Target),
 }
 
 impl fmt::Display for Hunk {
@@ -48,19 +55,34 @@ impl fmt::Display for Hunk {
         match self {
             Self::CreateFolder(name, target) => write!(f, "Adding folder {name} to {target}"),
             Self::DeleteFolder(name, target) => write!(f, "Removing folder {name} from {target}"),
+            Self::RenameFolder(from, to, target) => {
+                write!(f, "Renaming folder {from} to {to} in {target}")
+            }
         }
     }
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub enum CacheHunk {
     CreateFolder(FolderName, TargetRestricted),
     DeleteFolder(FolderName, TargetRestricted),
+    RenameFolder(FolderName, FolderName, TargetRestricted),
 }
 
-#[derive(Debug, Default)]
+/// Default minimum overlap between the envelopes of a vanished
+/// folder and the ones of an appeared folder for the folder to be
+/// considered renamed (see [`SyncBuilder::rename_threshold`]).
+pub const DEFAULT_RENAME_THRESHOLD: f64 = 1.0;
+
+#[derive(Debug, Default, Serialize)]
 pub struct SyncReport {
+    /// Represents the folders to synchronize the envelopes of.
     pub folders: FoldersName,
+    /// Represents the folders which could not be created on the local
+    /// or the remote backend. They are excluded from [`Self::folders`]
+    /// and from the cache, so that the next synchronization tries to
+    /// create them again.
+    pub failed_folders: FoldersName,
     pub patch: Vec<(Hunk, Option<Error>)>,
     pub cache_patch: (Vec<CacheHunk>, Option<Error>),
 }
@@ -68,6 +90,8 @@ pub struct SyncReport {
 pub struct SyncBuilder<'a> {
     account_config: &'a AccountConfig,
     dry_run: bool,
+    rename_threshold: f64,
+    executor: SyncExecutor<'a>,
     on_progress: Box<dyn Fn(BackendSyncProgressEvent) -> Result<()> + Sync + Send + 'a>,
 }
 
@@ -76,6 +100,8 @@ impl<'a> SyncBuilder<'a> {
         Self {
             account_config,
             dry_run: false,
+            rename_threshold: DEFAULT_RENAME_THRESHOLD,
+            executor: SyncExecutor::default(),
             on_progress: Box::new(|_| Ok(())),
         }
     }
@@ -85,6 +111,29 @@ impl<'a> SyncBuilder<'a> {
         self
     }
 
+    /// Sets the minimum overlap between the envelopes of a vanished
+    /// folder and the ones of an appeared folder for the folder to be
+    /// considered renamed, from 0 to 1.
+    ///
+    /// The overlap is the amount of message ids in common divided by
+    /// the amount of distinct message ids of both folders. A renamed
+    /// folder is renamed on the other side and in the caches, instead
+    /// of being deleted then created again, which would download all
+    /// its emails again. Empty folders are never considered renamed.
+    /// Defaults to [`DEFAULT_RENAME_THRESHOLD`], which requires both
+    /// folders to contain the exact same emails.
+    pub fn rename_threshold(mut self, threshold: f64) -> Self {
+        self.rename_threshold = threshold;
+        self
+    }
+
+    /// Sets how the patch is processed. Defaults to
+    /// [`SyncExecutor::GlobalPool`].
+    pub fn executor(mut self, executor: SyncExecutor<'a>) -> Self {
+        self.executor = executor;
+        self
+    }
+
     pub fn on_progress<F>(mut self, f: F) -> Self
     where
         F: Fn(BackendSyncProgressEvent) -> Result<()> + Sync + Send + 'a,
@@ -111,8 +160,13 @@ impl<'a> SyncBuilder<'a> {
 
         self.try_progress(BackendSyncProgressEvent::GetLocalCachedFolders);
 
-        let local_folders_cached: FoldersName =
-            HashSet::from_iter(Cache::list_local_folders(conn, account)?.iter().cloned());
+        // folder names are canonicalized so that servers changing the
+        // casing of the inbox do not make it appear as a new folder
+        let local_folders_cached: FoldersName = HashSet::from_iter(
+            Cache::list_local_folders(conn, account)?
+                .iter()
+                .map(|name| folder::canonical_folder_name(name)),
+        );
 
         trace!("local folders cached: {:#?}", local_folders_cached);
 
@@ -123,15 +177,18 @@ impl<'a> SyncBuilder<'a> {
                 .list_folders()
                 .map_err(Box::new)?
                 .iter()
-                .map(|folder| folder.name.clone()),
+                .map(|folder| folder::canonical_folder_name(&folder.name)),
         );
 
         trace!("local folders: {:#?}", local_folders);
 
         self.try_progress(BackendSyncProgressEvent::GetRemoteCachedFolders);
 
-        let remote_folders_cached: FoldersName =
-            HashSet::from_iter(Cache::list_remote_folders(conn, account)?.iter().cloned());
+        let remote_folders_cached: FoldersName = HashSet::from_iter(
+            Cache::list_remote_folders(conn, account)?
+                .iter()
+                .map(|name| folder::canonical_folder_name(name)),
+        );
 
         trace!("remote folders cached: {:#?}", remote_folders_cached);
 
@@ -142,20 +199,87 @@ impl<'a> SyncBuilder<'a> {
                 .list_folders()
                 .map_err(Box::new)?
                 .iter()
-                .map(|folder| folder.name.clone()),
+                .map(|folder| folder::canonical_folder_name(&folder.name)),
         );
 
         trace!("remote folders: {:#?}", remote_folders);
 
         self.try_progress(BackendSyncProgressEvent::BuildFoldersPatch);
 
-        let (patch, folders) = build_patch(
+        // folders renamed remote side vanished from the remote side
+        // only, and appeared remote side only
+        let remote_renames = find_renames(
+            remote_folders_cached.iter().filter(|folder| {
+                local_folders_cached.contains(*folder)
+                    && local_folders.contains(*folder)
+                    && !remote_folders.contains(*folder)
+            }),
+            remote_folders.iter().filter(|folder| {
+                !local_folders_cached.contains(*folder)
+                    && !local_folders.contains(*folder)
+                    && !remote_folders_cached.contains(*folder)
+            }),
+            |folder| {
+                let envelopes = envelope::sync::Cache::list_remote_envelopes(conn, account, folder)
+                    .map_err(Box::new)?;
+                Ok(envelopes.iter().map(|e| e.message_id.clone()).collect())
+            },
+            |folder| {
+                let envelopes = remote.list_envelopes(folder, 0, 0).map_err(Box::new)?;
+                Ok(envelopes.iter().map(|e| e.message_id.clone()).collect())
+            },
+            self.rename_threshold,
+        );
+
+        // folders renamed local side vanished from the local side
+        // only, and appeared local side only
+        let local_renames = find_renames(
+            local_folders_cached.iter().filter(|folder| {
+                !local_folders.contains(*folder)
+                    && remote_folders_cached.contains(*folder)
+                    && remote_folders.contains(*folder)
+            }),
+            local_folders.iter().filter(|folder| {
+                !local_folders_cached.contains(*folder)
+                    && !remote_folders_cached.contains(*folder)
+                    && !remote_folders.contains(*folder)
+            }),
+            |folder| {
+                let envelopes = envelope::sync::Cache::list_local_envelopes(conn, account, folder)
+                    .map_err(Box::new)?;
+                Ok(envelopes.iter().map(|e| e.message_id.clone()).collect())
+            },
+            |folder| {
+                let envelopes = local.list_envelopes(folder, 0, 0).map_err(Box::new)?;
+                Ok(envelopes.iter().map(|e| e.message_id.clone()).collect())
+            },
+            self.rename_threshold,
+        );
+
+        let (mut patch, mut folders) = build_patch(
             local_folders_cached,
             local_folders,
             remote_folders_cached,
             remote_folders,
         );
 
+        patch_renames(
+            &mut patch,
+            &mut folders,
+            &remote_renames,
+            [HunkKind::LocalCache, HunkKind::Local, HunkKind::RemoteCache],
+        );
+        patch_renames(
+            &mut patch,
+            &mut folders,
+            &local_renames,
+            [
+                HunkKind::LocalCache,
+                HunkKind::RemoteCache,
+                HunkKind::Remote,
+            ],
+        );
+
         self.try_progress(BackendSyncProgressEvent::ProcessFoldersPatch(patch.len()));
 
         debug!("folders patch: {:#?}", patch);
@@ -175,7 +299,9 @@ impl<'a> SyncBuilder<'a> {
                         )]
                     }
                     Hunk::CreateFolder(ref folder, HunkKind::Local) => {
-                        local.add_folder(folder).map_err(Box::new)?;
+                        local
+                            .add_folder(folder)
+                            .map_err(|err| Error::SyncFolderError(Box::new(err), folder.clone()))?;
                         vec![]
                     }
                     Hunk::CreateFolder(ref folder, HunkKind::RemoteCache) => {
@@ -185,7 +311,9 @@ impl<'a> SyncBuilder<'a> {
                         )]
                     }
                     Hunk::CreateFolder(ref folder, HunkKind::Remote) => {
-                        remote.add_folder(&folder).map_err(Box::new)?;
+                        remote
+                            .add_folder(&folder)
+                            .map_err(|err| Error::SyncFolderError(Box::new(err), folder.clone()))?;
                         vec![]
                     }
                     Hunk::DeleteFolder(ref folder, HunkKind::LocalCache) => {
@@ -195,7 +323,9 @@ impl<'a> SyncBuilder<'a> {
                         )]
                     }
                     Hunk::DeleteFolder(ref folder, HunkKind::Local) => {
-                        local.delete_folder(folder).map_err(Box::new)?;
+                        local
+                            .delete_folder(folder)
+                            .map_err(|err| Error::SyncFolderError(Box::new(err), folder.clone()))?;
                         vec![]
                     }
                     Hunk::DeleteFolder(ref folder, HunkKind::RemoteCache) => {
@@ -205,40 +335,102 @@ impl<'a> SyncBuilder<'a> {
                         )]
                     }
                     Hunk::DeleteFolder(ref folder, HunkKind::Remote) => {
-                        remote.delete_folder(&folder).map_err(Box::new)?;
+                        remote
+                            .delete_folder(&folder)
+                            .map_err(|err| Error::SyncFolderError(Box::new(err), folder.clone()))?;
+                        vec![]
+                    }
+                    Hunk::RenameFolder(from, to, HunkKind::LocalCache) => {
+                        vec![CacheHunk::RenameFolder(
+                            from.clone(),
+                            to.clone(),
+                            TargetRestricted::Local,
+                        )]
+                    }
+                    Hunk::RenameFolder(from, to, HunkKind::Local) => {
+                        local
+                            .rename_folder(from, to)
+                            .map_err(|err| Error::SyncFolderError(Box::new(err), from.clone()))?;
+                        vec![]
+                    }
+                    Hunk::RenameFolder(from, to, HunkKind::RemoteCache) => {
+                        vec![CacheHunk::RenameFolder(
+                            from.clone(),
+                            to.clone(),
+                            TargetRestricted::Remote,
+                        )]
+                    }
+                    Hunk::RenameFolder(from, to, HunkKind::Remote) => {
+                        remote
+                            .rename_folder(from, to)
+                            .map_err(|err| Error::SyncFolderError(Box::new(err), from.clone()))?;
                         vec![]
                     }
                 })
             };
 
-            report = patch
-                .par_iter()
-                .fold(SyncReport::default, |mut report, hunk| {
-                    let hunk_str = hunk.to_string();
+            let process_report_hunk = |mut report: SyncReport, hunk: &Hunk| {
+                let hunk_str = hunk.to_string();
 
-                    trace!("processing hunk: {hunk:#?}");
-                    debug!("{hunk_str}");
+                trace!("processing hunk: {hunk:#?}");
+                debug!("{hunk_str}");
 
-                    self.try_progress(BackendSyncProgressEvent::ProcessFolderHunk(hunk_str));
+                self.try_progress(BackendSyncProgressEvent::ProcessFolderHunk(hunk_str));
 
-                    match process_hunk(hunk) {
-                        Ok(cache_hunks) => {
-                            report.patch.push((hunk.clone(), None));
-                            report.cache_patch.0.extend(cache_hunks);
-                        }
-                        Err(err) => {
-                            warn!("error while processing hunk {hunk:?}, skipping it: {err:?}");
-                            report.patch.push((hunk.clone(), Some(err)));
-                        }
-                    };
+                match process_hunk(hunk) {
+                    Ok(cache_hunks) => {
+                        report.patch.push((hunk.clone(), None));
+                        report.cache_patch.0.extend(cache_hunks);
+                    }
+                    Err(err) => {
+                        warn!("error while processing hunk {hunk}, skipping it: {err:?}");
+                        report.patch.push((hunk.clone(), Some(err)));
+                    }
+                };
+
+                report
+            };
+
+            let process_patch = || {
+                patch
+                    .par_iter()
+                    .fold(SyncReport::default, &process_report_hunk)
+                    .reduce(SyncReport::default, |mut r1, r2| {
+                        r1.patch.extend(r2.patch);
+                        r1.cache_patch.0.extend(r2.cache_patch.0);
+                        r1
+                    })
+            };
+
+            report = match self.executor {
+                SyncExecutor::GlobalPool => process_patch(),
+                SyncExecutor::Pool(pool) => pool.install(process_patch),
+                SyncExecutor::SingleThreaded => patch
+                    .iter()
+                    .fold(SyncReport::default(), &process_report_hunk),
+            };
 
-                    report
+            let failed_folders: FoldersName = report
+                .patch
+                .iter()
+                .filter_map(|(hunk, err)| match (hunk, err) {
+                    (Hunk::CreateFolder(folder, HunkKind::Local | HunkKind::Remote), Some(err)) => {
+                        warn!("cannot create folder {folder}, excluding it: {err}");
+                        Some(folder.clone())
+                    }
+                    _ => None,
                 })
-                .reduce(SyncReport::default, |mut r1, r2| {
-                    r1.patch.extend(r2.patch);
-                    r1.cache_patch.0.extend(r2.cache_patch.0);
-                    r1
-                });
+                .collect();
+
+            // caching a folder missing on one side would make the next
+            // synchronization consider it deleted from that side
+            report.cache_patch.0.retain(|hunk| match hunk {
+                CacheHunk::CreateFolder(folder, _) => !failed_folders.contains(folder),
+                _ => true,
+            });
+
+            folders.retain(|folder| !failed_folders.contains(folder));
+            report.failed_folders = failed_folders;
 
             let mut process_cache_patch = || {
                 let tx = conn.transaction()?;
@@ -256,6 +448,18 @@ impl<'a> SyncBuilder<'a> {
                         CacheHunk::DeleteFolder(folder, TargetRestricted::Remote) => {
                             Cache::delete_remote_folder(&tx, account, folder)?;
                         }
+                        CacheHunk::RenameFolder(from, to, TargetRestricted::Local) => {
+                            Cache::delete_local_folder(&tx, account, from)?;
+                            Cache::insert_local_folder(&tx, account, to)?;
+                            envelope::sync::Cache::rename_local_folder(&tx, account, from, to)
+                                .map_err(Box::new)?;
+                        }
+                        CacheHunk::RenameFolder(from, to, TargetRestricted::Remote) => {
+                            Cache::delete_remote_folder(&tx, account, from)?;
+                            Cache::insert_remote_folder(&tx, account, to)?;
+                            envelope::sync::Cache::rename_remote_folder(&tx, account, from, to)
+                                .map_err(Box::new)?;
+                        }
                     }
                 }
                 tx.commit()?;
@@ -268,14 +472,14 @@ impl<'a> SyncBuilder<'a> {
             }
         };
 
-        report.folders = folders
-            .into_iter()
-            .map(|folder| {
-                urlencoding::decode(&folder)
-                    .map(|folder| folder.to_string())
-                    .unwrap_or_else(|_| folder)
-            })
-            .collect();
+        let decode = |folder: FolderName| {
+            urlencoding::decode(&folder)
+                .map(|folder| folder.to_string())
+                .unwrap_or_else(|_| folder)
+        };
+
+        report.folders = folders.into_iter().map(decode).collect();
+        report.failed_folders = report.failed_folders.drain().map(decode).collect();
 
         trace!("sync report: {:#?}", report);
 
@@ -403,10 +607,154 @@ pub fn build_patch(
     (patch, folders)
 }
 
+/// Finds the renamed folders among the given vanished and appeared
+/// folders, by comparing the message ids of their envelopes: the
+/// vanished folder is matched with the appeared folder having the
+/// biggest overlap, if it reaches the given threshold. Folders whose
+/// envelopes cannot be listed are not considered renamed.
+pub fn find_renames<'f, V, A, C, L>(
+    vanished: V,
+    appeared: A,
+    mut list_cached_ids: C,
+    mut list_ids: L,
+    threshold: f64,
+) -> Vec<(FolderName, FolderName)>
+where
+    V: IntoIterator<Item = &'f FolderName>,
+    A: IntoIterator<Item = &'f FolderName>,
+    C: FnMut(&str) -> Result<HashSet<String>>,
+    L: FnMut(&str) -> Result<HashSet<String>>,
+{
+    let mut renames = Vec::new();
+
+    let mut vanished: Vec<&FolderName> = vanished.into_iter().collect();
+    let mut appeared: Vec<&FolderName> = appeared.into_iter().collect();
+    if vanished.is_empty() || appeared.is_empty() {
+        return renames;
+    }
+    vanished.sort();
+    appeared.sort();
+
+    let mut appeared: Vec<(&FolderName, HashSet<String>)> = appeared
+        .into_iter()
+        .filter_map(|folder| match list_ids(folder) {
+            Ok(ids) => Some((folder, ids)),
+            Err(err) => {
+                warn!("cannot list envelopes of folder {folder}, skipping rename detection: {err}");
+                None
+            }
+        })
+        .collect();
+
+    for from in vanished {
+        let from_ids = match list_cached_ids(from) {
+            Ok(ids) if !ids.is_empty() => ids,
+            Ok(_) => continue,
+            Err(err) => {
+                warn!("cannot list cached envelopes of folder {from}, skipping rename detection: {err}");
+                continue;
+            }
+        };
+
+        let best = appeared
+            .iter()
+            .enumerate()
+            .map(|(i, (_, to_ids))| {
+                let common = from_ids.intersection(to_ids).count();
+                let distinct = from_ids.union(to_ids).count();
+                (i, common as f64 / distinct as f64)
+            })
+            .filter(|(_, overlap)| *overlap >= threshold)
+            .max_by(|(_, a), (_, b)| a.total_cmp(b));
+
+        if let Some((i, overlap)) = best {
+            let (to, _) = appeared.remove(i);
+            debug!("folder {from} renamed to {to} (overlap: {overlap})");
+            renames.push((from.clone(), to.clone()));
+        }
+    }
+
+    renames
+}
+
+/// Replaces the hunks of the given renamed folders by rename hunks
+/// for the given targets, and removes the old folder names from the
+/// synchronized folders.
+pub fn patch_renames(
+    patch: &mut Patch,
+    folders: &mut FoldersName,
+    renames: &[(FolderName, FolderName)],
+    targets: [HunkKind; 3],
+) {
+    for (from, to) in renames {
+        patch.retain(|hunk| match hunk {
+            Hunk::CreateFolder(folder, _) | Hunk::DeleteFolder(folder, _) => {
+                folder != from && folder != to
+            }
+            Hunk::RenameFolder(..) => true,
+        });
+        patch.extend(
+            targets
+                .iter()
+                .map(|target| Hunk::RenameFolder(from.clone(), to.clone(), target.clone())),
+        );
+        folders.remove(from);
+    }
+}
+
 #[cfg(test)]
 mod folders_sync {
+    use std::collections::HashSet;
+
     use super::{FoldersName, Hunk, HunkKind, Patch};
 
+    #[test]
+    fn find_and_patch_renames() {
+        let ids = |folder: &str| {
+            Ok(match folder {
+                "Old" | "New" => {
+                    HashSet::from_iter(["<a@localhost>".into(), "<b@localhost>".into()])
+                }
+                "Other" => HashSet::from_iter(["<a@localhost>".into()]),
+                _ => HashSet::new(),
+            })
+        };
+
+        let vanished = FoldersName::from_iter(["Old".into(), "Empty".into()]);
+        let appeared = FoldersName::from_iter(["New".into(), "Other".into()]);
+
+        let renames = super::find_renames(&vanished, &appeared, ids, ids, 1.0);
+        assert_eq!(vec![("Old".to_owned(), "New".to_owned())], renames);
+
+        let renames = super::find_renames(&vanished, ["Other".to_owned()].iter(), ids, ids, 1.0);
+        assert!(renames.is_empty());
+
+        let renames = super::find_renames(&vanished, ["Other".to_owned()].iter(), ids, ids, 0.5);
+        assert_eq!(vec![("Old".to_owned(), "Other".to_owned())], renames);
+
+        let (mut patch, mut folders) = super::build_patch(
+            FoldersName::from_iter(["Old".into()]),
+            FoldersName::from_iter(["Old".into()]),
+            FoldersName::from_iter(["Old".into()]),
+            FoldersName::from_iter(["New".into()]),
+        );
+        super::patch_renames(
+            &mut patch,
+            &mut folders,
+            &[("Old".into(), "New".into())],
+            [HunkKind::LocalCache, HunkKind::Local, HunkKind::RemoteCache],
+        );
+        assert_eq!(
+            vec![
+                Hunk::RenameFolder("Old".into(), "New".into(), HunkKind::LocalCache),
+                Hunk::RenameFolder("Old".into(), "New".into(), HunkKind::Local),
+                Hunk::RenameFolder("Old".into(), "New".into(), HunkKind::RemoteCache),
+            ],
+            patch
+        );
+        assert_eq!(FoldersName::from_iter(["New".into()]), folders);
+    }
+
     #[test]
     fn build_folder_patch() {
         // 0000
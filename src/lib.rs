@@ -4,6 +4,14 @@ pub use mime_msg_builder::{
 };
 
 pub(crate) mod process;
+pub(crate) mod sqlite;
+pub(crate) mod trace;
+
+pub mod proxy;
+pub use proxy::ProxyConfig;
+
+pub mod tls;
+pub use tls::TlsVersion;
 
 pub mod backend;
 pub use backend::*;
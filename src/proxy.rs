@@ -0,0 +1,364 @@
+//! Proxy module.
+//!
+//! This module contains the proxy configuration of the network
+//! backends, as well as the handshakes used to open a TCP stream
+//! through a SOCKS5 or an HTTP CONNECT proxy.
+
+use log::debug;
+use std::{
+    io::{self, Read, Write},
+    net::TcpStream,
+    result,
+};
+use thiserror::Error;
+
+use crate::process;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("cannot connect to proxy {1}:{2}")]
+    ConnectProxyError(#[source] io::Error, String, u16),
+    #[error("cannot write to proxy")]
+    WriteProxyError(#[source] io::Error),
+    #[error("cannot read from proxy")]
+    ReadProxyError(#[source] io::Error),
+    #[error("cannot get proxy password")]
+    GetPasswdError(#[source] process::Error),
+    #[error("cannot get proxy password: password is empty")]
+    GetPasswdEmptyError,
+    #[error("cannot use proxy: host {0} is longer than 255 bytes")]
+    HostTooLongError(String),
+    #[error("cannot use proxy: login or password is longer than 255 bytes")]
+    AuthTooLongError,
+    #[error("cannot use socks5 proxy: unexpected version {0}")]
+    Socks5VersionError(u8),
+    #[error("cannot use socks5 proxy: no acceptable authentication method")]
+    Socks5NoAcceptableMethodError,
+    #[error("cannot authenticate to socks5 proxy")]
+    Socks5AuthError,
+    #[error("cannot connect to {1}:{2} through socks5 proxy: {} (reply code {0})", socks5_reply_message(.0))]
+    Socks5ConnectError(u8, String, u16),
+    #[error("cannot use socks5 proxy: unexpected address type {0}")]
+    Socks5AddressTypeError(u8),
+    #[error("cannot connect to {1}:{2} through http proxy: {0}")]
+    HttpConnectError(String, String, u16),
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+/// Represents the proxy configuration.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ProxyConfig {
+    /// Represents a SOCKS5 proxy (RFC 1928), for example the one
+    /// opened by `ssh -D`. The target host name is resolved by the
+    /// proxy.
+    Socks5 {
+        host: String,
+        port: u16,
+        auth: Option<ProxyAuth>,
+    },
+    /// Represents an HTTP proxy supporting the `CONNECT` method.
+    HttpConnect {
+        host: String,
+        port: u16,
+        auth: Option<ProxyAuth>,
+    },
+}
+
+/// Represents the proxy credentials.
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct ProxyAuth {
+    /// Represents the proxy login.
+    pub login: String,
+    /// Represents the proxy password command.
+    pub passwd_cmd: String,
+}
+
+impl ProxyAuth {
+    /// Executes the password command in order to retrieve the proxy
+    /// password.
+    pub fn passwd(&self) -> Result<String> {
+        let passwd = process::run(&self.passwd_cmd, &[]).map_err(Error::GetPasswdError)?;
+        let passwd = String::from_utf8_lossy(&passwd).to_string();
+        let passwd = passwd.lines().next().ok_or(Error::GetPasswdEmptyError)?;
+        Ok(passwd.to_owned())
+    }
+}
+
+impl ProxyConfig {
+    /// Opens a TCP stream to the given target through the proxy.
+    pub fn connect(&self, target_host: &str, target_port: u16) -> Result<TcpStream> {
+        let (host, port, auth) = match self {
+            Self::Socks5 { host, port, auth } => (host, *port, auth),
+            Self::HttpConnect { host, port, auth } => (host, *port, auth),
+        };
+
+        debug!("connecting to {target_host}:{target_port} through proxy {host}:{port}");
+        let mut stream = TcpStream::connect((host.as_str(), port))
+            .map_err(|err| Error::ConnectProxyError(err, host.clone(), port))?;
+
+        let auth = match auth {
+            Some(auth) => Some((auth.login.clone(), auth.passwd()?)),
+            None => None,
+        };
+
+        match self {
+            Self::Socks5 { .. } => socks5_handshake(&mut stream, target_host, target_port, auth)?,
+            Self::HttpConnect { .. } => {
+                http_connect_handshake(&mut stream, target_host, target_port, auth)?
+            }
+        }
+
+        Ok(stream)
+    }
+}
+
+fn read_exact<S: Read>(stream: &mut S, len: usize) -> Result<Vec<u8>> {
+    let mut buf = vec![0; len];
+    stream.read_exact(&mut buf).map_err(Error::ReadProxyError)?;
+    Ok(buf)
+}
+
+/// Reads a CRLF-terminated line byte by byte, so that nothing after
+/// the line is consumed from the stream.
+pub(crate) fn read_line<S: Read>(stream: &mut S) -> io::Result<String> {
+    let mut line = Vec::new();
+    let mut byte = [0; 1];
+
+    while !line.ends_with(b"\r\n") {
+        stream.read_exact(&mut byte)?;
+        line.push(byte[0]);
+    }
+
+    line.truncate(line.len() - 2);
+    Ok(String::from_utf8_lossy(&line).to_string())
+}
+
+/// Returns the meaning of the given SOCKS5 reply code, as defined by
+/// [RFC 1928].
+///
+/// [RFC 1928]: https://www.rfc-editor.org/rfc/rfc1928#section-6
+fn socks5_reply_message(code: &u8) -> &'static str {
+    match *code {
+        0x01 => "general failure",
+        0x02 => "connection not allowed by ruleset",
+        0x03 => "network unreachable",
+        0x04 => "host unreachable",
+        0x05 => "connection refused",
+        0x06 => "ttl expired",
+        0x07 => "command not supported",
+        0x08 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+fn socks5_handshake<S: Read + Write>(
+    stream: &mut S,
+    host: &str,
+    port: u16,
+    auth: Option<(String, String)>,
+) -> Result<()> {
+    const NO_AUTH: u8 = 0x00;
+    const USER_PASS: u8 = 0x02;
+
+    let method = if auth.is_some() { USER_PASS } else { NO_AUTH };
+    stream
+        .write_all(&[0x05, 0x01, method])
+        .map_err(Error::WriteProxyError)?;
+
+    let reply = read_exact(stream, 2)?;
+    if reply[0] != 0x05 {
+        return Err(Error::Socks5VersionError(reply[0]));
+    }
+    if reply[1] != method {
+        return Err(Error::Socks5NoAcceptableMethodError);
+    }
+
+    if let Some((login, passwd)) = auth {
+        if login.len() > 255 || passwd.len() > 255 {
+            return Err(Error::AuthTooLongError);
+        }
+
+        let mut req = vec![0x01, login.len() as u8];
+        req.extend(login.as_bytes());
+        req.push(passwd.len() as u8);
+        req.extend(passwd.as_bytes());
+        stream.write_all(&req).map_err(Error::WriteProxyError)?;
+
+        if read_exact(stream, 2)?[1] != 0x00 {
+            return Err(Error::Socks5AuthError);
+        }
+    }
+
+    if host.len() > 255 {
+        return Err(Error::HostTooLongError(host.to_owned()));
+    }
+
+    // CONNECT command with a domain name address type
+    let mut req = vec![0x05, 0x01, 0x00, 0x03, host.len() as u8];
+    req.extend(host.as_bytes());
+    req.extend(port.to_be_bytes());
+    stream.write_all(&req).map_err(Error::WriteProxyError)?;
+
+    let reply = read_exact(stream, 4)?;
+    if reply[0] != 0x05 {
+        return Err(Error::Socks5VersionError(reply[0]));
+    }
+    if reply[1] != 0x00 {
+        return Err(Error::Socks5ConnectError(reply[1], host.to_owned(), port));
+    }
+
+    // skips the bound address and port
+    let addr_len = match reply[3] {
+        0x01 => 4,
+        0x03 => read_exact(stream, 1)?[0] as usize,
+        0x04 => 16,
+        atyp => return Err(Error::Socks5AddressTypeError(atyp)),
+    };
+    read_exact(stream, addr_len + 2)?;
+
+    Ok(())
+}
+
+fn http_connect_handshake<S: Read + Write>(
+    stream: &mut S,
+    host: &str,
+    port: u16,
+    auth: Option<(String, String)>,
+) -> Result<()> {
+    let mut req = format!("CONNECT {host}:{port} HTTP/1.1\r\nHost: {host}:{port}\r\n");
+    if let Some((login, passwd)) = auth {
+        let credentials = base64::encode(format!("{login}:{passwd}"));
+        req.push_str(&format!("Proxy-Authorization: Basic {credentials}\r\n"));
+    }
+    req.push_str("\r\n");
+    stream
+        .write_all(req.as_bytes())
+        .map_err(Error::WriteProxyError)?;
+
+    let status = read_line(stream).map_err(Error::ReadProxyError)?;
+    let code = status.split_whitespace().nth(1);
+
+    // skips the response headers
+    while !read_line(stream).map_err(Error::ReadProxyError)?.is_empty() {}
+
+    if code != Some("200") {
+        return Err(Error::HttpConnectError(status, host.to_owned(), port));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod proxy {
+    use std::io::{self, Cursor, Read, Write};
+
+    /// Represents a fake proxy stream replaying the given responses
+    /// and recording the requests.
+    struct Stream {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Stream {
+        fn new(input: &[u8]) -> Self {
+            Self {
+                input: Cursor::new(input.to_vec()),
+                output: Vec::new(),
+            }
+        }
+    }
+
+    impl Read for Stream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Stream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn socks5_handshake() {
+        let mut stream = Stream::new(&[
+            0x05, 0x02, // user/pass method selected
+            0x01, 0x00, // auth succeeded
+            0x05, 0x00, 0x00, 0x01, 127, 0, 0, 1, 0x1f, 0x90, // connected
+            b'*', // server greeting
+        ]);
+
+        super::socks5_handshake(
+            &mut stream,
+            "imap.localhost",
+            993,
+            Some(("user".into(), "pass".into())),
+        )
+        .unwrap();
+
+        let mut expected = vec![0x05, 0x01, 0x02];
+        expected.extend([0x01, 4, b'u', b's', b'e', b'r', 4, b'p', b'a', b's', b's']);
+        expected.extend([0x05, 0x01, 0x00, 0x03, 14]);
+        expected.extend(b"imap.localhost");
+        expected.extend(993u16.to_be_bytes());
+        assert_eq!(expected, stream.output);
+
+        // the rest of the stream is left untouched
+        assert_eq!(14, stream.input.position());
+    }
+
+    #[test]
+    fn socks5_handshake_refused() {
+        let mut stream = Stream::new(&[0x05, 0x00, 0x05, 0x05, 0x00, 0x01]);
+        let err = super::socks5_handshake(&mut stream, "localhost", 143, None).unwrap_err();
+        assert!(matches!(
+            err,
+            super::Error::Socks5ConnectError(0x05, _, 143)
+        ));
+        assert_eq!(
+            "cannot connect to localhost:143 through socks5 proxy: connection refused (reply code 5)",
+            err.to_string()
+        );
+    }
+
+    #[test]
+    fn http_connect_handshake() {
+        let mut stream = Stream::new(b"HTTP/1.1 200 Connection established\r\nVia: proxy\r\n\r\n*");
+
+        super::http_connect_handshake(
+            &mut stream,
+            "imap.localhost",
+            993,
+            Some(("user".into(), "pass".into())),
+        )
+        .unwrap();
+
+        assert_eq!(
+            concat!(
+                "CONNECT imap.localhost:993 HTTP/1.1\r\n",
+                "Host: imap.localhost:993\r\n",
+                "Proxy-Authorization: Basic dXNlcjpwYXNz\r\n",
+                "\r\n",
+            ),
+            String::from_utf8(stream.output).unwrap(),
+        );
+
+        // the rest of the stream is left untouched
+        let mut rest = String::new();
+        stream.input.read_to_string(&mut rest).unwrap();
+        assert_eq!("*", rest);
+    }
+
+    #[test]
+    fn http_connect_handshake_refused() {
+        let mut stream = Stream::new(b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n");
+        let err = super::http_connect_handshake(&mut stream, "localhost", 143, None).unwrap_err();
+        assert!(matches!(err, super::Error::HttpConnectError(_, _, 143)));
+    }
+}
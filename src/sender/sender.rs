@@ -2,10 +2,14 @@
 //!
 //! This module contains the sender interface.
 
+use log::{info, warn};
 use std::result;
 use thiserror::Error;
 
-use crate::{account, email, sendmail, AccountConfig, EmailSender, Sendmail};
+use crate::{
+    account, backend, email, ensure_message_id, sendmail, AccountConfig, Backend, EmailSender,
+    Flag, Flags, Sendmail,
+};
 
 #[cfg(feature = "smtp-sender")]
 use crate::{smtp, Smtp};
@@ -46,3 +50,70 @@ impl<'a> SenderBuilder {
         }
     }
 }
+
+/// Represents the outcome of saving a copy of a sent email (see
+/// [`send_and_save`]). Whatever the variant, the email has been
+/// sent.
+#[derive(Debug)]
+pub enum SentEmailCopy {
+    /// The copy has been saved to the given folder under the given
+    /// id.
+    Saved { folder: String, id: String },
+    /// Saving copies is disabled by the account configuration.
+    Disabled,
+    /// The copy could not be saved to the given folder.
+    Failed {
+        folder: String,
+        error: backend::Error,
+    },
+}
+
+/// Sends the given email, then saves a copy of it with the
+/// [`Flag::Seen`] flag to the sent folder of the account, unless
+/// disabled by [`AccountConfig::email_sending_save_copy`].
+///
+/// Only a sending failure is reported as an error: a saving failure
+/// is reported by [`SentEmailCopy::Failed`], so that callers know
+/// the email went out.
+///
+/// An email without Message-ID gets one generated from the domain of
+/// the account email address (see [`ensure_message_id`]), so that
+/// the sent email and its copy share it.
+pub fn send_and_save(
+    account_config: &AccountConfig,
+    sender: &mut dyn Sender,
+    backend: &dyn Backend,
+    email: &[u8],
+) -> Result<SentEmailCopy> {
+    // The folder is resolved before sending, so that a config error
+    // cannot be mistaken for a sending error.
+    let folder = if account_config.email_sending_save_copy() {
+        Some(account_config.sent_folder_alias()?)
+    } else {
+        None
+    };
+
+    let domain = account_config
+        .email
+        .rsplit_once('@')
+        .map(|(_, domain)| domain)
+        .unwrap_or("localhost");
+    let email = ensure_message_id(email, domain);
+
+    sender.send(&email)?;
+
+    let folder = match folder {
+        Some(folder) => folder,
+        None => return Ok(SentEmailCopy::Disabled),
+    };
+    info!("saving copy of sent email to folder {folder}");
+
+    let flags = Flags::from_iter([Flag::Seen]);
+    match backend.add_email(&folder, &email, &flags) {
+        Ok(id) => Ok(SentEmailCopy::Saved { folder, id }),
+        Err(error) => {
+            warn!("cannot save copy of sent email to folder {folder}: {error}");
+            Ok(SentEmailCopy::Failed { folder, error })
+        }
+    }
+}
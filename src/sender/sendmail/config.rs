@@ -7,4 +7,15 @@
 pub struct SendmailConfig {
     /// Represents the sendmail command.
     pub cmd: String,
+    /// Passes the address of the `From` header as envelope sender
+    /// to the command, using the `-f` option supported by sendmail
+    /// compatible commands (msmtp, postfix, exim…).
+    pub envelope_sender: Option<bool>,
+}
+
+impl SendmailConfig {
+    /// Gets the envelope sender option. Defaults to `false`.
+    pub fn envelope_sender(&self) -> bool {
+        self.envelope_sender.unwrap_or_default()
+    }
 }
@@ -3,7 +3,8 @@
 //! This module contains the representation of the sendmail email
 //! sender.
 
-use mailparse::MailParseError;
+use log::debug;
+use mailparse::{addrparse_header, MailAddr, MailHeaderMap, MailParseError, ParsedMail};
 use std::result;
 use thiserror::Error;
 
@@ -33,6 +34,40 @@ impl<'a> Sendmail<'a> {
             sendmail_config,
         }
     }
+
+    /// Builds the command sending the given email. The envelope
+    /// sender is appended when enabled and when the email has a
+    /// `From` address (see [`SendmailConfig::envelope_sender`]).
+    fn cmd(&self, email: &ParsedMail) -> String {
+        let cmd = self.sendmail_config.cmd.clone();
+
+        if !self.sendmail_config.envelope_sender() {
+            return cmd;
+        }
+
+        match envelope_sender(email) {
+            Some(addr) => format!("{cmd} -f {}", shell_quote(&addr)),
+            None => {
+                debug!("cannot find envelope sender, skipping -f option");
+                cmd
+            }
+        }
+    }
+}
+
+/// Gets the first address of the `From` header.
+fn envelope_sender(email: &ParsedMail) -> Option<String> {
+    let header = email.get_headers().get_first_header("From")?;
+    let addrs = addrparse_header(header).ok()?;
+    match addrs.first()? {
+        MailAddr::Group(group) => group.addrs.first().map(|addr| addr.addr.clone()),
+        MailAddr::Single(single) => Some(single.addr.clone()),
+    }
+}
+
+/// Quotes the given argument for POSIX shells.
+fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', "'\\''"))
 }
 
 impl<'a> Sender for Sendmail<'a> {
@@ -45,7 +80,45 @@ impl<'a> Sender for Sendmail<'a> {
             email = mailparse::parse_mail(&buffer).map_err(Error::ParseEmailError)?;
         };
 
-        process::run(&self.sendmail_config.cmd, email.raw_bytes).map_err(Error::RunCmdError)?;
+        process::run(&self.cmd(&email), email.raw_bytes).map_err(Error::RunCmdError)?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod sendmail {
+    use crate::{AccountConfig, SendmailConfig};
+
+    use super::Sendmail;
+
+    #[test]
+    fn cmd() {
+        let account_config = AccountConfig::default();
+        let email = |from: &str| format!("From: {from}\r\nSubject: test\r\n\r\nHello\r\n");
+        let cmd = |config: &SendmailConfig, from: &str| {
+            let email = email(from);
+            let email = mailparse::parse_mail(email.as_bytes()).unwrap();
+            Sendmail::new(&account_config, config).cmd(&email)
+        };
+
+        let config = SendmailConfig {
+            cmd: "msmtp -t".into(),
+            ..SendmailConfig::default()
+        };
+        assert_eq!("msmtp -t", cmd(&config, "alice@localhost"));
+
+        let config = SendmailConfig {
+            envelope_sender: Some(true),
+            ..config
+        };
+        assert_eq!(
+            "msmtp -t -f 'alice@localhost'",
+            cmd(&config, "Alice <alice@localhost>")
+        );
+        assert_eq!(
+            "msmtp -t -f 'o'\\''neil@localhost'",
+            cmd(&config, "o'neil@localhost")
+        );
+        assert_eq!("msmtp -t", cmd(&config, ""));
+    }
+}
@@ -3,12 +3,12 @@
 //! This module contains the representation of the SMTP email sender
 //! configuration of the user account.
 
-use std::result;
+use std::{fmt, result};
 
 use lettre::transport::smtp::authentication::Credentials as SmtpCredentials;
 use thiserror::Error;
 
-use crate::process;
+use crate::{account, process, TlsVersion};
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -16,42 +16,185 @@ pub enum Error {
     GetPasswdError(#[source] process::Error),
     #[error("cannot get smtp password: password is empty")]
     GetPasswdEmptyError,
+    #[error(transparent)]
+    ConfigError(#[from] account::config::Error),
 }
 
 pub type Result<T> = result::Result<T, Error>;
 
+/// Represents how the connection to the SMTP server is secured.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SmtpSecurity {
+    /// Sends everything in plaintext, including the credentials.
+    None,
+    /// Connects in plaintext, then upgrades the connection with the
+    /// `STARTTLS` command. Usually on port 587.
+    StartTls,
+    /// Connects using TLS from the start (implicit TLS). Usually on
+    /// port 465.
+    Tls,
+}
+
+impl SmtpSecurity {
+    /// Returns a hint about the security mode the server on the given
+    /// port probably expects, shown when the connection fails.
+    pub fn connect_hint(&self, port: u16) -> &'static str {
+        match (self, port) {
+            (Self::Tls, 25 | 587) => {
+                "this port usually expects a plaintext greeting: did you mean STARTTLS?"
+            }
+            (Self::None | Self::StartTls, 465) => {
+                "this port usually expects implicit TLS: did you mean TLS?"
+            }
+            (Self::Tls, _) => {
+                "if the server sent a plaintext greeting, did you mean STARTTLS on port 587?"
+            }
+            (Self::None | Self::StartTls, _) => {
+                "if the server expects TLS from the start, did you mean TLS on port 465?"
+            }
+        }
+    }
+}
+
+impl fmt::Display for SmtpSecurity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::None => write!(f, "no encryption"),
+            Self::StartTls => write!(f, "STARTTLS"),
+            Self::Tls => write!(f, "TLS"),
+        }
+    }
+}
+
+/// Represents a SASL mechanism used to authenticate to the SMTP
+/// server.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SmtpAuthMechanism {
+    /// Sends the login and the password in a single message ([RFC
+    /// 4616]).
+    ///
+    /// [RFC 4616]: https://www.rfc-editor.org/rfc/rfc4616
+    Plain,
+    /// Sends the login then the password, on server request.
+    /// Obsolete, but still required by some providers.
+    Login,
+    /// Proves the knowledge of the password without sending it, by
+    /// answering a challenge of the server ([RFC 2195]).
+    ///
+    /// [RFC 2195]: https://www.rfc-editor.org/rfc/rfc2195
+    CramMd5,
+    /// Sends an OAuth 2.0 access token, taken from the password
+    /// command.
+    XOAuth2,
+}
+
+impl fmt::Display for SmtpAuthMechanism {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Plain => write!(f, "PLAIN"),
+            Self::Login => write!(f, "LOGIN"),
+            Self::CramMd5 => write!(f, "CRAM-MD5"),
+            Self::XOAuth2 => write!(f, "XOAUTH2"),
+        }
+    }
+}
+
 /// Represents the internal sender config.
 #[derive(Debug, Default, Clone, Eq, PartialEq)]
 pub struct SmtpConfig {
-    /// Represents the SMTP server host.
+    /// Represents the SMTP server host. `${VAR}` references are
+    /// replaced with the matching environment variables.
     pub host: String,
     /// Represents the SMTP server port.
     pub port: u16,
-    /// Enables SSL.
+    /// Represents how the connection is secured. Takes precedence
+    /// over the `ssl` and `starttls` options.
+    pub security: Option<SmtpSecurity>,
+    /// Enables SSL. Prefer the `security` option.
     pub ssl: Option<bool>,
-    /// Enables StartTLS.
+    /// Enables StartTLS. Prefer the `security` option.
     pub starttls: Option<bool>,
     /// Trusts any certificate.
     pub insecure: Option<bool>,
+    /// Represents the minimum TLS version accepted from the server.
+    /// TLS 1.3 is not supported as minimum version by the native TLS
+    /// backend.
+    pub min_tls_version: Option<TlsVersion>,
+    /// Represents the names of the cipher suites the TLS connection
+    /// is restricted to. The native TLS backend cannot restrict
+    /// cipher suites, so setting this option makes the connection
+    /// fail instead of silently ignoring it.
+    pub allowed_cipher_suites: Option<Vec<String>>,
     /// Represents the SMTP server login.
     pub login: String,
-    /// Represents the SMTP password command.
+    /// Represents the SASL mechanisms tried in order to authenticate,
+    /// until the server accepts one. Defaults to `PLAIN` then
+    /// `LOGIN`.
+    pub auth_mechanisms: Vec<SmtpAuthMechanism>,
+    /// Represents the SMTP password command. A lone `${VAR}`
+    /// reference is the password itself, otherwise the command is
+    /// run by the shell, which expands its variables.
     pub passwd_cmd: String,
+    /// Requests delivery status notifications ([RFC 3461]) for every
+    /// sent email, on success and on failure. Ignored if the server
+    /// does not advertise the `DSN` extension.
+    ///
+    /// [RFC 3461]: https://www.rfc-editor.org/rfc/rfc3461
+    pub dsn: Option<bool>,
+    /// Represents the maximum size in bytes of an email. Bigger
+    /// emails are rejected before connecting to the server, based on
+    /// the size of the email as sent. The `SIZE` limit advertised by
+    /// the server is checked anyway.
+    pub max_message_size_bytes: Option<usize>,
 }
 
 impl SmtpConfig {
     /// Builds the internal SMTP sender credentials.
     pub fn credentials(&self) -> Result<SmtpCredentials> {
-        let passwd = process::run(&self.passwd_cmd, &[]).map_err(Error::GetPasswdError)?;
-        let passwd = String::from_utf8_lossy(&passwd).to_string();
+        Ok(SmtpCredentials::new(self.login.to_owned(), self.passwd()?))
+    }
+
+    /// Gets the SMTP password, from the first line of the output of
+    /// the password command.
+    pub fn passwd(&self) -> Result<String> {
+        let passwd = if account::config::is_lone_env_ref(&self.passwd_cmd) {
+            account::config::interpolate_env(&self.passwd_cmd)?
+        } else {
+            let passwd = process::run(&self.passwd_cmd, &[]).map_err(Error::GetPasswdError)?;
+            String::from_utf8_lossy(&passwd).to_string()
+        };
         let passwd = passwd
             .lines()
             .next()
             .ok_or_else(|| Error::GetPasswdEmptyError)?;
-        Ok(SmtpCredentials::new(
-            self.login.to_owned(),
-            passwd.to_owned(),
-        ))
+        Ok(passwd.to_owned())
+    }
+
+    /// Gets the SMTP server host, with its environment variables
+    /// interpolated.
+    pub fn host(&self) -> Result<String> {
+        Ok(account::config::interpolate_env(&self.host)?)
+    }
+
+    /// Gets the security mode. When not explicitly set, it is derived
+    /// from the `ssl` and `starttls` options, which defaults to
+    /// implicit TLS.
+    pub fn security(&self) -> SmtpSecurity {
+        match self.security {
+            Some(security) => security,
+            None if !self.ssl() => SmtpSecurity::None,
+            None if self.starttls() => SmtpSecurity::StartTls,
+            None => SmtpSecurity::Tls,
+        }
+    }
+
+    /// Gets the SASL mechanisms to try, in order.
+    pub fn auth_mechanisms(&self) -> Vec<SmtpAuthMechanism> {
+        if self.auth_mechanisms.is_empty() {
+            vec![SmtpAuthMechanism::Plain, SmtpAuthMechanism::Login]
+        } else {
+            self.auth_mechanisms.clone()
+        }
     }
 
     pub fn ssl(&self) -> bool {
@@ -65,4 +208,8 @@ impl SmtpConfig {
     pub fn insecure(&self) -> bool {
         self.insecure.unwrap_or_default()
     }
+
+    pub fn dsn(&self) -> bool {
+        self.dsn.unwrap_or_default()
+    }
 }
@@ -1,33 +1,74 @@
 //! SMTP module.
 //!
 //! This module contains the representation of the SMTP email sender.
+//!
+//! The sender talks to the server through a [`SmtpClient`], which
+//! exposes the extensions advertised in the EHLO response. They are
+//! used to reject emails exceeding the `SIZE` limit ([RFC 1870])
+//! before transmission, to declare 8-bit bodies (`8BITMIME`, [RFC
+//! 6152]) and to request delivery status notifications (`DSN`, [RFC
+//! 3461]).
+//!
+//! Authentication tries the configured SASL mechanisms in order: a
+//! mechanism rejecting the credentials (535) or not supported by the
+//! server (504) falls back to the next one.
+//!
+//! [RFC 1870]: https://www.rfc-editor.org/rfc/rfc1870
+//! [RFC 6152]: https://www.rfc-editor.org/rfc/rfc6152
+//! [RFC 3461]: https://www.rfc-editor.org/rfc/rfc3461
 
 use lettre::{
     self,
-    address::Envelope,
+    address::{Address, Envelope},
     error::Error as LettreError,
     transport::smtp::{
-        client::{Tls, TlsParameters},
-        SmtpTransport,
+        authentication::{Credentials as SmtpCredentials, Mechanism},
+        client::{SmtpConnection, TlsParameters},
+        commands::{Auth, Data, Ehlo, Mail, Rcpt},
+        extension::{ClientId, MailBodyParameter, MailParameter, RcptParameter},
     },
-    Transport,
 };
+use log::{debug, warn};
 use mailparse::{addrparse_header, MailAddr, MailHeaderMap};
-use std::result;
+use std::{result, time::Duration};
 use thiserror::Error;
 
-use crate::{account, email, process, sender, AccountConfig, Sender, SmtpConfig};
+use crate::{
+    account, email, process, sender, tls, AccountConfig, Sender, SmtpAuthMechanism, SmtpConfig,
+    SmtpSecurity,
+};
 
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("cannot build envelope")]
     BuildEnvelopeError(#[source] LettreError),
-    #[error("cannot build smtp transport relay")]
-    BuildTransportRelayError(#[source] lettre::transport::smtp::Error),
+    #[error("cannot connect to smtp server {1}:{2} using {3}: {hint}", hint = .3.connect_hint(*.2))]
+    ConnectError(
+        #[source] lettre::transport::smtp::Error,
+        String,
+        u16,
+        SmtpSecurity,
+    ),
+    #[error("cannot start tls session with smtp server {1}:{2}: if the server does not support STARTTLS, did you mean TLS on port 465?")]
+    StartTlsError(#[source] lettre::transport::smtp::Error, String, u16),
+    #[error("cannot authenticate to smtp server")]
+    AuthenticateError(#[source] lettre::transport::smtp::Error),
+    #[error("cannot authenticate to smtp server: no acceptable mechanism among {}", .0.iter().map(ToString::to_string).collect::<Vec<_>>().join(", "))]
+    NoAcceptableAuthMechanismError(Vec<SmtpAuthMechanism>),
+    #[error("cannot authenticate to smtp server using {0}: too many challenges")]
+    TooManyAuthChallengesError(SmtpAuthMechanism),
+    #[error("cannot get smtp server extensions")]
+    GetExtensionsError(#[source] lettre::transport::smtp::Error),
     #[error("cannot build smtp tls parameters")]
     BuildTlsParamsError(#[source] lettre::transport::smtp::Error),
+    #[error("cannot configure tls connection to smtp server")]
+    TlsConfigurationError(#[source] tls::Error),
     #[error("cannot parse email before sending")]
     ParseEmailError(#[source] mailparse::MailParseError),
+    #[error("cannot send email of {size} bytes: limit is {max} bytes")]
+    MessageTooLargeError { size: usize, max: usize },
+    #[error("cannot send email with non-ascii addresses: server does not support SMTPUTF8")]
+    SmtpUtf8NotSupportedError,
     #[error("cannot send email")]
     SendError(#[source] lettre::transport::smtp::Error),
     #[error("cannot execute pre-send hook")]
@@ -43,10 +84,349 @@ pub enum Error {
 
 pub type Result<T> = result::Result<T, Error>;
 
+/// Represents the SMTP extensions advertised by the server in its
+/// EHLO response. Only the extensions used by the sender are kept.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SmtpExtensions {
+    /// The server supports the `SIZE` extension.
+    pub size: bool,
+    /// Represents the maximum size in bytes of an email, if the
+    /// server declares one.
+    pub max_size: Option<usize>,
+    /// The server supports the `8BITMIME` extension.
+    pub eight_bit_mime: bool,
+    /// The server supports the `SMTPUTF8` extension.
+    pub smtp_utf8: bool,
+    /// The server supports the `DSN` extension.
+    pub dsn: bool,
+    /// Represents the SASL mechanisms advertised by the `AUTH`
+    /// extension, in upper case.
+    pub auth_mechanisms: Vec<String>,
+}
+
+impl SmtpExtensions {
+    /// Parses the lines of an EHLO response. The first line, which
+    /// contains the server greeting, is ignored.
+    pub fn from_ehlo<S: AsRef<str>>(lines: &[S]) -> Self {
+        let mut extensions = Self::default();
+
+        for line in lines.iter().skip(1) {
+            let mut words = line.as_ref().split_whitespace();
+            let keyword = words.next().unwrap_or_default().to_uppercase();
+
+            match keyword.as_str() {
+                "SIZE" => {
+                    extensions.size = true;
+                    // a limit of 0 means that there is no limit
+                    extensions.max_size = words
+                        .next()
+                        .and_then(|max| max.parse().ok())
+                        .filter(|max| *max > 0);
+                }
+                "8BITMIME" => extensions.eight_bit_mime = true,
+                "SMTPUTF8" => extensions.smtp_utf8 = true,
+                "DSN" => extensions.dsn = true,
+                "AUTH" => extensions
+                    .auth_mechanisms
+                    .extend(words.map(|mechanism| mechanism.to_uppercase())),
+                _ => (),
+            }
+        }
+
+        extensions
+    }
+
+    /// Returns `true` if the server advertises the given SASL
+    /// mechanism.
+    pub fn supports_auth_mechanism(&self, mechanism: SmtpAuthMechanism) -> bool {
+        let mechanism = mechanism.to_string();
+        self.auth_mechanisms.iter().any(|m| *m == mechanism)
+    }
+}
+
+/// Represents why an authentication attempt failed.
+#[derive(Debug)]
+enum AuthFailure {
+    /// The server does not support the mechanism (504), the next one
+    /// can be tried.
+    Unsupported(Error),
+    /// Any other failure, rejected credentials (535) included, which
+    /// stops the authentication so that a failed login is not
+    /// repeated with another mechanism.
+    Failed(Error),
+}
+
+impl From<lettre::transport::smtp::Error> for AuthFailure {
+    fn from(err: lettre::transport::smtp::Error) -> Self {
+        match err.status().map(|code| code.to_string()).as_deref() {
+            Some("504") => Self::Unsupported(Error::AuthenticateError(err)),
+            _ => Self::Failed(Error::AuthenticateError(err)),
+        }
+    }
+}
+
+/// Tries in order the given mechanisms advertised by the server
+/// until one succeeds, and returns it. Mechanisms not supported by
+/// the server fall back to the next one, other failures stop the
+/// authentication and are returned as is, like the last failure when
+/// no mechanism is left.
+fn negotiate_auth(
+    mechanisms: &[SmtpAuthMechanism],
+    extensions: &SmtpExtensions,
+    mut auth: impl FnMut(SmtpAuthMechanism) -> result::Result<(), AuthFailure>,
+) -> Result<SmtpAuthMechanism> {
+    let mut last_err = None;
+
+    for mechanism in mechanisms {
+        if !extensions.supports_auth_mechanism(*mechanism) {
+            debug!("smtp server does not advertise {mechanism} authentication, skipping it");
+            continue;
+        }
+
+        match auth(*mechanism) {
+            Ok(()) => {
+                debug!("authenticated to smtp server using {mechanism}");
+                return Ok(*mechanism);
+            }
+            Err(AuthFailure::Unsupported(err)) => {
+                debug!("smtp server does not support {mechanism} authentication, skipping it");
+                last_err = Some(err);
+            }
+            Err(AuthFailure::Failed(err)) => return Err(err),
+        }
+    }
+
+    Err(last_err.unwrap_or_else(|| Error::NoAcceptableAuthMechanismError(mechanisms.to_vec())))
+}
+
+/// Computes the HMAC-MD5 of the given data ([RFC 2104]).
+///
+/// [RFC 2104]: https://www.rfc-editor.org/rfc/rfc2104
+fn hmac_md5(key: &[u8], data: &[u8]) -> md5::Digest {
+    const BLOCK_SIZE: usize = 64;
+
+    let mut key = if key.len() > BLOCK_SIZE {
+        md5::compute(key).to_vec()
+    } else {
+        key.to_vec()
+    };
+    key.resize(BLOCK_SIZE, 0);
+
+    let mut inner = md5::Context::new();
+    inner.consume(key.iter().map(|b| b ^ 0x36).collect::<Vec<_>>());
+    inner.consume(data);
+
+    let mut outer = md5::Context::new();
+    outer.consume(key.iter().map(|b| b ^ 0x5c).collect::<Vec<_>>());
+    outer.consume(inner.compute().0);
+    outer.compute()
+}
+
+/// Builds the answer to the given base64-encoded CRAM-MD5 challenge
+/// ([RFC 2195]), followed by CRLF.
+///
+/// [RFC 2195]: https://www.rfc-editor.org/rfc/rfc2195
+fn cram_md5_response(login: &str, passwd: &str, challenge: &str) -> String {
+    let challenge = base64::decode(challenge).unwrap_or_default();
+    let digest = hmac_md5(passwd.as_bytes(), &challenge);
+    format!("{}\r\n", base64::encode(format!("{login} {digest:x}")))
+}
+
+/// Represents the client used by the [`Smtp`] sender to talk to the
+/// server. The sender decides which parameters to send based on the
+/// extensions, the client only transmits the commands.
+pub trait SmtpClient {
+    /// Returns the extensions advertised by the server.
+    fn extensions(&self) -> &SmtpExtensions;
+
+    /// Returns `true` if the client can still be used to send emails.
+    fn is_connected(&mut self) -> bool;
+
+    /// Sends the `MAIL FROM` command.
+    fn mail(&mut self, from: Option<Address>, params: Vec<MailParameter>) -> Result<()>;
+
+    /// Sends the `RCPT TO` command.
+    fn rcpt(&mut self, to: Address, params: Vec<RcptParameter>) -> Result<()>;
+
+    /// Sends the `DATA` command followed by the email.
+    fn data(&mut self, email: &[u8]) -> Result<()>;
+}
+
+/// Represents the SMTP client based on a [`lettre`] connection.
+pub struct LettreSmtpClient {
+    conn: SmtpConnection,
+    extensions: SmtpExtensions,
+}
+
+impl LettreSmtpClient {
+    const TIMEOUT: Duration = Duration::from_secs(60);
+    const MAX_AUTH_CHALLENGES: usize = 10;
+
+    /// Builds the TLS parameters matching the security mode of the
+    /// given config. The first parameters secure the connection from
+    /// the start, the second ones upgrade it with `STARTTLS`.
+    fn tls_params(config: &SmtpConfig) -> Result<(Option<TlsParameters>, Option<TlsParameters>)> {
+        let host = config.host()?;
+        let tls = || {
+            tls::check_cipher_suites(config.allowed_cipher_suites.as_ref())
+                .map_err(Error::TlsConfigurationError)?;
+
+            let mut builder = TlsParameters::builder(host.clone())
+                .dangerous_accept_invalid_hostnames(config.insecure())
+                .dangerous_accept_invalid_certs(config.insecure());
+            if let Some(version) = config.min_tls_version {
+                let version = version.to_lettre().map_err(Error::TlsConfigurationError)?;
+                builder = builder.set_min_tls_version(version);
+            }
+
+            builder.build().map_err(Error::BuildTlsParamsError)
+        };
+
+        match config.security() {
+            SmtpSecurity::None => Ok((None, None)),
+            SmtpSecurity::StartTls => Ok((None, Some(tls()?))),
+            SmtpSecurity::Tls => Ok((Some(tls()?), None)),
+        }
+    }
+
+    pub fn connect(config: &SmtpConfig) -> Result<Self> {
+        let hello_name = ClientId::default();
+        let security = config.security();
+        debug!("connecting to smtp server using {security}");
+
+        let host = config.host()?;
+        let (wrapper_tls, starttls) = Self::tls_params(config)?;
+        let mut conn = SmtpConnection::connect(
+            (host.as_str(), config.port),
+            Some(Self::TIMEOUT),
+            &hello_name,
+            wrapper_tls.as_ref(),
+            None,
+        )
+        .map_err(|err| Error::ConnectError(err, host.clone(), config.port, security))?;
+
+        if let Some(tls) = starttls {
+            conn.starttls(&tls, &hello_name)
+                .map_err(|err| Error::StartTlsError(err, host.clone(), config.port))?;
+        }
+
+        // lettre does not expose the raw EHLO response, so it needs
+        // to be sent again in order to read the extensions
+        let ehlo = conn
+            .command(Ehlo::new(hello_name))
+            .map_err(Error::GetExtensionsError)?;
+        let lines: Vec<&str> = ehlo.message().collect();
+        let extensions = SmtpExtensions::from_ehlo(&lines);
+        debug!("smtp server extensions: {extensions:?}");
+
+        let passwd = config.passwd()?;
+        negotiate_auth(&config.auth_mechanisms(), &extensions, |mechanism| {
+            Self::auth(&mut conn, mechanism, &config.login, &passwd)
+        })?;
+
+        Ok(Self { conn, extensions })
+    }
+
+    /// Runs the SASL exchange of the given mechanism. Unlike
+    /// [`SmtpConnection::auth`], a failed exchange keeps the
+    /// connection open, so that the next mechanism can be tried.
+    fn auth(
+        conn: &mut SmtpConnection,
+        mechanism: SmtpAuthMechanism,
+        login: &str,
+        passwd: &str,
+    ) -> result::Result<(), AuthFailure> {
+        let credentials = SmtpCredentials::new(login.to_owned(), passwd.to_owned());
+        let lettre_mechanism = match mechanism {
+            SmtpAuthMechanism::Plain => Some(Mechanism::Plain),
+            SmtpAuthMechanism::Login => Some(Mechanism::Login),
+            SmtpAuthMechanism::XOAuth2 => Some(Mechanism::Xoauth2),
+            // lettre does not support CRAM-MD5
+            SmtpAuthMechanism::CramMd5 => None,
+        };
+
+        let mut res = match lettre_mechanism {
+            Some(lettre_mechanism) => {
+                conn.command(Auth::new(lettre_mechanism, credentials.clone(), None)?)?
+            }
+            None => conn.command(format!("AUTH {mechanism}\r\n"))?,
+        };
+
+        for _ in 0..Self::MAX_AUTH_CHALLENGES {
+            if !res.has_code(334) {
+                return Ok(());
+            }
+
+            let cmd = match mechanism {
+                SmtpAuthMechanism::Login => {
+                    Auth::new_from_response(Mechanism::Login, credentials.clone(), &res)?
+                        .to_string()
+                }
+                SmtpAuthMechanism::CramMd5 => {
+                    cram_md5_response(login, passwd, res.first_word().unwrap_or_default())
+                }
+                // a challenge following an initial response contains
+                // the error details, an empty answer gets the final
+                // reply
+                SmtpAuthMechanism::Plain | SmtpAuthMechanism::XOAuth2 => String::from("\r\n"),
+            };
+            res = conn.command(cmd)?;
+        }
+
+        // cancels the exchange, which the server answers with an error
+        let _ = conn.command("*\r\n");
+        Err(AuthFailure::Failed(Error::TooManyAuthChallengesError(
+            mechanism,
+        )))
+    }
+
+    /// Aborts the connection if the given result is an error, since
+    /// the session is left in an unknown state.
+    fn abort_on_error<T>(
+        &mut self,
+        res: result::Result<T, lettre::transport::smtp::Error>,
+    ) -> Result<()> {
+        match res {
+            Ok(_) => Ok(()),
+            Err(err) => {
+                self.conn.abort();
+                Err(Error::SendError(err))
+            }
+        }
+    }
+}
+
+impl SmtpClient for LettreSmtpClient {
+    fn extensions(&self) -> &SmtpExtensions {
+        &self.extensions
+    }
+
+    fn is_connected(&mut self) -> bool {
+        !self.conn.has_broken() && self.conn.test_connected()
+    }
+
+    fn mail(&mut self, from: Option<Address>, params: Vec<MailParameter>) -> Result<()> {
+        let res = self.conn.command(Mail::new(from, params));
+        self.abort_on_error(res)
+    }
+
+    fn rcpt(&mut self, to: Address, params: Vec<RcptParameter>) -> Result<()> {
+        let res = self.conn.command(Rcpt::new(to, params));
+        self.abort_on_error(res)
+    }
+
+    fn data(&mut self, email: &[u8]) -> Result<()> {
+        let res = self.conn.command(Data);
+        self.abort_on_error(res)?;
+        let res = self.conn.message(email);
+        self.abort_on_error(res)
+    }
+}
+
 pub struct Smtp<'a> {
     account_config: &'a AccountConfig,
     smtp_config: &'a SmtpConfig,
-    transport: Option<SmtpTransport>,
+    client: Option<Box<dyn SmtpClient>>,
 }
 
 impl<'a> Smtp<'a> {
@@ -54,45 +434,101 @@ impl<'a> Smtp<'a> {
         Self {
             account_config,
             smtp_config,
-            transport: None,
-        }
-    }
-
-    fn transport(&mut self) -> Result<&SmtpTransport> {
-        if let Some(ref transport) = self.transport {
-            Ok(transport)
-        } else {
-            let builder = if self.smtp_config.ssl() {
-                let tls = TlsParameters::builder(self.smtp_config.host.to_owned())
-                    .dangerous_accept_invalid_hostnames(self.smtp_config.insecure())
-                    .dangerous_accept_invalid_certs(self.smtp_config.insecure())
-                    .build()
-                    .map_err(Error::BuildTlsParamsError)?;
-
-                if self.smtp_config.starttls() {
-                    SmtpTransport::starttls_relay(&self.smtp_config.host)
-                        .map_err(Error::BuildTransportRelayError)?
-                        .tls(Tls::Required(tls))
-                } else {
-                    SmtpTransport::relay(&self.smtp_config.host)
-                        .map_err(Error::BuildTransportRelayError)?
-                        .tls(Tls::Wrapper(tls))
-                }
-            } else {
-                SmtpTransport::relay(&self.smtp_config.host)
-                    .map_err(Error::BuildTransportRelayError)?
-                    .tls(Tls::None)
-            };
+            client: None,
+        }
+    }
+
+    /// Builds a SMTP sender using the given client instead of
+    /// connecting to the server from the config.
+    pub fn with_client(
+        account_config: &'a AccountConfig,
+        smtp_config: &'a SmtpConfig,
+        client: Box<dyn SmtpClient>,
+    ) -> Self {
+        Self {
+            account_config,
+            smtp_config,
+            client: Some(client),
+        }
+    }
+
+    fn client(&mut self) -> Result<&mut dyn SmtpClient> {
+        let connected = match self.client.as_mut() {
+            Some(client) => client.is_connected(),
+            None => false,
+        };
 
-            self.transport = Some(
-                builder
-                    .port(self.smtp_config.port)
-                    .credentials(self.smtp_config.credentials()?)
-                    .build(),
-            );
+        if !connected {
+            let client = LettreSmtpClient::connect(self.smtp_config)?;
+            self.client = Some(Box::new(client));
+        }
+
+        Ok(self.client.as_deref_mut().unwrap())
+    }
+
+    /// Sends the given email using the given client. The `MAIL FROM`
+    /// and `RCPT TO` parameters depend on the extensions advertised
+    /// by the server.
+    fn send_with_client(
+        client: &mut dyn SmtpClient,
+        envelope: &Envelope,
+        email: &[u8],
+        dsn: bool,
+    ) -> Result<()> {
+        let extensions = client.extensions();
+        let size = email.len();
+        let mut mail_params = Vec::new();
+        let mut rcpt_params = Vec::new();
+
+        if let Some(max) = extensions.max_size {
+            if size > max {
+                return Err(Error::MessageTooLargeError { size, max });
+            }
+        }
+
+        if extensions.size {
+            mail_params.push(MailParameter::Size(size));
+        }
+
+        // only the body is declared, non-ascii headers are covered
+        // by SMTPUTF8
+        let (_, body) = email::split_raw_email(email);
+        if !body.is_ascii() && extensions.eight_bit_mime {
+            mail_params.push(MailParameter::Body(MailBodyParameter::EightBitMime));
+        }
+
+        let non_ascii_addresses = envelope
+            .from()
+            .into_iter()
+            .chain(envelope.to())
+            .any(|addr| !addr.user().is_ascii() || !addr.domain().is_ascii());
+        if non_ascii_addresses {
+            if !extensions.smtp_utf8 {
+                return Err(Error::SmtpUtf8NotSupportedError);
+            }
+            mail_params.push(MailParameter::SmtpUtfEight);
+        }
+
+        if dsn && extensions.dsn {
+            mail_params.push(MailParameter::Other {
+                keyword: "RET".into(),
+                value: Some("HDRS".into()),
+            });
+            rcpt_params.push(RcptParameter::Other {
+                keyword: "NOTIFY".into(),
+                value: Some("SUCCESS,FAILURE".into()),
+            });
+        } else if dsn {
+            warn!("smtp server does not support DSN, skipping delivery status notifications");
+        }
 
-            Ok(self.transport.as_ref().unwrap())
+        client.mail(envelope.from().cloned(), mail_params)?;
+        for to in envelope.to() {
+            client.rcpt(to.clone(), rcpt_params.clone())?;
         }
+        client.data(email)?;
+
+        Ok(())
     }
 }
 
@@ -142,10 +578,400 @@ impl<'a> Sender for Smtp<'a> {
         )
         .map_err(Error::BuildEnvelopeError)?;
 
-        self.transport()?
-            .send_raw(&envelope, email.raw_bytes)
-            .map_err(Error::SendError)?;
+        // the configured limit is checked before connecting, so that
+        // a large email is not transferred for nothing
+        if let Some(max) = self.smtp_config.max_message_size_bytes {
+            let size = email.raw_bytes.len();
+            if size > max {
+                Err(Error::MessageTooLargeError { size, max })?;
+            }
+        }
+
+        let dsn = self.smtp_config.dsn();
+        Self::send_with_client(self.client()?, &envelope, email.raw_bytes, dsn)?;
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod smtp {
+    use lettre::{
+        address::{Address, Envelope},
+        transport::smtp::{
+            commands::{Mail, Rcpt},
+            extension::{MailParameter, RcptParameter},
+        },
+    };
+    use std::{cell::RefCell, rc::Rc};
+
+    use crate::{
+        tls, AccountConfig, Sender, SmtpAuthMechanism, SmtpConfig, SmtpSecurity, TlsVersion,
+    };
+
+    use super::{AuthFailure, Error, LettreSmtpClient, Result, Smtp, SmtpClient, SmtpExtensions};
+
+    /// Represents a client recording the commands it receives.
+    struct MockClient {
+        extensions: SmtpExtensions,
+        commands: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl SmtpClient for MockClient {
+        fn extensions(&self) -> &SmtpExtensions {
+            &self.extensions
+        }
+
+        fn is_connected(&mut self) -> bool {
+            true
+        }
+
+        fn mail(&mut self, from: Option<Address>, params: Vec<MailParameter>) -> Result<()> {
+            let cmd = Mail::new(from, params).to_string();
+            self.commands.borrow_mut().push(cmd.trim_end().to_owned());
+            Ok(())
+        }
+
+        fn rcpt(&mut self, to: Address, params: Vec<RcptParameter>) -> Result<()> {
+            let cmd = Rcpt::new(to, params).to_string();
+            self.commands.borrow_mut().push(cmd.trim_end().to_owned());
+            Ok(())
+        }
+
+        fn data(&mut self, email: &[u8]) -> Result<()> {
+            let cmd = format!("DATA {}", email.len());
+            self.commands.borrow_mut().push(cmd);
+            Ok(())
+        }
+    }
+
+    const EMAIL: &str =
+        "From: alice@localhost\r\nTo: bob@localhost\r\nSubject: hello\r\n\r\nhéllo\r\n";
+
+    fn send(extensions: SmtpExtensions, dsn: bool) -> (Result<()>, Vec<String>) {
+        let smtp_config = SmtpConfig {
+            dsn: Some(dsn),
+            ..SmtpConfig::default()
+        };
+        let (res, commands) = send_with_config(extensions, smtp_config);
+        let res = res.map_err(|err| match err {
+            crate::sender::Error::SmtpError(err) => err,
+            err => panic!("unexpected error: {err}"),
+        });
+        (res, commands)
+    }
+
+    fn send_with_config(
+        extensions: SmtpExtensions,
+        smtp_config: SmtpConfig,
+    ) -> (crate::sender::Result<()>, Vec<String>) {
+        let account_config = AccountConfig::default();
+        let commands = Rc::new(RefCell::new(Vec::new()));
+        let client = MockClient {
+            extensions,
+            commands: commands.clone(),
+        };
+
+        let mut smtp = Smtp::with_client(&account_config, &smtp_config, Box::new(client));
+        let res = smtp.send(EMAIL.as_bytes());
+
+        let commands = commands.borrow().clone();
+        (res, commands)
+    }
+
+    #[test]
+    fn parse_extensions() {
+        let ehlo = [
+            "localhost greets you",
+            "SIZE 35882577",
+            "8BITMIME",
+            "dsn",
+            "AUTH PLAIN LOGIN",
+        ];
+
+        assert_eq!(
+            SmtpExtensions::from_ehlo(&ehlo),
+            SmtpExtensions {
+                size: true,
+                max_size: Some(35882577),
+                eight_bit_mime: true,
+                smtp_utf8: false,
+                dsn: true,
+                auth_mechanisms: vec![String::from("PLAIN"), String::from("LOGIN")],
+            }
+        );
+
+        let ehlo = ["localhost", "SIZE 0"];
+        assert_eq!(SmtpExtensions::from_ehlo(&ehlo).max_size, None);
+    }
+
+    #[test]
+    fn reject_too_large_email() {
+        let extensions = SmtpExtensions {
+            size: true,
+            max_size: Some(10),
+            eight_bit_mime: true,
+            ..SmtpExtensions::default()
+        };
+
+        let (res, commands) = send(extensions, false);
+
+        assert!(matches!(
+            res,
+            Err(Error::MessageTooLargeError { size, max: 10 }) if size == EMAIL.len(),
+        ));
+        assert!(commands.is_empty());
+    }
+
+    #[test]
+    fn reject_email_above_configured_limit() {
+        let config = |max| SmtpConfig {
+            max_message_size_bytes: Some(max),
+            ..SmtpConfig::default()
+        };
+        let extensions = SmtpExtensions {
+            eight_bit_mime: true,
+            ..SmtpExtensions::default()
+        };
+
+        let (res, commands) = send_with_config(extensions.clone(), config(10));
+        assert!(matches!(
+            res,
+            Err(crate::sender::Error::SmtpError(Error::MessageTooLargeError { size, max: 10 }))
+                if size == EMAIL.len(),
+        ));
+        assert!(commands.is_empty());
+
+        let (res, commands) = send_with_config(extensions, config(1024));
+        assert!(res.is_ok());
+        assert_eq!(3, commands.len());
+    }
+
+    #[test]
+    fn send_with_extensions() {
+        let extensions = SmtpExtensions {
+            size: true,
+            max_size: Some(1024),
+            eight_bit_mime: true,
+            dsn: true,
+            ..SmtpExtensions::default()
+        };
+
+        let (res, commands) = send(extensions, true);
+
+        assert!(res.is_ok());
+        assert_eq!(
+            commands,
+            vec![
+                format!(
+                    "MAIL FROM:<alice@localhost> SIZE={} BODY=8BITMIME RET=HDRS",
+                    EMAIL.len()
+                ),
+                String::from("RCPT TO:<bob@localhost> NOTIFY=SUCCESS,FAILURE"),
+                format!("DATA {}", EMAIL.len()),
+            ]
+        );
+    }
+
+    #[test]
+    fn send_8bit_email_without_8bitmime() {
+        let (res, commands) = send(SmtpExtensions::default(), true);
+
+        assert!(res.is_ok());
+        assert_eq!(
+            commands,
+            vec![
+                String::from("MAIL FROM:<alice@localhost>"),
+                String::from("RCPT TO:<bob@localhost>"),
+                format!("DATA {}", EMAIL.len()),
+            ]
+        );
+    }
+
+    #[test]
+    fn declare_8bit_body_only() {
+        let extensions = SmtpExtensions {
+            eight_bit_mime: true,
+            ..SmtpExtensions::default()
+        };
+        let client = |commands: &Rc<RefCell<Vec<String>>>| MockClient {
+            extensions: extensions.clone(),
+            commands: commands.clone(),
+        };
+        let envelope = Envelope::new(
+            Some("alice@localhost".parse().unwrap()),
+            vec!["bob@localhost".parse().unwrap()],
+        )
+        .unwrap();
+
+        let commands = Rc::new(RefCell::new(Vec::new()));
+        let email = "Subject: héllo\r\n\r\nhello\r\n".as_bytes();
+        Smtp::send_with_client(&mut client(&commands), &envelope, email, false).unwrap();
+        assert_eq!("MAIL FROM:<alice@localhost>", commands.borrow()[0]);
+
+        let commands = Rc::new(RefCell::new(Vec::new()));
+        let email = "Subject: hello\r\n\r\nhéllo\r\n".as_bytes();
+        Smtp::send_with_client(&mut client(&commands), &envelope, email, false).unwrap();
+        assert_eq!(
+            "MAIL FROM:<alice@localhost> BODY=8BITMIME",
+            commands.borrow()[0]
+        );
+    }
+
+    #[test]
+    fn security_to_transport() {
+        let config = |security, ssl, starttls| SmtpConfig {
+            host: "localhost".into(),
+            security,
+            ssl,
+            starttls,
+            ..SmtpConfig::default()
+        };
+
+        // the explicit security mode takes precedence over the legacy
+        // ssl and starttls options
+        let configs = [
+            (config(None, None, None), SmtpSecurity::Tls),
+            (config(None, Some(true), Some(true)), SmtpSecurity::StartTls),
+            (config(None, Some(false), Some(true)), SmtpSecurity::None),
+            (
+                config(Some(SmtpSecurity::StartTls), Some(true), Some(false)),
+                SmtpSecurity::StartTls,
+            ),
+            (
+                config(Some(SmtpSecurity::None), Some(true), None),
+                SmtpSecurity::None,
+            ),
+        ];
+
+        for (config, security) in configs {
+            assert_eq!(security, config.security(), "{config:?}");
+
+            let (wrapper_tls, starttls) = LettreSmtpClient::tls_params(&config).unwrap();
+            assert_eq!(security == SmtpSecurity::Tls, wrapper_tls.is_some());
+            assert_eq!(security == SmtpSecurity::StartTls, starttls.is_some());
+        }
+    }
+
+    #[test]
+    fn tls_options() {
+        let config = |min_tls_version, allowed_cipher_suites| SmtpConfig {
+            host: "localhost".into(),
+            security: Some(SmtpSecurity::Tls),
+            min_tls_version,
+            allowed_cipher_suites,
+            ..SmtpConfig::default()
+        };
+
+        let (wrapper_tls, _) =
+            LettreSmtpClient::tls_params(&config(Some(TlsVersion::Tlsv12), None)).unwrap();
+        assert!(wrapper_tls.is_some());
+
+        // invalid combinations are rejected before connecting
+        assert!(matches!(
+            LettreSmtpClient::tls_params(&config(Some(TlsVersion::Tlsv13), None)),
+            Err(Error::TlsConfigurationError(
+                tls::Error::UnsupportedTlsVersionError(TlsVersion::Tlsv13)
+            ))
+        ));
+        assert!(matches!(
+            LettreSmtpClient::tls_params(&config(
+                None,
+                Some(vec!["TLS_AES_128_GCM_SHA256".into()])
+            )),
+            Err(Error::TlsConfigurationError(
+                tls::Error::UnsupportedCipherSuitesError(_)
+            ))
+        ));
+
+        // plaintext connections ignore tls options
+        let mut plaintext = config(Some(TlsVersion::Tlsv13), None);
+        plaintext.security = Some(SmtpSecurity::None);
+        assert!(LettreSmtpClient::tls_params(&plaintext).is_ok());
+    }
+
+    #[test]
+    fn connect_hint() {
+        assert!(SmtpSecurity::Tls.connect_hint(587).contains("STARTTLS"));
+        assert!(SmtpSecurity::StartTls
+            .connect_hint(465)
+            .contains("implicit TLS"));
+        assert!(SmtpSecurity::None
+            .connect_hint(25)
+            .contains("TLS on port 465"));
+    }
+
+    #[test]
+    fn negotiate_auth() {
+        use SmtpAuthMechanism::*;
+
+        let extensions =
+            SmtpExtensions::from_ehlo(&["localhost", "AUTH XOAUTH2 CRAM-MD5 LOGIN PLAIN"]);
+        let unsupported = || AuthFailure::Unsupported(Error::TooManyAuthChallengesError(Plain));
+        let rejected = || AuthFailure::Failed(Error::TooManyAuthChallengesError(CramMd5));
+
+        // mechanisms not advertised are not tried
+        let config = SmtpConfig {
+            auth_mechanisms: vec![XOAuth2, CramMd5, Login],
+            ..SmtpConfig::default()
+        };
+        let extensions_without_cram_md5 =
+            SmtpExtensions::from_ehlo(&["localhost", "AUTH XOAUTH2 LOGIN"]);
+        let mut tried = Vec::new();
+        let mechanism = super::negotiate_auth(
+            &config.auth_mechanisms(),
+            &extensions_without_cram_md5,
+            |mechanism| {
+                tried.push(mechanism);
+                match mechanism {
+                    XOAuth2 => Err(unsupported()),
+                    _ => Ok(()),
+                }
+            },
+        );
+        assert_eq!(Login, mechanism.unwrap());
+        assert_eq!(vec![XOAuth2, Login], tried);
+
+        // a rejection stops the authentication and is returned
+        let mut tried = Vec::new();
+        let res = super::negotiate_auth(&config.auth_mechanisms(), &extensions, |mechanism| {
+            tried.push(mechanism);
+            match mechanism {
+                XOAuth2 => Err(unsupported()),
+                _ => Err(rejected()),
+            }
+        });
+        assert_eq!(vec![XOAuth2, CramMd5], tried);
+        assert!(matches!(
+            res,
+            Err(Error::TooManyAuthChallengesError(CramMd5))
+        ));
+
+        // the last failure is returned when no mechanism is left
+        let mechanisms = SmtpConfig::default().auth_mechanisms();
+        assert_eq!(vec![Plain, Login], mechanisms);
+        let res = super::negotiate_auth(&mechanisms, &extensions, |_| Err(unsupported()));
+        assert!(matches!(res, Err(Error::TooManyAuthChallengesError(Plain))));
+
+        // no mechanism advertised
+        let res = super::negotiate_auth(&mechanisms, &SmtpExtensions::default(), |_| Ok(()));
+        assert!(matches!(
+            res,
+            Err(Error::NoAcceptableAuthMechanismError(mechanisms)) if mechanisms == [Plain, Login]
+        ));
+    }
+
+    #[test]
+    fn cram_md5_response() {
+        // example from RFC 2195
+        let challenge = base64::encode("<1896.697170952@postoffice.reston.mci.net>");
+        let res = super::cram_md5_response("tim", "tanstaaftanstaaf", &challenge);
+        assert_eq!(
+            format!(
+                "{}\r\n",
+                base64::encode("tim b913a602c7eda7a495b4e6e7334d3890")
+            ),
+            res
+        );
+    }
+}
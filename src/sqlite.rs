@@ -0,0 +1,24 @@
+//! SQLite module.
+//!
+//! This module contains the maintenance helpers shared by the
+//! envelopes and folders synchronization caches, which live in the
+//! same database file.
+
+use rusqlite::Connection;
+
+/// Rebuilds the database file, reclaiming the space left by deleted
+/// rows.
+pub(crate) fn vacuum(conn: &mut Connection) -> rusqlite::Result<()> {
+    conn.execute_batch("VACUUM")
+}
+
+/// Checks the integrity of the database and returns the list of
+/// problems found. An empty list means the database is sane.
+pub(crate) fn integrity_check(conn: &mut Connection) -> rusqlite::Result<Vec<String>> {
+    let mut stmt = conn.prepare("PRAGMA integrity_check")?;
+    let problems: Vec<String> = stmt
+        .query_map((), |row| row.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+
+    Ok(problems.into_iter().filter(|p| p != "ok").collect())
+}
@@ -0,0 +1,135 @@
+//! TLS module.
+//!
+//! This module contains the TLS options shared by the IMAP backend
+//! and the SMTP sender, like the minimum protocol version.
+
+use std::{fmt, result, str::FromStr};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("cannot parse tls version {0}: expected 1.0, 1.1, 1.2 or 1.3")]
+    ParseTlsVersionError(String),
+    #[error("cannot use {0} as minimum version: not supported by the native tls backend")]
+    UnsupportedTlsVersionError(TlsVersion),
+    #[error("cannot restrict tls cipher suites to an empty list")]
+    EmptyCipherSuitesError,
+    #[error("cannot restrict tls cipher suites to {0}: not supported by the native tls backend")]
+    UnsupportedCipherSuitesError(String),
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+/// Represents a TLS protocol version.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum TlsVersion {
+    /// Represents TLS 1.0, deprecated by [RFC 8996].
+    ///
+    /// [RFC 8996]: https://www.rfc-editor.org/rfc/rfc8996
+    Tlsv10,
+    /// Represents TLS 1.1, deprecated by [RFC 8996].
+    ///
+    /// [RFC 8996]: https://www.rfc-editor.org/rfc/rfc8996
+    Tlsv11,
+    Tlsv12,
+    Tlsv13,
+}
+
+impl TlsVersion {
+    /// Converts the version into the matching `native_tls` protocol.
+    pub(crate) fn to_native_tls(self) -> native_tls::Protocol {
+        match self {
+            Self::Tlsv10 => native_tls::Protocol::Tlsv10,
+            Self::Tlsv11 => native_tls::Protocol::Tlsv11,
+            Self::Tlsv12 => native_tls::Protocol::Tlsv12,
+            Self::Tlsv13 => native_tls::Protocol::Tlsv13,
+        }
+    }
+
+    /// Converts the version into the matching `lettre` version. The
+    /// native TLS backend of `lettre` rejects TLS 1.3 as minimum
+    /// version, so it is reported as unsupported upfront.
+    #[cfg(feature = "smtp-sender")]
+    pub(crate) fn to_lettre(self) -> Result<lettre::transport::smtp::client::TlsVersion> {
+        use lettre::transport::smtp::client::TlsVersion as LettreTlsVersion;
+
+        match self {
+            Self::Tlsv10 => Ok(LettreTlsVersion::Tlsv10),
+            Self::Tlsv11 => Ok(LettreTlsVersion::Tlsv11),
+            Self::Tlsv12 => Ok(LettreTlsVersion::Tlsv12),
+            Self::Tlsv13 => Err(Error::UnsupportedTlsVersionError(self)),
+        }
+    }
+}
+
+impl fmt::Display for TlsVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tlsv10 => write!(f, "TLS 1.0"),
+            Self::Tlsv11 => write!(f, "TLS 1.1"),
+            Self::Tlsv12 => write!(f, "TLS 1.2"),
+            Self::Tlsv13 => write!(f, "TLS 1.3"),
+        }
+    }
+}
+
+impl FromStr for TlsVersion {
+    type Err = Error;
+
+    /// Parses versions like `1.2`, `TLS 1.2`, `tls1.2` or `TLSv1.2`.
+    fn from_str(s: &str) -> Result<Self> {
+        let version = s.trim().to_lowercase().replace(' ', "");
+        let version = version.trim_start_matches("tls").trim_start_matches('v');
+
+        match version {
+            "1.0" => Ok(Self::Tlsv10),
+            "1.1" => Ok(Self::Tlsv11),
+            "1.2" => Ok(Self::Tlsv12),
+            "1.3" => Ok(Self::Tlsv13),
+            _ => Err(Error::ParseTlsVersionError(s.to_owned())),
+        }
+    }
+}
+
+/// Checks the allowed cipher suites option. The native TLS backend
+/// does not expose any way to restrict cipher suites, so any list is
+/// rejected instead of being silently ignored.
+pub(crate) fn check_cipher_suites(cipher_suites: Option<&Vec<String>>) -> Result<()> {
+    match cipher_suites {
+        None => Ok(()),
+        Some(cipher_suites) if cipher_suites.is_empty() => Err(Error::EmptyCipherSuitesError),
+        Some(cipher_suites) => Err(Error::UnsupportedCipherSuitesError(
+            cipher_suites.join(", "),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tls {
+    use super::{Error, TlsVersion};
+
+    #[test]
+    fn parse_tls_version() {
+        assert_eq!(TlsVersion::Tlsv12, "1.2".parse().unwrap());
+        assert_eq!(TlsVersion::Tlsv13, "TLS 1.3".parse().unwrap());
+        assert_eq!(TlsVersion::Tlsv11, "TLSv1.1".parse().unwrap());
+        assert_eq!(TlsVersion::Tlsv10, "tls1.0".parse().unwrap());
+        assert!(matches!(
+            "1.4".parse::<TlsVersion>(),
+            Err(Error::ParseTlsVersionError(_))
+        ));
+    }
+
+    #[test]
+    fn check_cipher_suites() {
+        assert!(super::check_cipher_suites(None).is_ok());
+        assert!(matches!(
+            super::check_cipher_suites(Some(&vec![])),
+            Err(Error::EmptyCipherSuitesError)
+        ));
+        assert!(matches!(
+            super::check_cipher_suites(Some(&vec!["TLS_AES_128_GCM_SHA256".into()])),
+            Err(Error::UnsupportedCipherSuitesError(_))
+        ));
+    }
+}
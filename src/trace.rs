@@ -0,0 +1,80 @@
+//! Trace module.
+//!
+//! This module contains the instrumentation of the synchronization
+//! and of the backends. With the `tracing` feature, spans are opened
+//! per account, folder, batch and hunk, and per IMAP, Maildir and
+//! Notmuch backend call, and the expensive phases emit timing events,
+//! so that a `tracing` subscriber can show where the synchronization
+//! time goes. Without subscriber, `tracing` forwards its events to
+//! `log`. Without the feature, spans are no-ops and timings are
+//! logged at the debug level.
+
+use std::time::Instant;
+
+#[cfg(feature = "tracing")]
+pub(crate) use tracing::Span;
+
+/// Represents a no-op span, used when the `tracing` feature is
+/// disabled.
+#[cfg(not(feature = "tracing"))]
+#[derive(Clone, Debug)]
+pub(crate) struct Span;
+
+#[cfg(not(feature = "tracing"))]
+impl Span {
+    pub(crate) fn current() -> Self {
+        Self
+    }
+
+    pub(crate) fn entered(self) -> Self {
+        self
+    }
+
+    pub(crate) fn record<V>(&self, _field: &str, _value: V) -> &Self {
+        self
+    }
+}
+
+/// Builds an info span with the given name and fields, optionally
+/// child of the given parent span. Fields are only evaluated when
+/// the `tracing` feature is enabled.
+macro_rules! span {
+    (parent: $parent:expr, $name:literal $(, $($fields:tt)*)?) => {{
+        #[cfg(feature = "tracing")]
+        let span = tracing::info_span!(parent: &$parent, $name $(, $($fields)*)?);
+        #[cfg(not(feature = "tracing"))]
+        let span = {
+            let _ = &$parent;
+            $crate::trace::Span
+        };
+        span
+    }};
+    ($name:literal $(, $($fields:tt)*)?) => {{
+        #[cfg(feature = "tracing")]
+        let span = tracing::info_span!($name $(, $($fields)*)?);
+        #[cfg(not(feature = "tracing"))]
+        let span = $crate::trace::Span;
+        span
+    }};
+}
+
+pub(crate) use span;
+
+/// Runs the given phase of the synchronization and reports how long
+/// it took.
+pub(crate) fn timed<T>(phase: &'static str, f: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let res = f();
+    let elapsed = start.elapsed();
+
+    #[cfg(feature = "tracing")]
+    tracing::info!(
+        phase,
+        elapsed_ms = elapsed.as_millis() as u64,
+        "synchronization phase done"
+    );
+    #[cfg(not(feature = "tracing"))]
+    log::debug!("synchronization phase {phase} took {elapsed:?}");
+
+    res
+}
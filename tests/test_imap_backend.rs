@@ -8,8 +8,8 @@ use std::time::{Duration, SystemTime};
 
 #[cfg(feature = "imap-backend")]
 use himalaya_lib::{
-    AccountConfig, Backend, CompilerBuilder, ImapBackend, ImapConfig, TplBuilder,
-    DEFAULT_INBOX_FOLDER,
+    AccountConfig, Backend, CompilerBuilder, Flag, Flags, ImapBackend, ImapBackendBuilder,
+    ImapConfig, TplBuilder, DEFAULT_INBOX_FOLDER,
 };
 
 struct ImapTestServer {
@@ -186,5 +186,137 @@ fn test_imap_backend() {
     // imap.delete_folder("Отправленные").unwrap();
     // imap.close().unwrap();
 
+    // checking that concurrent operations share the sessions pool
+    let config = AccountConfig::default();
+    let imap = ImapBackendBuilder::new()
+        .pool_size(4)
+        .build(
+            Cow::Borrowed(&config),
+            Cow::Owned(ImapConfig {
+                host: "localhost".into(),
+                port: 3143,
+                ssl: Some(false),
+                starttls: Some(false),
+                insecure: Some(true),
+                login: "bob@localhost".into(),
+                passwd_cmd: "echo 'password'".into(),
+                ..ImapConfig::default()
+            }),
+        )
+        .unwrap();
+
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..20)
+            .map(|_| scope.spawn(|| imap.list_envelopes(DEFAULT_INBOX_FOLDER, 0, 0)))
+            .collect();
+
+        for handle in handles {
+            assert!(handle.join().unwrap().is_ok());
+        }
+    });
+
+    // checking that new messages can be polled without idling
+    let email = |subject: &str| {
+        format!(
+            "From: alice@localhost\r\nTo: bob@localhost\r\nSubject: {subject}\r\n\r\nHello!\r\n"
+        )
+    };
+    let uid: u32 = imap
+        .add_email(
+            DEFAULT_INBOX_FOLDER,
+            email("first").as_bytes(),
+            &Flags::default(),
+        )
+        .unwrap()
+        .parse()
+        .unwrap();
+    imap.add_email(
+        DEFAULT_INBOX_FOLDER,
+        email("second").as_bytes(),
+        &Flags::default(),
+    )
+    .unwrap();
+
+    let envelopes = imap.poll_new_messages(DEFAULT_INBOX_FOLDER, uid).unwrap();
+    assert_eq!(1, envelopes.len());
+    assert_eq!("second", envelopes[0].subject);
+    let last_uid = envelopes[0].id.parse().unwrap();
+    assert!(imap
+        .poll_new_messages(DEFAULT_INBOX_FOLDER, last_uid)
+        .unwrap()
+        .is_empty());
+
+    // checking that envelopes can be counted, with or without the
+    // esearch extension
+    assert_eq!(
+        1,
+        imap.count_envelopes(DEFAULT_INBOX_FOLDER, "SUBJECT second")
+            .unwrap()
+    );
+    let unseen = imap
+        .count_envelopes(DEFAULT_INBOX_FOLDER, "UNSEEN")
+        .unwrap();
+    assert!(unseen >= 2);
+    let status = imap.folder_status(DEFAULT_INBOX_FOLDER).unwrap();
+    assert_eq!(unseen as usize, status.unseen);
+
+    // checking that the status of all folders matches the one of
+    // each folder
+    let statuses = imap.folders_status().unwrap();
+    let (_, inbox_status) = statuses
+        .iter()
+        .find(|(folder, _)| folder.name == DEFAULT_INBOX_FOLDER)
+        .unwrap();
+    assert_eq!(&status, inbox_status);
+
+    // checking that flags of several emails are set at once
+    let (first, second) = (uid.to_string(), last_uid.to_string());
+    imap.set_flags_internal(
+        DEFAULT_INBOX_FOLDER,
+        vec![&first, &second],
+        &Flags::from_iter([Flag::Seen, Flag::Flagged]),
+    )
+    .unwrap();
+    let envelopes = imap.list_envelopes(DEFAULT_INBOX_FOLDER, 0, 0).unwrap();
+    let flagged = envelopes
+        .iter()
+        .filter(|envelope| envelope.id == first || envelope.id == second)
+        .filter(|envelope| envelope.flags.contains(&Flag::Flagged))
+        .count();
+    assert_eq!(2, flagged);
+
+    // checking that only subscribed folders are listed when asked
+    let subscribed = ImapBackend::new(
+        Cow::Borrowed(&config),
+        Cow::Owned(ImapConfig {
+            host: "localhost".into(),
+            port: 3143,
+            ssl: Some(false),
+            starttls: Some(false),
+            insecure: Some(true),
+            login: "bob@localhost".into(),
+            passwd_cmd: "echo 'password'".into(),
+            list_only_subscribed: true,
+            ..ImapConfig::default()
+        }),
+    )
+    .unwrap();
+    let is_listed = |imap: &ImapBackend| {
+        imap.list_folders()
+            .unwrap()
+            .iter()
+            .any(|folder| folder.name == DEFAULT_INBOX_FOLDER)
+    };
+
+    imap.subscribe_folder(DEFAULT_INBOX_FOLDER).unwrap();
+    assert!(is_listed(&subscribed));
+    imap.unsubscribe_folder(DEFAULT_INBOX_FOLDER).unwrap();
+    assert!(!is_listed(&subscribed));
+    assert!(is_listed(&imap));
+    subscribed.close().unwrap();
+
+    imap.purge_folder(DEFAULT_INBOX_FOLDER).unwrap();
+    imap.close().unwrap();
+
     drop(test_server)
 }
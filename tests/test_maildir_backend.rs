@@ -3,13 +3,19 @@ use concat_with::concat_line;
 #[cfg(feature = "maildir-backend")]
 use maildir::Maildir;
 #[cfg(feature = "maildir-backend")]
-use std::{borrow::Cow, collections::HashMap, fs, iter::FromIterator};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    fs,
+    iter::FromIterator,
+};
 #[cfg(feature = "maildir-backend")]
 use tempfile::tempdir;
 
 #[cfg(feature = "maildir-backend")]
 use himalaya_lib::{
-    AccountConfig, Backend, CompilerBuilder, Flag, Flags, MaildirBackend, MaildirConfig, TplBuilder,
+    AccountConfig, Backend, BackendEvent, CompilerBuilder, Flag, Flags, MaildirBackend,
+    MaildirConfig, TplBuilder,
 };
 
 #[cfg(feature = "maildir-backend")]
@@ -61,6 +67,9 @@ fn test_maildir_backend() {
     let flags = Flags::from_iter([Flag::Seen]);
     let id = mdir.add_email("INBOX", &email, &flags).unwrap();
 
+    // check that the message has been moved out of the tmp folder
+    assert_eq!(0, fs::read_dir(mdir_path.join("tmp")).unwrap().count());
+
     // check that the added message exists
     let emails = mdir.get_emails("INBOX", vec![&id]).unwrap();
     assert_eq!(
@@ -86,6 +95,34 @@ fn test_maildir_backend() {
     assert_eq!(1, envelopes.len());
     assert_eq!("alice@localhost", envelope.from.addr);
     assert_eq!("Plain message!", envelope.subject);
+    let uid = envelope.internal_id.clone();
+    assert!(uid.parse::<u64>().is_ok());
+
+    // check that the folder status is computed from file names
+    let status = mdir.folder_status("INBOX").unwrap();
+    assert_eq!(1, status.total);
+    assert_eq!(0, status.unseen);
+    assert_eq!(0, status.recent);
+    assert_eq!(Some(email.len() as u64), status.size_bytes);
+    let statuses = mdir.folders_status().unwrap();
+    assert!(statuses
+        .iter()
+        .any(|(folder, folder_status)| folder.name == "INBOX" && *folder_status == status));
+
+    // check that the message can be searched by headers and body
+    let envelopes = mdir
+        .search_envelopes("INBOX", "Subject: *plain* From: *alice*", "", 0, 0)
+        .unwrap();
+    assert_eq!(1, envelopes.len());
+    assert_eq!(envelope.id, envelopes.first().unwrap().id);
+    let envelopes = mdir
+        .search_envelopes("INBOX", "Subject: *meeting*", "", 0, 0)
+        .unwrap();
+    assert_eq!(0, envelopes.len());
+    let envelopes = mdir
+        .search_envelopes("INBOX", "Body: *message!*", "", 0, 0)
+        .unwrap();
+    assert_eq!(1, envelopes.len());
 
     // check that a flag can be added to the message
     let flags = Flags::from_iter([Flag::Flagged]);
@@ -114,16 +151,53 @@ fn test_maildir_backend() {
     assert!(!envelope.flags.contains(&Flag::Flagged));
     assert!(!envelope.flags.contains(&Flag::Answered));
 
-    // check that the message can be copied
+    // check that the internal id is a UID kept across flag changes
+    // and backend restarts
+    assert_eq!(uid, envelope.internal_id);
+    let reopened = MaildirBackend::new(
+        Cow::Borrowed(&account_config),
+        Cow::Owned(MaildirConfig {
+            root_dir: mdir_path.clone(),
+        }),
+    )
+    .unwrap();
+    let envelopes = reopened.list_envelopes("INBOX", 0, 0).unwrap();
+    assert_eq!(uid, envelopes.first().unwrap().internal_id);
+    assert_eq!(mdir.uid_validity(), reopened.uid_validity());
+    drop(reopened);
+
+    // check that flags are updated all or nothing
+    let flags = Flags::from_iter([Flag::Flagged]);
+    assert!(mdir
+        .add_flags_internal("INBOX", vec![&envelope.internal_id, "unknown"], &flags)
+        .is_err());
+    let envelopes = mdir.list_envelopes("INBOX", 1, 0).unwrap();
+    let envelope = envelopes.first().unwrap();
+    assert!(!envelope.flags.contains(&Flag::Flagged));
+
+    // check that the message can be copied, under a new unique
+    // file name keeping the flags
     mdir.copy_emails("INBOX", "subdir", vec![&envelope.id])
         .unwrap();
     let inbox = mdir.list_envelopes("INBOX", 0, 0).unwrap();
     let subdir = mdir.list_envelopes("subdir", 0, 0).unwrap();
     assert_eq!(1, inbox.len());
     assert_eq!(1, subdir.len());
+    let copy = subdir.first().unwrap();
+    assert_ne!(envelope.internal_id, copy.internal_id);
+    assert_eq!(envelope.flags, copy.flags);
     assert!(mdir.get_emails("INBOX", vec![&id]).is_ok());
-    assert!(mdir.get_emails("subdir", vec![&id]).is_ok());
-    assert!(submdir.get_emails("INBOX", vec![&id]).is_ok());
+    assert!(mdir.get_emails("subdir", vec![&copy.id]).is_ok());
+    let sub_envelopes = submdir.list_envelopes("INBOX", 0, 0).unwrap();
+    let sub_copy = sub_envelopes.first().unwrap();
+    assert!(submdir.get_emails("INBOX", vec![&sub_copy.id]).is_ok());
+
+    // check that the copy does not share flags with the original
+    let flags = Flags::from_iter([Flag::Flagged]);
+    mdir.add_flags("subdir", vec![&copy.id], &flags).unwrap();
+    let inbox = mdir.list_envelopes("INBOX", 0, 0).unwrap();
+    assert!(!inbox.first().unwrap().flags.contains(&Flag::Flagged));
+    mdir.delete_emails("subdir", vec![&copy.id]).unwrap();
 
     // check that the message can be moved
     mdir.move_emails("INBOX", "subdir", vec![&envelope.id])
@@ -132,16 +206,126 @@ fn test_maildir_backend() {
     let subdir = mdir.list_envelopes("subdir", 0, 0).unwrap();
     assert_eq!(0, inbox.len());
     assert_eq!(1, subdir.len());
+    let moved = subdir.first().unwrap();
+    assert_eq!(envelope.internal_id, moved.internal_id);
     assert!(mdir.get_emails("INBOX", vec![&id]).is_err());
-    assert!(mdir.get_emails("subdir", vec![&id]).is_ok());
-    assert!(submdir.get_emails("INBOX", vec![&id]).is_ok());
+    assert!(mdir.get_emails("subdir", vec![&moved.id]).is_ok());
+    let sub_envelopes = submdir.list_envelopes("INBOX", 0, 0).unwrap();
+    let sub_moved = sub_envelopes.first().unwrap();
+    assert!(submdir.get_emails("INBOX", vec![&sub_moved.id]).is_ok());
 
     // check that the message can be deleted
-    mdir.delete_emails("subdir", vec![&id]).unwrap();
+    mdir.delete_emails("subdir", vec![&moved.id]).unwrap();
     let inbox = mdir.list_envelopes("INBOX", 0, 0).unwrap();
     let subdir = mdir.list_envelopes("subdir", 0, 0).unwrap();
     assert_eq!(0, inbox.len());
     assert_eq!(0, subdir.len());
-    assert!(mdir.get_emails("subdir", vec![&id]).is_err());
-    assert!(submdir.get_emails("INBOX", vec![&id]).is_err());
+    assert!(mdir.get_emails("subdir", vec![&moved.id]).is_err());
+    assert!(submdir.get_emails("INBOX", vec![&sub_moved.id]).is_err());
+}
+
+#[cfg(feature = "maildir-backend")]
+#[test]
+fn test_maildir_backend_envelope_pages() {
+    let mdir: Maildir = tempdir().unwrap().path().to_owned().into();
+    mdir.create_dirs().unwrap();
+
+    let account_config = AccountConfig {
+        name: "account".into(),
+        ..AccountConfig::default()
+    };
+
+    let mdir = MaildirBackend::new(
+        Cow::Borrowed(&account_config),
+        Cow::Owned(MaildirConfig {
+            root_dir: mdir.path().to_owned(),
+        }),
+    )
+    .unwrap();
+
+    for i in 0..10 {
+        let email = TplBuilder::default()
+            .from("alice@localhost")
+            .to("bob@localhost")
+            .subject(&format!("Message {i}"))
+            .text_plain_part("Plain message!")
+            .compile(CompilerBuilder::default())
+            .unwrap();
+        mdir.add_email("INBOX", &email, &Flags::default()).unwrap();
+    }
+
+    // check that only the first 2 pages of the 5 pages are listed
+    let pages: Vec<_> = mdir
+        .envelope_pages("INBOX", 2)
+        .unwrap()
+        .take(2)
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(2, pages.len());
+    assert!(pages.iter().all(|page| page.len() == 2));
+
+    let ids: HashSet<_> = pages
+        .iter()
+        .flat_map(|page| page.iter())
+        .map(|e| &e.id)
+        .collect();
+    assert_eq!(4, ids.len());
+
+    // check that all the pages are listed when iterating until the
+    // end
+    let pages = mdir.envelope_pages("INBOX", 2).unwrap();
+    assert_eq!(5, pages.count());
+}
+
+#[cfg(feature = "maildir-backend")]
+#[test]
+fn test_maildir_backend_subscribe() {
+    let mdir: Maildir = tempdir().unwrap().path().to_owned().into();
+    mdir.create_dirs().unwrap();
+
+    let account_config = AccountConfig {
+        name: "account".into(),
+        ..AccountConfig::default()
+    };
+
+    let mdir = MaildirBackend::new(
+        Cow::Borrowed(&account_config),
+        Cow::Owned(MaildirConfig {
+            root_dir: mdir.path().to_owned(),
+        }),
+    )
+    .unwrap();
+
+    let mut events = mdir.subscribe().unwrap();
+
+    // check that adding a message emits a new envelope event
+    let email = TplBuilder::default()
+        .from("alice@localhost")
+        .to("bob@localhost")
+        .subject("Plain message!")
+        .text_plain_part("Plain message!")
+        .compile(CompilerBuilder::default())
+        .unwrap();
+    let id = mdir.add_email("INBOX", &email, &Flags::default()).unwrap();
+
+    match events.next().unwrap().unwrap() {
+        BackendEvent::NewEnvelope { folder, envelope } => {
+            assert_eq!("INBOX", folder);
+            assert_eq!(id, envelope.id);
+            assert_eq!("Plain message!", envelope.subject);
+        }
+        event => panic!("unexpected event {event:?}"),
+    }
+
+    // check that changing flags emits a flags changed event
+    mdir.add_flags("INBOX", vec![&id], &Flags::from_iter([Flag::Seen]))
+        .unwrap();
+
+    match events.next().unwrap().unwrap() {
+        BackendEvent::FlagsChanged { envelope, .. } => {
+            assert_eq!(id, envelope.id);
+            assert!(envelope.flags.contains(&Flag::Seen));
+        }
+        event => panic!("unexpected event {event:?}"),
+    }
 }
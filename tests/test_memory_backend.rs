@@ -0,0 +1,1867 @@
+#[cfg(feature = "memory-backend")]
+use mailparse::MailHeaderMap;
+#[cfg(feature = "memory-backend")]
+use std::{
+    borrow::Cow,
+    fs,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+#[cfg(feature = "memory-backend")]
+use tempfile::{tempdir, TempDir};
+
+#[cfg(feature = "memory-backend")]
+use himalaya_lib::{
+    backend,
+    backend::memory::Operation,
+    drafts,
+    envelope::{
+        self,
+        sync::{
+            BackendHunk, Cache, CacheHunk, HunkKindRestricted, ReadOnlyStrategy,
+            SyncDeletedBehavior,
+        },
+    },
+    send_and_save, sender, transfer_emails, AccountConfig, Backend, BackendSyncBuilder,
+    BackendSyncProgressEvent, CompilerBuilder, Email, Flag, Flags, MaildirBackend, MaildirConfig,
+    MemoryBackend, Sender, SentEmailCopy, SyncExecutor, SyncHooks, TplBuilder,
+    DEFAULT_DRAFTS_FOLDER, DEFAULT_TRANSFER_BATCH_SIZE,
+};
+
+/// Account synchronizing a memory backend with a Maildir living in
+/// its own temporary sync directory.
+#[cfg(feature = "memory-backend")]
+struct SyncAccount {
+    sync_dir: TempDir,
+    config: AccountConfig,
+}
+
+#[cfg(feature = "memory-backend")]
+impl SyncAccount {
+    fn new(name: &str) -> Self {
+        Self::with_config(AccountConfig {
+            name: name.into(),
+            ..AccountConfig::default()
+        })
+    }
+
+    fn with_config(config: AccountConfig) -> Self {
+        let sync_dir = tempdir().unwrap();
+        let config = AccountConfig {
+            sync: true,
+            sync_dir: Some(sync_dir.path().to_owned()),
+            ..config
+        };
+        Self { sync_dir, config }
+    }
+
+    fn remote(&self) -> MemoryBackend<'_> {
+        MemoryBackend::new(Cow::Borrowed(&self.config))
+    }
+
+    /// Opens the Maildir living in the sync directory.
+    fn local(&self) -> MaildirBackend<'_> {
+        MaildirBackend::new(
+            Cow::Borrowed(&self.config),
+            Cow::Owned(MaildirConfig {
+                root_dir: self.sync_dir.path().to_owned(),
+            }),
+        )
+        .unwrap()
+    }
+}
+
+#[cfg(feature = "memory-backend")]
+fn email(message_id: &str, subject: &str) -> Vec<u8> {
+    TplBuilder::default()
+        .message_id(message_id)
+        .from("alice@localhost")
+        .to("bob@localhost")
+        .subject(subject)
+        .text_plain_part(subject)
+        .compile(CompilerBuilder::default())
+        .unwrap()
+}
+
+#[cfg(feature = "memory-backend")]
+#[test]
+fn test_memory_backend() {
+    let account_config = AccountConfig::default();
+    let memory = MemoryBackend::new(Cow::Borrowed(&account_config));
+
+    // check that folders can be added and listed
+
+    memory.add_folder("Archives").unwrap();
+    let folders = memory.list_folders().unwrap();
+    assert_eq!(
+        vec!["Archives", "INBOX"],
+        folders.iter().map(|f| f.name.as_str()).collect::<Vec<_>>()
+    );
+
+    // check that ids are deterministic
+
+    let flags = Flags::from_iter([Flag::Seen]);
+    let id = memory
+        .add_email("inbox", &email("<a@localhost>", "A"), &flags)
+        .unwrap();
+    assert_eq!("1", id);
+
+    let envelope = memory.get_envelope("INBOX", &id).unwrap();
+    assert_eq!("<a@localhost>", envelope.message_id);
+    assert_eq!("A", envelope.subject);
+    assert_eq!(flags, envelope.flags);
+
+    // check that flags can be changed
+
+    memory
+        .add_flags("INBOX", vec![&id], &Flags::from_iter([Flag::Flagged]))
+        .unwrap();
+    memory
+        .remove_flags("INBOX", vec![&id], &Flags::from_iter([Flag::Seen]))
+        .unwrap();
+    let envelope = memory.get_envelope("INBOX", &id).unwrap();
+    assert_eq!(Flags::from_iter([Flag::Flagged]), envelope.flags);
+
+    // check that emails can be moved
+
+    memory.move_emails("INBOX", "Archives", vec![&id]).unwrap();
+    assert!(memory.list_envelopes("INBOX", 0, 0).unwrap().is_empty());
+    let envelopes = memory.list_envelopes("Archives", 0, 0).unwrap();
+    assert_eq!(1, envelopes.len());
+    assert_eq!("2", envelopes[0].id);
+
+    // check that replied emails can be marked as answered
+
+    assert_eq!(
+        1,
+        memory
+            .mark_answered_by_message_id(None, "a@localhost")
+            .unwrap()
+    );
+    let envelope = memory.get_envelope("Archives", "2").unwrap();
+    assert!(envelope.flags.contains(&Flag::Answered));
+
+    // check that failures can be injected
+
+    memory.fail_next(Operation::ListFolders).unwrap();
+    assert!(memory.list_folders().is_err());
+    assert!(memory.list_folders().is_ok());
+}
+
+#[cfg(feature = "memory-backend")]
+#[test]
+fn test_memory_backend_sync() {
+    let account = SyncAccount::new("memory");
+
+    let remote = account.remote();
+    remote
+        .add_email("INBOX", &email("<a@localhost>", "A"), &Flags::default())
+        .unwrap();
+    remote
+        .add_email("INBOX", &email("<b@localhost>", "B"), &Flags::default())
+        .unwrap();
+
+    // make the download of one email fail
+
+    remote.fail_next(Operation::PreviewEmails).unwrap();
+
+    let report = BackendSyncBuilder::new(&account.config)
+        .sync(&remote)
+        .unwrap();
+    assert_eq!(1, report.envelopes_failed_hunks.len());
+
+    let local = account.local();
+    assert_eq!(1, local.list_envelopes("INBOX", 0, 0).unwrap().len());
+
+    // check that the next synchronization downloads the missing email
+
+    let report = BackendSyncBuilder::new(&account.config)
+        .sync(&remote)
+        .unwrap();
+    assert!(report.envelopes_failed_hunks.is_empty());
+    assert_eq!(2, local.list_envelopes("INBOX", 0, 0).unwrap().len());
+}
+
+#[cfg(feature = "memory-backend")]
+#[test]
+fn test_memory_backend_sync_keeps_unseen() {
+    let account = SyncAccount::new("memory");
+
+    let remote = account.remote();
+    remote
+        .add_email("INBOX", &email("<a@localhost>", "A"), &Flags::default())
+        .unwrap();
+
+    // check that copying emails does not mark them as seen, neither
+    // remote side nor local side
+
+    BackendSyncBuilder::new(&account.config)
+        .sync(&remote)
+        .unwrap();
+    let local = account.local();
+    let remote_envelopes = remote.list_envelopes("INBOX", 0, 0).unwrap();
+    let local_envelopes = local.list_envelopes("INBOX", 0, 0).unwrap();
+    assert_eq!(1, local_envelopes.len());
+    assert!(!remote_envelopes[0].flags.contains(&Flag::Seen));
+    assert!(!local_envelopes[0].flags.contains(&Flag::Seen));
+
+    // check that seen is set only when explicitly asked
+
+    let id = remote_envelopes[0].id.as_str();
+    remote.fetch_emails("INBOX", vec![id], false).unwrap();
+    assert!(!remote
+        .get_envelope("INBOX", id)
+        .unwrap()
+        .flags
+        .contains(&Flag::Seen));
+    remote.fetch_emails("INBOX", vec![id], true).unwrap();
+    assert!(remote
+        .get_envelope("INBOX", id)
+        .unwrap()
+        .flags
+        .contains(&Flag::Seen));
+}
+
+#[cfg(feature = "memory-backend")]
+#[test]
+fn test_memory_backend_sync_failing_copy() {
+    let account = SyncAccount::new("memory-failing-copy");
+
+    let remote = account.remote();
+    let local = account.local();
+
+    // add a local email and make its upload fail
+
+    local
+        .add_email("INBOX", &email("<a@localhost>", "A"), &Flags::default())
+        .unwrap();
+    remote.fail_next(Operation::AddEmail).unwrap();
+
+    let report = BackendSyncBuilder::new(&account.config)
+        .sync(&remote)
+        .unwrap();
+    assert_eq!(1, report.envelopes_failed_hunks.len());
+
+    // check that the failed copy does not lead to a deletion on the
+    // next synchronization
+
+    let report = BackendSyncBuilder::new(&account.config)
+        .sync(&remote)
+        .unwrap();
+    assert!(report.envelopes_failed_hunks.is_empty());
+    assert!(!report
+        .envelopes_patch
+        .iter()
+        .any(|hunk| matches!(hunk, BackendHunk::RemoveEmail(..))));
+    assert_eq!(1, local.list_envelopes("INBOX", 0, 0).unwrap().len());
+    assert_eq!(1, remote.list_envelopes("INBOX", 0, 0).unwrap().len());
+}
+
+#[cfg(feature = "memory-backend")]
+#[test]
+fn test_memory_backend_sync_unparsable_email() {
+    let account = SyncAccount::new("memory-unparsable-email");
+
+    let remote = account.remote();
+    BackendSyncBuilder::new(&account.config)
+        .sync(&remote)
+        .unwrap();
+
+    // add a valid email and a corrupted one, whose date cannot be
+    // parsed, to the local Maildir
+
+    let local = account.local();
+    local
+        .add_email("INBOX", &email("<a@localhost>", "A"), &Flags::default())
+        .unwrap();
+    fs::write(
+        account
+            .sync_dir
+            .path()
+            .join("cur")
+            .join("1700000000.corrupted.localhost:2,S"),
+        "Message-ID: <b@localhost>\r\nDate: not a date\r\nSubject: B\r\n\r\nB\r\n",
+    )
+    .unwrap();
+
+    // check that the corrupted email is listed as a placeholder
+    // instead of making the listing fail
+
+    let envelopes = local.list_envelopes("INBOX", 0, 0).unwrap();
+    assert_eq!(2, envelopes.len());
+    let placeholder = envelopes
+        .iter()
+        .find(|envelope| envelope.is_unparsable())
+        .unwrap();
+    assert!(placeholder.parse_error.is_some());
+    assert!(placeholder.subject.is_empty());
+    assert_ne!("<b@localhost>", placeholder.message_id);
+    assert!(placeholder.flags.contains(&Flag::Seen));
+
+    // check that the placeholder is reported and skipped, while the
+    // valid email is synchronized
+
+    let report = BackendSyncBuilder::new(&account.config)
+        .sync(&remote)
+        .unwrap();
+    assert_eq!(1, report.envelopes_unparsable.len());
+    let (folder, envelope, kind) = &report.envelopes_unparsable[0];
+    assert_eq!("INBOX", folder);
+    assert_eq!(placeholder.id, envelope.id);
+    assert!(matches!(kind, HunkKindRestricted::Local));
+
+    let remote_envelopes = remote.list_envelopes("INBOX", 0, 0).unwrap();
+    assert_eq!(1, remote_envelopes.len());
+    assert_eq!("A", remote_envelopes[0].subject);
+
+    // check that the next synchronization neither uploads nor
+    // removes the corrupted email
+
+    let report = BackendSyncBuilder::new(&account.config)
+        .sync(&remote)
+        .unwrap();
+    assert_eq!(1, report.envelopes_unparsable.len());
+    assert_eq!(2, local.list_envelopes("INBOX", 0, 0).unwrap().len());
+    assert_eq!(1, remote.list_envelopes("INBOX", 0, 0).unwrap().len());
+}
+
+#[cfg(feature = "memory-backend")]
+#[test]
+fn test_memory_backend_sync_systemic_error() {
+    let account = SyncAccount::new("memory-systemic-error");
+
+    let remote = account.remote();
+    remote
+        .add_email("INBOX", &email("<a@localhost>", "A"), &Flags::default())
+        .unwrap();
+    remote
+        .add_email("INBOX", &email("<b@localhost>", "B"), &Flags::default())
+        .unwrap();
+
+    // one download out of two fails, which is below the threshold
+
+    remote.fail_next(Operation::PreviewEmails).unwrap();
+
+    let report = BackendSyncBuilder::new(&account.config)
+        .executor(SyncExecutor::SingleThreaded)
+        .abort_on_systemic_error(0.5)
+        .sync(&remote)
+        .unwrap();
+    assert_eq!(1, report.envelopes_failed_hunks.len());
+
+    let report = envelope::sync::SyncReport {
+        failed_hunks: report.envelopes_failed_hunks,
+        ..Default::default()
+    };
+    assert_eq!(1, report.error_summary().len());
+    assert_eq!(Some(&1), report.error_summary().values().next());
+    assert!(report.has_systemic_error(0.5));
+
+    // the only remaining download fails, which aborts the
+    // synchronization
+
+    remote.fail_next(Operation::PreviewEmails).unwrap();
+
+    let err = BackendSyncBuilder::new(&account.config)
+        .executor(SyncExecutor::SingleThreaded)
+        .abort_on_systemic_error(0.5)
+        .sync(&remote)
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        backend::Error::SyncEnvelopesError(envelope::sync::Error::SystemicSyncError(_, _, 1, 1))
+    ));
+}
+
+#[cfg(feature = "memory-backend")]
+#[test]
+fn test_memory_backend_sync_folder_timeout() {
+    let account = SyncAccount::new("memory-folder-timeout");
+
+    let remote = account.remote();
+    remote.add_folder("Archives").unwrap();
+    remote
+        .add_email("INBOX", &email("<a@localhost>", "A"), &Flags::default())
+        .unwrap();
+    remote
+        .add_email("Archives", &email("<b@localhost>", "B"), &Flags::default())
+        .unwrap();
+
+    // stall the first folder past its timeout, so that its watchdog
+    // cancels it before its first hunk
+
+    let stalled = AtomicBool::new(false);
+    let report = BackendSyncBuilder::new(&account.config)
+        .executor(SyncExecutor::SingleThreaded)
+        .folder_timeout(Duration::from_millis(500))
+        .on_progress(|evt| {
+            if let BackendSyncProgressEvent::ProcessEnvelopesPatch(_) = evt {
+                if !stalled.swap(true, Ordering::SeqCst) {
+                    thread::sleep(Duration::from_secs(1));
+                }
+            }
+            Ok(())
+        })
+        .sync(&remote)
+        .unwrap();
+
+    assert_eq!(1, report.timed_out_folders.len());
+    let timed_out = report.timed_out_folders.iter().next().unwrap().as_str();
+    let synced = if timed_out == "INBOX" {
+        "Archives"
+    } else {
+        "INBOX"
+    };
+
+    // check that the timed out folder is skipped, and that the next
+    // folder is still synchronized
+
+    let local = account.local();
+    assert_eq!(0, local.list_envelopes(timed_out, 0, 0).unwrap().len());
+    assert_eq!(1, local.list_envelopes(synced, 0, 0).unwrap().len());
+}
+
+#[cfg(feature = "memory-backend")]
+#[test]
+fn test_memory_backend_sync_hooks() {
+    let hooks_dir = tempdir().unwrap();
+    let new_emails_file = hooks_dir.path().join("new-emails");
+    let account = SyncAccount::with_config(AccountConfig {
+        name: "memory-hooks".into(),
+        sync_hooks: SyncHooks {
+            on_new_email: Some(format!("cat >> {}", new_emails_file.display())),
+            ..SyncHooks::default()
+        },
+        ..AccountConfig::default()
+    });
+
+    let remote = account.remote();
+    remote
+        .add_email("INBOX", &email("<a@localhost>", "A"), &Flags::default())
+        .unwrap();
+    remote
+        .add_email("INBOX", &email("<b@localhost>", "B"), &Flags::default())
+        .unwrap();
+
+    let new_emails = Mutex::new(Vec::new());
+    let report = BackendSyncBuilder::new(&account.config)
+        .pre_sync(|| Err(backend::Error::NotSupported))
+        .on_new_email(|path, envelope| {
+            assert!(path.is_file());
+            new_emails.lock().unwrap().push(envelope.message_id.clone());
+            Ok(())
+        })
+        .post_sync(|report| {
+            assert_eq!(2, report.envelopes_patch.len());
+            Ok(())
+        })
+        .sync(&remote)
+        .unwrap();
+
+    // the failing hook does not abort the synchronization
+    assert_eq!(1, report.hooks_errors.len());
+    assert!(matches!(
+        report.hooks_errors[0],
+        backend::Error::NotSupported
+    ));
+
+    let mut new_emails = new_emails.into_inner().unwrap();
+    new_emails.sort();
+    assert_eq!(vec!["<a@localhost>", "<b@localhost>"], new_emails);
+    assert_eq!(
+        2,
+        std::fs::read_to_string(&new_emails_file)
+            .unwrap()
+            .lines()
+            .count()
+    );
+
+    // nothing is downloaded by the next synchronization
+
+    let report = BackendSyncBuilder::new(&account.config)
+        .on_new_email(|_, _| panic!("unexpected new email"))
+        .sync(&remote)
+        .unwrap();
+    assert!(report.hooks_errors.is_empty());
+}
+
+#[cfg(feature = "memory-backend")]
+#[test]
+fn test_memory_backend_send_and_save() {
+    struct FakeSender(Vec<Vec<u8>>);
+
+    impl Sender for FakeSender {
+        fn send(&mut self, email: &[u8]) -> sender::Result<()> {
+            self.0.push(email.to_vec());
+            Ok(())
+        }
+    }
+
+    let account_config = AccountConfig::default();
+    let memory = MemoryBackend::new(Cow::Borrowed(&account_config));
+    memory.add_folder("Sent").unwrap();
+    let mut sender = FakeSender(Vec::new());
+
+    // check that a seen copy of the sent email is saved
+
+    let copy = send_and_save(
+        &account_config,
+        &mut sender,
+        &memory,
+        &email("<a@localhost>", "A"),
+    )
+    .unwrap();
+    assert!(matches!(copy, SentEmailCopy::Saved { ref folder, .. } if folder == "Sent"));
+    assert_eq!(1, sender.0.len());
+    let envelopes = memory.list_envelopes("Sent", 0, 0).unwrap();
+    assert_eq!(1, envelopes.len());
+    assert_eq!(Flags::from_iter([Flag::Seen]), envelopes[0].flags);
+
+    // check that a saving failure is not a sending failure
+
+    memory.fail_next(Operation::AddEmail).unwrap();
+    let copy = send_and_save(
+        &account_config,
+        &mut sender,
+        &memory,
+        &email("<b@localhost>", "B"),
+    )
+    .unwrap();
+    assert!(matches!(copy, SentEmailCopy::Failed { .. }));
+    assert_eq!(2, sender.0.len());
+
+    // check that saving can be disabled
+
+    let account_config = AccountConfig {
+        email_sending_save_copy: Some(false),
+        ..AccountConfig::default()
+    };
+    let copy = send_and_save(
+        &account_config,
+        &mut sender,
+        &memory,
+        &email("<c@localhost>", "C"),
+    )
+    .unwrap();
+    assert!(matches!(copy, SentEmailCopy::Disabled));
+    assert_eq!(3, sender.0.len());
+    assert_eq!(1, memory.list_envelopes("Sent", 0, 0).unwrap().len());
+
+    // check that an email without Message-ID gets one, shared by the
+    // sent email and its copy
+
+    let account_config = AccountConfig {
+        email: "alice@example.com".into(),
+        ..AccountConfig::default()
+    };
+    send_and_save(
+        &account_config,
+        &mut sender,
+        &memory,
+        b"From: alice@example.com\r\nSubject: D\r\n\r\nD\r\n",
+    )
+    .unwrap();
+    let sent = Email::from(sender.0[3].clone());
+    let message_id = sent
+        .parsed()
+        .unwrap()
+        .get_headers()
+        .get_first_value("Message-ID")
+        .unwrap();
+    assert!(message_id.ends_with("@example.com>"));
+    let envelopes = memory.list_envelopes("Sent", 0, 0).unwrap();
+    assert!(envelopes
+        .iter()
+        .any(|envelope| envelope.message_id == message_id));
+}
+
+#[cfg(feature = "memory-backend")]
+#[test]
+fn test_memory_backend_sync_max_message_size() {
+    let account = SyncAccount::new("memory-max-message-size");
+
+    let remote = account.remote();
+    remote
+        .add_email("INBOX", &email("<a@localhost>", "A"), &Flags::default())
+        .unwrap();
+    let big_email = TplBuilder::default()
+        .message_id("<b@localhost>")
+        .from("alice@localhost")
+        .to("bob@localhost")
+        .subject("B")
+        .text_plain_part(&"big body ".repeat(1000))
+        .compile(CompilerBuilder::default())
+        .unwrap();
+    remote
+        .add_email("INBOX", &big_email, &Flags::from_iter([Flag::Seen]))
+        .unwrap();
+
+    let sync_builder = BackendSyncBuilder::new(&account.config).max_message_size(1000);
+    let report = sync_builder.sync(&remote).unwrap();
+    assert!(report.envelopes_failed_hunks.is_empty());
+
+    let local = account.local();
+    let envelopes = local.list_envelopes("INBOX", 0, 0).unwrap();
+    assert_eq!(2, envelopes.len());
+
+    // check that only the headers of the big email are downloaded
+
+    let envelope = envelopes
+        .iter()
+        .find(|envelope| envelope.message_id == "<b@localhost>")
+        .unwrap();
+    assert_eq!("B", envelope.subject);
+    assert_eq!(Flags::from_iter([Flag::Seen]), envelope.flags);
+    let emails = local
+        .preview_emails_internal("INBOX", vec![&envelope.internal_id])
+        .unwrap();
+    let raw = emails.to_vec()[0].raw().unwrap().to_vec();
+    assert!(!String::from_utf8_lossy(&raw).contains("big body"));
+
+    // check that the next synchronization leaves the partial copy
+    // untouched
+
+    let report = sync_builder.sync(&remote).unwrap();
+    assert!(!report
+        .envelopes_patch
+        .iter()
+        .any(|hunk| matches!(hunk, BackendHunk::CopyEmail(..))));
+
+    // check that the complete email can be fetched on demand
+
+    let internal_id = sync_builder
+        .fetch_full(&remote, "INBOX", &envelope.internal_id)
+        .unwrap();
+    assert_ne!(envelope.internal_id, internal_id);
+    let emails = local
+        .preview_emails_internal("INBOX", vec![&internal_id])
+        .unwrap();
+    let raw = emails.to_vec()[0].raw().unwrap().to_vec();
+    assert!(String::from_utf8_lossy(&raw).contains("big body"));
+    assert_eq!(2, local.list_envelopes("INBOX", 0, 0).unwrap().len());
+
+    let report = sync_builder.sync(&remote).unwrap();
+    assert!(!report
+        .envelopes_patch
+        .iter()
+        .any(|hunk| matches!(hunk, BackendHunk::CopyEmail(..))));
+}
+
+#[cfg(feature = "memory-backend")]
+#[test]
+fn test_memory_backend_should_sync() {
+    let account = SyncAccount::with_config(AccountConfig {
+        name: "memory-should-sync".into(),
+        sync_interval_secs: Some(3600),
+        ..AccountConfig::default()
+    });
+
+    let remote = account.remote();
+    remote
+        .add_email("INBOX", &email("<a@localhost>", "A"), &Flags::default())
+        .unwrap();
+
+    // check that a never synchronized account is due
+
+    let sync_builder = BackendSyncBuilder::new(&account.config);
+    assert!(sync_builder.should_sync().unwrap());
+
+    // check that a successful synchronization delays the next one
+
+    sync_builder.sync(&remote).unwrap();
+    assert!(!sync_builder.should_sync().unwrap());
+
+    // check that accounts without interval are always due
+
+    let account_config = AccountConfig {
+        sync_interval_secs: None,
+        ..account.config.clone()
+    };
+    assert!(BackendSyncBuilder::new(&account_config)
+        .should_sync()
+        .unwrap());
+}
+
+#[cfg(feature = "memory-backend")]
+#[test]
+fn test_memory_backend_sync_renamed_folder() {
+    let account = SyncAccount::new("memory-renamed-folder");
+
+    let remote = account.remote();
+    remote.add_folder("Old").unwrap();
+    remote
+        .add_email("Old", &email("<a@localhost>", "A"), &Flags::default())
+        .unwrap();
+    remote
+        .add_email("Old", &email("<b@localhost>", "B"), &Flags::default())
+        .unwrap();
+
+    BackendSyncBuilder::new(&account.config)
+        .sync(&remote)
+        .unwrap();
+
+    // check that a remote rename is applied locally without
+    // downloading the emails again
+
+    remote.rename_folder("Old", "New").unwrap();
+
+    let report = BackendSyncBuilder::new(&account.config)
+        .sync(&remote)
+        .unwrap();
+    assert!(report.folders.contains("New"));
+    assert!(!report.folders.contains("Old"));
+    assert!(!report
+        .envelopes_patch
+        .iter()
+        .any(|hunk| matches!(hunk, BackendHunk::CopyEmail(..))));
+
+    let local = account.local();
+    let folders = local.list_folders().unwrap();
+    assert!(folders.find_by_name("New").is_some());
+    assert!(folders.find_by_name("Old").is_none());
+    assert_eq!(2, local.list_envelopes("New", 0, 0).unwrap().len());
+}
+
+#[cfg(feature = "memory-backend")]
+#[test]
+fn test_memory_backend_sync_folders_two_way() {
+    let account = SyncAccount::new("memory");
+
+    let remote = account.remote();
+    remote.add_folder("Old").unwrap();
+    remote
+        .add_email("Old", &email("<a@localhost>", "A"), &Flags::default())
+        .unwrap();
+
+    BackendSyncBuilder::new(&account.config)
+        .sync(&remote)
+        .unwrap();
+
+    let local = account.local();
+    assert!(local.list_folders().unwrap().find_by_name("Old").is_some());
+
+    // check that a folder created locally is created remotely, and
+    // that a folder deleted remotely is deleted locally
+
+    local.add_folder("Projects").unwrap();
+    remote.delete_folder("Old").unwrap();
+
+    let report = BackendSyncBuilder::new(&account.config)
+        .sync(&remote)
+        .unwrap();
+    assert!(report.folders_patch.iter().all(|(_, err)| err.is_none()));
+
+    let remote_folders = remote.list_folders().unwrap();
+    assert!(remote_folders.find_by_name("Projects").is_some());
+    assert!(remote_folders.find_by_name("Old").is_none());
+
+    let local_folders = local.list_folders().unwrap();
+    assert!(local_folders.find_by_name("Projects").is_some());
+    assert!(local_folders.find_by_name("Old").is_none());
+
+    // check that a folder deleted locally is deleted remotely
+
+    local.delete_folder("Projects").unwrap();
+
+    BackendSyncBuilder::new(&account.config)
+        .sync(&remote)
+        .unwrap();
+    assert!(remote
+        .list_folders()
+        .unwrap()
+        .find_by_name("Projects")
+        .is_none());
+}
+
+#[cfg(feature = "memory-backend")]
+#[test]
+fn test_memory_backend_sync_failing_folder() {
+    let account = SyncAccount::new("memory");
+
+    let remote = account.remote();
+    BackendSyncBuilder::new(&account.config)
+        .sync(&remote)
+        .unwrap();
+
+    let local = account.local();
+    local.add_folder("Projects").unwrap();
+    local
+        .add_email("Projects", &email("<a@localhost>", "A"), &Flags::default())
+        .unwrap();
+
+    // check that a folder which cannot be created remotely does not
+    // abort the synchronization, and is excluded from the envelopes
+    // synchronization
+
+    remote.fail_next(Operation::AddFolder).unwrap();
+    let report = BackendSyncBuilder::new(&account.config)
+        .sync(&remote)
+        .unwrap();
+    assert!(report.folders_failed.contains("Projects"));
+    assert!(!report.folders.contains("Projects"));
+    assert!(report.envelopes_patch.is_empty());
+
+    let errs: Vec<_> = report
+        .folders_patch
+        .iter()
+        .filter_map(|(_, err)| err.as_ref())
+        .collect();
+    assert_eq!(1, errs.len());
+    assert_eq!(Some("Projects"), errs[0].folder());
+
+    // check that the next synchronization creates it
+
+    let report = BackendSyncBuilder::new(&account.config)
+        .sync(&remote)
+        .unwrap();
+    assert!(report.folders_failed.is_empty());
+    assert!(report.folders.contains("Projects"));
+    assert_eq!(1, remote.list_envelopes("Projects", 0, 0).unwrap().len());
+}
+
+#[cfg(feature = "memory-backend")]
+#[test]
+fn test_memory_backend_drafts() {
+    let account_config = AccountConfig {
+        drafts_folder: Some("Brouillons".into()),
+        ..AccountConfig::default()
+    };
+    let memory = MemoryBackend::new(Cow::Borrowed(&account_config));
+    let drafts = account_config.drafts_folder_alias().unwrap();
+    assert_eq!("Brouillons", drafts);
+    memory.add_folder(&drafts).unwrap();
+
+    // check that a draft is saved with the draft flag
+
+    let raw = email("<draft@localhost>", "Unfinished");
+    let id = Email::from(raw).save_draft(&drafts, &memory).unwrap();
+
+    let envelope = memory.get_envelope(&drafts, &id).unwrap();
+    assert_eq!(Flags::from_iter([Flag::Draft]), envelope.flags);
+
+    // check that the composition can be resumed from the draft
+
+    let tpl = Email::from_draft(&account_config, &id, &drafts, &memory)
+        .unwrap()
+        .build();
+    assert!(tpl.contains("To: bob@localhost"));
+    assert!(tpl.contains("Subject: Unfinished"));
+    assert!(tpl.contains("Unfinished"));
+
+    assert!(Email::from_draft(&account_config, "42", &drafts, &memory).is_err());
+}
+
+#[cfg(feature = "memory-backend")]
+#[test]
+fn test_memory_backend_drafts_revisions() {
+    let account_config = AccountConfig::default();
+    let memory = MemoryBackend::new(Cow::Borrowed(&account_config));
+    memory.add_folder(DEFAULT_DRAFTS_FOLDER).unwrap();
+
+    // check that a draft id is given to the first revision
+
+    let id =
+        drafts::save_draft(&memory, &account_config, &email("<a@localhost>", "First")).unwrap();
+    let tpl = drafts::resume_draft(&memory, &account_config, &id).unwrap();
+    assert!(tpl.contains(drafts::DRAFT_ID_HEADER));
+    assert!(tpl.contains("Subject: First"));
+
+    let first = memory
+        .preview_emails(DEFAULT_DRAFTS_FOLDER, vec![&id])
+        .unwrap();
+    let draft_id = drafts::draft_id(first.to_vec()[0].raw().unwrap()).unwrap();
+
+    // check that the next revision replaces the first one
+
+    let second = format!(
+        "{}: {draft_id}\r\n{}",
+        drafts::DRAFT_ID_HEADER,
+        String::from_utf8(email("<a@localhost>", "Second")).unwrap()
+    );
+    let id = drafts::save_draft(&memory, &account_config, second.as_bytes()).unwrap();
+    let envelopes = drafts::list_drafts(&memory, &account_config).unwrap();
+    assert_eq!(1, envelopes.len());
+    assert_eq!("Second", envelopes[0].subject);
+    assert_eq!(Flags::from_iter([Flag::Draft]), envelopes[0].flags);
+
+    // check that other drafts are kept
+
+    drafts::save_draft(&memory, &account_config, &email("<b@localhost>", "Other")).unwrap();
+    assert_eq!(
+        2,
+        drafts::list_drafts(&memory, &account_config).unwrap().len()
+    );
+
+    // check that the draft can be deleted once sent
+
+    drafts::delete_draft(&memory, &account_config, &id).unwrap();
+    let envelopes = drafts::list_drafts(&memory, &account_config).unwrap();
+    assert_eq!(1, envelopes.len());
+    assert_eq!("Other", envelopes[0].subject);
+}
+
+#[cfg(feature = "memory-backend")]
+#[test]
+fn test_memory_backend_sync_newest_first() {
+    let account = SyncAccount::new("memory");
+
+    let remote = account.remote();
+    let mut total_bytes = 0;
+    for (id, date) in [
+        ("a", "Sun, 1 Jan 2023 10:00:00 +0000"),
+        ("b", "Tue, 3 Jan 2023 10:00:00 +0000"),
+        ("c", "Mon, 2 Jan 2023 10:00:00 +0000"),
+    ] {
+        let email = format!(
+            "Message-ID: <{id}@localhost>\r\nDate: {date}\r\nSubject: {id}\r\n\r\n{id}\r\n"
+        );
+        total_bytes += email.len() as u64;
+        remote
+            .add_email("INBOX", email.as_bytes(), &Flags::default())
+            .unwrap();
+    }
+
+    let events = Mutex::new(Vec::new());
+    let report = BackendSyncBuilder::new(&account.config)
+        .newest_first(true)
+        .parallelism(1)
+        .batch_size(1)
+        .on_progress(|evt| {
+            events.lock().unwrap().push(evt);
+            Ok(())
+        })
+        .sync(&remote)
+        .unwrap();
+    assert!(report.envelopes_failed_hunks.is_empty());
+
+    // check that the newest emails are downloaded first
+
+    let downloaded_subjects: Vec<&str> = report
+        .envelopes_patch
+        .iter()
+        .filter_map(|hunk| match hunk {
+            BackendHunk::CopyEmail(
+                _,
+                envelope,
+                HunkKindRestricted::Remote,
+                HunkKindRestricted::Local,
+                _,
+            ) => Some(envelope.subject.as_str()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(vec!["b", "c", "a"], downloaded_subjects);
+
+    // check that the download progress is reported
+
+    let events = events.into_inner().unwrap();
+    assert!(
+        events.contains(&BackendSyncProgressEvent::StartEmailsDownload(
+            3,
+            total_bytes
+        ))
+    );
+    let last_download = events.iter().rev().find_map(|evt| match evt {
+        BackendSyncProgressEvent::DownloadEmail(done, total) => Some((*done, *total)),
+        _ => None,
+    });
+    assert_eq!(Some((total_bytes, total_bytes)), last_download);
+}
+
+#[cfg(feature = "memory-backend")]
+#[test]
+fn test_memory_backend_sync_executors() {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(1)
+        .build()
+        .unwrap();
+
+    for executor in [SyncExecutor::Pool(&pool), SyncExecutor::SingleThreaded] {
+        let account = SyncAccount::new("memory");
+
+        let remote = account.remote();
+        remote.add_folder("Archives").unwrap();
+        remote
+            .add_email("INBOX", &email("<a@localhost>", "A"), &Flags::default())
+            .unwrap();
+        remote
+            .add_email("Archives", &email("<b@localhost>", "B"), &Flags::default())
+            .unwrap();
+
+        let report = BackendSyncBuilder::new(&account.config)
+            .executor(executor)
+            .sync(&remote)
+            .unwrap();
+        assert!(report.envelopes_failed_hunks.is_empty(), "{executor:?}");
+
+        let local = account.local();
+        assert_eq!(1, local.list_envelopes("INBOX", 0, 0).unwrap().len());
+        assert_eq!(1, local.list_envelopes("Archives", 0, 0).unwrap().len());
+    }
+}
+
+#[cfg(feature = "memory-backend")]
+#[test]
+fn test_memory_backend_sync_deleted_move_to_trash() {
+    let account = SyncAccount::new("memory");
+
+    let remote = account.remote();
+    remote.add_folder("Trash").unwrap();
+    let id = remote
+        .add_email("INBOX", &email("<a@localhost>", "A"), &Flags::default())
+        .unwrap();
+
+    let sync = || {
+        let report = BackendSyncBuilder::new(&account.config)
+            .sync_deleted(SyncDeletedBehavior::MoveToTrash)
+            .sync(&remote)
+            .unwrap();
+        assert!(report.envelopes_failed_hunks.is_empty());
+    };
+
+    sync();
+    remote
+        .add_flags("INBOX", vec![&id], &Flags::from_iter([Flag::Deleted]))
+        .unwrap();
+
+    // the first synchronization propagates the flag, the second one
+    // moves the email to the trash, the last one synchronizes the
+    // trash folder
+    sync();
+    sync();
+    sync();
+
+    let local = account.local();
+
+    assert!(remote.list_envelopes("INBOX", 0, 0).unwrap().is_empty());
+    assert!(local.list_envelopes("INBOX", 0, 0).unwrap().is_empty());
+
+    let remote_trash = remote.list_envelopes("Trash", 0, 0).unwrap();
+    assert_eq!(1, remote_trash.len());
+    assert!(!remote_trash[0].flags.contains(&Flag::Deleted));
+
+    let local_trash = local.list_envelopes("Trash", 0, 0).unwrap();
+    assert_eq!(1, local_trash.len());
+    assert!(!local_trash[0].flags.contains(&Flag::Deleted));
+}
+
+#[cfg(feature = "memory-backend")]
+#[test]
+fn test_memory_backend_purge_message() {
+    let account = SyncAccount::new("memory");
+
+    let remote = account.remote();
+    remote.add_folder("Archive").unwrap();
+    for folder in ["INBOX", "Archive"] {
+        remote
+            .add_email(folder, &email("<a@localhost>", "A"), &Flags::default())
+            .unwrap();
+    }
+    remote
+        .add_email("INBOX", &email("<b@localhost>", "B"), &Flags::default())
+        .unwrap();
+
+    BackendSyncBuilder::new(&account.config)
+        .sync(&remote)
+        .unwrap();
+
+    // check that a dry run only reports where the email is
+
+    let report = BackendSyncBuilder::new(&account.config)
+        .dry_run(true)
+        .purge_message(&remote, "INBOX", "<a@localhost>", false)
+        .unwrap();
+    assert_eq!(1, report.remote.len());
+    assert_eq!(1, report.local_cache.len());
+    assert_eq!(2, remote.list_envelopes("INBOX", 0, 0).unwrap().len());
+
+    // check that the email is removed everywhere, from all folders
+
+    let report = BackendSyncBuilder::new(&account.config)
+        .purge_message(&remote, "INBOX", "a@localhost", true)
+        .unwrap();
+    for entries in [
+        &report.local,
+        &report.local_cache,
+        &report.remote,
+        &report.remote_cache,
+    ] {
+        let mut folders: Vec<_> = entries.iter().map(|(folder, _)| folder.as_str()).collect();
+        folders.sort();
+        assert_eq!(vec!["Archive", "INBOX"], folders);
+    }
+
+    // check that the next synchronization does not bring it back
+
+    BackendSyncBuilder::new(&account.config)
+        .sync(&remote)
+        .unwrap();
+
+    let local = account.local();
+
+    for folder in ["INBOX", "Archive"] {
+        let subjects = |envelopes: himalaya_lib::Envelopes| -> Vec<String> {
+            envelopes.iter().map(|e| e.subject.clone()).collect()
+        };
+        let expected: Vec<String> = match folder {
+            "INBOX" => vec!["B".into()],
+            _ => vec![],
+        };
+        assert_eq!(
+            expected,
+            subjects(remote.list_envelopes(folder, 0, 0).unwrap())
+        );
+        assert_eq!(
+            expected,
+            subjects(local.list_envelopes(folder, 0, 0).unwrap())
+        );
+    }
+
+    let report = BackendSyncBuilder::new(&account.config)
+        .purge_message(&remote, "INBOX", "<a@localhost>", true)
+        .unwrap();
+    assert!(report.is_empty());
+}
+
+#[cfg(feature = "memory-backend")]
+#[test]
+fn test_memory_backend_sync_remotes() {
+    let account = SyncAccount::new("memory");
+
+    let remote_a = Arc::new(MemoryBackend::new(Cow::Owned(account.config.clone())));
+    remote_a
+        .add_email("INBOX", &email("<a@localhost>", "A"), &Flags::default())
+        .unwrap();
+    remote_a
+        .add_email("INBOX", &email("<c@localhost>", "C"), &Flags::default())
+        .unwrap();
+
+    let remote_b = Arc::new(MemoryBackend::new(Cow::Owned(account.config.clone())));
+    let id = remote_b
+        .add_email("INBOX", &email("<b@localhost>", "B"), &Flags::default())
+        .unwrap();
+    remote_b
+        .add_email("INBOX", &email("<c@localhost>", "C"), &Flags::default())
+        .unwrap();
+
+    let remotes: Vec<Arc<dyn Backend + Sync + Send>> = vec![remote_a.clone(), remote_b.clone()];
+
+    let local = account.local();
+
+    let mut conn =
+        rusqlite::Connection::open(account.sync_dir.path().join(".sync.sqlite")).unwrap();
+    envelope::sync::Cache::init(&mut conn).unwrap();
+
+    let sync = |conn: &mut rusqlite::Connection| {
+        let reports = envelope::SyncBuilder::new(&account.config)
+            .sync_remotes("INBOX", conn, &local, &remotes);
+        assert_eq!(2, reports.len());
+        assert!(reports
+            .iter()
+            .all(|report| report.as_ref().unwrap().failed_hunks.is_empty()));
+    };
+
+    let message_ids = |backend: &dyn Backend| {
+        let mut ids = backend
+            .list_envelopes("INBOX", 0, 0)
+            .unwrap()
+            .iter()
+            .map(|envelope| envelope.message_id.clone())
+            .collect::<Vec<_>>();
+        ids.sort();
+        ids
+    };
+
+    // check that emails of each remote reach the other one, and that
+    // the email present on both remotes is not duplicated
+
+    sync(&mut conn);
+
+    let all = vec!["<a@localhost>", "<b@localhost>", "<c@localhost>"];
+    assert_eq!(all, message_ids(&local));
+    assert_eq!(all, message_ids(remote_a.as_ref()));
+    assert_eq!(all, message_ids(remote_b.as_ref()));
+
+    // check that flags changed on the last remote reach the first one
+
+    remote_b
+        .add_flags("INBOX", vec![&id], &Flags::from_iter([Flag::Flagged]))
+        .unwrap();
+
+    sync(&mut conn);
+
+    let envelope = remote_a
+        .list_envelopes("INBOX", 0, 0)
+        .unwrap()
+        .iter()
+        .find(|envelope| envelope.message_id == "<b@localhost>")
+        .cloned()
+        .unwrap();
+    assert!(envelope.flags.contains(&Flag::Flagged));
+}
+
+/// Returns the sorted message ids of the inbox of the given backend.
+#[cfg(feature = "memory-backend")]
+fn inbox_message_ids(backend: &dyn Backend) -> Vec<String> {
+    let mut ids = backend
+        .list_envelopes("INBOX", 0, 0)
+        .unwrap()
+        .iter()
+        .map(|envelope| envelope.message_id.clone())
+        .collect::<Vec<_>>();
+    ids.sort();
+    ids
+}
+
+/// Returns the identifier of the inbox email matching the given
+/// message id.
+#[cfg(feature = "memory-backend")]
+fn inbox_id(backend: &dyn Backend, message_id: &str) -> String {
+    backend
+        .list_envelopes("INBOX", 0, 0)
+        .unwrap()
+        .iter()
+        .find(|envelope| envelope.message_id == message_id)
+        .unwrap()
+        .id
+        .clone()
+}
+
+#[cfg(feature = "memory-backend")]
+#[test]
+fn test_memory_backend_sync_remotes_delete_vs_update() {
+    // the removal wins whichever remote it happens on, like for a
+    // single remote where an email removed from one side is removed
+    // from the other side even if its flags changed there
+    for deleting in [0, 1] {
+        let account = SyncAccount::new("memory");
+
+        let remote_a = Arc::new(MemoryBackend::new(Cow::Owned(account.config.clone())));
+        remote_a
+            .add_email("INBOX", &email("<a@localhost>", "A"), &Flags::default())
+            .unwrap();
+        remote_a
+            .add_email("INBOX", &email("<b@localhost>", "B"), &Flags::default())
+            .unwrap();
+        let remote_b = Arc::new(MemoryBackend::new(Cow::Owned(account.config.clone())));
+
+        let remotes: Vec<Arc<dyn Backend + Sync + Send>> = vec![remote_a.clone(), remote_b.clone()];
+
+        let local = account.local();
+
+        let mut conn =
+            rusqlite::Connection::open(account.sync_dir.path().join(".sync.sqlite")).unwrap();
+        envelope::sync::Cache::init(&mut conn).unwrap();
+
+        let mut sync = || {
+            let reports = envelope::SyncBuilder::new(&account.config)
+                .sync_remotes("INBOX", &mut conn, &local, &remotes);
+            assert!(reports
+                .iter()
+                .all(|report| report.as_ref().unwrap().failed_hunks.is_empty()));
+        };
+
+        sync();
+
+        let all = vec!["<a@localhost>", "<b@localhost>"];
+        assert_eq!(all, inbox_message_ids(remote_b.as_ref()));
+
+        // remove A from one remote while flagging it on the other one
+
+        let (deleter, updater) = if deleting == 0 {
+            (remote_a.as_ref(), remote_b.as_ref())
+        } else {
+            (remote_b.as_ref(), remote_a.as_ref())
+        };
+
+        let id = inbox_id(deleter, "<a@localhost>");
+        deleter.delete_emails("INBOX", vec![&id]).unwrap();
+        let id = inbox_id(updater, "<a@localhost>");
+        updater
+            .add_flags("INBOX", vec![&id], &Flags::from_iter([Flag::Flagged]))
+            .unwrap();
+
+        sync();
+
+        let remaining = vec!["<b@localhost>"];
+        assert_eq!(remaining, inbox_message_ids(&local));
+        assert_eq!(remaining, inbox_message_ids(remote_a.as_ref()));
+        assert_eq!(remaining, inbox_message_ids(remote_b.as_ref()));
+
+        // check that the remotes are stable once converged
+
+        sync();
+
+        assert_eq!(remaining, inbox_message_ids(remote_a.as_ref()));
+        assert_eq!(remaining, inbox_message_ids(remote_b.as_ref()));
+    }
+}
+
+#[cfg(feature = "memory-backend")]
+#[test]
+fn test_memory_backend_sync_remotes_failing_remote() {
+    let account = SyncAccount::new("memory");
+
+    let remote_a = Arc::new(MemoryBackend::new(Cow::Owned(account.config.clone())));
+    remote_a
+        .add_email("INBOX", &email("<a@localhost>", "A"), &Flags::default())
+        .unwrap();
+    let remote_b = Arc::new(MemoryBackend::new(Cow::Owned(account.config.clone())));
+    remote_b
+        .add_email("INBOX", &email("<b@localhost>", "B"), &Flags::default())
+        .unwrap();
+    let remote_c = Arc::new(MemoryBackend::new(Cow::Owned(account.config.clone())));
+    remote_c
+        .add_email("INBOX", &email("<c@localhost>", "C"), &Flags::default())
+        .unwrap();
+
+    let remotes: Vec<Arc<dyn Backend + Sync + Send>> =
+        vec![remote_a.clone(), remote_b.clone(), remote_c.clone()];
+
+    let local = account.local();
+
+    let mut conn =
+        rusqlite::Connection::open(account.sync_dir.path().join(".sync.sqlite")).unwrap();
+    envelope::sync::Cache::init(&mut conn).unwrap();
+
+    // check that the remote failing in the middle of the passes is
+    // skipped, while the remotes around it are still synchronized
+
+    remote_b.fail_next(Operation::ListEnvelopes).unwrap();
+
+    let reports = envelope::SyncBuilder::new(&account.config)
+        .sync_remotes("INBOX", &mut conn, &local, &remotes);
+
+    assert_eq!(3, reports.len());
+    assert!(reports[0].is_ok());
+    assert!(reports[1].is_err());
+    assert!(reports[2].is_ok());
+
+    let synced = vec!["<a@localhost>", "<c@localhost>"];
+    assert_eq!(synced, inbox_message_ids(&local));
+    assert_eq!(synced, inbox_message_ids(remote_a.as_ref()));
+    assert_eq!(synced, inbox_message_ids(remote_c.as_ref()));
+    assert_eq!(vec!["<b@localhost>"], inbox_message_ids(remote_b.as_ref()));
+
+    // check that the failed remote catches up at the next
+    // synchronization
+
+    let reports = envelope::SyncBuilder::new(&account.config)
+        .sync_remotes("INBOX", &mut conn, &local, &remotes);
+
+    assert!(reports.iter().all(|report| report.is_ok()));
+
+    let all = vec!["<a@localhost>", "<b@localhost>", "<c@localhost>"];
+    assert_eq!(all, inbox_message_ids(&local));
+    assert_eq!(all, inbox_message_ids(remote_a.as_ref()));
+    assert_eq!(all, inbox_message_ids(remote_b.as_ref()));
+    assert_eq!(all, inbox_message_ids(remote_c.as_ref()));
+}
+
+#[cfg(feature = "memory-backend")]
+#[test]
+fn test_memory_backend_sync_prefetched() {
+    let account = SyncAccount::new("memory");
+
+    let remote = account.remote();
+    remote.add_folder("Archives").unwrap();
+    remote
+        .add_email("INBOX", &email("<a@localhost>", "A"), &Flags::default())
+        .unwrap();
+    remote
+        .add_email("Archives", &email("<b@localhost>", "B"), &Flags::default())
+        .unwrap();
+
+    let local = account.local();
+
+    let mut conn =
+        rusqlite::Connection::open(account.sync_dir.path().join(".sync.sqlite")).unwrap();
+    envelope::sync::Cache::init(&mut conn).unwrap();
+
+    // check that the default implementation lists each folder
+
+    let mut prefetched = remote.list_envelopes_multi(&["INBOX", "Archives"]).unwrap();
+    assert_eq!(2, prefetched.len());
+    assert_eq!(
+        vec!["<b@localhost>"],
+        prefetched["Archives"]
+            .iter()
+            .map(|envelope| envelope.message_id.as_str())
+            .collect::<Vec<_>>()
+    );
+
+    // check that the plan uses the prefetched envelopes instead of
+    // listing them again, and keeps the given modseq
+
+    remote
+        .add_email("INBOX", &email("<c@localhost>", "C"), &Flags::default())
+        .unwrap();
+
+    let sync = envelope::SyncBuilder::new(&account.config);
+    let plan = sync
+        .plan_prefetched(
+            "INBOX",
+            &mut conn,
+            &local,
+            &remote,
+            prefetched.remove("INBOX").unwrap(),
+            Some((7, 1)),
+        )
+        .unwrap();
+    let report = sync.apply(plan, &mut conn, &local, &remote).unwrap();
+    assert!(report.failed_hunks.is_empty());
+
+    let message_ids: Vec<String> = local
+        .list_envelopes("INBOX", 0, 0)
+        .unwrap()
+        .iter()
+        .map(|envelope| envelope.message_id.clone())
+        .collect();
+    assert_eq!(vec!["<a@localhost>"], message_ids);
+    assert_eq!(
+        Some(7),
+        Cache::get_modseq(&conn, "memory", "INBOX").unwrap()
+    );
+}
+
+#[cfg(feature = "memory-backend")]
+#[test]
+fn test_memory_backend_preview_emails() {
+    let account = SyncAccount::new("memory");
+
+    let remote = account.remote();
+    let id = remote
+        .add_email("INBOX", &email("<a@localhost>", "A"), &Flags::default())
+        .unwrap();
+
+    // check that previewing emails does not mark them as seen
+
+    remote.preview_emails_internal("INBOX", vec![&id]).unwrap();
+    let envelope = remote.get_envelope("INBOX", &id).unwrap();
+    assert!(!envelope.flags.contains(&Flag::Seen));
+
+    // check that the synchronization does not mark downloaded
+    // emails as seen
+
+    let report = BackendSyncBuilder::new(&account.config)
+        .sync(&remote)
+        .unwrap();
+    assert!(report.envelopes_failed_hunks.is_empty());
+    let envelope = remote.get_envelope("INBOX", &id).unwrap();
+    assert!(!envelope.flags.contains(&Flag::Seen));
+
+    // check that getting emails marks them as seen
+
+    remote.get_emails_internal("INBOX", vec![&id]).unwrap();
+    let envelope = remote.get_envelope("INBOX", &id).unwrap();
+    assert!(envelope.flags.contains(&Flag::Seen));
+}
+
+#[cfg(feature = "memory-backend")]
+#[test]
+fn test_memory_backend_rebuild_cache() {
+    let account = SyncAccount::new("memory");
+
+    let remote = account.remote();
+    remote
+        .add_email("INBOX", &email("<a@localhost>", "A"), &Flags::default())
+        .unwrap();
+
+    BackendSyncBuilder::new(&account.config)
+        .sync(&remote)
+        .unwrap();
+
+    // simulate a lost cache, then a new email received meanwhile
+
+    std::fs::remove_file(account.sync_dir.path().join(".sync.sqlite")).unwrap();
+    remote
+        .add_email("INBOX", &email("<b@localhost>", "B"), &Flags::default())
+        .unwrap();
+
+    let report = BackendSyncBuilder::new(&account.config)
+        .rebuild_cache(&remote)
+        .unwrap();
+    assert!(report.folders.contains("INBOX"));
+    assert!(report.local_only_envelopes.is_empty());
+    assert_eq!(1, report.remote_only_envelopes.len());
+    assert_eq!(
+        "<b@localhost>",
+        report.remote_only_envelopes[0].1.message_id
+    );
+
+    // check that the next synchronization only downloads the new
+    // email, without duplicating the matched one
+
+    let report = BackendSyncBuilder::new(&account.config)
+        .sync(&remote)
+        .unwrap();
+    assert!(report.envelopes_failed_hunks.is_empty());
+    assert_eq!(
+        1,
+        report
+            .envelopes_patch
+            .iter()
+            .filter(|hunk| hunk.is_download())
+            .count()
+    );
+
+    let local = account.local();
+    assert_eq!(2, local.list_envelopes("INBOX", 0, 0).unwrap().len());
+    assert_eq!(2, remote.list_envelopes("INBOX", 0, 0).unwrap().len());
+}
+
+#[cfg(feature = "memory-backend")]
+#[test]
+fn test_memory_backend_sync_resume_from_journal() {
+    let account = SyncAccount::new("memory");
+
+    let remote = account.remote();
+    remote
+        .add_email("INBOX", &email("<a@localhost>", "A"), &Flags::default())
+        .unwrap();
+
+    BackendSyncBuilder::new(&account.config)
+        .sync(&remote)
+        .unwrap();
+
+    // simulate a synchronization interrupted right after copying a
+    // new email, its cache hunks being only journaled
+
+    remote
+        .add_email("INBOX", &email("<b@localhost>", "B"), &Flags::default())
+        .unwrap();
+    let local = account.local();
+    local
+        .add_email("INBOX", &email("<b@localhost>", "B"), &Flags::default())
+        .unwrap();
+
+    let find_b = |backend: &dyn Backend| {
+        backend
+            .list_envelopes("INBOX", 0, 0)
+            .unwrap()
+            .iter()
+            .find(|envelope| envelope.message_id == "<b@localhost>")
+            .cloned()
+            .unwrap()
+    };
+    let cache_hunks = [
+        CacheHunk::InsertEnvelope("INBOX".into(), find_b(&local), HunkKindRestricted::Local),
+        CacheHunk::InsertEnvelope("INBOX".into(), find_b(&remote), HunkKindRestricted::Remote),
+    ];
+
+    let mut conn =
+        rusqlite::Connection::open(account.sync_dir.path().join(".sync.sqlite")).unwrap();
+    let tx = conn.transaction().unwrap();
+    Cache::journal(&tx, "memory", "INBOX", &cache_hunks).unwrap();
+    tx.commit().unwrap();
+
+    // check that the journal repairs the cache, so that the copied
+    // email is neither copied again nor cached again
+
+    let report = BackendSyncBuilder::new(&account.config)
+        .sync(&remote)
+        .unwrap();
+    assert!(report.envelopes_failed_hunks.is_empty());
+    assert!(report.envelopes_patch.is_empty());
+    assert!(Cache::list_journal(&conn, "memory", "INBOX")
+        .unwrap()
+        .is_empty());
+    assert_eq!(2, local.list_envelopes("INBOX", 0, 0).unwrap().len());
+}
+
+#[cfg(feature = "memory-backend")]
+#[test]
+fn test_memory_backend_transfer_emails() {
+    let maildir_dir = tempdir().unwrap();
+    let account_config = AccountConfig::default();
+
+    let src = MemoryBackend::new(Cow::Borrowed(&account_config));
+    let dst = MaildirBackend::new(
+        Cow::Borrowed(&account_config),
+        Cow::Owned(MaildirConfig {
+            root_dir: maildir_dir.path().to_owned(),
+        }),
+    )
+    .unwrap();
+
+    let flagged = Flags::from_iter([Flag::Seen, Flag::Flagged]);
+    let a = src
+        .add_email("INBOX", &email("<a@localhost>", "A"), &Flags::default())
+        .unwrap();
+    let b = src
+        .add_email("INBOX", &email("<b@localhost>", "B"), &flagged)
+        .unwrap();
+    let c = src
+        .add_email("INBOX", &email("<c@localhost>", "C"), &Flags::default())
+        .unwrap();
+
+    // make the first email fail and check that the transfer goes on
+
+    src.fail_next(Operation::PreviewEmails).unwrap();
+    let progress = Mutex::new(Vec::new());
+    let report = transfer_emails(
+        &src,
+        "INBOX",
+        &dst,
+        "INBOX",
+        [a.as_str(), b.as_str(), c.as_str()],
+        2,
+        |done, total| progress.lock().unwrap().push((done, total)),
+    );
+
+    assert_eq!(vec![(2, 3), (3, 3)], *progress.lock().unwrap());
+    assert_eq!(vec![a.as_str()], report.failed_ids());
+    assert_eq!(2, report.transferred.len());
+
+    // check that flags are preserved and that source emails are
+    // left untouched
+
+    let (src_id, dst_id) = &report.transferred[0];
+    assert_eq!(&b, src_id);
+    let envelope = dst.get_envelope("INBOX", dst_id).unwrap();
+    assert_eq!("<b@localhost>", envelope.message_id);
+    assert_eq!(flagged, envelope.flags);
+    assert_eq!(3, src.list_envelopes("INBOX", 0, 0).unwrap().len());
+    let envelope = src.get_envelope("INBOX", &c).unwrap();
+    assert!(!envelope.flags.contains(&Flag::Seen));
+
+    // check that failed ids can be retried
+
+    let report = transfer_emails(
+        &src,
+        "INBOX",
+        &dst,
+        "INBOX",
+        report.failed_ids(),
+        DEFAULT_TRANSFER_BATCH_SIZE,
+        |_, _| (),
+    );
+    assert!(report.failed.is_empty());
+    assert_eq!(3, dst.list_envelopes("INBOX", 0, 0).unwrap().len());
+}
+
+#[cfg(feature = "memory-backend")]
+#[test]
+fn test_memory_backend_sync_already_running() {
+    let account = SyncAccount::new("memory-already-running");
+    let remote = account.remote();
+
+    let (locked_tx, locked_rx) = mpsc::channel();
+    let (release_tx, release_rx) = mpsc::channel::<()>();
+    let locked_tx = Mutex::new(locked_tx);
+    let release_rx = Mutex::new(release_rx);
+
+    thread::scope(|scope| {
+        // start a synchronization and pause it while it holds the
+        // lock
+        let first = scope.spawn(|| {
+            BackendSyncBuilder::new(&account.config)
+                .on_progress(|event| {
+                    if event == BackendSyncProgressEvent::GetLocalCachedFolders {
+                        locked_tx.lock().unwrap().send(()).unwrap();
+                        release_rx.lock().unwrap().recv().unwrap();
+                    }
+                    Ok(())
+                })
+                .sync(&remote)
+        });
+        locked_rx.recv().unwrap();
+
+        // check that a concurrent synchronization times out with the
+        // lock holder
+        let err = BackendSyncBuilder::new(&account.config)
+            .lock_timeout(Duration::from_millis(200))
+            .sync(&remote)
+            .unwrap_err();
+        match err {
+            backend::Error::SyncAlreadyRunningError(account, holder) => {
+                assert_eq!("memory-already-running", account);
+                assert!(holder.starts_with(&format!("pid {}", std::process::id())));
+            }
+            err => panic!("unexpected error: {err}"),
+        }
+
+        release_tx.send(()).unwrap();
+        first.join().unwrap().unwrap();
+    });
+
+    // check that the lock is released once the first synchronization
+    // is done, and that it can be removed
+    BackendSyncBuilder::new(&account.config)
+        .lock_timeout(Duration::ZERO)
+        .sync(&remote)
+        .unwrap();
+    BackendSyncBuilder::new(&account.config)
+        .force_unlock()
+        .unwrap();
+    assert!(!account.sync_dir.path().join(".sync.lock").exists());
+}
+
+#[cfg(feature = "memory-backend")]
+#[test]
+fn test_memory_backend_sync_dedup() {
+    let account = SyncAccount::with_config(AccountConfig {
+        name: "memory".into(),
+        sync_dedup: true,
+        ..AccountConfig::default()
+    });
+
+    // the same email is listed twice by the remote, with different
+    // flags
+    let remote = account.remote();
+    remote
+        .add_email(
+            "INBOX",
+            &email("<a@localhost>", "A"),
+            &Flags::from_iter([Flag::Seen]),
+        )
+        .unwrap();
+    remote
+        .add_email(
+            "INBOX",
+            &email("<a@localhost>", "A"),
+            &Flags::from_iter([Flag::Flagged]),
+        )
+        .unwrap();
+    assert!(remote
+        .list_envelopes("INBOX", 0, 0)
+        .unwrap()
+        .has_duplicate_message_ids());
+
+    // check that the local side gets a single email with the flags
+    // of both duplicates
+
+    BackendSyncBuilder::new(&account.config)
+        .sync(&remote)
+        .unwrap();
+    let local = account.local();
+    let local_envelopes = local.list_envelopes("INBOX", 0, 0).unwrap();
+    assert_eq!(1, local_envelopes.len());
+    assert_eq!(
+        Flags::from_iter([Flag::Seen, Flag::Flagged]),
+        local_envelopes[0].flags
+    );
+}
+
+#[cfg(feature = "memory-backend")]
+#[test]
+fn test_memory_backend_sync_read_only() {
+    for strategy in [ReadOnlyStrategy::Skip, ReadOnlyStrategy::CacheOnly] {
+        let account = SyncAccount::new("memory");
+
+        let remote = account.remote();
+        remote
+            .add_email("INBOX", &email("<a@localhost>", "A"), &Flags::default())
+            .unwrap();
+        remote
+            .add_email("INBOX", &email("<b@localhost>", "B"), &Flags::default())
+            .unwrap();
+        remote.set_read_only("INBOX", true).unwrap();
+
+        let sync = || {
+            BackendSyncBuilder::new(&account.config)
+                .read_only_strategy(strategy)
+                .sync(&remote)
+                .unwrap()
+        };
+
+        // check that a read-only folder can still be downloaded
+
+        let report = sync();
+        assert!(report.envelopes_read_only.is_empty());
+        let local = account.local();
+        let local_envelopes = local.list_envelopes("INBOX", 0, 0).unwrap();
+        assert_eq!(2, local_envelopes.len());
+
+        // check that local changes are reported once for the folder
+        // instead of failing hunk by hunk
+
+        let ids: Vec<&str> = local_envelopes.iter().map(|e| e.id.as_str()).collect();
+        local
+            .add_flags("INBOX", ids, &Flags::from_iter([Flag::Flagged]))
+            .unwrap();
+
+        let report = sync();
+        assert!(report.envelopes_failed_hunks.is_empty());
+        assert_eq!(1, report.envelopes_read_only.len());
+        let (folder, target, hunks) = &report.envelopes_read_only[0];
+        assert_eq!("INBOX", folder);
+        assert_eq!(&HunkKindRestricted::Remote, target);
+        assert_eq!(2, hunks.len());
+        let remote_envelopes = remote.list_envelopes("INBOX", 0, 0).unwrap();
+        assert!(remote_envelopes
+            .iter()
+            .all(|envelope| !envelope.flags.contains(&Flag::Flagged)));
+
+        // check that skipped changes are kept locally, whereas cached
+        // ones are reverted to match the read-only folder
+
+        let report = sync();
+        let local_envelopes = local.list_envelopes("INBOX", 0, 0).unwrap();
+        match strategy {
+            ReadOnlyStrategy::Skip => {
+                assert_eq!(1, report.envelopes_read_only.len());
+                assert!(local_envelopes
+                    .iter()
+                    .all(|envelope| envelope.flags.contains(&Flag::Flagged)));
+            }
+            ReadOnlyStrategy::CacheOnly => {
+                assert!(report.envelopes_read_only.is_empty());
+                assert!(local_envelopes
+                    .iter()
+                    .all(|envelope| !envelope.flags.contains(&Flag::Flagged)));
+            }
+        }
+    }
+}
+
+#[cfg(feature = "memory-backend")]
+#[test]
+fn test_memory_backend_mark_all_seen() {
+    let account = SyncAccount::new("memory");
+
+    let remote = account.remote();
+    remote
+        .add_email("INBOX", &email("<a@localhost>", "A"), &Flags::default())
+        .unwrap();
+    remote
+        .add_email(
+            "INBOX",
+            &email("<b@localhost>", "B"),
+            &Flags::from_iter([Flag::Flagged]),
+        )
+        .unwrap();
+
+    let sync_builder = BackendSyncBuilder::new(&account.config);
+    sync_builder.sync(&remote).unwrap();
+
+    // check that all emails are marked as seen on both sides, other
+    // flags being kept
+
+    sync_builder.mark_all_seen(&remote, "INBOX").unwrap();
+
+    let local = account.local();
+    let all_seen = |backend: &dyn Backend| {
+        let envelopes = backend.list_envelopes("INBOX", 0, 0).unwrap();
+        assert_eq!(2, envelopes.len());
+        assert!(envelopes
+            .iter()
+            .all(|envelope| envelope.flags.contains(&Flag::Seen)));
+        let flagged = envelopes.iter().find(|envelope| envelope.subject == "B");
+        assert!(flagged.unwrap().flags.contains(&Flag::Flagged));
+    };
+    all_seen(&remote);
+    all_seen(&local);
+
+    // check that the next synchronization has nothing to do, since
+    // the cache has been updated too
+
+    let report = sync_builder.sync(&remote).unwrap();
+    assert!(report.envelopes_patch.is_empty());
+    all_seen(&remote);
+    all_seen(&local);
+}
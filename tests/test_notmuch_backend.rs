@@ -34,6 +34,7 @@ fn test_notmuch_backend() {
         Cow::Borrowed(&account_config),
         Cow::Owned(NotmuchConfig {
             db_path: mdir.path().to_owned(),
+            ..NotmuchConfig::default()
         }),
     )
     .unwrap();
@@ -23,6 +23,7 @@ fn test_sendmail_sender() {
             "--read-recipients",
         ]
         .join(" "),
+        ..SendmailConfig::default()
     };
     let mut sendmail = Sendmail::new(&account_config, &sendmail_config);
     let imap = ImapBackend::new(